@@ -0,0 +1,14 @@
+//! Link the cdylib with the version script of the C control API
+//!
+//! Mach-O has no symbol versioning, so macOS builds export the control
+//! functions unversioned. awf-preload embeds the library digests given in
+//! AWF_ONE_SHOT_LIB_SHA256 at build time, so it is rebuilt when they change.
+
+fn main() {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rerun-if-changed=awf.map");
+    println!("cargo:rerun-if-env-changed=AWF_ONE_SHOT_LIB_SHA256");
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("macos") {
+        println!("cargo:rustc-cdylib-link-arg=-Wl,--version-script={manifest_dir}/awf.map");
+    }
+}
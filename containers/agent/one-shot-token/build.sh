@@ -1,48 +1,122 @@
 #!/bin/bash
 # Build the one-shot-token LD_PRELOAD library
-# This script compiles the Rust shared library
+# This script compiles the Rust shared library for the host and, for each of
+# the other supported architectures whose Rust target is installed, a variant
+# for programs of that architecture (see preload.sh). On macOS it builds one
+# universal dylib for arm64 and x86_64 programs instead.
+#
+# Usage: ./build.sh [cargo build options, e.g. --features minimal]
+#
+# Set ONE_SHOT_BUILD_VARIANTS=0 to build for the host only, or
+# ONE_SHOT_BUILD_VARIANTS=1 to fail when a variant (or the other half of the
+# universal dylib) cannot be built.
 
 set -e
 
 SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
 LINK_FILE="${SCRIPT_DIR}/one-shot-token.so"
+HOST_TARGET="$(rustc -vV | sed -n 's/^host: //p')"
 
-echo "[build] Building one-shot-token with Cargo..."
+# Rust target, link name and architecture of each variant
+VARIANTS=(
+    "i686-unknown-linux-gnu one-shot-token32.so i686"
+    "x86_64-unknown-linux-gnu one-shot-token-x86_64.so x86_64"
+    "aarch64-unknown-linux-gnu one-shot-token-aarch64.so aarch64"
+)
+
+# Rust targets combined into the universal macOS dylib
+MACOS_TARGETS=(aarch64-apple-darwin x86_64-apple-darwin)
+
+cd "${SCRIPT_DIR}"
+INSTALLED="$(rustup target list --installed 2>/dev/null || true)"
+
+# Print the architecture of an ELF shared library (ET_DYN)
+elf_arch() {
+    local header
+    header="$(od -An -tx1 -N20 "$1" | tr -d ' \n')"
+    # Magic, class, little-endian data; e_type ET_DYN; e_machine
+    case "${header:0:8}${header:10:2}/${header:32:4}/${header:8:2}${header:36:4}" in
+        7f454c4601/0300/010300) echo i686 ;;
+        7f454c4601/0300/023e00) echo x86_64 ;;
+        7f454c4601/0300/02b700) echo aarch64 ;;
+        *) echo unknown ;;
+    esac
+}
+
+# Check that a file is a shared library for the expected architecture
+verify() {
+    local file="$1" arch="$2"
+    if [ "$(elf_arch "${file}")" != "${arch}" ]; then
+        echo "[build] ERROR: ${file} is not a shared library for ${arch}"
+        exit 1
+    fi
+    echo "[build] Verified: ${file} is a shared library for ${arch}"
+}
 
-# Compile as a shared library with hardened build flags:
-# -shared: create a shared library
-# -fPIC: position-independent code (required for shared libs)
-# -fvisibility=hidden: hide all symbols by default (only getenv/secure_getenv
-#   are exported via __attribute__((visibility("default"))))
-# -ldl: link with libdl for dlsym
-# -lpthread: link with pthread for mutex
-# -O2: optimize for performance
-# -Wall -Wextra: enable warnings
-# -s: strip symbol table and relocation info at link time
-gcc -shared -fPIC \
-    -fvisibility=hidden \
-    -O2 -Wall -Wextra -s \
-    -o "${OUTPUT_FILE}" \
-    "${SOURCE_FILE}" \
-    -ldl -lpthread
-
-# Remove remaining unneeded symbols (debug sections, build metadata)
-strip --strip-unneeded "${OUTPUT_FILE}"
-
-echo "[build] Successfully built: ${OUTPUT_FILE}"
-
-# Verify it's a valid shared library
-if file "${OUTPUT_FILE}" | grep -qE "shared object|dynamically linked"; then
-    echo "[build] Verified: valid shared library"
-else
-    echo "[build] ERROR: Output is not a valid shared library"
-    exit 1
+# Build the universal dylib for DYLD_INSERT_LIBRARIES
+build_macos() {
+    local link="${SCRIPT_DIR}/one-shot-token.dylib" target arch
+    local slices=(target/release/libone_shot_token.dylib)
+    echo "[build] Building one-shot-token with Cargo for macOS..."
+    cargo build --release "$@"
+    for target in "${MACOS_TARGETS[@]}"; do
+        if [ "${target}" = "${HOST_TARGET}" ] || [ "${ONE_SHOT_BUILD_VARIANTS:-auto}" = "0" ]; then
+            continue
+        fi
+        if grep -qx "${target}" <<< "${INSTALLED}" &&
+           cargo build --release --target "${target}" "$@"; then
+            slices+=("target/${target}/release/libone_shot_token.dylib")
+        elif [ "${ONE_SHOT_BUILD_VARIANTS:-auto}" = "1" ]; then
+            echo "[build] ERROR: ${target} slice failed; it needs rustup target add ${target}"
+            exit 1
+        else
+            echo "[build] ${target} slice not built (needs rustup target ${target})"
+        fi
+    done
+    lipo -create -output target/release/one-shot-token-universal.dylib "${slices[@]}"
+    ln -sf target/release/one-shot-token-universal.dylib "${link}"
+    # lipo calls the host architecture arm64 where Rust says aarch64
+    arch="${HOST_TARGET%%-*}"
+    if ! lipo -archs "${link}" | tr ' ' '\n' | grep -qx "${arch/aarch64/arm64}"; then
+        echo "[build] ERROR: ${link} has no ${arch} code"
+        exit 1
+    fi
+    echo "[build] Verified: ${link} is a dylib for $(lipo -archs "${link}")"
+    echo "[build] Successfully built: ${link}"
+}
+
+if [ "$(uname -s)" = "Darwin" ]; then
+    build_macos "$@"
+    exit 0
 fi
 
-# Verify hardening: token names should NOT appear in binary
-if strings -a "${OUTPUT_FILE}" | grep -qE '(COPILOT_GITHUB_TOKEN|OPENAI_API_KEY|ANTHROPIC_API_KEY)'; then
-    echo "[build] WARNING: Cleartext token names still present in binary"
-    exit 1
-else
-    echo "[build] Verified: no cleartext token names in binary"
+echo "[build] Building one-shot-token with Cargo..."
+cargo build --release "$@"
+ln -sf target/release/libone_shot_token.so "${LINK_FILE}"
+verify "${LINK_FILE}" "${HOST_TARGET%%-*}"
+
+if [ "${ONE_SHOT_BUILD_VARIANTS:-auto}" = "0" ]; then
+    echo "[build] Skipping variants for other architectures (ONE_SHOT_BUILD_VARIANTS=0)"
+    VARIANTS=()
 fi
+
+for variant in "${VARIANTS[@]}"; do
+    read -r target link arch <<< "${variant}"
+    if [ "${target}" = "${HOST_TARGET}" ]; then
+        continue
+    fi
+    if grep -qx "${target}" <<< "${INSTALLED}" &&
+       cargo build --release --target "${target}" "$@"; then
+        ln -sf "target/${target}/release/libone_shot_token.so" "${SCRIPT_DIR}/${link}"
+        verify "${SCRIPT_DIR}/${link}" "${arch}"
+    elif [ "${ONE_SHOT_BUILD_VARIANTS:-auto}" = "1" ]; then
+        echo "[build] ERROR: ${arch} variant failed; it needs rustup target add ${target}"
+        echo "[build]        and a C toolchain for it (gcc-multilib, or a cross linker set"
+        echo "[build]        with CARGO_TARGET_<TRIPLE>_LINKER)"
+        exit 1
+    else
+        echo "[build] ${arch} variant not built (needs rustup target ${target} and a C toolchain for it)"
+    fi
+done
+
+echo "[build] Successfully built: ${LINK_FILE}"
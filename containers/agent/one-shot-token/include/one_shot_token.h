@@ -0,0 +1,88 @@
+/*
+ * C control API of the one-shot-token library (symbol versions AWF_1.0 to
+ * AWF_1.3)
+ *
+ * The functions are exported by the LD_PRELOAD library. Look them up with
+ * dlsym(RTLD_DEFAULT, ...) when the library may not be preloaded, or link
+ * against libone_shot_token.so to bind to the versions they were added in.
+ */
+
+#ifndef ONE_SHOT_TOKEN_H
+#define ONE_SHOT_TOKEN_H
+
+#include <sys/types.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ * Zeroize a protected token, or every protected token if `name` is NULL
+ *
+ * # Safety
+ * `name` must be null or a valid null-terminated C string
+ */
+int awf_token_wipe(const char *name);
+
+/*
+ * Refuse every later read of a protected token
+ *
+ * # Safety
+ * `name` must be a valid null-terminated C string
+ */
+int awf_token_lock(const char *name);
+
+/*
+ * Write the access statistics as JSON into `buf`
+ *
+ * Like snprintf, at most `len - 1` bytes are written followed by a NUL, and
+ * the length of the full summary is returned; `buf` may be NULL if `len` is 0.
+ *
+ * # Safety
+ * `buf` must be valid for writes of `len` bytes
+ */
+ssize_t awf_token_stats(char *buf, size_t len);
+
+/*
+ * Write the protection status as JSON into `buf`
+ *
+ * Like snprintf, at most `len - 1` bytes are written followed by a NUL, and
+ * the length of the full status is returned; `buf` may be NULL if `len` is 0.
+ *
+ * # Safety
+ * `buf` must be valid for writes of `len` bytes
+ */
+ssize_t awf_token_list_protected(char *buf, size_t len);
+
+/*
+ * Protect a variable from now on, with the options of `rule`
+ *
+ * # Safety
+ * `rule` must be null or a valid null-terminated C string
+ */
+int awf_token_protect(const char *rule);
+
+/*
+ * Stop protecting a variable added with awf_token_protect
+ *
+ * # Safety
+ * `name` must be a valid null-terminated C string
+ */
+int awf_token_unprotect(const char *name);
+
+/*
+ * Write the health check as JSON into `buf`
+ *
+ * Like snprintf, at most `len - 1` bytes are written followed by a NUL, and
+ * the length of the full status is returned; `buf` may be NULL if `len` is 0.
+ *
+ * # Safety
+ * `buf` must be valid for writes of `len` bytes
+ */
+ssize_t awf_one_shot_healthcheck(char *buf, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* ONE_SHOT_TOKEN_H */
@@ -0,0 +1,131 @@
+#!/bin/bash
+# Run a command with the one-shot-token build matching its architecture preloaded
+#
+# Usage: preload.sh <command> [args...]
+#
+# AWF_ONE_SHOT_LIB64, AWF_ONE_SHOT_LIB32 and AWF_ONE_SHOT_LIB_AARCH64 name the
+# x86_64, i686 and aarch64 builds. The dynamic linker skips an LD_PRELOAD
+# entry of the wrong ELF class or machine, so the command (or the interpreter
+# of a #! script) is inspected and the matching build is put first in
+# LD_PRELOAD. If the architecture cannot be determined, every build is listed
+# and the dynamic linker loads the one that fits. The library makes the same
+# choice for every program the command starts.
+#
+# On macOS AWF_ONE_SHOT_LIB64 names the universal dylib, which is inserted
+# with DYLD_INSERT_LIBRARIES. A warning is printed when System Integrity
+# Protection will start the command (or its #! interpreter) without it.
+
+set -e
+
+if [ $# -eq 0 ]; then
+    echo "Usage: $0 <command> [args...]" >&2
+    exit 2
+fi
+
+LIB64="${AWF_ONE_SHOT_LIB64:-}"
+LIB32="${AWF_ONE_SHOT_LIB32:-}"
+LIB_AARCH64="${AWF_ONE_SHOT_LIB_AARCH64:-}"
+BUILDS=()
+for build in "$LIB64" "$LIB32" "$LIB_AARCH64"; do
+    if [ -n "$build" ]; then
+        BUILDS+=("$build")
+    fi
+done
+
+# Print the architecture of an ELF file (i686, x86_64 or aarch64), following
+# one #! line. Reads EI_CLASS, EI_DATA and e_machine from the header.
+elf_arch() {
+    local file="$1" follow="$2" header interpreter
+    [ -r "$file" ] || return 1
+    header="$(od -An -tx1 -N20 "$file" 2>/dev/null | tr -d ' \n')"
+    case "$header" in
+        7f454c460101*) ;;
+        7f454c460201*) ;;
+        2321*)
+            [ "$follow" = "follow" ] || return 1
+            read -r interpreter _ < <(head -n1 "$file" | cut -c3-)
+            elf_arch "$interpreter" nofollow
+            return
+            ;;
+        *) return 1 ;;
+    esac
+    # Class (byte 4) and little-endian machine (bytes 18-19)
+    case "${header:8:2}${header:36:4}" in
+        010300) echo i686 ;;
+        023e00) echo x86_64 ;;
+        02b700) echo aarch64 ;;
+        *) return 1 ;;
+    esac
+}
+
+# Print the first of the file and its #! interpreter that dyld starts without
+# DYLD_INSERT_LIBRARIES: SIP-restricted or set-user-ID/set-group-ID programs
+sip_restricted() {
+    local file="$1" interpreter
+    [ -f "$file" ] || return 1
+    if [[ ",$(stat -f %Sf "$file" 2>/dev/null)," == *,restricted,* ]] ||
+       [ -u "$file" ] || [ -g "$file" ]; then
+        echo "$file"
+        return
+    fi
+    if [ "$(head -c2 "$file" 2>/dev/null)" = "#!" ]; then
+        read -r interpreter _ < <(head -n1 "$file" | cut -c3-)
+        [ -n "$interpreter" ] && [ "$interpreter" != "$file" ] || return 1
+        sip_restricted "$interpreter"
+        return
+    fi
+    return 1
+}
+
+PROGRAM="$(command -v -- "$1" || true)"
+
+if [ "$(uname -s)" = "Darwin" ]; then
+    if RESTRICTED="$(sip_restricted "$PROGRAM")"; then
+        echo "$0: warning: $RESTRICTED is protected by System Integrity Protection;" \
+             "$1 will run without one-shot-token" >&2
+    fi
+    if [ -n "$LIB64" ]; then
+        case ":${DYLD_INSERT_LIBRARIES:-}:" in
+            *":$LIB64:"*) ;;
+            *) export DYLD_INSERT_LIBRARIES="$LIB64${DYLD_INSERT_LIBRARIES:+:$DYLD_INSERT_LIBRARIES}" ;;
+        esac
+    fi
+    exec "$@"
+fi
+
+ARCH="$(elf_arch "$PROGRAM" follow || true)"
+
+case "$ARCH" in
+    i686) CHOSEN="$LIB32" ;;
+    x86_64) CHOSEN="$LIB64" ;;
+    aarch64) CHOSEN="$LIB_AARCH64" ;;
+    *) CHOSEN="" ;;
+esac
+
+# Keep other preloaded libraries, dropping the builds this script manages
+KEPT=""
+IFS=': ' read -r -a ENTRIES <<< "${LD_PRELOAD:-}"
+for entry in "${ENTRIES[@]}"; do
+    managed=0
+    for build in "${BUILDS[@]}"; do
+        if [ "$entry" = "$build" ]; then
+            managed=1
+        fi
+    done
+    if [ -n "$entry" ] && [ "$managed" = 0 ]; then
+        KEPT="${KEPT:+$KEPT:}$entry"
+    fi
+done
+
+if [ -n "$CHOSEN" ]; then
+    PRELOAD="$CHOSEN"
+else
+    # Unknown architecture: let the dynamic linker pick the build that loads
+    PRELOAD="$(IFS=:; echo "${BUILDS[*]}")"
+fi
+PRELOAD="${PRELOAD}${PRELOAD:+${KEPT:+:}}${KEPT}"
+
+if [ -n "$PRELOAD" ]; then
+    export LD_PRELOAD="$PRELOAD"
+fi
+exec "$@"
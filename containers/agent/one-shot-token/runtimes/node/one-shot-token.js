@@ -0,0 +1,32 @@
+// Re-expose tokens scrubbed by AWF_ONE_SHOT_EAGER in process.env
+//
+// Load with NODE_OPTIONS="--require /path/to/one-shot-token.js". The
+// preloaded one-shot-token library is also a Node-API addon (see
+// src/napi.rs): loading it again with process.dlopen replaces process.env
+// with a proxy that reads the protected tokens through the library's getenv.
+// Does nothing when the library is not preloaded.
+
+'use strict';
+
+const fs = require('fs');
+
+function loadedLibrary() {
+  let maps;
+  try {
+    maps = fs.readFileSync('/proc/self/maps', 'utf8');
+  } catch {
+    return undefined;
+  }
+  for (const line of maps.split('\n')) {
+    const path = line.slice(line.indexOf('/'));
+    if (line.includes('/') && /\/(lib)?one[-_]shot[-_]token[^/]*\.so$/.test(path)) {
+      return path;
+    }
+  }
+  return undefined;
+}
+
+const library = loadedLibrary();
+if (library) {
+  process.dlopen({ exports: {} }, library);
+}
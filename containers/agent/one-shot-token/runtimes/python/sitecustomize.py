@@ -0,0 +1,68 @@
+"""Re-expose tokens scrubbed by AWF_ONE_SHOT_EAGER in os.environ
+
+Put this directory on PYTHONPATH. Python copies environ into os.environ when
+it starts, after the one-shot-token library scrubbed the protected tokens at
+load, so os.environ and os.getenv() would not find them. The hook asks the
+preloaded library which protected tokens hold a value (awf_token_stats) and
+makes os.environ fall back to the library's getenv for those names: every
+lookup is a read under the token's policy. The names are left out of
+iteration, so copying os.environ reads nothing and hands no token to a child.
+Does nothing when the library is not preloaded.
+"""
+
+import ctypes
+import json
+import os
+
+
+def _install():
+    try:
+        library = ctypes.CDLL(None)
+        stats = library.awf_token_stats
+    except (AttributeError, OSError):
+        return
+    stats.argtypes = (ctypes.c_char_p, ctypes.c_size_t)
+    stats.restype = ctypes.c_ssize_t
+    getenv = library.getenv
+    getenv.argtypes = (ctypes.c_char_p,)
+    getenv.restype = ctypes.c_char_p
+
+    length = stats(None, 0)
+    buf = ctypes.create_string_buffer(length + 1)
+    stats(buf, len(buf))
+    listed = {
+        token["name"]
+        for token in json.loads(buf.value)["tokens"]
+        if token["set"] and not token["wiped"] and not token["locked"]
+    }
+    environ = os.environ
+
+    class _Environ(type(environ)):
+        def __getitem__(self, key):
+            try:
+                return super().__getitem__(key)
+            except KeyError:
+                if key not in listed:
+                    raise
+            value = getenv(os.fsencode(key))
+            if value is None:
+                raise KeyError(key) from None
+            return os.fsdecode(value)
+
+        def __contains__(self, key):
+            return key in listed or super().__contains__(key)
+
+        def __delitem__(self, key):
+            was_listed = key in listed
+            listed.discard(key)
+            try:
+                super().__delitem__(key)
+            except KeyError:
+                if not was_listed:
+                    raise
+
+    environ.__class__ = _Environ
+
+
+_install()
+del _install
@@ -0,0 +1,336 @@
+//! Safe Rust API for in-process use
+//!
+//! Rust components can link this crate as an rlib and protect tokens without
+//! LD_PRELOAD. Build it without default features, so that no libc functions
+//! are interposed and no load-time constructors run in the linking program:
+//!
+//! ```toml
+//! one-shot-token = { path = "...", default-features = false }
+//! ```
+//!
+//! [`TokenGuard`] applies the same caching, scrubbing and per-token policies
+//! as the preloaded library to the tokens it is given. [`Policy`] parses a
+//! token entry as in AWF_ONE_SHOT_TOKENS and [`Detector`] finds secrets in
+//! arbitrary data.
+//!
+//! ```no_run
+//! use one_shot_token::{Policy, TokenGuard};
+//!
+//! let guard = TokenGuard::new(["GITHUB_TOKEN:max_reads=2".parse::<Policy>().unwrap()]);
+//! if let Some(token) = guard.get("GITHUB_TOKEN") {
+//!     // GITHUB_TOKEN is no longer in the environment
+//!     let _ = token.as_str();
+//! }
+//! ```
+
+use crate::{
+    cache_token, call_real_getenv, detect, envname::EnvName, policy, serve_cached_token, sys,
+    TokenState, LOAD_TIME, REDACTED_PLACEHOLDER,
+};
+use std::ffi::CStr;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// A protected token and its access policy
+///
+/// Parsed from an entry of the form used in AWF_ONE_SHOT_TOKENS, such as
+/// `GITHUB_TOKEN`, `GITHUB_TOKEN:strict` or `GITHUB_TOKEN:ttl=30:max_reads=2`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    spec: policy::TokenSpec,
+}
+
+impl Policy {
+    /// Parse a token entry; unknown options are an error
+    pub fn parse(entry: &str) -> Result<Self, PolicyError> {
+        let error = |unknown| PolicyError {
+            entry: entry.to_string(),
+            unknown,
+        };
+        match policy::parse_token_spec(entry) {
+            None => Err(error(Vec::new())),
+            Some((_, unknown)) if !unknown.is_empty() => Err(error(unknown)),
+            Some((spec, _)) => Ok(Policy { spec }),
+        }
+    }
+
+    /// Name of the protected variable
+    pub fn name(&self) -> &str {
+        // Policies are parsed from a str, so the name is valid UTF-8
+        std::str::from_utf8(self.spec.name.as_bytes()).unwrap_or_default()
+    }
+}
+
+impl FromStr for Policy {
+    type Err = PolicyError;
+
+    fn from_str(entry: &str) -> Result<Self, PolicyError> {
+        Policy::parse(entry)
+    }
+}
+
+/// A token entry that could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyError {
+    entry: String,
+    unknown: Vec<String>,
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.unknown.is_empty() {
+            write!(f, "invalid token entry '{}'", self.entry)
+        } else {
+            write!(
+                f,
+                "unknown option(s) {} in token entry '{}'",
+                self.unknown.join(", "),
+                self.entry
+            )
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+/// Finds secrets in arbitrary data
+///
+/// Matches well-known credential formats (GitHub tokens, OpenAI and Anthropic
+/// keys) and, optionally, exact occurrences of known secret values.
+#[derive(Clone, Default)]
+pub struct Detector {
+    known: Vec<Vec<u8>>,
+}
+
+impl Detector {
+    /// A detector for the built-in credential formats only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also match exact occurrences of `value`
+    ///
+    /// Values shorter than 8 bytes are ignored, so that short test values
+    /// do not match ordinary words.
+    pub fn with_known(mut self, value: impl AsRef<[u8]>) -> Self {
+        self.known.push(value.as_ref().to_vec());
+        self
+    }
+
+    /// Byte ranges of the secrets in `data`, sorted and non-overlapping
+    pub fn find(&self, data: &[u8]) -> Vec<Range<usize>> {
+        let known: Vec<&[u8]> = self.known.iter().map(Vec::as_slice).collect();
+        detect::find_secret_spans(data, &known)
+    }
+
+    /// Whether `data` contains a secret
+    pub fn contains_secret(&self, data: &[u8]) -> bool {
+        !self.find(data).is_empty()
+    }
+
+    /// Copy of `text` with every secret replaced by the redaction placeholder
+    pub fn redact(&self, text: &str) -> String {
+        let mut redacted = String::with_capacity(text.len());
+        let mut last = 0;
+        for span in self.find(text.as_bytes()) {
+            // Spans start and end at ASCII bytes, so they are char boundaries
+            redacted.push_str(&text[last..span.start]);
+            redacted.push_str(REDACTED_PLACEHOLDER);
+            last = span.end;
+        }
+        redacted.push_str(&text[last..]);
+        redacted
+    }
+}
+
+impl fmt::Debug for Detector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Detector")
+            .field("known", &self.known.len())
+            .finish()
+    }
+}
+
+/// A copy of a token value, zeroized when dropped
+pub struct Secret {
+    bytes: Vec<u8>,
+}
+
+impl Secret {
+    /// The value's bytes
+    pub fn expose(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The value as a string, if it is valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.bytes).ok()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // SAFETY: bytes is a valid buffer of its length
+        unsafe { sys::explicit_bzero(self.bytes.as_mut_ptr().cast(), self.bytes.len()) };
+    }
+}
+
+/// In-process protection for a set of tokens
+///
+/// On first access a token is copied into protected memory and removed from
+/// the process environment; later accesses are served from the copy under
+/// the token's policy (`redact`, `strict`, `max_reads`, `ttl`,
+/// `expires_at`). Ttls count from library load or, without the preload
+/// feature, from the first guard.
+/// The `exe=` option has no effect here.
+///
+/// Like any environment change, scrubbing is not safe while other threads
+/// call getenv or setenv without going through a guard.
+pub struct TokenGuard {
+    state: Mutex<TokenState>,
+}
+
+impl TokenGuard {
+    /// Guard the tokens named by `policies`
+    pub fn new(policies: impl IntoIterator<Item = Policy>) -> Self {
+        LOAD_TIME.get_or_init(Instant::now);
+        let mut state = TokenState::new();
+        state.tokens = policies.into_iter().map(|policy| policy.spec).collect();
+        state.initialized = true;
+        TokenGuard {
+            state: Mutex::new(state),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TokenState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Read a guarded token
+    ///
+    /// Returns None if the token is not set, not guarded, or its policy
+    /// refuses the read.
+    pub fn get(&self, name: &str) -> Option<Secret> {
+        let mut state = self.lock();
+        let name = EnvName::from(name);
+        if !state.tokens.iter().any(|spec| spec.name == name) {
+            return None;
+        }
+        let value = match serve_cached_token(&mut state, &name) {
+            Some(value) => value,
+            None => {
+                // SAFETY: call_real_getenv is the real getenv
+                unsafe { cache_token(&mut state, &name, call_real_getenv) };
+                serve_cached_token(&mut state, &name)?
+            }
+        };
+        if value.is_null() {
+            return None;
+        }
+        // SAFETY: served values are valid null-terminated strings in the cache
+        let bytes = unsafe { CStr::from_ptr(value) }.to_bytes().to_vec();
+        Some(Secret { bytes })
+    }
+
+    /// Cache and scrub every guarded token now, without counting a read
+    pub fn scrub(&self) {
+        let mut state = self.lock();
+        let names: Vec<EnvName> = state.tokens.iter().map(|spec| spec.name.clone()).collect();
+        for name in names {
+            if !state.cache.contains_key(&name) {
+                // SAFETY: call_real_getenv is the real getenv
+                unsafe { cache_token(&mut state, &name, call_real_getenv) };
+            }
+        }
+    }
+
+    /// Zeroize every cached value; later reads return None
+    pub fn wipe(&self) {
+        let mut state = self.lock();
+        for entry in state.cache.values_mut() {
+            if !entry.value.is_null() {
+                entry.wipe("wiped");
+            }
+        }
+    }
+
+    /// A detector that also matches the values of the guarded tokens
+    ///
+    /// Only tokens that have been cached (read or scrubbed) are known.
+    pub fn detector(&self) -> Detector {
+        let state = self.lock();
+        state
+            .cache
+            .values()
+            .filter_map(|entry| entry.plaintext())
+            .filter(|value| value.as_slice() != REDACTED_PLACEHOLDER.as_bytes())
+            .fold(Detector::new(), Detector::with_known)
+    }
+}
+
+impl fmt::Debug for TokenGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = self
+            .lock()
+            .tokens
+            .iter()
+            .map(|s| s.name.to_string())
+            .collect();
+        f.debug_struct("TokenGuard")
+            .field("tokens", &names)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_parse() {
+        let policy: Policy = "GITHUB_TOKEN:max_reads=2".parse().unwrap();
+        assert_eq!(policy.name(), "GITHUB_TOKEN");
+        let err = Policy::parse("GITHUB_TOKEN:bogus").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown option(s) bogus in token entry 'GITHUB_TOKEN:bogus'"
+        );
+        assert!(Policy::parse("").is_err());
+    }
+
+    #[test]
+    fn test_detector_redact() {
+        let detector = Detector::new().with_known("hunter2hunter2");
+        let text = "a ghp_0123456789abcdefghijklmn b hunter2hunter2 c";
+        assert!(detector.contains_secret(text.as_bytes()));
+        assert_eq!(
+            detector.redact(text),
+            "a ***AWF_REDACTED*** b ***AWF_REDACTED*** c"
+        );
+        assert!(!Detector::new().contains_secret(b"hunter2hunter2"));
+    }
+
+    #[test]
+    fn test_token_guard() {
+        unsafe { libc::setenv(c"AWF_TEST_GUARD_TOKEN".as_ptr(), c"guard-value".as_ptr(), 1) };
+        let guard = TokenGuard::new([Policy::parse("AWF_TEST_GUARD_TOKEN:max_reads=2").unwrap()]);
+
+        let value = guard.get("AWF_TEST_GUARD_TOKEN").unwrap();
+        assert_eq!(value.as_str(), Some("guard-value"));
+        assert!(unsafe { call_real_getenv(c"AWF_TEST_GUARD_TOKEN".as_ptr()) }.is_null());
+        assert!(guard.detector().contains_secret(b"leaked guard-value here"));
+
+        assert!(guard.get("AWF_TEST_GUARD_TOKEN").is_some());
+        assert!(guard.get("AWF_TEST_GUARD_TOKEN").is_none());
+        assert!(guard.get("HOME").is_none());
+    }
+}
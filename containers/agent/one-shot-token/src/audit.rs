@@ -0,0 +1,145 @@
+//! Audit events for policy decisions
+//!
+//! Debug logging describes what the library is doing and is silent by default.
+//! Audit events record policy decisions (for example a read of a deny-listed
+//! variable) and are always emitted: they only arise from opt-in policies, and
+//! the host relies on them to see what the agent attempted.
+//!
+//! Every event records the thread it happened on and when: `tid` (gettid),
+//! `thread` (the thread name) and `mono` (CLOCK_MONOTONIC, in seconds), so
+//! accesses from a thread pool can be told apart and ordered even when the
+//! wall clock jumps.
+
+use crate::{log, sys};
+
+/// Emit an audit event to stderr
+///
+/// Lines have the form
+/// `[one-shot-token] AUDIT event=<event> token=<token> <detail>` so they can be
+/// picked out of mixed program output with a simple grep. In the JSON log
+/// format the `key=value` details become fields of the line. Token names are
+/// shown lossily if they are not valid UTF-8 (see the envname module).
+pub(crate) fn emit(event: &str, token: impl std::fmt::Display, detail: &str) {
+    let token = token.to_string();
+    let thread = thread_fields()
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, detail_word(value)))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let detail = if detail.is_empty() {
+        thread
+    } else {
+        format!("{} {}", detail, thread)
+    };
+    log::write_audit(
+        event,
+        &token,
+        &detail,
+        &format_event(event, &token, &detail),
+    );
+}
+
+/// A detail value, quoted if it would not form a single word
+pub(crate) fn detail_word(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c == ' ' || c == '"' || c.is_control()) {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Name of the calling thread
+fn thread_name() -> String {
+    let mut buf = [0 as libc::c_char; 16];
+    // SAFETY: buf holds the 16 bytes thread names are limited to
+    let result = unsafe { libc::pthread_getname_np(libc::pthread_self(), buf.as_mut_ptr(), 16) };
+    if result != 0 {
+        return "?".to_string();
+    }
+    // SAFETY: pthread_getname_np NUL-terminates the name on success
+    let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+    name.to_string_lossy().into_owned()
+}
+
+/// CLOCK_MONOTONIC as `seconds.nanoseconds`
+fn monotonic() -> String {
+    // SAFETY: timespec is a plain struct filled by clock_gettime
+    let ts = unsafe {
+        let mut ts: libc::timespec = std::mem::zeroed();
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+        ts
+    };
+    format!("{}.{:09}", ts.tv_sec, ts.tv_nsec)
+}
+
+/// Thread and monotonic time of the current access: `tid`, `thread`, `mono`
+pub(crate) fn thread_fields() -> [(String, String); 3] {
+    let tid = sys::thread_id();
+    [
+        ("tid".to_string(), tid.to_string()),
+        ("thread".to_string(), thread_name()),
+        ("mono".to_string(), monotonic()),
+    ]
+}
+
+/// Current wall-clock time as `seconds.milliseconds` since the Unix epoch,
+/// for events the host correlates with its own logs
+pub(crate) fn timestamp() -> String {
+    format_time(std::time::SystemTime::now())
+}
+
+/// Format a wall-clock time as `seconds.milliseconds` since the Unix epoch
+pub(crate) fn format_time(time: std::time::SystemTime) -> String {
+    let since_epoch = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    format!(
+        "{}.{:03}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Format an audit event line (without trailing newline)
+fn format_event(event: &str, token: &str, detail: &str) -> String {
+    if detail.is_empty() {
+        format!("[one-shot-token] AUDIT event={} token={}", event, token)
+    } else {
+        format!(
+            "[one-shot-token] AUDIT event={} token={} {}",
+            event, token, detail
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_fields() {
+        let spawned = std::thread::Builder::new()
+            .name("awf pool-1".to_string())
+            .spawn(thread_fields)
+            .unwrap();
+        let [(_, tid), (_, thread), (_, mono)] = spawned.join().unwrap();
+        assert!(tid.parse::<i32>().unwrap() > 0);
+        assert_eq!(thread, "awf pool-1");
+        assert_eq!(detail_word(&thread), "\"awf pool-1\"");
+        assert_eq!(detail_word("main"), "main");
+        let (secs, nanos) = mono.split_once('.').unwrap();
+        assert!(secs.parse::<u64>().is_ok() && nanos.len() == 9);
+    }
+
+    #[test]
+    fn test_format_event() {
+        assert_eq!(
+            format_event("denied", "GITHUB_TOKEN", ""),
+            "[one-shot-token] AUDIT event=denied token=GITHUB_TOKEN"
+        );
+        assert_eq!(
+            format_event("denied", "GITHUB_TOKEN", "present=true"),
+            "[one-shot-token] AUDIT event=denied token=GITHUB_TOKEN present=true"
+        );
+    }
+}
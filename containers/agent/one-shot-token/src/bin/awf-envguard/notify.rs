@@ -0,0 +1,630 @@
+//! Mediation of opens through seccomp user notifications
+//!
+//! The command is started with a filter that returns SECCOMP_RET_USER_NOTIF
+//! for open(), openat() and openat2() in every system call ABI the machine
+//! runs, and the filter's listener is passed back over a socket pair before
+//! the command is exec'd. A thread of this process then answers each call:
+//! it reads the path from the caller's memory and either lets the kernel
+//! carry the call out (SECCOMP_USER_NOTIF_FLAG_CONTINUE) or installs a
+//! descriptor of its own in the caller as the call's result
+//! (SECCOMP_ADDFD_FLAG_SEND, Linux 5.14).
+//!
+//! A continued call reads its path again, so a thread of the caller that
+//! rewrites the path in between opens what it names then: the check holds
+//! against tools reading environ files, not against a process racing it.
+//! Paths that reach an environ file through a symbolic link other than
+//! /proc/self are not recognized. Once this process exits, every open made
+//! by a process the command left running fails with ENOSYS.
+
+use crate::common::{audit, detail_word, environ_owner, forward_signals, read_c_string};
+use crate::common::{read_unredacted, EnvironOwner};
+use crate::{may_read, redact, resolve, Policy};
+use crate::{EXIT_CANNOT_EXECUTE, EXIT_FAILED, EXIT_NOT_FOUND, VIA};
+use libc::{pid_t, sock_filter, sock_fprog};
+use std::ffi::{CString, OsString};
+use std::fs::File;
+use std::io::{self, Read, Seek, Write};
+use std::mem::offset_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+/// The open system calls, by where their arguments are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Call {
+    /// open(path, flags, mode)
+    Open,
+    /// openat(dirfd, path, flags, mode)
+    OpenAt,
+    /// openat2(dirfd, path, how, size), the flags being the first field of
+    /// `how`
+    OpenAt2,
+}
+
+impl Call {
+    /// The directory a relative path is taken from
+    fn dirfd(self, args: &[u64; 6]) -> libc::c_int {
+        match self {
+            Call::Open => libc::AT_FDCWD,
+            Call::OpenAt | Call::OpenAt2 => args[0] as libc::c_int,
+        }
+    }
+
+    fn path(self, args: &[u64; 6]) -> u64 {
+        match self {
+            Call::Open => args[0],
+            Call::OpenAt | Call::OpenAt2 => args[1],
+        }
+    }
+}
+
+/// A system call ABI: its audit architecture and open calls
+struct Abi {
+    arch: u32,
+    calls: &'static [(u32, Call)],
+}
+
+/// Set in the numbers of x32 system calls, made through the x86_64 ABI
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const X32_BIT: u32 = 0x4000_0000;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const ABIS: &[Abi] = &[
+    Abi {
+        arch: 0xc000_003e,
+        calls: &[
+            (2, Call::Open),
+            (257, Call::OpenAt),
+            (437, Call::OpenAt2),
+            (2 | X32_BIT, Call::Open),
+            (257 | X32_BIT, Call::OpenAt),
+            (437 | X32_BIT, Call::OpenAt2),
+        ],
+    },
+    Abi {
+        arch: 0x4000_0003,
+        calls: &[(5, Call::Open), (295, Call::OpenAt), (437, Call::OpenAt2)],
+    },
+];
+#[cfg(target_arch = "aarch64")]
+const ABIS: &[Abi] = &[
+    Abi {
+        arch: 0xc000_00b7,
+        calls: &[(56, Call::OpenAt), (437, Call::OpenAt2)],
+    },
+    Abi {
+        arch: 0x4000_0028,
+        calls: &[(5, Call::Open), (322, Call::OpenAt), (437, Call::OpenAt2)],
+    },
+];
+
+/// Stages of starting the command, as reported by the child
+const STAGE_FILTER: i32 = 0;
+const STAGE_EXEC: i32 = 1;
+
+/// The open call `nr` is in ABI `arch`, if it is one
+fn call(arch: u32, nr: u32) -> Option<Call> {
+    let abi = ABIS.iter().find(|abi| abi.arch == arch)?;
+    abi.calls
+        .iter()
+        .find(|&&(number, _)| number == nr)
+        .map(|&(_, call)| call)
+}
+
+fn filter() -> Vec<sock_filter> {
+    let stmt = |code: u32, k: u32| sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let jump = |k: u32, jf: u8| sock_filter {
+        code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+        jt: 0,
+        jf,
+        k,
+    };
+    let load = |offset: usize| stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset as u32);
+
+    // One block per ABI, skipped when the architecture differs; every check
+    // jumps forward to the notify return at the end on a match
+    let mut program = Vec::new();
+    let mut checks = Vec::new();
+    for abi in ABIS {
+        program.push(load(offset_of!(libc::seccomp_data, arch)));
+        program.push(jump(abi.arch, abi.calls.len() as u8 + 2));
+        program.push(load(offset_of!(libc::seccomp_data, nr)));
+        for &(nr, _) in abi.calls {
+            checks.push(program.len());
+            program.push(jump(nr, 0));
+        }
+        program.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+    }
+    program.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+    let notify = program.len();
+    program.push(stmt(
+        libc::BPF_RET | libc::BPF_K,
+        libc::SECCOMP_RET_USER_NOTIF,
+    ));
+    for check in checks {
+        program[check].jt = (notify - check - 1) as u8;
+    }
+    program
+}
+
+/// Report a failure to start the command on `fd` and exit; run in the child
+unsafe fn report(fd: libc::c_int, stage: i32) -> ! {
+    let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+    let message = [stage, errno];
+    libc::write(fd, message.as_ptr().cast(), 8);
+    libc::_exit(EXIT_CANNOT_EXECUTE);
+}
+
+/// Start the command with the filter installed, returning its pid, this
+/// end of the socket its listener is sent on and the pipe on which it
+/// reports a failure to start
+fn spawn(command: &[OsString]) -> io::Result<(pid_t, OwnedFd, File)> {
+    let argv = command
+        .iter()
+        .map(|arg| {
+            CString::new(arg.as_bytes()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let argv: Vec<*const libc::c_char> = argv
+        .iter()
+        .map(|arg| arg.as_ptr())
+        .chain(std::iter::once(std::ptr::null()))
+        .collect();
+    let program = filter();
+    let prog = sock_fprog {
+        len: program.len() as u16,
+        filter: program.as_ptr() as *mut sock_filter,
+    };
+
+    let (mut fds, mut sockets) = ([0; 2], [0; 2]);
+    // SAFETY: fds and sockets have room for two descriptors; after fork the
+    // child only makes async-signal-safe calls on memory prepared above
+    unsafe {
+        if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let errors = File::from_raw_fd(fds[0]);
+        if libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+            0,
+            sockets.as_mut_ptr(),
+        ) != 0
+        {
+            libc::close(fds[1]);
+            return Err(io::Error::last_os_error());
+        }
+        let socket = OwnedFd::from_raw_fd(sockets[0]);
+        let pid = libc::fork();
+        if pid == 0 {
+            libc::close(fds[0]);
+            libc::close(sockets[0]);
+            let install = || {
+                libc::syscall(
+                    libc::SYS_seccomp,
+                    libc::SECCOMP_SET_MODE_FILTER,
+                    libc::SECCOMP_FILTER_FLAG_NEW_LISTENER,
+                    &prog as *const sock_fprog,
+                )
+            };
+            // Without CAP_SYS_ADMIN the filter needs no_new_privs, which
+            // would stop set-user-ID programs from gaining privileges
+            let mut listener = install();
+            if listener < 0 && io::Error::last_os_error().raw_os_error() == Some(libc::EACCES) {
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                    report(fds[1], STAGE_FILTER);
+                }
+                listener = install();
+            }
+            if listener < 0 || send_fd(sockets[1], listener as libc::c_int) < 0 {
+                report(fds[1], STAGE_FILTER);
+            }
+            libc::close(listener as libc::c_int);
+            libc::close(sockets[1]);
+            libc::execvp(argv[0], argv.as_ptr());
+            report(fds[1], STAGE_EXEC);
+        }
+        libc::close(fds[1]);
+        libc::close(sockets[1]);
+        if pid < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((pid, socket, errors))
+    }
+}
+
+/// Send `fd` over the socket `socket`; run in the child
+unsafe fn send_fd(socket: libc::c_int, fd: libc::c_int) -> libc::ssize_t {
+    let mut byte = 0u8;
+    let mut iov = libc::iovec {
+        iov_base: (&mut byte as *mut u8).cast(),
+        iov_len: 1,
+    };
+    let mut control = [0u64; 4];
+    let mut msg: libc::msghdr = std::mem::zeroed();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = libc::CMSG_SPACE(4) as _;
+    let cmsg = libc::CMSG_FIRSTHDR(&msg);
+    (*cmsg).cmsg_level = libc::SOL_SOCKET;
+    (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+    (*cmsg).cmsg_len = libc::CMSG_LEN(4) as _;
+    std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast(), fd);
+    libc::sendmsg(socket, &msg, 0)
+}
+
+/// The listener the child sent, or None if it failed before sending it
+fn receive_fd(socket: &OwnedFd) -> Option<OwnedFd> {
+    let mut byte = 0u8;
+    let mut iov = libc::iovec {
+        iov_base: (&mut byte as *mut u8).cast(),
+        iov_len: 1,
+    };
+    let mut control = [0u64; 4];
+    // SAFETY: msg points at buffers that outlive the call; a received
+    // descriptor is owned by the returned OwnedFd
+    unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+        if libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) <= 0 {
+            return None;
+        }
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+        {
+            return None;
+        }
+        let fd: libc::c_int = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast());
+        Some(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+/// The stage at which the command failed to start and the error, if it did
+fn start_error(mut errors: File) -> Option<(i32, io::Error)> {
+    let mut message = [0u8; 8];
+    errors.read_exact(&mut message).ok()?;
+    let stage = i32::from_ne_bytes(message[..4].try_into().unwrap());
+    let errno = i32::from_ne_bytes(message[4..].try_into().unwrap());
+    Some((stage, io::Error::from_raw_os_error(errno)))
+}
+
+/// How a notified open is answered
+enum Answer {
+    /// Carried out by the kernel
+    Continue,
+    /// Failed with EACCES
+    Deny,
+    /// Given this descriptor, close-on-exec if the open asked for it
+    Serve(File, bool),
+}
+
+/// Whether notification `id` is still pending: the caller has not died or
+/// been interrupted, so its pid and memory are still the ones read
+fn pending(listener: &OwnedFd, id: u64) -> bool {
+    // SAFETY: id is a valid pointer for the duration of the call
+    unsafe {
+        libc::ioctl(
+            listener.as_raw_fd(),
+            libc::SECCOMP_IOCTL_NOTIF_ID_VALID,
+            &id as *const u64,
+        ) == 0
+    }
+}
+
+/// A memfd holding `contents`, positioned at its start
+fn memfd(contents: &[u8]) -> io::Result<File> {
+    // SAFETY: valid C string; a returned descriptor is owned by the File
+    let mut file = unsafe {
+        let fd = libc::memfd_create(c"environ".as_ptr(), libc::MFD_CLOEXEC);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        File::from_raw_fd(fd)
+    };
+    file.write_all(contents)?;
+    file.rewind()?;
+    Ok(file)
+}
+
+/// Decide how to answer an open
+fn answer(listener: &OwnedFd, notif: &libc::seccomp_notif, policy: &Policy) -> Answer {
+    let Some(call) = call(notif.data.arch, notif.data.nr as u32) else {
+        return Answer::Continue;
+    };
+    let (pid, args) = (notif.pid, &notif.data.args);
+    // Without the caller's memory nothing can be decided
+    let Ok(memory) = File::open(format!("/proc/{}/mem", pid)) else {
+        return Answer::Deny;
+    };
+    // An unreadable path fails in the kernel as well
+    let Ok(path) = read_c_string(&memory, call.path(args)) else {
+        return Answer::Continue;
+    };
+    if !path.ends_with(b"environ") {
+        return Answer::Continue;
+    }
+    let base = if path.starts_with(b"/") {
+        Vec::new()
+    } else {
+        let dir = match call.dirfd(args) {
+            libc::AT_FDCWD => format!("/proc/{}/cwd", pid),
+            fd => format!("/proc/{}/fd/{}", pid, fd),
+        };
+        match std::fs::read_link(dir) {
+            Ok(dir) => dir.into_os_string().into_vec(),
+            Err(_) => return Answer::Continue,
+        }
+    };
+    let target = match environ_owner(&resolve(&base, &path)) {
+        Some(EnvironOwner::Current) => pid,
+        Some(EnvironOwner::Pid(target)) => target,
+        None => return Answer::Continue,
+    };
+    let flags = match call {
+        Call::Open => args[1],
+        Call::OpenAt => args[2],
+        Call::OpenAt2 => {
+            let mut how = [0u8; 8];
+            match memory.read_exact_at(&mut how, args[2]) {
+                Ok(()) => u64::from_ne_bytes(how),
+                Err(_) => return Answer::Continue,
+            }
+        }
+    } as libc::c_int;
+    if !pending(listener, notif.id) {
+        return Answer::Continue;
+    }
+
+    let detail = format!("pid={} path={}", pid, detail_word(&path));
+    let status = |pid: u32| std::fs::read_to_string(format!("/proc/{}/status", pid));
+    let (Ok(caller), Ok(owner)) = (status(pid), status(target)) else {
+        // The target is gone, and the kernel fails the open
+        return Answer::Continue;
+    };
+    if policy.observe {
+        audit(
+            VIA,
+            "observed",
+            "*",
+            &format!("would=redact_environ {}", detail),
+        );
+        return Answer::Continue;
+    }
+    // This process could read what the caller may not
+    if !may_read(&caller, &owner) || flags & libc::O_ACCMODE != libc::O_RDONLY {
+        audit(
+            VIA,
+            "environ_read",
+            "*",
+            &format!("{} action=denied", detail),
+        );
+        return Answer::Deny;
+    }
+    let served =
+        read_unredacted(Path::new(&format!("/proc/{}/environ", target))).and_then(|environ| {
+            let (redacted, entries) = redact(&environ, &policy.names);
+            Ok((memfd(&redacted)?, entries))
+        });
+    match served {
+        Ok((file, entries)) => {
+            audit(
+                VIA,
+                "environ_read",
+                "*",
+                &format!("{} action=redacted entries={}", detail, entries),
+            );
+            Answer::Serve(file, flags & libc::O_CLOEXEC != 0)
+        }
+        Err(err) => {
+            eprintln!(
+                "awf-envguard: warning: cannot read the environment of process {}: {}",
+                target, err
+            );
+            Answer::Deny
+        }
+    }
+}
+
+/// Send the answer to notification `id`
+fn respond(listener: &OwnedFd, id: u64, answer: Answer) {
+    let mut response = libc::seccomp_notif_resp {
+        id,
+        val: 0,
+        error: 0,
+        flags: 0,
+    };
+    match answer {
+        Answer::Continue => response.flags = libc::SECCOMP_USER_NOTIF_FLAG_CONTINUE as _,
+        Answer::Deny => response.error = -libc::EACCES,
+        Answer::Serve(file, cloexec) => {
+            let mut addfd = libc::seccomp_notif_addfd {
+                id,
+                flags: libc::SECCOMP_ADDFD_FLAG_SEND as _,
+                srcfd: file.as_raw_fd() as u32,
+                newfd: 0,
+                newfd_flags: if cloexec { libc::O_CLOEXEC as u32 } else { 0 },
+            };
+            // SAFETY: addfd is a valid pointer for the duration of the call
+            let added = unsafe {
+                libc::ioctl(
+                    listener.as_raw_fd(),
+                    libc::SECCOMP_IOCTL_NOTIF_ADDFD,
+                    &mut addfd as *mut libc::seccomp_notif_addfd,
+                )
+            };
+            let err = io::Error::last_os_error();
+            if added >= 0 || err.raw_os_error() == Some(libc::ENOENT) {
+                return;
+            }
+            eprintln!(
+                "awf-envguard: warning: cannot pass a redacted environment: {}",
+                err
+            );
+            response.error = -libc::EACCES;
+        }
+    }
+    // SAFETY: response is a valid pointer for the duration of the call; a
+    // caller that died or was interrupted (ENOENT) needs no answer
+    unsafe {
+        libc::ioctl(
+            listener.as_raw_fd(),
+            libc::SECCOMP_IOCTL_NOTIF_SEND,
+            &mut response as *mut libc::seccomp_notif_resp,
+        )
+    };
+}
+
+/// Answer the filter's notifications until the listener fails
+fn serve(listener: OwnedFd, policy: Policy) {
+    loop {
+        // SAFETY: an all-zero seccomp_notif is valid, and RECV requires it
+        let mut notif: libc::seccomp_notif = unsafe { std::mem::zeroed() };
+        // SAFETY: notif is a valid out pointer
+        let received = unsafe {
+            libc::ioctl(
+                listener.as_raw_fd(),
+                libc::SECCOMP_IOCTL_NOTIF_RECV,
+                &mut notif as *mut libc::seccomp_notif,
+            )
+        };
+        if received != 0 {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::EINTR | libc::ENOENT) => continue,
+                _ => {
+                    eprintln!("awf-envguard: warning: cannot receive opens: {}", err);
+                    return;
+                }
+            }
+        }
+        let answer = answer(&listener, &notif, &policy);
+        respond(&listener, notif.id, answer);
+    }
+}
+
+fn fail(status: i32, message: &str) -> ! {
+    eprintln!("awf-envguard: {}", message);
+    std::process::exit(status);
+}
+
+/// Run `command` with opens mediated, reaping every process that ends up
+/// below this one, and return its exit status
+pub fn run(command: &[OsString], policy: Policy) -> i32 {
+    let display = PathBuf::from(&command[0]);
+    let display = display.display();
+    let (child, socket, errors) = spawn(command)
+        .unwrap_or_else(|err| fail(EXIT_FAILED, &format!("cannot start {}: {}", display, err)));
+    let listener = receive_fd(&socket);
+    drop(socket);
+    if let Some(listener) = listener {
+        std::thread::spawn(move || serve(listener, policy));
+    }
+    // The pipe closes at the exec, or reports why there was none
+    if let Some((stage, err)) = start_error(errors) {
+        if stage == STAGE_FILTER {
+            fail(
+                EXIT_FAILED,
+                &format!("cannot install the seccomp filter: {}", err),
+            );
+        }
+        match err.kind() {
+            io::ErrorKind::NotFound => {
+                fail(EXIT_NOT_FOUND, &format!("{}: command not found", display))
+            }
+            _ => fail(EXIT_CANNOT_EXECUTE, &format!("{}: {}", display, err)),
+        }
+    }
+    forward_signals(child as u32);
+
+    loop {
+        let mut status = 0;
+        // SAFETY: status is a valid out pointer
+        let pid = unsafe { libc::waitpid(-1, &mut status, 0) };
+        if pid < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            fail(EXIT_FAILED, &format!("waiting for {}: {}", display, err));
+        }
+        if pid != child {
+            continue;
+        }
+        if libc::WIFEXITED(status) {
+            return libc::WEXITSTATUS(status);
+        }
+        if libc::WIFSIGNALED(status) {
+            return 128 + libc::WTERMSIG(status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a filter on a system call
+    fn evaluate(program: &[sock_filter], arch: u32, nr: u32) -> u32 {
+        let mut acc = 0;
+        let mut pc = 0;
+        loop {
+            let insn = program[pc];
+            match insn.code as u32 {
+                code if code == libc::BPF_LD | libc::BPF_W | libc::BPF_ABS => {
+                    acc = if insn.k as usize == offset_of!(libc::seccomp_data, arch) {
+                        arch
+                    } else {
+                        nr
+                    };
+                }
+                code if code == libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K => {
+                    pc += if acc == insn.k { insn.jt } else { insn.jf } as usize;
+                }
+                code if code == libc::BPF_RET | libc::BPF_K => return insn.k,
+                code => panic!("unexpected instruction {:#x}", code),
+            }
+            pc += 1;
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let program = filter();
+        for abi in ABIS {
+            for &(nr, kind) in abi.calls {
+                assert_eq!(
+                    evaluate(&program, abi.arch, nr),
+                    libc::SECCOMP_RET_USER_NOTIF
+                );
+                assert_eq!(call(abi.arch, nr), Some(kind));
+            }
+            assert_eq!(evaluate(&program, abi.arch, 0), libc::SECCOMP_RET_ALLOW);
+            assert_eq!(call(abi.arch, 0), None);
+        }
+        assert_eq!(
+            evaluate(&program, 0xdead, ABIS[0].calls[0].0),
+            libc::SECCOMP_RET_ALLOW
+        );
+    }
+
+    #[test]
+    fn test_call_args() {
+        let args = [3, 7, 9, 0, 0, 0];
+        assert_eq!(Call::Open.path(&args), 3);
+        assert_eq!(Call::Open.dirfd(&args), libc::AT_FDCWD);
+        assert_eq!(Call::OpenAt.path(&args), 7);
+        assert_eq!(Call::OpenAt2.dirfd(&args), 3);
+    }
+}
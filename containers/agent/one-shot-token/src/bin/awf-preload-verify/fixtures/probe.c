@@ -0,0 +1,48 @@
+/* awf-preload-verify fixture: C, through libc */
+#define _GNU_SOURCE
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static char needle[128];
+
+/* Whether the output of `command` or the file at `path` holds the needle */
+static const char *scan(FILE *file) {
+    if (!file) {
+        return "error";
+    }
+    char buf[65536];
+    size_t len = fread(buf, 1, sizeof(buf) - 1, file);
+    return memmem(buf, len, needle, strlen(needle)) ? "found" : "clean";
+}
+
+static const char *classify(const char *value) {
+    if (!value) {
+        return "null";
+    }
+    return strstr(value, needle) ? "value" : "other";
+}
+
+int main(void) {
+    if (!fgets(needle, sizeof(needle), stdin)) {
+        return 2;
+    }
+    needle[strcspn(needle, "\n")] = 0;
+
+    printf("getenv=%s\n", classify(getenv("GITHUB_TOKEN")));
+    printf("reread=%s\n", classify(getenv("GITHUB_TOKEN")));
+    printf("denied=%s\n", classify(getenv("AWF_VERIFY_DENIED")));
+
+    FILE *environ_file = fopen("/proc/self/environ", "r");
+    printf("environ=%s\n", scan(environ_file));
+    if (environ_file) {
+        fclose(environ_file);
+    }
+    fflush(stdout);
+    FILE *child = popen("env", "r");
+    printf("child=%s\n", scan(child));
+    if (child) {
+        pclose(child);
+    }
+    return 0;
+}
@@ -0,0 +1,20 @@
+// awf-preload-verify fixture: Node.js, whose process.env calls getenv
+const { execFileSync } = require("child_process");
+const fs = require("fs");
+
+const needle = fs.readFileSync(0, "utf8").split("\n")[0];
+const classify = (value) =>
+  value === undefined ? "null" : value.includes(needle) ? "value" : "other";
+const scan = (read) => {
+  try {
+    return read().includes(needle) ? "found" : "clean";
+  } catch (err) {
+    return "error";
+  }
+};
+
+console.log(`getenv=${classify(process.env.GITHUB_TOKEN)}`);
+console.log(`reread=${classify(process.env.GITHUB_TOKEN)}`);
+console.log(`denied=${classify(process.env.AWF_VERIFY_DENIED)}`);
+console.log(`environ=${scan(() => fs.readFileSync("/proc/self/environ", "latin1"))}`);
+console.log(`child=${scan(() => execFileSync("env", { encoding: "latin1" }))}`);
@@ -0,0 +1,35 @@
+# awf-preload-verify fixture: Python, whose os.environ is a copy taken at
+# startup, so getenv is called through ctypes
+import ctypes
+import subprocess
+import sys
+
+needle = sys.stdin.readline().strip().encode()
+libc = ctypes.CDLL(None)
+libc.getenv.restype = ctypes.c_char_p
+libc.getenv.argtypes = [ctypes.c_char_p]
+
+
+def classify(value):
+    if value is None:
+        return "null"
+    return "value" if needle in value else "other"
+
+
+def scan(read):
+    try:
+        return "found" if needle in read() else "clean"
+    except OSError:
+        return "error"
+
+
+def read_environ():
+    with open("/proc/self/environ", "rb") as environ:
+        return environ.read()
+
+
+print("getenv=" + classify(libc.getenv(b"GITHUB_TOKEN")))
+print("reread=" + classify(libc.getenv(b"GITHUB_TOKEN")))
+print("denied=" + classify(libc.getenv(b"AWF_VERIFY_DENIED")))
+print("environ=" + scan(read_environ))
+print("child=" + scan(lambda: subprocess.run(["env"], capture_output=True).stdout))
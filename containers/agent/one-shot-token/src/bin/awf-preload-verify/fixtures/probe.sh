@@ -0,0 +1,13 @@
+# awf-preload-verify fixture: POSIX shell, which copies the environment into
+# its own variables at startup without calling getenv
+read -r needle
+
+scan() {
+    case "$1" in
+        *"$needle"*) echo found ;;
+        *) echo clean ;;
+    esac
+}
+
+echo "environ=$(scan "$(tr '\0' '\n' < /proc/$$/environ)")"
+echo "child=$(scan "$(env)")"
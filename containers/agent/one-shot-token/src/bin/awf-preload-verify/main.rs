@@ -0,0 +1,560 @@
+//! awf-preload-verify: check the library end to end in the runtimes a
+//! container runs
+//!
+//! Usage: awf-preload-verify [--lib PATH] [--set NAME=VALUE]...
+//!                           [--fixture NAME]...
+//!
+//! The unit and integration tests exercise the library in the build
+//! environment; this driver checks the installed library in the image or on
+//! the runner where agents actually run. It starts a small fixture program
+//! in each runtime with the library preloaded and a made-up protected token
+//! in its environment, and prints a JSON report of what each one observed:
+//!
+//! - `getenv`, `reread`: the token is readable, twice
+//! - `environ`: its value is absent from /proc/self/environ
+//! - `child`: its value is absent from the environment of a child process
+//! - `denied`: a deny-listed variable (AWF_ONE_SHOT_DENY_TOKENS) reads as
+//!   unset
+//!
+//! The fixtures are bundled into this binary: `c` (compiled with $CC or
+//! `cc`), `shell` (/bin/sh), `node` and `python` (`python3`, through ctypes,
+//! since os.environ is a copy taken at startup). The shell has no getenv: it
+//! reads tokens from the variables it copied at startup, which eager
+//! scrubbing empties, so only `environ` and `child` are checked for it. A
+//! fixture whose runtime is missing is skipped; interpreters are resolved to
+//! their real executable first, so version manager shims do not exec them
+//! with a scrubbed environment. `--fixture` runs a subset and `--set` adds
+//! AWF_* configuration, such as a hardening profile.
+//!
+//! The library is `--lib` or found as for awf-preload. Token values are
+//! random for each run and never printed.
+//!
+//! Exit status: 0 if every fixture that ran passed, 1 if any failed, 2 on
+//! errors.
+
+#[path = "../common/mod.rs"]
+mod common;
+
+use common::{json_string, search_library, PRELOAD_VAR};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "Usage: awf-preload-verify [--lib PATH] [--set NAME=VALUE]... \
+[--fixture NAME]...";
+
+/// Exit status when a fixture failed
+const EXIT_FAILED: i32 = 1;
+/// Exit status on usage errors or when the library cannot be found
+const EXIT_ERROR: i32 = 2;
+
+/// PATH searched when the variable is unset
+const DEFAULT_PATH: &str = "/bin:/usr/bin";
+
+/// Protected token the fixtures read
+const TOKEN_VAR: &str = "GITHUB_TOKEN";
+/// Deny-listed variable the fixtures read
+const DENIED_VAR: &str = "AWF_VERIFY_DENIED";
+
+/// What each check must observe
+const EXPECTED: &[(&str, &str)] = &[
+    ("getenv", "value"),
+    ("reread", "value"),
+    ("environ", "clean"),
+    ("child", "clean"),
+    ("denied", "null"),
+];
+
+/// How a fixture is run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Runtime {
+    /// Compiled with the C compiler
+    C,
+    /// Run by /bin/sh
+    Shell,
+    /// Run by an interpreter, resolved with the arguments that print its
+    /// executable
+    Interpreter(&'static str, &'static [&'static str]),
+}
+
+/// A bundled fixture program
+struct Fixture {
+    name: &'static str,
+    file: &'static str,
+    source: &'static str,
+    runtime: Runtime,
+    checks: &'static [&'static str],
+}
+
+const FIXTURES: &[Fixture] = &[
+    Fixture {
+        name: "c",
+        file: "probe.c",
+        source: include_str!("fixtures/probe.c"),
+        runtime: Runtime::C,
+        checks: &["getenv", "reread", "environ", "child", "denied"],
+    },
+    Fixture {
+        name: "shell",
+        file: "probe.sh",
+        source: include_str!("fixtures/probe.sh"),
+        runtime: Runtime::Shell,
+        checks: &["environ", "child"],
+    },
+    Fixture {
+        name: "node",
+        file: "probe.js",
+        source: include_str!("fixtures/probe.js"),
+        runtime: Runtime::Interpreter("node", &["-p", "process.execPath"]),
+        checks: &["getenv", "reread", "environ", "child", "denied"],
+    },
+    Fixture {
+        name: "python",
+        file: "probe.py",
+        source: include_str!("fixtures/probe.py"),
+        runtime: Runtime::Interpreter("python3", &["-c", "import sys; print(sys.executable)"]),
+        checks: &["getenv", "reread", "environ", "child", "denied"],
+    },
+];
+
+/// Command-line options
+#[derive(Debug, PartialEq)]
+struct Options {
+    library: Option<PathBuf>,
+    config: Vec<(String, String)>,
+    fixtures: Vec<String>,
+}
+
+/// Parse the arguments after the program name
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        library: None,
+        config: Vec::new(),
+        fixtures: Vec::new(),
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--lib" => {
+                options.library = Some(PathBuf::from(args.next().ok_or("--lib needs a value")?))
+            }
+            "--set" => {
+                let setting = args.next().ok_or("--set needs a value")?;
+                let Some((name, value)) = setting.split_once('=') else {
+                    return Err(format!("--set {}: expected NAME=VALUE", setting));
+                };
+                if !name.starts_with("AWF_") {
+                    return Err(format!("--set {}: not a library setting", name));
+                }
+                options.config.push((name.to_string(), value.to_string()));
+            }
+            "--fixture" => {
+                let name = args.next().ok_or("--fixture needs a value")?;
+                if !FIXTURES.iter().any(|fixture| fixture.name == name) {
+                    return Err(format!("unknown fixture {}", name));
+                }
+                options.fixtures.push(name);
+            }
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+/// A random hex string, unique to this run
+fn random_hex() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos());
+    hasher.write_u128(nanos);
+    format!("{:016x}", hasher.finish())
+}
+
+/// The result of one check
+#[derive(Debug, PartialEq)]
+struct Check {
+    name: &'static str,
+    expected: &'static str,
+    observed: String,
+}
+
+impl Check {
+    fn ok(&self) -> bool {
+        self.observed == self.expected
+    }
+
+    fn json(&self) -> String {
+        format!(
+            "{{\"name\":{},\"expected\":{},\"observed\":{},\"ok\":{}}}",
+            json_string(self.name),
+            json_string(self.expected),
+            json_string(&self.observed),
+            self.ok()
+        )
+    }
+}
+
+/// How a fixture ended
+#[derive(Debug, PartialEq)]
+enum Status {
+    Pass,
+    Fail(Option<String>),
+    Skipped(String),
+}
+
+/// The result of one fixture
+struct Outcome {
+    name: &'static str,
+    status: Status,
+    checks: Vec<Check>,
+}
+
+impl Outcome {
+    fn json(&self) -> String {
+        let (status, reason) = match &self.status {
+            Status::Pass => ("pass", None),
+            Status::Fail(reason) => ("fail", reason.as_deref()),
+            Status::Skipped(reason) => ("skipped", Some(reason.as_str())),
+        };
+        let checks: Vec<String> = self.checks.iter().map(Check::json).collect();
+        format!(
+            "{{\"name\":{},\"status\":{},\"reason\":{},\"checks\":[{}]}}",
+            json_string(self.name),
+            json_string(status),
+            reason.map_or("null".to_string(), json_string),
+            checks.join(",")
+        )
+    }
+}
+
+/// Compare the `key=value` lines a fixture printed with the expectations
+/// for its `checks`; a check it did not report is `missing`
+fn evaluate(output: &str, checks: &[&'static str]) -> Vec<Check> {
+    checks
+        .iter()
+        .map(|&name| {
+            let expected = EXPECTED
+                .iter()
+                .find(|(check, _)| *check == name)
+                .map_or("", |(_, expected)| *expected);
+            let observed = output
+                .lines()
+                .filter_map(|line| line.split_once('='))
+                .find(|(key, _)| *key == name)
+                .map_or("missing", |(_, value)| value.trim());
+            Check {
+                name,
+                expected,
+                observed: observed.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// The real executable of an interpreter, found on `path` and asked for
+/// without the library loaded, or None if it is not installed
+fn resolve_interpreter(program: &str, args: &[&str], path: &str) -> Option<PathBuf> {
+    let output = Command::new(program)
+        .args(args)
+        .env("PATH", path)
+        .env_remove(PRELOAD_VAR)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let executable = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Path::new(&executable)
+        .is_absolute()
+        .then(|| PathBuf::from(executable))
+}
+
+/// The command that runs `fixture`, written to `dir`, or why it is skipped
+fn prepare(fixture: &Fixture, dir: &Path, path: &str) -> Result<Command, Status> {
+    let script = dir.join(fixture.file);
+    std::fs::write(&script, fixture.source)
+        .map_err(|err| Status::Fail(Some(format!("{}: {}", script.display(), err))))?;
+    match fixture.runtime {
+        Runtime::C => {
+            let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+            let program = dir.join(fixture.name);
+            let output = Command::new(&compiler)
+                .arg("-o")
+                .arg(&program)
+                .arg(&script)
+                .env("PATH", path)
+                .env_remove(PRELOAD_VAR)
+                .stdin(Stdio::null())
+                .output();
+            match output {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    Err(Status::Skipped(format!("{} not found", compiler)))
+                }
+                Err(err) => Err(Status::Fail(Some(format!("{}: {}", compiler, err)))),
+                Ok(output) if !output.status.success() => Err(Status::Fail(Some(format!(
+                    "{} failed: {}",
+                    compiler,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )))),
+                Ok(_) => Ok(Command::new(program)),
+            }
+        }
+        Runtime::Shell => {
+            let mut command = Command::new("/bin/sh");
+            command.arg(script);
+            Ok(command)
+        }
+        Runtime::Interpreter(program, args) => {
+            let interpreter = resolve_interpreter(program, args, path)
+                .ok_or_else(|| Status::Skipped(format!("{} not found", program)))?;
+            let mut command = Command::new(interpreter);
+            command.arg(script);
+            Ok(command)
+        }
+    }
+}
+
+/// Everything a fixture run needs
+struct Run<'a> {
+    library: &'a Path,
+    config: &'a [(String, String)],
+    dir: &'a Path,
+    path: &'a str,
+    needle: &'a str,
+}
+
+impl Run<'_> {
+    /// Run `fixture` with the library and a fresh token
+    fn fixture(&self, fixture: &Fixture) -> Outcome {
+        let mut command = match prepare(fixture, self.dir, self.path) {
+            Ok(command) => command,
+            Err(status) => {
+                return Outcome {
+                    name: fixture.name,
+                    status,
+                    checks: Vec::new(),
+                }
+            }
+        };
+        let value = format!("ghp_awfverify{}", self.needle);
+        command
+            .env_clear()
+            .env("PATH", self.path)
+            .env(PRELOAD_VAR, self.library)
+            .env(TOKEN_VAR, &value)
+            .env(DENIED_VAR, &value)
+            .env("AWF_ONE_SHOT_DENY_TOKENS", DENIED_VAR)
+            .envs(self.config.iter().map(|(name, value)| (name, value)))
+            .current_dir(self.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let result = command.spawn().and_then(|mut child| {
+            // The needle goes through stdin, so the fixture's own argv and
+            // environment do not hold it
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = writeln!(stdin, "{}", self.needle);
+            }
+            child.wait_with_output()
+        });
+        let output = match result {
+            Ok(output) => output,
+            Err(err) => {
+                return Outcome {
+                    name: fixture.name,
+                    status: Status::Fail(Some(format!("cannot run: {}", err))),
+                    checks: Vec::new(),
+                }
+            }
+        };
+        let checks = evaluate(&String::from_utf8_lossy(&output.stdout), fixture.checks);
+        let status = if !output.status.success() {
+            Status::Fail(Some(format!("exited with {}", output.status)))
+        } else if checks.iter().all(Check::ok) {
+            Status::Pass
+        } else {
+            Status::Fail(None)
+        };
+        Outcome {
+            name: fixture.name,
+            status,
+            checks,
+        }
+    }
+}
+
+/// The report of a whole run
+fn report(library: &Path, outcomes: &[Outcome]) -> String {
+    let fixtures: Vec<String> = outcomes.iter().map(Outcome::json).collect();
+    format!(
+        "{{\"version\":{},\"library\":{},\"ok\":{},\"fixtures\":[{}]}}",
+        json_string(env!("CARGO_PKG_VERSION")),
+        json_string(&library.display().to_string()),
+        passed(outcomes),
+        fixtures.join(",")
+    )
+}
+
+/// Whether no fixture failed
+fn passed(outcomes: &[Outcome]) -> bool {
+    !outcomes
+        .iter()
+        .any(|outcome| matches!(outcome.status, Status::Fail(_)))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if matches!(args.first().map(String::as_str), Some("-h" | "--help")) {
+        println!("{}", USAGE);
+        return;
+    }
+    let options = parse_args(args).unwrap_or_else(|err| {
+        eprintln!("awf-preload-verify: {}\n{}", err, USAGE);
+        std::process::exit(EXIT_ERROR);
+    });
+    let library = options
+        .library
+        .map_or_else(search_library, Ok)
+        .and_then(|library| {
+            std::fs::canonicalize(&library).map_err(|err| format!("{}: {}", library.display(), err))
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("awf-preload-verify: {}", err);
+            std::process::exit(EXIT_ERROR);
+        });
+
+    let dir = std::env::temp_dir().join(format!("awf-preload-verify-{}", std::process::id()));
+    if let Err(err) = std::fs::create_dir_all(&dir) {
+        eprintln!("awf-preload-verify: {}: {}", dir.display(), err);
+        std::process::exit(EXIT_ERROR);
+    }
+    let path = std::env::var("PATH").unwrap_or_else(|_| DEFAULT_PATH.to_string());
+    let needle = format!("{}{}", random_hex(), random_hex());
+    let run = Run {
+        library: &library,
+        config: &options.config,
+        dir: &dir,
+        path: &path,
+        needle: &needle,
+    };
+    let outcomes: Vec<Outcome> = FIXTURES
+        .iter()
+        .filter(|fixture| {
+            options.fixtures.is_empty() || options.fixtures.iter().any(|name| name == fixture.name)
+        })
+        .map(|fixture| run.fixture(fixture))
+        .collect();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    println!("{}", report(&library, &outcomes));
+    for outcome in &outcomes {
+        let status = match &outcome.status {
+            Status::Pass => "pass".to_string(),
+            Status::Fail(None) => "fail".to_string(),
+            Status::Fail(Some(reason)) => format!("fail ({})", reason),
+            Status::Skipped(reason) => format!("skipped ({})", reason),
+        };
+        eprintln!("awf-preload-verify: {}: {}", outcome.name, status);
+    }
+    if !passed(&outcomes) {
+        std::process::exit(EXIT_FAILED);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse_args(args(&[
+            "--lib",
+            "/opt/one-shot-token.so",
+            "--set",
+            "AWF_ONE_SHOT_PROFILE=paranoid",
+            "--fixture",
+            "python",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                library: Some(PathBuf::from("/opt/one-shot-token.so")),
+                config: vec![("AWF_ONE_SHOT_PROFILE".to_string(), "paranoid".to_string())],
+                fixtures: vec!["python".to_string()],
+            }
+        );
+        assert!(parse_args(args(&["--set", "PATH=/tmp"])).is_err());
+        assert!(parse_args(args(&["--set", "AWF_ONE_SHOT_EAGER"])).is_err());
+        assert!(parse_args(args(&["--fixture", "ruby"])).is_err());
+        assert!(parse_args(args(&["--lib"])).is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let checks = evaluate(
+            "getenv=value\nreread=null\nenviron=clean\n",
+            &["getenv", "reread", "environ", "child"],
+        );
+        let observed: Vec<(&str, &str, bool)> = checks
+            .iter()
+            .map(|check| (check.name, check.observed.as_str(), check.ok()))
+            .collect();
+        assert_eq!(
+            observed,
+            vec![
+                ("getenv", "value", true),
+                ("reread", "null", false),
+                ("environ", "clean", true),
+                ("child", "missing", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let outcomes = vec![
+            Outcome {
+                name: "c",
+                status: Status::Pass,
+                checks: evaluate("denied=null", &["denied"]),
+            },
+            Outcome {
+                name: "node",
+                status: Status::Skipped("node not found".to_string()),
+                checks: Vec::new(),
+            },
+        ];
+        let report = report(Path::new("/opt/one-shot-token.so"), &outcomes);
+        assert!(report.contains("\"ok\":true"));
+        assert!(report.contains(
+            "{\"name\":\"c\",\"status\":\"pass\",\"reason\":null,\"checks\":[{\"name\":\"denied\",\
+             \"expected\":\"null\",\"observed\":\"null\",\"ok\":true}]}"
+        ));
+        assert!(report.contains("\"status\":\"skipped\",\"reason\":\"node not found\""));
+
+        let failed = [Outcome {
+            name: "shell",
+            status: Status::Fail(None),
+            checks: Vec::new(),
+        }];
+        assert!(!passed(&failed));
+    }
+
+    #[test]
+    fn test_random_hex() {
+        let (first, second) = (random_hex(), random_hex());
+        assert_eq!(first.len(), 16);
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,244 @@
+//! Installation into /etc/ld.so.preload
+//!
+//! LD_PRELOAD only reaches the processes that inherit it: a program started
+//! with a scrubbed environment (`env -i`, sudo, many sandboxes) runs without
+//! the library. The dynamic linker also reads /etc/ld.so.preload, for every
+//! dynamically linked program whatever its environment. `--install` lists
+//! the library build for this machine there, under the root given with
+//! `--root` (default /):
+//!
+//! - The library is located and checked as for a command, digests included
+//! - Builds of the library already listed are replaced; other entries are
+//!   kept, after it
+//! - The new contents are written to a temporary file and renamed over the
+//!   old one, so the dynamic linker never sees a partial file
+//! - With root /, a dynamically linked program is started with an empty
+//!   environment and the confirmation pipe. Unless the library confirms it
+//!   loaded, the previous contents are restored (or the file removed if
+//!   there was none)
+//!
+//! `--uninstall` removes builds of the library from the file, and the file
+//! itself once nothing else is listed.
+
+use crate::common::LIBRARY_NAMES;
+use crate::{
+    confirmation_pipe, host_arch, program_header, read_confirmation, Arch, Confirmation, Header,
+};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// The preload file, relative to the root
+const PRELOAD_FILE: &str = "etc/ld.so.preload";
+
+/// Whether `entry` of the preload file is a build of the library
+fn is_build(entry: &str) -> bool {
+    let name = Path::new(entry)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    LIBRARY_NAMES.contains(&name) || Arch::ALL.iter().any(|arch| arch.variant() == name)
+}
+
+/// Entries of a preload file that are not builds of the library, in order
+///
+/// The dynamic linker separates entries with white space or colons.
+fn other_entries(contents: &str) -> Vec<&str> {
+    contents
+        .split(|c: char| c == ':' || c.is_ascii_whitespace())
+        .filter(|entry| !entry.is_empty() && !is_build(entry))
+        .collect()
+}
+
+/// The preload file listing `library` first, then the other entries of
+/// `current`, one per line
+fn installed_contents(library: &Path, current: &str) -> String {
+    let mut contents = format!("{}\n", library.display());
+    for entry in other_entries(current) {
+        contents.push_str(entry);
+        contents.push('\n');
+    }
+    contents
+}
+
+/// The preload file without builds of the library, or None if nothing else
+/// is listed
+fn uninstalled_contents(current: &str) -> Option<String> {
+    let entries = other_entries(current);
+    (!entries.is_empty()).then(|| entries.iter().map(|entry| format!("{}\n", entry)).collect())
+}
+
+/// The preload file under `root`
+fn preload_file(root: &Path) -> PathBuf {
+    root.join(PRELOAD_FILE)
+}
+
+/// The current contents of the preload file, or None if there is none
+fn read_preload(path: &Path) -> Result<Option<String>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("{}: {}", path.display(), err)),
+    }
+}
+
+/// Replace the preload file with `contents`, or remove it for None
+fn write_preload(path: &Path, contents: Option<&str>) -> Result<(), String> {
+    let error = |err: io::Error| format!("{}: {}", path.display(), err);
+    let Some(contents) = contents else {
+        return match std::fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(error(err)),
+            _ => Ok(()),
+        };
+    };
+    let temporary = path.with_extension("preload.awf-tmp");
+    let written = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o644)
+        .open(&temporary)
+        .and_then(|mut file: File| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&temporary, path));
+    if let Err(err) = written {
+        let _ = std::fs::remove_file(&temporary);
+        return Err(error(err));
+    }
+    Ok(())
+}
+
+/// A dynamically linked program of this machine to verify the install with,
+/// and its arguments
+fn verify_program() -> Option<(PathBuf, &'static [&'static str])> {
+    let candidates: [(Option<PathBuf>, &'static [&'static str]); 2] = [
+        (std::env::current_exe().ok(), &["--help"]),
+        (Some(PathBuf::from("/bin/sh")), &["-c", ":"]),
+    ];
+    candidates.into_iter().find_map(|(program, args)| {
+        let program = program?;
+        match program_header(&program, false)? {
+            Header::Elf {
+                arch,
+                dynamic: true,
+                ..
+            } if arch == host_arch() => Some((program, args)),
+            _ => None,
+        }
+    })
+}
+
+/// Check that a program started without LD_PRELOAD loads the library
+fn verify(timeout: Duration) -> Result<(), String> {
+    let (program, args) = verify_program()
+        .ok_or_else(|| "no dynamically linked program to verify the install with".to_string())?;
+    let (mut pipe, read_fd, write_fd) =
+        confirmation_pipe().map_err(|err| format!("cannot create pipe: {}", err))?;
+    let spawned = Command::new(&program)
+        .args(args)
+        .env_clear()
+        .env(crate::READY_VAR, write_fd.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    // SAFETY: the write end now belongs to the program only
+    unsafe { libc::close(write_fd) };
+    let mut child = spawned.map_err(|err| format!("{}: {}", program.display(), err))?;
+    let confirmation = read_confirmation(&mut pipe, read_fd, timeout);
+    let _ = child.kill();
+    let _ = child.wait();
+    match confirmation {
+        Confirmation::Loaded { pid, .. } if pid == child.id() => Ok(()),
+        Confirmation::Loaded { pid, .. } => Err(format!(
+            "{} reported a load in process {}, not the one started",
+            program.display(),
+            pid
+        )),
+        Confirmation::Closed => Err(format!("{} ran without it", program.display())),
+        Confirmation::TimedOut => Err(format!("no confirmation within {:?}", timeout)),
+        Confirmation::Invalid(line) => Err(format!("unexpected confirmation {:?}", line)),
+    }
+}
+
+/// List `library` in the preload file under `root`, restoring the previous
+/// contents if it does not load
+pub(crate) fn install(library: &Path, root: &Path, timeout: Duration) -> Result<String, String> {
+    let path = preload_file(root);
+    let previous = read_preload(&path)?;
+    let contents = installed_contents(library, previous.as_deref().unwrap_or(""));
+    write_preload(&path, Some(&contents))?;
+    if read_preload(&path)?.as_deref() != Some(contents.as_str()) {
+        write_preload(&path, previous.as_deref())?;
+        return Err(format!("{}: contents did not read back", path.display()));
+    }
+    let live = root.canonicalize().is_ok_and(|root| root == Path::new("/"));
+    if !live {
+        return Ok(format!(
+            "{} listed in {}; not verified outside the root file system",
+            library.display(),
+            path.display()
+        ));
+    }
+    if let Err(problem) = verify(timeout) {
+        write_preload(&path, previous.as_deref())?;
+        return Err(format!(
+            "{} did not load from {}: {}; restored the previous contents",
+            library.display(),
+            path.display(),
+            problem
+        ));
+    }
+    Ok(format!(
+        "{} listed in {} and verified",
+        library.display(),
+        path.display()
+    ))
+}
+
+/// Remove builds of the library from the preload file under `root`
+pub(crate) fn uninstall(root: &Path) -> Result<String, String> {
+    let path = preload_file(root);
+    let Some(current) = read_preload(&path)? else {
+        return Ok(format!("{} does not exist", path.display()));
+    };
+    let contents = uninstalled_contents(&current);
+    write_preload(&path, contents.as_deref())?;
+    Ok(match contents {
+        Some(_) => format!("library removed from {}", path.display()),
+        None => format!("{} removed", path.display()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preload_contents() {
+        let library = Path::new("/usr/local/lib/one-shot-token.so");
+        assert_eq!(
+            installed_contents(library, ""),
+            "/usr/local/lib/one-shot-token.so\n"
+        );
+        let current =
+            "/opt/other.so\n/old/libone_shot_token.so:/opt/more.so /lib/one-shot-token32.so\n";
+        assert_eq!(
+            installed_contents(library, current),
+            "/usr/local/lib/one-shot-token.so\n/opt/other.so\n/opt/more.so\n"
+        );
+        assert_eq!(
+            uninstalled_contents(current).as_deref(),
+            Some("/opt/other.so\n/opt/more.so\n")
+        );
+        assert_eq!(
+            uninstalled_contents("/usr/local/lib/one-shot-token.so\n"),
+            None
+        );
+    }
+}
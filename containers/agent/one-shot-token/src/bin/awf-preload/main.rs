@@ -0,0 +1,1192 @@
+//! awf-preload: run a command with the one-shot-token library loaded, or not
+//! at all
+//!
+//! Usage: awf-preload [--lib PATH] [--set NAME=VALUE]... [--timeout SECONDS]
+//!                    [--] COMMAND [ARGS...]
+//!        awf-preload --validate [--lib PATH] [--set NAME=VALUE]...
+//!        awf-preload --install [--lib PATH] [--root DIR] [--timeout SECONDS]
+//!        awf-preload --uninstall [--root DIR]
+//!
+//! Setting LD_PRELOAD by hand fails open: the dynamic linker only prints a
+//! warning for a mistyped path, a build of the wrong architecture or a
+//! statically linked program, and the command runs unprotected. This launcher
+//! checks the library before the command starts and confirms afterwards that
+//! it was loaded:
+//!
+//! - The library is `--lib`, or `one-shot-token.so` (`libone_shot_token.so`)
+//!   found next to this binary, in `../lib` relative to it or in
+//!   /usr/local/lib. The builds named by AWF_ONE_SHOT_LIB32, LIB64 and
+//!   LIB_AARCH64 and the variants next to the library are used for programs
+//!   of other architectures, as preload.sh does. Every path must be a shared
+//!   library; the command is not started otherwise.
+//! - With expected SHA-256 digests, every build must hash to one of them, so
+//!   a tampered or swapped library is refused instead of loading and
+//!   protecting nothing. Digests come from AWF_ONE_SHOT_LIB_SHA256 (set by the
+//!   host or with `--set`) and from the same variable at build time, embedded
+//!   in this binary; when both are given a build must match both lists.
+//! - `--set` adds AWF_* configuration to the command's environment.
+//! - The write end of a pipe is passed in AWF_ONE_SHOT_READY_FD. The library
+//!   writes `ready <pid> <version>` to it when the dynamic linker runs its
+//!   constructors. Without that line from the command's own process within
+//!   `--timeout` seconds (default 10), the command is killed.
+//!
+//! A statically linked program cannot load the library. On Linux it is run
+//! under a ptrace supervisor instead (see the supervise module).
+//!
+//! On macOS the library is `one-shot-token.dylib`, a universal build, and is
+//! inserted with DYLD_INSERT_LIBRARIES.
+//!
+//! Exit status: the command's, or 128 + the signal that ended it; 125 if the
+//! library could not be found or did not load; 126 if the command could not
+//! be run and 127 if it was not found. INT, TERM, HUP and QUIT are passed on
+//! to the command.
+//!
+//! `--validate` runs no command: the library build for this machine is
+//! dlopen-ed with the configuration of this process and `--set`, and its
+//! health check (awf_one_shot_healthcheck) is printed as JSON on stdout, with
+//! a verdict on stderr. Missing required tokens are reported, not fatal. The library also logs every configuration problem as
+//! it loads. Exit status 0 if the library loaded without problems, 125
+//! otherwise.
+//!
+//! `--install` lists the library in /etc/ld.so.preload instead, so that
+//! programs started without LD_PRELOAD load it too, and `--uninstall` takes
+//! it out again (see the install module). Linux only.
+
+#[path = "../common/mod.rs"]
+mod common;
+#[allow(dead_code)]
+#[path = "../../detect.rs"]
+mod detect;
+#[cfg(not(target_os = "macos"))]
+mod install;
+#[allow(dead_code)]
+#[path = "../../json.rs"]
+mod json;
+#[allow(dead_code)]
+#[path = "../../sha256.rs"]
+mod sha256;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64")
+))]
+mod supervise;
+#[allow(dead_code)]
+#[path = "../../sys.rs"]
+mod sys;
+
+use common::{forward_signals, search_library, PRELOAD_VAR};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, OsString};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::FromRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage: awf-preload [--lib PATH] [--set NAME=VALUE]... \
+[--timeout SECONDS] [--] COMMAND [ARGS...]
+       awf-preload --validate [--lib PATH] [--set NAME=VALUE]...
+       awf-preload --install [--lib PATH] [--root DIR] [--timeout SECONDS]
+       awf-preload --uninstall [--root DIR]";
+
+/// Exit status when the library cannot be found or did not load
+const EXIT_FAILED: i32 = 125;
+/// Exit status when the command cannot be executed
+const EXIT_CANNOT_EXECUTE: i32 = 126;
+/// Exit status when the command is not found
+const EXIT_NOT_FOUND: i32 = 127;
+
+/// How long the library has to confirm the load by default
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Variable that passes the confirmation pipe to the library
+const READY_VAR: &str = "AWF_ONE_SHOT_READY_FD";
+
+/// Variable with the expected SHA-256 digests of the library builds
+const DIGEST_VAR: &str = "AWF_ONE_SHOT_LIB_SHA256";
+
+/// Expected digests embedded at build time
+const BUILT_IN_DIGESTS: Option<&str> = option_env!("AWF_ONE_SHOT_LIB_SHA256");
+
+/// PATH searched when the variable is unset
+const DEFAULT_PATH: &str = "/bin:/usr/bin";
+
+/// Bytes read from the start of a file to classify it, enough for the program
+/// headers of an ELF program
+const HEADER_LEN: usize = 4096;
+
+/// ELF program header type naming the program interpreter
+const PT_INTERP: u64 = 3;
+
+/// Architecture of a program or library build
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Arch {
+    I686,
+    X86_64,
+    Aarch64,
+}
+
+impl Arch {
+    const ALL: [Arch; 3] = [Arch::I686, Arch::X86_64, Arch::Aarch64];
+
+    /// The architecture of an ELF class (EI_CLASS) and machine (e_machine)
+    fn from_elf(class: u8, machine: u16) -> Option<Arch> {
+        match (class, machine) {
+            (1, 3) => Some(Arch::I686),
+            (2, 62) => Some(Arch::X86_64),
+            (2, 183) => Some(Arch::Aarch64),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Arch::I686 => "i686",
+            Arch::X86_64 => "x86_64",
+            Arch::Aarch64 => "aarch64",
+        }
+    }
+
+    /// Variable naming the build for this architecture
+    fn variable(self) -> &'static str {
+        match self {
+            Arch::I686 => "AWF_ONE_SHOT_LIB32",
+            Arch::X86_64 => "AWF_ONE_SHOT_LIB64",
+            Arch::Aarch64 => "AWF_ONE_SHOT_LIB_AARCH64",
+        }
+    }
+
+    /// File name build.sh gives the variant for this architecture
+    fn variant(self) -> &'static str {
+        match self {
+            Arch::I686 => "one-shot-token32.so",
+            Arch::X86_64 => "one-shot-token-x86_64.so",
+            Arch::Aarch64 => "one-shot-token-aarch64.so",
+        }
+    }
+}
+
+/// What the first bytes of a file say about it
+#[derive(Debug, PartialEq, Eq)]
+enum Header {
+    /// An ELF file: shared object (ET_DYN) or not, its architecture if
+    /// supported, and whether it names an interpreter (the dynamic linker),
+    /// as every dynamically linked program does. Assumed when the program
+    /// headers lie beyond the bytes read.
+    Elf {
+        shared: bool,
+        arch: Option<Arch>,
+        dynamic: bool,
+    },
+    /// A Mach-O file or universal binary
+    MachO,
+    /// A `#!` script, with the interpreter path
+    Script(PathBuf),
+    Other,
+}
+
+/// Classify a file from its first bytes
+fn parse_header(header: &[u8]) -> Header {
+    if header.starts_with(b"\x7fELF") {
+        // e_type and e_machine follow e_ident (16 bytes), in the byte order
+        // given by EI_DATA
+        let (Some(&class), Some(&data), Some(fields)) =
+            (header.get(4), header.get(5), header.get(16..20))
+        else {
+            return Header::Other;
+        };
+        let read = |bytes: &[u8]| match data {
+            1 => Some(u16::from_le_bytes([bytes[0], bytes[1]])),
+            2 => Some(u16::from_be_bytes([bytes[0], bytes[1]])),
+            _ => None,
+        };
+        let (Some(kind), Some(machine)) = (read(&fields[..2]), read(&fields[2..])) else {
+            return Header::Other;
+        };
+        return Header::Elf {
+            shared: kind == 3,
+            arch: Arch::from_elf(class, machine),
+            dynamic: has_interpreter(header, class, data).unwrap_or(true),
+        };
+    }
+    // MH_MAGIC_64 as stored on little-endian machines, and FAT_MAGIC
+    if header.starts_with(&[0xcf, 0xfa, 0xed, 0xfe])
+        || header.starts_with(&[0xca, 0xfe, 0xba, 0xbe])
+    {
+        return Header::MachO;
+    }
+    let Some(line) = header.strip_prefix(b"#!") else {
+        return Header::Other;
+    };
+    let interpreter = line.split(|&byte| byte == b'\n').next().and_then(|line| {
+        line.split(|byte| byte.is_ascii_whitespace())
+            .find(|word| !word.is_empty())
+    });
+    match interpreter {
+        Some(interpreter) => {
+            Header::Script(PathBuf::from(std::ffi::OsStr::from_bytes(interpreter)))
+        }
+        None => Header::Other,
+    }
+}
+
+/// Whether the ELF program headers in `header` include PT_INTERP, or None if
+/// they are not all there
+fn has_interpreter(header: &[u8], class: u8, data: u8) -> Option<bool> {
+    let read = |offset: u64, len: usize| {
+        let offset = usize::try_from(offset).ok()?;
+        let bytes = header.get(offset..offset.checked_add(len)?)?;
+        Some(
+            bytes
+                .iter()
+                .enumerate()
+                .fold(0u64, |value, (index, &byte)| {
+                    let shift = if data == 1 { index } else { len - 1 - index };
+                    value | u64::from(byte) << (shift * 8)
+                }),
+        )
+    };
+    // e_phoff, e_phentsize and e_phnum
+    let (offset, size, count) = match class {
+        1 => (read(28, 4)?, read(42, 2)?, read(44, 2)?),
+        2 => (read(32, 8)?, read(54, 2)?, read(56, 2)?),
+        _ => return None,
+    };
+    let mut found = false;
+    for index in 0..count {
+        found |= read(offset.checked_add(index * size)?, 4)? == PT_INTERP;
+    }
+    Some(found)
+}
+
+/// Read and classify the file at `path`
+fn read_header(path: &Path) -> io::Result<Header> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    File::open(path)?
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+    Ok(parse_header(&header))
+}
+
+/// Check that `path` is a library build, returning its architecture
+///
+/// Mach-O builds have none: the macOS build is universal.
+fn check_library(path: &Path) -> Result<Option<Arch>, String> {
+    let header = read_header(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    match header {
+        Header::Elf {
+            shared: true, arch, ..
+        } if !cfg!(target_os = "macos") => Ok(arch),
+        Header::MachO if cfg!(target_os = "macos") => Ok(None),
+        _ => Err(format!("{}: not a shared library", path.display())),
+    }
+}
+
+/// The ELF header of the program at `path`, following one `#!` line
+fn program_header(path: &Path, follow: bool) -> Option<Header> {
+    match read_header(path).ok()? {
+        header @ Header::Elf { .. } => Some(header),
+        Header::Script(interpreter) if follow => program_header(&interpreter, false),
+        _ => None,
+    }
+}
+
+/// Find `command` the way execvp will: as given if it contains a slash,
+/// otherwise in the first PATH directory holding an executable file
+fn resolve_command(command: &Path, path: Option<&str>) -> Option<PathBuf> {
+    if command.as_os_str().as_bytes().contains(&b'/') {
+        return Some(command.to_path_buf());
+    }
+    path.unwrap_or(DEFAULT_PATH).split(':').find_map(|dir| {
+        let dir = if dir.is_empty() { "." } else { dir };
+        let candidate = Path::new(dir).join(command);
+        let metadata = candidate.metadata().ok()?;
+        (metadata.is_file() && metadata.permissions().mode() & 0o111 != 0).then_some(candidate)
+    })
+}
+
+/// Command-line options
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    library: Option<PathBuf>,
+    config: Vec<(String, String)>,
+    timeout: Option<Duration>,
+    /// Check the configuration instead of running a command
+    validate: bool,
+    /// List the library in ld.so.preload instead of running a command
+    install: bool,
+    /// Remove the library from ld.so.preload
+    uninstall: bool,
+    /// Root file system of the ld.so.preload to change
+    root: Option<PathBuf>,
+    command: Vec<OsString>,
+}
+
+/// Parse the arguments after the program name
+fn parse_args(args: impl IntoIterator<Item = OsString>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.to_str() {
+            Some("--") => break,
+            Some("--lib") => options.library = Some(PathBuf::from(value("--lib")?)),
+            Some("--set") => {
+                let setting = value("--set")?;
+                let setting = setting
+                    .to_str()
+                    .ok_or_else(|| "--set needs a UTF-8 NAME=VALUE".to_string())?;
+                let Some((name, value)) = setting.split_once('=') else {
+                    return Err(format!("--set {}: expected NAME=VALUE", setting));
+                };
+                if !name.starts_with("AWF_") || name == READY_VAR {
+                    return Err(format!("--set {}: not a library setting", name));
+                }
+                options.config.push((name.to_string(), value.to_string()));
+            }
+            Some("--validate") => options.validate = true,
+            Some("--install") => options.install = true,
+            Some("--uninstall") => options.uninstall = true,
+            Some("--root") => options.root = Some(PathBuf::from(value("--root")?)),
+            Some("--timeout") => {
+                let seconds = value("--timeout")?;
+                let seconds = seconds
+                    .to_str()
+                    .and_then(|seconds| seconds.parse::<f64>().ok())
+                    .filter(|seconds| seconds.is_finite() && *seconds > 0.0)
+                    .ok_or_else(|| "--timeout needs a positive number of seconds".to_string())?;
+                options.timeout = Some(Duration::from_secs_f64(seconds));
+            }
+            Some(flag) if flag.starts_with('-') => {
+                return Err(format!("unknown option {}", flag));
+            }
+            _ => {
+                options.command.push(arg);
+                break;
+            }
+        }
+    }
+    options.command.extend(args);
+    let modes = [
+        (options.validate, "--validate"),
+        (options.install, "--install"),
+        (options.uninstall, "--uninstall"),
+    ];
+    let mut modes = modes.iter().filter(|(set, _)| *set).map(|(_, name)| *name);
+    match (modes.next(), modes.next()) {
+        (Some(first), Some(second)) => {
+            return Err(format!("{} and {} cannot be combined", first, second));
+        }
+        (Some(mode), None) if !options.command.is_empty() => {
+            return Err(format!("{} runs no command", mode));
+        }
+        (None, _) if options.command.is_empty() => return Err("no command given".to_string()),
+        _ => {}
+    }
+    if options.root.is_some() && !options.install && !options.uninstall {
+        return Err("--root needs --install or --uninstall".to_string());
+    }
+    Ok(options)
+}
+
+/// The library builds to choose from
+#[derive(Debug, Default)]
+struct Builds {
+    /// The library found or given with --lib
+    primary: PathBuf,
+    /// Builds for each architecture, including the primary one
+    by_arch: HashMap<Arch, PathBuf>,
+}
+
+impl Builds {
+    /// Locate and check the library and its variants
+    ///
+    /// `config` holds the AWF_* settings the command will see.
+    fn locate(library: Option<&Path>, config: &HashMap<String, String>) -> Result<Self, String> {
+        let primary = match library {
+            Some(library) => library.to_path_buf(),
+            None => search_library()?,
+        };
+        let mut by_arch = HashMap::new();
+        if let Some(arch) = check_library(&primary)? {
+            by_arch.insert(arch, primary.clone());
+        }
+        for arch in Arch::ALL {
+            // Configured builds must exist; variants next to the library may not
+            let configured = config
+                .get(arch.variable())
+                .filter(|path| !path.trim().is_empty());
+            let path = match configured {
+                Some(path) => PathBuf::from(path),
+                None if by_arch.contains_key(&arch) => continue,
+                None => match primary.parent().map(|dir| dir.join(arch.variant())) {
+                    Some(variant) if variant.exists() => variant,
+                    _ => continue,
+                },
+            };
+            match check_library(&path)? {
+                Some(found) if found == arch => {
+                    by_arch.insert(arch, path);
+                }
+                _ => {
+                    return Err(format!(
+                        "{}: not a build for {} programs",
+                        path.display(),
+                        arch.name()
+                    ))
+                }
+            }
+        }
+        Ok(Self { primary, by_arch })
+    }
+
+    /// Paths to preload for a program of `arch`
+    fn for_program(&self, arch: Option<Arch>) -> Result<Vec<&Path>, String> {
+        if cfg!(target_os = "macos") || self.by_arch.is_empty() {
+            return Ok(vec![&self.primary]);
+        }
+        match arch {
+            Some(arch) => match self.by_arch.get(&arch) {
+                Some(path) => Ok(vec![path]),
+                None => Err(format!(
+                    "no one-shot-token build for {} programs (set {})",
+                    arch.name(),
+                    arch.variable()
+                )),
+            },
+            // Unknown: let the dynamic linker load the build that fits
+            None => {
+                let mut paths = vec![self.primary.as_path()];
+                paths.extend(
+                    Arch::ALL
+                        .iter()
+                        .filter_map(|arch| self.by_arch.get(arch))
+                        .filter(|path| **path != self.primary)
+                        .map(PathBuf::as_path),
+                );
+                Ok(paths)
+            }
+        }
+    }
+
+    /// Every build path
+    fn paths(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.primary.as_path()).chain(self.by_arch.values().map(PathBuf::as_path))
+    }
+}
+
+/// Parse a comma-separated list of SHA-256 digests (64 hex digits, an
+/// optional `sha256:` prefix)
+fn parse_digests(list: &str) -> Result<Vec<[u8; 32]>, String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let hex = entry.strip_prefix("sha256:").unwrap_or(entry);
+            let mut digest = [0u8; 32];
+            let valid = hex.len() == 64
+                && digest
+                    .iter_mut()
+                    .zip(hex.as_bytes().chunks(2))
+                    .all(|(byte, pair)| {
+                        let pair = std::str::from_utf8(pair).unwrap_or_default();
+                        u8::from_str_radix(pair, 16)
+                            .map(|value| *byte = value)
+                            .is_ok()
+                    });
+            valid
+                .then_some(digest)
+                .ok_or_else(|| format!("{}: {:?} is not a SHA-256 digest", DIGEST_VAR, entry))
+        })
+        .collect()
+}
+
+/// The lists of expected digests: built in, and from `config`
+fn expected_digests(config: &HashMap<String, String>) -> Result<Vec<Vec<[u8; 32]>>, String> {
+    let lists = [BUILT_IN_DIGESTS, config.get(DIGEST_VAR).map(String::as_str)];
+    let mut expected = Vec::new();
+    for list in lists.into_iter().flatten() {
+        let digests = parse_digests(list)?;
+        if !digests.is_empty() {
+            expected.push(digests);
+        }
+    }
+    Ok(expected)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Check that every build hashes to a digest of each list in `expected`
+fn verify_digests<'a>(
+    paths: impl IntoIterator<Item = &'a Path>,
+    expected: &[Vec<[u8; 32]>],
+) -> Result<(), String> {
+    if expected.is_empty() {
+        return Ok(());
+    }
+    for path in paths {
+        let data = std::fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let digest = sha256::sha256(&data);
+        if !expected.iter().all(|digests| digests.contains(&digest)) {
+            return Err(format!(
+                "{}: SHA-256 {} is not an expected digest ({}); refusing a tampered or \
+                 swapped library",
+                path.display(),
+                hex(&digest),
+                DIGEST_VAR
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The preload value: `chosen` first, then the entries of `current` that are
+/// not builds of the library
+fn preload_value(chosen: &[&Path], current: Option<&str>, builds: &Builds) -> String {
+    let managed: Vec<&Path> = builds.paths().collect();
+    let mut entries: Vec<String> = chosen
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    entries.extend(
+        current
+            .unwrap_or("")
+            .split([':', ' '])
+            .filter(|entry| !entry.is_empty() && !managed.contains(&Path::new(entry)))
+            .map(str::to_string),
+    );
+    entries.join(":")
+}
+
+/// What the library reported through the confirmation pipe
+#[derive(Debug, PartialEq)]
+enum Confirmation {
+    /// Loaded in process `pid`, with protection disabled or not
+    Loaded {
+        pid: u32,
+        disabled: bool,
+    },
+    /// The pipe was closed without a line
+    Closed,
+    TimedOut,
+    Invalid(String),
+}
+
+/// Parse the line the library writes once loaded
+fn parse_confirmation(line: &str) -> Confirmation {
+    let mut words = line.split_whitespace();
+    let disabled = match words.next() {
+        Some("ready") => false,
+        Some("disabled") => true,
+        _ => return Confirmation::Invalid(line.trim().to_string()),
+    };
+    match words.next().and_then(|pid| pid.parse().ok()) {
+        Some(pid) => Confirmation::Loaded { pid, disabled },
+        None => Confirmation::Invalid(line.trim().to_string()),
+    }
+}
+
+/// Read one line from `pipe`, waiting at most `timeout`
+fn read_confirmation(pipe: &mut File, fd: libc::c_int, timeout: Duration) -> Confirmation {
+    let deadline = Instant::now() + timeout;
+    let mut line = Vec::new();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Confirmation::TimedOut;
+        }
+        let mut poll = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = remaining.as_millis().clamp(1, i32::MAX as u128) as libc::c_int;
+        // SAFETY: poll is a valid pollfd array of one entry
+        let ready = unsafe { libc::poll(&mut poll, 1, millis) };
+        if ready < 0 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Confirmation::Closed;
+        }
+        if ready == 0 {
+            continue;
+        }
+        let mut buf = [0u8; 128];
+        match pipe.read(&mut buf) {
+            Ok(0) if line.is_empty() => return Confirmation::Closed,
+            Ok(0) => break,
+            Ok(len) => line.extend_from_slice(&buf[..len]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return Confirmation::Closed,
+        }
+        if line.contains(&b'\n') {
+            break;
+        }
+    }
+    parse_confirmation(&String::from_utf8_lossy(&line))
+}
+
+/// Create the confirmation pipe: a close-on-exec read end and a write end
+/// the command inherits
+fn confirmation_pipe() -> io::Result<(File, libc::c_int, libc::c_int)> {
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for two descriptors; this process has no other
+    // threads that could exec while the write end is inheritable
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::fcntl(fds[0], libc::F_SETFD, libc::FD_CLOEXEC);
+        Ok((File::from_raw_fd(fds[0]), fds[0], fds[1]))
+    }
+}
+
+/// The architecture of this machine, as a library build
+fn host_arch() -> Option<Arch> {
+    if cfg!(target_arch = "x86_64") {
+        Some(Arch::X86_64)
+    } else if cfg!(target_arch = "x86") {
+        Some(Arch::I686)
+    } else if cfg!(target_arch = "aarch64") {
+        Some(Arch::Aarch64)
+    } else {
+        None
+    }
+}
+
+/// The health check of the library at `path`, loaded into this process
+fn healthcheck(path: &Path) -> Result<String, String> {
+    type Healthcheck = unsafe extern "C" fn(*mut libc::c_char, libc::size_t) -> libc::ssize_t;
+    let dlerror = || {
+        // SAFETY: dlerror returns NULL or a valid C string
+        let error = unsafe { libc::dlerror() };
+        if error.is_null() {
+            "unknown error".to_string()
+        } else {
+            // SAFETY: checked for NULL above
+            unsafe { CStr::from_ptr(error) }
+                .to_string_lossy()
+                .into_owned()
+        }
+    };
+    let name = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| format!("{}: invalid path", path.display()))?;
+    // SAFETY: valid C string; loading runs the library's constructors, which
+    // read this process's configuration
+    let library = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+    if library.is_null() {
+        return Err(dlerror());
+    }
+    // SAFETY: valid handle and C string
+    let symbol = unsafe { libc::dlsym(library, c"awf_one_shot_healthcheck".as_ptr()) };
+    if symbol.is_null() {
+        return Err(format!(
+            "{}: no health check, not a one-shot-token build or too old",
+            path.display()
+        ));
+    }
+    // SAFETY: the symbol is the function declared in one_shot_token.h
+    let healthcheck: Healthcheck = unsafe { std::mem::transmute(symbol) };
+    let mut buf = vec![0u8; 4096];
+    loop {
+        // SAFETY: buf is valid for writes of its length
+        let len = unsafe { healthcheck(buf.as_mut_ptr().cast(), buf.len()) };
+        let len = usize::try_from(len).map_err(|_| "health check failed".to_string())?;
+        if len < buf.len() {
+            buf.truncate(len);
+            return Ok(String::from_utf8_lossy(&buf).into_owned());
+        }
+        buf.resize(len + 1, 0);
+    }
+}
+
+/// The verdict on a health check: whether it passed, and why not
+fn verdict(status: &str) -> Result<(), String> {
+    let status = json::parse(status.as_bytes())?;
+    let count = |section: &str, list: &str| match status.get(section).and_then(|s| s.get(list)) {
+        Some(json::Value::Array(items)) => items.len(),
+        _ => 0,
+    };
+    if status.get("ok") == Some(&json::Value::Bool(true)) {
+        return Ok(());
+    }
+    Err(format!(
+        "{} configuration problem(s), {} missing libc function(s)",
+        count("config", "problems"),
+        count("symbols", "missing")
+    ))
+}
+
+/// Print the health check of the library with this configuration
+fn validate(options: Options) -> i32 {
+    let mut config: HashMap<String, String> = std::env::vars()
+        .filter(|(name, _)| name.starts_with("AWF_"))
+        .collect();
+    config.extend(options.config.iter().cloned());
+    let builds = Builds::locate(options.library.as_deref(), &config)
+        .and_then(|builds| verify_builds(&builds, &config).map(|()| builds))
+        .unwrap_or_else(|err| fail(EXIT_FAILED, &err));
+    let library = host_arch()
+        .and_then(|arch| builds.by_arch.get(&arch))
+        .unwrap_or(&builds.primary);
+    for (name, value) in &options.config {
+        std::env::set_var(name, value);
+    }
+    // Report missing required tokens rather than exit
+    std::env::set_var("AWF_ONE_SHOT_REQUIRED_ACTION", "log");
+    let status = healthcheck(library).unwrap_or_else(|err| fail(EXIT_FAILED, &err));
+    println!("{}", status);
+    match verdict(&status) {
+        Ok(()) => {
+            eprintln!("awf-preload: {}: configuration OK", library.display());
+            0
+        }
+        Err(problems) => fail(EXIT_FAILED, &format!("{}: {}", library.display(), problems)),
+    }
+}
+
+/// List the build for this machine in ld.so.preload, or remove it
+#[cfg(not(target_os = "macos"))]
+fn install(options: Options) -> i32 {
+    let root = options.root.unwrap_or_else(|| PathBuf::from("/"));
+    let result = if options.uninstall {
+        install::uninstall(&root)
+    } else {
+        let config: HashMap<String, String> = std::env::vars()
+            .filter(|(name, _)| name.starts_with("AWF_"))
+            .collect();
+        let builds = Builds::locate(options.library.as_deref(), &config)
+            .and_then(|builds| verify_builds(&builds, &config).map(|()| builds))
+            .unwrap_or_else(|err| fail(EXIT_FAILED, &err));
+        let library = host_arch()
+            .and_then(|arch| builds.by_arch.get(&arch))
+            .unwrap_or(&builds.primary);
+        let library = library
+            .canonicalize()
+            .unwrap_or_else(|err| fail(EXIT_FAILED, &format!("{}: {}", library.display(), err)));
+        install::install(&library, &root, options.timeout.unwrap_or(DEFAULT_TIMEOUT))
+    };
+    match result {
+        Ok(done) => {
+            eprintln!("awf-preload: {}", done);
+            0
+        }
+        Err(err) => fail(EXIT_FAILED, &err),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn install(_options: Options) -> i32 {
+    fail(EXIT_FAILED, "ld.so.preload is not used on macOS")
+}
+
+/// Check the digests of every build against the expected ones, if any
+fn verify_builds(builds: &Builds, config: &HashMap<String, String>) -> Result<(), String> {
+    verify_digests(builds.paths(), &expected_digests(config)?)
+}
+
+/// Print an error and exit with `status`
+fn fail(status: i32, message: &str) -> ! {
+    eprintln!("awf-preload: {}", message);
+    std::process::exit(status);
+}
+
+fn run(options: Options) -> i32 {
+    let mut config: HashMap<String, String> = std::env::vars()
+        .filter(|(name, _)| name.starts_with("AWF_"))
+        .collect();
+    config.extend(options.config.iter().cloned());
+    let builds = Builds::locate(options.library.as_deref(), &config)
+        .and_then(|builds| verify_builds(&builds, &config).map(|()| builds))
+        .unwrap_or_else(|err| fail(EXIT_FAILED, &err));
+
+    let command = Path::new(&options.command[0]);
+    let path = std::env::var("PATH").ok();
+    let program = resolve_command(command, path.as_deref());
+    let header = program
+        .as_deref()
+        .and_then(|program| program_header(program, true));
+    let (arch, dynamic) = match header {
+        Some(Header::Elf { arch, dynamic, .. }) => (arch, dynamic),
+        _ => (None, true),
+    };
+    let chosen = builds
+        .for_program(arch)
+        .unwrap_or_else(|err| fail(EXIT_FAILED, &format!("{}: {}", command.display(), err)));
+    let preload = preload_value(&chosen, std::env::var(PRELOAD_VAR).ok().as_deref(), &builds);
+
+    // What the command's environment adds to this process's
+    let mut env: Vec<(OsString, OsString)> = options
+        .config
+        .iter()
+        .map(|(name, value)| (name.into(), value.into()))
+        .collect();
+    env.push((PRELOAD_VAR.into(), preload.clone().into()));
+    if builds.by_arch.len() > 1 {
+        env.extend(
+            builds
+                .by_arch
+                .iter()
+                .map(|(arch, path)| (arch.variable().into(), path.into())),
+        );
+    }
+
+    if let (false, Some(program)) = (dynamic, &program) {
+        #[cfg(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64")
+        ))]
+        return supervise::run(program, &options.command, env, &config);
+        #[cfg(not(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64")
+        )))]
+        fail(
+            EXIT_FAILED,
+            &format!(
+                "{}: statically linked programs cannot be supervised on this platform",
+                program.display()
+            ),
+        );
+    }
+
+    let (mut pipe, read_fd, write_fd) = confirmation_pipe()
+        .unwrap_or_else(|err| fail(EXIT_FAILED, &format!("cannot create pipe: {}", err)));
+    let spawned = Command::new(&options.command[0])
+        .args(&options.command[1..])
+        .envs(env)
+        .env(READY_VAR, write_fd.to_string())
+        .spawn();
+    // SAFETY: the write end now belongs to the command only
+    unsafe { libc::close(write_fd) };
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => fail(
+            EXIT_NOT_FOUND,
+            &format!("{}: command not found", command.display()),
+        ),
+        Err(err) => fail(
+            EXIT_CANNOT_EXECUTE,
+            &format!("{}: {}", command.display(), err),
+        ),
+    };
+    forward_signals(child.id());
+
+    let timeout = options.timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let problem = match read_confirmation(&mut pipe, read_fd, timeout) {
+        Confirmation::Loaded { pid, disabled } if pid == child.id() => {
+            if disabled {
+                eprintln!("awf-preload: warning: protection is disabled (AWF_ONE_SHOT_DISABLE)");
+            }
+            None
+        }
+        Confirmation::Loaded { pid, .. } => Some(format!(
+            "it was loaded in process {} started by the command, not in the command",
+            pid
+        )),
+        Confirmation::Closed => Some("the command ran without it".to_string()),
+        Confirmation::TimedOut => Some(format!("no confirmation within {:?}", timeout)),
+        Confirmation::Invalid(line) => Some(format!("unexpected confirmation {:?}", line)),
+    };
+    drop(pipe);
+    if let Some(problem) = problem {
+        let _ = child.kill();
+        let _ = child.wait();
+        let hint = if cfg!(target_os = "macos") {
+            "check that the program is not protected by System Integrity Protection, \
+             set-user-ID or built with the hardened runtime"
+        } else {
+            "check that the program is dynamically linked and not set-user-ID"
+        };
+        fail(
+            EXIT_FAILED,
+            &format!(
+                "{} did not load into {}: {}; {}",
+                preload,
+                command.display(),
+                problem,
+                hint
+            ),
+        );
+    }
+
+    match child.wait() {
+        Ok(status) => status
+            .code()
+            .or_else(|| status.signal().map(|signum| 128 + signum))
+            .unwrap_or(EXIT_FAILED),
+        Err(err) => fail(
+            EXIT_FAILED,
+            &format!("waiting for {}: {}", command.display(), err),
+        ),
+    }
+}
+
+fn main() {
+    let args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    if matches!(
+        args.first().and_then(|arg| arg.to_str()),
+        Some("-h" | "--help")
+    ) {
+        println!("{}", USAGE);
+        return;
+    }
+    let options = parse_args(args).unwrap_or_else(|err| {
+        eprintln!("awf-preload: {}\n{}", err, USAGE);
+        std::process::exit(EXIT_FAILED);
+    });
+    if options.validate {
+        std::process::exit(validate(options));
+    }
+    if options.install || options.uninstall {
+        std::process::exit(install(options));
+    }
+    std::process::exit(run(options));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    /// The first 20 bytes of an ELF header
+    fn elf(class: u8, kind: u8, machine: u8) -> Vec<u8> {
+        let mut header = b"\x7fELF".to_vec();
+        header.extend_from_slice(&[class, 1, 1]);
+        header.resize(16, 0);
+        header.extend_from_slice(&[kind, 0, machine, 0]);
+        header
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse_args(args(&[
+            "--lib",
+            "/opt/one-shot-token.so",
+            "--set",
+            "AWF_ONE_SHOT_EAGER=1",
+            "--timeout",
+            "2.5",
+            "--",
+            "gh",
+            "--version",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options.library,
+            Some(PathBuf::from("/opt/one-shot-token.so"))
+        );
+        assert_eq!(
+            options.config,
+            vec![("AWF_ONE_SHOT_EAGER".to_string(), "1".to_string())]
+        );
+        assert_eq!(options.timeout, Some(Duration::from_millis(2500)));
+        assert_eq!(options.command, args(&["gh", "--version"]));
+
+        // The command starts at the first argument that is not an option
+        let options = parse_args(args(&["gh", "--", "-x"])).unwrap();
+        assert_eq!(options.command, args(&["gh", "--", "-x"]));
+
+        let options =
+            parse_args(args(&["--validate", "--set", "AWF_ONE_SHOT_MODE=observe"])).unwrap();
+        assert!(options.validate && options.command.is_empty());
+        assert!(parse_args(args(&["--validate", "gh"])).is_err());
+
+        let options = parse_args(args(&["--install", "--root", "/mnt/image"])).unwrap();
+        assert!(options.install && options.command.is_empty());
+        assert_eq!(options.root, Some(PathBuf::from("/mnt/image")));
+        assert!(parse_args(args(&["--uninstall"])).unwrap().uninstall);
+        assert!(parse_args(args(&["--install", "--uninstall"])).is_err());
+        assert!(parse_args(args(&["--install", "gh"])).is_err());
+        assert!(parse_args(args(&["--root", "/", "gh"])).is_err());
+
+        assert!(parse_args(args(&[])).is_err());
+        assert!(parse_args(args(&["--"])).is_err());
+        assert!(parse_args(args(&["--lib"])).is_err());
+        assert!(parse_args(args(&["--set", "PATH=/tmp", "gh"])).is_err());
+        assert!(parse_args(args(&["--set", "AWF_ONE_SHOT_READY_FD=1", "gh"])).is_err());
+        assert!(parse_args(args(&["--set", "AWF_ONE_SHOT_EAGER", "gh"])).is_err());
+        assert!(parse_args(args(&["--timeout", "0", "gh"])).is_err());
+        assert!(parse_args(args(&["--verbose", "gh"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            parse_header(&elf(2, 3, 62)),
+            Header::Elf {
+                shared: true,
+                arch: Some(Arch::X86_64),
+                dynamic: true
+            }
+        );
+        assert_eq!(
+            parse_header(&elf(1, 2, 3)),
+            Header::Elf {
+                shared: false,
+                arch: Some(Arch::I686),
+                dynamic: true
+            }
+        );
+        assert_eq!(
+            parse_header(&elf(2, 3, 40)),
+            Header::Elf {
+                shared: true,
+                arch: None,
+                dynamic: true
+            }
+        );
+        assert_eq!(parse_header(&[0xcf, 0xfa, 0xed, 0xfe, 7]), Header::MachO);
+
+        // 64-bit program headers at 64, 56 bytes each: PT_LOAD and PT_INTERP
+        let mut program = elf(2, 2, 62);
+        program.resize(64, 0);
+        program[32] = 64;
+        program[54] = 56;
+        program[56] = 2;
+        for p_type in [1, 3] {
+            program.push(p_type);
+            program.resize(program.len() + 55, 0);
+        }
+        assert!(matches!(
+            parse_header(&program),
+            Header::Elf { dynamic: true, .. }
+        ));
+        program[64 + 56] = 1;
+        assert!(matches!(
+            parse_header(&program),
+            Header::Elf { dynamic: false, .. }
+        ));
+        program.truncate(100);
+        assert!(matches!(
+            parse_header(&program),
+            Header::Elf { dynamic: true, .. }
+        ));
+        assert_eq!(
+            parse_header(b"#! /usr/bin/env python3\n"),
+            Header::Script(PathBuf::from("/usr/bin/env"))
+        );
+        assert_eq!(parse_header(b"#!\n/bin/sh"), Header::Other);
+        assert_eq!(parse_header(b"\x7fELF\x02"), Header::Other);
+        assert_eq!(parse_header(b"GITHUB_TOKEN=x"), Header::Other);
+    }
+
+    #[test]
+    fn test_verify_digests() {
+        let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let other = "sha256:".to_string() + &"ab".repeat(32);
+        let digests = parse_digests(&format!(" {}, {} ,", empty, other)).unwrap();
+        assert_eq!(digests.len(), 2);
+        assert_eq!(hex(&digests[0]), empty);
+        assert!(parse_digests("abc").is_err());
+        assert!(parse_digests(&"zz".repeat(32)).is_err());
+
+        let path = std::env::temp_dir().join(format!("awf-preload-digest-{}", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+        let paths = [path.as_path()];
+        assert_eq!(verify_digests(paths, &[]), Ok(()));
+        assert_eq!(
+            verify_digests(paths, std::slice::from_ref(&digests)),
+            Ok(())
+        );
+        // Every list must accept the build
+        let mismatch = verify_digests(paths, &[digests, vec![[0xab; 32]]]).unwrap_err();
+        assert!(
+            mismatch.contains(&format!("SHA-256 {} is not", empty)),
+            "{}",
+            mismatch
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_command() {
+        assert_eq!(
+            resolve_command(Path::new("./tool"), None),
+            Some(PathBuf::from("./tool"))
+        );
+        assert_eq!(
+            resolve_command(Path::new("sh"), Some("/nonexistent:/bin")),
+            Some(PathBuf::from("/bin/sh"))
+        );
+        assert_eq!(resolve_command(Path::new("sh"), Some("/nonexistent")), None);
+    }
+
+    #[test]
+    fn test_preload_value() {
+        let mut builds = Builds {
+            primary: PathBuf::from("/lib/one-shot-token.so"),
+            ..Builds::default()
+        };
+        builds
+            .by_arch
+            .insert(Arch::X86_64, PathBuf::from("/lib/one-shot-token.so"));
+        builds
+            .by_arch
+            .insert(Arch::I686, PathBuf::from("/lib/one-shot-token32.so"));
+
+        assert_eq!(
+            builds.for_program(Some(Arch::I686)).unwrap(),
+            vec![Path::new("/lib/one-shot-token32.so")]
+        );
+        assert!(builds.for_program(Some(Arch::Aarch64)).is_err());
+        let all = builds.for_program(None).unwrap();
+        assert_eq!(
+            all,
+            vec![
+                Path::new("/lib/one-shot-token.so"),
+                Path::new("/lib/one-shot-token32.so")
+            ]
+        );
+
+        let chosen = [Path::new("/lib/one-shot-token32.so")];
+        assert_eq!(
+            preload_value(&chosen, None, &builds),
+            "/lib/one-shot-token32.so"
+        );
+        assert_eq!(
+            preload_value(
+                &chosen,
+                Some("/lib/one-shot-token.so:/opt/other.so /opt/more.so"),
+                &builds
+            ),
+            "/lib/one-shot-token32.so:/opt/other.so:/opt/more.so"
+        );
+    }
+
+    #[test]
+    fn test_parse_confirmation() {
+        assert_eq!(
+            parse_confirmation("ready 42 0.1.0\n"),
+            Confirmation::Loaded {
+                pid: 42,
+                disabled: false
+            }
+        );
+        assert_eq!(
+            parse_confirmation("disabled 42 0.1.0\n"),
+            Confirmation::Loaded {
+                pid: 42,
+                disabled: true
+            }
+        );
+        assert_eq!(
+            parse_confirmation("ready\n"),
+            Confirmation::Invalid("ready".to_string())
+        );
+        assert_eq!(
+            parse_confirmation("hello"),
+            Confirmation::Invalid("hello".to_string())
+        );
+    }
+}
@@ -0,0 +1,764 @@
+//! Supervision of statically linked programs
+//!
+//! A statically linked program never loads the library: its environment
+//! stays in /proc/<pid>/environ and is passed on whole to what it runs. Such
+//! a command is run with this process as its ptrace tracer instead. A seccomp
+//! filter installed before the exec stops the command, and every process it
+//! starts, at the system calls below; for each process running a static
+//! program the supervisor:
+//!
+//! - masks protected values in the process's own environment block (what
+//!   /proc/<pid>/environ shows) with `*` at its first clone, fork or exec.
+//!   Go programs copy their environment when they start, before their first
+//!   thread; C programs calling getenv after that see the masked value.
+//! - removes protected tokens from the environment of the programs it execs,
+//!   as the library does for dynamically linked ones
+//! - reports opens of /proc/<pid>/environ files
+//!
+//! Processes running dynamically linked programs are left to the library.
+//! Events are audit lines in the library's text format, on stderr; with
+//! AWF_ONE_SHOT_MODE=observe they say what would have been changed.
+//!
+//! Tracing cannot end while the filter is installed, so processes the
+//! command leaves running are killed when it exits. SIGSTOP and the other
+//! job control signals do not stop supervised processes. System calls made
+//! through another ABI (i386 calls in an x86_64 process) are not seen, and a
+//! static program of another architecture is killed at exec.
+
+use crate::common::{
+    audit, detail_word, environ_owner, forward_signals, protected_names, read_c_string,
+};
+use crate::detect::preset_tokens;
+use crate::{fail, read_header, Arch, Header};
+use crate::{EXIT_CANNOT_EXECUTE, EXIT_FAILED, EXIT_NOT_FOUND};
+use libc::{c_long, pid_t, sock_filter, sock_fprog};
+use std::collections::HashMap;
+use std::ffi::{CString, OsString};
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem::offset_of;
+use std::os::fd::FromRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+/// Audit architecture of the native system call ABI
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "x86")]
+const AUDIT_ARCH: u32 = 0x4000_0003;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// Architecture of the static programs that can be supervised
+#[cfg(target_arch = "x86_64")]
+const NATIVE: Arch = Arch::X86_64;
+#[cfg(target_arch = "x86")]
+const NATIVE: Arch = Arch::I686;
+#[cfg(target_arch = "aarch64")]
+const NATIVE: Arch = Arch::Aarch64;
+
+/// ptrace request reading the system call a tracee is stopped at (Linux 5.3)
+const PTRACE_GET_SYSCALL_INFO: libc::c_long = 0x420e;
+/// `op` of a stop caused by SECCOMP_RET_TRACE
+const PTRACE_SYSCALL_INFO_SECCOMP: u8 = 3;
+
+/// Tracing options: follow every new process and exec, and report the
+/// filter's stops; tracees are killed if this process dies
+const OPTIONS: libc::c_int = libc::PTRACE_O_TRACESECCOMP
+    | libc::PTRACE_O_TRACEEXEC
+    | libc::PTRACE_O_TRACEFORK
+    | libc::PTRACE_O_TRACEVFORK
+    | libc::PTRACE_O_TRACECLONE
+    | libc::PTRACE_O_EXITKILL;
+
+/// How the supervisor's audit lines are marked
+const VIA: &str = "awf-preload";
+
+/// Longest string or environment block read from a tracee
+const MAX_READ: usize = 1 << 20;
+
+/// struct ptrace_syscall_info, for a seccomp stop
+#[repr(C)]
+#[derive(Default)]
+struct SyscallInfo {
+    op: u8,
+    reserved: [u8; 3],
+    arch: u32,
+    instruction_pointer: u64,
+    stack_pointer: u64,
+    nr: u64,
+    args: [u64; 6],
+    ret_data: u32,
+    reserved2: u32,
+}
+
+/// What a stopped system call does, with the addresses of its arguments
+#[derive(Debug, PartialEq)]
+enum Call {
+    Open { path: u64 },
+    Spawn,
+    Exec { path: u64, envp: u64 },
+    Other,
+}
+
+/// The system calls the filter stops at
+fn traced_syscalls() -> Vec<c_long> {
+    #[allow(unused_mut)]
+    let mut calls = vec![
+        libc::SYS_openat,
+        libc::SYS_openat2,
+        libc::SYS_clone,
+        libc::SYS_clone3,
+        libc::SYS_execve,
+        libc::SYS_execveat,
+    ];
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    calls.extend([libc::SYS_open, libc::SYS_fork, libc::SYS_vfork]);
+    calls
+}
+
+/// Classify the system call `nr` with arguments `args`
+fn classify(nr: c_long, args: &[u64; 6]) -> Call {
+    match nr {
+        libc::SYS_openat | libc::SYS_openat2 => Call::Open { path: args[1] },
+        libc::SYS_clone | libc::SYS_clone3 => Call::Spawn,
+        libc::SYS_execve => Call::Exec {
+            path: args[0],
+            envp: args[2],
+        },
+        libc::SYS_execveat => Call::Exec {
+            path: args[1],
+            envp: args[3],
+        },
+        #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+        libc::SYS_open => Call::Open { path: args[0] },
+        #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+        libc::SYS_fork | libc::SYS_vfork => Call::Spawn,
+        _ => Call::Other,
+    }
+}
+
+/// Build the seccomp program: SECCOMP_RET_TRACE for the traced calls of the
+/// native ABI, allow everything else
+fn filter() -> Vec<sock_filter> {
+    let stmt = |code: u32, k: u32| sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let jump = |k: u32, jt: u8| sock_filter {
+        code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+        jt,
+        jf: 0,
+        k,
+    };
+    let load = |offset: usize| stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset as u32);
+
+    let calls = traced_syscalls();
+    let mut program = vec![load(offset_of!(libc::seccomp_data, arch))];
+    program.push(jump(AUDIT_ARCH, 1));
+    program.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+    program.push(load(offset_of!(libc::seccomp_data, nr)));
+    // Every check jumps forward to the trace return at the end on a match
+    for (index, &nr) in calls.iter().enumerate() {
+        program.push(jump(nr as u32, (calls.len() - index) as u8));
+    }
+    program.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+    program.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_TRACE));
+    program
+}
+
+/// Start and end address of the environment block, from /proc/<pid>/stat
+fn environ_bounds(stat: &str) -> Option<(u64, u64)> {
+    // env_start and env_end are fields 50 and 51; the state after the
+    // command name is field 3
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    Some((fields.get(47)?.parse().ok()?, fields.get(48)?.parse().ok()?))
+}
+
+/// The protected values in an environment block that are not masked yet:
+/// name, offset of the value and its length
+fn exposed_values(block: &[u8], names: &[String]) -> Vec<(String, usize, usize)> {
+    let mut exposed = Vec::new();
+    let mut offset = 0;
+    for entry in block.split(|&byte| byte == 0) {
+        if let Some(name) = protected_entry(entry, names) {
+            let value = &entry[name.len() + 1..];
+            if value.iter().any(|&byte| byte != b'*') {
+                exposed.push((name, offset + entry.len() - value.len(), value.len()));
+            }
+        }
+        offset += entry.len() + 1;
+    }
+    exposed
+}
+
+/// The name of an environment entry if it is protected
+fn protected_entry(entry: &[u8], names: &[String]) -> Option<String> {
+    let name = &entry[..entry.iter().position(|&byte| byte == b'=')?];
+    names
+        .iter()
+        .find(|protected| protected.as_bytes() == name)
+        .cloned()
+}
+
+/// What the supervisor does, from the command's AWF_* configuration
+struct Policy {
+    names: Vec<String>,
+    observe: bool,
+}
+
+impl Policy {
+    fn from_config(config: &HashMap<String, String>) -> Self {
+        let value = |name: &str| config.get(name).map(String::as_str);
+        Self {
+            names: protected_names(
+                value("AWF_ONE_SHOT_TOKENS"),
+                value("AWF_ONE_SHOT_EXTRA_TOKENS"),
+                &preset_tokens(value("AWF_ONE_SHOT_PRESETS")),
+                &[],
+            ),
+            observe: value("AWF_ONE_SHOT_MODE")
+                .is_some_and(|mode| mode.trim().eq_ignore_ascii_case("observe")),
+        }
+    }
+
+    /// Report a change, or in observe mode that it would have been made;
+    /// true if it is to be made
+    fn apply(&self, event: &str, would: &str, token: &str, detail: &str) -> bool {
+        if self.observe {
+            audit(
+                VIA,
+                "observed",
+                token,
+                &format!("would={} {}", would, detail),
+            );
+        } else {
+            audit(VIA, event, token, detail);
+        }
+        !self.observe
+    }
+}
+
+/// What is known about a traced process or thread
+#[derive(Debug, Default, Clone, Copy)]
+struct Tracee {
+    /// Running a static program of the native architecture
+    supervised: bool,
+    /// Its environment block has been masked
+    masked: bool,
+    /// The event of the process that started it has been seen
+    attached: bool,
+    /// Its first stop has been seen
+    started: bool,
+}
+
+/// Memory of a stopped tracee
+struct Memory(File);
+
+impl Memory {
+    fn open(pid: pid_t) -> io::Result<Self> {
+        File::options()
+            .read(true)
+            .write(true)
+            .open(format!("/proc/{}/mem", pid))
+            .map(Memory)
+    }
+
+    /// Read a NUL-terminated string at `addr`
+    fn string(&self, addr: u64) -> io::Result<Vec<u8>> {
+        read_c_string(&self.0, addr)
+    }
+
+    /// Read the NULL-terminated pointer array at `addr`
+    fn pointers(&self, addr: u64) -> io::Result<Vec<usize>> {
+        let size = std::mem::size_of::<usize>();
+        let mut pointers = Vec::new();
+        loop {
+            let mut bytes = [0u8; std::mem::size_of::<usize>()];
+            self.0
+                .read_exact_at(&mut bytes, addr + (pointers.len() * size) as u64)?;
+            match usize::from_ne_bytes(bytes) {
+                0 => return Ok(pointers),
+                pointer => pointers.push(pointer),
+            }
+            if pointers.len() * size > MAX_READ {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+        }
+    }
+}
+
+/// Mask the protected values in the environment block of `pid`
+fn mask_environ(pid: pid_t, policy: &Policy) -> io::Result<()> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    let (start, end) = environ_bounds(&stat).ok_or(io::ErrorKind::InvalidData)?;
+    let len = usize::try_from(end.saturating_sub(start))
+        .ok()
+        .filter(|&len| len <= MAX_READ)
+        .ok_or(io::ErrorKind::InvalidData)?;
+    let memory = Memory::open(pid)?;
+    let mut block = vec![0u8; len];
+    memory.0.read_exact_at(&mut block, start)?;
+    for (name, offset, len) in exposed_values(&block, &policy.names) {
+        let detail = format!("action=masked pid={}", pid);
+        if policy.apply("environ_scrubbed", "scrub", &name, &detail) {
+            memory
+                .0
+                .write_all_at(&vec![b'*'; len], start + offset as u64)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove protected tokens from the environment `pid` is about to exec
+/// with: the array at `envp` is compacted in place
+fn scrub_exec(pid: pid_t, path: u64, envp: u64, policy: &Policy) -> io::Result<()> {
+    if envp == 0 {
+        return Ok(());
+    }
+    let memory = Memory::open(pid)?;
+    let program = memory.string(path).unwrap_or_default();
+    let pointers = memory.pointers(envp)?;
+    let mut kept = Vec::with_capacity(pointers.len());
+    for &pointer in &pointers {
+        let entry = memory.string(pointer as u64)?;
+        match protected_entry(&entry, &policy.names) {
+            Some(name) => {
+                let detail = format!("action=removed pid={} exe={}", pid, detail_word(&program));
+                if !policy.apply("exec_scrubbed", "exec_scrub", &name, &detail) {
+                    kept.push(pointer);
+                }
+            }
+            None => kept.push(pointer),
+        }
+    }
+    if kept.len() == pointers.len() {
+        return Ok(());
+    }
+    let mut bytes: Vec<u8> = kept
+        .iter()
+        .flat_map(|pointer| pointer.to_ne_bytes())
+        .collect();
+    bytes.extend_from_slice(&0usize.to_ne_bytes());
+    memory.0.write_all_at(&bytes, envp)
+}
+
+/// The system call `pid` is stopped at by the filter
+fn syscall_info(pid: pid_t) -> io::Result<(c_long, [u64; 6])> {
+    let mut info = SyscallInfo::default();
+    // SAFETY: the kernel writes at most the given size into info
+    let len = unsafe {
+        libc::ptrace(
+            PTRACE_GET_SYSCALL_INFO as _,
+            pid,
+            std::mem::size_of::<SyscallInfo>() as c_long,
+            &mut info as *mut SyscallInfo,
+        )
+    };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if info.op != PTRACE_SYSCALL_INFO_SECCOMP {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+    Ok((info.nr as c_long, info.args))
+}
+
+/// Act on the system call a supervised process is stopped at
+fn on_syscall(pid: pid_t, tracee: &mut Tracee, call: Call, policy: &Policy) {
+    let result = match call {
+        Call::Open { path } => {
+            if let Ok(path) = Memory::open(pid).and_then(|memory| memory.string(path)) {
+                if environ_owner(&path).is_some() {
+                    audit(
+                        VIA,
+                        "environ_read",
+                        "*",
+                        &format!("pid={} path={}", pid, detail_word(&path)),
+                    );
+                }
+            }
+            Ok(())
+        }
+        Call::Spawn | Call::Exec { .. } if !tracee.masked => {
+            tracee.masked = true;
+            mask_environ(pid, policy).and_then(|()| match call {
+                Call::Exec { path, envp } => scrub_exec(pid, path, envp, policy),
+                _ => Ok(()),
+            })
+        }
+        Call::Exec { path, envp } => scrub_exec(pid, path, envp, policy),
+        Call::Spawn | Call::Other => Ok(()),
+    };
+    if let Err(err) = result {
+        eprintln!(
+            "awf-preload: warning: cannot scrub process {}: {}",
+            pid, err
+        );
+    }
+}
+
+/// Whether the program `pid` has just exec'd is to be supervised; static
+/// programs of another architecture are killed
+fn on_exec(pid: pid_t) -> bool {
+    let exe = std::fs::read_link(format!("/proc/{}/exe", pid)).unwrap_or_default();
+    let Ok(Header::Elf {
+        arch,
+        dynamic: false,
+        ..
+    }) = read_header(Path::new(&format!("/proc/{}/exe", pid)))
+    else {
+        return false;
+    };
+    let detail = format!(
+        "pid={} exe={}",
+        pid,
+        detail_word(exe.as_os_str().as_bytes())
+    );
+    if arch != Some(NATIVE) {
+        audit(VIA, "supervise_refused", "*", &detail);
+        // SAFETY: kill has no memory safety preconditions
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+        return false;
+    }
+    audit(VIA, "supervised", "*", &detail);
+    true
+}
+
+/// Resume a stopped tracee, delivering `signum` (0 for none)
+fn resume(pid: pid_t, signum: libc::c_int) {
+    // SAFETY: PTRACE_CONT takes no pointers
+    unsafe { libc::ptrace(libc::PTRACE_CONT, pid, 0 as c_long, signum as c_long) };
+}
+
+/// Start `program` stopped and traced by this process, with the filter
+/// installed for its exec
+///
+/// Returns its pid and the read end of a pipe that carries errno if the exec
+/// fails.
+fn spawn(
+    program: &Path,
+    command: &[OsString],
+    env: &[(OsString, OsString)],
+) -> io::Result<(pid_t, File)> {
+    let c_string = |bytes: &[u8]| {
+        CString::new(bytes).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+    };
+    let path = c_string(program.as_os_str().as_bytes())?;
+    let argv = command
+        .iter()
+        .map(|arg| c_string(arg.as_bytes()))
+        .collect::<io::Result<Vec<_>>>()?;
+    let envp = env
+        .iter()
+        .map(|(name, value)| c_string(&[name.as_bytes(), b"=", value.as_bytes()].concat()))
+        .collect::<io::Result<Vec<_>>>()?;
+    let pointers = |strings: &[CString]| {
+        strings
+            .iter()
+            .map(|string| string.as_ptr())
+            .chain(std::iter::once(std::ptr::null()))
+            .collect::<Vec<_>>()
+    };
+    let (argv, envp) = (pointers(&argv), pointers(&envp));
+    let program = filter();
+    let prog = sock_fprog {
+        len: program.len() as u16,
+        filter: program.as_ptr() as *mut sock_filter,
+    };
+
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for two descriptors; after fork the child only
+    // makes async-signal-safe calls on memory prepared above
+    unsafe {
+        if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let pid = libc::fork();
+        if pid == 0 {
+            libc::close(fds[0]);
+            if libc::ptrace(libc::PTRACE_TRACEME, 0, 0 as c_long, 0 as c_long) == 0
+                && libc::raise(libc::SIGSTOP) == 0
+                && libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == 0
+                && libc::syscall(
+                    libc::SYS_seccomp,
+                    libc::SECCOMP_SET_MODE_FILTER,
+                    0,
+                    &prog as *const sock_fprog,
+                ) == 0
+            {
+                libc::execve(path.as_ptr(), argv.as_ptr(), envp.as_ptr());
+            }
+            let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+            libc::write(fds[1], (&errno as *const i32).cast(), 4);
+            libc::_exit(EXIT_CANNOT_EXECUTE);
+        }
+        libc::close(fds[1]);
+        let errors = File::from_raw_fd(fds[0]);
+        if pid < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut status = 0;
+        let stopped = libc::waitpid(pid, &mut status, 0) == pid
+            && libc::WIFSTOPPED(status)
+            && libc::WSTOPSIG(status) == libc::SIGSTOP;
+        if !stopped {
+            return Err(exec_error(errors).unwrap_or(io::ErrorKind::Other.into()));
+        }
+        if libc::ptrace(libc::PTRACE_SETOPTIONS, pid, 0 as c_long, OPTIONS as c_long) != 0 {
+            let err = io::Error::last_os_error();
+            libc::kill(pid, libc::SIGKILL);
+            return Err(err);
+        }
+        Ok((pid, errors))
+    }
+}
+
+/// The errno the child reported before exiting, if any
+fn exec_error(mut errors: File) -> Option<io::Error> {
+    let mut errno = [0u8; 4];
+    errors.read_exact(&mut errno).ok()?;
+    Some(io::Error::from_raw_os_error(i32::from_ne_bytes(errno)))
+}
+
+/// Run `program` (the resolved command) with `env` added to this process's
+/// environment, supervising it and everything it starts
+pub fn run(
+    program: &Path,
+    command: &[OsString],
+    env: Vec<(OsString, OsString)>,
+    config: &HashMap<String, String>,
+) -> i32 {
+    let mut environment: Vec<(OsString, OsString)> = std::env::vars_os()
+        .filter(|(name, _)| !env.iter().any(|(set, _)| set == name))
+        .collect();
+    environment.extend(env);
+    let policy = Policy::from_config(config);
+    let display = PathBuf::from(&command[0]);
+    let display = display.display();
+
+    let (root, errors) = match spawn(program, command, &environment) {
+        Ok(spawned) => spawned,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            fail(EXIT_NOT_FOUND, &format!("{}: command not found", display))
+        }
+        Err(err) => fail(
+            EXIT_CANNOT_EXECUTE,
+            &format!("{}: cannot supervise: {}", display, err),
+        ),
+    };
+    forward_signals(root as u32);
+    let mut errors = Some(errors);
+    let mut tracees = HashMap::from([(
+        root,
+        Tracee {
+            attached: true,
+            started: true,
+            ..Tracee::default()
+        },
+    )]);
+    resume(root, 0);
+
+    loop {
+        let mut status = 0;
+        // SAFETY: status is a valid out pointer
+        let pid = unsafe { libc::waitpid(-1, &mut status, libc::__WALL) };
+        if pid < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            fail(EXIT_FAILED, &format!("waiting for {}: {}", display, err));
+        }
+        if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+            tracees.remove(&pid);
+            if pid != root {
+                continue;
+            }
+            if let Some(err) = errors.take().and_then(exec_error) {
+                let status = match err.kind() {
+                    io::ErrorKind::NotFound => EXIT_NOT_FOUND,
+                    _ => EXIT_CANNOT_EXECUTE,
+                };
+                fail(status, &format!("{}: {}", display, err));
+            }
+            return if libc::WIFEXITED(status) {
+                libc::WEXITSTATUS(status)
+            } else {
+                128 + libc::WTERMSIG(status)
+            };
+        }
+        if !libc::WIFSTOPPED(status) {
+            continue;
+        }
+
+        let tracee = tracees.entry(pid).or_default();
+        let mut signum = 0;
+        match status >> 16 {
+            libc::PTRACE_EVENT_SECCOMP => {
+                let (nr, args) = syscall_info(pid).unwrap_or_else(|err| {
+                    fail(
+                        EXIT_FAILED,
+                        &format!("cannot read system calls of supervised processes: {}", err),
+                    )
+                });
+                if tracee.supervised {
+                    on_syscall(pid, tracee, classify(nr, &args), &policy);
+                }
+            }
+            libc::PTRACE_EVENT_EXEC => {
+                if pid == root {
+                    errors = None;
+                }
+                *tracee = Tracee {
+                    supervised: on_exec(pid),
+                    masked: false,
+                    ..*tracee
+                };
+                // A thread other than the leader exec'd: its old id is gone
+                let mut former: libc::c_ulong = 0;
+                // SAFETY: former is a valid out pointer
+                unsafe {
+                    libc::ptrace(
+                        libc::PTRACE_GETEVENTMSG,
+                        pid,
+                        0 as c_long,
+                        &mut former as *mut libc::c_ulong,
+                    )
+                };
+                if former as pid_t != pid {
+                    tracees.remove(&(former as pid_t));
+                }
+            }
+            libc::PTRACE_EVENT_FORK | libc::PTRACE_EVENT_VFORK | libc::PTRACE_EVENT_CLONE => {
+                let parent = *tracee;
+                let mut child: libc::c_ulong = 0;
+                // SAFETY: child is a valid out pointer
+                unsafe {
+                    libc::ptrace(
+                        libc::PTRACE_GETEVENTMSG,
+                        pid,
+                        0 as c_long,
+                        &mut child as *mut libc::c_ulong,
+                    )
+                };
+                let child = child as pid_t;
+                let entry = tracees.entry(child).or_default();
+                entry.supervised = parent.supervised;
+                entry.masked = parent.masked;
+                entry.attached = true;
+                // Its first stop came before this event and was held
+                if entry.started {
+                    resume(child, 0);
+                }
+            }
+            0 if !tracee.started && libc::WSTOPSIG(status) == libc::SIGSTOP => {
+                tracee.started = true;
+                // Held until the process that started it says what it runs
+                if !tracee.attached {
+                    continue;
+                }
+            }
+            0 => {
+                let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+                // SAFETY: info is a valid out pointer
+                let delivered = unsafe {
+                    libc::ptrace(
+                        libc::PTRACE_GETSIGINFO,
+                        pid,
+                        0 as c_long,
+                        &mut info as *mut libc::siginfo_t,
+                    )
+                } == 0;
+                // A group stop (without siginfo) is resumed rather than kept
+                if delivered {
+                    signum = libc::WSTOPSIG(status);
+                }
+            }
+            _ => {}
+        }
+        resume(pid, signum);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let program = filter();
+        let calls = traced_syscalls();
+        assert_eq!(program.len(), calls.len() + 6);
+        assert_eq!(program[1].k, AUDIT_ARCH);
+        assert_eq!(program.last().unwrap().k, libc::SECCOMP_RET_TRACE);
+        // Every check lands on the trace return
+        for (index, &nr) in calls.iter().enumerate() {
+            let check = &program[4 + index];
+            assert_eq!(check.k, nr as u32);
+            assert_eq!(4 + index + 1 + check.jt as usize, program.len() - 1);
+        }
+        assert_eq!(
+            classify(libc::SYS_execve, &[1, 2, 3, 0, 0, 0]),
+            Call::Exec { path: 1, envp: 3 }
+        );
+        assert_eq!(
+            classify(libc::SYS_openat, &[0, 7, 0, 0, 0, 0]),
+            Call::Open { path: 7 }
+        );
+        assert_eq!(classify(libc::SYS_read, &[0; 6]), Call::Other);
+    }
+
+    #[test]
+    fn test_environ_owner() {
+        use crate::common::EnvironOwner;
+        assert_eq!(
+            environ_owner(b"/proc/self/environ"),
+            Some(EnvironOwner::Current)
+        );
+        assert_eq!(
+            environ_owner(b"/proc/812/environ"),
+            Some(EnvironOwner::Pid(812))
+        );
+        assert_eq!(
+            environ_owner(b"/proc//812/task/813/environ"),
+            Some(EnvironOwner::Pid(813))
+        );
+        assert_eq!(
+            environ_owner(b"/proc/thread-self/environ"),
+            Some(EnvironOwner::Current)
+        );
+        assert_eq!(environ_owner(b"/proc/812/cmdline"), None);
+        assert_eq!(environ_owner(b"/proc/self/task/environ"), None);
+        assert_eq!(environ_owner(b"proc/self/environ"), None);
+    }
+
+    #[test]
+    fn test_environ_bounds() {
+        let mut stat = "812 (go tool) S".to_string();
+        for field in 4..=52 {
+            stat.push_str(&format!(" {}", field));
+        }
+        assert_eq!(environ_bounds(&stat), Some((50, 51)));
+        assert_eq!(environ_bounds("812 (go) S 1 2"), None);
+    }
+
+    #[test]
+    fn test_exposed_values() {
+        let names = vec!["GITHUB_TOKEN".to_string(), "OPENAI_API_KEY".to_string()];
+        let block = b"PATH=/bin\0GITHUB_TOKEN=ghp_x\0OPENAI_API_KEY=***\0GITHUB_TOKEN_X=1\0";
+        assert_eq!(
+            exposed_values(block, &names),
+            vec![("GITHUB_TOKEN".to_string(), 23, 5)]
+        );
+        assert_eq!(&block[23..28], b"ghp_x");
+        assert_eq!(
+            protected_entry(b"OPENAI_API_KEY=", &names),
+            Some("OPENAI_API_KEY".to_string())
+        );
+        assert_eq!(protected_entry(b"GITHUB_TOKEN", &names), None);
+    }
+}
@@ -0,0 +1,497 @@
+//! awf-token-broker: hand out token values for opaque handles over a Unix
+//! socket
+//!
+//! Usage: awf-token-broker --socket PATH [--uid UID]... [--once]
+//!        [--env-file PATH] [--mint NAME=COMMAND]... [NAME...]
+//!
+//! Runs on the host, next to the container. For each NAME taken from its own
+//! environment it makes up a random handle and writes `NAME=awf-broker:HANDLE`
+//! (to stdout, or to `--env-file`, created with mode 0600) for the container's
+//! environment, then serves the values on the socket, which is mounted into
+//! the container. The library, started with AWF_ONE_SHOT_BROKER_SOCKET set to
+//! the socket, fetches a token's value when it is first read, so the
+//! plaintext never appears in the container's environment.
+//!
+//! With `--mint`, the broker mints values instead: on a `MINT NAME` request
+//! it runs COMMAND with `sh -c`, AWF_MINT_TOKEN set to NAME and its own
+//! environment otherwise (on a GitHub Actions runner, including the
+//! ACTIONS_ID_TOKEN_REQUEST_* variables of the OIDC token), and answers with
+//! the first line the command prints. The command exchanges the OIDC token for
+//! a short-lived token; it must succeed within 25 seconds. Minted tokens need
+//! no handle: the library asks for them when they are read and not set.
+//!
+//! Each connection carries one request, `GET NAME HANDLE` or `MINT NAME`,
+//! answered with `OK VALUE` or `ERR REASON`. Only processes of the users given with `--uid`
+//! (by default the broker's own user) are served, as the kernel reports them
+//! with SO_PEERCRED (getpeereid on macOS); a handle is only good for the name
+//! it was made for, and with `--once` for a single answer. Every request is
+//! reported with a `broker_request` audit line on stderr; values and handles
+//! never are.
+//!
+//! Exit status: 1 if the socket cannot be set up, 2 on usage errors; the
+//! broker otherwise serves until it is killed. A socket left behind is
+//! replaced on the next start.
+
+mod common;
+
+use common::{audit, bind_socket, detail_word, peer_credentials};
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage: awf-token-broker --socket PATH [--uid UID]... [--once] \
+                     [--env-file PATH] [--mint NAME=COMMAND]... [NAME...]";
+
+/// Exit status when the socket or env file cannot be set up
+const EXIT_FAILED: i32 = 1;
+/// Exit status on usage errors
+const EXIT_USAGE: i32 = 2;
+
+/// How the broker's audit lines are marked
+const VIA: &str = "awf-token-broker";
+
+/// Prefix of handle values, as the library expects it
+const HANDLE_PREFIX: &str = "awf-broker:";
+
+/// How long a client has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request line read
+const MAX_REQUEST: u64 = 4096;
+
+/// How long a mint command may run (the library waits 30 seconds)
+const MINT_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// Most output read from a mint command
+const MAX_MINT_OUTPUT: u64 = 64 * 1024;
+
+/// Command-line options
+#[derive(Debug, PartialEq)]
+struct Options {
+    socket: PathBuf,
+    uids: Vec<u32>,
+    once: bool,
+    env_file: Option<PathBuf>,
+    names: Vec<String>,
+    /// Names and commands of minted tokens
+    mints: Vec<(String, String)>,
+}
+
+/// Parse the arguments after the program name
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut socket = None;
+    let mut options = Options {
+        socket: PathBuf::new(),
+        uids: Vec::new(),
+        once: false,
+        env_file: None,
+        names: Vec::new(),
+        mints: Vec::new(),
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => {
+                socket = Some(PathBuf::from(args.next().ok_or("--socket needs a value")?))
+            }
+            "--uid" => {
+                let uid = args.next().ok_or("--uid needs a value")?;
+                options
+                    .uids
+                    .push(uid.parse().map_err(|_| format!("invalid uid {:?}", uid))?);
+            }
+            "--once" => options.once = true,
+            "--env-file" => {
+                options.env_file = Some(PathBuf::from(
+                    args.next().ok_or("--env-file needs a value")?,
+                ))
+            }
+            "--mint" => {
+                let mint = args.next().ok_or("--mint needs a value")?;
+                let (name, command) = mint
+                    .split_once('=')
+                    .filter(|(name, command)| !name.is_empty() && !command.is_empty())
+                    .ok_or_else(|| format!("invalid --mint {:?}", mint))?;
+                options.mints.push((name.to_string(), command.to_string()));
+            }
+            flag if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+            name => {
+                if name.contains('=') {
+                    return Err(format!("invalid variable name {:?}", name));
+                }
+                options.names.push(name.to_string());
+            }
+        }
+    }
+    options.socket = socket.ok_or("--socket is required")?;
+    if options.names.is_empty() && options.mints.is_empty() {
+        return Err("no token names given".to_string());
+    }
+    if let Some((name, _)) = options
+        .mints
+        .iter()
+        .find(|(name, _)| options.names.contains(name))
+    {
+        return Err(format!("{} is both served and minted", name));
+    }
+    Ok(options)
+}
+
+/// A value the broker hands out
+struct Entry {
+    name: String,
+    handle: String,
+    value: Vec<u8>,
+    served: u32,
+}
+
+/// What the broker answered, for the audit line
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Served,
+    Minted,
+    Refused(&'static str),
+}
+
+/// Make up a handle: 32 hex digits from the system's random source
+fn new_handle() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Compare two byte strings in time that depends only on their lengths
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Answer one request line from a permitted user
+fn answer(entries: &mut [Entry], request: &str, once: bool) -> (String, Vec<u8>, Outcome) {
+    let mut parts = request.trim_end_matches('\n').split(' ');
+    let (Some("GET"), Some(name), Some(handle), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return (
+            "*".to_string(),
+            b"ERR bad_request\n".to_vec(),
+            Outcome::Refused("bad_request"),
+        );
+    };
+    let refuse = |reason: &'static str| {
+        (
+            name.to_string(),
+            format!("ERR {}\n", reason).into_bytes(),
+            Outcome::Refused(reason),
+        )
+    };
+    let Some(entry) = entries
+        .iter_mut()
+        .find(|entry| same(entry.handle.as_bytes(), handle.as_bytes()))
+        .filter(|entry| entry.name == name)
+    else {
+        return refuse("unknown_handle");
+    };
+    if once && entry.served > 0 {
+        return refuse("already_served");
+    }
+    entry.served += 1;
+    let reply = [b"OK ", entry.value.as_slice(), b"\n"].concat();
+    (name.to_string(), reply, Outcome::Served)
+}
+
+/// Run a mint command and take the first line it prints
+fn run_mint(command: &str, name: &str) -> Result<Vec<u8>, &'static str> {
+    let mut child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .env("AWF_MINT_TOKEN", name)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|_| "mint_failed")?;
+    let stdout = child.stdout.take().ok_or("mint_failed")?;
+    let reader = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stdout.take(MAX_MINT_OUTPUT).read_to_end(&mut output);
+        output
+    });
+
+    let deadline = Instant::now() + MINT_TIMEOUT;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(20)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                // The reader is left behind: a background process of the
+                // command may still hold the pipe open
+                return Err("mint_timeout");
+            }
+        }
+    };
+    let mut output = reader.join().unwrap_or_default();
+    let line = output
+        .split(|&byte| byte == b'\n')
+        .next()
+        .unwrap_or_default();
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let value = (status.success() && !line.is_empty()).then(|| line.to_vec());
+    output.fill(0);
+    value.ok_or("mint_failed")
+}
+
+/// Answer a `MINT` request for `name` from a permitted user
+fn answer_mint(mints: &[(String, String)], name: &str) -> (String, Vec<u8>, Outcome) {
+    let refuse = |reason: &'static str| {
+        (
+            name.to_string(),
+            format!("ERR {}\n", reason).into_bytes(),
+            Outcome::Refused(reason),
+        )
+    };
+    let Some((_, command)) = mints.iter().find(|(mint, _)| mint == name) else {
+        return refuse("unknown_token");
+    };
+    match run_mint(command, name) {
+        Ok(mut value) => {
+            let reply = [b"OK ", value.as_slice(), b"\n"].concat();
+            value.fill(0);
+            (name.to_string(), reply, Outcome::Minted)
+        }
+        Err(reason) => refuse(reason),
+    }
+}
+
+/// Serve one connection
+fn serve(stream: UnixStream, entries: &mut [Entry], options: &Options) -> io::Result<()> {
+    let (pid, uid) = peer_credentials(&stream)?;
+    let client = match pid {
+        Some(pid) => format!("pid={} uid={}", pid, uid),
+        None => format!("uid={}", uid),
+    };
+    // The request is read either way, so the refusal reaches the client
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = String::new();
+    BufReader::new((&stream).take(MAX_REQUEST)).read_line(&mut request)?;
+    if !options.uids.contains(&uid) {
+        audit(
+            VIA,
+            "broker_request",
+            "*",
+            &format!("{} action=refused reason=forbidden_uid", client),
+        );
+        return (&stream).write_all(b"ERR forbidden\n");
+    }
+    let (token, mut reply, outcome) = match request.trim_end_matches('\n').strip_prefix("MINT ") {
+        Some(name) if !name.is_empty() && !name.contains(' ') => answer_mint(&options.mints, name),
+        _ => answer(entries, &request, options.once),
+    };
+    let action = match outcome {
+        Outcome::Served => "action=served".to_string(),
+        Outcome::Minted => "action=minted".to_string(),
+        Outcome::Refused(reason) => format!("action=refused reason={}", reason),
+    };
+    audit(
+        VIA,
+        "broker_request",
+        &detail_word(token.as_bytes()),
+        &format!("{} {}", client, action),
+    );
+    let result = (&stream).write_all(&reply);
+    reply.fill(0);
+    result
+}
+
+/// Write the handle assignments for the container's environment
+fn write_handles(entries: &[Entry], env_file: Option<&PathBuf>) -> io::Result<()> {
+    let lines: String = entries
+        .iter()
+        .map(|entry| format!("{}={}{}\n", entry.name, HANDLE_PREFIX, entry.handle))
+        .collect();
+    match env_file {
+        Some(path) => OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?
+            .write_all(lines.as_bytes()),
+        None => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(lines.as_bytes())?;
+            stdout.flush()
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if matches!(args.first().map(String::as_str), Some("-h" | "--help")) {
+        println!("{}", USAGE);
+        return;
+    }
+    let mut options = parse_args(args).unwrap_or_else(|err| {
+        eprintln!("awf-token-broker: {}\n{}", err, USAGE);
+        std::process::exit(EXIT_USAGE);
+    });
+    if options.uids.is_empty() {
+        // SAFETY: getuid cannot fail
+        options.uids.push(unsafe { libc::getuid() });
+    }
+
+    let mut entries = Vec::new();
+    for name in &options.names {
+        let Some(value) = std::env::var_os(name) else {
+            eprintln!("awf-token-broker: {} is not set", name);
+            std::process::exit(EXIT_USAGE);
+        };
+        // The broker's own children need not see the values
+        std::env::remove_var(name);
+        let handle = new_handle().unwrap_or_else(|err| {
+            eprintln!("awf-token-broker: cannot make a handle: {}", err);
+            std::process::exit(EXIT_FAILED);
+        });
+        entries.push(Entry {
+            name: name.clone(),
+            handle,
+            value: std::os::unix::ffi::OsStringExt::into_vec(value),
+            served: 0,
+        });
+    }
+
+    let listener = bind_socket(&options.socket).unwrap_or_else(|err| {
+        eprintln!("awf-token-broker: {}: {}", options.socket.display(), err);
+        std::process::exit(EXIT_FAILED);
+    });
+    if let Err(err) = write_handles(&entries, options.env_file.as_ref()) {
+        eprintln!("awf-token-broker: cannot write the handles: {}", err);
+        std::process::exit(EXIT_FAILED);
+    }
+    eprintln!(
+        "awf-token-broker: serving {} tokens and minting {} on {}",
+        entries.len(),
+        options.mints.len(),
+        options.socket.display()
+    );
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| serve(stream, &mut entries, &options));
+        if let Err(err) = result {
+            eprintln!("awf-token-broker: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<Entry> {
+        vec![Entry {
+            name: "GITHUB_TOKEN".to_string(),
+            handle: "0123abcd".to_string(),
+            value: b"ghp_value".to_vec(),
+            served: 0,
+        }]
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let options = parse_args(args(&[
+            "--socket",
+            "/run/awf.sock",
+            "--uid",
+            "1000",
+            "--once",
+            "GITHUB_TOKEN",
+        ]))
+        .unwrap();
+        assert_eq!(options.socket, PathBuf::from("/run/awf.sock"));
+        assert_eq!(options.uids, [1000]);
+        assert!(options.once);
+        assert_eq!(options.names, ["GITHUB_TOKEN"]);
+        assert!(parse_args(args(&["GITHUB_TOKEN"])).is_err());
+        assert!(parse_args(args(&["--socket", "/s"])).is_err());
+        assert!(parse_args(args(&["--socket", "/s", "--uid", "root", "A"])).is_err());
+        assert!(parse_args(args(&["--socket", "/s", "A=B"])).is_err());
+
+        let options = parse_args(args(&[
+            "--socket",
+            "/s",
+            "--mint",
+            "GH_TOKEN=mint-gh --app=1",
+        ]))
+        .unwrap();
+        assert!(options.names.is_empty());
+        assert_eq!(
+            options.mints,
+            [("GH_TOKEN".to_string(), "mint-gh --app=1".to_string())]
+        );
+        assert!(parse_args(args(&["--socket", "/s", "--mint", "GH_TOKEN"])).is_err());
+        assert!(parse_args(args(&["--socket", "/s", "--mint", "A=x", "A"])).is_err());
+    }
+
+    #[test]
+    fn test_answer_mint() {
+        let mints = [
+            (
+                "GH_TOKEN".to_string(),
+                "printf 'ghs_%s\\nrest\\n' \"$AWF_MINT_TOKEN\"".to_string(),
+            ),
+            ("BROKEN".to_string(), "echo ghs_partial; exit 1".to_string()),
+        ];
+        let (token, reply, outcome) = answer_mint(&mints, "GH_TOKEN");
+        assert_eq!(
+            (token.as_str(), reply.as_slice(), outcome),
+            ("GH_TOKEN", &b"OK ghs_GH_TOKEN\n"[..], Outcome::Minted)
+        );
+        let (_, reply, _) = answer_mint(&mints, "BROKEN");
+        assert_eq!(reply, b"ERR mint_failed\n");
+        let (_, reply, _) = answer_mint(&mints, "OTHER");
+        assert_eq!(reply, b"ERR unknown_token\n");
+    }
+
+    #[test]
+    fn test_answer() {
+        let mut entries = entries();
+        let (token, reply, outcome) = answer(&mut entries, "GET GITHUB_TOKEN 0123abcd\n", false);
+        assert_eq!(
+            (token.as_str(), reply.as_slice(), outcome),
+            ("GITHUB_TOKEN", &b"OK ghp_value\n"[..], Outcome::Served)
+        );
+        let (_, reply, _) = answer(&mut entries, "GET GITHUB_TOKEN 0123abcd\n", false);
+        assert_eq!(reply, b"OK ghp_value\n");
+
+        // A handle only works for its own name
+        let (_, reply, outcome) = answer(&mut entries, "GET OTHER 0123abcd\n", false);
+        assert_eq!(reply, b"ERR unknown_handle\n");
+        assert_eq!(outcome, Outcome::Refused("unknown_handle"));
+        let (token, _, outcome) = answer(&mut entries, "PUT x\n", false);
+        assert_eq!(
+            (token.as_str(), outcome),
+            ("*", Outcome::Refused("bad_request"))
+        );
+    }
+
+    #[test]
+    fn test_answer_once() {
+        let mut entries = entries();
+        let (_, _, outcome) = answer(&mut entries, "GET GITHUB_TOKEN 0123abcd\n", true);
+        assert_eq!(outcome, Outcome::Served);
+        let (_, reply, _) = answer(&mut entries, "GET GITHUB_TOKEN 0123abcd\n", true);
+        assert_eq!(reply, b"ERR already_served\n");
+    }
+
+    #[test]
+    fn test_new_handle() {
+        let handle = new_handle().unwrap();
+        assert_eq!(handle.len(), 32);
+        assert!(handle.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert_ne!(new_handle().unwrap(), handle);
+    }
+}
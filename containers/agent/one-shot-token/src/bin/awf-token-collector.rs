@@ -0,0 +1,839 @@
+//! awf-token-collector: one report of the token events of a whole run
+//!
+//! Usage: awf-token-collector --socket PATH [--report FILE] [--proxy-log FILE [--window SECS]]
+//!
+//! Runs on the host. Every process the library is loaded into, the agent
+//! and every child it starts in every container, sends its token accesses,
+//! audit events and scrub results to the datagram socket named by
+//! AWF_ONE_SHOT_EVENT_SOCKET (see the library's events module), each on its
+//! own. The collector binds that socket, reads the events of all processes
+//! (see the library's schema module), drops duplicates, and when it is
+//! stopped with SIGTERM or SIGINT writes a single JSON report of the run to
+//! FILE (or stdout):
+//!
+//! - `processes`: every process that sent an event, by pid and container,
+//!   with its step, the time of its first and last event and its counts
+//! - `tokens`: per token, the reads served, how many processes read it and
+//!   by which function, the first and last read and the least successful
+//!   scrub result
+//! - `audit`: per audit event and token, how often it happened, in how many
+//!   processes, and when first and last
+//!
+//! With `--proxy-log`, the collector also follows the proxy's access log
+//! (Squid's `firewall_detailed` format) and joins it with the events in
+//! `egress`. Token reads and connections are linked by correlation IDs
+//! (AWF_ONE_SHOT_CORRELATION=connect, see the library's correlation module):
+//! a read's `token_read` event carries its ID, and every connection the
+//! process or its forked children make afterwards is reported with an
+//! `egress_correlated` event carrying the ID and the local address, which
+//! the proxy logs as the client. Each read is listed with the requests of
+//! the connections made within `--window` seconds (default 10) after it, and
+//! flagged `suspicious` if none of them was allowed by the proxy: a
+//! legitimate read is normally followed by a request to an allowed host.
+//! Lines the log had when the collector started are skipped.
+//!
+//! Events of a newer schema version and datagrams that are not events are
+//! counted as `invalid`; datagrams received twice are counted as
+//! `duplicates`. Token values are never part of events.
+//!
+//! Exit status: 0 once the report is written, 1 if the socket cannot be set
+//! up or the report cannot be written, 2 on usage errors. A socket left
+//! behind is replaced on the next start.
+
+mod common;
+#[allow(dead_code)]
+#[path = "../json.rs"]
+mod json;
+#[allow(dead_code)]
+#[path = "../schema.rs"]
+mod schema;
+
+use common::json_string;
+use schema::{Event, EventError};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read as _, Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+const USAGE: &str =
+    "Usage: awf-token-collector --socket PATH [--report FILE] [--proxy-log FILE [--window SECS]]";
+
+/// Exit status when the socket or the report fails
+const EXIT_FAILED: i32 = 1;
+/// Exit status on usage errors
+const EXIT_USAGE: i32 = 2;
+
+/// How often the receive loop checks for a stop signal
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Largest datagram read; the library's events are far smaller
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+/// Scrub results from the most to the least successful
+const SCRUB_RESULTS: &[&str] = &["cleared", "masked", "exposed"];
+
+/// Default seconds after a read in which its egress is expected
+const DEFAULT_WINDOW: f64 = 10.0;
+
+/// Seconds a proxy log entry may precede the connection it belongs to, for
+/// clocks that round differently
+const CLOCK_SLACK: f64 = 1.0;
+
+/// Command-line options
+#[derive(Debug, PartialEq)]
+struct Options {
+    socket: PathBuf,
+    report: Option<PathBuf>,
+    proxy_log: Option<PathBuf>,
+    /// Seconds after a read in which its egress is expected
+    window: f64,
+}
+
+/// Parse the arguments after the program name
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut socket = None;
+    let mut report = None;
+    let mut proxy_log = None;
+    let mut window = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => {
+                socket = Some(PathBuf::from(args.next().ok_or("--socket needs a value")?))
+            }
+            "--report" => {
+                report = Some(PathBuf::from(args.next().ok_or("--report needs a value")?))
+            }
+            "--proxy-log" => {
+                proxy_log = Some(PathBuf::from(
+                    args.next().ok_or("--proxy-log needs a value")?,
+                ))
+            }
+            "--window" => {
+                let value = args.next().ok_or("--window needs a value")?;
+                window = Some(
+                    value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|window| window.is_finite() && *window > 0.0)
+                        .ok_or_else(|| format!("invalid --window {:?}", value))?,
+                );
+            }
+            flag => return Err(format!("unknown option {}", flag)),
+        }
+    }
+    if window.is_some() && proxy_log.is_none() {
+        return Err("--window needs --proxy-log".to_string());
+    }
+    Ok(Options {
+        socket: socket.ok_or("--socket is required")?,
+        report,
+        proxy_log,
+        window: window.unwrap_or(DEFAULT_WINDOW),
+    })
+}
+
+/// A process is known by its container (pids are per pid namespace) and pid
+type ProcessKey = (String, u32);
+
+/// What a process sent
+#[derive(Debug, Default)]
+struct Process {
+    step: Option<String>,
+    first: f64,
+    last: f64,
+    events: u64,
+    accesses: u64,
+    audit: u64,
+}
+
+/// How a token was used across the run
+#[derive(Debug, Default)]
+struct Token {
+    reads: u64,
+    processes: BTreeSet<ProcessKey>,
+    /// Reads per function (`getenv`, `secure_getenv`)
+    via: BTreeMap<String, u64>,
+    first_read: Option<f64>,
+    last_read: Option<f64>,
+    scrub: Option<String>,
+}
+
+/// Occurrences of one audit event for one token
+#[derive(Debug, Default)]
+struct Audit {
+    count: u64,
+    processes: BTreeSet<ProcessKey>,
+    first: f64,
+    last: f64,
+}
+
+/// A correlated token read (`token_read`)
+#[derive(Debug)]
+struct Read {
+    corr: String,
+    token: String,
+    process: ProcessKey,
+    time: f64,
+}
+
+/// A connection made after a correlated read (`egress_correlated`)
+#[derive(Debug)]
+struct Connection {
+    corr: String,
+    /// Local address, the client address in the proxy log
+    local: String,
+    time: f64,
+}
+
+/// An entry of the proxy's access log
+#[derive(Debug, PartialEq)]
+struct Request {
+    time: f64,
+    client: String,
+    host: String,
+    status: u16,
+    decision: String,
+}
+
+impl Request {
+    /// Parse a `firewall_detailed` line:
+    /// `%ts.%03tu %>a:%>p %{Host}>h %<a:%<p %rv %rm %>Hs %Ss:%Sh %ru "%{User-Agent}>h"`
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let time = fields.next()?.parse::<f64>().ok()?;
+        let client = fields.next()?;
+        let host = fields.next()?;
+        let method = fields.nth(2)?;
+        let status = fields.next()?.parse::<u16>().ok()?;
+        let decision = fields.next()?;
+        // The library's own firewall-format lines are not requests
+        if method == "TOKEN" || !client.contains(':') {
+            return None;
+        }
+        Some(Request {
+            time,
+            client: client.to_string(),
+            host: host.to_string(),
+            status,
+            decision: decision.to_string(),
+        })
+    }
+
+    /// Whether the proxy let the request through
+    fn allowed(&self) -> bool {
+        self.decision.starts_with("TCP_TUNNEL") || self.decision.starts_with("TCP_MISS")
+    }
+}
+
+/// The run's events, aggregated
+#[derive(Debug, Default)]
+struct Collector {
+    /// Hashes of the datagrams received, to drop duplicates
+    seen: HashSet<u64>,
+    events: u64,
+    duplicates: u64,
+    invalid: u64,
+    /// Whether a newer schema version was reported
+    warned_version: bool,
+    processes: BTreeMap<ProcessKey, Process>,
+    tokens: BTreeMap<String, Token>,
+    audit: BTreeMap<(String, String), Audit>,
+    /// Seconds after a read in which its egress is expected, if the proxy
+    /// log is joined
+    egress_window: Option<f64>,
+    reads: Vec<Read>,
+    connections: Vec<Connection>,
+    requests: Vec<Request>,
+}
+
+impl Collector {
+    /// Take in one datagram
+    fn add(&mut self, datagram: &[u8]) {
+        let mut hasher = DefaultHasher::new();
+        datagram.hash(&mut hasher);
+        if !self.seen.insert(hasher.finish()) {
+            self.duplicates += 1;
+            return;
+        }
+        let event = match std::str::from_utf8(datagram)
+            .map_err(|err| EventError::Json(err.to_string()))
+            .and_then(Event::parse)
+        {
+            Ok(event) => event,
+            Err(err) => {
+                if matches!(err, EventError::UnsupportedVersion(_)) && !self.warned_version {
+                    self.warned_version = true;
+                    eprintln!("awf-token-collector: {}", err);
+                }
+                self.invalid += 1;
+                return;
+            }
+        };
+        self.add_event(&event);
+    }
+
+    fn add_event(&mut self, event: &Event) {
+        self.events += 1;
+        let container = event
+            .field("container")
+            .or(event.field("cgroup"))
+            .unwrap_or_default();
+        let key: ProcessKey = (container.to_string(), event.pid);
+        let process = self
+            .processes
+            .entry(key.clone())
+            .or_insert_with(|| Process {
+                first: event.time,
+                last: event.time,
+                ..Process::default()
+            });
+        process.events += 1;
+        process.first = process.first.min(event.time);
+        process.last = process.last.max(event.time);
+        if let Some(step) = event.field("step") {
+            process.step = Some(step.to_string());
+        }
+        let Some(name) = event.token.as_deref() else {
+            return;
+        };
+        match (event.level.as_str(), event.event.as_str()) {
+            (_, "access") => {
+                process.accesses += 1;
+                let token = self.tokens.entry(name.to_string()).or_default();
+                token.reads += 1;
+                token.processes.insert(key);
+                let via = event.field("via").unwrap_or("getenv");
+                *token.via.entry(via.to_string()).or_default() += 1;
+                token.first_read = Some(token.first_read.map_or(event.time, |t| t.min(event.time)));
+                token.last_read = Some(token.last_read.map_or(event.time, |t| t.max(event.time)));
+            }
+            (_, "unset_verification") => {
+                let Some(result) = event.field("result") else {
+                    return;
+                };
+                let token = self.tokens.entry(name.to_string()).or_default();
+                let rank = |result: &str| SCRUB_RESULTS.iter().position(|known| *known == result);
+                if token.scrub.as_deref().and_then(rank) <= rank(result) {
+                    token.scrub = Some(result.to_string());
+                }
+            }
+            ("audit", audit_event) => {
+                process.audit += 1;
+                let audit = self
+                    .audit
+                    .entry((audit_event.to_string(), name.to_string()))
+                    .or_insert_with(|| Audit {
+                        first: event.time,
+                        last: event.time,
+                        ..Audit::default()
+                    });
+                audit.count += 1;
+                audit.processes.insert(key.clone());
+                audit.first = audit.first.min(event.time);
+                audit.last = audit.last.max(event.time);
+                if self.egress_window.is_none() {
+                    return;
+                }
+                let Some(corr) = event.field("corr") else {
+                    return;
+                };
+                match audit_event {
+                    "token_read" => self.reads.push(Read {
+                        corr: corr.to_string(),
+                        token: name.to_string(),
+                        process: key,
+                        time: event.time,
+                    }),
+                    "egress_correlated" => {
+                        if let Some(local) = event.field("local").filter(|local| *local != "-") {
+                            self.connections.push(Connection {
+                                corr: corr.to_string(),
+                                local: local.to_string(),
+                                time: event.time,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Take in one line of the proxy's access log
+    fn add_request(&mut self, line: &str) {
+        if let Some(request) = Request::parse(line) {
+            self.requests.push(request);
+        }
+    }
+
+    /// The `egress` section of the report: each correlated read with the
+    /// proxy requests of the connections made within the window after it
+    fn egress(&self, window: f64) -> String {
+        let mut suspicious = 0;
+        let reads: Vec<String> = self
+            .reads
+            .iter()
+            .map(|read| {
+                let requests: Vec<&Request> = self
+                    .connections
+                    .iter()
+                    .filter(|connection| {
+                        connection.corr == read.corr
+                            && (0.0..=window).contains(&(connection.time - read.time))
+                    })
+                    .filter_map(|connection| {
+                        // The earliest entry for the address: ports are
+                        // reused, but not while a connection is open
+                        self.requests
+                            .iter()
+                            .filter(|request| {
+                                request.client == connection.local
+                                    && request.time >= connection.time - CLOCK_SLACK
+                            })
+                            .min_by(|a, b| a.time.total_cmp(&b.time))
+                    })
+                    .collect();
+                let flagged = !requests.iter().any(|request| request.allowed());
+                suspicious += usize::from(flagged);
+                let requests: Vec<String> = requests
+                    .iter()
+                    .map(|request| {
+                        format!(
+                            "{{\"time\":{:.3},\"client\":{},\"host\":{},\"status\":{},\"decision\":{},\"allowed\":{}}}",
+                            request.time,
+                            json_string(&request.client),
+                            json_string(&request.host),
+                            request.status,
+                            json_string(&request.decision),
+                            request.allowed()
+                        )
+                    })
+                    .collect();
+                format!(
+                    "{{\"corr\":{},\"token\":{},\"pid\":{},\"container\":{},\"time\":{:.3},\"requests\":[{}],\"suspicious\":{}}}",
+                    json_string(&read.corr),
+                    json_string(&read.token),
+                    read.process.1,
+                    json_string(&read.process.0),
+                    read.time,
+                    requests.join(","),
+                    flagged
+                )
+            })
+            .collect();
+        format!(
+            "{{\"window\":{},\"requests\":{},\"suspicious\":{},\"reads\":[{}]}}",
+            window,
+            self.requests.len(),
+            suspicious,
+            reads.join(",")
+        )
+    }
+
+    /// The run report, as one JSON object
+    fn report(&self) -> String {
+        let time = |time: f64| format!("{:.3}", time);
+        let optional_time = |value: Option<f64>| value.map_or_else(|| "null".to_string(), time);
+        let processes: Vec<String> = self
+            .processes
+            .iter()
+            .map(|((container, pid), process)| {
+                format!(
+                    "{{\"pid\":{},\"container\":{},\"step\":{},\"first\":{},\"last\":{},\"events\":{},\"accesses\":{},\"audit\":{}}}",
+                    pid,
+                    json_string(container),
+                    process
+                        .step
+                        .as_deref()
+                        .map_or_else(|| "null".to_string(), json_string),
+                    time(process.first),
+                    time(process.last),
+                    process.events,
+                    process.accesses,
+                    process.audit
+                )
+            })
+            .collect();
+        let tokens: Vec<String> = self
+            .tokens
+            .iter()
+            .map(|(name, token)| {
+                let via: Vec<String> = token
+                    .via
+                    .iter()
+                    .map(|(via, reads)| format!("{}:{}", json_string(via), reads))
+                    .collect();
+                format!(
+                    "{{\"name\":{},\"reads\":{},\"processes\":{},\"via\":{{{}}},\"first_read\":{},\"last_read\":{},\"scrub\":{}}}",
+                    json_string(name),
+                    token.reads,
+                    token.processes.len(),
+                    via.join(","),
+                    optional_time(token.first_read),
+                    optional_time(token.last_read),
+                    token
+                        .scrub
+                        .as_deref()
+                        .map_or_else(|| "null".to_string(), json_string)
+                )
+            })
+            .collect();
+        let audit: Vec<String> = self
+            .audit
+            .iter()
+            .map(|((event, token), audit)| {
+                format!(
+                    "{{\"event\":{},\"token\":{},\"count\":{},\"processes\":{},\"first\":{},\"last\":{}}}",
+                    json_string(event),
+                    json_string(token),
+                    audit.count,
+                    audit.processes.len(),
+                    time(audit.first),
+                    time(audit.last)
+                )
+            })
+            .collect();
+        let egress = self
+            .egress_window
+            .map(|window| format!(",\"egress\":{}", self.egress(window)))
+            .unwrap_or_default();
+        format!(
+            "{{\"events\":{},\"duplicates\":{},\"invalid\":{},\"processes\":[{}],\"tokens\":[{}],\"audit\":[{}]{}}}",
+            self.events,
+            self.duplicates,
+            self.invalid,
+            processes.join(","),
+            tokens.join(","),
+            audit.join(","),
+            egress
+        )
+    }
+}
+
+/// Bind the event socket, replacing one a previous run left behind; any
+/// process may send to it
+fn bind_socket(path: &Path) -> io::Result<UnixDatagram> {
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let socket = UnixDatagram::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o666))?;
+    Ok(socket)
+}
+
+/// The proxy's access log, read as it grows
+struct Tail {
+    path: PathBuf,
+    file: Option<File>,
+    /// Where to start once the file can be opened: its end if it existed
+    /// when the collector started, its beginning if it appeared later
+    start: u64,
+    /// A line not yet complete
+    partial: Vec<u8>,
+}
+
+impl Tail {
+    fn new(path: PathBuf) -> Self {
+        let start = std::fs::metadata(&path).map_or(0, |meta| meta.len());
+        Tail {
+            path,
+            file: None,
+            start,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Pass the lines appended since the last call to `collector`
+    fn read(&mut self, collector: &mut Collector) {
+        if self.file.is_none() {
+            let Ok(mut file) = File::open(&self.path) else {
+                return;
+            };
+            if file.seek(SeekFrom::Start(self.start)).is_err() {
+                return;
+            }
+            self.file = Some(file);
+        }
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        // Start over if the log was truncated for rotation
+        let position = file.stream_position().unwrap_or(0);
+        if file.metadata().is_ok_and(|meta| meta.len() < position) {
+            self.partial.clear();
+            let _ = file.seek(SeekFrom::Start(0));
+        }
+        if file.read_to_end(&mut self.partial).is_err() {
+            return;
+        }
+        let Some(end) = self.partial.iter().rposition(|&byte| byte == b'\n') else {
+            return;
+        };
+        let rest = self.partial.split_off(end + 1);
+        for line in String::from_utf8_lossy(&self.partial).lines() {
+            collector.add_request(line);
+        }
+        self.partial = rest;
+    }
+}
+
+/// Set by SIGTERM and SIGINT
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_stop(_signum: libc::c_int) {
+    STOP.store(true, Ordering::Relaxed);
+}
+
+/// Write the report to `path` (through a temporary file, so readers never
+/// see half of it) or to stdout
+fn write_report(path: Option<&Path>, report: &str) -> io::Result<()> {
+    let Some(path) = path else {
+        let mut stdout = io::stdout().lock();
+        return writeln!(stdout, "{}", report).and_then(|()| stdout.flush());
+    };
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    std::fs::write(&partial, format!("{}\n", report))?;
+    std::fs::rename(&partial, path)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if matches!(args.first().map(String::as_str), Some("-h" | "--help")) {
+        println!("{}", USAGE);
+        return;
+    }
+    let options = parse_args(args).unwrap_or_else(|err| {
+        eprintln!("awf-token-collector: {}\n{}", err, USAGE);
+        std::process::exit(EXIT_USAGE);
+    });
+    let socket = bind_socket(&options.socket)
+        .and_then(|socket| {
+            socket.set_read_timeout(Some(POLL_INTERVAL))?;
+            Ok(socket)
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("awf-token-collector: {}: {}", options.socket.display(), err);
+            std::process::exit(EXIT_FAILED);
+        });
+    for signum in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: request_stop only stores to an atomic
+        unsafe {
+            libc::signal(
+                signum,
+                request_stop as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+    }
+    eprintln!(
+        "awf-token-collector: collecting events on {}",
+        options.socket.display()
+    );
+
+    let mut collector = Collector {
+        egress_window: options.proxy_log.as_ref().map(|_| options.window),
+        ..Collector::default()
+    };
+    let mut tail = options.proxy_log.clone().map(Tail::new);
+    let mut tailed = Instant::now();
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    while !STOP.load(Ordering::Relaxed) {
+        if let Some(tail) = tail.as_mut().filter(|_| tailed.elapsed() >= POLL_INTERVAL) {
+            tail.read(&mut collector);
+            tailed = Instant::now();
+        }
+        match socket.recv(&mut buf) {
+            Ok(len) => collector.add(&buf[..len]),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) => {}
+            Err(err) => {
+                eprintln!("awf-token-collector: {}", err);
+                break;
+            }
+        }
+    }
+    // Take in what was sent before the stop
+    if socket.set_nonblocking(true).is_ok() {
+        while let Ok(len) = socket.recv(&mut buf) {
+            collector.add(&buf[..len]);
+        }
+    }
+    let _ = std::fs::remove_file(&options.socket);
+    if let Some(tail) = tail.as_mut() {
+        tail.read(&mut collector);
+    }
+
+    if let Err(err) = write_report(options.report.as_deref(), &collector.report()) {
+        eprintln!("awf-token-collector: cannot write the report: {}", err);
+        std::process::exit(EXIT_FAILED);
+    }
+    eprintln!(
+        "awf-token-collector: {} events from {} processes",
+        collector.events,
+        collector.processes.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            parse_args(args(&[
+                "--socket",
+                "/run/awf/events.sock",
+                "--report",
+                "/tmp/r.json"
+            ])),
+            Ok(Options {
+                socket: PathBuf::from("/run/awf/events.sock"),
+                report: Some(PathBuf::from("/tmp/r.json")),
+                proxy_log: None,
+                window: DEFAULT_WINDOW,
+            })
+        );
+        let options = parse_args(args(&[
+            "--socket",
+            "/s",
+            "--proxy-log",
+            "/var/log/squid/access.log",
+            "--window",
+            "2.5",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options.proxy_log,
+            Some(PathBuf::from("/var/log/squid/access.log"))
+        );
+        assert_eq!(options.window, 2.5);
+        assert!(parse_args(args(&["--socket", "/s", "--window", "5"])).is_err());
+        assert!(parse_args(args(&[
+            "--socket",
+            "/s",
+            "--proxy-log",
+            "/l",
+            "--window",
+            "0"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&["--report", "/tmp/r.json"])).is_err());
+        assert!(parse_args(args(&["--socket"])).is_err());
+        assert!(parse_args(args(&["--socket", "/s", "--json"])).is_err());
+    }
+
+    #[test]
+    fn test_collect() {
+        let mut collector = Collector::default();
+        let lines = [
+            r#"{"time":10.000,"pid":7,"schema_version":1,"container":"agent","step":"build","level":"info","event":"access","token":"GITHUB_TOKEN","via":"getenv","reads":"1","first":"true"}"#,
+            r#"{"time":10.500,"pid":7,"schema_version":1,"container":"agent","step":"build","level":"info","event":"unset_verification","token":"GITHUB_TOKEN","result":"cleared"}"#,
+            // A forked child reading the inherited value
+            r#"{"time":11.000,"pid":8,"schema_version":1,"container":"agent","level":"info","event":"access","token":"GITHUB_TOKEN","via":"secure_getenv","reads":"2","first":"false"}"#,
+            r#"{"time":11.200,"pid":8,"schema_version":1,"container":"agent","level":"warning","event":"unset_verification","token":"GITHUB_TOKEN","result":"exposed"}"#,
+            r#"{"time":12.000,"pid":8,"schema_version":1,"container":"agent","level":"audit","event":"max_reads_exceeded","token":"GITHUB_TOKEN","max_reads":"2","reads":"2"}"#,
+            r#"{"time":12.000,"pid":8,"schema_version":1,"container":"agent","level":"audit","event":"max_reads_exceeded","token":"GITHUB_TOKEN","max_reads":"2","reads":"2"}"#,
+            // Same pid in another container
+            r#"{"time":13.000,"pid":7,"schema_version":1,"container":"proxy","level":"audit","event":"max_reads_exceeded","token":"GITHUB_TOKEN","max_reads":"2","reads":"3"}"#,
+            r#"{"time":14.000,"pid":9,"schema_version":2,"level":"info","event":"access"}"#,
+            "not json",
+        ];
+        for line in lines {
+            collector.add(line.as_bytes());
+        }
+        assert_eq!(
+            collector.report(),
+            concat!(
+                "{\"events\":6,\"duplicates\":1,\"invalid\":2,\"processes\":[",
+                "{\"pid\":7,\"container\":\"agent\",\"step\":\"build\",\"first\":10.000,\"last\":10.500,\"events\":2,\"accesses\":1,\"audit\":0},",
+                "{\"pid\":8,\"container\":\"agent\",\"step\":null,\"first\":11.000,\"last\":12.000,\"events\":3,\"accesses\":1,\"audit\":1},",
+                "{\"pid\":7,\"container\":\"proxy\",\"step\":null,\"first\":13.000,\"last\":13.000,\"events\":1,\"accesses\":0,\"audit\":1}],",
+                "\"tokens\":[{\"name\":\"GITHUB_TOKEN\",\"reads\":2,\"processes\":2,",
+                "\"via\":{\"getenv\":1,\"secure_getenv\":1},\"first_read\":10.000,\"last_read\":11.000,\"scrub\":\"exposed\"}],",
+                "\"audit\":[{\"event\":\"max_reads_exceeded\",\"token\":\"GITHUB_TOKEN\",\"count\":2,\"processes\":2,\"first\":12.000,\"last\":13.000}]}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_request_parse() {
+        assert_eq!(
+            Request::parse(
+                r#"1761074374.646 172.30.0.20:39748 api.github.com:443 140.82.114.22:443 1.1 CONNECT 200 TCP_TUNNEL:HIER_DIRECT api.github.com:443 "-""#
+            ),
+            Some(Request {
+                time: 1761074374.646,
+                client: "172.30.0.20:39748".to_string(),
+                host: "api.github.com:443".to_string(),
+                status: 200,
+                decision: "TCP_TUNNEL:HIER_DIRECT".to_string(),
+            })
+        );
+        let denied = Request::parse(r#"1760994429.358 172.30.0.20:36274 github.com:8443 -:- 1.1 CONNECT 403 TCP_DENIED:HIER_NONE github.com:8443 "curl/7.81.0""#).unwrap();
+        assert!(!denied.allowed());
+        assert_eq!(
+            Request::parse(
+                r#"1792172349.860 -:25124 GITHUB_TOKEN -:- - TOKEN 200 TCP_MISS:ACCESS token://GITHUB_TOKEN/access "gh""#
+            ),
+            None
+        );
+        assert_eq!(Request::parse("garbage"), None);
+    }
+
+    #[test]
+    fn test_egress() {
+        let mut collector = Collector {
+            egress_window: Some(5.0),
+            ..Collector::default()
+        };
+        let lines = [
+            // Read followed by an allowed request from a forked child
+            r#"{"time":100.000,"pid":7,"schema_version":1,"container":"agent","level":"audit","event":"token_read","token":"GITHUB_TOKEN","corr":"aaaa","via":"getenv"}"#,
+            r#"{"time":101.000,"pid":8,"schema_version":1,"container":"agent","level":"audit","event":"egress_correlated","token":"GITHUB_TOKEN","corr":"aaaa","local":"172.30.0.20:40001","peer":"172.30.0.10:3128"}"#,
+            // Read followed only by a denied request
+            r#"{"time":110.000,"pid":9,"schema_version":1,"container":"agent","level":"audit","event":"token_read","token":"GITHUB_TOKEN","corr":"bbbb","via":"getenv"}"#,
+            r#"{"time":111.000,"pid":9,"schema_version":1,"container":"agent","level":"audit","event":"egress_correlated","token":"GITHUB_TOKEN","corr":"bbbb","local":"172.30.0.20:40002","peer":"172.30.0.10:3128"}"#,
+            // Connection outside the window
+            r#"{"time":130.000,"pid":9,"schema_version":1,"container":"agent","level":"audit","event":"egress_correlated","token":"GITHUB_TOKEN","corr":"bbbb","local":"172.30.0.20:40003","peer":"172.30.0.10:3128"}"#,
+        ];
+        for line in lines {
+            collector.add(line.as_bytes());
+        }
+        for line in [
+            r#"90.000 172.30.0.20:40001 old.example.com:443 -:- 1.1 CONNECT 200 TCP_TUNNEL:HIER_DIRECT old.example.com:443 "-""#,
+            r#"103.500 172.30.0.20:40001 api.github.com:443 140.82.114.22:443 1.1 CONNECT 200 TCP_TUNNEL:HIER_DIRECT api.github.com:443 "-""#,
+            r#"111.200 172.30.0.20:40002 evil.example.com:443 -:- 1.1 CONNECT 403 TCP_DENIED:HIER_NONE evil.example.com:443 "-""#,
+            r#"130.100 172.30.0.20:40003 api.github.com:443 140.82.114.22:443 1.1 CONNECT 200 TCP_TUNNEL:HIER_DIRECT api.github.com:443 "-""#,
+        ] {
+            collector.add_request(line);
+        }
+        assert_eq!(
+            collector.egress(5.0),
+            concat!(
+                "{\"window\":5,\"requests\":4,\"suspicious\":1,\"reads\":[",
+                "{\"corr\":\"aaaa\",\"token\":\"GITHUB_TOKEN\",\"pid\":7,\"container\":\"agent\",\"time\":100.000,\"requests\":[",
+                "{\"time\":103.500,\"client\":\"172.30.0.20:40001\",\"host\":\"api.github.com:443\",\"status\":200,\"decision\":\"TCP_TUNNEL:HIER_DIRECT\",\"allowed\":true}],\"suspicious\":false},",
+                "{\"corr\":\"bbbb\",\"token\":\"GITHUB_TOKEN\",\"pid\":9,\"container\":\"agent\",\"time\":110.000,\"requests\":[",
+                "{\"time\":111.200,\"client\":\"172.30.0.20:40002\",\"host\":\"evil.example.com:443\",\"status\":403,\"decision\":\"TCP_DENIED:HIER_NONE\",\"allowed\":false}],\"suspicious\":true}]}"
+            )
+        );
+        assert!(collector
+            .report()
+            .ends_with(&format!(",\"egress\":{}}}", collector.egress(5.0))));
+    }
+}
@@ -0,0 +1,618 @@
+//! awf-token-swap: swap placeholder tokens for real ones at the proxy
+//!
+//! Usage: awf-token-swap --socket PATH [--uid UID]... --allow NAME=HOST[,HOST...]...
+//!
+//! Runs on the host, next to the proxy that forwards the container's
+//! requests. The library serves tokens with the `placeholder` policy as a
+//! random `awfp_` placeholder and registers the placeholder with the real
+//! value on the socket, which is mounted into the container (see the
+//! library's swap module). The request is `REGISTER NAME PLACEHOLDER VALUE`,
+//! answered with `OK registered` or `ERR REASON`; only processes of the users
+//! given with `--uid` (by default the service's own user) may register, only
+//! names given with `--allow`, and a placeholder only once.
+//!
+//! The proxy writes `HOST PLACEHOLDER` on a line of stdin for each placeholder
+//! it finds in an Authorization header, and gets back `OK VALUE` with the
+//! value to put in its place, or `ERR REASON`: `host_not_allowed` unless HOST
+//! is one of the hosts allowed for the token (`.example.com` also allows its
+//! subdomains), `unknown_placeholder` if it was never registered, and
+//! `bad_request`. A placeholder sent anywhere else is thus worthless. Every
+//! registration and lookup is reported with a `token_swap` audit line on
+//! stderr; placeholders and values never are.
+//!
+//! The library also registers honeypot values, fakes it serves to readers a
+//! token's policy refuses, with `HONEYPOT NAME VALUE`, from the same users
+//! and for any name. The proxy should then look up every credential it finds,
+//! not only placeholders, and may add the request's method and target after
+//! it: `HOST CREDENTIAL [METHOD TARGET]`. A honeypot value is answered with
+//! `ERR honeypot`, on which the proxy refuses the request, and reported with
+//! a `honeypot_egress` audit line naming the host, the target and the process
+//! that registered the value.
+//!
+//! Exit status: 0 when stdin is closed, 1 if the socket cannot be set up, 2
+//! on usage errors. A socket left behind is replaced on the next start.
+
+mod common;
+
+use common::{audit, bind_socket, detail_word, peer_credentials};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const USAGE: &str =
+    "Usage: awf-token-swap --socket PATH [--uid UID]... --allow NAME=HOST[,HOST...]...";
+
+/// Exit status when the socket cannot be set up
+const EXIT_FAILED: i32 = 1;
+/// Exit status on usage errors
+const EXIT_USAGE: i32 = 2;
+
+/// How the service's audit lines are marked
+const VIA: &str = "awf-token-swap";
+
+/// Prefix of placeholder values, as the library makes them up
+const PLACEHOLDER_PREFIX: &str = "awfp_";
+
+/// How long a client has to send its registration
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest registration line read
+const MAX_REQUEST: u64 = 64 * 1024;
+
+/// Command-line options
+#[derive(Debug, PartialEq)]
+struct Options {
+    socket: PathBuf,
+    uids: Vec<u32>,
+    /// Token names and the hosts their values may be sent to
+    allow: Vec<(String, Vec<String>)>,
+}
+
+/// Parse the arguments after the program name
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut socket = None;
+    let mut options = Options {
+        socket: PathBuf::new(),
+        uids: Vec::new(),
+        allow: Vec::new(),
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => {
+                socket = Some(PathBuf::from(args.next().ok_or("--socket needs a value")?))
+            }
+            "--uid" => {
+                let uid = args.next().ok_or("--uid needs a value")?;
+                options
+                    .uids
+                    .push(uid.parse().map_err(|_| format!("invalid uid {:?}", uid))?);
+            }
+            "--allow" => {
+                let allow = args.next().ok_or("--allow needs a value")?;
+                let (name, hosts) = allow
+                    .split_once('=')
+                    .filter(|(name, _)| !name.is_empty())
+                    .ok_or_else(|| format!("invalid --allow {:?}", allow))?;
+                let hosts: Vec<String> = hosts
+                    .split(',')
+                    .map(|host| host.trim().to_ascii_lowercase())
+                    .filter(|host| !host.is_empty())
+                    .collect();
+                if hosts.is_empty() {
+                    return Err(format!("invalid --allow {:?}", allow));
+                }
+                match options
+                    .allow
+                    .iter_mut()
+                    .find(|(allowed, _)| allowed == name)
+                {
+                    Some((_, allowed)) => allowed.extend(hosts),
+                    None => options.allow.push((name.to_string(), hosts)),
+                }
+            }
+            flag => return Err(format!("unknown option {}", flag)),
+        }
+    }
+    options.socket = socket.ok_or("--socket is required")?;
+    if options.allow.is_empty() {
+        return Err("no --allow given".to_string());
+    }
+    Ok(options)
+}
+
+/// A placeholder the library registered
+struct Registration {
+    name: String,
+    placeholder: String,
+    value: Vec<u8>,
+    /// The registering client (`pid=... uid=...`) of a honeypot value, which
+    /// stands for no value; None for placeholders
+    honeypot: Option<String>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.value.fill(0);
+    }
+}
+
+/// What the service answered, for the audit line
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Registered,
+    Swapped,
+    Refused(&'static str),
+    /// A honeypot value was looked up; holds the request's method and target,
+    /// if given, and the client that registered the value
+    Honeypot(String),
+}
+
+/// Compare two byte strings in time that depends only on their lengths
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Whether `host` (a host name, with or without port) matches an allowed
+/// host: exactly, or as a subdomain of a `.example.com` entry
+fn host_allowed(allowed: &[String], host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|byte| byte.is_ascii_digit()) => name,
+        _ => &host,
+    };
+    let host = host.trim_end_matches('.');
+    allowed
+        .iter()
+        .any(|allowed| match allowed.strip_prefix('.') {
+            Some(domain) => host == domain || host.ends_with(allowed.as_str()),
+            None => host == allowed,
+        })
+}
+
+/// Answer one registration line from a permitted user, `client`
+fn register(
+    options: &Options,
+    registrations: &mut Vec<Registration>,
+    request: &[u8],
+    client: &str,
+) -> (String, Vec<u8>, Outcome) {
+    let request = request.strip_suffix(b"\n").unwrap_or(request);
+    if let Some(request) = request.strip_prefix(b"HONEYPOT ") {
+        return register_honeypot(registrations, request, client);
+    }
+    let mut parts = request.splitn(4, |&byte| byte == b' ');
+    let (Some(b"REGISTER"), Some(name), Some(placeholder), Some(value)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return (
+            "*".to_string(),
+            b"ERR bad_request\n".to_vec(),
+            Outcome::Refused("bad_request"),
+        );
+    };
+    let name = String::from_utf8_lossy(name).into_owned();
+    let refuse = |reason: &'static str| {
+        (
+            name.clone(),
+            format!("ERR {}\n", reason).into_bytes(),
+            Outcome::Refused(reason),
+        )
+    };
+    let placeholder = match std::str::from_utf8(placeholder) {
+        Ok(placeholder)
+            if placeholder.len() > PLACEHOLDER_PREFIX.len()
+                && placeholder.starts_with(PLACEHOLDER_PREFIX) =>
+        {
+            placeholder
+        }
+        _ => return refuse("bad_request"),
+    };
+    if value.is_empty() {
+        return refuse("bad_request");
+    }
+    if !options.allow.iter().any(|(allowed, _)| *allowed == name) {
+        return refuse("unknown_token");
+    }
+    if registrations
+        .iter()
+        .any(|registration| same(registration.placeholder.as_bytes(), placeholder.as_bytes()))
+    {
+        return refuse("duplicate_placeholder");
+    }
+    registrations.push(Registration {
+        name: name.clone(),
+        placeholder: placeholder.to_string(),
+        value: value.to_vec(),
+        honeypot: None,
+    });
+    (name, b"OK registered\n".to_vec(), Outcome::Registered)
+}
+
+/// Answer a `HONEYPOT NAME VALUE` line (without the keyword)
+fn register_honeypot(
+    registrations: &mut Vec<Registration>,
+    request: &[u8],
+    client: &str,
+) -> (String, Vec<u8>, Outcome) {
+    let mut parts = request.split(|&byte| byte == b' ');
+    let (Some(name), Some(value), None) = (parts.next(), parts.next(), parts.next()) else {
+        return (
+            "*".to_string(),
+            b"ERR bad_request\n".to_vec(),
+            Outcome::Refused("bad_request"),
+        );
+    };
+    let name = String::from_utf8_lossy(name).into_owned();
+    let refuse = |reason: &'static str| {
+        (
+            name.clone(),
+            format!("ERR {}\n", reason).into_bytes(),
+            Outcome::Refused(reason),
+        )
+    };
+    let Ok(value) = std::str::from_utf8(value) else {
+        return refuse("bad_request");
+    };
+    if name.is_empty() || value.is_empty() {
+        return refuse("bad_request");
+    }
+    if registrations
+        .iter()
+        .any(|registration| same(registration.placeholder.as_bytes(), value.as_bytes()))
+    {
+        return refuse("duplicate_placeholder");
+    }
+    registrations.push(Registration {
+        name: name.clone(),
+        placeholder: value.to_string(),
+        value: Vec::new(),
+        honeypot: Some(client.to_string()),
+    });
+    (name, b"OK registered\n".to_vec(), Outcome::Registered)
+}
+
+/// Answer one lookup line from the proxy: the token and host for the audit
+/// line, the reply, and the outcome
+fn lookup(
+    options: &Options,
+    registrations: &[Registration],
+    request: &str,
+) -> (String, String, Vec<u8>, Outcome) {
+    let refuse = |token: &str, host: &str, reason: &'static str| {
+        (
+            token.to_string(),
+            host.to_string(),
+            format!("ERR {}\n", reason).into_bytes(),
+            Outcome::Refused(reason),
+        )
+    };
+    let mut parts = request.trim_end_matches(['\n', '\r']).split(' ');
+    let (Some(host), Some(placeholder)) = (parts.next(), parts.next()) else {
+        return refuse("*", "*", "bad_request");
+    };
+    let target = match (parts.next(), parts.next(), parts.next()) {
+        (None, _, _) => None,
+        (Some(method), Some(target), None) if !method.is_empty() && !target.is_empty() => {
+            Some((method, target))
+        }
+        _ => return refuse("*", "*", "bad_request"),
+    };
+    if host.is_empty() {
+        return refuse("*", "*", "bad_request");
+    }
+    let Some(registration) = registrations
+        .iter()
+        .find(|registration| same(registration.placeholder.as_bytes(), placeholder.as_bytes()))
+    else {
+        return refuse("*", host, "unknown_placeholder");
+    };
+    if let Some(client) = &registration.honeypot {
+        let request = match target {
+            Some((method, target)) => format!(
+                "method={} target={} ",
+                detail_word(method.as_bytes()),
+                detail_word(target.as_bytes())
+            ),
+            None => String::new(),
+        };
+        return (
+            registration.name.clone(),
+            host.to_string(),
+            b"ERR honeypot\n".to_vec(),
+            Outcome::Honeypot(format!("{}{}", request, client)),
+        );
+    }
+    let allowed = options
+        .allow
+        .iter()
+        .find(|(name, _)| *name == registration.name)
+        .is_some_and(|(_, hosts)| host_allowed(hosts, host));
+    if !allowed {
+        return refuse(&registration.name, host, "host_not_allowed");
+    }
+    let reply = [b"OK ", registration.value.as_slice(), b"\n"].concat();
+    (
+        registration.name.clone(),
+        host.to_string(),
+        reply,
+        Outcome::Swapped,
+    )
+}
+
+/// The audit detail of an outcome
+fn action(outcome: Outcome) -> String {
+    match outcome {
+        Outcome::Registered => "action=registered".to_string(),
+        Outcome::Swapped => "action=swapped".to_string(),
+        Outcome::Refused(reason) => format!("action=refused reason={}", reason),
+        Outcome::Honeypot(detail) => format!("severity=high {} action=blocked", detail),
+    }
+}
+
+/// Serve one registration connection
+fn serve(
+    stream: UnixStream,
+    options: &Options,
+    registrations: &Mutex<Vec<Registration>>,
+) -> io::Result<()> {
+    let (pid, uid) = peer_credentials(&stream)?;
+    let client = match pid {
+        Some(pid) => format!("pid={} uid={}", pid, uid),
+        None => format!("uid={}", uid),
+    };
+    // The request is read either way, so the refusal reaches the client
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = Vec::new();
+    BufReader::new((&stream).take(MAX_REQUEST)).read_until(b'\n', &mut request)?;
+    if !options.uids.contains(&uid) {
+        request.fill(0);
+        audit(
+            VIA,
+            "token_swap",
+            "*",
+            &format!("{} action=refused reason=forbidden_uid", client),
+        );
+        return (&stream).write_all(b"ERR forbidden\n");
+    }
+    let (token, reply, outcome) = {
+        let mut registrations = registrations.lock().unwrap_or_else(|err| err.into_inner());
+        register(options, &mut registrations, &request, &client)
+    };
+    request.fill(0);
+    audit(
+        VIA,
+        "token_swap",
+        &detail_word(token.as_bytes()),
+        &format!("{} {}", client, action(outcome)),
+    );
+    (&stream).write_all(&reply)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if matches!(args.first().map(String::as_str), Some("-h" | "--help")) {
+        println!("{}", USAGE);
+        return;
+    }
+    let mut options = parse_args(args).unwrap_or_else(|err| {
+        eprintln!("awf-token-swap: {}\n{}", err, USAGE);
+        std::process::exit(EXIT_USAGE);
+    });
+    if options.uids.is_empty() {
+        // SAFETY: getuid cannot fail
+        options.uids.push(unsafe { libc::getuid() });
+    }
+
+    let listener = bind_socket(&options.socket).unwrap_or_else(|err| {
+        eprintln!("awf-token-swap: {}: {}", options.socket.display(), err);
+        std::process::exit(EXIT_FAILED);
+    });
+    eprintln!(
+        "awf-token-swap: swapping {} tokens, registrations on {}",
+        options.allow.len(),
+        options.socket.display()
+    );
+    let options = Arc::new(options);
+    let registrations = Arc::new(Mutex::new(Vec::new()));
+    {
+        let options = Arc::clone(&options);
+        let registrations = Arc::clone(&registrations);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| serve(stream, &options, &registrations));
+                if let Err(err) = result {
+                    eprintln!("awf-token-swap: {}", err);
+                }
+            }
+        });
+    }
+
+    let mut stdout = io::stdout().lock();
+    for request in io::stdin().lock().lines() {
+        let Ok(request) = request else {
+            break;
+        };
+        let (token, host, mut reply, outcome) = {
+            let registrations = registrations.lock().unwrap_or_else(|err| err.into_inner());
+            lookup(&options, &registrations, &request)
+        };
+        let event = match outcome {
+            Outcome::Honeypot(_) => "honeypot_egress",
+            _ => "token_swap",
+        };
+        audit(
+            VIA,
+            event,
+            &detail_word(token.as_bytes()),
+            &format!("host={} {}", detail_word(host.as_bytes()), action(outcome)),
+        );
+        let result = stdout.write_all(&reply).and_then(|()| stdout.flush());
+        reply.fill(0);
+        if result.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> Options {
+        Options {
+            socket: PathBuf::from("/run/awf/swap.sock"),
+            uids: vec![1000],
+            allow: vec![(
+                "GITHUB_TOKEN".to_string(),
+                vec![
+                    "api.github.com".to_string(),
+                    ".githubusercontent.com".to_string(),
+                ],
+            )],
+        }
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let options = parse_args(args(&[
+            "--socket",
+            "/run/awf/swap.sock",
+            "--uid",
+            "1000",
+            "--allow",
+            "GITHUB_TOKEN=api.github.com",
+            "--allow",
+            "GITHUB_TOKEN=.GitHubUserContent.com, ",
+        ]))
+        .unwrap();
+        assert_eq!(options, super::tests::options());
+        assert!(parse_args(args(&["--allow", "A=h"])).is_err());
+        assert!(parse_args(args(&["--socket", "/s"])).is_err());
+        assert!(parse_args(args(&["--socket", "/s", "--allow", "A="])).is_err());
+        assert!(parse_args(args(&["--socket", "/s", "--allow", "=h"])).is_err());
+        assert!(parse_args(args(&["--socket", "/s", "--allow", "A=h", "B"])).is_err());
+    }
+
+    #[test]
+    fn test_host_allowed() {
+        let allowed = options().allow.remove(0).1;
+        assert!(host_allowed(&allowed, "api.github.com"));
+        assert!(host_allowed(&allowed, "API.GitHub.com:443"));
+        assert!(host_allowed(&allowed, "raw.githubusercontent.com"));
+        assert!(host_allowed(&allowed, "githubusercontent.com."));
+        assert!(!host_allowed(&allowed, "github.com"));
+        assert!(!host_allowed(&allowed, "api.github.com.evil.com"));
+        assert!(!host_allowed(&allowed, "evilgithubusercontent.com"));
+    }
+
+    #[test]
+    fn test_register_lookup() {
+        let options = options();
+        let mut registrations = Vec::new();
+        let (token, reply, outcome) = register(
+            &options,
+            &mut registrations,
+            b"REGISTER GITHUB_TOKEN awfp_0123 ghp_value\n",
+            "pid=42 uid=1000",
+        );
+        assert_eq!(
+            (token.as_str(), reply.as_slice(), outcome),
+            ("GITHUB_TOKEN", &b"OK registered\n"[..], Outcome::Registered)
+        );
+        let (_, reply, _) = register(
+            &options,
+            &mut registrations,
+            b"REGISTER GITHUB_TOKEN awfp_0123 ghp_other\n",
+            "pid=42 uid=1000",
+        );
+        assert_eq!(reply, b"ERR duplicate_placeholder\n");
+        let (_, reply, _) = register(
+            &options,
+            &mut registrations,
+            b"REGISTER NPM_TOKEN awfp_1 x\n",
+            "pid=42 uid=1000",
+        );
+        assert_eq!(reply, b"ERR unknown_token\n");
+        let (_, reply, _) = register(
+            &options,
+            &mut registrations,
+            b"REGISTER GITHUB_TOKEN x y\n",
+            "uid=1000",
+        );
+        assert_eq!(reply, b"ERR bad_request\n");
+
+        let (token, host, reply, outcome) =
+            lookup(&options, &registrations, "api.github.com awfp_0123\n");
+        assert_eq!(
+            (token.as_str(), host.as_str(), reply.as_slice(), outcome),
+            (
+                "GITHUB_TOKEN",
+                "api.github.com",
+                &b"OK ghp_value\n"[..],
+                Outcome::Swapped
+            )
+        );
+        let (token, _, reply, _) = lookup(&options, &registrations, "evil.com awfp_0123");
+        assert_eq!(
+            (token.as_str(), reply.as_slice()),
+            ("GITHUB_TOKEN", &b"ERR host_not_allowed\n"[..])
+        );
+        let (_, _, reply, _) = lookup(&options, &registrations, "api.github.com awfp_9999");
+        assert_eq!(reply, b"ERR unknown_placeholder\n");
+        let (_, _, reply, _) = lookup(&options, &registrations, "awfp_0123");
+        assert_eq!(reply, b"ERR bad_request\n");
+        let (_, _, reply, _) = lookup(&options, &registrations, "h awfp_0123 GET");
+        assert_eq!(reply, b"ERR bad_request\n");
+    }
+
+    #[test]
+    fn test_honeypot() {
+        let options = options();
+        let mut registrations = Vec::new();
+        // Any name may have a honeypot value
+        let (token, reply, outcome) = register(
+            &options,
+            &mut registrations,
+            b"HONEYPOT NPM_TOKEN npm_Fake0123\n",
+            "pid=42 uid=1000",
+        );
+        assert_eq!(
+            (token.as_str(), reply.as_slice(), outcome),
+            ("NPM_TOKEN", &b"OK registered\n"[..], Outcome::Registered)
+        );
+        let (_, reply, _) = register(
+            &options,
+            &mut registrations,
+            b"HONEYPOT NPM_TOKEN npm_Fake0123\n",
+            "pid=42 uid=1000",
+        );
+        assert_eq!(reply, b"ERR duplicate_placeholder\n");
+        let (_, reply, _) = register(&options, &mut registrations, b"HONEYPOT NPM_TOKEN\n", "");
+        assert_eq!(reply, b"ERR bad_request\n");
+
+        let (token, host, reply, outcome) = lookup(
+            &options,
+            &registrations,
+            "evil.example:443 npm_Fake0123 POST /upload\n",
+        );
+        assert_eq!(
+            (token.as_str(), host.as_str(), reply.as_slice()),
+            ("NPM_TOKEN", "evil.example:443", &b"ERR honeypot\n"[..])
+        );
+        assert_eq!(
+            action(outcome),
+            "severity=high method=POST target=/upload pid=42 uid=1000 action=blocked"
+        );
+        let (_, _, reply, outcome) =
+            lookup(&options, &registrations, "registry.npmjs.org npm_Fake0123");
+        assert_eq!(reply, b"ERR honeypot\n");
+        assert_eq!(
+            action(outcome),
+            "severity=high pid=42 uid=1000 action=blocked"
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! awf-token-translate: turn derived tokens back into real ones at the proxy
+//!
+//! Usage: awf-token-translate [--revoked PATH] NAME...
+//!
+//! Runs on the host, next to the proxy that forwards the container's
+//! requests. The library serves tokens with the `derive` policy as
+//! `awfd_IDENTITY_MAC`, a value of their own for every executable that reads
+//! them (see the library's derive module). For each NAME taken from its own
+//! environment, the translator recognizes the values derived from it.
+//!
+//! The proxy writes each credential it finds in a request on a line of the
+//! translator's stdin, and gets back `OK VALUE` with the real value to send
+//! upstream, or `ERR REASON`: `revoked` if the identity is listed in the
+//! `--revoked` file (one identity per line, `#` starts a comment; re-read for
+//! every request, so revoking a tool takes effect at once), `unknown_token`
+//! if no NAME's value matches, and `bad_request` for anything that is not a
+//! derived value. Every request is reported with a `token_translate` audit
+//! line on stderr naming the token and identity; values never are.
+//!
+//! Exit status: 0 when stdin is closed, 2 on usage errors.
+
+mod common;
+#[allow(dead_code)]
+#[path = "../derive.rs"]
+mod derive;
+#[allow(dead_code)]
+#[path = "../sha256.rs"]
+mod sha256;
+#[allow(dead_code)]
+#[path = "../sys.rs"]
+mod sys;
+
+use common::{audit, detail_word};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "Usage: awf-token-translate [--revoked PATH] NAME...";
+
+/// Exit status on usage errors
+const EXIT_USAGE: i32 = 2;
+
+/// How the translator's audit lines are marked
+const VIA: &str = "awf-token-translate";
+
+/// Command-line options
+#[derive(Debug, PartialEq)]
+struct Options {
+    revoked: Option<PathBuf>,
+    names: Vec<String>,
+}
+
+/// Parse the arguments after the program name
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        revoked: None,
+        names: Vec::new(),
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--revoked" => {
+                options.revoked = Some(PathBuf::from(args.next().ok_or("--revoked needs a value")?))
+            }
+            flag if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+            name => {
+                if name.contains('=') {
+                    return Err(format!("invalid variable name {:?}", name));
+                }
+                options.names.push(name.to_string());
+            }
+        }
+    }
+    if options.names.is_empty() {
+        return Err("no token names given".to_string());
+    }
+    Ok(options)
+}
+
+/// A real value derived tokens are translated to
+struct Entry {
+    name: String,
+    value: Vec<u8>,
+}
+
+/// What the translator answered, for the audit line
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Translated,
+    Refused(&'static str),
+}
+
+/// Identities listed in the revocation file
+fn read_revoked(path: &Path) -> io::Result<Vec<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Answer one request line: the token and identity for the audit line, the
+/// reply, and the outcome
+fn answer(
+    entries: &[Entry],
+    revoked: &io::Result<Vec<String>>,
+    request: &str,
+) -> (String, String, Vec<u8>, Outcome) {
+    let refuse = |token: &str, identity: &str, reason: &'static str| {
+        (
+            token.to_string(),
+            identity.to_string(),
+            format!("ERR {}\n", reason).into_bytes(),
+            Outcome::Refused(reason),
+        )
+    };
+    let Some((identity, mac)) = derive::parse(request.trim_end_matches(['\n', '\r'])) else {
+        return refuse("*", "*", "bad_request");
+    };
+    let Some(entry) = entries
+        .iter()
+        .find(|entry| derive::matches(&entry.value, identity, &mac))
+    else {
+        return refuse("*", identity, "unknown_token");
+    };
+    match revoked {
+        Ok(revoked) if revoked.iter().any(|revoked| revoked == identity) => {
+            return refuse(&entry.name, identity, "revoked");
+        }
+        Ok(_) => {}
+        // A revocation list that cannot be read revokes everything
+        Err(_) => return refuse(&entry.name, identity, "revocation_unreadable"),
+    }
+    let reply = [b"OK ", entry.value.as_slice(), b"\n"].concat();
+    (
+        entry.name.clone(),
+        identity.to_string(),
+        reply,
+        Outcome::Translated,
+    )
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if matches!(args.first().map(String::as_str), Some("-h" | "--help")) {
+        println!("{}", USAGE);
+        return;
+    }
+    let options = parse_args(args).unwrap_or_else(|err| {
+        eprintln!("awf-token-translate: {}\n{}", err, USAGE);
+        std::process::exit(EXIT_USAGE);
+    });
+
+    let mut entries = Vec::new();
+    for name in &options.names {
+        let Some(value) = std::env::var_os(name) else {
+            eprintln!("awf-token-translate: {} is not set", name);
+            std::process::exit(EXIT_USAGE);
+        };
+        entries.push(Entry {
+            name: name.clone(),
+            value: std::os::unix::ffi::OsStringExt::into_vec(value),
+        });
+    }
+
+    let mut stdout = io::stdout().lock();
+    for request in io::stdin().lock().lines() {
+        let Ok(request) = request else {
+            break;
+        };
+        let revoked = match &options.revoked {
+            Some(path) => read_revoked(path),
+            None => Ok(Vec::new()),
+        };
+        let (token, identity, mut reply, outcome) = answer(&entries, &revoked, &request);
+        let action = match outcome {
+            Outcome::Translated => "action=translated".to_string(),
+            Outcome::Refused(reason) => format!("action=refused reason={}", reason),
+        };
+        audit(
+            VIA,
+            "token_translate",
+            &detail_word(token.as_bytes()),
+            &format!("identity={} {}", detail_word(identity.as_bytes()), action),
+        );
+        let result = stdout.write_all(&reply).and_then(|()| stdout.flush());
+        reply.fill(0);
+        if result.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry {
+                name: "GITHUB_TOKEN".to_string(),
+                value: b"ghp_value".to_vec(),
+            },
+            Entry {
+                name: "OPENAI_API_KEY".to_string(),
+                value: b"sk-value".to_vec(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let options = parse_args(args(&["--revoked", "/run/awf/revoked", "GITHUB_TOKEN"])).unwrap();
+        assert_eq!(options.revoked, Some(PathBuf::from("/run/awf/revoked")));
+        assert_eq!(options.names, ["GITHUB_TOKEN"]);
+        assert!(parse_args(args(&[])).is_err());
+        assert!(parse_args(args(&["--revoked"])).is_err());
+        assert!(parse_args(args(&["A=B"])).is_err());
+        assert!(parse_args(args(&["--once", "A"])).is_err());
+    }
+
+    #[test]
+    fn test_answer() {
+        let entries = entries();
+        let none = Ok(Vec::new());
+        let request = format!("{}\n", derive::derive(b"sk-value", "python3.12"));
+        let (token, identity, reply, outcome) = answer(&entries, &none, &request);
+        assert_eq!(
+            (token.as_str(), identity.as_str(), reply.as_slice(), outcome),
+            (
+                "OPENAI_API_KEY",
+                "python3.12",
+                &b"OK sk-value\n"[..],
+                Outcome::Translated
+            )
+        );
+
+        let (_, _, reply, _) = answer(&entries, &none, &derive::derive(b"ghp_other", "gh"));
+        assert_eq!(reply, b"ERR unknown_token\n");
+        let (token, _, reply, _) = answer(&entries, &none, "ghp_value\n");
+        assert_eq!(
+            (token.as_str(), reply.as_slice()),
+            ("*", &b"ERR bad_request\n"[..])
+        );
+    }
+
+    #[test]
+    fn test_answer_revoked() {
+        let entries = entries();
+        let request = derive::derive(b"ghp_value", "gh");
+        let revoked = Ok(vec!["gh".to_string()]);
+        let (token, _, reply, outcome) = answer(&entries, &revoked, &request);
+        assert_eq!(token, "GITHUB_TOKEN");
+        assert_eq!(reply, b"ERR revoked\n");
+        assert_eq!(outcome, Outcome::Refused("revoked"));
+        let (_, _, reply, _) = answer(&entries, &revoked, &derive::derive(b"ghp_value", "git"));
+        assert_eq!(reply, b"OK ghp_value\n");
+        let unreadable = Err(io::ErrorKind::NotFound.into());
+        let (_, _, reply, _) = answer(&entries, &unreadable, &request);
+        assert_eq!(reply, b"ERR revocation_unreadable\n");
+    }
+
+    #[test]
+    fn test_read_revoked() {
+        let path = std::env::temp_dir().join(format!("awf-revoked-{}", std::process::id()));
+        std::fs::write(&path, "# revoked tools\ngh\n\n  python3.12 # leaked\n").unwrap();
+        assert_eq!(read_revoked(&path).unwrap(), ["gh", "python3.12"]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(read_revoked(&path).is_err());
+    }
+}
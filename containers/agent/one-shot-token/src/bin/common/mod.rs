@@ -0,0 +1,291 @@
+//! Shared by the companion binaries: where the library is and how it is
+//! loaded, which names it protects, reading /proc past its redaction and
+//! the memory of other processes, audit lines in the library's format, and
+//! the Unix sockets of the host-side services
+
+// Each binary uses part of this module
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Variable that makes the dynamic linker load the library
+#[cfg(not(target_os = "macos"))]
+pub const PRELOAD_VAR: &str = "LD_PRELOAD";
+#[cfg(target_os = "macos")]
+pub const PRELOAD_VAR: &str = "DYLD_INSERT_LIBRARIES";
+
+/// File names the library is installed or built under
+#[cfg(not(target_os = "macos"))]
+pub const LIBRARY_NAMES: &[&str] = &["one-shot-token.so", "libone_shot_token.so"];
+#[cfg(target_os = "macos")]
+pub const LIBRARY_NAMES: &[&str] = &["one-shot-token.dylib", "libone_shot_token.dylib"];
+
+/// Where the container image installs the library
+const INSTALL_DIR: &str = "/usr/local/lib";
+
+/// Look for the library next to the running binary, in `../lib` relative to
+/// it, then in /usr/local/lib
+pub fn search_library() -> Result<PathBuf, String> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    {
+        dirs.push(dir.join("../lib"));
+        dirs.insert(0, dir);
+    }
+    dirs.push(PathBuf::from(INSTALL_DIR));
+    dirs.iter()
+        .flat_map(|dir| LIBRARY_NAMES.iter().map(move |name| dir.join(name)))
+        .find(|path| path.exists())
+        .ok_or_else(|| {
+            format!(
+                "{} not found next to this program or in {}; pass --lib",
+                LIBRARY_NAMES[0], INSTALL_DIR
+            )
+        })
+}
+
+/// Read a whole file, opening it with a raw system call on Linux
+///
+/// The library redacts /proc/<pid>/environ for opens made through libc, so a
+/// binary that has it preloaded would otherwise see the redacted copy.
+pub fn read_unredacted(path: &Path) -> io::Result<Vec<u8>> {
+    #[cfg(target_os = "linux")]
+    let mut file = {
+        use std::os::fd::FromRawFd;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        // SAFETY: valid C string; a returned descriptor is owned by the File
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat,
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                libc::O_RDONLY | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe { std::fs::File::from_raw_fd(fd as libc::c_int) }
+    };
+    #[cfg(not(target_os = "linux"))]
+    let mut file = std::fs::File::open(path)?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// The protected names: AWF_ONE_SHOT_TOKENS or `defaults` (the library's
+/// built-in list), with AWF_ONE_SHOT_EXTRA_TOKENS applied, plus `extra`
+///
+/// Policy options after `:` are ignored.
+pub fn protected_names(
+    tokens: Option<&str>,
+    extra_tokens: Option<&str>,
+    defaults: &[&str],
+    extra: &[String],
+) -> Vec<String> {
+    let name = |entry: &str| entry.split(':').next().unwrap_or("").trim().to_string();
+    let mut names: Vec<String> = match tokens.filter(|tokens| !tokens.trim().is_empty()) {
+        Some(tokens) => tokens
+            .split(',')
+            .map(name)
+            .filter(|name| !name.is_empty())
+            .collect(),
+        None => defaults.iter().map(|name| name.to_string()).collect(),
+    };
+    for entry in extra_tokens.unwrap_or("").split(',').map(str::trim) {
+        if let Some(removed) = entry.strip_prefix('-') {
+            let removed = name(removed);
+            names.retain(|listed| *listed != removed);
+        } else if !name(entry).is_empty() && !names.contains(&name(entry)) {
+            names.push(name(entry));
+        }
+    }
+    for name in extra {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    names
+}
+
+/// Quote a string as a JSON string literal
+pub fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Longest string read from another process's memory
+const MAX_STRING: usize = 1 << 20;
+
+/// Read the NUL-terminated string at `addr` from `memory`, an open
+/// /proc/<pid>/mem file
+pub fn read_c_string(memory: &File, mut addr: u64) -> io::Result<Vec<u8>> {
+    let mut string = Vec::new();
+    loop {
+        // Chunks never cross a page boundary, past which nothing may be
+        // mapped
+        let mut chunk = [0u8; 256];
+        let len = chunk.len() - (addr % chunk.len() as u64) as usize;
+        let read = memory.read_at(&mut chunk[..len], addr)?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if let Some(end) = chunk[..read].iter().position(|&byte| byte == 0) {
+            string.extend_from_slice(&chunk[..end]);
+            return Ok(string);
+        }
+        string.extend_from_slice(&chunk[..read]);
+        if string.len() > MAX_STRING {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        addr += read as u64;
+    }
+}
+
+/// The process an environ file under /proc belongs to
+#[derive(Debug, PartialEq, Eq)]
+pub enum EnvironOwner {
+    /// `/proc/self` or `/proc/thread-self`: the process opening it
+    Current,
+    /// `/proc/<pid>` or `/proc/<pid>/task/<tid>` (the tid)
+    Pid(u32),
+}
+
+/// The owner of `path` if it is an environ file under /proc
+///
+/// Only absolute paths are recognized, as in the library.
+pub fn environ_owner(path: &[u8]) -> Option<EnvironOwner> {
+    let rest = path.strip_prefix(b"/proc/")?;
+    let parts: Vec<&[u8]> = rest
+        .split(|&byte| byte == b'/')
+        .filter(|part| !part.is_empty())
+        .collect();
+    let owner = |part: &[u8]| match part {
+        b"self" | b"thread-self" => Some(EnvironOwner::Current),
+        _ => std::str::from_utf8(part)
+            .ok()?
+            .parse()
+            .ok()
+            .map(EnvironOwner::Pid),
+    };
+    match parts.as_slice() {
+        [process, b"environ"] => owner(process),
+        [process, b"task", tid, b"environ"] => {
+            owner(process)?;
+            owner(tid).filter(|tid| *tid != EnvironOwner::Current)
+        }
+        _ => None,
+    }
+}
+
+/// A detail value, quoted if it would not form a single word
+pub fn detail_word(value: &[u8]) -> String {
+    let value = String::from_utf8_lossy(value);
+    if value.is_empty() || value.contains(|c: char| c == ' ' || c == '"' || c.is_control()) {
+        format!("{:?}", value)
+    } else {
+        value.into_owned()
+    }
+}
+
+/// Write an audit line in the library's text format to stderr, marked with
+/// the binary that wrote it
+pub fn audit(via: &str, event: &str, token: &str, detail: &str) {
+    eprintln!(
+        "[one-shot-token] AUDIT event={} token={} {} via={}",
+        event, token, detail, via
+    );
+}
+
+/// Process id (where the platform reports it) and user id of the client
+pub fn peer_credentials(stream: &UnixStream) -> io::Result<(Option<i32>, u32)> {
+    let fd = stream.as_raw_fd();
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: ucred is plain data, and the length matches the buffer
+        let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                (&mut cred as *mut libc::ucred).cast(),
+                &mut len,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((Some(cred.pid), cred.uid))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let (mut uid, mut gid) = (0, 0);
+        // SAFETY: uid and gid are valid out-pointers
+        if unsafe { libc::getpeereid(fd, &mut uid, &mut gid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((None, uid))
+    }
+}
+
+/// Bind a service socket, replacing one a previous run left behind; anyone
+/// may connect, and the user check decides who is served
+pub fn bind_socket(path: &Path) -> io::Result<UnixListener> {
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o666))?;
+    Ok(listener)
+}
+
+/// Child process that forwarded signals go to
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// Pass a signal on to the command
+extern "C" fn forward_signal(signum: libc::c_int) {
+    let pid = CHILD.load(Ordering::Relaxed);
+    if pid > 0 {
+        // SAFETY: kill is async-signal-safe
+        unsafe { libc::kill(pid, signum) };
+    }
+}
+
+/// Forward the terminating signals a supervisor sends to the command
+pub fn forward_signals(pid: u32) {
+    CHILD.store(pid as i32, Ordering::Relaxed);
+    for signum in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT] {
+        // SAFETY: forward_signal only calls async-signal-safe functions
+        unsafe {
+            libc::signal(
+                signum,
+                forward_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+    }
+}
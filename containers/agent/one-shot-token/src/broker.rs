@@ -0,0 +1,444 @@
+//! Token broker handles (`awf-broker:` values)
+//!
+//! With AWF_ONE_SHOT_BROKER_SOCKET set, a protected token whose value is
+//! `awf-broker:` followed by a handle does not hold the secret at all. When
+//! the token is cached (on its first read, or at load with AWF_ONE_SHOT_EAGER)
+//! the library connects to the broker listening on that Unix socket, e.g.
+//! awf-token-broker on the host with the socket mounted into the container,
+//! sends the token name and handle, and caches the value it answers with. The
+//! plaintext never appears in the container's environment, environ files or
+//! any other file; the variable is scrubbed like any other token.
+//!
+//! Before the handle is sent, the credentials of the socket's other end
+//! (SO_PEERCRED, or getpeereid on macOS) must show the user of
+//! AWF_ONE_SHOT_BROKER_UID (default 0), so a process that took over the socket
+//! path cannot collect handles. The broker checks the credentials of the
+//! library's process in turn. A broker that refuses the handle or does not
+//! answer within five seconds leaves the token unset.
+//!
+//! Tokens listed in AWF_ONE_SHOT_MINT_TOKENS need no handle at all: when one
+//! is read and not set, the library asks the broker to mint a value, e.g. a
+//! short-lived installation token that the broker obtains by exchanging the
+//! Actions OIDC token. The environment holds no credential until it is
+//! needed. Minting may involve network requests, so the broker gets thirty
+//! seconds to answer.
+//!
+//! Protocol: one exchange per connection. The request is the line
+//! `GET <name> <handle>` or `MINT <name>`, the answer `OK <value>` or
+//! `ERR <reason>`.
+
+use crate::{audit, egress, sys};
+use libc::{c_int, uid_t};
+use std::ffi::{CStr, CString};
+use std::io;
+
+/// Prefix of token values that are broker handles
+pub(crate) const PREFIX: &[u8] = b"awf-broker:";
+
+/// Send and receive timeout
+pub(crate) const TIMEOUT_SECS: libc::time_t = 5;
+
+/// Send and receive timeout of mint requests
+const MINT_TIMEOUT_SECS: libc::time_t = 30;
+
+/// Largest answer read
+const MAX_ANSWER: usize = 64 * 1024;
+
+/// The handle a token value holds, if it is a broker handle
+pub(crate) fn handle(value: &[u8]) -> Option<&[u8]> {
+    value.strip_prefix(PREFIX).filter(|handle| {
+        !handle.is_empty()
+            && handle
+                .iter()
+                .all(|&byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+    })
+}
+
+/// Where the broker listens and which user must run it
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Broker {
+    socket: CString,
+    uid: uid_t,
+}
+
+impl Broker {
+    /// The broker of AWF_ONE_SHOT_BROKER_SOCKET and AWF_ONE_SHOT_BROKER_UID;
+    /// None if the socket is empty or the uid is not a number
+    pub(crate) fn parse(socket: &str, uid: Option<&str>) -> Option<Self> {
+        let socket = socket.trim();
+        if socket.is_empty() {
+            return None;
+        }
+        let uid = match uid.map(str::trim).filter(|uid| !uid.is_empty()) {
+            Some(uid) => uid.parse().ok()?,
+            None => 0,
+        };
+        Some(Broker {
+            socket: CString::new(socket).ok()?,
+            uid,
+        })
+    }
+
+    pub(crate) fn socket(&self) -> String {
+        self.socket.to_string_lossy().into_owned()
+    }
+
+    /// Connect to the socket, once its other end shows the expected user
+    ///
+    /// # Safety
+    /// Only performs system calls on a socket it creates
+    pub(crate) unsafe fn connect(&self, timeout_secs: libc::time_t) -> io::Result<c_int> {
+        let fd = connect(&self.socket, timeout_secs)?;
+        match peer_uid(fd) {
+            Ok(uid) if uid == self.uid => Ok(fd),
+            result => {
+                libc::close(fd);
+                let err = result.map_or_else(
+                    |err| err,
+                    |uid| {
+                        io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            format!("peer runs as uid {}", uid),
+                        )
+                    },
+                );
+                Err(err)
+            }
+        }
+    }
+}
+
+/// The answer's value, or the reason the broker gave for refusing
+fn parse_answer(answer: &[u8]) -> Result<&[u8], String> {
+    let answer = answer.strip_suffix(b"\n").unwrap_or(answer);
+    if let Some(value) = answer.strip_prefix(b"OK ") {
+        return Ok(value);
+    }
+    let reason = answer.strip_prefix(b"ERR ").unwrap_or(b"malformed_answer");
+    Err(String::from_utf8_lossy(reason).into_owned())
+}
+
+/// User id of the process at the other end of the connected socket `fd`
+fn peer_uid(fd: c_int) -> io::Result<uid_t> {
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: ucred is plain data, and the length matches the buffer
+        let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                (&mut cred as *mut libc::ucred).cast(),
+                &mut len,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(cred.uid)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let (mut uid, mut gid) = (0, 0);
+        // SAFETY: uid and gid are valid out-pointers
+        if unsafe { libc::getpeereid(fd, &mut uid, &mut gid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(uid)
+    }
+}
+
+/// Connect to the broker's socket with the send and receive timeouts set
+///
+/// # Safety
+/// Only performs system calls on a socket it creates
+unsafe fn connect(path: &CStr, timeout_secs: libc::time_t) -> io::Result<c_int> {
+    let mut addr: libc::sockaddr_un = std::mem::zeroed();
+    let path = path.to_bytes();
+    if path.len() >= addr.sun_path.len() {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (dest, &byte) in addr.sun_path.iter_mut().zip(path) {
+        *dest = byte as libc::c_char;
+    }
+
+    let fd = libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0);
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    let timeout = libc::timeval {
+        tv_sec: timeout_secs,
+        tv_usec: 0,
+    };
+    for option in [libc::SO_RCVTIMEO, libc::SO_SNDTIMEO] {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            (&timeout as *const libc::timeval).cast(),
+            std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+        );
+    }
+    let len = std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+    if libc::connect(fd, (&addr as *const libc::sockaddr_un).cast(), len) != 0 {
+        let err = io::Error::last_os_error();
+        libc::close(fd);
+        return Err(err);
+    }
+    Ok(fd)
+}
+
+/// Send the request and read the whole answer
+///
+/// # Safety
+/// `fd` must be a connected socket
+unsafe fn exchange(fd: c_int, request: &[u8]) -> io::Result<Vec<u8>> {
+    send(fd, request)?;
+    let mut answer = Vec::new();
+    receive(fd, &mut answer, |answer| answer.ends_with(b"\n"))?;
+    Ok(answer)
+}
+
+/// Write all of `request` to `fd`
+///
+/// # Safety
+/// `fd` must be a connected socket
+pub(crate) unsafe fn send(fd: c_int, request: &[u8]) -> io::Result<()> {
+    let mut sent = 0;
+    while sent < request.len() {
+        let rest = &request[sent..];
+        // Bypass the egress scan: the request holds no secret, or one meant
+        // for the swap proxy (see the swap module)
+        let n = egress::call_real_write(fd, rest.as_ptr().cast(), rest.len());
+        if n < 0 && *sys::errno_location() == libc::EINTR {
+            continue;
+        }
+        if n <= 0 {
+            return Err(io::Error::last_os_error());
+        }
+        sent += n as usize;
+    }
+    Ok(())
+}
+
+/// Read from `fd` into `answer` until `complete` says it is whole or the
+/// other end closes the connection
+///
+/// `answer` is zeroed on errors, including answers over 64 KiB.
+///
+/// # Safety
+/// `fd` must be a connected socket
+pub(crate) unsafe fn receive(
+    fd: c_int,
+    answer: &mut Vec<u8>,
+    complete: impl Fn(&[u8]) -> bool,
+) -> io::Result<()> {
+    let mut buf = [0u8; 4096];
+    while !complete(answer) {
+        let n = libc::read(fd, buf.as_mut_ptr().cast(), buf.len());
+        match n {
+            0 => break,
+            n if n > 0 => answer.extend_from_slice(&buf[..n as usize]),
+            _ if *sys::errno_location() == libc::EINTR => continue,
+            _ => {
+                let err = io::Error::last_os_error();
+                zero(answer);
+                zero(&mut buf);
+                return Err(err);
+            }
+        }
+        if answer.len() > MAX_ANSWER {
+            zero(answer);
+            zero(&mut buf);
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+    }
+    zero(&mut buf);
+    Ok(())
+}
+
+/// Why a handle could not be resolved
+pub(crate) enum Failure {
+    /// The broker could not be reached, or is not run by the expected user
+    Unreachable(io::Error),
+    /// The broker refused the handle
+    Refused(String),
+}
+
+/// Send `request` to the broker and take the value it answers with
+///
+/// # Safety
+/// Only performs system calls on a socket it creates
+pub(crate) unsafe fn request(
+    broker: &Broker,
+    request: &[u8],
+    timeout_secs: libc::time_t,
+) -> Result<CString, Failure> {
+    let fd = broker.connect(timeout_secs).map_err(Failure::Unreachable)?;
+    let answer = exchange(fd, request);
+    libc::close(fd);
+    let mut answer = answer.map_err(Failure::Unreachable)?;
+    let value = parse_answer(&answer)
+        .map_err(Failure::Refused)
+        .and_then(|value| {
+            CString::new(value).map_err(|_| Failure::Refused("malformed_value".to_string()))
+        });
+    zero(&mut answer);
+    value
+}
+
+/// Resolve the broker handle of `token`
+///
+/// Emits a `broker` audit event either way (the handle itself is never
+/// logged). Returns None if the broker could not be reached or refused.
+///
+/// # Safety
+/// Only performs system calls on a socket it creates
+pub(crate) unsafe fn fetch(broker: &Broker, token: &str, handle: &[u8]) -> Option<CString> {
+    let line = [b"GET ", token.as_bytes(), b" ", handle, b"\n"].concat();
+    let result = request(broker, &line, TIMEOUT_SECS);
+    report(broker, token, "fetched", result)
+}
+
+/// Have the broker mint a value for `token`
+///
+/// Emits a `broker` audit event either way. Returns None if the broker could
+/// not be reached or refused.
+///
+/// # Safety
+/// Only performs system calls on a socket it creates
+pub(crate) unsafe fn mint(broker: &Broker, token: &str) -> Option<CString> {
+    let line = [b"MINT ", token.as_bytes(), b"\n"].concat();
+    let result = request(broker, &line, MINT_TIMEOUT_SECS);
+    report(broker, token, "minted", result)
+}
+
+/// Report the outcome of a request with a `broker` audit event
+fn report(
+    broker: &Broker,
+    token: &str,
+    action: &str,
+    result: Result<CString, Failure>,
+) -> Option<CString> {
+    let socket = audit::detail_word(&broker.socket());
+    match result {
+        Ok(value) => {
+            audit::emit(
+                "broker",
+                token,
+                &format!(
+                    "action={} socket={} bytes={}",
+                    action,
+                    socket,
+                    value.as_bytes().len()
+                ),
+            );
+            Some(value)
+        }
+        Err(Failure::Refused(reason)) => {
+            audit::emit(
+                "broker",
+                token,
+                &format!(
+                    "action=refused socket={} reason={}",
+                    socket,
+                    audit::detail_word(&reason)
+                ),
+            );
+            None
+        }
+        Err(Failure::Unreachable(err)) => {
+            audit::emit(
+                "broker",
+                token,
+                &format!(
+                    "action=unreachable socket={} error={}",
+                    socket,
+                    audit::detail_word(&err.to_string())
+                ),
+            );
+            None
+        }
+    }
+}
+
+fn zero(bytes: &mut [u8]) {
+    // SAFETY: bytes is a writable buffer of the given length
+    unsafe { sys::explicit_bzero(bytes.as_mut_ptr().cast(), bytes.len()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_handle() {
+        assert_eq!(handle(b"awf-broker:3f9a_b-1"), Some(&b"3f9a_b-1"[..]));
+        assert_eq!(handle(b"awf-broker:"), None);
+        assert_eq!(handle(b"awf-broker:a b"), None);
+        assert_eq!(handle(b"ghp_abc"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let broker = Broker::parse(" /run/awf/broker.sock ", None).unwrap();
+        assert_eq!(broker.socket(), "/run/awf/broker.sock");
+        assert_eq!(broker.uid, 0);
+        assert_eq!(Broker::parse("/s", Some("1000")).unwrap().uid, 1000);
+        assert!(Broker::parse("/s", Some("root")).is_none());
+        assert!(Broker::parse("  ", None).is_none());
+    }
+
+    #[test]
+    fn test_parse_answer() {
+        assert_eq!(parse_answer(b"OK ghp_value\n"), Ok(&b"ghp_value"[..]));
+        assert_eq!(
+            parse_answer(b"ERR unknown_handle\n"),
+            Err("unknown_handle".to_string())
+        );
+        assert_eq!(parse_answer(b""), Err("malformed_answer".to_string()));
+    }
+
+    #[test]
+    fn test_fetch() {
+        let path = std::env::temp_dir().join(format!("awf-broker-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..3 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut line = String::new();
+                BufReader::new(&stream).read_line(&mut line).unwrap();
+                let answer = match line.as_str() {
+                    "GET MY_TOKEN h1\n" => "OK brokered-value\n",
+                    "MINT MY_TOKEN\n" => "OK minted-value\n",
+                    _ => "ERR unknown_handle\n",
+                };
+                (&stream).write_all(answer.as_bytes()).unwrap();
+                requests.push(line);
+            }
+            requests
+        });
+
+        let uid = unsafe { libc::geteuid() };
+        let broker = Broker::parse(path.to_str().unwrap(), Some(&uid.to_string())).unwrap();
+        let value = unsafe { fetch(&broker, "MY_TOKEN", b"h1") }.unwrap();
+        assert_eq!(value.as_bytes(), b"brokered-value");
+        assert!(unsafe { fetch(&broker, "MY_TOKEN", b"h2") }.is_none());
+        let value = unsafe { mint(&broker, "MY_TOKEN") }.unwrap();
+        assert_eq!(value.as_bytes(), b"minted-value");
+        assert_eq!(server.join().unwrap().len(), 3);
+
+        // A socket run by another user is not sent the handle
+        let other = Broker::parse(path.to_str().unwrap(), Some(&(uid + 1).to_string())).unwrap();
+        assert!(unsafe { fetch(&other, "MY_TOKEN", b"h1") }.is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+//! Attribution of token reads to the calling shared object
+//!
+//! A token read by libcurl on behalf of a configured remote is expected; the
+//! same token read by the agent's own code may not be. With
+//! AWF_ONE_SHOT_CALLER_POLICY set, every read of a protected token is
+//! attributed to the shared object (or executable) that called getenv, found
+//! by walking the stack past this library and looking the return address up
+//! with dladdr. The policy is a comma-separated list of `OBJECT:POLICY`
+//! entries (allow, log or deny, as for credential files) plus an optional
+//! `*:POLICY` default; unlisted callers are allowed.
+//!
+//! Unwinding and dladdr take the dynamic linker's lock, which another thread
+//! may hold while running constructors that call getenv. Callers are therefore
+//! identified without holding the state lock.
+
+use crate::credfile::FilePolicy;
+use libc::c_void;
+use std::ffi::CStr;
+
+/// Maximum stack depth searched for the first frame outside this library
+const MAX_FRAMES: usize = 16;
+
+/// Per-caller policies (AWF_ONE_SHOT_CALLER_POLICY)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct CallerPolicy {
+    rules: Vec<(String, FilePolicy)>,
+    default: FilePolicy,
+}
+
+impl CallerPolicy {
+    /// Parse `OBJECT:POLICY` entries; entries with an unknown policy are ignored
+    pub(crate) fn parse(config: &str) -> Self {
+        let mut policy = CallerPolicy {
+            rules: Vec::new(),
+            default: FilePolicy::Allow,
+        };
+        let entries = config
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty());
+        for entry in entries {
+            let Some((object, rule)) = entry.rsplit_once(':') else {
+                continue;
+            };
+            let (object, Some(rule)) = (object.trim(), FilePolicy::parse(rule.trim())) else {
+                continue;
+            };
+            match object {
+                "" => {}
+                "*" => policy.default = rule,
+                object => policy.rules.push((object.to_string(), rule)),
+            }
+        }
+        policy
+    }
+
+    /// Policy for a caller as reported by identify
+    ///
+    /// An entry matches the object's file name exactly, or the name without
+    /// its `.so` suffix and version (`libcurl` matches `libcurl.so.4`).
+    pub(crate) fn for_caller(&self, caller: &str) -> FilePolicy {
+        self.rules
+            .iter()
+            .find(|(object, _)| {
+                caller == object
+                    || caller
+                        .strip_prefix(object.as_str())
+                        .is_some_and(|rest| rest.starts_with(".so"))
+            })
+            .map(|(_, rule)| *rule)
+            .unwrap_or(self.default)
+    }
+}
+
+/// Look up the object containing `addr`
+///
+/// Returns the object's base address and file name.
+fn object_of(addr: *const c_void) -> Option<(*mut c_void, String)> {
+    // SAFETY: Dl_info is plain data; dladdr fills it in for any address inside
+    // a loaded object
+    let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+    if unsafe { libc::dladdr(addr, &mut info) } == 0 {
+        return None;
+    }
+    let path = if info.dli_fname.is_null() {
+        String::new()
+    } else {
+        // SAFETY: dli_fname is a valid C string owned by the dynamic linker
+        unsafe { CStr::from_ptr(info.dli_fname) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    Some((info.dli_fbase, path))
+}
+
+/// File name of a shared object path; the main executable may be reported
+/// with an empty path
+fn object_name(path: &str) -> String {
+    match path.rsplit('/').next() {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => std::env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.file_name()?.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "unknown".to_string()),
+    }
+}
+
+/// Identify the object that called into this library
+///
+/// Must not be called with the state lock held (see the module docs).
+pub(crate) fn identify() -> String {
+    let Some((own_base, _)) = object_of(identify as *const c_void) else {
+        return "unknown".to_string();
+    };
+    let mut frames = [std::ptr::null_mut::<c_void>(); MAX_FRAMES];
+    // SAFETY: frames holds MAX_FRAMES entries
+    let depth = unsafe { libc::backtrace(frames.as_mut_ptr(), MAX_FRAMES as libc::c_int) };
+    frames[..depth.max(0) as usize]
+        .iter()
+        .filter_map(|&frame| object_of(frame))
+        .find(|(base, _)| *base != own_base)
+        .map(|(_, path)| object_name(&path))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_caller_policy() {
+        let policy =
+            CallerPolicy::parse("libcurl:allow, node:log, libgit2.so.1.7:deny, *:deny, x:bogus");
+        assert_eq!(policy.for_caller("libcurl.so.4"), FilePolicy::Allow);
+        assert_eq!(policy.for_caller("libcurl-gnutls.so.4"), FilePolicy::Deny);
+        assert_eq!(policy.for_caller("node"), FilePolicy::Log);
+        assert_eq!(policy.for_caller("libgit2.so.1.7"), FilePolicy::Deny);
+        assert_eq!(policy.for_caller("python3"), FilePolicy::Deny);
+
+        let policy = CallerPolicy::parse("python3:deny");
+        assert_eq!(policy.for_caller("libcurl.so.4"), FilePolicy::Allow);
+    }
+}
@@ -0,0 +1,126 @@
+//! Canary (decoy) variables
+//!
+//! The host can plant decoy credentials with AWF_ONE_SHOT_CANARIES, a
+//! comma-separated list of `NAME=VALUE` pairs. A constructor adds each one to
+//! the environment unless the variable is already set, then removes
+//! AWF_ONE_SHOT_CANARIES itself. Nothing legitimate
+//! uses them, so reading a canary through getenv, or writing its value to a
+//! socket (see the egress module), is a strong sign that the agent is
+//! following injected instructions; both emit an audit event with
+//! `severity=high`. Canaries are reported but never blocked, so the agent
+//! cannot tell them apart from real credentials.
+//!
+//! Like the credential file policies, the canary table lives outside the
+//! state lock so the write path can consult it.
+
+use crate::detect::find_subslice;
+use crate::setenv::{call_real_setenv, call_real_unsetenv};
+use crate::{audit, lock_state, protection_disabled, read_config_var};
+use once_cell::sync::Lazy;
+use std::ffi::CString;
+
+/// Canary names and values, loaded on first use
+static CANARIES: Lazy<Vec<(String, String)>> = Lazy::new(|| {
+    if protection_disabled() {
+        return Vec::new();
+    }
+    read_config_var(c"AWF_ONE_SHOT_CANARIES")
+        .map(|config| parse_canaries(&config))
+        .unwrap_or_default()
+});
+
+/// Parse AWF_ONE_SHOT_CANARIES into (name, value) pairs
+///
+/// Whitespace around names and values is trimmed; entries without a name or
+/// value are skipped.
+pub(crate) fn parse_canaries(config: &str) -> Vec<(String, String)> {
+    config
+        .split(',')
+        .filter_map(|entry| {
+            let (name, value) = entry.split_once('=')?;
+            let (name, value) = (name.trim(), value.trim());
+            if name.is_empty() || value.is_empty() {
+                return None;
+            }
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Plant the canaries at load and hide their configuration
+///
+/// AWF_ONE_SHOT_CANARIES would tell the agent which variables are decoys, so
+/// it is unset once the state (and with it the configuration propagated to
+/// children) has been loaded.
+extern "C" fn inject_canaries_at_load() {
+    if CANARIES.is_empty() {
+        return;
+    }
+    drop(lock_state());
+
+    for (name, value) in CANARIES.iter() {
+        let (Ok(name), Ok(value)) = (CString::new(name.as_str()), CString::new(value.as_str()))
+        else {
+            continue;
+        };
+        // SAFETY: both are valid C strings; an existing value is kept
+        unsafe { call_real_setenv(name.as_ptr(), value.as_ptr(), 0) };
+    }
+    // SAFETY: valid C string
+    unsafe { call_real_unsetenv(c"AWF_ONE_SHOT_CANARIES".as_ptr()) };
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static INJECT_CANARIES_AT_LOAD: extern "C" fn() = inject_canaries_at_load;
+
+/// Report a getenv of a canary name
+pub(crate) fn check_read(name: &[u8], via: &str) {
+    if let Some((canary, _)) = CANARIES
+        .iter()
+        .find(|(canary, _)| canary.as_bytes() == name)
+    {
+        audit::emit("canary_read", canary, &format!("severity=high via={}", via));
+    }
+}
+
+/// Names of the configured canaries
+pub(crate) fn names() -> impl Iterator<Item = &'static str> {
+    CANARIES.iter().map(|(name, _)| name.as_str())
+}
+
+/// Name of the canary whose value appears in `buf`, if any
+pub(crate) fn find_in(buf: &[u8]) -> Option<&'static str> {
+    CANARIES
+        .iter()
+        .find(|(_, value)| find_subslice(buf, value.as_bytes()).is_some())
+        .map(|(name, _)| name.as_str())
+}
+
+/// Whether any canaries are configured
+pub(crate) fn configured() -> bool {
+    !CANARIES.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_canaries() {
+        assert_eq!(
+            parse_canaries("FAKE_GITHUB_TOKEN=canary-xyz, AWS_KEY = AKIA0000 ,=x,BAD,EMPTY="),
+            vec![
+                ("FAKE_GITHUB_TOKEN".to_string(), "canary-xyz".to_string()),
+                ("AWS_KEY".to_string(), "AKIA0000".to_string()),
+            ]
+        );
+    }
+}
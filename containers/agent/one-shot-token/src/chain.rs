@@ -0,0 +1,149 @@
+//! Hash chain over audit records (AWF_ONE_SHOT_AUDIT_CHAIN)
+//!
+//! The agent can write to its own log. With AWF_ONE_SHOT_AUDIT_CHAIN set,
+//! every audit record carries `pid`, `seq` and `hash` fields, where `hash` is
+//! the SHA-256 of the previous record's hash and this record, so the host
+//! verifier detects a record that was deleted, reordered or rewritten.
+//!
+//! Each process keeps its own chain (a forked child starts a new one): `seq`
+//! counts from 1, and the first record's previous hash is 64 zeros. The hash
+//! input is the previous hash in lowercase hex, a newline, then one line per
+//! field of the record:
+//!
+//! ```text
+//! <key>=<length of value in bytes>:<value>\n
+//! ```
+//!
+//! for `pid`, `seq`, `event` and `token`, then the details in order (keys as
+//! in the JSON format, including `tid`, `thread` and `mono`), then the run
+//! context (`step`, `container`, `cgroup`). Records of a process are ordered
+//! by `seq`, not by their position in the file, since threads race to write.
+//!
+//! The chain is not keyed: a process rewriting its whole log could recompute
+//! it. The host anchors it with a copy it holds itself, such as the event
+//! socket datagrams (which carry the same fields) or a forwarded journal.
+
+use crate::sha256::sha256;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Whether audit records are chained
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Last record of this process's chain
+struct Chain {
+    seq: u64,
+    hash: [u8; 32],
+}
+
+static CHAIN: Mutex<Chain> = Mutex::new(Chain {
+    seq: 0,
+    hash: [0; 32],
+});
+
+/// Chain audit records from now on
+pub(crate) fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Link a record into the chain, returning its `seq` and `hash`, or None if
+/// chaining is off
+///
+/// `fields` are the details followed by the run context.
+pub(crate) fn link(event: &str, token: &str, fields: &[(String, String)]) -> Option<(u64, String)> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let mut chain = CHAIN.lock().unwrap_or_else(|err| err.into_inner());
+    let seq = chain.seq + 1;
+    let hash = record_hash(&chain.hash, std::process::id(), seq, event, token, fields);
+    chain.seq = seq;
+    chain.hash = hash;
+    Some((seq, hex(&hash)))
+}
+
+/// Start a new chain in a forked child
+pub(crate) fn reset_after_fork() {
+    let mut chain = CHAIN.lock().unwrap_or_else(|err| err.into_inner());
+    chain.seq = 0;
+    chain.hash = [0; 32];
+}
+
+/// Hash of a record chained to `prev`
+fn record_hash(
+    prev: &[u8; 32],
+    pid: u32,
+    seq: u64,
+    event: &str,
+    token: &str,
+    fields: &[(String, String)],
+) -> [u8; 32] {
+    let mut input = hex(prev);
+    input.push('\n');
+    input.push_str(&record_input(pid, seq, event, token, fields));
+    sha256(input.as_bytes())
+}
+
+/// One `<key>=<length of value in bytes>:<value>` line per field of a record:
+/// `pid`, `seq`, `event`, `token`, then `fields`
+///
+/// Also the input of audit signatures (see the sign module).
+pub(crate) fn record_input(
+    pid: u32,
+    seq: u64,
+    event: &str,
+    token: &str,
+    fields: &[(String, String)],
+) -> String {
+    let mut input = String::new();
+    let head = [
+        ("pid", pid.to_string()),
+        ("seq", seq.to_string()),
+        ("event", event.to_string()),
+        ("token", token.to_string()),
+    ];
+    let head = head.iter().map(|(key, value)| (*key, value.as_str()));
+    let rest = fields
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()));
+    for (key, value) in head.chain(rest) {
+        input.push_str(&format!("{}={}:{}\n", key, value.len(), value));
+    }
+    input
+}
+
+/// Lowercase hex encoding
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_hash() {
+        let fields = vec![
+            ("action".to_string(), "blocked".to_string()),
+            ("thread".to_string(), "pool 1".to_string()),
+        ];
+        let first = record_hash(&[0; 32], 42, 1, "denied", "GITHUB_TOKEN", &fields);
+        let input = format!(
+            "{}\npid=2:42\nseq=1:1\nevent=6:denied\ntoken=12:GITHUB_TOKEN\n\
+             action=7:blocked\nthread=6:pool 1\n",
+            "0".repeat(64)
+        );
+        assert_eq!(first, sha256(input.as_bytes()));
+
+        // The second record depends on the first
+        let second = record_hash(&first, 42, 2, "denied", "GITHUB_TOKEN", &fields);
+        let input = input
+            .replacen(&"0".repeat(64), &hex(&first), 1)
+            .replace("seq=1:1", "seq=1:2");
+        assert_eq!(second, sha256(input.as_bytes()));
+        assert_ne!(
+            second,
+            record_hash(&[0; 32], 42, 2, "denied", "GITHUB_TOKEN", &fields)
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! Configuration file (AWF_ONE_SHOT_CONFIG), reloaded when it changes
+//!
+//! Operators can tighten the token policy of a running agent, e.g. when the
+//! firewall sees suspicious traffic, without restarting it. The file named by
+//! AWF_ONE_SHOT_CONFIG holds `NAME=VALUE` lines for the token list settings
+//! AWF_ONE_SHOT_TOKENS, AWF_ONE_SHOT_EXTRA_TOKENS and AWF_ONE_SHOT_DENY_TOKENS;
+//! blank lines and lines starting with `#` are skipped. A setting in the file
+//! takes precedence over the variable of the same name.
+//!
+//! On Linux a background thread watches the directory of the file with
+//! inotify (configuration management replaces files by renaming them into
+//! place) and rebuilds the token and deny lists when the settings changed,
+//! emitting a `config_reload` audit event. Elsewhere the file is only read at
+//! startup. A file that cannot be read keeps the current lists, so deleting
+//! it never loosens the policy.
+//!
+//! Lines that are not one of the settings are skipped and reported: at load
+//! by the configuration check (see the healthcheck module), on reload with a
+//! warning.
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+#[cfg(target_os = "linux")]
+use crate::{audit, lock_state, reload_token_list, sys};
+use std::ffi::{CStr, CString};
+
+/// Settings the file may hold
+const SETTINGS: [&str; 3] = [
+    "AWF_ONE_SHOT_TOKENS",
+    "AWF_ONE_SHOT_EXTRA_TOKENS",
+    "AWF_ONE_SHOT_DENY_TOKENS",
+];
+
+/// Settings read from the file, and the names of the lines skipped
+type Parsed = (Vec<(&'static str, Vec<u8>)>, Vec<String>);
+
+/// The configuration file and the settings last read from it
+pub(crate) struct ConfigFile {
+    path: CString,
+    settings: Vec<(&'static str, Vec<u8>)>,
+    /// Names of the lines that are not settings, or the lines themselves
+    unknown: Vec<String>,
+    /// Whether the last read succeeded, so that a file that went missing is
+    /// reported once rather than on every change in its directory
+    readable: bool,
+}
+
+impl ConfigFile {
+    /// Read the file at `path`; None if the path is empty
+    ///
+    /// A file that cannot be read yet holds no settings until it is written.
+    pub(crate) fn load(path: &str) -> Option<Self> {
+        let path = CString::new(path.trim())
+            .ok()
+            .filter(|path| !path.is_empty())?;
+        let parsed = read(&path);
+        let readable = parsed.is_some();
+        let (settings, unknown) = parsed.unwrap_or_default();
+        Some(ConfigFile {
+            path,
+            settings,
+            unknown,
+            readable,
+        })
+    }
+
+    /// The path of the file, for messages
+    pub(crate) fn path(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+
+    /// The lines of the file that are not settings, when it was last read
+    pub(crate) fn unknown(&self) -> &[String] {
+        &self.unknown
+    }
+
+    /// Whether the file could be read when it was last loaded
+    pub(crate) fn readable(&self) -> bool {
+        self.readable
+    }
+
+    /// The value the file gives `name`, if any
+    pub(crate) fn get(&self, name: &CStr) -> Option<&[u8]> {
+        self.settings
+            .iter()
+            .find(|(setting, _)| setting.as_bytes() == name.to_bytes())
+            .map(|(_, value)| value.as_slice())
+    }
+}
+
+/// Read and parse the file at `path`
+fn read(path: &CStr) -> Option<Parsed> {
+    // SAFETY: path is a valid C string
+    let content = unsafe { crate::procfs::read_file(path) }?;
+    Some(parse(&content))
+}
+
+/// Parse `NAME=VALUE` lines into the known settings and the unknown names
+///
+/// Later lines override earlier ones.
+fn parse(content: &[u8]) -> Parsed {
+    let mut settings: Vec<(&'static str, Vec<u8>)> = Vec::new();
+    let mut unknown = Vec::new();
+    for line in content.split(|&byte| byte == b'\n') {
+        let line = line.trim_ascii();
+        if line.is_empty() || line.starts_with(b"#") {
+            continue;
+        }
+        let Some(eq) = line.iter().position(|&byte| byte == b'=') else {
+            unknown.push(String::from_utf8_lossy(line).into_owned());
+            continue;
+        };
+        let (name, value) = (line[..eq].trim_ascii(), line[eq + 1..].trim_ascii());
+        let Some(setting) = SETTINGS.iter().find(|setting| setting.as_bytes() == name) else {
+            unknown.push(String::from_utf8_lossy(name).into_owned());
+            continue;
+        };
+        settings.retain(|(listed, _)| listed != setting);
+        settings.push((setting, value.to_vec()));
+    }
+    (settings, unknown)
+}
+
+/// Start watching the configuration file at load
+#[cfg(target_os = "linux")]
+extern "C" fn start_config_watch_at_load() {
+    start();
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", target_os = "linux"),
+    link_section = ".init_array"
+)]
+#[cfg(target_os = "linux")]
+static START_CONFIG_WATCH_AT_LOAD: extern "C" fn() = start_config_watch_at_load;
+
+/// Start the thread watching AWF_ONE_SHOT_CONFIG, if it is configured
+///
+/// Called at load, and again in forked children, which do not inherit the
+/// parent's threads.
+#[cfg(target_os = "linux")]
+pub(crate) fn start() {
+    let Some(path) = lock_state()
+        .config
+        .as_ref()
+        .map(|config| config.path.clone())
+    else {
+        return;
+    };
+
+    let spawned = std::thread::Builder::new()
+        .name("awf-config".to_string())
+        .spawn(move || watch(&path));
+
+    if let Err(err) = spawned {
+        log_line!(
+            Warning,
+            "config",
+            None,
+            "Could not start configuration watch thread: {}",
+            err
+        );
+    }
+}
+
+/// Reload the file whenever its directory changes
+#[cfg(target_os = "linux")]
+fn watch(path: &CStr) {
+    let bytes = path.to_bytes();
+    let dir = match bytes.iter().rposition(|&byte| byte == b'/') {
+        Some(0) => c"/".to_owned(),
+        Some(slash) => CString::new(&bytes[..slash]).unwrap_or_default(),
+        None => c".".to_owned(),
+    };
+
+    // SAFETY: plain system calls on a descriptor this thread owns
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    let mask = libc::IN_CLOSE_WRITE
+        | libc::IN_MOVED_TO
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_ATTRIB;
+    if fd < 0 || unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), mask) } < 0 {
+        log_line!(
+            Warning,
+            "config",
+            None,
+            "Could not watch {}: {}",
+            dir.to_string_lossy(),
+            std::io::Error::last_os_error()
+        );
+        if fd >= 0 {
+            // SAFETY: fd is open and owned by this thread
+            unsafe { libc::close(fd) };
+        }
+        return;
+    }
+
+    let mut events = [0u8; 4096];
+    loop {
+        // SAFETY: events is a writable buffer of the given length
+        let n = unsafe { libc::read(fd, events.as_mut_ptr().cast(), events.len()) };
+        if n < 0 {
+            // SAFETY: errno is thread-local
+            if unsafe { *sys::errno_location() } == libc::EINTR {
+                continue;
+            }
+            return;
+        }
+        // Events name any file in the directory: comparing the settings
+        // covers renames of the file and of symlinks it goes through
+        reload(path);
+    }
+}
+
+/// Apply the settings of the file if they changed
+#[cfg(target_os = "linux")]
+fn reload(path: &CStr) {
+    let mut state = lock_state();
+    let parsed = read(path);
+    let Some(config) = state.config.as_mut() else {
+        return;
+    };
+    let was_readable = std::mem::replace(&mut config.readable, parsed.is_some());
+    let Some((settings, unknown)) = parsed else {
+        drop(state);
+        if !was_readable {
+            return;
+        }
+        audit::emit(
+            "config_reload",
+            "*",
+            &format!(
+                "action=unreadable path={}",
+                audit::detail_word(&path.to_string_lossy())
+            ),
+        );
+        return;
+    };
+    if unknown != config.unknown && !unknown.is_empty() {
+        log_line!(
+            Warning,
+            "config",
+            None,
+            "AWF_ONE_SHOT_CONFIG: ignoring unknown setting(s) {} in {}",
+            unknown.join(","),
+            path.to_string_lossy()
+        );
+    }
+    config.unknown = unknown;
+    if config.settings == settings {
+        return;
+    }
+    config.settings = settings;
+
+    let (added, removed) = reload_token_list(&mut state);
+    let mut detail = format!(
+        "action=applied path={} tokens={} deny={}",
+        audit::detail_word(&path.to_string_lossy()),
+        state.tokens.len(),
+        state.deny.len()
+    );
+    let join = |names: Vec<crate::envname::EnvName>| {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        audit::detail_word(&names.join(","))
+    };
+    if !added.is_empty() {
+        detail.push_str(&format!(" added={}", join(added)));
+    }
+    if !removed.is_empty() {
+        detail.push_str(&format!(" removed={}", join(removed)));
+    }
+    drop(state);
+    audit::emit("config_reload", "*", &detail);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let (settings, unknown) = parse(
+            b"# tightened after an alert\n\
+              AWF_ONE_SHOT_TOKENS = GITHUB_TOKEN:max_reads=1\n\
+              \n\
+              AWF_ONE_SHOT_DENY_TOKENS=OPENAI_API_KEY\n\
+              AWF_ONE_SHOT_MODE=observe\n\
+              not a setting\n\
+              AWF_ONE_SHOT_DENY_TOKENS=OPENAI_API_KEY,NPM_TOKEN\n",
+        );
+        assert_eq!(
+            settings,
+            vec![
+                ("AWF_ONE_SHOT_TOKENS", b"GITHUB_TOKEN:max_reads=1".to_vec()),
+                (
+                    "AWF_ONE_SHOT_DENY_TOKENS",
+                    b"OPENAI_API_KEY,NPM_TOKEN".to_vec()
+                ),
+            ]
+        );
+        assert_eq!(unknown, vec!["AWF_ONE_SHOT_MODE", "not a setting"]);
+    }
+
+    #[test]
+    fn test_load_and_get() {
+        let path = std::env::temp_dir().join(format!("awf-config-{}", std::process::id()));
+        std::fs::write(&path, "AWF_ONE_SHOT_EXTRA_TOKENS=-GH_TOKEN\n").unwrap();
+        let config = ConfigFile::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            config.get(c"AWF_ONE_SHOT_EXTRA_TOKENS"),
+            Some(&b"-GH_TOKEN"[..])
+        );
+        assert_eq!(config.get(c"AWF_ONE_SHOT_TOKENS"), None);
+        std::fs::remove_file(&path).unwrap();
+
+        let missing = ConfigFile::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(missing.get(c"AWF_ONE_SHOT_EXTRA_TOKENS"), None);
+        assert!(!missing.readable());
+        assert!(ConfigFile::load("").is_none());
+    }
+}
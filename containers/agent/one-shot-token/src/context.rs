@@ -0,0 +1,180 @@
+//! Run context attached to every event: workflow step, container and cgroup
+//!
+//! A workflow run interleaves the logs of several steps and containers. To
+//! reconstruct which one an event came from, structured lines (JSON, event
+//! datagrams, journald fields, firewall lines) and audit lines carry:
+//!
+//! - `step`: the value of AWF_STEP_ID, set by the host for each step
+//! - `container`: the container ID, found in the cgroup path or, with a
+//!   private cgroup namespace, in the mount table
+//! - `cgroup`: the process's cgroup path (the unified hierarchy if set up,
+//!   else the first controller with a non-root path)
+//!
+//! Each is left out when unknown. The context is read when the state is
+//! initialized, through the real open (see the procfs module), before a
+//! Landlock ruleset can deny access to /proc. Minimal builds, which have no
+//! Landlock support, read it when the first event is written instead: most
+//! processes never write one, and reading /proc at load is the bulk of the
+//! time the library adds to process startup.
+
+use crate::procfs;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::OnceLock;
+
+static FIELDS: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+/// Fields with the step replaced after a step change (see the step module),
+/// leaked so that `fields` keeps handing out a static slice: steps change a
+/// handful of times per process at most
+static CHANGED: AtomicPtr<Vec<(String, String)>> = AtomicPtr::new(ptr::null_mut());
+
+/// AWF_STEP_ID as passed to `init`, until the context is read
+#[cfg(feature = "minimal")]
+static STEP: OnceLock<Option<String>> = OnceLock::new();
+
+/// Read the context, with `step` from AWF_STEP_ID
+#[cfg(not(feature = "minimal"))]
+pub(crate) fn init(step: Option<String>) {
+    FIELDS.get_or_init(|| read(step));
+}
+
+/// Keep `step` from AWF_STEP_ID for when the context is first used
+#[cfg(feature = "minimal")]
+pub(crate) fn init(step: Option<String>) {
+    let _ = STEP.set(step);
+}
+
+/// Context fields, in the order step, container, cgroup
+pub(crate) fn fields() -> &'static [(String, String)] {
+    let changed = CHANGED.load(Ordering::Acquire);
+    if !changed.is_null() {
+        // SAFETY: CHANGED only ever holds leaked, never freed vectors
+        return unsafe { (*changed).as_slice() };
+    }
+    #[cfg(feature = "minimal")]
+    if let Some(step) = STEP.get() {
+        return FIELDS.get_or_init(|| read(step.clone()));
+    }
+    FIELDS.get().map_or(&[], Vec::as_slice)
+}
+
+/// Report `step` as the step from now on
+pub(crate) fn set_step(step: Option<&str>) {
+    let mut fields: Vec<(String, String)> = fields()
+        .iter()
+        .filter(|(key, _)| key != "step")
+        .cloned()
+        .collect();
+    if let Some(step) = step {
+        fields.insert(0, ("step".to_string(), step.to_string()));
+    }
+    CHANGED.store(Box::into_raw(Box::new(fields)), Ordering::Release);
+}
+
+/// Read the cgroup and container of this process
+fn read(step: Option<String>) -> Vec<(String, String)> {
+    // SAFETY: the paths are valid C strings
+    let cgroup_file = unsafe { procfs::read_file(c"/proc/self/cgroup") };
+    let cgroup = cgroup_file.and_then(|content| cgroup_path(&String::from_utf8_lossy(&content)));
+    let container = cgroup.as_deref().and_then(container_id).or_else(|| {
+        // SAFETY: as above
+        let mountinfo = unsafe { procfs::read_file(c"/proc/self/mountinfo") }?;
+        mount_container_id(&String::from_utf8_lossy(&mountinfo))
+    });
+    let step = step
+        .map(|step| step.trim().to_string())
+        .filter(|step| !step.is_empty());
+    [("step", step), ("container", container), ("cgroup", cgroup)]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value?)))
+        .collect()
+}
+
+/// Cgroup path from the contents of /proc/self/cgroup, or None at the root
+fn cgroup_path(content: &str) -> Option<String> {
+    let paths: Vec<(&str, &str)> = content
+        .lines()
+        .filter_map(|line| {
+            let (_, rest) = line.split_once(':')?;
+            rest.split_once(':')
+        })
+        .filter(|(_, path)| *path != "/")
+        .collect();
+    paths
+        .iter()
+        .find(|(controllers, _)| controllers.is_empty())
+        .or_else(|| paths.first())
+        .map(|(_, path)| path.to_string())
+}
+
+/// Container ID in a cgroup path or path component: 64 hex digits, possibly
+/// wrapped as in `docker-<id>.scope`
+fn container_id(path: &str) -> Option<String> {
+    path.split('/').find_map(|component| {
+        let id = component.rsplit(['-', ':']).next()?;
+        let id = id.strip_suffix(".scope").unwrap_or(id);
+        (id.len() == 64 && id.bytes().all(|byte| byte.is_ascii_hexdigit())).then(|| id.to_string())
+    })
+}
+
+/// Container ID from the mount table: the runtime bind-mounts files such as
+/// /etc/hostname from `.../containers/<id>/`
+fn mount_container_id(mountinfo: &str) -> Option<String> {
+    mountinfo
+        .split("/containers/")
+        .skip(1)
+        .find_map(|rest| container_id(rest.split(['/', ' ']).next()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "3f5c6ac3e91c9a8f0e2d4b6a8c0e2f4a6b8d0c2e4f6a8b0d2c4e6f8a0b2d4c6e";
+
+    #[test]
+    fn test_cgroup_path() {
+        assert_eq!(
+            cgroup_path("0::/system.slice/docker-abc.scope\n"),
+            Some("/system.slice/docker-abc.scope".to_string())
+        );
+        assert_eq!(
+            cgroup_path("2:cpuacct:/\n1:memory:/docker/abc\n0::/\n"),
+            Some("/docker/abc".to_string())
+        );
+        assert_eq!(cgroup_path("0::/\n"), None);
+    }
+
+    #[test]
+    fn test_container_id() {
+        let scope = format!("/system.slice/docker-{}.scope", ID);
+        assert_eq!(container_id(&scope), Some(ID.to_string()));
+        assert_eq!(
+            container_id(&format!("/docker/{}", ID)),
+            Some(ID.to_string())
+        );
+        assert_eq!(
+            container_id(&format!("/kubepods/pod1/cri-containerd:{}", ID)),
+            Some(ID.to_string())
+        );
+        assert_eq!(container_id("/user.slice/user-1000.slice"), None);
+    }
+
+    #[test]
+    fn test_mount_container_id() {
+        let mountinfo = format!(
+            concat!(
+                "612 590 0:52 / / rw - overlay overlay rw,upperdir=/var/lib/docker/overlay2/{}/diff\n",
+                "630 612 254:1 /var/lib/docker/containers/{}/hostname /etc/hostname rw - ext4 /dev/vda1 rw\n"
+            ),
+            ID.replace('3', "4"),
+            ID
+        );
+        assert_eq!(mount_container_id(&mountinfo), Some(ID.to_string()));
+        assert_eq!(
+            mount_container_id("612 590 0:52 / / rw - overlay overlay rw\n"),
+            None
+        );
+    }
+}
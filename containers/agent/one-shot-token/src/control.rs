@@ -0,0 +1,496 @@
+//! C control API for the agent
+//!
+//! The library is passive by default. These exported functions, looked up
+//! with dlsym(RTLD_DEFAULT, ...), let the agent act on the cache:
+//!
+//! - `int awf_token_wipe(const char *name)` zeroizes a protected token (or all
+//!   of them when `name` is NULL), e.g. before running an untrusted tool call.
+//!   Tokens not read yet are scrubbed from the environment first, so every
+//!   later read returns NULL. Returns the number of values wiped.
+//! - `int awf_token_lock(const char *name)` refuses every later read of a
+//!   token without discarding the value. Returns 0 (-1 with EINVAL for NULL).
+//! - `ssize_t awf_token_stats(char *buf, size_t len)` writes the access
+//!   summary also used for the exit report as JSON, NUL-terminated and
+//!   truncated to `len` like snprintf. Returns the full length.
+//! - `ssize_t awf_token_list_protected(char *buf, size_t len)` writes the
+//!   protection status the same way, for the agent CLI or the host's
+//!   preflight to show workflow authors: every configured token with its
+//!   policy, where the rule came from (see the merge module), its aliases,
+//!   its status and read count, and the deny list.
+//! - `int awf_token_protect(const char *rule)` adds a variable to the
+//!   protected set mid-run, e.g. once a tool call has minted a credential:
+//!   `rule` is a name with the options of AWF_ONE_SHOT_TOKENS. The rule is
+//!   kept across configuration reloads and passed on to exec'd children in
+//!   AWF_ONE_SHOT_EXTRA_TOKENS. Returns 0, also for a name already protected
+//!   (whose rule is left as it is); -1 with EINVAL for a malformed rule,
+//!   ENOSPC beyond MAX_TOKENS and ENOTSUP while protection is disabled.
+//! - `int awf_token_unprotect(const char *name)` takes a token added with
+//!   awf_token_protect out of the set again. Returns 0; -1 with EPERM for
+//!   tokens the configuration protects, and EBUSY once the value has been
+//!   cached, since it has left the environment by then (wipe it instead).
+//!
+//! Names may be aliases. The functions return -1 and set errno to ENOENT for
+//! names that are not protected tokens. In observe mode wipe and lock are
+//! only reported; protect and unprotect apply, as they only change which
+//! reads are reported.
+//!
+//! The functions are declared in include/one_shot_token.h and exported with
+//! the symbol version AWF_1.0 (awf_token_list_protected: AWF_1.2,
+//! awf_token_protect and awf_token_unprotect: AWF_1.3), so programs linked
+//! against them keep working across upgrades. Changing a signature requires a new version node in
+//! awf.map; the header test below catches declarations that drift.
+
+use crate::envname::EnvName;
+use crate::log::json_string;
+use crate::merge::{Origin, Source};
+use crate::policy::{self, TokenSpec};
+use crate::{
+    audit, cache_token, call_real_getenv, egress, environ, is_denied_token, load_exe, lock_state,
+    past_expiry, protection_disabled, publish_watched_names, report, resolve_sensitive_token,
+    snapshot, sys, ttl_elapsed, CachedToken, Mode, StateGuard, TokenState, MAX_TOKENS,
+};
+use libc::{c_char, c_int, size_t, ssize_t};
+use std::ffi::CStr;
+use std::ptr;
+
+/// Resolve `name` to the canonical tokens it covers (all for NULL)
+///
+/// Sets errno to ENOENT if the name is not a protected token.
+///
+/// # Safety
+/// `name` must be null or a valid null-terminated C string
+unsafe fn target_tokens(state: &TokenState, name: *const c_char) -> Option<Vec<EnvName>> {
+    if name.is_null() {
+        return Some(state.tokens.iter().map(|spec| spec.name.clone()).collect());
+    }
+    let canonical = resolve_sensitive_token(state, CStr::from_ptr(name).to_bytes());
+    if canonical.is_none() {
+        *sys::errno_location() = libc::ENOENT;
+    }
+    canonical.map(|canonical| vec![canonical.clone()])
+}
+
+/// Cache (and so scrub) a token that has not been looked up yet
+fn ensure_cached(state: &mut TokenState, canonical: &EnvName) {
+    if !state.cache.contains_key(canonical) {
+        // SAFETY: call_real_getenv is the real getenv
+        unsafe { cache_token(state, canonical, call_real_getenv) };
+    }
+}
+
+/// Zeroize a protected token, or every protected token if `name` is NULL
+///
+/// # Safety
+/// `name` must be null or a valid null-terminated C string
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn awf_token_wipe(name: *const c_char) -> c_int {
+    let state = lock_state();
+    let Some(tokens) = target_tokens(&state, name) else {
+        return -1;
+    };
+    wipe_tokens(state, &tokens, "awf_token_wipe")
+}
+
+/// Zeroize every protected token, reporting `via` as the trigger
+#[cfg(not(feature = "minimal"))]
+pub(crate) fn wipe_all(via: &str) -> c_int {
+    let state = lock_state();
+    let tokens: Vec<EnvName> = state.tokens.iter().map(|spec| spec.name.clone()).collect();
+    wipe_tokens(state, &tokens, via)
+}
+
+/// Zeroize `tokens` and return the number of values wiped
+fn wipe_tokens(mut state: StateGuard, tokens: &[EnvName], via: &str) -> c_int {
+    if state.mode == Mode::Observe {
+        drop(state);
+        for token in tokens {
+            audit::emit("observed", token, &format!("would=wipe via={}", via));
+        }
+        return 0;
+    }
+
+    let mut wiped = 0;
+    for token in tokens {
+        ensure_cached(&mut state, token);
+        let Some(entry) = state.cache.get_mut(token) else {
+            continue;
+        };
+        if entry.value.is_null() {
+            continue;
+        }
+        entry.wipe("wiped");
+        wiped += 1;
+        audit::emit("wiped", token, &format!("via={}", via));
+    }
+    egress::publish_secrets(&state);
+    wiped
+}
+
+/// Refuse every later read of a protected token
+///
+/// # Safety
+/// `name` must be a valid null-terminated C string
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn awf_token_lock(name: *const c_char) -> c_int {
+    if name.is_null() {
+        *sys::errno_location() = libc::EINVAL;
+        return -1;
+    }
+    let mut state = lock_state();
+    let Some(token) = target_tokens(&state, name).and_then(|tokens| tokens.into_iter().next())
+    else {
+        return -1;
+    };
+    if state.mode == Mode::Observe {
+        drop(state);
+        audit::emit("observed", &token, "would=lock via=awf_token_lock");
+        return 0;
+    }
+
+    ensure_cached(&mut state, &token);
+    if let Some(entry) = state.cache.get_mut(&token) {
+        entry.locked = true;
+    }
+    audit::emit("locked", &token, "via=awf_token_lock");
+    0
+}
+
+/// Fail with `errno`
+fn fail(errno: c_int) -> c_int {
+    // SAFETY: errno_location points to this thread's errno
+    unsafe { *sys::errno_location() = errno };
+    -1
+}
+
+/// Protect a variable from now on, with the options of `rule`
+///
+/// # Safety
+/// `rule` must be null or a valid null-terminated C string
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn awf_token_protect(rule: *const c_char) -> c_int {
+    if rule.is_null() {
+        return fail(libc::EINVAL);
+    }
+    let rule = CStr::from_ptr(rule).to_bytes().trim_ascii();
+    let Some((spec, unknown)) = policy::parse_token_spec(rule) else {
+        return fail(libc::EINVAL);
+    };
+    if !unknown.is_empty() || spec.name.as_bytes().contains(&b'=') {
+        return fail(libc::EINVAL);
+    }
+    if protection_disabled() {
+        return fail(libc::ENOTSUP);
+    }
+    let mut state = lock_state();
+    if resolve_sensitive_token(&state, spec.name.as_bytes()).is_some() {
+        return 0;
+    }
+    if state.tokens.len() >= MAX_TOKENS {
+        return fail(libc::ENOSPC);
+    }
+    let name = spec.name.clone();
+    state.tokens.push(TokenSpec {
+        origin: Origin::API,
+        ..spec
+    });
+    load_exe(&mut state);
+    publish_watched_names(&state);
+    if let Some(propagation) = state.propagation.as_mut() {
+        propagation.set_runtime_token(&name, Some(&String::from_utf8_lossy(rule)));
+    }
+    audit::emit("protected", &name, "via=awf_token_protect");
+    0
+}
+
+/// Stop protecting a variable added with awf_token_protect
+///
+/// # Safety
+/// `name` must be a valid null-terminated C string
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn awf_token_unprotect(name: *const c_char) -> c_int {
+    if name.is_null() {
+        return fail(libc::EINVAL);
+    }
+    let mut state = lock_state();
+    let Some(token) = target_tokens(&state, name).and_then(|tokens| tokens.into_iter().next())
+    else {
+        return -1;
+    };
+    if state
+        .tokens
+        .iter()
+        .any(|spec| spec.name == token && spec.origin.source != Source::Api)
+    {
+        return fail(libc::EPERM);
+    }
+    let cached = state
+        .cache
+        .get(&token)
+        .is_some_and(|entry| !entry.value.is_null() || entry.wiped.is_some());
+    if cached || state.aliases.values().any(|canonical| *canonical == token) {
+        return fail(libc::EBUSY);
+    }
+    state.tokens.retain(|spec| spec.name != token);
+    state.cache.remove(&token);
+    publish_watched_names(&state);
+    // Snapshot entries refer to tokens by position
+    snapshot::reset();
+    if let Some(propagation) = state.propagation.as_mut() {
+        propagation.set_runtime_token(&token, None);
+    }
+    audit::emit("unprotected", &token, "via=awf_token_unprotect");
+    0
+}
+
+/// Write the access statistics as JSON into `buf`
+///
+/// Like snprintf, at most `len - 1` bytes are written followed by a NUL, and
+/// the length of the full summary is returned; `buf` may be NULL if `len` is 0.
+///
+/// # Safety
+/// `buf` must be valid for writes of `len` bytes
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn awf_token_stats(buf: *mut c_char, len: size_t) -> ssize_t {
+    let summary = report::summary(&lock_state());
+    copy_out(&summary, buf, len)
+}
+
+/// What a read of a protected token would get now
+///
+/// `set` is whether the token was set in the environment when it was looked
+/// up, or is set now if it was not looked up yet.
+fn token_status(
+    state: &TokenState,
+    spec: &TokenSpec,
+    entry: Option<&CachedToken>,
+    set: bool,
+) -> &'static str {
+    if is_denied_token(state, &spec.name) {
+        return "denied";
+    }
+    if !set {
+        return "unset";
+    }
+    let expired = ttl_elapsed(Some(spec)) || past_expiry(Some(spec)).is_some();
+    match entry {
+        _ if expired => "expired",
+        Some(entry) if entry.wiped == Some("expired") => "expired",
+        Some(entry) if !entry.handed_over.is_null() => "exhausted",
+        Some(entry) if entry.wiped.is_some() => "wiped",
+        Some(entry) if entry.locked => "locked",
+        Some(entry) if spec.max_reads.is_some_and(|max| entry.reads >= max) => "exhausted",
+        Some(_) => "cached",
+        None => "pending",
+    }
+}
+
+/// The protection status as a single JSON object
+fn protection_status(state: &TokenState) -> String {
+    let tokens: Vec<String> = state
+        .tokens
+        .iter()
+        .map(|spec| {
+            let entry = state.cache.get(&spec.name);
+            let set = match entry {
+                Some(entry) => !entry.value.is_null() || entry.wiped.is_some(),
+                // SAFETY: environ is only read; the state lock is held
+                None => unsafe { environ::find_entry(&spec.name) }.is_some(),
+            };
+            let mut aliases: Vec<String> = state
+                .aliases
+                .iter()
+                .filter(|(_, canonical)| **canonical == spec.name)
+                .map(|(alias, _)| json_string(&alias.lossy()))
+                .collect();
+            aliases.sort();
+            format!(
+                concat!(
+                    "{{\"name\":{},\"policy\":{},\"source\":{},\"setting\":{},",
+                    "\"aliases\":[{}],\"status\":{},\"reads\":{},\"max_reads\":{},",
+                    "\"wiped\":{}}}"
+                ),
+                json_string(&spec.name.lossy()),
+                json_string(spec.policy.as_str()),
+                json_string(spec.origin.source.as_str()),
+                json_string(&spec.origin.setting.to_string_lossy()),
+                aliases.join(","),
+                json_string(token_status(state, spec, entry, set)),
+                entry.map_or(0, |entry| entry.reads),
+                spec.max_reads
+                    .map_or_else(|| "null".to_string(), |max| max.to_string()),
+                entry
+                    .and_then(|entry| entry.wiped)
+                    .map_or_else(|| "null".to_string(), json_string)
+            )
+        })
+        .collect();
+    let denied: Vec<String> = state
+        .deny
+        .iter()
+        .map(|name| json_string(&name.lossy()))
+        .collect();
+    let disabled = protection_disabled();
+    format!(
+        "{{\"pid\":{},\"mode\":{},\"disabled\":{},\"tokens\":[{}],\"denied\":[{}]}}",
+        std::process::id(),
+        json_string(if disabled {
+            "disabled"
+        } else {
+            state.mode.as_str()
+        }),
+        disabled,
+        tokens.join(","),
+        denied.join(",")
+    )
+}
+
+/// Write the protection status as JSON into `buf`
+///
+/// Like snprintf, at most `len - 1` bytes are written followed by a NUL, and
+/// the length of the full status is returned; `buf` may be NULL if `len` is 0.
+///
+/// # Safety
+/// `buf` must be valid for writes of `len` bytes
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn awf_token_list_protected(buf: *mut c_char, len: size_t) -> ssize_t {
+    let status = protection_status(&lock_state());
+    copy_out(&status, buf, len)
+}
+
+/// Copy `text` into `buf` like snprintf and return its full length
+///
+/// # Safety
+/// `buf` must be valid for writes of `len` bytes
+pub(crate) unsafe fn copy_out(text: &str, buf: *mut c_char, len: size_t) -> ssize_t {
+    if !buf.is_null() && len > 0 {
+        let copied = text.len().min(len - 1);
+        ptr::copy_nonoverlapping(text.as_ptr(), buf.cast::<u8>(), copied);
+        *buf.add(copied) = 0;
+    }
+    text.len() as ssize_t
+}
+
+// Bind the control functions to the AWF_1.0 version defined in awf.map. The
+// interposers stay unversioned, since versioned definitions would no longer
+// satisfy the programs' references to the glibc versions.
+#[cfg(all(feature = "preload", not(target_os = "macos")))]
+std::arch::global_asm!(
+    ".symver awf_token_wipe, awf_token_wipe@@@AWF_1.0",
+    ".symver awf_token_lock, awf_token_lock@@@AWF_1.0",
+    ".symver awf_token_stats, awf_token_stats@@@AWF_1.0",
+    ".symver awf_token_list_protected, awf_token_list_protected@@@AWF_1.2",
+    ".symver awf_token_protect, awf_token_protect@@@AWF_1.3",
+    ".symver awf_token_unprotect, awf_token_unprotect@@@AWF_1.3",
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy;
+
+    #[test]
+    fn test_target_tokens() {
+        let mut state = TokenState::new();
+        for token in ["GITHUB_TOKEN", "OPENAI_API_KEY"] {
+            state
+                .tokens
+                .push(policy::parse_token_spec(token).unwrap().0);
+        }
+        state
+            .aliases
+            .insert("GH_TOKEN".into(), "GITHUB_TOKEN".into());
+
+        unsafe {
+            assert_eq!(
+                target_tokens(&state, ptr::null()),
+                Some(vec!["GITHUB_TOKEN".into(), "OPENAI_API_KEY".into()])
+            );
+            assert_eq!(
+                target_tokens(&state, c"GH_TOKEN".as_ptr()),
+                Some(vec!["GITHUB_TOKEN".into()])
+            );
+            assert_eq!(target_tokens(&state, c"HOME".as_ptr()), None);
+            assert_eq!(*sys::errno_location(), libc::ENOENT);
+        }
+    }
+
+    #[test]
+    fn test_protection_status() {
+        let mut state = TokenState::new();
+        for token in [
+            "STATUS_TEST_CACHED:max_reads=2",
+            "STATUS_TEST_SPENT:max_reads=1",
+            "STATUS_TEST_EXPIRED:expires_at=2020-01-01T00:00:00Z",
+            "STATUS_TEST_UNSET",
+            "STATUS_TEST_DENIED",
+        ] {
+            state
+                .tokens
+                .push(policy::parse_token_spec(token).unwrap().0);
+        }
+        state
+            .aliases
+            .insert("STATUS_TEST_ALIAS".into(), "STATUS_TEST_CACHED".into());
+        state.deny.push("STATUS_TEST_DENIED".into());
+        let mut value = *b"value\0";
+        for (name, reads) in [
+            ("STATUS_TEST_CACHED", 1),
+            ("STATUS_TEST_SPENT", 1),
+            ("STATUS_TEST_EXPIRED", 0),
+        ] {
+            let entry = CachedToken {
+                value: value.as_mut_ptr().cast(),
+                reads,
+                ..CachedToken::unset()
+            };
+            state.cache.insert(name.into(), entry);
+        }
+        state
+            .cache
+            .insert("STATUS_TEST_UNSET".into(), CachedToken::unset());
+
+        let status = protection_status(&state);
+        let statuses: Vec<&str> = status
+            .split("\"status\":\"")
+            .skip(1)
+            .map(|rest| rest.split('"').next().unwrap())
+            .collect();
+        assert_eq!(
+            statuses,
+            ["cached", "exhausted", "expired", "unset", "denied"]
+        );
+        assert!(
+            status.contains(concat!(
+                "{\"name\":\"STATUS_TEST_CACHED\",\"policy\":\"cache\",",
+                "\"source\":\"default\",\"setting\":\"AWF_ONE_SHOT_PRESETS\",",
+                "\"aliases\":[\"STATUS_TEST_ALIAS\"],\"status\":\"cached\",",
+                "\"reads\":1,\"max_reads\":2,\"wiped\":null}"
+            )),
+            "{}",
+            status
+        );
+        assert!(
+            status.ends_with("\"denied\":[\"STATUS_TEST_DENIED\"]}"),
+            "{}",
+            status
+        );
+    }
+
+    #[test]
+    fn test_header_declarations() {
+        let header = include_str!("../include/one_shot_token.h");
+        let map = include_str!("../awf.map");
+        for declaration in [
+            "int awf_token_wipe(const char *name);",
+            "int awf_token_lock(const char *name);",
+            "ssize_t awf_token_stats(char *buf, size_t len);",
+            "ssize_t awf_token_list_protected(char *buf, size_t len);",
+            "int awf_token_protect(const char *rule);",
+            "int awf_token_unprotect(const char *name);",
+        ] {
+            assert!(header.contains(declaration), "{}", declaration);
+            let name = declaration.split(['(', ' ']).nth(1).unwrap();
+            assert!(map.contains(&format!("{};", name)), "{}", name);
+        }
+    }
+}
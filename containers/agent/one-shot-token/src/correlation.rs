@@ -0,0 +1,153 @@
+//! Correlation IDs linking token reads to later egress
+//! (AWF_ONE_SHOT_CORRELATION)
+//!
+//! The firewall sees requests leave the container, the library sees tokens
+//! being read, but neither can tell which read led to which request. With
+//! AWF_ONE_SHOT_CORRELATION set, every served read of a protected token gets
+//! a random correlation ID, reported with a `token_read` audit event and
+//! added as `corr` to the access event. The value is a comma-separated list:
+//!
+//! - `on`: only the events above
+//! - `env`: also export the ID of the latest read as AWF_CORRELATION_ID, so
+//!   tools the agent runs afterwards (and its children) can pass it on, for
+//!   example as a request header the proxy logs
+//! - `connect`: also report every later connect() to an IP address with an
+//!   `egress_correlated` audit event carrying the latest ID and the local and
+//!   peer addresses, which the host matches against the proxy's connection
+//!   log (audit events also reach the event socket, see the events module;
+//!   awf-token-collector does the matching with `--proxy-log`)
+//!
+//! Reads served this way always take the state lock (see the snapshot module).
+
+use crate::envname::EnvName;
+use crate::setenv::call_real_setenv;
+use crate::{audit, sys};
+use std::ffi::{CStr, CString};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Variable the latest correlation ID is exported as
+const ENV_VAR: &CStr = c"AWF_CORRELATION_ID";
+
+/// Next fallback ID, for systems without an entropy source
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Correlation settings and the latest read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Correlation {
+    /// Whether the latest ID is exported as AWF_CORRELATION_ID
+    export_env: bool,
+    /// Whether connections are reported with the latest ID
+    report_connect: bool,
+    /// ID and token of the latest read
+    latest: Option<(String, EnvName)>,
+}
+
+impl Correlation {
+    /// Parse AWF_ONE_SHOT_CORRELATION; None if it is off
+    ///
+    /// Unknown entries are returned so they can be reported.
+    pub(crate) fn parse(value: &str) -> (Option<Self>, Vec<String>) {
+        let mut correlation = Correlation {
+            export_env: false,
+            report_connect: false,
+            latest: None,
+        };
+        let mut enabled = false;
+        let mut unknown = Vec::new();
+        for entry in value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            match entry.to_ascii_lowercase().as_str() {
+                "off" | "0" => {}
+                "on" | "1" => enabled = true,
+                "env" => {
+                    enabled = true;
+                    correlation.export_env = true;
+                }
+                "connect" => {
+                    enabled = true;
+                    correlation.report_connect = true;
+                }
+                _ => unknown.push(entry.to_string()),
+            }
+        }
+        (enabled.then_some(correlation), unknown)
+    }
+
+    /// Start a correlation for a served read of `token` and return its ID
+    pub(crate) fn read(&mut self, token: &EnvName, via: &str) -> String {
+        let id = new_id();
+        audit::emit("token_read", token, &format!("corr={} via={}", id, via));
+        if self.export_env {
+            if let Ok(value) = CString::new(id.as_str()) {
+                // SAFETY: both are valid C strings
+                unsafe { call_real_setenv(ENV_VAR.as_ptr(), value.as_ptr(), 1) };
+            }
+        }
+        self.latest = Some((id.clone(), token.clone()));
+        id
+    }
+
+    /// Whether connections are reported, once a token has been read
+    pub(crate) fn reports_connect(&self) -> bool {
+        self.report_connect && self.latest.is_some()
+    }
+
+    /// Report a connection from `local` to `peer` made after a read
+    pub(crate) fn connected(&self, local: Option<SocketAddr>, peer: SocketAddr) {
+        if !self.report_connect {
+            return;
+        }
+        let Some((id, token)) = &self.latest else {
+            return;
+        };
+        let local = local.map_or_else(|| "-".to_string(), |local| local.to_string());
+        audit::emit(
+            "egress_correlated",
+            token,
+            &format!("corr={} local={} peer={}", id, local, peer),
+        );
+    }
+}
+
+/// A new random correlation ID: 16 hex digits
+fn new_id() -> String {
+    let mut bytes = [0u8; 8];
+    if !sys::fill_random(&mut bytes) {
+        // No entropy source: unique within the process is enough to correlate
+        let next = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        bytes = (next ^ ((std::process::id() as u64) << 32)).to_be_bytes();
+    }
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Correlation::parse(""), (None, vec![]));
+        assert_eq!(Correlation::parse("off"), (None, vec![]));
+        let (on, unknown) = Correlation::parse("on");
+        assert!(unknown.is_empty());
+        let on = on.unwrap();
+        assert!(!on.export_env && !on.report_connect);
+
+        let (both, unknown) = Correlation::parse(" ENV , connect,header ");
+        let both = both.unwrap();
+        assert!(both.export_env && both.report_connect);
+        assert_eq!(unknown, vec!["header"]);
+    }
+
+    #[test]
+    fn test_new_id() {
+        let (first, second) = (new_id(), new_id());
+        assert_eq!(first.len(), 16);
+        assert!(first.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert_ne!(first, second);
+    }
+}
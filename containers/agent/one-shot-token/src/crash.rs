@@ -0,0 +1,113 @@
+//! Zeroization of cached values when the process crashes
+//! (AWF_ONE_SHOT_CRASH_WIPE)
+//!
+//! A crash of a token-holding process can hand its memory to whatever runs
+//! next: a core file, systemd-coredump, or a language runtime's crash
+//! reporter. With AWF_ONE_SHOT_CRASH_WIPE set, SIGSEGV, SIGBUS and SIGABRT
+//! handlers are installed at load that zeroize every cached value (see the
+//! secmem module) and the environment values of protected, aliased and
+//! deny-listed names not read yet, then re-raise the signal with its default
+//! action, so the process still dies the way it would have.
+//!
+//! The handler only does async-signal-safe work: it takes no lock and does
+//! not allocate. Sealed values that were never served stay obfuscated (see
+//! the seal module). A signal that already has a handler at load keeps it,
+//! and a program that installs its own handler later replaces this one; a
+//! fault while wiping (for example a read-only environment string) ends the
+//! process with the default action right away. Forked children inherit the
+//! handlers and wipe only their own memory.
+
+use crate::{environ, lock_state, published_watched_names, secmem, Mode};
+use libc::c_int;
+
+/// Signals that end the process with a core dump
+const SIGNALS: [c_int; 3] = [libc::SIGSEGV, libc::SIGBUS, libc::SIGABRT];
+
+/// Install the crash handlers at load if AWF_ONE_SHOT_CRASH_WIPE is set
+extern "C" fn install_crash_handlers_at_load() {
+    let state = lock_state();
+    if !state.crash_wipe {
+        return;
+    }
+    let observe = state.mode == Mode::Observe;
+    let debug = state.debug_enabled;
+    drop(state);
+    if observe {
+        crate::audit::emit("observed", "*", "would=install_crash_wipe");
+        return;
+    }
+
+    for signum in SIGNALS {
+        match install(signum) {
+            Ok(true) => {}
+            Ok(false) if debug => log_line!(
+                Debug,
+                "crash",
+                None,
+                "Signal {} already has a handler; not wiping on it",
+                signum
+            ),
+            Ok(false) => {}
+            Err(err) => log_line!(
+                Warning,
+                "crash",
+                None,
+                "Could not install the handler for signal {}: {}",
+                signum,
+                err
+            ),
+        }
+    }
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static INSTALL_CRASH_HANDLERS_AT_LOAD: extern "C" fn() = install_crash_handlers_at_load;
+
+/// Install the handler for `signum`, unless it already has one
+fn install(signum: c_int) -> std::io::Result<bool> {
+    // SAFETY: sigaction is a plain struct; on_crash is async-signal-safe
+    unsafe {
+        let mut previous: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(signum, std::ptr::null(), &mut previous) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if previous.sa_sigaction != libc::SIG_DFL {
+            return Ok(false);
+        }
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_crash as extern "C" fn(c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESETHAND | libc::SA_ONSTACK;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signum, &action, std::ptr::null_mut()) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(true)
+}
+
+/// Crash handler: wipe, then die of the same signal
+///
+/// The default action is restored here rather than only through
+/// SA_RESETHAND, in case the handler was installed again without it.
+extern "C" fn on_crash(signum: c_int) {
+    // SAFETY: the process is dying; both wipes are async-signal-safe
+    unsafe {
+        secmem::wipe_tracked();
+        if let Some(names) = published_watched_names() {
+            environ::zero_values_unchecked(environ::current(), |name| names.contains(name));
+        }
+        // Blocked until the handler returns; a fault re-faults on return
+        let mut default: libc::sigaction = std::mem::zeroed();
+        default.sa_sigaction = libc::SIG_DFL;
+        libc::sigaction(signum, &default, std::ptr::null_mut());
+        libc::raise(signum);
+    }
+}
@@ -0,0 +1,212 @@
+//! Access policy for well-known credential files
+//!
+//! Secrets live on disk as well as in the environment. Opens of well-known
+//! credential files (see the open module) are checked against a per-path
+//! policy: `allow` lets the open through silently, `log` allows it and emits a
+//! `file_access` audit event, and `deny` fails it with EACCES and emits
+//! `file_denied`. The built-in files default to `log`.
+//! AWF_ONE_SHOT_CREDENTIAL_FILES adds paths or changes their policy with
+//! comma-separated `PATH:POLICY` entries; a leading `~/` refers to $HOME.
+//!
+//! Paths are compared after lexical normalization; symlinks are not resolved.
+//! The policy table lives outside the state lock because the library opens
+//! files (such as /proc/self/maps) while holding that lock.
+
+use crate::{audit, lock_state, protection_disabled, read_config_var, Mode};
+use once_cell::sync::Lazy;
+use std::ffi::CStr;
+
+/// What happens when a credential file is opened
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FilePolicy {
+    Allow,
+    Log,
+    Deny,
+}
+
+impl FilePolicy {
+    /// Parse a policy name (case-insensitive)
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "allow" => Some(FilePolicy::Allow),
+            "log" | "allow-and-log" => Some(FilePolicy::Log),
+            "deny" => Some(FilePolicy::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// Credential files covered by default, all with the `log` policy
+const DEFAULT_CREDENTIAL_FILES: &[&str] = &[
+    "~/.aws/credentials",
+    "~/.npmrc",
+    "~/.git-credentials",
+    "~/.docker/config.json",
+];
+
+/// Normalized absolute paths and their policies, loaded on first use
+static POLICIES: Lazy<Vec<(Vec<u8>, FilePolicy)>> = Lazy::new(|| {
+    if protection_disabled() {
+        return Vec::new();
+    }
+    load_policies(
+        read_config_var(c"AWF_ONE_SHOT_CREDENTIAL_FILES").as_deref(),
+        read_config_var(c"HOME").as_deref(),
+    )
+});
+
+/// Build the policy table from the defaults and AWF_ONE_SHOT_CREDENTIAL_FILES
+///
+/// Entries with an unknown policy, or relative paths, are ignored. `~/` paths
+/// are ignored when HOME is not set.
+fn load_policies(config: Option<&str>, home: Option<&str>) -> Vec<(Vec<u8>, FilePolicy)> {
+    let mut policies: Vec<(Vec<u8>, FilePolicy)> = DEFAULT_CREDENTIAL_FILES
+        .iter()
+        .filter_map(|path| expand_home(path, home))
+        .map(|path| (path, FilePolicy::Log))
+        .collect();
+
+    let entries = config
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty());
+    for entry in entries {
+        let Some((path, policy)) = entry.rsplit_once(':') else {
+            continue;
+        };
+        let (Some(path), Some(policy)) = (
+            expand_home(path.trim(), home),
+            FilePolicy::parse(policy.trim()),
+        ) else {
+            continue;
+        };
+        match policies.iter_mut().find(|(existing, _)| *existing == path) {
+            Some(existing) => existing.1 = policy,
+            None => policies.push((path, policy)),
+        }
+    }
+    policies
+}
+
+/// Expand a leading `~/` and normalize; None for relative paths
+fn expand_home(path: &str, home: Option<&str>) -> Option<Vec<u8>> {
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => format!("{}/{}", home?, rest),
+        None => path.to_string(),
+    };
+    path.starts_with('/')
+        .then(|| normalize_path(path.as_bytes()))
+}
+
+/// Collapse repeated slashes, `.` and `..` components of an absolute path
+fn normalize_path(path: &[u8]) -> Vec<u8> {
+    let mut parts: Vec<&[u8]> = Vec::new();
+    for part in path.split(|&b| b == b'/') {
+        match part {
+            b"" | b"." => {}
+            b".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    let mut normalized = Vec::with_capacity(path.len());
+    for part in parts {
+        normalized.push(b'/');
+        normalized.extend_from_slice(part);
+    }
+    if normalized.is_empty() {
+        normalized.push(b'/');
+    }
+    normalized
+}
+
+fn file_name(path: &[u8]) -> &[u8] {
+    path.rsplit(|&b| b == b'/').next().unwrap_or(path)
+}
+
+/// Look up the policy for an opened path
+///
+/// Relative paths are resolved against the working directory, but only when
+/// their file name matches a covered file, to keep ordinary opens cheap.
+fn policy_for(path: &[u8]) -> Option<(Vec<u8>, FilePolicy)> {
+    let name = file_name(path);
+    if !POLICIES
+        .iter()
+        .any(|(covered, _)| file_name(covered) == name)
+    {
+        return None;
+    }
+
+    let absolute = if path.starts_with(b"/") {
+        normalize_path(path)
+    } else {
+        let cwd = std::env::current_dir().ok()?;
+        let mut joined = cwd.into_os_string().into_encoded_bytes();
+        joined.push(b'/');
+        joined.extend_from_slice(path);
+        normalize_path(&joined)
+    };
+    POLICIES
+        .iter()
+        .find(|(covered, _)| *covered == absolute)
+        .map(|(_, policy)| (absolute, *policy))
+}
+
+/// Apply the credential file policy to an open of `path`
+///
+/// Emits the audit event for the matching policy and returns false if the
+/// open must fail with EACCES.
+pub(crate) fn check_access(path: &CStr, via: &str) -> bool {
+    let Some((absolute, policy)) = policy_for(path.to_bytes()) else {
+        return true;
+    };
+    let detail = format!("path={} via={}", String::from_utf8_lossy(&absolute), via);
+
+    match policy {
+        FilePolicy::Allow => true,
+        FilePolicy::Log => {
+            audit::emit("file_access", "*", &detail);
+            true
+        }
+        FilePolicy::Deny if lock_state().mode == Mode::Observe => {
+            audit::emit("observed", "*", &format!("would=deny_file {}", detail));
+            true
+        }
+        FilePolicy::Deny => {
+            audit::emit("file_denied", "*", &detail);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_policies() {
+        let policies = load_policies(
+            Some("~/.npmrc:deny, /etc/secret.json:allow-and-log, /x:bogus, rel/path:deny"),
+            Some("/home/agent"),
+        );
+        assert_eq!(policies.len(), 5);
+        assert!(policies.contains(&(b"/home/agent/.npmrc".to_vec(), FilePolicy::Deny)));
+        assert!(policies.contains(&(b"/home/agent/.aws/credentials".to_vec(), FilePolicy::Log)));
+        assert!(policies.contains(&(b"/etc/secret.json".to_vec(), FilePolicy::Log)));
+
+        // Without HOME only absolute entries remain
+        let policies = load_policies(Some("~/.npmrc:deny,/etc/a:deny"), None);
+        assert_eq!(policies, vec![(b"/etc/a".to_vec(), FilePolicy::Deny)]);
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(
+            normalize_path(b"/home//agent/./.aws/../.aws/credentials"),
+            b"/home/agent/.aws/credentials"
+        );
+        assert_eq!(normalize_path(b"/../"), b"/");
+    }
+}
@@ -0,0 +1,166 @@
+//! Derived tokens (the `derive` token policy)
+//!
+//! A `derive` token is served as `awfd_IDENTITY_MAC` instead of its value,
+//! where IDENTITY is the file name of the reading executable and MAC is the
+//! hex HMAC-SHA256 of IDENTITY keyed with the value. Each tool thus holds a
+//! credential of its own that is useless to the upstream service: the
+//! host-side awf-token-translate recognizes it at the proxy, attributes the
+//! request to the tool, refuses tools that were revoked and puts the real
+//! value in its place.
+//!
+//! Shared with awf-token-translate, so nothing here depends on the rest of
+//! the library but the sha256 and sys modules.
+
+use crate::sha256::sha256;
+use crate::sys;
+
+/// Prefix of derived values
+pub(crate) const PREFIX: &str = "awfd_";
+
+/// Longest identity kept; longer executable names are truncated
+const MAX_IDENTITY: usize = 64;
+
+/// SHA-256 block size
+const BLOCK: usize = 64;
+
+/// HMAC-SHA256 (RFC 2104) of `message` keyed with `key`
+pub(crate) fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner: Vec<u8> = block.iter().map(|byte| byte ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let inner_digest = sha256(&inner);
+    let mut outer: Vec<u8> = block.iter().map(|byte| byte ^ 0x5c).collect();
+    outer.extend_from_slice(&inner_digest);
+    let mac = sha256(&outer);
+    zero(&mut block);
+    zero(&mut inner);
+    zero(&mut outer);
+    mac
+}
+
+/// Identity of the executable at `exe`: its file name, with every byte but
+/// ASCII letters, digits, `.` and `-` replaced by `-`
+pub(crate) fn identity(exe: &str) -> String {
+    let name = exe.rsplit('/').next().unwrap_or_default();
+    let identity: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' => c,
+            _ => '-',
+        })
+        .take(MAX_IDENTITY)
+        .collect();
+    if identity.is_empty() {
+        "unknown".to_string()
+    } else {
+        identity
+    }
+}
+
+/// The value served to `identity` for a token whose real value is `value`
+pub(crate) fn derive(value: &[u8], identity: &str) -> String {
+    let mac = hmac_sha256(value, identity.as_bytes());
+    let hex: String = mac.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}{}_{}", PREFIX, identity, hex)
+}
+
+/// Split a derived value into its identity and MAC
+///
+/// Returns None for anything that is not a well-formed derived value. Only
+/// awf-token-translate reads derived values.
+#[allow(dead_code)]
+pub(crate) fn parse(derived: &str) -> Option<(&str, [u8; 32])> {
+    let (identity, hex) = derived.strip_prefix(PREFIX)?.split_once('_')?;
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '.' || c == '-';
+    if identity.is_empty() || identity.len() > MAX_IDENTITY || !identity.chars().all(valid) {
+        return None;
+    }
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut mac = [0u8; 32];
+    for (byte, pair) in mac.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let high = (pair[0] as char).to_digit(16)?;
+        let low = (pair[1] as char).to_digit(16)?;
+        *byte = (high * 16 + low) as u8;
+    }
+    Some((identity, mac))
+}
+
+/// Whether `mac` was derived from `value` for `identity`, compared in
+/// constant time
+#[allow(dead_code)]
+pub(crate) fn matches(value: &[u8], identity: &str, mac: &[u8; 32]) -> bool {
+    let expected = hmac_sha256(value, identity.as_bytes());
+    expected
+        .iter()
+        .zip(mac)
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+fn zero(bytes: &mut [u8]) {
+    // SAFETY: bytes is a writable buffer of the given length
+    unsafe { sys::explicit_bzero(bytes.as_mut_ptr().cast(), bytes.len()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231 test cases 1, 2 and 6
+        assert_eq!(
+            hex(&hmac_sha256(&[0x0b; 20], b"Hi There")),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_identity() {
+        assert_eq!(identity("/usr/bin/gh"), "gh");
+        assert_eq!(identity("/usr/bin/python3.12"), "python3.12");
+        assert_eq!(identity("/opt/my tool_v2"), "my-tool-v2");
+        assert_eq!(identity(""), "unknown");
+        assert_eq!(identity(&"x".repeat(100)).len(), 64);
+    }
+
+    #[test]
+    fn test_derive_parse() {
+        let derived = derive(b"ghp_0123456789", "gh");
+        assert!(derived.starts_with("awfd_gh_"));
+        assert_eq!(derived.len(), "awfd_gh_".len() + 64);
+        let (identity, mac) = parse(&derived).unwrap();
+        assert_eq!(identity, "gh");
+        assert!(matches(b"ghp_0123456789", identity, &mac));
+        assert!(!matches(b"ghp_0123456780", identity, &mac));
+        assert!(!matches(b"ghp_0123456789", "git", &mac));
+        assert_ne!(derive(b"ghp_0123456789", "git"), derived);
+
+        assert!(parse("ghp_0123456789").is_none());
+        assert!(parse(&derived[..derived.len() - 1]).is_none());
+        assert!(parse(&derived.replace("awfd_gh", "awfd_g h")).is_none());
+        assert!(parse(&format!("{}_{}", PREFIX, "0".repeat(64))).is_none());
+    }
+}
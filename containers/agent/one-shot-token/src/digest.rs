@@ -0,0 +1,98 @@
+//! Token integrity verification (AWF_ONE_SHOT_TOKEN_DIGESTS)
+//!
+//! The host can pass the SHA-256 digest of every token it injects, as a
+//! comma-separated list of `NAME=HEX` entries (an optional `sha256:` prefix is
+//! accepted). When a token is cached, its value is hashed and compared, and a
+//! value that was substituted or truncated somewhere between the host and the
+//! program, or never arrived, raises a `token_digest` audit event with
+//! `severity=high`. The value is still served: verification reports problems
+//! in the injection pipeline, it does not replace the token policies.
+
+use crate::audit;
+use crate::envname::{self, EnvName};
+use crate::sha256::sha256;
+
+/// Parse AWF_ONE_SHOT_TOKEN_DIGESTS
+///
+/// Entries without a name or with anything but 64 hex digits are skipped.
+pub(crate) fn parse(config: &[u8]) -> Vec<(EnvName, [u8; 32])> {
+    envname::split_list(config)
+        .filter_map(|entry| {
+            let eq = entry.iter().position(|&byte| byte == b'=')?;
+            let (name, hex) = (entry[..eq].trim_ascii(), entry[eq + 1..].trim_ascii());
+            let hex = hex.strip_prefix(b"sha256:").unwrap_or(hex);
+            if name.is_empty() || hex.len() != 64 {
+                return None;
+            }
+            let mut digest = [0u8; 32];
+            for (byte, pair) in digest.iter_mut().zip(hex.chunks(2)) {
+                let high = (pair[0] as char).to_digit(16)?;
+                let low = (pair[1] as char).to_digit(16)?;
+                *byte = (high * 16 + low) as u8;
+            }
+            Some((EnvName::new(name), digest))
+        })
+        .collect()
+}
+
+/// Compare the value of `token` with its expected digest
+///
+/// Emits a `token_digest` audit event either way (the digest of the value is
+/// not logged). Returns whether the value matches.
+pub(crate) fn verify(token: &str, expected: &[u8; 32], value: &[u8]) -> bool {
+    let actual = sha256(value);
+    let diff = actual
+        .iter()
+        .zip(expected)
+        .fold(0, |diff, (a, b)| diff | (a ^ b));
+    if diff == 0 {
+        audit::emit(
+            "token_digest",
+            token,
+            &format!("action=verified bytes={}", value.len()),
+        );
+        return true;
+    }
+    audit::emit(
+        "token_digest",
+        token,
+        &format!("severity=high action=mismatch bytes={}", value.len()),
+    );
+    false
+}
+
+/// Report a token the host gave a digest for that is not set
+pub(crate) fn report_missing(token: &str) {
+    audit::emit("token_digest", token, "severity=high action=missing");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn test_parse() {
+        let config = format!(
+            " GITHUB_TOKEN = {ABC} ,GH_TOKEN=sha256:{},SHORT=abc,=0{},BAD={}",
+            ABC.to_uppercase(),
+            &ABC[1..],
+            ABC.replace('b', "g")
+        );
+        let digests = parse(config.as_bytes());
+        assert_eq!(digests.len(), 2);
+        assert_eq!(digests[0].0, "GITHUB_TOKEN");
+        assert_eq!(digests[0].1, sha256(b"abc"));
+        assert_eq!(digests[1].0, "GH_TOKEN");
+        assert_eq!(digests[1].1, sha256(b"abc"));
+    }
+
+    #[test]
+    fn test_verify() {
+        let expected = sha256(b"ghp_0123456789");
+        assert!(verify("GITHUB_TOKEN", &expected, b"ghp_0123456789"));
+        assert!(!verify("GITHUB_TOKEN", &expected, b"ghp_012345678"));
+        assert!(!verify("GITHUB_TOKEN", &expected, b""));
+    }
+}
@@ -0,0 +1,227 @@
+//! getenv calls made before the library is initialized
+//!
+//! Code can reach the interposers before the library's constructors ran:
+//! IFUNC resolvers, constructors of libraries preloaded after this one (the
+//! dynamic linker runs those first) or an allocator reading its tuning
+//! variables. Touching the lazily initialized state then could call dlsym
+//! while the dynamic linker holds its lock, or allocate before an interposed
+//! malloc is ready. Until a constructor marks the library initialized, the
+//! interposers therefore look names up in environ themselves, without
+//! allocating or locking, and neither cache nor scrub: protection is
+//! deferred to the first call after initialization, which still finds the
+//! value in the environment.
+//!
+//! Names whose value was found are remembered as hashes in a fixed table.
+//! Once initialized, every protected token among them is reported with an
+//! `early_read` audit event, as the early caller may have kept the value.
+
+use crate::{audit, environ, lock_state, protection_disabled, resolve_sensitive_token};
+use libc::c_char;
+use std::ffi::CStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// Whether the interposers may use the library state
+///
+/// Builds without the preload feature have no constructors and are always
+/// initialized.
+static INITIALIZED: AtomicBool = AtomicBool::new(!cfg!(feature = "preload"));
+
+/// getenv calls served before initialization
+static EARLY_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Names found before initialization
+static EARLY_NAMES: NameTable = NameTable::new();
+
+/// Number of distinct names remembered; later names are not reported
+const SLOTS: usize = 64;
+
+/// A fixed set of name hashes that can be filled without allocating
+struct NameTable {
+    slots: [AtomicU64; SLOTS],
+}
+
+impl NameTable {
+    const fn new() -> Self {
+        NameTable {
+            slots: [const { AtomicU64::new(0) }; SLOTS],
+        }
+    }
+
+    /// FNV-1a of `name`, never 0 (which marks a free slot)
+    fn hash(name: &[u8]) -> u64 {
+        let hash = name.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+        hash.max(1)
+    }
+
+    /// Remember `name`; returns false if the table is full
+    fn insert(&self, name: &[u8]) -> bool {
+        let hash = Self::hash(name);
+        self.slots.iter().any(|slot| {
+            match slot.compare_exchange(0, hash, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => true,
+                Err(current) => current == hash,
+            }
+        })
+    }
+
+    /// Whether `name` was remembered (or collides with a name that was)
+    fn contains(&self, name: &[u8]) -> bool {
+        let hash = Self::hash(name);
+        self.slots
+            .iter()
+            .any(|slot| slot.load(Ordering::Acquire) == hash)
+    }
+}
+
+/// Whether the interposers may use the library state
+pub(crate) fn initialized() -> bool {
+    INITIALIZED.load(Ordering::Acquire)
+}
+
+/// The value of `name` in the environment array `envp`, or null
+///
+/// # Safety
+/// `envp` must be null or a null-terminated array of valid C strings
+unsafe fn lookup(envp: *const *mut c_char, name: &[u8]) -> *mut c_char {
+    match environ::find_entry_in(envp, name) {
+        Some(entry) => entry.add(name.len() + 1),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// getenv before initialization: the value straight from environ
+///
+/// With `secure`, returns null in set-user-ID and capability-raising
+/// programs, as secure_getenv does.
+///
+/// # Safety
+/// `name` must be null or a valid null-terminated C string
+pub(crate) unsafe fn getenv(name: *const c_char, secure: bool) -> *mut c_char {
+    EARLY_CALLS.fetch_add(1, Ordering::Relaxed);
+    if name.is_null() || (secure && setuid()) {
+        return std::ptr::null_mut();
+    }
+    let name = CStr::from_ptr(name).to_bytes();
+    let value = lookup(environ::current(), name);
+    if !value.is_null() {
+        EARLY_NAMES.insert(name);
+    }
+    value
+}
+
+/// Whether the process runs with elevated privileges
+fn setuid() -> bool {
+    #[cfg(target_os = "linux")]
+    // SAFETY: getauxval has no preconditions
+    return unsafe { libc::getauxval(libc::AT_SECURE) } != 0;
+    #[cfg(target_os = "macos")]
+    // SAFETY: issetugid has no preconditions
+    return unsafe { libc::issetugid() } != 0;
+}
+
+/// Initialize the state, let the interposers use it and report the
+/// protected tokens read before
+extern "C" fn finish_early_at_load() {
+    let state = lock_state();
+    INITIALIZED.store(true, Ordering::Release);
+    let calls = EARLY_CALLS.load(Ordering::Relaxed);
+    if calls == 0 || protection_disabled() {
+        return;
+    }
+    if state.debug_enabled {
+        log_line!(
+            Debug,
+            "early_read",
+            None,
+            "Served {} getenv call(s) from environ before initialization",
+            calls
+        );
+    }
+    let mut reported = Vec::new();
+    let names = state
+        .tokens
+        .iter()
+        .map(|spec| &spec.name)
+        .chain(state.aliases.keys());
+    for name in names {
+        if !EARLY_NAMES.contains(name.as_bytes()) {
+            continue;
+        }
+        let Some(canonical) = resolve_sensitive_token(&state, name.as_bytes()) else {
+            continue;
+        };
+        if reported.contains(&canonical) {
+            continue;
+        }
+        reported.push(canonical);
+        let detail = if name == canonical {
+            "severity=medium".to_string()
+        } else {
+            format!("severity=medium name={}", audit::detail_word(&name.lossy()))
+        };
+        audit::emit("early_read", canonical.lossy(), &detail);
+    }
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static FINISH_EARLY_AT_LOAD: extern "C" fn() = finish_early_at_load;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn test_name_table() {
+        let table = NameTable::new();
+        assert!(!table.contains(b"GITHUB_TOKEN"));
+        assert!(table.insert(b"GITHUB_TOKEN"));
+        assert!(table.insert(b"GITHUB_TOKEN"));
+        assert!(table.contains(b"GITHUB_TOKEN"));
+        assert!(!table.contains(b"GITHUB_TOKENS"));
+        for index in 1..SLOTS {
+            assert!(table.insert(format!("NAME_{}", index).as_bytes()));
+        }
+        assert!(!table.insert(b"ONE_TOO_MANY"));
+        assert!(!table.contains(b"ONE_TOO_MANY"));
+        assert!(table.contains(b"NAME_1"));
+    }
+
+    #[test]
+    fn test_lookup() {
+        let entries: Vec<CString> = ["PATH=/bin", "GITHUB_TOKEN=ghp_early", "EMPTY="]
+            .iter()
+            .map(|entry| CString::new(*entry).unwrap())
+            .collect();
+        let mut envp: Vec<*mut c_char> = entries
+            .iter()
+            .map(|entry| entry.as_ptr().cast_mut())
+            .collect();
+        envp.push(std::ptr::null_mut());
+        // SAFETY: envp is a null-terminated array of the C strings above,
+        // which outlive the lookups
+        unsafe {
+            let value = lookup(envp.as_ptr(), b"GITHUB_TOKEN");
+            assert_eq!(CStr::from_ptr(value).to_bytes(), b"ghp_early");
+            assert_eq!(CStr::from_ptr(lookup(envp.as_ptr(), b"EMPTY")).to_bytes(), b"");
+            assert!(lookup(envp.as_ptr(), b"GITHUB").is_null());
+            assert!(lookup(std::ptr::null(), b"PATH").is_null());
+        }
+    }
+
+    #[test]
+    fn test_initialized() {
+        // The constructor ran before the tests, as it runs before main
+        assert!(initialized());
+    }
+}
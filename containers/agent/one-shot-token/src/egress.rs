@@ -0,0 +1,645 @@
+//! Scanning of outgoing data for protected token values
+//!
+//! An agent that reads a token can paste it into an arbitrary request body.
+//! With AWF_ONE_SHOT_EGRESS_SCAN set to `log`, `block` or `kill`, buffers
+//! passed to write, send, sendto and SSL_write (when OpenSSL is loaded) are
+//! scanned for protected token values. A match on a socket whose peer is not
+//! allowed (see the net module; without an allowlist only loopback peers are)
+//! emits an `egress_secret` audit event and, with `block`, fails the call;
+//! `kill` terminates the process instead (see the kill module).
+//! Canary values (see the canary module) are looked for whenever canaries
+//! are configured, and reported for any IP peer. Honeypot values (see the
+//! honeypot module) are looked for once one was served, and fail the call
+//! unless the peer is allowed.
+//!
+//! With AWF_ONE_SHOT_REDACT_OUTPUT, writes to stdout and stderr have every
+//! protected value replaced with `***` before they reach the step log, where
+//! agents echoing their environment most often leak a token. Each redacted
+//! write emits an `output_redacted` audit event; in observe mode the output is
+//! left unchanged. A value split across two writes is not matched. Output
+//! through glibc's stdio is redacted by the stdiowrite module.
+//!
+//! write() also carries the library's own log output, which is produced while
+//! the state lock is held. The secret values are therefore published to a
+//! separate snapshot that the write path reads without touching the state
+//! lock; the state is only locked once a secret has actually been found on a
+//! socket. The snapshot keeps copies of the values, so it is only maintained
+//! while scanning, output redaction, file write scanning (see the filewrite
+//! module) or DNS query name scanning (see the dns module) is enabled.
+
+use crate::detect::{find_known_spans, MIN_KNOWN_SECRET_LEN};
+use crate::filewrite::{self, Check};
+use crate::net::{sockaddr_ip, sockaddr_socket};
+use crate::{
+    audit, canary, dns, honeypot, kill, lock_state, protected_token_entries, protection_disabled,
+    read_config_flag, read_config_var, reentry, resolve_next, sys, Mode, TokenState,
+};
+use libc::{c_int, c_void, msghdr, size_t, sockaddr, sockaddr_storage, socklen_t, ssize_t};
+use once_cell::sync::Lazy;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+/// What happens when a protected value is written to a non-allowed peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EgressPolicy {
+    Off,
+    Log,
+    Block,
+    /// Block, then terminate the process (see the kill module)
+    Kill,
+}
+
+impl EgressPolicy {
+    /// Parse AWF_ONE_SHOT_EGRESS_SCAN (case-insensitive)
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" | "0" | "" => Some(EgressPolicy::Off),
+            "log" => Some(EgressPolicy::Log),
+            "block" => Some(EgressPolicy::Block),
+            "kill" => Some(EgressPolicy::Kill),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `value` is an AWF_ONE_SHOT_EGRESS_SCAN value
+pub(crate) fn is_policy(value: &str) -> bool {
+    EgressPolicy::parse(value).is_some()
+}
+
+/// Scan policy, read once outside the state lock
+///
+/// Unrecognized values block, so a typo never silently disables scanning.
+static POLICY: Lazy<EgressPolicy> = Lazy::new(|| {
+    if protection_disabled() {
+        return EgressPolicy::Off;
+    }
+    read_config_var(c"AWF_ONE_SHOT_EGRESS_SCAN")
+        .map(|value| EgressPolicy::parse(&value).unwrap_or(EgressPolicy::Block))
+        .unwrap_or(EgressPolicy::Off)
+});
+
+/// Whether writes to stdout and stderr are redacted (AWF_ONE_SHOT_REDACT_OUTPUT)
+static REDACT_OUTPUT: Lazy<bool> =
+    Lazy::new(|| !protection_disabled() && read_config_flag(c"AWF_ONE_SHOT_REDACT_OUTPUT", false));
+
+/// What replaces a protected value in redacted output
+const OUTPUT_PLACEHOLDER: &[u8] = b"***";
+
+/// Protected values to look for, paired with their canonical token name
+static SECRETS: RwLock<Vec<(String, Vec<u8>)>> = RwLock::new(Vec::new());
+
+/// Whether the library is in observe mode, published with the secrets so the
+/// output path never takes the state lock
+static OBSERVE: AtomicBool = AtomicBool::new(false);
+
+type WriteFn = unsafe extern "C" fn(c_int, *const c_void, size_t) -> ssize_t;
+type SendFn = unsafe extern "C" fn(c_int, *const c_void, size_t, c_int) -> ssize_t;
+type SendtoFn = unsafe extern "C" fn(
+    c_int,
+    *const c_void,
+    size_t,
+    c_int,
+    *const sockaddr,
+    socklen_t,
+) -> ssize_t;
+type SendmsgFn = unsafe extern "C" fn(c_int, *const msghdr, c_int) -> ssize_t;
+type SslWriteFn = unsafe extern "C" fn(*mut c_void, *const c_void, c_int) -> c_int;
+type SslGetFdFn = unsafe extern "C" fn(*const c_void) -> c_int;
+
+/// Cached pointer to the real write function
+static REAL_WRITE: Lazy<WriteFn> = Lazy::new(|| {
+    // SAFETY: write has the WriteFn signature
+    unsafe { std::mem::transmute::<*mut c_void, WriteFn>(resolve_next(c"write")) }
+});
+
+/// Call the real write function, bypassing the egress scan
+///
+/// # Safety
+/// Arguments must satisfy the requirements of write(2)
+pub(crate) unsafe fn call_real_write(fd: c_int, buf: *const c_void, count: size_t) -> ssize_t {
+    (*REAL_WRITE)(fd, buf, count)
+}
+
+/// Call the real sendto function, bypassing the egress scan
+///
+/// # Safety
+/// Arguments must satisfy the requirements of sendto(2)
+pub(crate) unsafe fn call_real_sendto(
+    fd: c_int,
+    buf: *const c_void,
+    len: size_t,
+    flags: c_int,
+    dest: *const sockaddr,
+    dest_len: socklen_t,
+) -> ssize_t {
+    (*REAL_SENDTO)(fd, buf, len, flags, dest, dest_len)
+}
+
+/// Cached pointer to the real send function
+static REAL_SEND: Lazy<SendFn> = Lazy::new(|| {
+    // SAFETY: send has the SendFn signature
+    unsafe { std::mem::transmute::<*mut c_void, SendFn>(resolve_next(c"send")) }
+});
+
+/// Cached pointer to the real sendto function
+static REAL_SENDTO: Lazy<SendtoFn> = Lazy::new(|| {
+    // SAFETY: sendto has the SendtoFn signature
+    unsafe { std::mem::transmute::<*mut c_void, SendtoFn>(resolve_next(c"sendto")) }
+});
+
+/// Cached pointer to the real sendmsg function
+static REAL_SENDMSG: Lazy<SendmsgFn> = Lazy::new(|| {
+    // SAFETY: sendmsg has the SendmsgFn signature
+    unsafe { std::mem::transmute::<*mut c_void, SendmsgFn>(resolve_next(c"sendmsg")) }
+});
+
+/// Cached pointer to OpenSSL's SSL_write (only resolved once it is called,
+/// which implies libssl is loaded)
+static REAL_SSL_WRITE: Lazy<SslWriteFn> = Lazy::new(|| {
+    // SAFETY: SSL_write has the SslWriteFn signature
+    unsafe { std::mem::transmute::<*mut c_void, SslWriteFn>(resolve_next(c"SSL_write")) }
+});
+
+/// Cached pointer to OpenSSL's SSL_get_fd
+static REAL_SSL_GET_FD: Lazy<SslGetFdFn> = Lazy::new(|| {
+    // SAFETY: SSL_get_fd has the SslGetFdFn signature
+    unsafe { std::mem::transmute::<*mut c_void, SslGetFdFn>(resolve_next(c"SSL_get_fd")) }
+});
+
+/// Whether writes to stdout and stderr are redacted
+pub(crate) fn redacting_output() -> bool {
+    *REDACT_OUTPUT
+}
+
+/// Refresh the snapshot of protected values after the cache changed
+pub(crate) fn publish_secrets(state: &TokenState) {
+    if *POLICY == EgressPolicy::Off && !*REDACT_OUTPUT && !filewrite::enabled() && !dns::scanning()
+    {
+        return;
+    }
+    OBSERVE.store(state.mode == Mode::Observe, Ordering::Relaxed);
+    let secrets = protected_token_entries(state)
+        .into_iter()
+        .filter(|(_, value)| value.len() >= MIN_KNOWN_SECRET_LEN)
+        .map(|(name, value)| (name, value.into_bytes()))
+        .collect();
+    *SECRETS.write().unwrap_or_else(|err| err.into_inner()) = secrets;
+}
+
+/// Run `f` on the protected values, paired with their token names
+pub(crate) fn with_secrets<R>(f: impl FnOnce(&[(String, Vec<u8>)]) -> R) -> R {
+    let secrets = SECRETS.read().unwrap_or_else(|err| err.into_inner());
+    f(&secrets)
+}
+
+/// Name of the token whose value appears in `buf`, if any
+fn find_secret(buf: &[u8]) -> Option<String> {
+    let secrets = SECRETS.read().unwrap_or_else(|err| err.into_inner());
+    secrets
+        .iter()
+        .find(|(_, value)| !find_known_spans(buf, &[value.as_slice()]).is_empty())
+        .map(|(name, _)| name.clone())
+}
+
+/// Byte ranges of protected values in `buf`, with the names of the tokens
+/// found, or None if there are none
+pub(crate) fn find_protected_spans(buf: &[u8]) -> Option<(Vec<Range<usize>>, Vec<String>)> {
+    let secrets = SECRETS.read().unwrap_or_else(|err| err.into_inner());
+    let found: Vec<&(String, Vec<u8>)> = secrets
+        .iter()
+        .filter(|(_, value)| !find_known_spans(buf, &[value.as_slice()]).is_empty())
+        .collect();
+    if found.is_empty() {
+        return None;
+    }
+    let known: Vec<&[u8]> = found.iter().map(|(_, value)| value.as_slice()).collect();
+    let mut names: Vec<String> = found.iter().map(|(name, _)| name.clone()).collect();
+    names.dedup();
+    Some((find_known_spans(buf, &known), names))
+}
+
+/// Whether the library is in observe mode, as last published
+pub(crate) fn observing() -> bool {
+    OBSERVE.load(Ordering::Relaxed)
+}
+
+/// Copy of `buf` with each span replaced by the output placeholder
+fn redact_spans(buf: &[u8], spans: &[Range<usize>]) -> Vec<u8> {
+    let mut redacted = Vec::with_capacity(buf.len());
+    let mut last = 0;
+    for span in spans {
+        redacted.extend_from_slice(&buf[last..span.start]);
+        redacted.extend_from_slice(OUTPUT_PLACEHOLDER);
+        last = span.end;
+    }
+    redacted.extend_from_slice(&buf[last..]);
+    redacted
+}
+
+/// Copy of `data`, written to stdout or stderr, with protected values
+/// redacted, or None if it can be written as is
+///
+/// Emits the audit events of the redaction, or of observe mode.
+pub(crate) fn redact_output(fd: c_int, data: &[u8]) -> Option<Vec<u8>> {
+    if !*REDACT_OUTPUT || !(fd == 1 || fd == 2) || data.is_empty() {
+        return None;
+    }
+    // Our own log lines are written with the state lock held (see above)
+    if reentry::active() {
+        return None;
+    }
+    let (spans, names) = find_protected_spans(data)?;
+
+    if observing() {
+        for name in &names {
+            audit::emit("observed", name, &format!("would=redact_output fd={}", fd));
+        }
+        return None;
+    }
+    for name in &names {
+        audit::emit(
+            "output_redacted",
+            name,
+            &format!("fd={} matches={}", fd, spans.len()),
+        );
+    }
+    Some(redact_spans(data, &spans))
+}
+
+/// Write `buf` to stdout or stderr with protected values redacted
+///
+/// Returns None if the buffer holds no protected value and can be written as
+/// is. Otherwise the redacted copy is written in full and `count` is returned,
+/// as the caller's byte counts refer to the original buffer; -1 is returned
+/// only if nothing could be written.
+///
+/// # Safety
+/// `buf` must be null or valid for reads of `count` bytes
+unsafe fn write_redacted(fd: c_int, buf: *const c_void, count: size_t) -> Option<ssize_t> {
+    if buf.is_null() {
+        return None;
+    }
+    let data = std::slice::from_raw_parts(buf as *const u8, count);
+    let redacted = redact_output(fd, data)?;
+
+    let mut written = 0;
+    while written < redacted.len() {
+        let rest = &redacted[written..];
+        let n = (*REAL_WRITE)(fd, rest.as_ptr().cast(), rest.len());
+        if n >= 0 {
+            written += n as usize;
+            continue;
+        }
+        match *sys::errno_location() {
+            libc::EINTR => continue,
+            libc::EAGAIN if written > 0 => {
+                // Finish a partial write on a non-blocking descriptor, so
+                // the caller never resumes in the middle of a placeholder
+                let mut pollfd = libc::pollfd {
+                    fd,
+                    events: libc::POLLOUT,
+                    revents: 0,
+                };
+                libc::poll(&mut pollfd, 1, -1);
+            }
+            _ if written > 0 => break,
+            _ => return Some(-1),
+        }
+    }
+    Some(count as ssize_t)
+}
+
+/// Whether outgoing buffers need to be scanned at all
+fn scanning() -> bool {
+    *POLICY != EgressPolicy::Off || canary::configured() || honeypot::served()
+}
+
+/// Report a honeypot value of `token` written to `peer`; returns false if
+/// the write must fail
+///
+/// Writes to allowed peers go ahead, so the proxy can refuse the request and
+/// log its destination; writes anywhere else are blocked here.
+fn allow_honeypot(token: &str, peer: SocketAddr, via: &str) -> bool {
+    let allowed = match &lock_state().allowlist {
+        Some(allowlist) => allowlist.allows_ip(peer.ip()),
+        None => peer.ip().to_canonical().is_loopback(),
+    };
+    let action = if allowed { "forwarded" } else { "blocked" };
+    audit::emit(
+        "honeypot_egress",
+        token,
+        &format!(
+            "severity=high ip={} port={} via={} action={}",
+            peer.ip(),
+            peer.port(),
+            via,
+            action
+        ),
+    );
+    allowed
+}
+
+/// Peer address of a connected socket
+///
+/// Returns None for descriptors that are not IP sockets or not connected.
+fn socket_peer(fd: c_int) -> Option<SocketAddr> {
+    // SAFETY: storage is large enough for any socket address
+    unsafe {
+        let mut storage: sockaddr_storage = std::mem::zeroed();
+        let mut len = std::mem::size_of::<sockaddr_storage>() as socklen_t;
+        if libc::getpeername(fd, &mut storage as *mut _ as *mut sockaddr, &mut len) != 0 {
+            return None;
+        }
+        sockaddr_socket(&storage as *const _ as *const sockaddr)
+    }
+}
+
+/// Check an outgoing buffer
+///
+/// `dest` is the explicit destination of sendto, if any. Returns false if the
+/// write must fail.
+///
+/// # Safety
+/// `buf` must be null or valid for reads of `len` bytes, and `dest` must be
+/// null or a valid socket address
+unsafe fn allow_outgoing(
+    fd: c_int,
+    buf: *const c_void,
+    len: size_t,
+    dest: *const sockaddr,
+    via: &str,
+) -> bool {
+    if !scanning() || buf.is_null() || len == 0 {
+        return true;
+    }
+    let data = std::slice::from_raw_parts(buf as *const u8, len);
+    if let Some(canary) = canary::find_in(data) {
+        if let Some(peer) = sockaddr_ip(dest).or_else(|| socket_peer(fd).map(|peer| peer.ip())) {
+            audit::emit(
+                "canary_egress",
+                canary,
+                &format!("severity=high ip={} via={}", peer, via),
+            );
+        }
+    }
+    if let Some(token) = honeypot::find_in(data) {
+        if let Some(peer) = sockaddr_socket(dest).or_else(|| socket_peer(fd)) {
+            return allow_honeypot(&token, peer, via);
+        }
+    }
+    if *POLICY == EgressPolicy::Off {
+        return true;
+    }
+    let Some(token) = find_secret(data) else {
+        return true;
+    };
+    let Some(peer) = sockaddr_ip(dest).or_else(|| socket_peer(fd).map(|peer| peer.ip())) else {
+        return true;
+    };
+
+    let state = lock_state();
+    let allowed = match &state.allowlist {
+        Some(allowlist) => allowlist.allows_ip(peer),
+        None => peer.to_canonical().is_loopback(),
+    };
+    if allowed {
+        return true;
+    }
+
+    let detail = format!("ip={} via={}", peer, via);
+    match *POLICY {
+        EgressPolicy::Block if state.mode == Mode::Enforce => {
+            audit::emit(
+                "egress_secret",
+                &token,
+                &format!("{} action=blocked", detail),
+            );
+            false
+        }
+        EgressPolicy::Kill if state.mode == Mode::Enforce => {
+            drop(state);
+            audit::emit(
+                "egress_secret",
+                &token,
+                &format!("{} action=killed", detail),
+            );
+            kill::terminate("egress_secret", &token, &detail)
+        }
+        EgressPolicy::Block => {
+            audit::emit(
+                "observed",
+                &token,
+                &format!("would=block_egress {}", detail),
+            );
+            true
+        }
+        EgressPolicy::Kill => {
+            audit::emit("observed", &token, &format!("would=kill_egress {}", detail));
+            true
+        }
+        _ => {
+            audit::emit(
+                "egress_secret",
+                &token,
+                &format!("{} action=logged", detail),
+            );
+            true
+        }
+    }
+}
+
+/// Intercepted write function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of write(2).
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn write(fd: c_int, buf: *const c_void, count: size_t) -> ssize_t {
+    if !allow_outgoing(fd, buf, count, std::ptr::null(), "write") {
+        *sys::errno_location() = libc::EACCES;
+        return -1;
+    }
+    if let Some(result) = write_redacted(fd, buf, count) {
+        return result;
+    }
+    match filewrite::check(fd, buf, count, "write") {
+        Check::Allow => (*REAL_WRITE)(fd, buf, count),
+        Check::Redact(data) => (*REAL_WRITE)(fd, data.as_ptr().cast(), count),
+        Check::Block => {
+            *sys::errno_location() = libc::EACCES;
+            -1
+        }
+    }
+}
+
+/// Intercepted send function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of send(2).
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn send(fd: c_int, buf: *const c_void, len: size_t, flags: c_int) -> ssize_t {
+    if !allow_outgoing(fd, buf, len, std::ptr::null(), "send")
+        || !allow_query(fd, buf, len, std::ptr::null(), "send")
+    {
+        *sys::errno_location() = libc::EACCES;
+        return -1;
+    }
+    (*REAL_SEND)(fd, buf, len, flags)
+}
+
+/// Intercepted sendto function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of sendto(2).
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn sendto(
+    fd: c_int,
+    buf: *const c_void,
+    len: size_t,
+    flags: c_int,
+    dest: *const sockaddr,
+    dest_len: socklen_t,
+) -> ssize_t {
+    if !allow_outgoing(fd, buf, len, dest, "sendto")
+        || !allow_destination(dest, "sendto")
+        || !allow_query(fd, buf, len, dest, "sendto")
+    {
+        *sys::errno_location() = libc::EACCES;
+        return -1;
+    }
+    (*REAL_SENDTO)(fd, buf, len, flags, dest, dest_len)
+}
+
+/// Intercepted sendmsg function
+///
+/// Only DNS traffic is checked: the destination against the DNS resolver
+/// and the query name for protected values (see the dns module). The
+/// buffers are not otherwise scanned.
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of sendmsg(2).
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn sendmsg(fd: c_int, msg: *const msghdr, flags: c_int) -> ssize_t {
+    if msg.is_null() {
+        return (*REAL_SENDMSG)(fd, msg, flags);
+    }
+    let dest = (*msg).msg_name as *const sockaddr;
+    if !allow_destination(dest, "sendmsg") || !allow_message_query(fd, &*msg, dest) {
+        *sys::errno_location() = libc::EACCES;
+        return -1;
+    }
+    (*REAL_SENDMSG)(fd, msg, flags)
+}
+
+/// Check the explicit destination of a datagram against the DNS resolver
+///
+/// # Safety
+/// `dest` must be null or a valid socket address
+unsafe fn allow_destination(dest: *const sockaddr, via: &str) -> bool {
+    sockaddr_socket(dest).is_none_or(|peer| dns::allow_peer(peer, via))
+}
+
+/// Check an outgoing datagram for a DNS query carrying protected values
+///
+/// # Safety
+/// `buf` must be null or valid for reads of `len` bytes, and `dest` must be
+/// null or a valid socket address
+unsafe fn allow_query(
+    fd: c_int,
+    buf: *const c_void,
+    len: size_t,
+    dest: *const sockaddr,
+    via: &str,
+) -> bool {
+    if !dns::scanning() || buf.is_null() || len == 0 {
+        return true;
+    }
+    let data = std::slice::from_raw_parts(buf as *const u8, len);
+    dns::allow_datagram(
+        data,
+        || sockaddr_socket(dest).or_else(|| socket_peer(fd)),
+        via,
+    )
+}
+
+/// allow_query for the gathered buffers of a sendmsg() message
+///
+/// # Safety
+/// `msg` must satisfy the requirements of sendmsg(2)
+unsafe fn allow_message_query(fd: c_int, msg: &msghdr, dest: *const sockaddr) -> bool {
+    if !dns::scanning() || msg.msg_iov.is_null() {
+        return true;
+    }
+    let mut data = Vec::new();
+    // msg_iovlen is an int on some platforms
+    for iov in std::slice::from_raw_parts(msg.msg_iov, msg.msg_iovlen as _) {
+        if !iov.iov_base.is_null() {
+            data.extend_from_slice(std::slice::from_raw_parts(
+                iov.iov_base as *const u8,
+                iov.iov_len,
+            ));
+        }
+    }
+    allow_query(fd, data.as_ptr().cast(), data.len(), dest, "sendmsg")
+}
+
+/// Intercepted OpenSSL SSL_write function
+///
+/// Sees the plaintext of TLS connections made through a dynamically linked
+/// OpenSSL. A blocked write reports failure (-1) to the caller.
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of SSL_write(3).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn SSL_write(ssl: *mut c_void, buf: *const c_void, num: c_int) -> c_int {
+    if scanning() && num > 0 {
+        let fd = (*REAL_SSL_GET_FD)(ssl);
+        if fd >= 0 && !allow_outgoing(fd, buf, num as size_t, std::ptr::null(), "SSL_write") {
+            return -1;
+        }
+    }
+    (*REAL_SSL_WRITE)(ssl, buf, num)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_egress_policy_parse() {
+        assert_eq!(EgressPolicy::parse("LOG"), Some(EgressPolicy::Log));
+        assert_eq!(EgressPolicy::parse(" block "), Some(EgressPolicy::Block));
+        assert_eq!(EgressPolicy::parse("Kill"), Some(EgressPolicy::Kill));
+        assert_eq!(EgressPolicy::parse("off"), Some(EgressPolicy::Off));
+        assert_eq!(EgressPolicy::parse("drop"), None);
+    }
+
+    #[test]
+    fn test_find_secret() {
+        *SECRETS.write().unwrap() = vec![("GITHUB_TOKEN".to_string(), b"ghp_testsecret".to_vec())];
+        assert_eq!(
+            find_secret(b"POST / HTTP/1.1\r\n\r\ntoken=ghp_testsecret"),
+            Some("GITHUB_TOKEN".to_string())
+        );
+        assert_eq!(find_secret(b"GET / HTTP/1.1"), None);
+        SECRETS.write().unwrap().clear();
+    }
+
+    #[test]
+    fn test_redact_spans() {
+        let data = b"GITHUB_TOKEN=ghp_testsecret\nsecond ghp_testsecret\n";
+        let spans = find_known_spans(data, &[b"ghp_testsecret"]);
+        assert_eq!(
+            redact_spans(data, &spans),
+            b"GITHUB_TOKEN=***\nsecond ***\n".to_vec()
+        );
+        assert_eq!(redact_spans(b"plain", &[]), b"plain".to_vec());
+    }
+}
@@ -0,0 +1,357 @@
+//! Direct access to the process environment block
+//!
+//! These helpers walk the `environ` array themselves instead of going through
+//! libc, so they can inspect and rewrite entries in place.
+
+#[cfg(target_os = "macos")]
+use crate::macos::is_writable;
+use crate::sys;
+use libc::{c_char, c_void};
+use std::ffi::CStr;
+
+// External declaration of the environ pointer
+// This is a POSIX standard global that points to the process's environment
+#[cfg(not(target_os = "macos"))]
+extern "C" {
+    static mut environ: *mut *mut c_char;
+}
+
+/// Address of the environ pointer
+///
+/// On macOS environ is defined by the executable and cannot be linked from a
+/// shared library; dyld hands out its address through _NSGetEnviron.
+unsafe fn environ_ptr() -> *mut *mut *mut c_char {
+    #[cfg(target_os = "macos")]
+    return libc::_NSGetEnviron();
+    #[cfg(not(target_os = "macos"))]
+    return std::ptr::addr_of_mut!(environ);
+}
+
+/// Character written over masked values
+pub(crate) const MASK_BYTE: u8 = b'*';
+
+/// Value of the entries that stand in for scrubbed variables in placeholder
+/// mode
+pub(crate) const PROTECTED_PLACEHOLDER: &str = "***AWF-PROTECTED***";
+
+/// The current environment array
+///
+/// # Safety
+/// Must not race with concurrent modification of the environment
+pub(crate) unsafe fn current() -> *mut *mut c_char {
+    *environ_ptr()
+}
+
+/// Point environ at `envp`, returning the previous array
+///
+/// # Safety
+/// `envp` must be a null-terminated array of valid C strings that outlives its
+/// use as the environment. Must not race with concurrent modification of the
+/// environment
+pub(crate) unsafe fn replace(envp: *mut *mut c_char) -> *mut *mut c_char {
+    std::mem::replace(&mut *environ_ptr(), envp)
+}
+
+/// Find the `NAME=value` entry for `name` in the process environment
+///
+/// Returns None if environ is null or the variable is not present.
+///
+/// # Safety
+/// Must not race with concurrent modification of the environment
+pub(crate) unsafe fn find_entry(name: &[u8]) -> Option<*mut c_char> {
+    find_entry_in(current(), name)
+}
+
+/// Find the `NAME=value` entry for `name` in the environment array `envp`
+///
+/// Neither allocates nor locks, so it is safe before the library is
+/// initialized (see the early module).
+///
+/// # Safety
+/// `envp` must be null or a null-terminated array of valid C strings
+pub(crate) unsafe fn find_entry_in(envp: *const *mut c_char, name: &[u8]) -> Option<*mut c_char> {
+    find_slot_in(envp, name).map(|slot| *slot)
+}
+
+/// Find the slot of `envp` holding the entry for `name`
+///
+/// # Safety
+/// `envp` must be null or a null-terminated array of valid C strings
+unsafe fn find_slot_in(envp: *const *mut c_char, name: &[u8]) -> Option<*const *mut c_char> {
+    let mut env_ptr = envp;
+    if env_ptr.is_null() {
+        return None;
+    }
+
+    while !(*env_ptr).is_null() {
+        let env_bytes = CStr::from_ptr(*env_ptr).to_bytes();
+        if entry_matches(env_bytes, name) {
+            return Some(env_ptr);
+        }
+        env_ptr = env_ptr.add(1);
+    }
+
+    None
+}
+
+/// Check whether an environ entry (`NAME=value`) belongs to `name`
+fn entry_matches(entry: &[u8], name: &[u8]) -> bool {
+    entry.len() > name.len() && entry.starts_with(name) && entry[name.len()] == b'='
+}
+
+/// Overwrite the value bytes of `name`'s environ entry with `*`
+///
+/// The variable stays present with a value of the same length, for tools that
+/// break when a variable disappears. Returns false if the variable was not found
+/// or its string is not writable (e.g. a string literal passed to putenv).
+///
+/// # Safety
+/// Must not race with concurrent modification of the environment
+pub(crate) unsafe fn mask_value(name: &[u8]) -> bool {
+    let Some(entry) = find_entry(name) else {
+        return false;
+    };
+
+    let Some((value, len)) = writable_value(entry, name) else {
+        return false;
+    };
+    std::ptr::write_bytes(value.cast::<u8>(), MASK_BYTE, len);
+    true
+}
+
+/// Replace `name`'s environ entry with `NAME=***AWF-PROTECTED***`
+///
+/// The variable stays present for programs that walk environ, such as
+/// printenv, while the value bytes of the original string are zeroed. The
+/// new string is never freed, as putenv() strings are not. Returns false if
+/// the variable was not found or the original string is not writable (it is
+/// replaced anyway).
+///
+/// # Safety
+/// Must not race with concurrent modification of the environment
+pub(crate) unsafe fn replace_with_placeholder(name: &[u8]) -> bool {
+    let Some(slot) = find_slot_in(current(), name) else {
+        return false;
+    };
+    let zeroed = zero_value(*slot, name);
+    let entry = [name, b"=", PROTECTED_PLACEHOLDER.as_bytes(), b"\0"].concat();
+    *slot.cast_mut() = Box::leak(entry.into_boxed_slice()).as_mut_ptr().cast();
+    zeroed
+}
+
+/// Zero the value bytes of an environ entry string
+///
+/// unsetenv() only removes the pointer from environ; the `NAME=value` string
+/// itself stays in memory where core dumps or memory scans can find it. The
+/// entry must be zeroed before it is unset: musl's unsetenv frees the
+/// strings that setenv allocated. Returns false if the string is not writable.
+///
+/// # Safety
+/// `entry` must point to a valid null-terminated `NAME=value` string for `name`
+pub(crate) unsafe fn zero_value(entry: *mut c_char, name: &[u8]) -> bool {
+    let Some((value, len)) = writable_value(entry, name) else {
+        return false;
+    };
+    sys::explicit_bzero(value as *mut c_void, len);
+    true
+}
+
+/// Zero the value of every entry of `envp` whose name satisfies `wipe`,
+/// returning the number of entries zeroed
+///
+/// Async-signal-safe: unlike zero_value, it does not check that the strings
+/// are writable, so a read-only string faults.
+///
+/// # Safety
+/// `envp` must be null or a null-terminated array of valid C strings. Only for
+/// a process that is about to die
+#[cfg(not(feature = "minimal"))]
+pub(crate) unsafe fn zero_values_unchecked(
+    envp: *mut *mut c_char,
+    wipe: impl Fn(&[u8]) -> bool,
+) -> usize {
+    let mut zeroed = 0;
+    let mut env_ptr = envp;
+    while !env_ptr.is_null() && !(*env_ptr).is_null() {
+        let entry = *env_ptr;
+        let bytes = CStr::from_ptr(entry).to_bytes();
+        if let Some(eq) = bytes.iter().position(|&byte| byte == b'=') {
+            if wipe(&bytes[..eq]) {
+                let len = bytes.len() - eq - 1;
+                sys::explicit_bzero(entry.add(eq + 1).cast(), len);
+                zeroed += 1;
+            }
+        }
+        env_ptr = env_ptr.add(1);
+    }
+    zeroed
+}
+
+/// Locate the value bytes of an entry, if they can safely be written
+///
+/// Returns the value pointer and length (excluding the terminator).
+///
+/// # Safety
+/// `entry` must point to a valid null-terminated `NAME=value` string for `name`
+unsafe fn writable_value(entry: *mut c_char, name: &[u8]) -> Option<(*mut c_char, usize)> {
+    let value_start = name.len() + 1;
+    let len = libc::strlen(entry);
+    if len <= value_start {
+        return Some((entry.add(len), 0));
+    }
+
+    let value = entry.add(value_start);
+    let value_len = len - value_start;
+    if !is_writable(value as usize, value_len) {
+        return None;
+    }
+    Some((value, value_len))
+}
+
+/// Check whether an address range lies in a single writable mapping
+///
+/// Environment strings normally live on the initial stack or in setenv()'s
+/// heap allocations, but putenv() accepts caller-owned memory, which may be
+/// read-only. Writing there would crash the process, so the mapping is checked
+/// against /proc/self/maps first (the Mach VM regions on macOS). Returns false
+/// if the maps cannot be read.
+#[cfg(not(target_os = "macos"))]
+fn is_writable(addr: usize, len: usize) -> bool {
+    let Ok(maps) = std::fs::read_to_string("/proc/self/maps") else {
+        return false;
+    };
+    maps.lines()
+        .any(|line| mapping_covers_writable(line, addr, addr + len))
+}
+
+/// Check whether a /proc/self/maps line is a writable mapping covering [start, end)
+#[cfg(not(target_os = "macos"))]
+fn mapping_covers_writable(line: &str, start: usize, end: usize) -> bool {
+    let mut fields = line.split_whitespace();
+    let (Some(range), Some(perms)) = (fields.next(), fields.next()) else {
+        return false;
+    };
+    let Some((low, high)) = range.split_once('-') else {
+        return false;
+    };
+    let (Ok(low), Ok(high)) = (
+        usize::from_str_radix(low, 16),
+        usize::from_str_radix(high, 16),
+    ) else {
+        return false;
+    };
+
+    low <= start && end <= high && perms.as_bytes().get(1) == Some(&b'w')
+}
+
+/// Check whether an environ entry's value is the placeholder
+pub(crate) fn is_placeholder(entry: &CStr, name: &[u8]) -> bool {
+    &entry.to_bytes()[name.len() + 1..] == PROTECTED_PLACEHOLDER.as_bytes()
+}
+
+/// Check whether an environ entry's value consists only of mask characters
+pub(crate) fn is_masked(entry: &CStr, name: &[u8]) -> bool {
+    entry.to_bytes()[name.len() + 1..]
+        .iter()
+        .all(|&byte| byte == MASK_BYTE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_matches() {
+        assert!(entry_matches(b"GITHUB_TOKEN=ghp_x", b"GITHUB_TOKEN"));
+        assert!(entry_matches(b"GITHUB_TOKEN=", b"GITHUB_TOKEN"));
+        assert!(!entry_matches(b"GITHUB_TOKEN_X=ghp_x", b"GITHUB_TOKEN"));
+        assert!(!entry_matches(b"GITHUB_TOKEN", b"GITHUB_TOKEN"));
+    }
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn test_mapping_covers_writable() {
+        let line = "7ffd1000-7ffd3000 rw-p 00000000 00:00 0                          [stack]";
+        assert!(mapping_covers_writable(line, 0x7ffd1000, 0x7ffd2000));
+        assert!(!mapping_covers_writable(line, 0x7ffd2fff, 0x7ffd3001));
+
+        let line =
+            "55d0a000-55d0b000 r--p 00000000 fd:01 1234                       /usr/bin/agent";
+        assert!(!mapping_covers_writable(line, 0x55d0a000, 0x55d0a010));
+        assert!(!mapping_covers_writable("garbage", 0, 1));
+    }
+
+    #[test]
+    #[cfg(not(feature = "minimal"))]
+    fn test_zero_values_unchecked() {
+        let mut token = *b"GITHUB_TOKEN=ghp_secret\0";
+        let mut path = *b"PATH=/usr/bin\0";
+        let mut envp = [
+            token.as_mut_ptr() as *mut c_char,
+            path.as_mut_ptr() as *mut c_char,
+            std::ptr::null_mut(),
+        ];
+
+        let zeroed =
+            unsafe { zero_values_unchecked(envp.as_mut_ptr(), |name| name == b"GITHUB_TOKEN") };
+        assert_eq!(zeroed, 1);
+        assert!(token[13..].iter().all(|&byte| byte == 0));
+        assert_eq!(&path, b"PATH=/usr/bin\0");
+        assert_eq!(
+            unsafe { zero_values_unchecked(std::ptr::null_mut(), |_| true) },
+            0
+        );
+    }
+
+    #[test]
+    fn test_zero_value() {
+        let mut entry = *b"GITHUB_TOKEN=ghp_secret\0";
+        let entry_ptr = entry.as_mut_ptr() as *mut c_char;
+
+        assert!(unsafe { zero_value(entry_ptr, b"GITHUB_TOKEN") });
+        assert_eq!(&entry[..13], b"GITHUB_TOKEN=");
+        assert!(entry[13..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_zero_value_skips_read_only_strings() {
+        static ENTRY: &[u8] = b"GITHUB_TOKEN=ghp_secret\0";
+        let entry_ptr = ENTRY.as_ptr() as *mut c_char;
+
+        assert!(!unsafe { zero_value(entry_ptr, b"GITHUB_TOKEN") });
+        assert_eq!(ENTRY, b"GITHUB_TOKEN=ghp_secret\0");
+    }
+
+    #[test]
+    fn test_find_slot_in() {
+        let mut token = *b"GITHUB_TOKEN=ghp_secret\0";
+        let mut path = *b"PATH=/usr/bin\0";
+        let envp = [
+            path.as_mut_ptr() as *mut c_char,
+            token.as_mut_ptr() as *mut c_char,
+            std::ptr::null_mut(),
+        ];
+        unsafe {
+            assert_eq!(
+                find_slot_in(envp.as_ptr(), b"GITHUB_TOKEN"),
+                Some(envp.as_ptr().add(1))
+            );
+            assert_eq!(find_slot_in(envp.as_ptr(), b"GITHUB"), None);
+            assert_eq!(find_slot_in(std::ptr::null(), b"PATH"), None);
+        }
+    }
+
+    #[test]
+    fn test_is_placeholder() {
+        assert!(is_placeholder(c"TOKEN=***AWF-PROTECTED***", b"TOKEN"));
+        assert!(!is_placeholder(c"TOKEN=****", b"TOKEN"));
+        assert!(!is_placeholder(c"TOKEN=", b"TOKEN"));
+    }
+
+    #[test]
+    fn test_is_masked() {
+        assert!(is_masked(c"TOKEN=****", b"TOKEN"));
+        assert!(is_masked(c"TOKEN=", b"TOKEN"));
+        assert!(!is_masked(c"TOKEN=**x*", b"TOKEN"));
+    }
+}
@@ -0,0 +1,402 @@
+//! Environment variable names as raw bytes
+//!
+//! POSIX only rules out `=` and NUL in variable names: any other byte,
+//! including invalid UTF-8, may appear. Protected, aliased and denied names
+//! are therefore read from the configuration, stored and compared as bytes,
+//! so a token with unusual bytes in its name is matched exactly like any
+//! other. Log lines and reports show names lossily (invalid bytes as U+FFFD).
+
+use std::borrow::{Borrow, Cow};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fmt;
+use std::ops::Deref;
+
+/// An environment variable name
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub(crate) struct EnvName(Box<[u8]>);
+
+impl EnvName {
+    pub(crate) fn new(name: impl AsRef<[u8]>) -> Self {
+        EnvName(name.as_ref().into())
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The name for display, with invalid UTF-8 replaced
+    pub(crate) fn lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+
+    /// The name as a C string (names never contain NUL once parsed from the
+    /// configuration or a C string; None otherwise)
+    pub(crate) fn to_cstring(&self) -> Option<CString> {
+        CString::new(self.as_bytes()).ok()
+    }
+}
+
+impl Deref for EnvName {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for EnvName {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Borrow<[u8]> for EnvName {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&str> for EnvName {
+    fn from(name: &str) -> Self {
+        EnvName::new(name)
+    }
+}
+
+impl PartialEq<[u8]> for EnvName {
+    fn eq(&self, other: &[u8]) -> bool {
+        *self.0 == *other
+    }
+}
+
+impl PartialEq<&[u8]> for EnvName {
+    fn eq(&self, other: &&[u8]) -> bool {
+        *self.0 == **other
+    }
+}
+
+impl PartialEq<str> for EnvName {
+    fn eq(&self, other: &str) -> bool {
+        *self.0 == *other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for EnvName {
+    fn eq(&self, other: &&str) -> bool {
+        *self.0 == *other.as_bytes()
+    }
+}
+
+impl fmt::Display for EnvName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.lossy())
+    }
+}
+
+impl fmt::Debug for EnvName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.lossy(), f)
+    }
+}
+
+/// An immutable set of names, for the lock-free getenv fast path
+#[derive(Debug, Default)]
+pub(crate) struct NameSet(NameMap);
+
+impl NameSet {
+    pub(crate) fn new(names: impl IntoIterator<Item = EnvName>) -> Self {
+        NameSet(NameMap::new(names.into_iter().map(|name| (name, 0))))
+    }
+
+    pub(crate) fn contains(&self, name: &[u8]) -> bool {
+        self.0.get(name).is_some()
+    }
+}
+
+/// An immutable map from names to indexes, for the lock-free getenv paths
+///
+/// Lookups neither allocate nor lock. A bitmap of first bytes and the
+/// longest name rule out most names at once. The rest are found through a
+/// perfect hash built with the map: names are hashed once into buckets of a
+/// few names each, and every bucket is given a seed that, mixed into the
+/// hash, sends its names to free slots. A lookup therefore hashes the name
+/// once and compares it with at most one entry, however many names are
+/// configured.
+#[derive(Debug, Default)]
+pub(crate) struct NameMap {
+    first_bytes: [u64; 4],
+    max_len: usize,
+    /// Seed of the name hash, changed if two names hash alike
+    key: u64,
+    /// Seed of each bucket
+    seeds: Box<[u64]>,
+    /// Index in `entries` plus one of each slot, or 0 if the slot is free
+    slots: Box<[u32]>,
+    entries: Box<[(EnvName, usize)]>,
+}
+
+/// Multiplier of the hash (2^64 divided by the golden ratio)
+const K: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Seeds tried for a bucket before another key is tried
+const MAX_SEED: u64 = 1 << 16;
+
+/// Hash `name` eight bytes at a time, starting from `key`
+fn hash(name: &[u8], key: u64) -> u64 {
+    let mut hash = key ^ name.len() as u64;
+    let mut words = name.chunks_exact(8);
+    for word in &mut words {
+        let word = u64::from_le_bytes(word.try_into().unwrap_or_default());
+        hash = (hash ^ word).wrapping_mul(K).rotate_left(29);
+    }
+    let tail = words
+        .remainder()
+        .iter()
+        .rev()
+        .fold(0, |tail, &byte| tail << 8 | u64::from(byte));
+    hash = (hash ^ tail).wrapping_mul(K);
+    hash ^ (hash >> 32)
+}
+
+/// Slot of a name with hash `hash` in a bucket with seed `seed`
+fn slot(hash: u64, seed: u64, slot_count: usize) -> usize {
+    let mixed = (hash ^ seed.wrapping_mul(K)).wrapping_mul(K);
+    (mixed >> 32) as usize & (slot_count - 1)
+}
+
+impl NameMap {
+    /// Entries with an empty name are skipped; of a repeated name, the first
+    /// entry is kept
+    pub(crate) fn new(entries: impl IntoIterator<Item = (EnvName, usize)>) -> Self {
+        let mut seen = HashSet::new();
+        let entries: Vec<(EnvName, usize)> = entries
+            .into_iter()
+            .filter(|(name, _)| !name.is_empty() && seen.insert(name.clone()))
+            .collect();
+
+        let mut first_bytes = [0; 4];
+        for (name, _) in &entries {
+            let byte = name[0] as usize;
+            first_bytes[byte / 64] |= 1 << (byte % 64);
+        }
+
+        // At most half the slots are used, so seeds are found within a few
+        // tries; another key is only needed if two names hash alike
+        let (key, (seeds, slots)) = (0..)
+            .find_map(|key| Some((key, place(&entries, key)?)))
+            .unwrap_or_default();
+        NameMap {
+            first_bytes,
+            max_len: entries
+                .iter()
+                .map(|(name, _)| name.len())
+                .max()
+                .unwrap_or(0),
+            key,
+            seeds,
+            slots,
+            entries: entries.into_boxed_slice(),
+        }
+    }
+
+    pub(crate) fn get(&self, name: &[u8]) -> Option<usize> {
+        let &byte = name.first()?;
+        if name.len() > self.max_len
+            || self.first_bytes[byte as usize / 64] & (1 << (byte % 64)) == 0
+        {
+            return None;
+        }
+        let hash = hash(name, self.key);
+        let seed = self.seeds[hash as usize & (self.seeds.len() - 1)];
+        let slot = self.slots[slot(hash, seed, self.slots.len())];
+        let (entry, value) = self.entries.get((slot as usize).checked_sub(1)?)?;
+        (*entry == *name).then_some(*value)
+    }
+}
+
+/// Seeds of the buckets and contents of the slots of a NameMap
+type Table = (Box<[u64]>, Box<[u32]>);
+
+/// Find a seed for every bucket such that all names, hashed with `key`,
+/// land in distinct slots
+fn place(entries: &[(EnvName, usize)], key: u64) -> Option<Table> {
+    let bucket_count = entries.len().div_ceil(4).next_power_of_two();
+    let slot_count = (entries.len() * 2).next_power_of_two();
+    let mut buckets: Vec<Vec<u64>> = vec![Vec::new(); bucket_count];
+    let mut indexes: Vec<Vec<usize>> = vec![Vec::new(); bucket_count];
+    for (index, (name, _)) in entries.iter().enumerate() {
+        let hash = hash(name, key);
+        buckets[hash as usize & (bucket_count - 1)].push(hash);
+        indexes[hash as usize & (bucket_count - 1)].push(index);
+    }
+    // The fullest buckets are placed first, while most slots are free
+    let mut order: Vec<usize> = (0..bucket_count).collect();
+    order.sort_by_key(|&bucket| Reverse(buckets[bucket].len()));
+
+    let mut seeds = vec![0; bucket_count];
+    let mut slots = vec![0; slot_count];
+    let mut taken = Vec::new();
+    for bucket in order {
+        let seed = (0..MAX_SEED).find(|&seed| {
+            taken.clear();
+            buckets[bucket].iter().all(|&hash| {
+                let slot = slot(hash, seed, slot_count);
+                let free = slots[slot] == 0 && !taken.contains(&slot);
+                taken.push(slot);
+                free
+            })
+        })?;
+        seeds[bucket] = seed;
+        for (&slot, &index) in taken.iter().zip(&indexes[bucket]) {
+            slots[slot] = index as u32 + 1;
+        }
+    }
+    Some((seeds.into_boxed_slice(), slots.into_boxed_slice()))
+}
+
+/// Split a comma-separated configuration list, trimming ASCII whitespace
+/// around entries and skipping empty ones
+pub(crate) fn split_list(config: &[u8]) -> impl Iterator<Item = &[u8]> {
+    config
+        .split(|&byte| byte == b',')
+        .map(<[u8]>::trim_ascii)
+        .filter(|entry| !entry.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_env_name() {
+        let name = EnvName::new(b"TOKEN_\xff\xfe");
+        assert_eq!(name.as_bytes(), b"TOKEN_\xff\xfe");
+        assert_eq!(name.to_string(), "TOKEN_\u{fffd}\u{fffd}");
+        assert_ne!(name, EnvName::new(b"TOKEN_\xfe\xff"));
+        assert_eq!(EnvName::from("GITHUB_TOKEN"), "GITHUB_TOKEN");
+        assert!(EnvName::new(b"A\0B").to_cstring().is_none());
+
+        // Maps keyed by name are looked up with the bytes of a C string
+        let map = HashMap::from([(name.clone(), 1)]);
+        assert_eq!(map.get(&b"TOKEN_\xff\xfe"[..]), Some(&1));
+        assert_eq!(map.get(&b"TOKEN_\xfe\xff"[..]), None);
+    }
+
+    #[test]
+    fn test_name_set() {
+        let names = ["GITHUB_TOKEN", "GH_TOKEN", "HTTPS_PROXY", "GH_TOKEN", ""];
+        let set = NameSet::new(
+            names
+                .into_iter()
+                .map(EnvName::from)
+                .chain([EnvName::new(b"\xff")]),
+        );
+        assert!(set.contains(b"GITHUB_TOKEN"));
+        assert!(set.contains(b"GH_TOKEN"));
+        assert!(set.contains(b"\xff"));
+        // Same first byte, but not in the set
+        assert!(!set.contains(b"GITHUB_ACTOR"));
+        assert!(!set.contains(b"GITHUB_TOKEN_"));
+        assert!(!set.contains(b"PATH"));
+        assert!(!set.contains(b""));
+        assert!(!NameSet::default().contains(b"GITHUB_TOKEN"));
+    }
+
+    #[test]
+    fn test_name_map() {
+        let map = NameMap::new([
+            (EnvName::from("GITHUB_TOKEN"), 0),
+            (EnvName::from("GH_TOKEN"), 0),
+            (EnvName::from("OPENAI_API_KEY"), 1),
+            (EnvName::from("OPENAI_API_KEY"), 2),
+        ]);
+        assert_eq!(map.get(b"GITHUB_TOKEN"), Some(0));
+        assert_eq!(map.get(b"GH_TOKEN"), Some(0));
+        assert_eq!(map.get(b"OPENAI_API_KEY"), Some(1));
+        assert_eq!(map.get(b"OPENAI_API_KE"), None);
+        assert_eq!(NameMap::default().get(b"GITHUB_TOKEN"), None);
+
+        // Every name of a large map is found, and near misses are not
+        let names: Vec<EnvName> = (0..1000)
+            .map(|index| EnvName::new(format!("TOKEN_{}", index)))
+            .collect();
+        let map = NameMap::new(names.iter().cloned().zip(0..));
+        for (index, name) in names.iter().enumerate() {
+            assert_eq!(map.get(name), Some(index));
+        }
+        assert_eq!(map.get(b"TOKEN_1000"), None);
+        assert_eq!(map.get(b"TOKEN_"), None);
+    }
+
+    /// Lookup cost of the NameMap against the binary search of a sorted
+    /// list (default names) and the linear scan (many names) it replaced.
+    /// Run with `cargo test --release --lib bench_name_lookup -- --ignored
+    /// --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_name_lookup() {
+        use crate::detect::{preset, DEFAULT_PRESETS};
+        use std::hint::black_box;
+        use std::time::Instant;
+
+        const ITERATIONS: u32 = 20_000_000;
+        let time = |lookup: &dyn Fn(&[u8]) -> bool, name: &[u8]| {
+            let start = Instant::now();
+            for _ in 0..ITERATIONS {
+                black_box(lookup(black_box(name)));
+            }
+            start.elapsed().as_nanos() as f64 / f64::from(ITERATIONS)
+        };
+
+        let mut names: Vec<EnvName> = DEFAULT_PRESETS
+            .iter()
+            .filter_map(|name| preset(name))
+            .flat_map(|preset| preset.tokens.iter().map(|&token| EnvName::from(token)))
+            .collect();
+        names.sort();
+        let map = NameMap::new(names.iter().cloned().zip(0..));
+        let sorted = |name: &[u8]| {
+            names
+                .binary_search_by(|entry| entry.as_bytes().cmp(name))
+                .is_ok()
+        };
+        let hashed = |name: &[u8]| map.get(name).is_some();
+        println!("default names      binary search  perfect hash");
+        for name in ["GITHUB_ACTOR", "GITHUB_TOKEN"] {
+            let name = name.as_bytes();
+            assert_eq!(sorted(name), hashed(name));
+            println!(
+                "{:<18} {:>10.1} ns {:>10.1} ns",
+                String::from_utf8_lossy(name),
+                time(&sorted, name),
+                time(&hashed, name)
+            );
+        }
+
+        let names: Vec<EnvName> = (0..100)
+            .map(|index| EnvName::new(format!("TOKEN_{}", index)))
+            .collect();
+        let map = NameMap::new(names.iter().cloned().zip(0..));
+        let scanned = |name: &[u8]| names.iter().any(|entry| *entry == *name);
+        let hashed = |name: &[u8]| map.get(name).is_some();
+        println!("100 names          linear scan    perfect hash");
+        for (case, name) in [("hit", "TOKEN_99"), ("miss", "TOKEN_9X")] {
+            let name = name.as_bytes();
+            assert_eq!(scanned(name), hashed(name));
+            println!(
+                "{:<18} {:>10.1} ns {:>10.1} ns",
+                case,
+                time(&scanned, name),
+                time(&hashed, name)
+            );
+        }
+    }
+
+    #[test]
+    fn test_split_list() {
+        let entries: Vec<&[u8]> = split_list(b" A , ,B\xff,,").collect();
+        assert_eq!(entries, vec![&b"A"[..], &b"B\xff"[..]]);
+    }
+}
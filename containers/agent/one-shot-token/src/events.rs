@@ -0,0 +1,137 @@
+//! Real-time event stream to the host supervisor (AWF_ONE_SHOT_EVENT_SOCKET)
+//!
+//! Logs are reviewed after the fact. With AWF_ONE_SHOT_EVENT_SOCKET set to the
+//! path of a Unix datagram socket, every token access, every audit event
+//! (violations and other policy decisions) and the result of every scrub is
+//! also sent to that socket as it happens, one JSON object per datagram, so
+//! the supervisor can act on them live. Datagrams have the fields of JSON log
+//! lines (see the log module):
+//!
+//! - `access` (level `info`): a protected token was served, with `via`
+//!   (`getenv` or `secure_getenv`), `reads` (reads served so far), `first`
+//!   (`true` for the read that cached the value) and `name` when the token was
+//!   read through an alias
+//! - audit events (level `audit`) with their details as fields
+//! - `unset_verification` (level `info`, `warning` if exposed): whether a
+//!   scrubbed token is `cleared`, `masked` or still `exposed` in environ
+//!
+//! Events are sent regardless of debug logging. Sends never block: if the
+//! supervisor is not reading fast enough or not listening, events are kept
+//! and sent with the next one, up to a limit (see the sink module), rather
+//! than stalling the program. awf-token-collector reads the
+//! events of every process into one report of the run. The same events are
+//! exported to an OpenTelemetry collector, if one is configured (see the
+//! otlp module).
+
+use crate::log::{self, Level};
+#[cfg(not(feature = "minimal"))]
+use crate::otlp;
+use crate::sink::{self, Sink};
+use crate::{egress, sys};
+use libc::{c_int, sockaddr_un, socklen_t};
+use std::io;
+use std::sync::OnceLock;
+
+/// The supervisor's socket
+struct Socket {
+    fd: c_int,
+    addr: sockaddr_un,
+    addr_len: socklen_t,
+}
+
+static SOCKET: OnceLock<Socket> = OnceLock::new();
+
+/// Socket address of `path`
+fn socket_addr(path: &str) -> io::Result<(sockaddr_un, socklen_t)> {
+    // SAFETY: sockaddr_un is a plain struct
+    let mut addr: sockaddr_un = unsafe { std::mem::zeroed() };
+    // Leave room for the terminating NUL
+    if path.is_empty() || path.len() >= addr.sun_path.len() || path.contains('\0') {
+        return Err(io::Error::from_raw_os_error(libc::ENAMETOOLONG));
+    }
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (dest, byte) in addr.sun_path.iter_mut().zip(path.bytes()) {
+        *dest = byte as libc::c_char;
+    }
+    let addr_len = std::mem::offset_of!(sockaddr_un, sun_path) + path.len() + 1;
+    Ok((addr, addr_len as socklen_t))
+}
+
+/// Send events to the datagram socket at `path` from now on
+pub(crate) fn install(path: &str) -> io::Result<()> {
+    let (addr, addr_len) = socket_addr(path)?;
+    let fd = sys::socket_cloexec(libc::AF_UNIX, libc::SOCK_DGRAM);
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if SOCKET.set(Socket { fd, addr, addr_len }).is_err() {
+        // SAFETY: fd was just created and is not shared
+        unsafe { libc::close(fd) };
+    }
+    Ok(())
+}
+
+/// Whether events are being sent or exported
+pub(crate) fn enabled() -> bool {
+    #[cfg(not(feature = "minimal"))]
+    if otlp::enabled() {
+        return true;
+    }
+    SOCKET.get().is_some()
+}
+
+/// Send an event to the supervisor and the OTLP collector, if configured
+pub(crate) fn send(level: Level, event: &str, token: Option<&str>, fields: &[(String, String)]) {
+    #[cfg(not(feature = "minimal"))]
+    otlp::send(level, event, token, fields);
+    let Some(socket) = SOCKET.get() else {
+        return;
+    };
+    let datagram = log::json_line(level, event, token, fields);
+    sink::deliver(Sink::Events, &datagram, |datagram| {
+        // SAFETY: datagram and addr are valid for their lengths
+        let sent = unsafe {
+            egress::call_real_sendto(
+                socket.fd,
+                datagram.as_ptr().cast(),
+                datagram.len(),
+                libc::MSG_DONTWAIT | libc::MSG_NOSIGNAL,
+                &socket.addr as *const sockaddr_un as *const libc::sockaddr,
+                socket.addr_len,
+            )
+        };
+        sent >= 0
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn test_send() {
+        let path = std::env::temp_dir().join(format!("awf-events-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let supervisor = UnixDatagram::bind(&path).unwrap();
+        install(path.to_str().unwrap()).unwrap();
+        assert!(enabled());
+
+        send(
+            Level::Info,
+            "access",
+            Some("GITHUB_TOKEN"),
+            &[("reads".to_string(), "1".to_string())],
+        );
+        let mut buf = [0u8; 512];
+        let len = supervisor.recv(&mut buf).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let datagram = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(datagram.starts_with("{\"time\":"));
+        assert!(datagram.ends_with(
+            "\"level\":\"info\",\"event\":\"access\",\"token\":\"GITHUB_TOKEN\",\"reads\":\"1\"}"
+        ));
+        assert!(socket_addr(&"x".repeat(200)).is_err());
+    }
+}
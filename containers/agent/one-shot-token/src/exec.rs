@@ -0,0 +1,844 @@
+//! Scrubbing of protected secrets from the environment of exec'd programs
+//!
+//! execve, execvpe, posix_spawn and posix_spawnp take the child's environment as an explicit envp array,
+//! which programs often build from a snapshot taken before the library
+//! scrubbed environ (Python's os.environ, Node's process.env). Without
+//! interception, `env | curl -d @-` run this way hands the secret straight to
+//! the child. The outgoing envp is therefore rewritten before the real call:
+//! entries for protected tokens, their aliases and deny-listed names are
+//! removed, and any other entry whose value contains a protected token value
+//! has that value replaced with the redaction placeholder.
+//!
+//! A child exec'd without LD_PRELOAD would run unprotected, so unless
+//! AWF_ONE_SHOT_PROPAGATE=0 the rewritten envp also keeps this library in
+//! LD_PRELOAD and carries the library configuration seen at load. When builds
+//! for several architectures are configured, the one matching the child's
+//! architecture is used (see the multilib module).
+//!
+//! On macOS the library travels in DYLD_INSERT_LIBRARIES instead, and
+//! programs that System Integrity Protection keeps it out of are reported
+//! (see the macos module).
+
+use crate::environ::PROTECTED_PLACEHOLDER;
+use crate::envname::EnvName;
+use crate::multilib::{Arch, Builds, Program};
+use crate::{
+    audit, detect, fork, is_denied_token, lock_state, protected_token_values, read_env_var,
+    resolve_next, Mode, ScrubMode, TokenState, REDACTED_PLACEHOLDER,
+};
+use libc::{c_char, c_int, pid_t, posix_spawn_file_actions_t, posix_spawnattr_t};
+use once_cell::sync::Lazy;
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+type ExecveFn =
+    unsafe extern "C" fn(*const c_char, *const *const c_char, *const *const c_char) -> c_int;
+
+type PosixSpawnFn = unsafe extern "C" fn(
+    *mut pid_t,
+    *const c_char,
+    *const posix_spawn_file_actions_t,
+    *const posix_spawnattr_t,
+    *const *const c_char,
+    *const *const c_char,
+) -> c_int;
+
+/// Cached pointer to the real execve function
+static REAL_EXECVE: Lazy<ExecveFn> = Lazy::new(|| {
+    // SAFETY: execve has the ExecveFn signature
+    unsafe { std::mem::transmute::<*mut libc::c_void, ExecveFn>(resolve_next(c"execve")) }
+});
+
+/// Cached pointer to the real execvpe function
+static REAL_EXECVPE: Lazy<ExecveFn> = Lazy::new(|| {
+    // SAFETY: execvpe has the same signature as execve
+    unsafe { std::mem::transmute::<*mut libc::c_void, ExecveFn>(resolve_next(c"execvpe")) }
+});
+
+/// Cached pointer to the real posix_spawn function
+static REAL_POSIX_SPAWN: Lazy<PosixSpawnFn> = Lazy::new(|| {
+    // SAFETY: posix_spawn has the PosixSpawnFn signature
+    unsafe { std::mem::transmute::<*mut libc::c_void, PosixSpawnFn>(resolve_next(c"posix_spawn")) }
+});
+
+/// Cached pointer to the real posix_spawnp function
+static REAL_POSIX_SPAWNP: Lazy<PosixSpawnFn> = Lazy::new(|| {
+    // SAFETY: posix_spawnp has the same signature as posix_spawn
+    unsafe { std::mem::transmute::<*mut libc::c_void, PosixSpawnFn>(resolve_next(c"posix_spawnp")) }
+});
+
+/// Call the real posix_spawn function, bypassing the interposer
+///
+/// # Safety
+/// Arguments must satisfy the requirements of posix_spawn(3)
+pub(crate) unsafe fn call_real_posix_spawn(
+    pid: *mut pid_t,
+    path: *const c_char,
+    file_actions: *const posix_spawn_file_actions_t,
+    attrp: *const posix_spawnattr_t,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> c_int {
+    (*REAL_POSIX_SPAWN)(pid, path, file_actions, attrp, argv, envp)
+}
+
+/// What happens to a single `NAME=value` entry of a child's environment
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum EntryAction {
+    Keep,
+    Remove,
+    /// Replace the entry with the given bytes (without NUL terminator)
+    Redact(Vec<u8>),
+}
+
+impl EntryAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EntryAction::Keep => "kept",
+            EntryAction::Remove => "removed",
+            EntryAction::Redact(_) => "redacted",
+        }
+    }
+}
+
+/// Names whose entries never reach another process: protected tokens, their
+/// aliases and deny-listed variables
+pub(crate) fn scrubbed_names(state: &TokenState) -> Vec<EnvName> {
+    let mut names: Vec<EnvName> = state.tokens.iter().map(|spec| spec.name.clone()).collect();
+    names.extend(state.aliases.keys().cloned());
+    names.extend(state.deny.iter().cloned());
+    names
+}
+
+/// Decide what to do with one environment entry
+///
+/// Entries named in `removed_names` are dropped; in all other entries each
+/// occurrence of a `known` secret value is replaced with the placeholder.
+pub(crate) fn sanitize_entry(
+    entry: &[u8],
+    removed_names: &[EnvName],
+    known: &[&[u8]],
+) -> EntryAction {
+    let Some(eq) = entry.iter().position(|&b| b == b'=') else {
+        return EntryAction::Keep;
+    };
+    let (name, value) = (&entry[..eq], &entry[eq + 1..]);
+    if removed_names.iter().any(|removed| *removed == *name) {
+        return EntryAction::Remove;
+    }
+
+    let spans = detect::find_known_spans(value, known);
+    if spans.is_empty() {
+        return EntryAction::Keep;
+    }
+    let mut redacted = entry[..=eq].to_vec();
+    let mut last = 0;
+    for span in spans {
+        redacted.extend_from_slice(&value[last..span.start]);
+        redacted.extend_from_slice(REDACTED_PLACEHOLDER.as_bytes());
+        last = span.end;
+    }
+    redacted.extend_from_slice(&value[last..]);
+    EntryAction::Redact(redacted)
+}
+
+/// Replace a protected token's entry with `NAME=***AWF-PROTECTED***`, for
+/// placeholder scrub mode
+///
+/// An entry that already holds the placeholder is kept.
+pub(crate) fn placeholder_entry(entry: &[u8]) -> EntryAction {
+    let (name, value) = split_entry(entry);
+    if value == Some(PROTECTED_PLACEHOLDER.as_bytes()) {
+        return EntryAction::Keep;
+    }
+    EntryAction::Redact([name, b"=", PROTECTED_PLACEHOLDER.as_bytes()].concat())
+}
+
+/// A rewritten environment for a child process
+///
+/// Unchanged entries point into the caller's envp; redacted entries are owned
+/// here, so the ChildEnv must outlive the exec call.
+pub(crate) struct ChildEnv {
+    _owned: Vec<CString>,
+    ptrs: Vec<*const c_char>,
+}
+
+impl ChildEnv {
+    /// Null-terminated envp array to hand to the real exec function
+    pub(crate) fn as_ptr(&self) -> *const *const c_char {
+        self.ptrs.as_ptr()
+    }
+}
+
+/// Library configuration copied into child environments
+pub(crate) const PROPAGATED_CONFIG: &[&str] = &[
+    "AWF_ONE_SHOT_TOKENS",
+    "AWF_ONE_SHOT_PRESETS",
+    "AWF_ONE_SHOT_EXTRA_TOKENS",
+    "AWF_ONE_SHOT_TOKEN_ALIASES",
+    "AWF_ONE_SHOT_DENY_TOKENS",
+    "AWF_ONE_SHOT_DENY_KILL",
+    "AWF_ONE_SHOT_KILL_SIGNAL",
+    "AWF_ONE_SHOT_REQUIRED_ACTION",
+    "AWF_ONE_SHOT_REQUIRED_CHECKED",
+    "AWF_ONE_SHOT_CONFIG",
+    "AWF_ONE_SHOT_POLICY_FILE",
+    "AWF_ONE_SHOT_TOKEN_DIGESTS",
+    "AWF_ONE_SHOT_MODE",
+    "AWF_ONE_SHOT_SCRUB",
+    "AWF_ONE_SHOT_CACHE_BACKEND",
+    "AWF_ONE_SHOT_ARGV_SCRUB",
+    "AWF_ONE_SHOT_EAGER",
+    "AWF_ONE_SHOT_FILE_SECRETS",
+    "AWF_ONE_SHOT_SPLIT_TOKENS",
+    "AWF_ONE_SHOT_BROKER_SOCKET",
+    "AWF_ONE_SHOT_BROKER_UID",
+    "AWF_ONE_SHOT_VAULT_SOCKET",
+    "AWF_ONE_SHOT_VAULT_UID",
+    "AWF_ONE_SHOT_MINT_TOKENS",
+    "AWF_ONE_SHOT_REFRESH_COMMAND",
+    "AWF_ONE_SHOT_SWAP_SOCKET",
+    "AWF_ONE_SHOT_SWAP_UID",
+    "AWF_ONE_SHOT_STEP_FILE",
+    "AWF_ONE_SHOT_STEP_WATCH",
+    "AWF_ONE_SHOT_STEP_SIGNAL",
+    "AWF_ONE_SHOT_PRIVILEGE_WATCH",
+    "AWF_ONE_SHOT_TRACK_CHILDREN",
+    "AWF_ONE_SHOT_CORRELATION",
+    "AWF_ONE_SHOT_TRACE",
+    "AWF_ONE_SHOT_SECRET_NAMESPACE",
+    "AWF_ONE_SHOT_AUTODETECT",
+    "AWF_ONE_SHOT_SECRETS_DIR",
+    "AWF_ONE_SHOT_SECRETS_MAP",
+    "AWF_ONE_SHOT_SECRETS_REVOKE",
+    "AWF_ONE_SHOT_WATCHDOG",
+    "AWF_ONE_SHOT_SIGNALS",
+    "AWF_ONE_SHOT_SIGNAL_CHAIN",
+    "AWF_ONE_SHOT_CRASH_WIPE",
+    "AWF_ONE_SHOT_STRICT_GRACE",
+    "AWF_ONE_SHOT_PROTECT_PROXY",
+    "AWF_ONE_SHOT_PROC_ENVIRON",
+    "AWF_ONE_SHOT_PROC_SNOOP",
+    "AWF_ONE_SHOT_PROC_MEMORY",
+    "AWF_ONE_SHOT_PROC_MEMORY_ALLOW",
+    "AWF_ONE_SHOT_PROFILE",
+    "AWF_ONE_SHOT_CREDENTIAL_FILES",
+    "AWF_ONE_SHOT_DNS_AUDIT",
+    "AWF_ONE_SHOT_DNS_RESOLVER",
+    "AWF_ONE_SHOT_DNS_EXFIL",
+    "AWF_ONE_SHOT_ALLOWED_DOMAINS",
+    "AWF_ONE_SHOT_METADATA_ACCESS",
+    "AWF_ONE_SHOT_EGRESS_SCAN",
+    "AWF_ONE_SHOT_REDACT_OUTPUT",
+    "AWF_ONE_SHOT_FILE_WRITE_SCAN",
+    "AWF_ONE_SHOT_WORKSPACE_GUARD",
+    "AWF_ONE_SHOT_COMMAND_FILE_SCAN",
+    "AWF_ONE_SHOT_CANARIES",
+    "AWF_ONE_SHOT_HONEYPOT",
+    "AWF_ONE_SHOT_ALLOWED_COMMS",
+    "AWF_ONE_SHOT_RATE_LIMIT",
+    "AWF_ONE_SHOT_QUIET_PERIOD",
+    "AWF_ONE_SHOT_PHASE_END_FILE",
+    "AWF_ONE_SHOT_IDLE_TIMEOUT",
+    "AWF_ONE_SHOT_PARENT_POLICY",
+    "AWF_ONE_SHOT_CALLER_POLICY",
+    "AWF_ONE_SHOT_REPORT",
+    "AWF_ONE_SHOT_STEP_SUMMARY",
+    "AWF_ONE_SHOT_STATS_SHM",
+    "AWF_ONE_SHOT_LATENCY_SAMPLE",
+    "AWF_ONE_SHOT_NODUMP",
+    "AWF_ONE_SHOT_NOCORE",
+    "AWF_ONE_SHOT_SECCOMP",
+    "AWF_ONE_SHOT_LANDLOCK",
+    "AWF_ONE_SHOT_PROPAGATE",
+    "AWF_ONE_SHOT_LIB32",
+    "AWF_ONE_SHOT_LIB64",
+    "AWF_ONE_SHOT_LIB_AARCH64",
+    "AWF_ENFORCED_PROXY",
+    "AWF_ENFORCED_NO_PROXY",
+    "AWF_ONE_SHOT_TOKEN_DEBUG",
+    "AWF_ONE_SHOT_LOG_FORMAT",
+    "AWF_ONE_SHOT_LOG_FILE",
+    "AWF_ONE_SHOT_LOG_MAX_SIZE",
+    "AWF_ONE_SHOT_LOG_SYNC",
+    "AWF_ONE_SHOT_LOG_FD",
+    "AWF_ONE_SHOT_SYSLOG",
+    "AWF_ONE_SHOT_EVENT_SOCKET",
+    "AWF_ONE_SHOT_OTLP_ENDPOINT",
+    "AWF_ONE_SHOT_OTLP_HEADERS",
+    "AWF_ONE_SHOT_OTLP_SERVICE",
+    "AWF_ONE_SHOT_SINK_FAILURE",
+    "AWF_ONE_SHOT_AUDIT_CHAIN",
+    "AWF_ONE_SHOT_AUDIT_KEY_FILE",
+    "AWF_ONE_SHOT_DISABLE",
+    "AWF_STEP_ID",
+];
+
+/// Variable the rules of tokens protected at run time are propagated in
+const EXTRA_TOKENS_VAR: &str = "AWF_ONE_SHOT_EXTRA_TOKENS";
+
+/// Variable that makes the dynamic linker load the library
+#[cfg(not(target_os = "macos"))]
+const PRELOAD_VAR: &str = "LD_PRELOAD";
+#[cfg(target_os = "macos")]
+const PRELOAD_VAR: &str = "DYLD_INSERT_LIBRARIES";
+
+/// What a child's environment must contain to stay protected
+/// (AWF_ONE_SHOT_PROPAGATE)
+pub(crate) struct Propagation {
+    /// Path of this library, kept in the child's LD_PRELOAD
+    library: Option<String>,
+    /// Builds for each architecture, used instead of `library` when configured
+    builds: Builds,
+    /// Library configuration as seen at load
+    config: Vec<(String, String)>,
+    /// Rules of the tokens protected through awf_token_protect, added to the
+    /// child's AWF_ONE_SHOT_EXTRA_TOKENS
+    runtime_tokens: Vec<(EnvName, String)>,
+}
+
+impl Propagation {
+    /// Snapshot the library path and configuration of this process
+    pub(crate) fn load() -> Self {
+        let config = PROPAGATED_CONFIG
+            .iter()
+            .filter_map(|name| {
+                let name_cstr = CString::new(*name).ok()?;
+                read_env_var(&name_cstr).map(|value| (name.to_string(), value))
+            })
+            .collect();
+        Self {
+            library: library_path(),
+            builds: Builds::load(),
+            config,
+            runtime_tokens: Vec::new(),
+        }
+    }
+
+    /// Propagate `value` for configuration variable `name` from now on (or
+    /// stop propagating it)
+    pub(crate) fn set_config(&mut self, name: &str, value: Option<&str>) {
+        self.config.retain(|(configured, _)| configured != name);
+        if let Some(value) = value {
+            self.config.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    /// Propagate the rule of a token protected at run time (or stop
+    /// propagating it, for None)
+    pub(crate) fn set_runtime_token(&mut self, name: &EnvName, rule: Option<&str>) {
+        self.runtime_tokens.retain(|(listed, _)| listed != name);
+        if let Some(rule) = rule {
+            self.runtime_tokens.push((name.clone(), rule.to_string()));
+        }
+    }
+
+    /// The configuration to set in children, with the runtime tokens
+    /// appended to AWF_ONE_SHOT_EXTRA_TOKENS
+    fn child_config(&self) -> Vec<(String, String)> {
+        if self.runtime_tokens.is_empty() {
+            return self.config.clone();
+        }
+        let (mut config, extra): (Vec<_>, Vec<_>) = self
+            .config
+            .iter()
+            .cloned()
+            .partition(|(name, _)| name != EXTRA_TOKENS_VAR);
+        let rules: Vec<&str> = extra
+            .iter()
+            .map(|(_, value)| value.as_str())
+            .chain(self.runtime_tokens.iter().map(|(_, rule)| rule.as_str()))
+            .collect();
+        config.push((EXTRA_TOKENS_VAR.to_string(), rules.join(",")));
+        config
+    }
+
+    /// Whether the library in LD_PRELOAD depends on the child's architecture
+    fn per_arch(&self) -> bool {
+        self.builds.configured()
+    }
+
+    /// Variables to set in a child environment, as `(name, value)` pairs
+    ///
+    /// `current` looks a variable up in the child's environment. LD_PRELOAD
+    /// gets the build for the child's `arch` (or this library) prepended if
+    /// it does not already load it, in place of the other builds, and
+    /// configuration variables are set wherever they are missing or differ.
+    fn additions<'a>(
+        &self,
+        arch: Option<Arch>,
+        current: impl Fn(&str) -> Option<&'a [u8]>,
+    ) -> Vec<(String, Vec<u8>)> {
+        let mut additions = Vec::new();
+
+        let chosen = arch.and_then(|arch| self.builds.for_arch(arch));
+        if let Some(library) = chosen.or(self.library.as_deref()) {
+            let replaced: Vec<&str> = self
+                .builds
+                .paths()
+                .chain(self.library.as_deref())
+                .filter(|path| *path != library)
+                .collect();
+            let preload = current(PRELOAD_VAR).filter(|value| !value.is_empty());
+            if let Some(value) = preload_value(preload, library, &replaced) {
+                additions.push((PRELOAD_VAR.to_string(), value));
+            }
+        }
+
+        for (name, value) in self.child_config() {
+            if current(&name) != Some(value.as_bytes()) {
+                additions.push((name, value.into_bytes()));
+            }
+        }
+        additions
+    }
+}
+
+/// Path of the shared object containing this code
+fn library_path() -> Option<String> {
+    // SAFETY: Dl_info is plain data; dladdr fills it in for any address inside
+    // a loaded object
+    let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+    let found = unsafe { libc::dladdr(library_path as *const libc::c_void, &mut info) };
+    if found == 0 || info.dli_fname.is_null() {
+        return None;
+    }
+    // SAFETY: dli_fname is a valid C string owned by the dynamic linker
+    let path = unsafe { CStr::from_ptr(info.dli_fname) };
+    path.to_str().ok().map(str::to_string)
+}
+
+/// The LD_PRELOAD value that loads `library` instead of the `replaced` paths
+///
+/// Returns None if `preload` can stay as it is.
+fn preload_value(preload: Option<&[u8]>, library: &str, replaced: &[&str]) -> Option<Vec<u8>> {
+    let Some(preload) = preload else {
+        return Some(library.as_bytes().to_vec());
+    };
+    let entries: Vec<&[u8]> = preload
+        .split(|&b| b == b':' || b == b' ')
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    let kept: Vec<&[u8]> = entries
+        .iter()
+        .copied()
+        .filter(|entry| !replaced.iter().any(|path| path.as_bytes() == *entry))
+        .collect();
+
+    if kept.len() == entries.len() {
+        if preloads_library(preload, library) {
+            return None;
+        }
+        let mut value = format!("{}:", library).into_bytes();
+        value.extend_from_slice(preload);
+        return Some(value);
+    }
+    let mut value = library.as_bytes().to_vec();
+    for entry in kept
+        .into_iter()
+        .filter(|entry| *entry != library.as_bytes())
+    {
+        value.push(b':');
+        value.extend_from_slice(entry);
+    }
+    Some(value)
+}
+
+/// Check whether an LD_PRELOAD value already loads `library`
+///
+/// Entries are compared by file name, so a different path to the same
+/// library counts.
+fn preloads_library(preload: &[u8], library: &str) -> bool {
+    let file_name = |path: &[u8]| path.rsplit(|&b| b == b'/').next().map(<[u8]>::to_vec);
+    let wanted = file_name(library.as_bytes());
+    preload
+        .split(|&b| b == b':' || b == b' ')
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| file_name(entry) == wanted)
+}
+
+/// Split a `NAME=value` entry into name and value
+pub(crate) fn split_entry(entry: &[u8]) -> (&[u8], Option<&[u8]>) {
+    match entry.iter().position(|&b| b == b'=') {
+        Some(eq) => (&entry[..eq], Some(&entry[eq + 1..])),
+        None => (entry, None),
+    }
+}
+
+/// Build a scrubbed copy of `envp` for a child process running `program`
+///
+/// Also adds LD_PRELOAD and the library configuration when propagation is
+/// enabled. Returns None if the environment can be passed through unchanged.
+/// In observe mode would-be scrubbing is only reported.
+///
+/// # Safety
+/// `envp` must be null or a null-terminated array of valid C strings
+pub(crate) unsafe fn child_env(
+    state: &TokenState,
+    envp: *const *const c_char,
+    program: Program,
+    via: &str,
+) -> Option<ChildEnv> {
+    if envp.is_null() {
+        return None;
+    }
+
+    let values = protected_token_values(state);
+    let known: Vec<&[u8]> = values.iter().map(|value| value.as_bytes()).collect();
+    let removed_names = scrubbed_names(state);
+
+    let mut owned = Vec::new();
+    let mut ptrs = Vec::new();
+    let mut changed = false;
+    let mut index = 0;
+    loop {
+        let entry = *envp.add(index);
+        if entry.is_null() {
+            break;
+        }
+        index += 1;
+
+        let bytes = CStr::from_ptr(entry).to_bytes();
+        let mut action = sanitize_entry(bytes, &removed_names, &known);
+        // Placeholder mode keeps protected tokens visible to the child;
+        // deny-listed variables are still removed
+        if action == EntryAction::Remove
+            && state.scrub_mode == ScrubMode::Placeholder
+            && !is_denied_token(state, split_entry(bytes).0)
+        {
+            action = placeholder_entry(bytes);
+        }
+        if action == EntryAction::Keep {
+            ptrs.push(entry);
+            continue;
+        }
+
+        let name = String::from_utf8_lossy(split_entry(bytes).0);
+        if state.mode == Mode::Observe {
+            audit::emit(
+                "observed",
+                &name,
+                &format!("would=exec_scrub action={} via={}", action.as_str(), via),
+            );
+            ptrs.push(entry);
+            continue;
+        }
+        audit::emit(
+            "exec_scrubbed",
+            &name,
+            &format!("action={} via={}", action.as_str(), via),
+        );
+        changed = true;
+
+        if let EntryAction::Redact(redacted) = action {
+            // Values come from C strings, so they contain no interior NUL
+            let redacted = CString::new(redacted).unwrap();
+            ptrs.push(redacted.as_ptr());
+            owned.push(redacted);
+        }
+    }
+
+    if let Some(propagation) = &state.propagation {
+        #[cfg(target_os = "macos")]
+        crate::macos::check_exec(program, via);
+        let arch = propagation.per_arch().then(|| program.arch()).flatten();
+        let additions = propagation.additions(arch, |name| {
+            ptrs.iter().find_map(|&entry| {
+                let (entry_name, value) = split_entry(CStr::from_ptr(entry).to_bytes());
+                (entry_name == name.as_bytes()).then_some(value).flatten()
+            })
+        });
+        for (name, value) in additions {
+            if state.debug_enabled {
+                log_line!(
+                    Debug,
+                    "propagate",
+                    Some(&name),
+                    "Propagating {} to child via {}",
+                    name,
+                    via
+                );
+            }
+            ptrs.retain(|&entry| {
+                split_entry(CStr::from_ptr(entry).to_bytes()).0 != name.as_bytes()
+            });
+            let mut entry = format!("{}=", name).into_bytes();
+            entry.extend_from_slice(&value);
+            // Names and values come from C strings, so they contain no interior NUL
+            let entry = CString::new(entry).unwrap();
+            ptrs.push(entry.as_ptr());
+            owned.push(entry);
+            changed = true;
+        }
+    }
+
+    if !changed {
+        return None;
+    }
+    ptrs.push(ptr::null());
+    Some(ChildEnv {
+        _owned: owned,
+        ptrs,
+    })
+}
+
+/// Intercepted execve function
+///
+/// Protected tokens are removed from, and protected values redacted in, the
+/// child's environment before the real execve is called.
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of execve(2).
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn execve(
+    path: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> c_int {
+    fork::report_vfork_child();
+    let child = child_env(&lock_state(), envp, Program::path(path), "execve");
+    let envp = child.as_ref().map_or(envp, ChildEnv::as_ptr);
+    (*REAL_EXECVE)(path, argv, envp)
+}
+
+/// Intercepted execvpe function
+///
+/// Same environment scrubbing as execve, with a PATH search for `file`.
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of execvpe(3).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn execvpe(
+    file: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> c_int {
+    fork::report_vfork_child();
+    let child = child_env(&lock_state(), envp, Program::search(file), "execvpe");
+    let envp = child.as_ref().map_or(envp, ChildEnv::as_ptr);
+    (*REAL_EXECVPE)(file, argv, envp)
+}
+
+/// Intercepted posix_spawn function
+///
+/// Applies the same child environment scrubbing and propagation as execve.
+/// glibc's own callers (system, popen) use an internal entry point; they are
+/// handled by the shell module.
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of posix_spawn(3).
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn posix_spawn(
+    pid: *mut pid_t,
+    path: *const c_char,
+    file_actions: *const posix_spawn_file_actions_t,
+    attrp: *const posix_spawnattr_t,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> c_int {
+    let child = child_env(&lock_state(), envp, Program::path(path), "posix_spawn");
+    let envp = child.as_ref().map_or(envp, ChildEnv::as_ptr);
+    let result = (*REAL_POSIX_SPAWN)(pid, path, file_actions, attrp, argv, envp);
+    if result == 0 && !pid.is_null() {
+        fork::report_child(*pid, "posix_spawn");
+    }
+    result
+}
+
+/// Intercepted posix_spawnp function
+///
+/// Same environment handling as posix_spawn, with a PATH search for `file`.
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of posix_spawnp(3).
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn posix_spawnp(
+    pid: *mut pid_t,
+    file: *const c_char,
+    file_actions: *const posix_spawn_file_actions_t,
+    attrp: *const posix_spawnattr_t,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> c_int {
+    let child = child_env(&lock_state(), envp, Program::search(file), "posix_spawnp");
+    let envp = child.as_ref().map_or(envp, ChildEnv::as_ptr);
+    let result = (*REAL_POSIX_SPAWNP)(pid, file, file_actions, attrp, argv, envp);
+    if result == 0 && !pid.is_null() {
+        fork::report_child(*pid, "posix_spawnp");
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_entry() {
+        let removed = vec![EnvName::from("GITHUB_TOKEN")];
+        let known: &[&[u8]] = &[b"ghp_secretvalue"];
+
+        assert_eq!(
+            sanitize_entry(b"GITHUB_TOKEN=ghp_secretvalue", &removed, known),
+            EntryAction::Remove
+        );
+        assert_eq!(
+            sanitize_entry(b"DATA=a:ghp_secretvalue:b", &removed, known),
+            EntryAction::Redact(b"DATA=a:***AWF_REDACTED***:b".to_vec())
+        );
+        assert_eq!(
+            sanitize_entry(b"GITHUB_TOKEN_PATH=/tmp/x", &removed, known),
+            EntryAction::Keep
+        );
+        assert_eq!(
+            sanitize_entry(b"NOEQUALS", &removed, known),
+            EntryAction::Keep
+        );
+    }
+
+    #[test]
+    fn test_placeholder_entry() {
+        assert_eq!(
+            placeholder_entry(b"GITHUB_TOKEN=ghp_secretvalue"),
+            EntryAction::Redact(b"GITHUB_TOKEN=***AWF-PROTECTED***".to_vec())
+        );
+        assert_eq!(
+            placeholder_entry(b"GITHUB_TOKEN=***AWF-PROTECTED***"),
+            EntryAction::Keep
+        );
+    }
+
+    #[test]
+    fn test_propagation_additions() {
+        let propagation = Propagation {
+            library: Some("/usr/local/lib/one-shot-token.so".to_string()),
+            builds: Builds::default(),
+            config: vec![(
+                "AWF_ONE_SHOT_TOKENS".to_string(),
+                "GITHUB_TOKEN".to_string(),
+            )],
+            runtime_tokens: Vec::new(),
+        };
+
+        let additions = propagation.additions(None, |_| None);
+        assert_eq!(
+            additions,
+            vec![
+                (
+                    PRELOAD_VAR.to_string(),
+                    b"/usr/local/lib/one-shot-token.so".to_vec()
+                ),
+                ("AWF_ONE_SHOT_TOKENS".to_string(), b"GITHUB_TOKEN".to_vec()),
+            ]
+        );
+
+        let additions = propagation.additions(None, |name| match name {
+            PRELOAD_VAR => Some(b"libother.so".as_slice()),
+            "AWF_ONE_SHOT_TOKENS" => Some(b"GITHUB_TOKEN".as_slice()),
+            _ => None,
+        });
+        assert_eq!(
+            additions,
+            vec![(
+                PRELOAD_VAR.to_string(),
+                b"/usr/local/lib/one-shot-token.so:libother.so".to_vec()
+            )]
+        );
+
+        let additions = propagation.additions(None, |name| match name {
+            PRELOAD_VAR => Some(b"/tmp/one-shot-token.so".as_slice()),
+            _ => Some(b"".as_slice()),
+        });
+        assert_eq!(
+            additions,
+            vec![("AWF_ONE_SHOT_TOKENS".to_string(), b"GITHUB_TOKEN".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_propagation_runtime_tokens() {
+        let mut propagation = Propagation {
+            library: None,
+            builds: Builds::default(),
+            config: vec![(EXTRA_TOKENS_VAR.to_string(), "MY_API_KEY".to_string())],
+            runtime_tokens: Vec::new(),
+        };
+        propagation.set_runtime_token(&"MINTED".into(), Some("MINTED:max_reads=1"));
+        propagation.set_runtime_token(&"OTHER".into(), Some("OTHER"));
+        propagation.set_runtime_token(&"OTHER".into(), None);
+        assert_eq!(
+            propagation.additions(None, |_| None),
+            vec![(
+                EXTRA_TOKENS_VAR.to_string(),
+                b"MY_API_KEY,MINTED:max_reads=1".to_vec()
+            )]
+        );
+
+        propagation.config.clear();
+        assert_eq!(
+            propagation.additions(None, |_| None),
+            vec![(EXTRA_TOKENS_VAR.to_string(), b"MINTED:max_reads=1".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_propagation_per_arch() {
+        let propagation = Propagation {
+            library: Some("/usr/local/lib/one-shot-token.so".to_string()),
+            builds: Builds {
+                i686: Some("/usr/local/lib/one-shot-token32.so".to_string()),
+                x86_64: Some("/usr/local/lib/one-shot-token.so".to_string()),
+                aarch64: Some("/usr/local/lib/one-shot-token-aarch64.so".to_string()),
+            },
+            config: Vec::new(),
+            runtime_tokens: Vec::new(),
+        };
+        assert!(propagation.per_arch());
+
+        let preload =
+            |value: &'static [u8]| move |name: &str| (name == PRELOAD_VAR).then_some(value);
+        assert_eq!(
+            propagation.additions(
+                Some(Arch::I686),
+                preload(b"/usr/local/lib/one-shot-token.so:libother.so")
+            ),
+            vec![(
+                PRELOAD_VAR.to_string(),
+                b"/usr/local/lib/one-shot-token32.so:libother.so".to_vec()
+            )]
+        );
+        assert_eq!(
+            propagation.additions(
+                Some(Arch::X86_64),
+                preload(b"libother.so /usr/local/lib/one-shot-token32.so")
+            ),
+            vec![(
+                PRELOAD_VAR.to_string(),
+                b"/usr/local/lib/one-shot-token.so:libother.so".to_vec()
+            )]
+        );
+        assert_eq!(
+            propagation.additions(
+                Some(Arch::Aarch64),
+                preload(b"/usr/local/lib/one-shot-token.so")
+            ),
+            vec![(
+                PRELOAD_VAR.to_string(),
+                b"/usr/local/lib/one-shot-token-aarch64.so".to_vec()
+            )]
+        );
+        assert!(propagation
+            .additions(
+                Some(Arch::I686),
+                preload(b"/usr/local/lib/one-shot-token32.so")
+            )
+            .is_empty());
+        assert!(propagation
+            .additions(None, preload(b"/usr/local/lib/one-shot-token.so"))
+            .is_empty());
+    }
+}
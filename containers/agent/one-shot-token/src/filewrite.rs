@@ -0,0 +1,438 @@
+//! Scanning of file writes for protected token values
+//!
+//! An agent can stash a token it read in a file that is later committed or
+//! uploaded as an artifact. With AWF_ONE_SHOT_FILE_WRITE_SCAN set to `redact`
+//! or `block`, buffers passed to write, pwrite and pwrite64, and written
+//! through stdio (see the stdiowrite module), are scanned for protected token
+//! values (the snapshot kept by the egress module). A match in
+//! a write to a regular file under the workspace (GITHUB_WORKSPACE) or /tmp
+//! emits a `file_secret` audit event and either has each value overwritten
+//! with `*` of the same length, so file offsets stay what the caller expects,
+//! or fails with EACCES. In observe mode the write proceeds unchanged and is
+//! reported with an `observed` audit event.
+//!
+//! A token written to one of the workflow command files named by GITHUB_ENV,
+//! GITHUB_OUTPUT and GITHUB_STATE is laundered: the runner exports it to
+//! every later step, or hands it to other jobs, outside of this library.
+//! Writes to these files are checked the same way under
+//! AWF_ONE_SHOT_COMMAND_FILE_SCAN, which defaults to `block`, with a
+//! `command_file_secret` audit event naming the file. The paths are taken
+//! from the environment at load, before the program can change it. Not every
+//! write goes through an interposer (characters written one at a time through
+//! stdio, writev), so at exit (see the report module) the files are also
+//! searched for the values this process cached, and any found are
+//! overwritten with `*` in place.
+//!
+//! Setting AWF_ONE_SHOT_WORKSPACE_GUARD keeps tokens out of the checked-out
+//! repository: it raises the policy for files under GITHUB_WORKSPACE to
+//! `block`, even with AWF_ONE_SHOT_FILE_WRITE_SCAN off or `redact`.
+//!
+//! The target path is only looked up once a value has been found, through
+//! /proc/self/fd, so writes without secrets cost a scan of the buffer only.
+//! Elsewhere than on Linux file writes are not checked.
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+use crate::{audit, egress, protection_disabled, read_config_flag, read_config_var, reentry};
+#[cfg(target_os = "linux")]
+use crate::{resolve_next, sys};
+use libc::{c_int, c_void, size_t};
+#[cfg(target_os = "linux")]
+use libc::{off64_t, off_t, ssize_t};
+use once_cell::sync::Lazy;
+use std::ffi::CStr;
+
+/// What happens when a protected value is written to a scanned file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WritePolicy {
+    Off,
+    Redact,
+    Block,
+}
+
+impl WritePolicy {
+    /// Parse AWF_ONE_SHOT_FILE_WRITE_SCAN (case-insensitive)
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" | "0" | "" => Some(WritePolicy::Off),
+            "redact" => Some(WritePolicy::Redact),
+            "block" => Some(WritePolicy::Block),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `value` is an AWF_ONE_SHOT_FILE_WRITE_SCAN value
+pub(crate) fn is_policy(value: &str) -> bool {
+    WritePolicy::parse(value).is_some()
+}
+
+/// Scan policy, read once outside the state lock
+///
+/// Unrecognized values block, so a typo never silently disables scanning.
+static POLICY: Lazy<WritePolicy> = Lazy::new(|| {
+    if protection_disabled() {
+        return WritePolicy::Off;
+    }
+    read_config_var(c"AWF_ONE_SHOT_FILE_WRITE_SCAN")
+        .map(|value| WritePolicy::parse(&value).unwrap_or(WritePolicy::Block))
+        .unwrap_or(WritePolicy::Off)
+});
+
+/// Policy for the workflow command files, read once outside the state lock
+///
+/// Unrecognized values block, as for AWF_ONE_SHOT_FILE_WRITE_SCAN.
+static COMMAND_POLICY: Lazy<WritePolicy> = Lazy::new(|| {
+    if protection_disabled() {
+        return WritePolicy::Off;
+    }
+    read_config_var(c"AWF_ONE_SHOT_COMMAND_FILE_SCAN")
+        .map(|value| WritePolicy::parse(&value).unwrap_or(WritePolicy::Block))
+        .unwrap_or(WritePolicy::Block)
+});
+
+/// Variables naming the workflow command files
+const COMMAND_VARS: [&CStr; 3] = [c"GITHUB_ENV", c"GITHUB_OUTPUT", c"GITHUB_STATE"];
+
+/// The workflow command files, as (variable, path) with symlinks resolved
+static COMMAND_FILES: Lazy<Vec<(&'static str, String)>> = Lazy::new(|| {
+    if *COMMAND_POLICY == WritePolicy::Off {
+        return Vec::new();
+    }
+    COMMAND_VARS
+        .iter()
+        .filter_map(|var| {
+            let path = read_config_var(var).filter(|path| !path.trim().is_empty())?;
+            let path = std::fs::canonicalize(path.trim())
+                .ok()
+                .and_then(|path| path.to_str().map(str::to_string))
+                .unwrap_or_else(|| path.trim().to_string());
+            Some((var.to_str().ok()?, path))
+        })
+        .collect()
+});
+
+/// The workspace, with symlinks resolved
+static WORKSPACE: Lazy<Option<String>> = Lazy::new(|| {
+    let workspace = read_config_var(c"GITHUB_WORKSPACE").filter(|dir| !dir.trim().is_empty())?;
+    let workspace = std::fs::canonicalize(workspace.trim()).ok()?;
+    workspace.to_str().map(str::to_string)
+});
+
+/// Whether writes of protected values to the workspace always fail
+static WORKSPACE_GUARD: Lazy<bool> = Lazy::new(|| {
+    !protection_disabled() && read_config_flag(c"AWF_ONE_SHOT_WORKSPACE_GUARD", false)
+});
+
+/// Directories whose files are scanned, with symlinks resolved, and their
+/// policies, the workspace first
+static DIRS: Lazy<Vec<(String, WritePolicy)>> = Lazy::new(|| {
+    let workspace_policy = if *WORKSPACE_GUARD {
+        WritePolicy::Block
+    } else {
+        *POLICY
+    };
+    let workspace = WORKSPACE.clone().map(|dir| (dir, workspace_policy));
+    let tmp = std::fs::canonicalize("/tmp")
+        .ok()
+        .and_then(|dir| dir.to_str().map(|dir| (dir.to_string(), *POLICY)));
+    workspace
+        .into_iter()
+        .chain(tmp)
+        .filter(|(_, policy)| *policy != WritePolicy::Off)
+        .collect()
+});
+
+/// Whether file writes are scanned at all
+pub(crate) fn enabled() -> bool {
+    !DIRS.is_empty() || !COMMAND_FILES.is_empty()
+}
+
+/// Take the command file paths and the workspace from the environment
+/// (called at load)
+pub(crate) fn load() {
+    Lazy::force(&COMMAND_FILES);
+    Lazy::force(&DIRS);
+}
+
+/// What to do with a write
+pub(crate) enum Check {
+    /// Write the caller's buffer
+    Allow,
+    /// Write this copy instead (of the same length for file writes)
+    Redact(Vec<u8>),
+    /// Fail with EACCES
+    Block,
+}
+
+/// Whether `path` is `dir` or inside it
+fn under(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || dir.ends_with('/'))
+}
+
+/// Why a file is scanned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    /// Under the workspace or /tmp
+    Scanned,
+    /// The workflow command file named by this variable
+    Command(&'static str),
+}
+
+impl Kind {
+    /// The audit event for a protected value written to the file
+    fn event(self) -> &'static str {
+        match self {
+            Kind::Scanned => "file_secret",
+            Kind::Command(_) => "command_file_secret",
+        }
+    }
+
+    /// The `would=` value of the observe mode event
+    fn would(self, policy: WritePolicy) -> &'static str {
+        match (self, policy) {
+            (Kind::Scanned, WritePolicy::Block) => "block_file_write",
+            (Kind::Scanned, _) => "redact_file_write",
+            (Kind::Command(_), WritePolicy::Block) => "block_command_file_write",
+            (Kind::Command(_), _) => "redact_command_file_write",
+        }
+    }
+}
+
+/// A scanned file that a write goes to
+struct Target {
+    path: String,
+    policy: WritePolicy,
+    kind: Kind,
+}
+
+/// The scanned file `path` is, if any
+fn target(path: &str) -> Option<Target> {
+    let (policy, kind) = if let Some((var, _)) = COMMAND_FILES.iter().find(|(_, file)| file == path)
+    {
+        (*COMMAND_POLICY, Kind::Command(var))
+    } else {
+        let (_, policy) = DIRS.iter().find(|(dir, _)| under(path, dir))?;
+        (*policy, Kind::Scanned)
+    };
+    Some(Target {
+        path: path.to_string(),
+        policy,
+        kind,
+    })
+}
+
+/// The regular file open on `fd`, if it is scanned
+#[cfg(target_os = "linux")]
+fn scanned_target(fd: c_int) -> Option<Target> {
+    // SAFETY: stat is plain data filled by fstat
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat) } != 0 || stat.st_mode & libc::S_IFMT != libc::S_IFREG {
+        return None;
+    }
+    let path = std::fs::read_link(format!("/proc/self/fd/{}", fd)).ok()?;
+    let path = path.to_str()?;
+    target(path.strip_suffix(" (deleted)").unwrap_or(path))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn scanned_target(_fd: c_int) -> Option<Target> {
+    None
+}
+
+/// Check a buffer written to `fd`
+///
+/// # Safety
+/// `buf` must be null or valid for reads of `count` bytes
+pub(crate) unsafe fn check(fd: c_int, buf: *const c_void, count: size_t, via: &str) -> Check {
+    if !enabled() || buf.is_null() || count == 0 {
+        return Check::Allow;
+    }
+    // Our own log files are written with the state lock held
+    if reentry::active() {
+        return Check::Allow;
+    }
+    let data = std::slice::from_raw_parts(buf as *const u8, count);
+    let Some((spans, names)) = egress::find_protected_spans(data) else {
+        return Check::Allow;
+    };
+    let Some(target) = scanned_target(fd) else {
+        return Check::Allow;
+    };
+
+    let mut detail = format!("path={} via={}", audit::detail_word(&target.path), via);
+    if let Kind::Command(var) = target.kind {
+        detail = format!("file={} {}", var, detail);
+    }
+    let (action, result) = match target.policy {
+        WritePolicy::Block => ("blocked", Check::Block),
+        _ => {
+            let mut redacted = data.to_vec();
+            for span in &spans {
+                redacted[span.clone()].fill(b'*');
+            }
+            ("redacted", Check::Redact(redacted))
+        }
+    };
+    if egress::observing() {
+        let would = target.kind.would(target.policy);
+        for name in &names {
+            audit::emit("observed", name, &format!("would={} {}", would, detail));
+        }
+        return Check::Allow;
+    }
+    for name in &names {
+        audit::emit(
+            target.kind.event(),
+            name,
+            &format!("{} action={}", detail, action),
+        );
+    }
+    result
+}
+
+/// Overwrite the protected values found in the workflow command files with
+/// `*`, for lines that did not go through the write interposer
+///
+/// Called at exit, while the values are still known. In observe mode the
+/// values are only reported.
+pub(crate) fn sweep_command_files() {
+    use std::io::Read;
+    use std::os::unix::fs::FileExt;
+
+    for (var, path) in COMMAND_FILES.iter() {
+        let Ok(mut file) = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+        else {
+            continue;
+        };
+        let mut data = Vec::new();
+        if file.read_to_end(&mut data).is_err() {
+            continue;
+        }
+        let Some((spans, names)) = egress::find_protected_spans(&data) else {
+            continue;
+        };
+        let detail = format!("file={} path={} via=exit", var, audit::detail_word(path));
+        if egress::observing() {
+            for name in &names {
+                let detail = format!("would=redact_command_file_write {}", detail);
+                audit::emit("observed", name, &detail);
+            }
+            continue;
+        }
+        let redacted = spans.iter().all(|span| {
+            file.write_all_at(&vec![b'*'; span.len()], span.start as u64)
+                .is_ok()
+        });
+        let action = if redacted { "redacted" } else { "failed" };
+        for name in &names {
+            audit::emit(
+                "command_file_secret",
+                name,
+                &format!("{} action={}", detail, action),
+            );
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+type PwriteFn = unsafe extern "C" fn(c_int, *const c_void, size_t, off_t) -> ssize_t;
+#[cfg(target_os = "linux")]
+type Pwrite64Fn = unsafe extern "C" fn(c_int, *const c_void, size_t, off64_t) -> ssize_t;
+
+/// Cached pointer to the real pwrite function
+#[cfg(target_os = "linux")]
+static REAL_PWRITE: Lazy<PwriteFn> = Lazy::new(|| {
+    // SAFETY: pwrite has the PwriteFn signature
+    unsafe { std::mem::transmute::<*mut c_void, PwriteFn>(resolve_next(c"pwrite")) }
+});
+
+/// Cached pointer to the real pwrite64 function
+#[cfg(target_os = "linux")]
+static REAL_PWRITE64: Lazy<Pwrite64Fn> = Lazy::new(|| {
+    // SAFETY: pwrite64 has the Pwrite64Fn signature
+    unsafe { std::mem::transmute::<*mut c_void, Pwrite64Fn>(resolve_next(c"pwrite64")) }
+});
+
+/// Intercepted pwrite function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of pwrite(2).
+#[cfg(target_os = "linux")]
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn pwrite(
+    fd: c_int,
+    buf: *const c_void,
+    count: size_t,
+    offset: off_t,
+) -> ssize_t {
+    match check(fd, buf, count, "pwrite") {
+        Check::Allow => (*REAL_PWRITE)(fd, buf, count, offset),
+        Check::Redact(data) => (*REAL_PWRITE)(fd, data.as_ptr().cast(), count, offset),
+        Check::Block => {
+            *sys::errno_location() = libc::EACCES;
+            -1
+        }
+    }
+}
+
+/// Intercepted pwrite64 function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of pwrite64(2).
+#[cfg(target_os = "linux")]
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn pwrite64(
+    fd: c_int,
+    buf: *const c_void,
+    count: size_t,
+    offset: off64_t,
+) -> ssize_t {
+    match check(fd, buf, count, "pwrite64") {
+        Check::Allow => (*REAL_PWRITE64)(fd, buf, count, offset),
+        Check::Redact(data) => (*REAL_PWRITE64)(fd, data.as_ptr().cast(), count, offset),
+        Check::Block => {
+            *sys::errno_location() = libc::EACCES;
+            -1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_policy_parse() {
+        assert_eq!(WritePolicy::parse("REDACT"), Some(WritePolicy::Redact));
+        assert_eq!(WritePolicy::parse(" block "), Some(WritePolicy::Block));
+        assert_eq!(WritePolicy::parse("0"), Some(WritePolicy::Off));
+        assert_eq!(WritePolicy::parse("log"), None);
+    }
+
+    #[test]
+    fn test_target() {
+        // No command files or scanned directories are configured in tests
+        assert!(target("/home/runner/work/_temp/set_env_1").is_none());
+        assert!(target("/tmp/token.txt").is_none());
+        assert_eq!(Kind::Scanned.would(WritePolicy::Block), "block_file_write");
+        assert_eq!(Kind::Command("GITHUB_ENV").event(), "command_file_secret");
+    }
+
+    #[test]
+    fn test_under() {
+        assert!(under("/tmp", "/tmp"));
+        assert!(under("/tmp/out/token.txt", "/tmp"));
+        assert!(!under("/tmpfiles/token.txt", "/tmp"));
+        assert!(under(
+            "/home/runner/work/repo/.env",
+            "/home/runner/work/repo"
+        ));
+        assert!(under("/etc/passwd", "/"));
+    }
+}
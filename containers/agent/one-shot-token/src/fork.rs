@@ -0,0 +1,278 @@
+//! Fork safety for the global state
+//!
+//! fork() copies only the calling thread. If another thread holds the state
+//! lock at that moment, the child inherits a locked mutex with no owner and
+//! deadlocks on its first getenv. pthread_atfork handlers therefore take the
+//! lock before the fork and release it on both sides afterwards, so the child
+//! always starts with a consistent, unlocked state. Events queued for the
+//! OpenTelemetry collector are left to the parent (see the otlp module).
+//!
+//! The child handler does nothing else: a child that execs right away, as
+//! shells and build tools do, should not pay for more. The rest is done on
+//! the child's first use of the state lock: it starts a new region for cached
+//! values (see the secmem module), a new audit hash chain (see the chain
+//! module) and a key of its own for audit signatures (see the sign module).
+//! Values cached before the fork in secret memory, which the child shares
+//! with the parent, are copied into the child's region, so a wipe in the child
+//! (awf_token_wipe, a ttl, strict reads, the idle wipe, the exit handler, ...)
+//! leaves the parent's values intact. Values not yet served from the kernel
+//! keyring are handed over to the child's own keyring (see the keyring
+//! module). The background threads, which do not survive the fork (config
+//! reload, idle wipe, watchdog, signals, step watch), are restarted on the
+//! child's first getenv; until then, the signal handler ignores signals other
+//! than the ones that end the process (see the signals module).
+//!
+//! fork is also interposed to report each child with a `child_started` audit
+//! event if AWF_ONE_SHOT_TRACK_CHILDREN is set (posix_spawn children are
+//! reported by the exec module), so the host collector can attribute the
+//! reads and violations in a child to the agent action that started it.
+//! vfork is not interposed: the child borrows the caller's stack until it
+//! execs and would return through a wrapper's frame. A vfork child, in which
+//! the fork handlers do not run, is recognized by its PID and reports itself
+//! when it execs instead.
+
+#[cfg(target_os = "linux")]
+use crate::config;
+#[cfg(target_os = "linux")]
+use crate::resolve_next;
+#[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+use crate::stepwatch;
+use crate::{audit, idle, lock_state, secmem, CachedToken, StateGuard, TokenState};
+#[cfg(not(feature = "minimal"))]
+use crate::{chain, invalidate, otlp, sign, signals, watchdog};
+use libc::pid_t;
+#[cfg(target_os = "linux")]
+use once_cell::sync::Lazy;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+/// Type signature for fork
+#[cfg(target_os = "linux")]
+type ForkFn = unsafe extern "C" fn() -> pid_t;
+
+/// Cached pointer to the real fork function
+#[cfg(target_os = "linux")]
+static REAL_FORK: Lazy<ForkFn> = Lazy::new(|| {
+    // SAFETY: fork takes no arguments and returns a pid_t
+    unsafe { std::mem::transmute::<*mut libc::c_void, ForkFn>(resolve_next(c"fork")) }
+});
+
+/// Whether started children are reported (AWF_ONE_SHOT_TRACK_CHILDREN), kept
+/// out of the state so forks do not take the lock when they are not
+static TRACK_CHILDREN: AtomicBool = AtomicBool::new(false);
+
+/// PID of the process the fork handlers were last set up in
+static OWN_PID: AtomicI32 = AtomicI32::new(0);
+
+/// Whether this forked child has yet to settle its state (see `settle`)
+static SETTLE_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Whether this forked child has yet to start its background threads
+static THREADS_PENDING: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// State lock held by the forking thread between prepare and parent/child
+    static FORK_GUARD: RefCell<Option<StateGuard>> =
+        const { RefCell::new(None) };
+}
+
+/// Take the state lock before fork so no other thread holds it
+extern "C" fn prepare() {
+    let mut guard = lock_state();
+    for sealed in guard
+        .cache
+        .values_mut()
+        .filter_map(|entry| entry.sealed.as_mut())
+    {
+        sealed.prepare_fork();
+    }
+    FORK_GUARD.with(|slot| *slot.borrow_mut() = Some(guard));
+    #[cfg(not(feature = "minimal"))]
+    otlp::prepare_fork();
+}
+
+/// Release the state lock in the parent after fork
+extern "C" fn release_in_parent() {
+    #[cfg(not(feature = "minimal"))]
+    otlp::release_in_parent();
+    if let Some(mut state) = FORK_GUARD.with(|slot| slot.borrow_mut().take()) {
+        for sealed in state
+            .cache
+            .values_mut()
+            .filter_map(|entry| entry.sealed.as_mut())
+        {
+            sealed.after_fork_in_parent();
+        }
+    }
+}
+
+/// Give the child its own copy of a value cached in secret memory before the
+/// fork, which it would otherwise share with the parent
+///
+/// Pointers getenv returned before the fork keep pointing at the shared
+/// buffer; reads in the child are served from the copy, which the child's
+/// wipes zeroize, from the lock-free snapshot published when the lock is
+/// released.
+fn unshare(entry: &mut CachedToken) {
+    // A buffer a strict read handed over to the parent's caller is left
+    // alone by the child's wipes
+    if secmem::is_shared(entry.handed_over) {
+        entry.handed_over = std::ptr::null_mut();
+    }
+    if entry.value.is_null() {
+        return;
+    }
+    let len = match &entry.sealed {
+        Some(sealed) => sealed.len() + 1,
+        // SAFETY: an unsealed cached value is a null-terminated string
+        None => unsafe { libc::strlen(entry.value) + 1 },
+    };
+    // SAFETY: the buffer was allocated with room for `len` bytes
+    if let Some((value, protection)) = unsafe { secmem::unshare(entry.value, len) } {
+        entry.value = value;
+        entry.protection = Some(protection);
+    }
+}
+
+/// Release the state lock in the child and leave the rest for later (see
+/// the module documentation)
+extern "C" fn reinit_in_child() {
+    // SAFETY: getpid has no preconditions
+    OWN_PID.store(unsafe { libc::getpid() }, Ordering::Relaxed);
+    SETTLE_PENDING.store(true, Ordering::Relaxed);
+    THREADS_PENDING.store(true, Ordering::Relaxed);
+    #[cfg(not(feature = "minimal"))]
+    {
+        signals::forget_pipe();
+        otlp::reset_in_child();
+    }
+    FORK_GUARD.with(|slot| slot.borrow_mut().take());
+}
+
+/// Give a forked child state of its own, on its first use of the state lock
+pub(crate) fn settle(state: &mut TokenState) {
+    if !SETTLE_PENDING.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    secmem::reset_after_fork();
+    #[cfg(not(feature = "minimal"))]
+    {
+        chain::reset_after_fork();
+        sign::reset_after_fork();
+    }
+    for entry in state.cache.values_mut() {
+        if let Some(sealed) = &mut entry.sealed {
+            sealed.after_fork_in_child();
+        }
+        unshare(entry);
+    }
+    #[cfg(not(feature = "minimal"))]
+    invalidate::start_in_child(state);
+}
+
+/// Restart the background threads in a forked child, on its first getenv
+///
+/// Must not be called with the state lock held, as the threads' start
+/// functions take it.
+pub(crate) fn start_threads() {
+    if !THREADS_PENDING.load(Ordering::Relaxed) || !THREADS_PENDING.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    config::start();
+    idle::start();
+    #[cfg(not(feature = "minimal"))]
+    {
+        watchdog::start();
+        signals::start();
+    }
+    #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+    stepwatch::start();
+}
+
+/// Set whether started children are reported
+pub(crate) fn set_track_children(track: bool) {
+    TRACK_CHILDREN.store(track, Ordering::Relaxed);
+}
+
+/// Report child `pid` started through `via`, if AWF_ONE_SHOT_TRACK_CHILDREN
+/// is on
+pub(crate) fn report_child(pid: pid_t, via: &str) {
+    if pid <= 0 || !TRACK_CHILDREN.load(Ordering::Relaxed) {
+        return;
+    }
+    // SAFETY: getpid has no preconditions
+    let parent = unsafe { libc::getpid() };
+    audit::emit(
+        "child_started",
+        "*",
+        &format!("pid={} parent={} via={}", pid, parent, via),
+    );
+}
+
+/// Report the calling process if it is a vfork child about to exec, if
+/// AWF_ONE_SHOT_TRACK_CHILDREN is on
+///
+/// The event is written from the child, on behalf of its parent.
+pub(crate) fn report_vfork_child() {
+    if !TRACK_CHILDREN.load(Ordering::Relaxed) {
+        return;
+    }
+    // SAFETY: getpid and getppid have no preconditions
+    let (pid, parent) = unsafe { (libc::getpid(), libc::getppid()) };
+    let own = OWN_PID.load(Ordering::Relaxed);
+    if own == 0 || pid == own {
+        return;
+    }
+    audit::emit(
+        "child_started",
+        "*",
+        &format!("pid={} parent={} via=vfork", pid, parent),
+    );
+}
+
+/// Intercepted fork function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+#[cfg(target_os = "linux")]
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn fork() -> pid_t {
+    let pid = (*REAL_FORK)();
+    report_child(pid, "fork");
+    pid
+}
+
+/// Register the fork handlers at load
+extern "C" fn register_fork_handlers() {
+    // SAFETY: getpid has no preconditions
+    OWN_PID.store(unsafe { libc::getpid() }, Ordering::Relaxed);
+    // SAFETY: the handlers are plain extern "C" functions without arguments
+    let result = unsafe {
+        libc::pthread_atfork(
+            Some(prepare),
+            Some(release_in_parent),
+            Some(reinit_in_child),
+        )
+    };
+    if result != 0 {
+        log_line!(
+            Warning,
+            "init",
+            None,
+            "Could not register fork handlers: {}",
+            std::io::Error::from_raw_os_error(result)
+        );
+    }
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static REGISTER_FORK_HANDLERS: extern "C" fn() = register_fork_handlers;
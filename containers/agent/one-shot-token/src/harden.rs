@@ -0,0 +1,300 @@
+//! Process hardening applied at load
+//!
+//! With AWF_ONE_SHOT_NODUMP set, the process is marked non-dumpable with
+//! prctl(PR_SET_DUMPABLE, 0). Other processes running as the same user can
+//! then no longer ptrace it or open its /proc/<pid>/mem, environ or maps, so
+//! cached token values cannot be scraped out of it, and it never writes a
+//! core dump. This also stops debuggers and profilers from attaching, which
+//! is why it is opt-in. The kernel resets the flag on exec, so children are
+//! covered through propagation of the configuration.
+//!
+//! With AWF_ONE_SHOT_NOCORE set, RLIMIT_CORE is lowered to 0, so a crash of a
+//! token-holding process cannot write its memory to a core file on the
+//! runner. Unlike the dumpable flag, the limit survives exec and is inherited
+//! by every child, and debuggers can still attach.
+//!
+//! With AWF_ONE_SHOT_SECCOMP set, a seccomp filter is installed that fails
+//! ptrace, process_vm_readv and process_vm_writev with EPERM. The filter is
+//! inherited by every descendant and cannot be removed, so neither the agent
+//! nor anything it starts can read the memory of a sibling process holding
+//! tokens. Installing it requires no_new_privs, which also stops setuid
+//! programs such as sudo from gaining privileges.
+
+use crate::{audit, lock_state, Mode};
+use libc::{sock_filter, sock_fprog};
+use std::mem::offset_of;
+use std::sync::Once;
+
+/// Mark the process non-dumpable at load when AWF_ONE_SHOT_NODUMP is set
+fn set_nodump_at_load() {
+    let state = lock_state();
+    if !state.nodump {
+        return;
+    }
+    if state.mode == Mode::Observe {
+        drop(state);
+        audit::emit("observed", "*", "would=set_nodump");
+        return;
+    }
+
+    // SAFETY: PR_SET_DUMPABLE takes a single integer argument
+    if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0) } != 0 {
+        log_line!(
+            Warning,
+            "nodump",
+            None,
+            "Could not mark process non-dumpable: {}",
+            std::io::Error::last_os_error()
+        );
+    } else if state.debug_enabled {
+        log_line!(Debug, "nodump", None, "Process marked non-dumpable");
+    }
+}
+
+/// Human-readable form of a resource limit value
+fn describe_limit(limit: libc::rlim_t) -> String {
+    if limit == libc::RLIM_INFINITY {
+        "unlimited".to_string()
+    } else {
+        limit.to_string()
+    }
+}
+
+/// Disable core dumps at load when AWF_ONE_SHOT_NOCORE is set
+fn disable_core_at_load() {
+    let state = lock_state();
+    if !state.nocore {
+        return;
+    }
+    if state.mode == Mode::Observe {
+        drop(state);
+        audit::emit("observed", "*", "would=disable_core");
+        return;
+    }
+
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: limit is a valid rlimit to fill in
+    let previous = (unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) } == 0)
+        .then_some((limit.rlim_cur, limit.rlim_max));
+    // Lowering the hard limit as well keeps the program from raising it again
+    let disabled = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: disabled is a valid rlimit
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &disabled) } != 0 {
+        log_line!(
+            Warning,
+            "nocore",
+            None,
+            "Could not disable core dumps: {}",
+            std::io::Error::last_os_error()
+        );
+    } else if state.debug_enabled {
+        match previous {
+            Some((soft, hard)) => log_line!(
+                Debug,
+                "nocore",
+                None,
+                "Core dumps disabled (previous RLIMIT_CORE soft={} hard={})",
+                describe_limit(soft),
+                describe_limit(hard)
+            ),
+            None => log_line!(Debug, "nocore", None, "Core dumps disabled"),
+        }
+    }
+}
+
+/// Audit architecture of the native system call ABI
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "x86")]
+const AUDIT_ARCH: u32 = 0x4000_0003;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// System calls failed by the filter
+#[cfg(any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64"))]
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+];
+
+/// Build the seccomp program
+///
+/// System calls through a foreign ABI (i386 int 0x80 or x32 in a 64-bit
+/// process, the 64-bit ABI in a 32-bit one) are failed as well, since their
+/// numbers differ and would otherwise slip past the checks.
+#[cfg(any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64"))]
+fn filter() -> Vec<sock_filter> {
+    let stmt = |code: u32, k: u32| sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let jump = |code: u32, k: u32, jt: u8, jf: u8| sock_filter {
+        code: (libc::BPF_JMP | code | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    };
+    let load = |offset: usize| stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset as u32);
+
+    let mut checks = Vec::new();
+    #[cfg(target_arch = "x86_64")]
+    checks.push((libc::BPF_JGE, 0x4000_0000));
+    for &nr in DENIED_SYSCALLS {
+        checks.push((libc::BPF_JEQ, nr as u32));
+    }
+
+    // Every check jumps forward to the deny return at the end on a match
+    let mut program = vec![load(offset_of!(libc::seccomp_data, arch))];
+    program.push(jump(libc::BPF_JEQ, AUDIT_ARCH, 1, 0));
+    program.push(stmt(
+        libc::BPF_RET | libc::BPF_K,
+        libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+    ));
+    program.push(load(offset_of!(libc::seccomp_data, nr)));
+    let count = checks.len();
+    for (index, (code, k)) in checks.into_iter().enumerate() {
+        program.push(jump(code, k, (count - index) as u8, 0));
+    }
+    program.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+    program.push(stmt(
+        libc::BPF_RET | libc::BPF_K,
+        libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+    ));
+    program
+}
+
+/// Install the filter for every thread of the process
+#[cfg(any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64"))]
+fn install_seccomp() -> std::io::Result<()> {
+    let program = filter();
+    let prog = sock_fprog {
+        len: program.len() as u16,
+        filter: program.as_ptr() as *mut sock_filter,
+    };
+    // SAFETY: prctl and seccomp only read `prog`, which outlives the calls
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let result = libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_TSYNC,
+            &prog as *const sock_fprog,
+        );
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64")))]
+fn install_seccomp() -> std::io::Result<()> {
+    Err(std::io::Error::from_raw_os_error(libc::ENOSYS))
+}
+
+/// Install the seccomp filter at load when AWF_ONE_SHOT_SECCOMP is set
+fn install_seccomp_at_load() {
+    let state = lock_state();
+    if !state.seccomp {
+        return;
+    }
+    if state.mode == Mode::Observe {
+        drop(state);
+        audit::emit("observed", "*", "would=install_seccomp");
+        return;
+    }
+
+    match install_seccomp() {
+        Ok(()) if state.debug_enabled => {
+            log_line!(
+                Debug,
+                "seccomp",
+                None,
+                "Seccomp filter installed (ptrace, process_vm_readv/writev)"
+            );
+        }
+        Ok(()) => {}
+        Err(err) => {
+            log_line!(
+                Warning,
+                "seccomp",
+                None,
+                "Could not install seccomp filter: {}",
+                err
+            );
+        }
+    }
+}
+
+/// Apply the hardening the configuration asks for, once
+///
+/// Called by a constructor, and by the verification report before it checks
+/// the result (see the verify module), whichever the dynamic linker runs first.
+pub(crate) fn apply_at_load() {
+    static APPLIED: Once = Once::new();
+    APPLIED.call_once(|| {
+        set_nodump_at_load();
+        disable_core_at_load();
+        install_seccomp_at_load();
+    });
+}
+
+extern "C" fn harden_at_load() {
+    apply_at_load();
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static HARDEN_AT_LOAD: extern "C" fn() = harden_at_load;
+
+#[cfg(all(
+    test,
+    any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64")
+))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seccomp_filter_denies_ptrace() {
+        // The filter cannot be removed, so install it in a child process
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            let code = match install_seccomp() {
+                Err(_) => 2,
+                Ok(()) => {
+                    let traced = unsafe { libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0) };
+                    let errno = std::io::Error::last_os_error().raw_os_error();
+                    if traced == -1 && errno == Some(libc::EPERM) {
+                        0
+                    } else {
+                        1
+                    }
+                }
+            };
+            unsafe { libc::_exit(code) };
+        }
+        let mut status = 0;
+        unsafe { libc::waitpid(pid, &mut status, 0) };
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+}
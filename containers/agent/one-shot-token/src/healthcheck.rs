@@ -0,0 +1,748 @@
+//! Health check for preflight (awf_one_shot_healthcheck)
+//!
+//! Image build pipelines and the firewall's preflight dlopen the library and
+//! call `ssize_t awf_one_shot_healthcheck(char *buf, size_t len)` before a
+//! real workload runs with it. Like awf_token_stats (see the control module)
+//! it writes a JSON object snprintf-style and returns its full length:
+//!
+//! ```text
+//! {"version":"0.1.0","ok":false,"disabled":false,"tokens":11,
+//!  "symbols":{"ok":true,"missing":[]},
+//!  "config":{"ok":false,"problems":["AWF_ONE_SHOT_MODE: unknown value
+//!  'enforcing', expected enforce or observe; enforcing"]}}
+//! ```
+//!
+//! `symbols` lists the libc functions wrapped by the interposers that
+//! dlsym(RTLD_NEXT) cannot find; an interposer aborts the process when it is
+//! first called without its function. `config` lists the settings of the
+//! calling process that the library ignores or falls back on: AWF_ONE_SHOT_
+//! variables it does not know (with the closest known name), values and list
+//! entries it does not understand (with what it expects and does instead),
+//! unknown token options, a token list that parses to no tokens, required
+//! tokens that were not set (see the required module), and a configuration
+//! or host policy file that cannot be used. `ok` is false if
+//! either list is not empty. Loading the library runs its constructors in
+//! the calling process, as preloading it would.
+//!
+//! The same problems are logged as warnings when the library is loaded, so
+//! a typo does not silently fall back on a default; `awf-preload --validate`
+//! prints the health check of a configuration without running a workload.
+
+use crate::control::copy_out;
+use crate::detect;
+use crate::log::{self, json_string};
+use crate::policy::{self, Mode, ScrubMode};
+use crate::{autodetect, canary, correlation, credfile, digest, dns, egress, environ, envname};
+use crate::{filewrite, kill, lock_state, logfile, net, profile, protection_disabled, ratelimit};
+use crate::{read_config_var, read_policy_bytes, required, seal, secretdir, sink};
+use crate::{verify, TokenState};
+use libc::{c_char, size_t, ssize_t};
+use std::ffi::CStr;
+
+/// libc functions the interposers cannot work without, on every platform
+const REQUIRED_SYMBOLS: [&CStr; 21] = [
+    c"getenv",
+    c"setenv",
+    c"unsetenv",
+    c"putenv",
+    c"execve",
+    c"posix_spawn",
+    c"posix_spawnp",
+    c"system",
+    c"popen",
+    c"fork",
+    c"open",
+    c"openat",
+    c"fopen",
+    c"freopen",
+    c"write",
+    c"pwrite",
+    c"send",
+    c"sendto",
+    c"sendmsg",
+    c"getaddrinfo",
+    c"connect",
+];
+
+/// How the value of a setting is checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Check {
+    /// A flag read with read_config_flag, and its default
+    Flag(bool),
+    /// A whole number
+    Number,
+    /// A value is_known_value accepts: what those are, and what the library
+    /// does with any other
+    Value(&'static str, &'static str),
+    /// Comma-separated entries is_known_entry accepts, and their form
+    List(&'static str),
+    /// Token lists, names and paths, checked elsewhere or not at all
+    Any,
+}
+
+/// Every setting the library reads, in the order of the crate documentation
+const SETTINGS: [(&CStr, Check); 103] = [
+    (c"AWF_ONE_SHOT_TOKENS", Check::Any),
+    (
+        c"AWF_ONE_SHOT_PRESETS",
+        Check::List("default, github, openai, anthropic, codex, aws, gcp, azure or npm"),
+    ),
+    (c"AWF_ONE_SHOT_EXTRA_TOKENS", Check::Any),
+    (
+        c"AWF_ONE_SHOT_TOKEN_ALIASES",
+        Check::List("ALIAS=CANONICAL"),
+    ),
+    (c"AWF_ONE_SHOT_DENY_TOKENS", Check::Any),
+    (c"AWF_ONE_SHOT_DENY_KILL", Check::Number),
+    (
+        c"AWF_ONE_SHOT_KILL_SIGNAL",
+        Check::Value("KILL, TERM, ABRT, INT, QUIT or HUP", "using SIGKILL"),
+    ),
+    (
+        c"AWF_ONE_SHOT_REQUIRED_ACTION",
+        Check::Value("abort or log", "aborting"),
+    ),
+    (c"AWF_ONE_SHOT_REQUIRED_CHECKED", Check::Any),
+    (c"AWF_ONE_SHOT_CONFIG", Check::Any),
+    (c"AWF_ONE_SHOT_POLICY_FILE", Check::Any),
+    (
+        c"AWF_ONE_SHOT_PROFILE",
+        Check::Value("minimal, standard or paranoid", "using standard"),
+    ),
+    (
+        c"AWF_ONE_SHOT_MODE",
+        Check::Value("enforce or observe", "enforcing"),
+    ),
+    (
+        c"AWF_ONE_SHOT_SCRUB",
+        Check::Value("unset, mask or placeholder", "using unset"),
+    ),
+    (
+        c"AWF_ONE_SHOT_CACHE_BACKEND",
+        Check::Value("memory or keyring", "using memory"),
+    ),
+    (c"AWF_ONE_SHOT_ARGV_SCRUB", Check::Flag(true)),
+    (c"AWF_ONE_SHOT_EAGER", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_FILE_SECRETS", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_BROKER_SOCKET", Check::Any),
+    (c"AWF_ONE_SHOT_BROKER_UID", Check::Number),
+    (c"AWF_ONE_SHOT_VAULT_SOCKET", Check::Any),
+    (c"AWF_ONE_SHOT_VAULT_UID", Check::Number),
+    (c"AWF_ONE_SHOT_MINT_TOKENS", Check::Any),
+    (c"AWF_ONE_SHOT_SWAP_SOCKET", Check::Any),
+    (c"AWF_ONE_SHOT_SWAP_UID", Check::Number),
+    (c"AWF_ONE_SHOT_REFRESH_COMMAND", Check::Any),
+    (c"AWF_ONE_SHOT_ENV_KEY_FD", Check::Number),
+    (c"AWF_ONE_SHOT_ENV_KEY_FILE", Check::Any),
+    (c"AWF_ONE_SHOT_SPLIT_TOKENS", Check::Flag(false)),
+    (
+        c"AWF_ONE_SHOT_TOKEN_DIGESTS",
+        Check::List("NAME=SHA256 with 64 hex digits"),
+    ),
+    (c"AWF_ONE_SHOT_SECRET_NAMESPACE", Check::Flag(true)),
+    (
+        c"AWF_ONE_SHOT_AUTODETECT",
+        Check::Value("off, log or protect", "protecting"),
+    ),
+    (c"AWF_ONE_SHOT_SECRETS_DIR", Check::Any),
+    (
+        c"AWF_ONE_SHOT_SECRETS_MAP",
+        Check::List("FILE=NAME or -FILE"),
+    ),
+    (c"AWF_ONE_SHOT_SECRETS_REVOKE", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_WATCHDOG", Check::Number),
+    (c"AWF_ONE_SHOT_SIGNALS", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_SIGNAL_CHAIN", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_CRASH_WIPE", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_STRICT_GRACE", Check::Number),
+    (c"AWF_ONE_SHOT_PROTECT_PROXY", Check::Flag(true)),
+    (c"AWF_ONE_SHOT_PROC_ENVIRON", Check::Flag(true)),
+    (
+        c"AWF_ONE_SHOT_PROC_SNOOP",
+        Check::Value("log, deny or allow", "logging"),
+    ),
+    (
+        c"AWF_ONE_SHOT_PROC_MEMORY",
+        Check::Value("deny, log or allow", "denying"),
+    ),
+    (c"AWF_ONE_SHOT_PROC_MEMORY_ALLOW", Check::Any),
+    (c"AWF_ONE_SHOT_DNS_AUDIT", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_DNS_RESOLVER", Check::List("an IP address")),
+    (
+        c"AWF_ONE_SHOT_DNS_EXFIL",
+        Check::Value("off, log, block or kill", "blocking"),
+    ),
+    (
+        c"AWF_ONE_SHOT_CORRELATION",
+        Check::List("on, env, connect or off"),
+    ),
+    (c"AWF_ONE_SHOT_TRACK_CHILDREN", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_ALLOWED_DOMAINS", Check::Any),
+    (
+        c"AWF_ONE_SHOT_METADATA_ACCESS",
+        Check::Value("block, log or allow", "blocking"),
+    ),
+    (
+        c"AWF_ONE_SHOT_EGRESS_SCAN",
+        Check::Value("off, log, block or kill", "blocking"),
+    ),
+    (c"AWF_ONE_SHOT_REDACT_OUTPUT", Check::Flag(false)),
+    (
+        c"AWF_ONE_SHOT_FILE_WRITE_SCAN",
+        Check::Value("off, redact or block", "blocking"),
+    ),
+    (c"AWF_ONE_SHOT_WORKSPACE_GUARD", Check::Flag(false)),
+    (
+        c"AWF_ONE_SHOT_COMMAND_FILE_SCAN",
+        Check::Value("off, redact or block", "blocking"),
+    ),
+    (c"AWF_ONE_SHOT_CANARIES", Check::List("NAME=VALUE")),
+    (c"AWF_ONE_SHOT_HONEYPOT", Check::Flag(false)),
+    (
+        c"AWF_ONE_SHOT_CREDENTIAL_FILES",
+        Check::List("PATH:POLICY with an absolute or ~/ path and allow, log or deny"),
+    ),
+    (c"AWF_ONE_SHOT_ALLOWED_COMMS", Check::Any),
+    (
+        c"AWF_ONE_SHOT_RATE_LIMIT",
+        Check::Value(
+            "READS/SECONDS, optionally with :log or :deny",
+            "not limiting",
+        ),
+    ),
+    (c"AWF_ONE_SHOT_QUIET_PERIOD", Check::Number),
+    (c"AWF_ONE_SHOT_PHASE_END_FILE", Check::Any),
+    (c"AWF_ONE_SHOT_IDLE_TIMEOUT", Check::Number),
+    (
+        c"AWF_ONE_SHOT_PARENT_POLICY",
+        Check::List("PARENT:POLICY with allow, log or deny"),
+    ),
+    (
+        c"AWF_ONE_SHOT_CALLER_POLICY",
+        Check::List("OBJECT:POLICY with allow, log or deny"),
+    ),
+    (c"AWF_ONE_SHOT_REPORT", Check::Any),
+    (c"AWF_ONE_SHOT_STEP_FILE", Check::Any),
+    (c"AWF_ONE_SHOT_STEP_WATCH", Check::Flag(false)),
+    (
+        c"AWF_ONE_SHOT_STEP_SIGNAL",
+        Check::Value("a signal such as USR2 or RTMIN+1", "not watching for one"),
+    ),
+    (c"AWF_ONE_SHOT_PRIVILEGE_WATCH", Check::Flag(true)),
+    (c"AWF_ONE_SHOT_STEP_SUMMARY", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_STATS_SHM", Check::Any),
+    (c"AWF_ONE_SHOT_LATENCY_SAMPLE", Check::Number),
+    (c"AWF_ONE_SHOT_NODUMP", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_NOCORE", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_SECCOMP", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_LANDLOCK", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_PROPAGATE", Check::Flag(true)),
+    (c"AWF_ONE_SHOT_LIB32", Check::Any),
+    (c"AWF_ONE_SHOT_LIB64", Check::Any),
+    (c"AWF_ONE_SHOT_LIB_AARCH64", Check::Any),
+    (
+        c"AWF_ONE_SHOT_LIB_SHA256",
+        Check::List("SHA256 with 64 hex digits"),
+    ),
+    (c"AWF_ONE_SHOT_DISABLE", Check::Flag(false)),
+    (
+        c"AWF_ONE_SHOT_TOKEN_DEBUG",
+        Check::Value("1 or true, or 0 or false", "not debugging"),
+    ),
+    (c"AWF_ONE_SHOT_TRACE", Check::Flag(false)),
+    (
+        c"AWF_ONE_SHOT_LOG_FORMAT",
+        Check::Value("text, json or firewall", "using text"),
+    ),
+    (c"AWF_ONE_SHOT_LOG_FILE", Check::Any),
+    (
+        c"AWF_ONE_SHOT_LOG_MAX_SIZE",
+        Check::Value("a size such as 512K, 10M or 1G", "using 10M"),
+    ),
+    (
+        c"AWF_ONE_SHOT_LOG_SYNC",
+        Check::Value("none, periodic or event", "not syncing"),
+    ),
+    (c"AWF_ONE_SHOT_LOG_FD", Check::Number),
+    (
+        c"AWF_ONE_SHOT_SYSLOG",
+        Check::Value("journald, syslog, 1 or 0", "not sending to syslog"),
+    ),
+    (c"AWF_ONE_SHOT_EVENT_SOCKET", Check::Any),
+    (
+        c"AWF_ONE_SHOT_OTLP_ENDPOINT",
+        Check::Value("http://host[:port][/path]", "not exporting"),
+    ),
+    (c"AWF_ONE_SHOT_OTLP_HEADERS", Check::List("KEY=VALUE")),
+    (c"AWF_ONE_SHOT_OTLP_SERVICE", Check::Any),
+    (
+        c"AWF_ONE_SHOT_SINK_FAILURE",
+        Check::Value("open or closed", "keeping open"),
+    ),
+    (c"AWF_ONE_SHOT_AUDIT_CHAIN", Check::Flag(false)),
+    (c"AWF_ONE_SHOT_AUDIT_KEY_FD", Check::Number),
+    (c"AWF_ONE_SHOT_AUDIT_KEY_FILE", Check::Any),
+    (c"AWF_ONE_SHOT_READY_FD", Check::Number),
+    (
+        c"AWF_ONE_SHOT_VERIFY",
+        Check::Value("1, 0 or an absolute path", "not verifying"),
+    ),
+];
+
+/// Settings whose entries name decoys or carry credentials, and are not
+/// repeated in messages
+const SECRET_LISTS: [&str; 2] = ["AWF_ONE_SHOT_CANARIES", "AWF_ONE_SHOT_OTLP_HEADERS"];
+
+/// Whether `value` is one of the values of `setting`
+fn is_known_value(setting: &CStr, value: &str) -> bool {
+    match setting.to_bytes() {
+        b"AWF_ONE_SHOT_MODE" => Mode::parse(value).is_some(),
+        b"AWF_ONE_SHOT_SCRUB" => ScrubMode::parse(value).is_some(),
+        b"AWF_ONE_SHOT_CACHE_BACKEND" => seal::Backend::parse(value).is_some(),
+        b"AWF_ONE_SHOT_PROC_SNOOP" | b"AWF_ONE_SHOT_PROC_MEMORY" => {
+            credfile::FilePolicy::parse(value).is_some()
+        }
+        b"AWF_ONE_SHOT_LOG_FORMAT" => log::is_format(value),
+        b"AWF_ONE_SHOT_PROFILE" => profile::Profile::parse(value).is_some(),
+        b"AWF_ONE_SHOT_KILL_SIGNAL" => kill::is_signal(value),
+        b"AWF_ONE_SHOT_REQUIRED_ACTION" => required::is_action(value),
+        b"AWF_ONE_SHOT_AUTODETECT" => autodetect::is_action(value),
+        b"AWF_ONE_SHOT_DNS_EXFIL" => dns::is_exfil_policy(value),
+        b"AWF_ONE_SHOT_METADATA_ACCESS" => net::is_metadata_policy(value),
+        b"AWF_ONE_SHOT_EGRESS_SCAN" => egress::is_policy(value),
+        b"AWF_ONE_SHOT_FILE_WRITE_SCAN" | b"AWF_ONE_SHOT_COMMAND_FILE_SCAN" => {
+            filewrite::is_policy(value)
+        }
+        b"AWF_ONE_SHOT_RATE_LIMIT" => ratelimit::RateLimit::parse(value).is_some(),
+        b"AWF_ONE_SHOT_SINK_FAILURE" => sink::Policy::parse(value).is_some(),
+        b"AWF_ONE_SHOT_LOG_MAX_SIZE" => logfile::parse_size(value).is_some(),
+        b"AWF_ONE_SHOT_LOG_SYNC" => logfile::Durability::parse(value).is_some(),
+        b"AWF_ONE_SHOT_VERIFY" => verify::is_output(value),
+        b"AWF_ONE_SHOT_TOKEN_DEBUG" => matches!(
+            value.to_ascii_lowercase().as_str(),
+            "1" | "true" | "0" | "false"
+        ),
+        #[cfg(not(feature = "minimal"))]
+        b"AWF_ONE_SHOT_STEP_SIGNAL" => crate::signals::parse_signal(value).is_some(),
+        #[cfg(not(feature = "minimal"))]
+        b"AWF_ONE_SHOT_SYSLOG" => crate::syslog::is_request(value),
+        #[cfg(not(feature = "minimal"))]
+        b"AWF_ONE_SHOT_OTLP_ENDPOINT" => crate::otlp::is_endpoint(value),
+        _ => true,
+    }
+}
+
+/// Whether `entry`, one of the comma-separated entries of `setting`, is used
+fn is_known_entry(setting: &CStr, entry: &str) -> bool {
+    // PATH:POLICY and NAME:POLICY entries
+    let policy_entry = |absolute: bool| {
+        entry.rsplit_once(':').is_some_and(|(path, policy)| {
+            let path = path.trim();
+            let path_ok = if absolute {
+                path.starts_with('/') || path.starts_with("~/")
+            } else {
+                !path.is_empty()
+            };
+            path_ok && credfile::FilePolicy::parse(policy.trim()).is_some()
+        })
+    };
+    match setting.to_bytes() {
+        b"AWF_ONE_SHOT_PRESETS" => {
+            entry.trim().eq_ignore_ascii_case("default") || detect::preset(entry).is_some()
+        }
+        b"AWF_ONE_SHOT_TOKEN_ALIASES" => !crate::parse_token_aliases(entry.as_bytes()).is_empty(),
+        b"AWF_ONE_SHOT_TOKEN_DIGESTS" => !digest::parse(entry.as_bytes()).is_empty(),
+        b"AWF_ONE_SHOT_LIB_SHA256" => {
+            let hex = entry.trim();
+            let hex = hex.strip_prefix("sha256:").unwrap_or(hex);
+            hex.len() == 64 && hex.bytes().all(|byte| byte.is_ascii_hexdigit())
+        }
+        b"AWF_ONE_SHOT_SECRETS_MAP" => !secretdir::parse_map(entry).is_empty(),
+        b"AWF_ONE_SHOT_DNS_RESOLVER" => !dns::parse_resolvers(entry).is_empty(),
+        b"AWF_ONE_SHOT_CORRELATION" => correlation::Correlation::parse(entry).1.is_empty(),
+        b"AWF_ONE_SHOT_CANARIES" => !canary::parse_canaries(entry).is_empty(),
+        b"AWF_ONE_SHOT_CREDENTIAL_FILES" => policy_entry(true),
+        b"AWF_ONE_SHOT_PARENT_POLICY" | b"AWF_ONE_SHOT_CALLER_POLICY" => policy_entry(false),
+        #[cfg(not(feature = "minimal"))]
+        b"AWF_ONE_SHOT_OTLP_HEADERS" => !crate::otlp::parse_headers(entry).is_empty(),
+        _ => true,
+    }
+}
+
+/// Problems with `value`, the value of `setting`
+fn value_problems(setting: &CStr, check: Check, value: &str) -> Vec<String> {
+    let name = setting.to_string_lossy();
+    if value.trim().is_empty() {
+        return Vec::new();
+    }
+    let unknown = |expected: &str, fallback: &str| {
+        vec![format!(
+            "{}: unknown value '{}', expected {}; {}",
+            name, value, expected, fallback
+        )]
+    };
+    match check {
+        Check::Flag(default) if !is_flag(value) => unknown(
+            "1, true, yes or on, or 0, false, no or off",
+            if default {
+                "leaving it on"
+            } else {
+                "leaving it off"
+            },
+        ),
+        Check::Number if value.trim().parse::<u64>().is_err() => {
+            unknown("a whole number", "ignoring it")
+        }
+        Check::Value(expected, fallback) if !is_known_value(setting, value) => {
+            unknown(expected, fallback)
+        }
+        Check::List(form) => value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty() && !is_known_entry(setting, entry))
+            .map(|entry| {
+                if SECRET_LISTS.contains(&&*name) {
+                    format!("{}: ignoring an entry, expected {}", name, form)
+                } else {
+                    format!("{}: ignoring '{}', expected {}", name, entry, form)
+                }
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether read_config_flag understands `value`
+fn is_flag(value: &str) -> bool {
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "1" | "true" | "yes" | "on" | "0" | "false" | "no" | "off"
+    )
+}
+
+/// Names of the AWF_ONE_SHOT_ variables in the environment
+///
+/// Reads environ directly rather than copying every entry, values included.
+fn environment_settings() -> Vec<String> {
+    let mut names = Vec::new();
+    // SAFETY: environ is a null-terminated array of valid C strings
+    unsafe {
+        let mut entry = environ::current();
+        while !entry.is_null() && !(*entry).is_null() {
+            let bytes = CStr::from_ptr(*entry).to_bytes();
+            let name = bytes.split(|&byte| byte == b'=').next().unwrap_or_default();
+            if name.starts_with(b"AWF_ONE_SHOT_") {
+                names.push(String::from_utf8_lossy(name).into_owned());
+            }
+            entry = entry.add(1);
+        }
+    }
+    names
+}
+
+/// The message for an unknown setting, suggesting the closest known name
+fn unknown_setting(name: &str) -> String {
+    let closest = SETTINGS
+        .iter()
+        .map(|(setting, _)| setting.to_str().unwrap_or_default())
+        .map(|setting| (edit_distance(name, setting), setting))
+        .min()
+        .filter(|&(distance, _)| distance <= 3);
+    match closest {
+        Some((_, setting)) => format!("{}: unknown setting, did you mean {}?", name, setting),
+        None => format!("{}: unknown setting", name),
+    }
+}
+
+/// Levenshtein distance between two ASCII names
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.as_bytes();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, &ca) in a.as_bytes().iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The required symbols dlsym(RTLD_NEXT) does not find
+fn missing_symbols() -> Vec<&'static CStr> {
+    REQUIRED_SYMBOLS
+        .into_iter()
+        // SAFETY: dlsym with RTLD_NEXT and a valid C string
+        .filter(|name| unsafe { libc::dlsym(libc::RTLD_NEXT, name.as_ptr()) }.is_null())
+        .collect()
+}
+
+/// Problems with the entries of a token list setting
+///
+/// Removals ("-NAME") are only allowed in AWF_ONE_SHOT_EXTRA_TOKENS.
+fn token_list_problems(setting: &str, config: &[u8], removals: bool) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut tokens = 0;
+    for entry in envname::split_list(config) {
+        if removals && entry.starts_with(b"-") {
+            continue;
+        }
+        let Some((spec, unknown)) = policy::parse_token_spec(entry) else {
+            continue;
+        };
+        tokens += 1;
+        if !unknown.is_empty() {
+            problems.push(format!(
+                "{}: unknown option(s) {} for {}",
+                setting,
+                unknown.join(","),
+                spec.name
+            ));
+        }
+    }
+    if !removals && tokens == 0 && !config.trim_ascii().is_empty() {
+        problems.push(format!("{}: no tokens, using the defaults", setting));
+    }
+    problems
+}
+
+/// Settings the library ignores or falls back on
+fn config_problems(state: &TokenState) -> Vec<String> {
+    let mut problems: Vec<String> = environment_settings()
+        .iter()
+        .filter(|name| {
+            !SETTINGS
+                .iter()
+                .any(|(setting, _)| setting.to_bytes() == name.as_bytes())
+        })
+        .map(|name| unknown_setting(name))
+        .collect();
+    for (setting, check) in SETTINGS {
+        if let Some(value) = read_config_var(setting) {
+            problems.extend(value_problems(setting, check, &value));
+        }
+    }
+    if protection_disabled() {
+        return problems;
+    }
+    if let Some(config) = read_policy_bytes(state, c"AWF_ONE_SHOT_TOKENS") {
+        problems.extend(token_list_problems("AWF_ONE_SHOT_TOKENS", &config, false));
+    }
+    if let Some(config) = read_policy_bytes(state, c"AWF_ONE_SHOT_EXTRA_TOKENS") {
+        problems.extend(token_list_problems(
+            "AWF_ONE_SHOT_EXTRA_TOKENS",
+            &config,
+            true,
+        ));
+    }
+    if let Some(config) = state.config.as_ref() {
+        if !config.readable() {
+            problems.push(format!(
+                "AWF_ONE_SHOT_CONFIG: cannot read {}",
+                config.path()
+            ));
+        } else if !config.unknown().is_empty() {
+            problems.push(format!(
+                "AWF_ONE_SHOT_CONFIG: unknown setting(s) {} in {}, expected \
+                 AWF_ONE_SHOT_TOKENS, AWF_ONE_SHOT_EXTRA_TOKENS or AWF_ONE_SHOT_DENY_TOKENS",
+                config.unknown().join(","),
+                config.path()
+            ));
+        }
+    }
+    let policy_file = read_config_var(c"AWF_ONE_SHOT_POLICY_FILE")
+        .map(|path| path.trim().to_string())
+        .filter(|path| !path.is_empty());
+    if let Some(path) = policy_file.filter(|_| state.host_policy.is_none()) {
+        problems.push(format!(
+            "AWF_ONE_SHOT_POLICY_FILE: {} is missing, untrusted or invalid",
+            path
+        ));
+    }
+    problems
+}
+
+/// Log every configuration problem as a warning, once the state is loaded
+pub(crate) fn warn_config_problems(state: &TokenState) {
+    for problem in config_problems(state) {
+        log_line!(Warning, "config", None, "{}", problem);
+    }
+}
+
+/// The health check as a single JSON object
+fn status() -> String {
+    let state = lock_state();
+    let mut problems = config_problems(&state);
+    problems.extend(
+        required::missing_at_load()
+            .iter()
+            .map(|name| format!("{}: required token not set", name)),
+    );
+    let tokens = state.tokens.len();
+    drop(state);
+    let missing = missing_symbols();
+
+    let json_list = |items: Vec<String>| -> String {
+        let items: Vec<String> = items.iter().map(|item| json_string(item)).collect();
+        items.join(",")
+    };
+    format!(
+        concat!(
+            "{{\"version\":{},\"ok\":{},\"disabled\":{},\"tokens\":{},",
+            "\"symbols\":{{\"ok\":{},\"missing\":[{}]}},",
+            "\"config\":{{\"ok\":{},\"problems\":[{}]}}}}"
+        ),
+        json_string(env!("CARGO_PKG_VERSION")),
+        missing.is_empty() && problems.is_empty(),
+        protection_disabled(),
+        tokens,
+        missing.is_empty(),
+        json_list(
+            missing
+                .iter()
+                .map(|name| name.to_string_lossy().into_owned())
+                .collect()
+        ),
+        problems.is_empty(),
+        json_list(problems)
+    )
+}
+
+/// Write the health check as JSON into `buf`
+///
+/// Like snprintf, at most `len - 1` bytes are written followed by a NUL, and
+/// the length of the full status is returned; `buf` may be NULL if `len` is 0.
+///
+/// # Safety
+/// `buf` must be valid for writes of `len` bytes
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn awf_one_shot_healthcheck(buf: *mut c_char, len: size_t) -> ssize_t {
+    copy_out(&status(), buf, len)
+}
+
+// Bound to AWF_1.1 in awf.map (see the control module)
+#[cfg(all(feature = "preload", not(target_os = "macos")))]
+std::arch::global_asm!(".symver awf_one_shot_healthcheck, awf_one_shot_healthcheck@@@AWF_1.1");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_list_problems() {
+        assert!(
+            token_list_problems("AWF_ONE_SHOT_TOKENS", b"GITHUB_TOKEN:strict", false).is_empty()
+        );
+        assert_eq!(
+            token_list_problems(
+                "AWF_ONE_SHOT_TOKENS",
+                b"GITHUB_TOKEN:max_reads=0, NPM_TOKEN:stirct",
+                false
+            ),
+            vec![
+                "AWF_ONE_SHOT_TOKENS: unknown option(s) max_reads=0 for GITHUB_TOKEN",
+                "AWF_ONE_SHOT_TOKENS: unknown option(s) stirct for NPM_TOKEN",
+            ]
+        );
+        assert_eq!(
+            token_list_problems("AWF_ONE_SHOT_TOKENS", b" , :redact", false),
+            vec!["AWF_ONE_SHOT_TOKENS: no tokens, using the defaults"]
+        );
+        assert!(token_list_problems("AWF_ONE_SHOT_EXTRA_TOKENS", b"-GH_TOKEN", true).is_empty());
+    }
+
+    #[test]
+    fn test_value_problems() {
+        let check = |setting: &CStr, value: &str| {
+            let (_, check) = SETTINGS
+                .into_iter()
+                .find(|(known, _)| *known == setting)
+                .unwrap();
+            value_problems(setting, check, value)
+        };
+        assert!(check(c"AWF_ONE_SHOT_MODE", "observe").is_empty());
+        assert!(check(c"AWF_ONE_SHOT_MODE", " ").is_empty());
+        assert_eq!(
+            check(c"AWF_ONE_SHOT_MODE", "enforcing"),
+            vec!["AWF_ONE_SHOT_MODE: unknown value 'enforcing', expected enforce or observe; enforcing"]
+        );
+        assert_eq!(
+            check(c"AWF_ONE_SHOT_PROPAGATE", "enabled"),
+            vec![
+                "AWF_ONE_SHOT_PROPAGATE: unknown value 'enabled', expected 1, true, yes or on, \
+                 or 0, false, no or off; leaving it on"
+            ]
+        );
+        assert!(check(c"AWF_ONE_SHOT_EAGER", "Yes").is_empty());
+        assert_eq!(
+            check(c"AWF_ONE_SHOT_DENY_KILL", "3x"),
+            vec![
+                "AWF_ONE_SHOT_DENY_KILL: unknown value '3x', expected a whole number; ignoring it"
+            ]
+        );
+        assert!(check(c"AWF_ONE_SHOT_LOG_MAX_SIZE", "512K").is_empty());
+        assert_eq!(
+            check(
+                c"AWF_ONE_SHOT_CREDENTIAL_FILES",
+                "~/.netrc:deny, .npmrc:deny,/x:block"
+            ),
+            vec![
+                "AWF_ONE_SHOT_CREDENTIAL_FILES: ignoring '.npmrc:deny', expected PATH:POLICY \
+                 with an absolute or ~/ path and allow, log or deny",
+                "AWF_ONE_SHOT_CREDENTIAL_FILES: ignoring '/x:block', expected PATH:POLICY \
+                 with an absolute or ~/ path and allow, log or deny",
+            ]
+        );
+        // Decoy names and values are not repeated
+        assert_eq!(
+            check(c"AWF_ONE_SHOT_CANARIES", "AWS_KEY=decoy,OOPS"),
+            vec!["AWF_ONE_SHOT_CANARIES: ignoring an entry, expected NAME=VALUE"]
+        );
+        assert!(check(c"AWF_ONE_SHOT_TOKEN_ALIASES", "GH_PAT=GITHUB_TOKEN").is_empty());
+    }
+
+    #[test]
+    fn test_unknown_setting() {
+        assert_eq!(
+            unknown_setting("AWF_ONE_SHOT_TOKEN"),
+            "AWF_ONE_SHOT_TOKEN: unknown setting, did you mean AWF_ONE_SHOT_TOKENS?"
+        );
+        assert_eq!(
+            unknown_setting("AWF_ONE_SHOT_DEBUG_EVERYTHING"),
+            "AWF_ONE_SHOT_DEBUG_EVERYTHING: unknown setting"
+        );
+        assert_eq!(edit_distance("MODE", "MOOD"), 2);
+    }
+
+    #[test]
+    fn test_settings_cover_propagated_config() {
+        for name in crate::exec::PROPAGATED_CONFIG
+            .iter()
+            .filter(|name| name.starts_with("AWF_ONE_SHOT_"))
+        {
+            assert!(
+                SETTINGS
+                    .iter()
+                    .any(|(setting, _)| setting.to_bytes() == name.as_bytes()),
+                "{} is not checked",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_required_symbols_resolve() {
+        assert!(missing_symbols().is_empty());
+    }
+
+    #[test]
+    fn test_header_declaration() {
+        let header = include_str!("../include/one_shot_token.h");
+        let map = include_str!("../awf.map");
+        assert!(header.contains("ssize_t awf_one_shot_healthcheck(char *buf, size_t len);"));
+        assert!(
+            map.contains("AWF_1.1 {\n    global:\n        awf_one_shot_healthcheck;\n} AWF_1.0;")
+        );
+    }
+}
@@ -0,0 +1,317 @@
+//! Token policy provisioned by the host (/run/awf/token-policy.json)
+//!
+//! The gh-aw host writes the token list and per-token policies to a JSON
+//! file when it starts the container, the way the firewall already ships its
+//! allowlists, so the environment carries no policy details:
+//!
+//! ```json
+//! {
+//!   "tokens": [
+//!     "OPENAI_API_KEY",
+//!     {"name": "GITHUB_TOKEN", "policy": "strict", "max_reads": 2, "ttl": 600,
+//!      "refresh": 3000, "expires_at": "2026-10-17T12:00:00Z",
+//!      "exe": ["/usr/bin/gh"]}
+//!   ],
+//!   "deny": ["ACTIONS_RUNTIME_TOKEN"],
+//!   "aliases": {"GH_TOKEN": "GITHUB_TOKEN"}
+//! }
+//! ```
+//!
+//! Each member present replaces the matching variable: `tokens` stands for
+//! AWF_ONE_SHOT_TOKENS (and AWF_ONE_SHOT_EXTRA_TOKENS is ignored, since the
+//! list is complete), `deny` for AWF_ONE_SHOT_DENY_TOKENS and `aliases` for
+//! AWF_ONE_SHOT_TOKEN_ALIASES. Other members are ignored. `expires_at` is
+//! the expiry of the credential the host minted, so agents stop retrying
+//! with a dead token once it has passed.
+//!
+//! The file is only trusted if it is owned by root and not writable by group
+//! or others: the agent must not be able to loosen its own policy. A file
+//! that is untrusted, unreadable or malformed is ignored with a `host_policy`
+//! audit event, and the variables apply as if it did not exist.
+//! AWF_ONE_SHOT_POLICY_FILE names another path (empty to skip the file).
+
+use crate::json::{self, Value};
+use crate::{audit, open, policy, procfs};
+use std::ffi::{CStr, CString};
+
+/// Where the host provisions the policy
+pub(crate) const DEFAULT_PATH: &str = "/run/awf/token-policy.json";
+
+/// Numeric token options, named as in AWF_ONE_SHOT_TOKENS
+const NUMBER_OPTIONS: [&str; 3] = ["max_reads", "ttl", "refresh"];
+
+/// Policies a token entry may name
+const POLICIES: [&str; 5] = ["cache", "redact", "strict", "derive", "placeholder"];
+
+/// The settings taken from the policy file
+#[derive(Debug)]
+pub(crate) struct HostPolicy {
+    settings: Vec<(&'static str, Vec<u8>)>,
+}
+
+impl HostPolicy {
+    /// Load the policy at `path`
+    ///
+    /// Returns None, after emitting an audit event unless the file does not
+    /// exist, if the file cannot be used.
+    pub(crate) fn load(path: &str) -> Option<Self> {
+        let path_cstr = CString::new(path).ok()?;
+        let result = read_trusted(&path_cstr)
+            .and_then(|content| json::parse(&content).map_err(|err| ("invalid", err)))
+            .and_then(|document| settings(&document).map_err(|err| ("invalid", err)));
+        match result {
+            Ok(settings) => Some(HostPolicy { settings }),
+            Err(("missing", _)) => None,
+            Err((action, err)) => {
+                audit::emit(
+                    "host_policy",
+                    "*",
+                    &format!(
+                        "action={} path={} error={}",
+                        action,
+                        audit::detail_word(path),
+                        audit::detail_word(&err)
+                    ),
+                );
+                None
+            }
+        }
+    }
+
+    /// The value the policy gives `name`, if any
+    pub(crate) fn get(&self, name: &CStr) -> Option<&[u8]> {
+        self.settings
+            .iter()
+            .find(|(setting, _)| setting.as_bytes() == name.to_bytes())
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Number of settings the file replaces
+    pub(crate) fn len(&self) -> usize {
+        self.settings.len()
+    }
+}
+
+/// Read the file at `path` if root owns it and only root can write it
+fn read_trusted(path: &CStr) -> Result<Vec<u8>, (&'static str, String)> {
+    // SAFETY: path is a valid C string; the descriptor is closed below
+    let fd = unsafe { open::call_real_open(path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) };
+    if fd < 0 {
+        let err = std::io::Error::last_os_error();
+        let action = match err.raw_os_error() {
+            Some(libc::ENOENT) => "missing",
+            _ => "unreadable",
+        };
+        return Err((action, err.to_string()));
+    }
+    // SAFETY: stat is plain data, and fd is open
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    let result = if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+        Err(("unreadable", std::io::Error::last_os_error().to_string()))
+    } else if stat.st_uid != 0 || stat.st_mode & 0o022 != 0 {
+        Err((
+            "untrusted",
+            format!("owner {} mode {:o}", stat.st_uid, stat.st_mode & 0o7777),
+        ))
+    } else {
+        // SAFETY: fd is open
+        unsafe { procfs::read_fd(fd) }.ok_or(("unreadable", "read failed".to_string()))
+    };
+    // SAFETY: fd is open and not used afterwards
+    unsafe { libc::close(fd) };
+    result
+}
+
+/// Whether `name` can be written into the comma-separated settings
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains([',', ':', '=', '\0'])
+}
+
+/// Translate a policy document into the variables it replaces
+fn settings(document: &Value) -> Result<Vec<(&'static str, Vec<u8>)>, String> {
+    if !matches!(document, Value::Object(_)) {
+        return Err("not an object".to_string());
+    }
+    let mut settings = Vec::new();
+
+    if let Some(tokens) = document.get("tokens") {
+        let Value::Array(tokens) = tokens else {
+            return Err("tokens is not an array".to_string());
+        };
+        let entries = tokens
+            .iter()
+            .map(token_entry)
+            .collect::<Result<Vec<String>, String>>()?;
+        settings.push(("AWF_ONE_SHOT_TOKENS", entries.join(",").into_bytes()));
+        settings.push(("AWF_ONE_SHOT_EXTRA_TOKENS", Vec::new()));
+    }
+
+    if let Some(deny) = document.get("deny") {
+        let names = string_array(deny, "deny")?;
+        if let Some(name) = names.iter().find(|name| !valid_name(name)) {
+            return Err(format!("invalid deny name {:?}", name));
+        }
+        settings.push(("AWF_ONE_SHOT_DENY_TOKENS", names.join(",").into_bytes()));
+    }
+
+    if let Some(aliases) = document.get("aliases") {
+        let Value::Object(members) = aliases else {
+            return Err("aliases is not an object".to_string());
+        };
+        let mut pairs = Vec::new();
+        for (alias, canonical) in members {
+            let canonical = canonical
+                .as_str()
+                .ok_or_else(|| format!("alias {:?} is not a string", alias))?;
+            if !valid_name(alias) || !valid_name(canonical) {
+                return Err(format!("invalid alias {:?}", alias));
+            }
+            pairs.push(format!("{}={}", alias, canonical));
+        }
+        settings.push(("AWF_ONE_SHOT_TOKEN_ALIASES", pairs.join(",").into_bytes()));
+    }
+
+    Ok(settings)
+}
+
+/// An array of strings
+fn string_array<'a>(value: &'a Value, member: &str) -> Result<Vec<&'a str>, String> {
+    let Value::Array(elements) = value else {
+        return Err(format!("{} is not an array", member));
+    };
+    elements
+        .iter()
+        .map(|element| {
+            element
+                .as_str()
+                .ok_or_else(|| format!("{} holds a non-string", member))
+        })
+        .collect()
+}
+
+/// Translate a `tokens` element into AWF_ONE_SHOT_TOKENS entry syntax
+fn token_entry(token: &Value) -> Result<String, String> {
+    if let Some(name) = token.as_str() {
+        return match valid_name(name) {
+            true => Ok(name.to_string()),
+            false => Err(format!("invalid token name {:?}", name)),
+        };
+    }
+    let name = token
+        .get("name")
+        .and_then(Value::as_str)
+        .ok_or("token without a name")?;
+    if !valid_name(name) {
+        return Err(format!("invalid token name {:?}", name));
+    }
+    let mut entry = name.to_string();
+
+    if let Some(policy) = token.get("policy") {
+        match policy.as_str() {
+            Some("cache") => {}
+            Some(policy) if POLICIES.contains(&policy) => entry.push_str(&format!(":{}", policy)),
+            _ => return Err(format!("invalid policy for {}", name)),
+        }
+    }
+    for option in NUMBER_OPTIONS {
+        if let Some(value) = token.get(option) {
+            let value = value
+                .as_u64()
+                .ok_or_else(|| format!("invalid {} for {}", option, name))?;
+            entry.push_str(&format!(":{}={}", option, value));
+        }
+    }
+    if let Some(expires_at) = token.get("expires_at") {
+        let expires_at = expires_at
+            .as_str()
+            .filter(|value| policy::parse_expires_at(value).is_some())
+            .ok_or_else(|| format!("invalid expires_at for {}", name))?;
+        entry.push_str(&format!(":expires_at={}", expires_at.trim()));
+    }
+    if let Some(exes) = token.get("exe") {
+        for exe in string_array(exes, "exe")? {
+            if !exe.starts_with('/') || exe.contains([',', ':', '\0']) {
+                return Err(format!("invalid exe {:?} for {}", exe, name));
+            }
+            entry.push_str(&format!(":exe={}", exe));
+        }
+    }
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings_of(document: &str) -> Result<Vec<(&'static str, String)>, String> {
+        let document = json::parse(document.as_bytes()).unwrap();
+        settings(&document).map(|settings| {
+            settings
+                .into_iter()
+                .map(|(name, value)| (name, String::from_utf8(value).unwrap()))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn test_settings() {
+        let settings = settings_of(
+            r#"{"version": 1,
+                "tokens": ["OPENAI_API_KEY",
+                           {"name": "GITHUB_TOKEN", "policy": "strict", "max_reads": 2,
+                            "ttl": 600, "exe": ["/usr/bin/gh", "/usr/bin/git"]},
+                           {"name": "NPM_TOKEN", "policy": "cache",
+                            "expires_at": "2026-10-17T12:00:00Z"}],
+                "deny": ["ACTIONS_RUNTIME_TOKEN"],
+                "aliases": {"GH_TOKEN": "GITHUB_TOKEN"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            vec![
+                (
+                    "AWF_ONE_SHOT_TOKENS",
+                    "OPENAI_API_KEY,GITHUB_TOKEN:strict:max_reads=2:ttl=600:\
+                     exe=/usr/bin/gh:exe=/usr/bin/git,\
+                     NPM_TOKEN:expires_at=2026-10-17T12:00:00Z"
+                        .to_string()
+                ),
+                ("AWF_ONE_SHOT_EXTRA_TOKENS", String::new()),
+                (
+                    "AWF_ONE_SHOT_DENY_TOKENS",
+                    "ACTIONS_RUNTIME_TOKEN".to_string()
+                ),
+                (
+                    "AWF_ONE_SHOT_TOKEN_ALIASES",
+                    "GH_TOKEN=GITHUB_TOKEN".to_string()
+                ),
+            ]
+        );
+        assert_eq!(settings_of(r#"{"deny": []}"#).unwrap().len(), 1);
+        assert!(settings_of("{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_settings_invalid() {
+        for document in [
+            r#"[]"#,
+            r#"{"tokens": "GITHUB_TOKEN"}"#,
+            r#"{"tokens": ["A,B"]}"#,
+            r#"{"tokens": [{"policy": "strict"}]}"#,
+            r#"{"tokens": [{"name": "A", "policy": "lenient"}]}"#,
+            r#"{"tokens": [{"name": "A", "max_reads": -1}]}"#,
+            r#"{"tokens": [{"name": "A", "exe": ["gh"]}]}"#,
+            r#"{"tokens": [{"name": "A", "expires_at": "tomorrow"}]}"#,
+            r#"{"tokens": [{"name": "A", "expires_at": 1792238400}]}"#,
+            r#"{"deny": ["A:B"]}"#,
+            r#"{"aliases": {"GH_TOKEN": 1}}"#,
+        ] {
+            assert!(settings_of(document).is_err(), "{}", document);
+        }
+    }
+
+    #[test]
+    fn test_load_missing() {
+        assert!(HostPolicy::load("/nonexistent/awf/token-policy.json").is_none());
+    }
+}
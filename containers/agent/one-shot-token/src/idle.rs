@@ -0,0 +1,256 @@
+//! Wipe of the cache after a long idle time (AWF_ONE_SHOT_IDLE_TIMEOUT)
+//!
+//! An agent process that read its tokens and then sat idle for hours keeps
+//! the plaintext values in memory the whole time, where a crash dump, a
+//! debugger or a later prompt injection can find them. With
+//! AWF_ONE_SHOT_IDLE_TIMEOUT set to a number of minutes, once no protected
+//! token has been read for that long (counting from load when none has been
+//! read), every cached value is zeroized with an `idle_wipe` audit event.
+//! Tokens the broker mints are dropped from the cache, so they are minted
+//! again on their next read.
+//!
+//! The next read of another wiped token fetches a fresh value the way the
+//! `refresh` option does: from AWF_ONE_SHOT_REFRESH_COMMAND, or else minted
+//! by the broker, so the host decides whether the process still gets one.
+//! Without either, or if the fetch fails, the read returns NULL with an
+//! `idle_wiped` event; a failed fetch is retried on the first read
+//! refresh::RETRY later.
+//!
+//! A background thread wipes the cache when the timeout elapses, since reads
+//! served from the lock-free snapshot never take the state lock; reads that
+//! do take it check as well. Like the watchdog thread, it is restarted in
+//! forked children. In observe mode nothing is wiped: an `observed` event
+//! reports what would have been.
+
+use crate::envname::EnvName;
+use crate::{
+    alloc_cached_value, audit, egress, fetch_fresh_value, lock_state, read_config_var, refresh,
+    secretfile, Mode, TokenState,
+};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Why reads of a wiped token are refused, and the event they emit
+const REASON: &str = "idle_wiped";
+
+/// Least time the thread sleeps between two checks
+const MIN_WAIT: Duration = Duration::from_secs(1);
+
+/// The idle timeout and when the idle time last started over
+pub(crate) struct Idle {
+    timeout: Option<Duration>,
+    /// Load, or the latest wipe; reads after it count from their own time
+    since: SystemTime,
+}
+
+impl Default for Idle {
+    fn default() -> Self {
+        Idle {
+            timeout: None,
+            since: SystemTime::now(),
+        }
+    }
+}
+
+/// Parse AWF_ONE_SHOT_IDLE_TIMEOUT, a positive number of minutes
+pub(crate) fn parse_idle_timeout(value: &str) -> Option<Duration> {
+    let minutes = value
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|&minutes| minutes > 0)?;
+    Some(Duration::from_secs(minutes.checked_mul(60)?))
+}
+
+impl Idle {
+    pub(crate) fn load() -> Self {
+        Idle {
+            timeout: read_config_var(c"AWF_ONE_SHOT_IDLE_TIMEOUT")
+                .and_then(|value| parse_idle_timeout(&value)),
+            ..Idle::default()
+        }
+    }
+}
+
+/// When a protected token was last read, or the idle time started over
+fn last_activity(state: &TokenState) -> SystemTime {
+    state
+        .cache
+        .values()
+        .filter_map(|entry| entry.last_read)
+        .fold(state.idle.since, SystemTime::max)
+}
+
+/// How long the process has been idle at `now`
+fn idle_time(state: &TokenState, now: SystemTime) -> Duration {
+    now.duration_since(last_activity(state)).unwrap_or_default()
+}
+
+/// Wipe every cached value if no token was read for the idle timeout
+pub(crate) fn check(state: &mut TokenState) {
+    let Some(timeout) = state.idle.timeout else {
+        return;
+    };
+    let now = SystemTime::now();
+    let idle = idle_time(state, now);
+    if idle < timeout {
+        return;
+    }
+    state.idle.since = now;
+
+    let live = |entry: &crate::CachedToken| !entry.value.is_null() && entry.wiped.is_none();
+    let count = state.cache.values().filter(|entry| live(entry)).count();
+    if count == 0 {
+        return;
+    }
+    let detail = format!("idle={} wiped={}", idle.as_secs(), count);
+    if state.mode == Mode::Observe {
+        audit::emit("observed", "*", &format!("would=idle_wipe {}", detail));
+        return;
+    }
+    for entry in state.cache.values_mut().filter(|entry| live(entry)) {
+        entry.wipe(REASON);
+        entry.refresh_at = None;
+    }
+    let mint = &state.mint;
+    state.cache.retain(|name, _| !mint.contains(name));
+    egress::publish_secrets(state);
+    audit::emit("idle_wipe", "*", &detail);
+}
+
+/// Fetch a fresh value for `canonical` if the idle wipe zeroized it
+///
+/// # Safety
+/// Runs the refresh helper or performs system calls on the broker's socket
+pub(crate) unsafe fn refetch(state: &mut TokenState, canonical: &EnvName) {
+    let due = state.cache.get(canonical).is_some_and(|entry| {
+        !entry.locked
+            && entry.wiped == Some(REASON)
+            && entry.refresh_at.is_none_or(|at| Instant::now() >= at)
+    });
+    if !due {
+        return;
+    }
+    let Some(fresh) = fetch_fresh_value(state, canonical) else {
+        if let Some(entry) = state.cache.get_mut(canonical) {
+            entry.refresh_at = Some(Instant::now() + refresh::RETRY);
+        }
+        return;
+    };
+
+    let cached = alloc_cached_value(state, canonical, &fresh);
+    secretfile::wipe(fresh);
+    if let Some(entry) = state.cache.get_mut(canonical) {
+        entry.value = cached.value;
+        entry.sealed = cached.sealed;
+        entry.protection = cached.protection;
+        entry.refresh_at = cached.refresh_at;
+        entry.wiped = None;
+    }
+    egress::publish_secrets(state);
+}
+
+/// Start the idle thread at load if AWF_ONE_SHOT_IDLE_TIMEOUT is configured
+extern "C" fn start_idle_at_load() {
+    start();
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static START_IDLE_AT_LOAD: extern "C" fn() = start_idle_at_load;
+
+/// Start the thread that wipes the cache once the process is idle, if
+/// AWF_ONE_SHOT_IDLE_TIMEOUT is configured
+///
+/// Called at load, and again in forked children, which do not inherit the
+/// parent's threads.
+pub(crate) fn start() {
+    // Read without the state lock, so that processes without the setting do
+    // no more at load
+    let timeout =
+        read_config_var(c"AWF_ONE_SHOT_IDLE_TIMEOUT").and_then(|value| parse_idle_timeout(&value));
+    if timeout.is_none() {
+        return;
+    }
+    let spawned = std::thread::Builder::new()
+        .name("awf-idle".to_string())
+        .spawn(|| loop {
+            let wait = {
+                let mut state = lock_state();
+                check(&mut state);
+                let Some(timeout) = state.idle.timeout else {
+                    return;
+                };
+                timeout.saturating_sub(idle_time(&state, SystemTime::now()))
+            };
+            std::thread::sleep(wait.max(MIN_WAIT));
+        });
+
+    if let Err(err) = spawned {
+        log_line!(
+            Warning,
+            "idle",
+            None,
+            "Could not start idle thread: {}",
+            err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_idle_timeout() {
+        assert_eq!(
+            parse_idle_timeout(" 30 "),
+            Some(Duration::from_secs(30 * 60))
+        );
+        assert_eq!(parse_idle_timeout("0"), None);
+        assert_eq!(parse_idle_timeout("30m"), None);
+    }
+
+    #[test]
+    fn test_check() {
+        let mut state = TokenState::new();
+        let now = SystemTime::now();
+        let minutes = |n: u64| Some(now - Duration::from_secs(60 * n));
+        state.idle = Idle {
+            timeout: Some(Duration::from_secs(30 * 60)),
+            since: minutes(60).unwrap(),
+        };
+        let mut value = *b"ghp_idle\0";
+        state.cache.insert(
+            "GITHUB_TOKEN".into(),
+            crate::CachedToken {
+                last_read: minutes(10),
+                ..crate::CachedToken::cached(value.as_mut_ptr().cast())
+            },
+        );
+
+        // Read ten minutes ago: not idle yet
+        check(&mut state);
+        assert_eq!(state.cache[&EnvName::from("GITHUB_TOKEN")].wiped, None);
+
+        state
+            .cache
+            .get_mut(&EnvName::from("GITHUB_TOKEN"))
+            .unwrap()
+            .last_read = minutes(45);
+        check(&mut state);
+        assert_eq!(
+            state.cache[&EnvName::from("GITHUB_TOKEN")].wiped,
+            Some(REASON)
+        );
+        assert_eq!(value, [0u8; 9]);
+        // The idle time starts over
+        assert!(idle_time(&state, SystemTime::now()) < Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,425 @@
+//! Read-once pointer invalidation for strict tokens
+//! (AWF_ONE_SHOT_STRICT_GRACE)
+//!
+//! The `strict` policy refuses a second read, but the pointer the first read
+//! returned stays valid for the life of the process, so a copy of it kept by
+//! the program or one of its libraries still exposes the value. With
+//! AWF_ONE_SHOT_STRICT_GRACE set to a number of seconds, every strict value is
+//! cached in a mapping of its own (see the secmem module), and that many
+//! seconds after its first read a background thread zeroizes the mapping and
+//! makes it inaccessible (PROT_NONE), with a `strict_invalidated` audit event.
+//! Reads after that are refused like a strict re-read.
+//!
+//! Touching the pointer afterwards faults. The first strict read installs a
+//! SIGSEGV handler that recognizes faults on revoked mappings and reports
+//! them with a `strict_fault` audit line, then hands the signal on to the
+//! handler it replaced, or dies of it with the default action. The handler
+//! can neither lock nor allocate, so the line is written straight to stderr
+//! in the text format, whatever the log settings. A program that installs
+//! its own SIGSEGV handler later replaces it.
+//!
+//! The thread only runs while invalidations are pending. The queue lives in
+//! the state, so a forked child inherits it and restarts the thread to revoke
+//! its own copy of the mappings.
+
+use crate::envname::EnvName;
+use crate::{audit, lock_state, secmem, Mode, TokenState};
+use libc::{c_char, c_int, c_void};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Once, OnceLock};
+use std::time::{Duration, Instant};
+
+/// A served strict value waiting for its grace period to end
+struct Revocation {
+    at: Instant,
+    canonical: EnvName,
+    value: *mut c_char,
+}
+
+/// Invalidations waiting for the revoker thread
+#[derive(Default)]
+pub(crate) struct Pending {
+    /// Oldest first; all share the same grace period, so also by deadline
+    queue: VecDeque<Revocation>,
+    /// Whether this process's revoker thread is running
+    running: bool,
+}
+
+/// Parse an AWF_ONE_SHOT_STRICT_GRACE period in whole seconds (0 disables)
+pub(crate) fn parse_grace(value: &str) -> Option<Duration> {
+    match value.trim().parse::<u64>() {
+        Ok(0) | Err(_) => None,
+        Ok(secs) => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// Queue the invalidation of `value`, the cached buffer of strict token
+/// `canonical`, which was just served for the first time
+///
+/// Values that are not in a mapping of their own (secmem fell back to the
+/// shared region or the heap) stay readable.
+pub(crate) fn schedule(state: &mut TokenState, canonical: &EnvName, value: *mut c_char) {
+    let Some(grace) = state.strict_grace else {
+        return;
+    };
+    if state.mode != Mode::Enforce || value.is_null() {
+        return;
+    }
+    install_fault_handler();
+    state.revocations.queue.push_back(Revocation {
+        at: Instant::now() + grace,
+        canonical: canonical.clone(),
+        value,
+    });
+    start(state);
+}
+
+/// Restart the revoker thread in a forked child, which does not inherit the
+/// parent's threads, if invalidations are pending
+pub(crate) fn start_in_child(state: &mut TokenState) {
+    state.revocations.running = false;
+    start(state);
+}
+
+/// Start the revoker thread if invalidations are pending and it is not
+/// running
+fn start(state: &mut TokenState) {
+    if state.revocations.running || state.revocations.queue.is_empty() {
+        return;
+    }
+    let spawned = std::thread::Builder::new()
+        .name("awf-invalidate".to_string())
+        .spawn(run);
+    match spawned {
+        Ok(_) => state.revocations.running = true,
+        Err(err) => log_line!(
+            Warning,
+            "invalidate",
+            None,
+            "Could not start strict invalidation thread: {}",
+            err
+        ),
+    }
+}
+
+/// Revoker thread: revoke each value when it is due, exit when none is left
+fn run() {
+    loop {
+        let wait = {
+            let mut state = lock_state();
+            let now = Instant::now();
+            revoke_due(&mut state, now);
+            match state.revocations.queue.front() {
+                Some(next) => next.at.saturating_duration_since(now),
+                None => {
+                    state.revocations.running = false;
+                    return;
+                }
+            }
+        };
+        std::thread::sleep(wait);
+    }
+}
+
+/// Revoke the values whose grace period ended by `now`
+fn revoke_due(state: &mut TokenState, now: Instant) {
+    let grace = state.strict_grace.unwrap_or_default().as_secs();
+    while state
+        .revocations
+        .queue
+        .front()
+        .is_some_and(|next| next.at <= now)
+    {
+        let Some(due) = state.revocations.queue.pop_front() else {
+            break;
+        };
+        // A re-read may already have wiped the entry; the mapping is
+        // revoked all the same, since the program may still hold the pointer
+        if let Some(entry) = state.cache.get_mut(&due.canonical) {
+            if entry.value == due.value || entry.handed_over == due.value {
+                entry.wipe("strict_invalidated");
+            }
+        }
+        // SAFETY: the entry no longer points into the mapping
+        let action = match unsafe { secmem::revoke(due.value) } {
+            Some(len) => {
+                record(due.value as usize, len, &due.canonical);
+                "revoked"
+            }
+            None => "zeroized",
+        };
+        audit::emit(
+            "strict_invalidated",
+            &due.canonical,
+            &format!("action={} grace={}", action, grace),
+        );
+    }
+}
+
+/// Most revoked mappings the fault handler recognizes
+const MAX_REVOKED: usize = 64;
+
+/// Longest token name the fault report shows; longer ones are cut
+const NAME_MAX: usize = 64;
+
+/// A revoked mapping, as recorded for the fault handler
+struct Revoked {
+    start: AtomicUsize,
+    len: AtomicUsize,
+    name_len: AtomicUsize,
+    name: [AtomicU8; NAME_MAX],
+}
+
+/// Table of the revoked mappings
+///
+/// Entries are only added by the revoker thread under the state lock, and
+/// the count is published after the entry, so the handler never sees a
+/// partial entry.
+static REVOKED: [Revoked; MAX_REVOKED] = [const {
+    Revoked {
+        start: AtomicUsize::new(0),
+        len: AtomicUsize::new(0),
+        name_len: AtomicUsize::new(0),
+        name: [const { AtomicU8::new(0) }; NAME_MAX],
+    }
+}; MAX_REVOKED];
+
+/// Number of entries of REVOKED in use
+static REVOKED_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Record the revoked mapping of `len` bytes at `start`, which held
+/// `canonical`
+fn record(start: usize, len: usize, canonical: &EnvName) {
+    let count = REVOKED_COUNT.load(Ordering::Relaxed);
+    let Some(entry) = REVOKED.get(count) else {
+        return;
+    };
+    let name = canonical.as_bytes();
+    let name = &name[..name.len().min(NAME_MAX)];
+    for (slot, &byte) in entry.name.iter().zip(name) {
+        slot.store(byte, Ordering::Relaxed);
+    }
+    entry.name_len.store(name.len(), Ordering::Relaxed);
+    entry.start.store(start, Ordering::Relaxed);
+    entry.len.store(len, Ordering::Relaxed);
+    REVOKED_COUNT.store(count + 1, Ordering::Release);
+}
+
+/// The revoked mapping containing `addr`, if any
+///
+/// Async-signal-safe: takes no lock and does not allocate.
+fn revoked_at(addr: usize) -> Option<&'static Revoked> {
+    let count = REVOKED_COUNT.load(Ordering::Acquire);
+    REVOKED[..count.min(MAX_REVOKED)].iter().find(|entry| {
+        let start = entry.start.load(Ordering::Relaxed);
+        (start..start + entry.len.load(Ordering::Relaxed)).contains(&addr)
+    })
+}
+
+/// SIGSEGV disposition replaced by the fault handler
+static PREVIOUS: OnceLock<libc::sigaction> = OnceLock::new();
+
+/// Install the fault handler, once per process (forked children inherit it)
+fn install_fault_handler() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        // SAFETY: sigaction is a plain struct; on_fault is async-signal-safe
+        unsafe {
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(libc::SIGSEGV, std::ptr::null(), &mut previous) != 0 {
+                return;
+            }
+            let _ = PREVIOUS.set(previous);
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_fault
+                as extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void)
+                as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(libc::SIGSEGV, &action, std::ptr::null_mut()) != 0 {
+                log_line!(
+                    Warning,
+                    "invalidate",
+                    None,
+                    "Could not install the strict fault handler: {}",
+                    std::io::Error::last_os_error()
+                );
+            }
+        }
+    });
+}
+
+/// Address that caused the fault
+///
+/// # Safety
+/// `info` must be the siginfo of a SIGSEGV
+#[cfg(target_os = "linux")]
+unsafe fn fault_address(info: *const libc::siginfo_t) -> usize {
+    (*info).si_addr() as usize
+}
+
+/// Address that caused the fault
+///
+/// # Safety
+/// `info` must be the siginfo of a SIGSEGV
+#[cfg(not(target_os = "linux"))]
+unsafe fn fault_address(info: *const libc::siginfo_t) -> usize {
+    (*info).si_addr as usize
+}
+
+/// Fault handler: report faults on revoked mappings, then pass the signal on
+extern "C" fn on_fault(signum: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    // SAFETY: only async-signal-safe calls; info and context come from the
+    // kernel and are handed on unchanged
+    unsafe {
+        if !info.is_null() {
+            let addr = fault_address(info);
+            if let Some(entry) = revoked_at(addr) {
+                report(entry, addr);
+            }
+        }
+        match PREVIOUS.get() {
+            Some(previous)
+                if previous.sa_sigaction != libc::SIG_DFL
+                    && previous.sa_sigaction != libc::SIG_IGN =>
+            {
+                if previous.sa_flags & libc::SA_SIGINFO != 0 {
+                    let handler: extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) =
+                        std::mem::transmute(previous.sa_sigaction);
+                    handler(signum, info, context);
+                } else {
+                    let handler: extern "C" fn(c_int) = std::mem::transmute(previous.sa_sigaction);
+                    handler(signum);
+                }
+            }
+            _ => {
+                // Returning re-runs the faulting access, which now kills
+                let mut default: libc::sigaction = std::mem::zeroed();
+                default.sa_sigaction = libc::SIG_DFL;
+                libc::sigaction(signum, &default, std::ptr::null_mut());
+            }
+        }
+    }
+}
+
+/// Write the `strict_fault` line for a fault at `addr` in `entry` to stderr
+///
+/// Async-signal-safe: formats into a stack buffer and calls write(2).
+fn report(entry: &Revoked, addr: usize) {
+    let mut line = Line::default();
+    line.push(b"[one-shot-token] AUDIT event=strict_fault token=");
+    let name_len = entry.name_len.load(Ordering::Relaxed).min(NAME_MAX);
+    for slot in &entry.name[..name_len] {
+        line.push(&[slot.load(Ordering::Relaxed)]);
+    }
+    line.push(b" addr=0x");
+    line.push_number(addr as u64, 16);
+    line.push(b" pid=");
+    // SAFETY: getpid is async-signal-safe
+    line.push_number(unsafe { libc::getpid() } as u64, 10);
+    line.push(b"\n");
+    // SAFETY: the buffer holds `len` initialized bytes
+    unsafe { libc::write(libc::STDERR_FILENO, line.buf.as_ptr().cast(), line.len) };
+}
+
+/// Fixed-size line buffer for the fault handler; input past the end is cut
+struct Line {
+    buf: [u8; 192],
+    len: usize,
+}
+
+impl Default for Line {
+    fn default() -> Self {
+        Line {
+            buf: [0; 192],
+            len: 0,
+        }
+    }
+}
+
+impl Line {
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.len == self.buf.len() {
+                return;
+            }
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn push_number(&mut self, mut value: u64, radix: u64) {
+        let mut digits = [0u8; 20];
+        let mut count = 0;
+        loop {
+            digits[count] = b"0123456789abcdef"[(value % radix) as usize];
+            count += 1;
+            value /= radix;
+            if value == 0 {
+                break;
+            }
+        }
+        digits[..count].reverse();
+        self.push(&digits[..count]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{policy, CachedToken};
+
+    #[test]
+    fn test_parse_grace() {
+        assert_eq!(parse_grace("2"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_grace(" 10 "), Some(Duration::from_secs(10)));
+        assert_eq!(parse_grace("0"), None);
+        assert_eq!(parse_grace("soon"), None);
+    }
+
+    #[test]
+    fn test_line() {
+        let mut line = Line::default();
+        line.push(b"addr=0x");
+        line.push_number(0xdead_beef, 16);
+        line.push(b" pid=");
+        line.push_number(0, 10);
+        assert_eq!(&line.buf[..line.len], b"addr=0xdeadbeef pid=0");
+
+        let mut line = Line::default();
+        line.push(&[b'x'; 200]);
+        assert_eq!(line.len, 192);
+    }
+
+    #[test]
+    fn test_revoke_due() {
+        let mut state = TokenState::new();
+        state.strict_grace = Some(Duration::from_secs(1));
+        let (spec, _) = policy::parse_token_spec("AWF_TEST_GRACE_TOKEN:strict").unwrap();
+        state.tokens.push(spec);
+        let name = EnvName::from("AWF_TEST_GRACE_TOKEN");
+        let (value, _) = secmem::alloc_isolated(b"ghp_grace\0").unwrap();
+        state.cache.insert(name.clone(), CachedToken::cached(value));
+
+        let now = Instant::now();
+        state.revocations.queue.push_back(Revocation {
+            at: now + Duration::from_secs(1),
+            canonical: name.clone(),
+            value,
+        });
+        revoke_due(&mut state, now);
+        assert_eq!(state.revocations.queue.len(), 1);
+        assert!(state.cache[&name].wiped.is_none());
+
+        revoke_due(&mut state, now + Duration::from_secs(1));
+        assert!(state.revocations.queue.is_empty());
+        assert_eq!(state.cache[&name].wiped, Some("strict_invalidated"));
+        assert!(state.cache[&name].value.is_null());
+        let entry = revoked_at(value as usize + 4).unwrap();
+        assert_eq!(
+            entry.name_len.load(Ordering::Relaxed),
+            name.as_bytes().len()
+        );
+    }
+}
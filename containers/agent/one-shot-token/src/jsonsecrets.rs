@@ -0,0 +1,145 @@
+//! JSON secret blobs (`AWF_JSON_SECRETS`)
+//!
+//! A workflow with a dozen secrets needs a dozen `-e` flags, and every one
+//! of them is a chance to misspell a name or leave one out. The host can
+//! instead inject a single variable holding a JSON object,
+//! `AWF_JSON_SECRETS={"GITHUB_TOKEN":"ghp_...","NPM_TOKEN":"npm_..."}`: at
+//! load, each member is served through getenv as a protected token named
+//! after its key, and the blob itself is scrubbed from the environment.
+//!
+//! Members must have string values. Keys that do not make a valid variable
+//! name, or that start with `AWF_` (so the blob cannot carry library
+//! settings), are skipped and reported, as are values holding a NUL byte.
+//! When a key is repeated, the last value wins.
+
+use crate::envname::EnvName;
+use crate::json::{self, Value};
+use crate::sys;
+use std::ffi::{CStr, CString};
+
+/// Variable holding the blob
+pub(crate) const VAR: &CStr = c"AWF_JSON_SECRETS";
+
+/// A member of the blob to serve
+pub(crate) struct Secret {
+    pub(crate) name: EnvName,
+    pub(crate) value: CString,
+}
+
+/// The secrets of a blob and the keys skipped, with the reason
+#[derive(Default)]
+pub(crate) struct Blob {
+    pub(crate) secrets: Vec<Secret>,
+    pub(crate) skipped: Vec<(String, &'static str)>,
+}
+
+/// Why `key` cannot name a secret, if it cannot
+fn invalid_key(key: &str) -> Option<&'static str> {
+    let valid = !key.is_empty()
+        && !key.starts_with(|c: char| c.is_ascii_digit())
+        && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_');
+    if !valid {
+        Some("invalid_name")
+    } else if key.starts_with("AWF_") {
+        Some("reserved_name")
+    } else {
+        None
+    }
+}
+
+/// Parse a blob, or describe why it is not a JSON object
+///
+/// The parsed string values are zeroed once copied into their C strings.
+pub(crate) fn parse(blob: &[u8]) -> Result<Blob, String> {
+    let Value::Object(members) = json::parse(blob)? else {
+        return Err("not an object".to_string());
+    };
+    let mut parsed = Blob::default();
+    for (key, value) in members {
+        let value = match value {
+            Value::String(value) => value,
+            _ => {
+                parsed.skipped.push((key, "not_a_string"));
+                continue;
+            }
+        };
+        if let Some(reason) = invalid_key(&key) {
+            zero_string(value);
+            parsed.skipped.push((key, reason));
+            continue;
+        }
+        let Some(value) = to_cstring(value) else {
+            parsed.skipped.push((key, "nul_byte"));
+            continue;
+        };
+        let name = EnvName::from(key.as_str());
+        if let Some(index) = parsed.secrets.iter().position(|secret| secret.name == name) {
+            let earlier = parsed.secrets.remove(index);
+            crate::secretfile::wipe(earlier.value);
+        }
+        parsed.secrets.push(Secret { name, value });
+    }
+    Ok(parsed)
+}
+
+/// A C string copy of `value`, zeroing `value`; None if it holds a NUL byte
+fn to_cstring(value: String) -> Option<CString> {
+    let mut bytes = value.into_bytes();
+    let copy = CString::new(bytes.as_slice()).ok();
+    zero(&mut bytes);
+    copy
+}
+
+fn zero_string(value: String) {
+    zero(&mut value.into_bytes());
+}
+
+fn zero(bytes: &mut [u8]) {
+    // SAFETY: bytes is a writable buffer of the given length
+    unsafe { sys::explicit_bzero(bytes.as_mut_ptr().cast(), bytes.len()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let blob = br#"{
+            "GITHUB_TOKEN": "ghp_first",
+            "NPM_TOKEN": "npm_value",
+            "GITHUB_TOKEN": "ghp_last",
+            "AWF_ONE_SHOT_MODE": "observe",
+            "1TOKEN": "x",
+            "BAD-NAME": "x",
+            "PORT": 8080,
+            "NUL_TOKEN": "a\u0000b"
+        }"#;
+        let parsed = parse(blob).unwrap();
+        let secrets: Vec<(String, &CStr)> = parsed
+            .secrets
+            .iter()
+            .map(|secret| (secret.name.to_string(), secret.value.as_c_str()))
+            .collect();
+        assert_eq!(
+            secrets,
+            vec![
+                ("NPM_TOKEN".to_string(), c"npm_value"),
+                ("GITHUB_TOKEN".to_string(), c"ghp_last"),
+            ]
+        );
+        assert_eq!(
+            parsed.skipped,
+            vec![
+                ("AWF_ONE_SHOT_MODE".to_string(), "reserved_name"),
+                ("1TOKEN".to_string(), "invalid_name"),
+                ("BAD-NAME".to_string(), "invalid_name"),
+                ("PORT".to_string(), "not_a_string"),
+                ("NUL_TOKEN".to_string(), "nul_byte"),
+            ]
+        );
+
+        assert_eq!(parse(b"[\"GITHUB_TOKEN\"]").err().unwrap(), "not an object");
+        assert!(parse(b"{\"GITHUB_TOKEN\": ").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//! Kernel keyring storage of cached values (AWF_ONE_SHOT_CACHE_BACKEND=keyring)
+//!
+//! Instead of being kept in the heap until first served, a value can be
+//! handed to the kernel with add_key(2) as a `user` key in the process
+//! keyring, and read back with keyctl(KEYCTL_READ) straight into its stable
+//! return buffer when it is served. The key's permissions are narrowed to the
+//! possessor, and only this process possesses its process keyring: other
+//! processes of the same user can neither read nor list the key.
+//!
+//! A child created by fork() starts with an empty process keyring and cannot
+//! read its parent's keys, so the fork handlers copy the values over (see
+//! Sealed::prepare_fork). Container runtimes often block the keyring system
+//! calls; once they are refused, values stay in memory (see the seal module).
+
+use crate::sys;
+use libc::{c_char, c_long};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const KEY_SPEC_PROCESS_KEYRING: c_long = -2;
+const KEYCTL_REVOKE: c_long = 3;
+const KEYCTL_SETPERM: c_long = 5;
+const KEYCTL_READ: c_long = 11;
+const KEYCTL_INVALIDATE: c_long = 21;
+
+/// View, read, search and setattr for the possessor only
+const POSSESSOR_PERM: c_long = 0x2b00_0000;
+
+/// Set once the keyring system calls were refused; later values stay in memory
+static UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+/// A value stored as a key in the process keyring
+pub(crate) struct Key {
+    serial: i32,
+    /// Whether the key was added by this process, which then revokes it when
+    /// dropped (keys seen by a forked child belong to the parent)
+    owned: bool,
+}
+
+/// Whether the keyring may still be used
+pub(crate) fn available() -> bool {
+    !UNAVAILABLE.load(Ordering::Relaxed)
+}
+
+/// Stop using the keyring once its system calls are refused
+fn mark_unavailable() {
+    UNAVAILABLE.store(true, Ordering::Relaxed);
+}
+
+impl Key {
+    /// Add `value` to the process keyring under `description`
+    ///
+    /// When the system calls are not permitted, the keyring is not used again
+    /// by this process.
+    pub(crate) fn add(description: &str, value: &[u8]) -> io::Result<Self> {
+        let description = format!("{}\0", description);
+        // SAFETY: the type and description are NUL-terminated and the payload
+        // is valid for its length
+        let serial = unsafe {
+            libc::syscall(
+                libc::SYS_add_key,
+                c"user".as_ptr(),
+                description.as_ptr().cast::<c_char>(),
+                value.as_ptr(),
+                value.len(),
+                KEY_SPEC_PROCESS_KEYRING,
+            )
+        };
+        if serial < 0 {
+            let err = io::Error::last_os_error();
+            // A full quota (EDQUOT) or an oversized value only affects this one
+            if matches!(
+                err.raw_os_error(),
+                Some(libc::ENOSYS | libc::EPERM | libc::EACCES)
+            ) {
+                mark_unavailable();
+            }
+            return Err(err);
+        }
+        let key = Key {
+            serial: serial as i32,
+            owned: true,
+        };
+        // SAFETY: keyctl only takes integer arguments here
+        if unsafe { keyctl(KEYCTL_SETPERM, key.serial, POSSESSOR_PERM, 0) } < 0 {
+            let err = io::Error::last_os_error();
+            mark_unavailable();
+            return Err(err);
+        }
+        Ok(key)
+    }
+
+    /// Read the value into `dest`, which must hold exactly its length
+    ///
+    /// # Safety
+    /// `dest` must be valid for writes of `len` bytes
+    pub(crate) unsafe fn read_into(&self, dest: *mut u8, len: usize) -> io::Result<()> {
+        let read = keyctl(KEYCTL_READ, self.serial, dest as c_long, len as c_long);
+        if read < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if read as usize != len {
+            sys::explicit_bzero(dest.cast(), len);
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        Ok(())
+    }
+
+    /// Read the value into a new buffer of `len` bytes
+    pub(crate) fn read(&self, len: usize) -> io::Result<Vec<u8>> {
+        let mut value = vec![0u8; len];
+        // SAFETY: value is a writable buffer of len bytes
+        unsafe { self.read_into(value.as_mut_ptr(), len) }?;
+        Ok(value)
+    }
+
+    /// Give up the key without revoking it (it belongs to the parent)
+    pub(crate) fn disown(&mut self) {
+        self.owned = false;
+    }
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+        // SAFETY: keyctl only takes integer arguments here
+        unsafe {
+            if keyctl(KEYCTL_INVALIDATE, self.serial, 0, 0) < 0 {
+                keyctl(KEYCTL_REVOKE, self.serial, 0, 0);
+            }
+        }
+    }
+}
+
+/// keyctl(2) with a key serial and two more arguments
+///
+/// # Safety
+/// The arguments must be valid for `operation`
+unsafe fn keyctl(operation: c_long, serial: i32, arg3: c_long, arg4: c_long) -> c_long {
+    libc::syscall(libc::SYS_keyctl, operation, serial as c_long, arg3, arg4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_round_trip() {
+        // Sandboxes commonly block the keyring system calls
+        let Ok(key) = Key::add("awf-test", b"ghp_keyring_value") else {
+            return;
+        };
+        assert_eq!(key.read(17).unwrap(), b"ghp_keyring_value");
+        assert!(key.read(4).is_err());
+        let serial = key.serial;
+        drop(key);
+        let mut buf = [0u8; 17];
+        assert!(unsafe { keyctl(KEYCTL_READ, serial, buf.as_mut_ptr() as c_long, 17) } < 0);
+    }
+}
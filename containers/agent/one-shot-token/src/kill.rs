@@ -0,0 +1,135 @@
+//! Terminating the process on severe violations
+//!
+//! Blocking a call leaves the offending process running, free to try another
+//! route. For the violations where that is not enough, the policy action
+//! `kill` ends the process instead:
+//!
+//! - AWF_ONE_SHOT_EGRESS_SCAN=kill when a protected value is written to a
+//!   peer that is not allowed (see the egress module)
+//! - AWF_ONE_SHOT_DNS_EXFIL=kill when a lookup carries a fragment of one
+//!   (see the dns module)
+//! - AWF_ONE_SHOT_DENY_KILL=N at the Nth read of a deny-listed variable
+//!
+//! The violation is reported as usual, with `action=killed`, followed by a
+//! final `killed` audit event naming the violation and the signal. The
+//! signal is AWF_ONE_SHOT_KILL_SIGNAL (default SIGKILL); it is raised on the
+//! offending thread, and if a handler returns or the signal is blocked or
+//! ignored the process exits with status 128 + the signal number anyway. In
+//! observe mode the process keeps running: egress and DNS violations are
+//! reported with an `observed` audit event (`would=kill_egress`,
+//! `would=kill_dns_exfil`), and deny-listed reads are not counted.
+
+use crate::{audit, protection_disabled, read_config_var};
+use libc::c_int;
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Signals the process can be terminated with, by name without `SIG`
+const KILL_SIGNALS: [(&str, c_int); 6] = [
+    ("KILL", libc::SIGKILL),
+    ("TERM", libc::SIGTERM),
+    ("ABRT", libc::SIGABRT),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("HUP", libc::SIGHUP),
+];
+
+/// Signal that terminates the process, read once
+///
+/// Unrecognized values fall back to SIGKILL, so a typo never weakens the
+/// action.
+static SIGNAL: Lazy<c_int> = Lazy::new(|| {
+    let Some(value) = read_config_var(c"AWF_ONE_SHOT_KILL_SIGNAL") else {
+        return libc::SIGKILL;
+    };
+    parse_signal(&value).unwrap_or(libc::SIGKILL)
+});
+
+/// Reads of deny-listed variables after which the process is terminated
+/// (AWF_ONE_SHOT_DENY_KILL, 0 for never)
+static DENY_KILL: Lazy<u32> = Lazy::new(|| {
+    if protection_disabled() {
+        return 0;
+    }
+    read_config_var(c"AWF_ONE_SHOT_DENY_KILL")
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(0)
+});
+
+/// Reads of deny-listed variables so far
+static DENIED_READS: AtomicU32 = AtomicU32::new(0);
+
+/// Parse an AWF_ONE_SHOT_KILL_SIGNAL value: a name from KILL_SIGNALS, with or
+/// without `SIG`, or the number of one of those
+fn parse_signal(value: &str) -> Option<c_int> {
+    let value = value.trim().to_ascii_uppercase();
+    let name = value.strip_prefix("SIG").unwrap_or(&value);
+    KILL_SIGNALS
+        .iter()
+        .find(|&&(known, signum)| known == name || signum.to_string() == name)
+        .map(|&(_, signum)| signum)
+}
+
+/// Whether `value` is an AWF_ONE_SHOT_KILL_SIGNAL value
+pub(crate) fn is_signal(value: &str) -> bool {
+    parse_signal(value).is_some()
+}
+
+/// Name of `signum`, as configured
+fn signal_name(signum: c_int) -> String {
+    KILL_SIGNALS
+        .iter()
+        .find(|&&(_, known)| known == signum)
+        .map_or_else(|| signum.to_string(), |(name, _)| format!("SIG{}", name))
+}
+
+/// Count a read of a deny-listed variable
+///
+/// Returns the number of reads so far if it reached AWF_ONE_SHOT_DENY_KILL
+/// and the process must be terminated.
+pub(crate) fn count_denied_read() -> Option<u32> {
+    let limit = *DENY_KILL;
+    if limit == 0 {
+        return None;
+    }
+    let reads = DENIED_READS.fetch_add(1, Ordering::Relaxed) + 1;
+    (reads >= limit).then_some(reads)
+}
+
+/// Terminate the process for `event` on `token`
+///
+/// Emits the final `killed` audit event with `detail`, then raises the
+/// configured signal. Never returns.
+pub(crate) fn terminate(event: &str, token: &str, detail: &str) -> ! {
+    let signum = *SIGNAL;
+    let detail = format!("reason={} signal={} {}", event, signal_name(signum), detail);
+    audit::emit("killed", token, detail.trim_end());
+    // SAFETY: raise and _exit have no preconditions
+    unsafe {
+        libc::raise(signum);
+        libc::_exit(128 + signum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGKILL"), Some(libc::SIGKILL));
+        assert_eq!(parse_signal(" term "), Some(libc::SIGTERM));
+        assert_eq!(parse_signal("ABRT"), Some(libc::SIGABRT));
+        assert_eq!(parse_signal("9"), Some(libc::SIGKILL));
+        assert_eq!(parse_signal("SIGUSR1"), None);
+        assert_eq!(parse_signal("0"), None);
+        assert_eq!(parse_signal(""), None);
+    }
+
+    #[test]
+    fn test_signal_name() {
+        assert_eq!(signal_name(libc::SIGKILL), "SIGKILL");
+        assert_eq!(signal_name(libc::SIGTERM), "SIGTERM");
+        assert_eq!(signal_name(libc::SIGUSR1), libc::SIGUSR1.to_string());
+    }
+}
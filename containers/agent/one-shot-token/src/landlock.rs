@@ -0,0 +1,299 @@
+//! Kernel-enforced backstop for /proc snooping (AWF_ONE_SHOT_LANDLOCK)
+//!
+//! The open() interposers only see opens that go through libc. With
+//! AWF_ONE_SHOT_LANDLOCK set, a Landlock ruleset is installed at load that
+//! restricts file reads: reading is granted beneath every entry of the root
+//! directory except /proc, beneath every /proc entry except process
+//! directories, beneath this process's own directory, and for every other
+//! process that exists at load, on each file of its directory except
+//! environ, mem and the same files of its threads. Reads of another process's
+//! environ or mem therefore fail with EACCES however they are attempted.
+//!
+//! Landlock only grants access, so directories of processes started after
+//! load (including the own directory of a descendant, which inherits the
+//! ruleset) cannot be read at all. Entries are opened without following
+//! symbolic links, so magic links such as /proc/<pid>/root grant nothing.
+
+use crate::{audit, lock_state, open, Mode};
+use libc::c_int;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+/// LANDLOCK_ACCESS_FS_READ_FILE
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+/// LANDLOCK_CREATE_RULESET_VERSION
+const CREATE_RULESET_VERSION: u32 = 1 << 0;
+/// LANDLOCK_RULE_PATH_BENEATH
+const RULE_PATH_BENEATH: c_int = 1;
+
+/// Files of other processes that are never granted
+const PROTECTED_FILES: &[&str] = &["environ", "mem"];
+
+/// struct landlock_ruleset_attr (ABI v1)
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+/// struct landlock_path_beneath_attr
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: c_int,
+}
+
+/// Landlock ABI version supported by the kernel, if any
+fn abi_version() -> Option<libc::c_long> {
+    // SAFETY: a version query takes no attribute
+    let version = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0usize,
+            CREATE_RULESET_VERSION,
+        )
+    };
+    (version > 0).then_some(version)
+}
+
+/// Paths on which file reads are granted
+///
+/// `own_pid` names this process's directory under `proc`.
+fn granted_paths(root: &Path, proc: &Path, own_pid: u32) -> Vec<std::path::PathBuf> {
+    let entries = |dir: &Path| -> Vec<(String, std::path::PathBuf)> {
+        fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| {
+                        (
+                            entry.file_name().to_string_lossy().into_owned(),
+                            entry.path(),
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let unprotected = |dir: &Path| -> Vec<std::path::PathBuf> {
+        entries(dir)
+            .into_iter()
+            .filter(|(name, _)| !PROTECTED_FILES.contains(&name.as_str()) && name != "task")
+            .map(|(_, path)| path)
+            .collect()
+    };
+
+    let mut paths: Vec<_> = entries(root)
+        .into_iter()
+        .filter(|(_, path)| path != proc)
+        .map(|(_, path)| path)
+        .collect();
+    for (name, path) in entries(proc) {
+        match name.parse::<u32>() {
+            Err(_) => paths.push(path),
+            Ok(pid) if pid == own_pid => paths.push(path),
+            Ok(_) => {
+                paths.extend(unprotected(&path));
+                for (_, thread) in entries(&path.join("task")) {
+                    paths.extend(unprotected(&thread));
+                }
+            }
+        }
+    }
+    paths
+}
+
+/// Build and enforce the ruleset
+///
+/// Returns the number of rules added. Must not be called with the state lock
+/// held, since entries are opened through the real open.
+fn install() -> io::Result<usize> {
+    if abi_version().is_none() {
+        return Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP));
+    }
+    let attr = RulesetAttr {
+        handled_access_fs: ACCESS_FS_READ_FILE,
+    };
+    // SAFETY: attr is a valid ruleset attribute of the given size
+    let ruleset = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            std::mem::size_of::<RulesetAttr>(),
+            0u32,
+        )
+    } as c_int;
+    if ruleset < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut rules = 0;
+    for path in granted_paths(Path::new("/"), Path::new("/proc"), std::process::id()) {
+        let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+            continue;
+        };
+        // SAFETY: path is a valid C string
+        let fd = unsafe {
+            open::call_real_open(
+                path.as_ptr(),
+                libc::O_PATH | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            // The entry went away (e.g. an exited process)
+            continue;
+        }
+        let rule = PathBeneathAttr {
+            allowed_access: ACCESS_FS_READ_FILE,
+            parent_fd: fd,
+        };
+        // SAFETY: rule is a valid path-beneath attribute; fd is ours to close
+        unsafe {
+            if libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset,
+                RULE_PATH_BENEATH,
+                &rule as *const PathBeneathAttr,
+                0u32,
+            ) == 0
+            {
+                rules += 1;
+            }
+            libc::close(fd);
+        }
+    }
+
+    // SAFETY: plain system calls on the ruleset descriptor we own
+    let result = unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+            || libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0u32) != 0
+        {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(rules)
+        }
+    };
+    // SAFETY: ruleset is a descriptor we own
+    unsafe { libc::close(ruleset) };
+    result
+}
+
+/// Whether this library installed the ruleset in this process
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the ruleset was installed at load
+///
+/// The kernel offers no way to query a process's Landlock domain.
+pub(crate) fn installed() -> bool {
+    INSTALLED.load(Ordering::Relaxed)
+}
+
+/// Install the ruleset when AWF_ONE_SHOT_LANDLOCK is set, once
+///
+/// Called by a constructor, and by the verification report before it checks
+/// the result (see the verify module).
+pub(crate) fn install_at_load() {
+    static ATTEMPTED: Once = Once::new();
+    ATTEMPTED.call_once(install_landlock);
+}
+
+extern "C" fn install_landlock_at_load() {
+    install_at_load();
+}
+
+/// Install the ruleset if the configuration asks for it
+fn install_landlock() {
+    let state = lock_state();
+    if !state.landlock {
+        return;
+    }
+    let (observe, debug_enabled) = (state.mode == Mode::Observe, state.debug_enabled);
+    drop(state);
+    if observe {
+        audit::emit("observed", "*", "would=install_landlock");
+        return;
+    }
+
+    let result = install();
+    INSTALLED.store(result.is_ok(), Ordering::Relaxed);
+    match result {
+        Ok(rules) if debug_enabled => {
+            log_line!(
+                Debug,
+                "landlock",
+                None,
+                "Landlock ruleset installed ({} rules)",
+                rules
+            );
+        }
+        Ok(_) => {}
+        Err(err) => {
+            log_line!(
+                Warning,
+                "landlock",
+                None,
+                "Could not install Landlock ruleset: {}",
+                err
+            );
+        }
+    }
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static INSTALL_LANDLOCK_AT_LOAD: extern "C" fn() = install_landlock_at_load;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_granted_paths() {
+        let dir = std::env::temp_dir().join(format!("awf-landlock-{}", std::process::id()));
+        let proc = dir.join("proc");
+        for sub in ["usr", "proc/sys", "proc/7/task/7", "proc/42/task/42"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        for file in [
+            "proc/meminfo",
+            "proc/7/environ",
+            "proc/7/cmdline",
+            "proc/7/task/7/mem",
+            "proc/7/task/7/stat",
+            "proc/42/environ",
+        ] {
+            fs::write(dir.join(file), b"").unwrap();
+        }
+
+        let mut paths: Vec<String> = granted_paths(&dir, &proc, 42)
+            .iter()
+            .map(|path| path.strip_prefix(&dir).unwrap().display().to_string())
+            .collect();
+        paths.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            paths,
+            vec![
+                "proc/42",
+                "proc/7/cmdline",
+                "proc/7/task/7/stat",
+                "proc/meminfo",
+                "proc/sys",
+                "usr",
+            ]
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! Latency sampling of intercepted getenv (AWF_ONE_SHOT_LATENCY_SAMPLE)
+//!
+//! Every getenv call of the program goes through the library, so the cost
+//! it adds to environment-heavy workloads has to be measurable. With
+//! AWF_ONE_SHOT_LATENCY_SAMPLE set to N, one getenv (or secure_getenv) call
+//! in N is timed, from entry to return, into one of two histograms:
+//!
+//! - `sensitive`: names the library acts on (protected tokens and their
+//!   aliases, deny-listed, proxy and decoy variables), which take the state
+//!   lock unless served from the snapshot
+//! - `passthrough`: every other name, which takes the lock-free fast path
+//!
+//! Until the configuration is loaded every name counts as sensitive, since
+//! it takes the same path. Buckets are powers of two of nanoseconds. The
+//! histograms are added to the access report (see the report module) and,
+//! with AWF_ONE_SHOT_STATS_SHM, summed across processes in the statistics
+//! segment (see the shmstats module). `0`, the default, turns sampling off;
+//! unsampled calls only pay for a counter increment.
+
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Number of histogram buckets; the last one also holds longer calls
+pub(crate) const BUCKETS: usize = 32;
+
+/// Which histogram a call is recorded in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Class {
+    Sensitive,
+    Passthrough,
+}
+
+impl Class {
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Class::Sensitive => 0,
+            Class::Passthrough => 1,
+        }
+    }
+}
+
+/// Latencies of sampled calls
+///
+/// Bucket `i` counts calls that took from 2^i up to 2^(i+1) nanoseconds
+/// (bucket 0 also those under a nanosecond). The layout is shared with the
+/// statistics segment.
+#[repr(C)]
+pub(crate) struct Histogram {
+    samples: AtomicU64,
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl Histogram {
+    pub(crate) const fn new() -> Self {
+        Histogram {
+            samples: AtomicU64::new(0),
+            total_ns: AtomicU64::new(0),
+            max_ns: AtomicU64::new(0),
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+        }
+    }
+
+    /// Count a call that took `nanos`
+    pub(crate) fn record(&self, nanos: u64) {
+        self.buckets[bucket(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.total_ns.fetch_add(nanos, Ordering::Relaxed);
+        self.max_ns.fetch_max(nanos, Ordering::Relaxed);
+        self.samples.fetch_add(1, Ordering::Release);
+    }
+
+    /// JSON object with the sample count, mean, estimated percentiles,
+    /// maximum and the non-empty buckets keyed by their upper bound
+    pub(crate) fn json(&self) -> String {
+        let samples = self.samples.load(Ordering::Acquire);
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        let buckets: Vec<String> = counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(index, count)| format!("\"{}\":{}", upper_bound(index), count))
+            .collect();
+        format!(
+            "{{\"samples\":{},\"mean_ns\":{},\"p50_ns\":{},\"p99_ns\":{},\"max_ns\":{},\"buckets\":{{{}}}}}",
+            samples,
+            self.total_ns
+                .load(Ordering::Relaxed)
+                .checked_div(samples)
+                .unwrap_or(0),
+            percentile(&counts, 50),
+            percentile(&counts, 99),
+            self.max_ns.load(Ordering::Relaxed),
+            buckets.join(",")
+        )
+    }
+}
+
+/// Bucket of a call that took `nanos`
+fn bucket(nanos: u64) -> usize {
+    (nanos.max(1).ilog2() as usize).min(BUCKETS - 1)
+}
+
+/// Exclusive upper bound of bucket `index`, in nanoseconds
+fn upper_bound(index: usize) -> u64 {
+    1u64 << (index + 1)
+}
+
+/// Upper bound of the bucket holding the `percent`th percentile, 0 without
+/// samples
+fn percentile(counts: &[u64], percent: u64) -> u64 {
+    let total: u64 = counts.iter().sum();
+    let target = (total * percent).div_ceil(100).max(1);
+    let mut seen = 0;
+    for (index, count) in counts.iter().enumerate() {
+        seen += count;
+        if total > 0 && seen >= target {
+            return upper_bound(index);
+        }
+    }
+    0
+}
+
+/// Histograms of this process, indexed by Class::index
+static LOCAL: [Histogram; 2] = [Histogram::new(), Histogram::new()];
+
+/// Calls seen, for picking one in every RATE
+static CALLS: AtomicU64 = AtomicU64::new(0);
+
+/// One call in this many is timed, 0 for none; read once through the real
+/// getenv
+static RATE: Lazy<u64> = Lazy::new(|| {
+    crate::read_config_var(c"AWF_ONE_SHOT_LATENCY_SAMPLE")
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(0)
+});
+
+/// The configured sampling rate, 0 when sampling is off
+pub(crate) fn rate() -> u64 {
+    *RATE
+}
+
+/// Run `call`, a getenv of a name of `class`, timing it if it is sampled
+pub(crate) fn measure<T>(class: impl FnOnce() -> Class, call: impl FnOnce() -> T) -> T {
+    let rate = rate();
+    if rate == 0 || !CALLS.fetch_add(1, Ordering::Relaxed).is_multiple_of(rate) {
+        return call();
+    }
+    let class = class();
+    let start = Instant::now();
+    let result = call();
+    let nanos = start.elapsed().as_nanos().min(u64::MAX as u128) as u64;
+    LOCAL[class.index()].record(nanos);
+    #[cfg(not(feature = "minimal"))]
+    crate::shmstats::record_latency(class, nanos);
+    result
+}
+
+/// The `latency` object of the access report, or None when sampling is off
+pub(crate) fn json() -> Option<String> {
+    let rate = rate();
+    (rate > 0).then(|| {
+        format!(
+            "{{\"sample\":{},\"sensitive\":{},\"passthrough\":{}}}",
+            rate,
+            LOCAL[Class::Sensitive.index()].json(),
+            LOCAL[Class::Passthrough.index()].json()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(1), 0);
+        assert_eq!(bucket(1023), 9);
+        assert_eq!(bucket(1024), 10);
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+        assert_eq!(upper_bound(bucket(1500)), 2048);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::new();
+        assert_eq!(
+            histogram.json(),
+            "{\"samples\":0,\"mean_ns\":0,\"p50_ns\":0,\"p99_ns\":0,\"max_ns\":0,\"buckets\":{}}"
+        );
+        for _ in 0..98 {
+            histogram.record(100);
+        }
+        histogram.record(1500);
+        histogram.record(70_000);
+        assert_eq!(
+            histogram.json(),
+            concat!(
+                "{\"samples\":100,\"mean_ns\":813,\"p50_ns\":128,\"p99_ns\":2048,",
+                "\"max_ns\":70000,\"buckets\":{\"128\":98,\"2048\":1,\"131072\":1}}"
+            )
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Late re-read anomalies (AWF_ONE_SHOT_QUIET_PERIOD and
+//! AWF_ONE_SHOT_PHASE_END_FILE)
+//!
+//! An agent reads its tokens while its tools work, in bursts. A token read
+//! again long after the last burst, or once the workflow has said the tools
+//! are done, fits a prompt injection that surfaced late in the run and is
+//! now sending the token elsewhere. Such reads are still served (a slow tool
+//! looks the same) but reported with a `late_read` audit event of
+//! `severity=high`:
+//!
+//! - `reason=quiet_period` when the token's previous read was longer ago
+//!   than AWF_ONE_SHOT_QUIET_PERIOD seconds (`idle=` has the gap)
+//! - `reason=phase_ended` for every read once the file named by
+//!   AWF_ONE_SHOT_PHASE_END_FILE exists; the workflow creates it when the
+//!   agent's tool-use phase is over. The file is checked on reads, and its
+//!   first sighting is reported with a `phase_ended` event.
+
+use crate::envname::EnvName;
+use crate::{audit, read_config_var};
+use std::ffi::CString;
+use std::time::{Duration, SystemTime};
+
+/// The late-read settings and whether the tool-use phase has ended
+#[derive(Default)]
+pub(crate) struct LateRead {
+    quiet: Option<Duration>,
+    phase_file: Option<CString>,
+    phase_ended: bool,
+}
+
+/// Parse AWF_ONE_SHOT_QUIET_PERIOD, a positive number of seconds
+pub(crate) fn parse_quiet_period(value: &str) -> Option<Duration> {
+    let secs = value.trim().parse::<u64>().ok().filter(|&secs| secs > 0)?;
+    Some(Duration::from_secs(secs))
+}
+
+impl LateRead {
+    pub(crate) fn load() -> Self {
+        LateRead {
+            quiet: read_config_var(c"AWF_ONE_SHOT_QUIET_PERIOD")
+                .and_then(|value| parse_quiet_period(&value)),
+            phase_file: read_config_var(c"AWF_ONE_SHOT_PHASE_END_FILE")
+                .and_then(|path| CString::new(path.trim()).ok())
+                .filter(|path| !path.is_empty()),
+            phase_ended: false,
+        }
+    }
+
+    /// Whether reads are checked at all
+    pub(crate) fn enabled(&self) -> bool {
+        self.quiet.is_some() || self.phase_file.is_some()
+    }
+
+    /// Whether the phase-end file exists (or existed at an earlier check)
+    fn phase_ended(&mut self) -> bool {
+        if self.phase_ended {
+            return true;
+        }
+        let Some(path) = &self.phase_file else {
+            return false;
+        };
+        // SAFETY: path is a valid C string
+        if unsafe { libc::access(path.as_ptr(), libc::F_OK) } != 0 {
+            return false;
+        }
+        self.phase_ended = true;
+        let detail = format!("path={}", audit::detail_word(&path.to_string_lossy()));
+        audit::emit("phase_ended", "*", &detail);
+        true
+    }
+
+    /// The `late_read` event detail for a read at `now` of a token last read
+    /// at `previous`, if the read is late
+    fn detail(
+        &mut self,
+        previous: Option<SystemTime>,
+        now: SystemTime,
+        reads: u32,
+    ) -> Option<String> {
+        let idle = previous.map(|previous| now.duration_since(previous).unwrap_or_default());
+        let mut reasons = Vec::new();
+        if self
+            .quiet
+            .zip(idle)
+            .is_some_and(|(quiet, idle)| idle > quiet)
+        {
+            reasons.push("quiet_period");
+        }
+        if self.phase_ended() {
+            reasons.push("phase_ended");
+        }
+        if reasons.is_empty() {
+            return None;
+        }
+        let mut detail = format!("severity=high reason={} reads={}", reasons.join(","), reads);
+        if let Some(idle) = idle {
+            detail.push_str(&format!(" idle={}", idle.as_secs()));
+        }
+        Some(detail)
+    }
+
+    /// Report the served read of `token`, its `reads`th, if it is late
+    pub(crate) fn check(
+        &mut self,
+        token: &EnvName,
+        previous: Option<SystemTime>,
+        now: SystemTime,
+        reads: u32,
+    ) {
+        if let Some(detail) = self.detail(previous, now, reads) {
+            audit::emit("late_read", token, &detail);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quiet_period() {
+        assert_eq!(
+            parse_quiet_period(" 1800 "),
+            Some(Duration::from_secs(1800))
+        );
+        assert_eq!(parse_quiet_period("0"), None);
+        assert_eq!(parse_quiet_period("30m"), None);
+    }
+
+    #[test]
+    fn test_detail() {
+        let now = SystemTime::now();
+        let minutes = |n: u64| Some(now - Duration::from_secs(60 * n));
+        let mut late = LateRead {
+            quiet: Some(Duration::from_secs(30 * 60)),
+            ..LateRead::default()
+        };
+        assert_eq!(late.detail(None, now, 1), None);
+        assert_eq!(late.detail(minutes(10), now, 2), None);
+        assert_eq!(
+            late.detail(minutes(45), now, 3).unwrap(),
+            "severity=high reason=quiet_period reads=3 idle=2700"
+        );
+
+        let path = std::env::temp_dir().join(format!("awf-phase-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        late.phase_file = Some(CString::new(path.to_str().unwrap()).unwrap());
+        assert_eq!(late.detail(minutes(10), now, 4), None);
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(
+            late.detail(minutes(10), now, 5).unwrap(),
+            "severity=high reason=phase_ended reads=5 idle=600"
+        );
+        // The phase stays over once seen
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            late.detail(minutes(45), now, 6).unwrap(),
+            "severity=high reason=quiet_period,phase_ended reads=6 idle=2700"
+        );
+        assert_eq!(
+            late.detail(None, now, 1).unwrap(),
+            "severity=high reason=phase_ended reads=1"
+        );
+    }
+}
@@ -4,61 +4,453 @@
 //! On first access, caches the value in memory and unsets from environment.
 //! Subsequent calls return the cached value, so the process can read tokens
 //! multiple times while /proc/self/environ no longer exposes them.
+//! setenv()/putenv() of a protected name updates the cache instead of the real
+//! environment, so a scrubbed token cannot be put back into /proc/self/environ.
+//! clearenv() caches outstanding tokens first, so they survive the wipe.
+//! execve()/execvpe()/posix_spawn()/posix_spawnp() drop protected tokens from
+//! the child's envp and redact protected values found in any other entry;
+//! system()/popen() give the shell the same environment and audit the command.
 //!
 //! Configuration:
-//!   AWF_ONE_SHOT_TOKENS - Comma-separated list of token names to protect
-//!   If not set, uses built-in defaults
+//!   AWF_ONE_SHOT_TOKENS - Comma-separated list of token names to protect,
+//!   with optional per-token policy options (default: the names of the
+//!   AWF_ONE_SHOT_PRESETS)
 //!
 //!   AWF_ONE_SHOT_TOKEN_DEBUG - Enable debug logging output (default: off)
-//!   Set to "1" or "true" to enable logging. Logging is silent by default.
 //!
-//! Compile: cargo build --release
+//! Every other AWF_ONE_SHOT_* setting, the token policies and the audit
+//! event format are documented in README.md; `healthcheck::SETTINGS` lists
+//! the settings the library reads.
+//!
+//! Compile: cargo build --release (see README.md for the minimal feature)
 //! Usage: LD_PRELOAD=/path/to/libone_shot_token.so ./your-program
+//! On macOS: DYLD_INSERT_LIBRARIES=/path/to/libone_shot_token.dylib ./your-program
+//!
+//! The crate is also built as an rlib with a safe Rust API ([`TokenGuard`],
+//! [`Policy`], [`Detector`]) for in-process use; link it without default
+//! features so the `preload` interposers and constructors are left out.
+//! Host-side consumers read JSON lines and event datagrams with [`Event`],
+//! which follows the versioned event schema ([`EVENT_SCHEMA`]).
+
+// Without the preload feature the interposers are compiled but never called
+#![cfg_attr(not(feature = "preload"), allow(dead_code, non_snake_case))]
+
+// Declared first so the log_line! macro is visible in the other modules
+#[macro_use]
+mod log;
 
-use libc::{c_char, c_void};
+mod api;
+mod argv;
+mod audit;
+mod autodetect;
+mod broker;
+mod caller;
+mod canary;
+#[cfg(not(feature = "minimal"))]
+mod chain;
+mod config;
+mod context;
+mod control;
+mod correlation;
+#[cfg(not(feature = "minimal"))]
+mod crash;
+mod credfile;
+mod derive;
+mod detect;
+mod digest;
+mod dns;
+mod early;
+mod egress;
+mod envcrypt;
+mod environ;
+mod envname;
+mod events;
+mod exec;
+mod filewrite;
+mod fork;
+#[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+mod harden;
+mod healthcheck;
+mod honeypot;
+mod hostpolicy;
+mod idle;
+#[cfg(not(feature = "minimal"))]
+mod invalidate;
+mod json;
+mod jsonsecrets;
+#[cfg(target_os = "linux")]
+mod keyring;
+mod kill;
+#[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+mod landlock;
+mod latency;
+mod lateread;
+mod logfile;
+#[cfg(target_os = "macos")]
+mod macos;
+mod merge;
+mod multilib;
+mod musl;
+mod namespace;
+mod napi;
+mod net;
+mod open;
+#[cfg(not(feature = "minimal"))]
+mod otlp;
+mod policy;
+mod privilege;
+mod process;
+mod procfs;
+#[cfg(target_os = "linux")]
+mod procmem;
+mod profile;
+mod proxy;
+mod ratelimit;
+mod ready;
+mod reentry;
+mod refresh;
+mod report;
+mod required;
+mod schema;
+mod seal;
+mod secmem;
+mod secretdir;
+mod secretfile;
+mod setenv;
+mod sha256;
+mod shell;
+#[cfg(not(feature = "minimal"))]
+mod shmstats;
+#[cfg(not(feature = "minimal"))]
+mod sign;
+#[cfg(not(feature = "minimal"))]
+mod signals;
+mod sink;
+mod snapshot;
+mod split;
+#[cfg(all(
+    target_os = "linux",
+    target_env = "gnu",
+    any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod stdiowrite;
+mod step;
+#[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+mod stepwatch;
+mod swap;
+#[cfg(all(feature = "preload", target_env = "gnu"))]
+mod symver;
+mod sys;
+#[cfg(not(feature = "minimal"))]
+mod syslog;
+mod trace;
+mod vault;
+mod verify;
+#[cfg(not(feature = "minimal"))]
+mod watchdog;
+
+pub use api::{Detector, Policy, PolicyError, Secret, TokenGuard};
+pub use schema::{Event, EventError, EVENT_SCHEMA, SCHEMA_VERSION};
+
+use envname::{EnvName, NameSet};
+use libc::{c_char, c_int, c_void};
+use merge::{Origin, Source};
 use once_cell::sync::Lazy;
+use policy::{Mode, ScrubMode, TokenPolicy, TokenSpec, REDACTED_PLACEHOLDER};
+use setenv::call_real_unsetenv;
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
+use std::ops::{Deref, DerefMut};
 use std::ptr;
-use std::sync::Mutex;
-
-// External declaration of the environ pointer
-// This is a POSIX standard global that points to the process's environment
-extern "C" {
-    static mut environ: *mut *mut c_char;
-}
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
 
 /// Maximum number of tokens we can track
 const MAX_TOKENS: usize = 100;
 
-/// Default sensitive token environment variable names
-const DEFAULT_SENSITIVE_TOKENS: &[&str] = &[
-    // GitHub tokens
-    "COPILOT_GITHUB_TOKEN",
-    "GITHUB_TOKEN",
-    "GH_TOKEN",
-    "GITHUB_API_TOKEN",
-    "GITHUB_PAT",
-    "GH_ACCESS_TOKEN",
-    // OpenAI tokens
-    "OPENAI_API_KEY",
-    "OPENAI_KEY",
-    // Anthropic/Claude tokens
-    "ANTHROPIC_API_KEY",
-    "CLAUDE_API_KEY",
-    // Codex tokens
-    "CODEX_API_KEY",
-];
+/// A cached token value and its access bookkeeping
+struct CachedToken {
+    /// Cached C string (null if the token was not set); the stable buffer
+    /// returned to callers, zero-filled until the value is first served
+    value: *mut c_char,
+    /// Obfuscated value, present until it is written into `value`
+    sealed: Option<seal::Sealed>,
+    /// Number of reads that returned the cached value
+    reads: u32,
+    /// Times of the first and the latest read that returned the value
+    first_read: Option<SystemTime>,
+    last_read: Option<SystemTime>,
+    /// Buffer the one read of a strict token handed over to its caller; the
+    /// entry no longer serves it, but wipes still zeroize it
+    handed_over: *mut c_char,
+    /// How the value's buffer is protected (None if the token was not set)
+    protection: Option<secmem::Protection>,
+    /// Set by awf_token_lock: every later read is refused, but the value is
+    /// kept
+    locked: bool,
+    /// Set once the value has been zeroized; names the audit event emitted
+    /// for every later read (e.g. "expired" when the ttl elapsed)
+    wiped: Option<&'static str>,
+    /// Objects that served reads were attributed to, with their read counts
+    /// (only with AWF_ONE_SHOT_REPORT or AWF_ONE_SHOT_CALLER_POLICY)
+    callers: Vec<(String, u32)>,
+    /// Whether the environment was scrubbed: "cleared", "masked" or
+    /// "exposed" (None if the token was not set)
+    scrub: Option<&'static str>,
+    /// When the value is due to be replaced with a fresh one (tokens with the
+    /// refresh option, see the refresh module)
+    refresh_at: Option<Instant>,
+}
+
+impl CachedToken {
+    /// Cache entry for a token that was not set in the environment
+    fn unset() -> Self {
+        Self {
+            value: ptr::null_mut(),
+            sealed: None,
+            reads: 0,
+            first_read: None,
+            last_read: None,
+            handed_over: ptr::null_mut(),
+            protection: None,
+            locked: false,
+            wiped: None,
+            callers: Vec::new(),
+            scrub: None,
+            refresh_at: None,
+        }
+    }
+
+    /// Cache entry for a freshly cached value that has not been read yet
+    fn cached(value: *mut c_char) -> Self {
+        Self {
+            value,
+            ..Self::unset()
+        }
+    }
+
+    /// Cache entry for a value that stays sealed until it is first served
+    fn sealed(value: *mut c_char, sealed: seal::Sealed) -> Self {
+        Self {
+            sealed: Some(sealed),
+            ..Self::cached(value)
+        }
+    }
+
+    /// Plaintext of the cached value, without unsealing the stable buffer
+    ///
+    /// A strict value handed over to its caller still counts, so that output
+    /// redaction keeps recognizing it.
+    fn plaintext(&self) -> Option<Vec<u8>> {
+        if let Some(sealed) = &self.sealed {
+            return Some(sealed.open());
+        }
+        let value = if self.value.is_null() {
+            self.handed_over
+        } else {
+            self.value
+        };
+        // SAFETY: cached values are null or valid null-terminated strings we allocated
+        (!value.is_null()).then(|| unsafe { CStr::from_ptr(value) }.to_bytes().to_vec())
+    }
+
+    /// Write the plaintext into the stable buffer, ready to be served
+    fn unseal(&mut self) {
+        if let Some(sealed) = self.sealed.take() {
+            // SAFETY: the buffer was allocated with room for the value and its NUL
+            unsafe { sealed.open_into(self.value.cast::<u8>()) };
+        }
+    }
+
+    /// Zeroize the cached value and refuse all further reads
+    fn wipe(&mut self, reason: &'static str) {
+        // SAFETY: cached values are null or valid null-terminated strings we allocated
+        unsafe {
+            zeroize_cached_value(self.value);
+            zeroize_cached_value(self.handed_over);
+        }
+        self.value = ptr::null_mut();
+        self.handed_over = ptr::null_mut();
+        self.sealed = None;
+        self.wiped = Some(reason);
+    }
+
+    /// Hand the buffer of the one read a strict token allows over to its
+    /// caller, so that the cache no longer holds the value
+    ///
+    /// Every later read is refused with `strict_reread`, and zeroizes the
+    /// handed-over buffer like any other wipe.
+    fn hand_over(&mut self) {
+        self.handed_over = self.value;
+        self.value = ptr::null_mut();
+        self.sealed = None;
+        self.wiped = Some("strict_reread");
+    }
+}
 
 /// State for tracking tokens and their cached values
 struct TokenState {
-    /// List of sensitive token names to protect
-    tokens: Vec<String>,
+    /// List of sensitive tokens to protect, with their access policies
+    tokens: Vec<TokenSpec>,
     /// Cached token values - stored on first access so subsequent reads succeed
     /// even after the variable is unset from the environment. This allows
     /// /proc/self/environ to be cleaned while the process can still read tokens.
-    /// Maps token name to cached C string pointer (or null if token was not set).
-    cache: HashMap<String, *mut c_char>,
+    /// Maps canonical token name to its cached value and read count.
+    cache: HashMap<EnvName, CachedToken>,
+    /// Alias name -> canonical token name (from AWF_ONE_SHOT_TOKEN_ALIASES).
+    /// Aliases share the canonical token's cache entry and are scrubbed with it.
+    aliases: HashMap<EnvName, EnvName>,
+    /// Variables that are never served (from AWF_ONE_SHOT_DENY_TOKENS)
+    deny: Vec<EnvName>,
+    /// Global enforcement mode (from AWF_ONE_SHOT_MODE)
+    mode: Mode,
+    /// How tokens are removed from the environment (from AWF_ONE_SHOT_SCRUB)
+    scrub_mode: ScrubMode,
+    /// Where cached values are kept until served (AWF_ONE_SHOT_CACHE_BACKEND)
+    cache_backend: seal::Backend,
+    /// Whether secrets are scrubbed from argv at load (AWF_ONE_SHOT_ARGV_SCRUB)
+    argv_scrub: bool,
+    /// Whether all tokens are cached and scrubbed at load (AWF_ONE_SHOT_EAGER)
+    eager: bool,
+    /// Whether "@/path" values are read from the file and the file shredded
+    /// (AWF_ONE_SHOT_FILE_SECRETS)
+    file_secrets: bool,
+    /// Broker that "awf-broker:" handles are resolved with
+    /// (AWF_ONE_SHOT_BROKER_SOCKET)
+    broker: Option<broker::Broker>,
+    /// Key that "enc:v1:" values are decrypted with, sealed like a cached
+    /// value (AWF_ONE_SHOT_ENV_KEY_FD or AWF_ONE_SHOT_ENV_KEY_FILE)
+    env_key: Option<seal::Sealed>,
+    /// Names of the two parts of split tokens, which are also aliases of the
+    /// token (AWF_ONE_SHOT_SPLIT_TOKENS)
+    split: HashMap<EnvName, [EnvName; 2]>,
+    /// Expected SHA-256 digests of token values, by canonical name
+    /// (AWF_ONE_SHOT_TOKEN_DIGESTS)
+    digests: HashMap<EnvName, [u8; 32]>,
+    /// Tokens the broker mints when they are read and not set
+    /// (AWF_ONE_SHOT_MINT_TOKENS)
+    mint: Vec<EnvName>,
+    /// Vault agent that "vault:" references are resolved with
+    /// (AWF_ONE_SHOT_VAULT_SOCKET)
+    vault: Option<broker::Broker>,
+    /// Helper printing fresh values of tokens with the refresh option
+    /// (AWF_ONE_SHOT_REFRESH_COMMAND)
+    refresh_command: Option<CString>,
+    /// Proxy that placeholders are registered with
+    /// (AWF_ONE_SHOT_SWAP_SOCKET)
+    swap: Option<broker::Broker>,
+    /// Current workflow step (AWF_STEP_ID)
+    step: Option<String>,
+    /// File whose changes start a new step (AWF_ONE_SHOT_STEP_FILE)
+    step_file: Option<step::StepFile>,
+    /// Token list settings from the configuration file (AWF_ONE_SHOT_CONFIG)
+    config: Option<config::ConfigFile>,
+    /// Token list settings provisioned by the host (AWF_ONE_SHOT_POLICY_FILE)
+    host_policy: Option<hostpolicy::HostPolicy>,
+    /// Re-scrub interval of the watchdog thread (AWF_ONE_SHOT_WATCHDOG)
+    #[cfg(not(feature = "minimal"))]
+    watchdog_interval: Option<Duration>,
+    /// Whether SIGUSR1 dumps statistics and SIGUSR2 wipes every token
+    /// (AWF_ONE_SHOT_SIGNALS)
+    #[cfg(not(feature = "minimal"))]
+    signals: bool,
+    /// Whether the signal handlers are installed over the program's own,
+    /// calling them in turn (AWF_ONE_SHOT_SIGNAL_CHAIN)
+    #[cfg(not(feature = "minimal"))]
+    signal_chain: bool,
+    /// Signal that ends the current step (AWF_ONE_SHOT_STEP_SIGNAL)
+    #[cfg(not(feature = "minimal"))]
+    step_signal: Option<c_int>,
+    /// Whether a thread watches the step file for immediate step changes
+    /// (AWF_ONE_SHOT_STEP_WATCH)
+    #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+    step_watch: bool,
+    /// Whether crashes zeroize cached values first (AWF_ONE_SHOT_CRASH_WIPE)
+    #[cfg(not(feature = "minimal"))]
+    crash_wipe: bool,
+    /// Seconds after its first read before a strict value is revoked
+    /// (AWF_ONE_SHOT_STRICT_GRACE)
+    #[cfg(not(feature = "minimal"))]
+    strict_grace: Option<Duration>,
+    /// Strict values waiting to be revoked (see the invalidate module)
+    #[cfg(not(feature = "minimal"))]
+    revocations: invalidate::Pending,
+    /// Whether proxy variables are protected from tampering (AWF_ONE_SHOT_PROTECT_PROXY)
+    protect_proxy: bool,
+    /// Host-mandated proxy values served by getenv (AWF_ENFORCED_PROXY and
+    /// AWF_ENFORCED_NO_PROXY). The strings live as long as the state, so the
+    /// pointers handed out stay valid.
+    enforced_proxy: HashMap<String, CString>,
+    /// Whether opens of /proc/<pid>/environ are served a redacted copy
+    /// (AWF_ONE_SHOT_PROC_ENVIRON)
+    redact_proc_environ: bool,
+    /// Whether getaddrinfo lookups are reported (AWF_ONE_SHOT_DNS_AUDIT)
+    dns_audit: bool,
+    /// Correlation IDs for served reads, or None if they are off
+    /// (AWF_ONE_SHOT_CORRELATION)
+    correlation: Option<correlation::Correlation>,
+    /// Domain allowlist enforced on connect(), if delivered by the host
+    /// (AWF_ONE_SHOT_ALLOWED_DOMAINS)
+    allowlist: Option<net::Allowlist>,
+    /// Policy for opens of other processes' environ and cmdline files
+    /// (AWF_ONE_SHOT_PROC_SNOOP)
+    proc_snoop: credfile::FilePolicy,
+    /// Policy for access to the memory of other processes
+    /// (AWF_ONE_SHOT_PROC_MEMORY)
+    #[cfg(target_os = "linux")]
+    proc_memory: credfile::FilePolicy,
+    /// Whether this process is a debugging tool exempted from the memory
+    /// access policy (AWF_ONE_SHOT_PROC_MEMORY_ALLOW)
+    #[cfg(target_os = "linux")]
+    proc_memory_exempt: bool,
+    /// LD_PRELOAD and configuration added to child environments, or None if
+    /// propagation is disabled (AWF_ONE_SHOT_PROPAGATE)
+    propagation: Option<exec::Propagation>,
+    /// Command names allowed to read protected tokens, or None if every
+    /// process may (AWF_ONE_SHOT_ALLOWED_COMMS)
+    allowed_comms: Option<Vec<String>>,
+    /// Command name of this process, read at init when allowed_comms is set
+    comm: String,
+    /// Executable path of this process, read at init when a token policy
+    /// restricts executables
+    exe: String,
+    /// Policy keyed on the parent process, or None if the parent does not
+    /// matter (AWF_ONE_SHOT_PARENT_POLICY)
+    parent_policy: Option<process::ParentPolicy>,
+    /// Parent process, read at init when parent_policy is set
+    parent: Option<process::Parent>,
+    /// Per-token read-rate limit (AWF_ONE_SHOT_RATE_LIMIT)
+    rate_limit: Option<ratelimit::RateLimit>,
+    /// Recent reads of each protected token, for the rate limit
+    read_history: ratelimit::ReadHistory,
+    /// When reads count as late (AWF_ONE_SHOT_QUIET_PERIOD and
+    /// AWF_ONE_SHOT_PHASE_END_FILE)
+    late_read: lateread::LateRead,
+    /// When the cache is wiped for lack of reads (AWF_ONE_SHOT_IDLE_TIMEOUT)
+    idle: idle::Idle,
+    /// Per-caller policy for protected token reads, or None if reads are not
+    /// attributed (AWF_ONE_SHOT_CALLER_POLICY)
+    caller_policy: Option<caller::CallerPolicy>,
+    /// File the access report is appended to at exit (AWF_ONE_SHOT_REPORT)
+    report_path: Option<String>,
+    /// GitHub Actions step summary file a table of token accesses is
+    /// appended to at exit (AWF_ONE_SHOT_STEP_SUMMARY)
+    step_summary_path: Option<String>,
+    /// Whether the process is marked non-dumpable at load (AWF_ONE_SHOT_NODUMP)
+    #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+    nodump: bool,
+    /// Whether core dumps are disabled at load (AWF_ONE_SHOT_NOCORE)
+    #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+    nocore: bool,
+    /// Whether the memory-inspection seccomp filter is installed at load
+    /// (AWF_ONE_SHOT_SECCOMP)
+    #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+    seccomp: bool,
+    /// Whether the Landlock ruleset restricting /proc reads is installed at
+    /// load (AWF_ONE_SHOT_LANDLOCK)
+    #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+    landlock: bool,
     /// Whether initialization has completed
     initialized: bool,
     /// Whether debug logging is enabled (controlled by AWF_ONE_SHOT_TOKEN_DEBUG)
@@ -74,25 +466,334 @@ impl TokenState {
         Self {
             tokens: Vec::new(),
             cache: HashMap::new(),
+            aliases: HashMap::new(),
+            deny: Vec::new(),
+            mode: Mode::default(),
+            scrub_mode: ScrubMode::default(),
+            cache_backend: seal::Backend::default(),
+            argv_scrub: true,
+            eager: false,
+            file_secrets: false,
+            broker: None,
+            env_key: None,
+            split: HashMap::new(),
+            digests: HashMap::new(),
+            mint: Vec::new(),
+            refresh_command: None,
+            swap: None,
+            vault: None,
+            step: None,
+            step_file: None,
+            config: None,
+            host_policy: None,
+            #[cfg(not(feature = "minimal"))]
+            watchdog_interval: None,
+            #[cfg(not(feature = "minimal"))]
+            signals: false,
+            #[cfg(not(feature = "minimal"))]
+            signal_chain: false,
+            #[cfg(not(feature = "minimal"))]
+            step_signal: None,
+            #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+            step_watch: false,
+            #[cfg(not(feature = "minimal"))]
+            crash_wipe: false,
+            #[cfg(not(feature = "minimal"))]
+            strict_grace: None,
+            #[cfg(not(feature = "minimal"))]
+            revocations: invalidate::Pending::default(),
+            protect_proxy: true,
+            enforced_proxy: HashMap::new(),
+            redact_proc_environ: true,
+            proc_snoop: credfile::FilePolicy::Log,
+            #[cfg(target_os = "linux")]
+            proc_memory: credfile::FilePolicy::Deny,
+            #[cfg(target_os = "linux")]
+            proc_memory_exempt: false,
+            dns_audit: false,
+            correlation: None,
+            allowlist: None,
+            propagation: None,
+            allowed_comms: None,
+            comm: String::new(),
+            exe: String::new(),
+            parent_policy: None,
+            parent: None,
+            rate_limit: None,
+            read_history: ratelimit::ReadHistory::default(),
+            late_read: lateread::LateRead::default(),
+            idle: idle::Idle::default(),
+            caller_policy: None,
+            report_path: None,
+            step_summary_path: None,
+            #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+            nodump: false,
+            #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+            nocore: false,
+            #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+            seccomp: false,
+            #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+            landlock: false,
             initialized: false,
             debug_enabled: false,
         }
     }
 }
 
+/// Monotonic timestamp of library load, the reference point for token ttls
+static LOAD_TIME: OnceLock<Instant> = OnceLock::new();
+
+/// Record the load timestamp and the process credentials as soon as the
+/// dynamic linker runs our constructors
+extern "C" fn record_load_time() {
+    LOAD_TIME.get_or_init(Instant::now);
+    privilege::record();
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static RECORD_LOAD_TIME: extern "C" fn() = record_load_time;
+
+/// Scrub secrets from the command line once the dynamic linker has loaded us
+///
+/// glibc passes (argc, argv, envp) to ELF constructors, as dyld does to
+/// macOS initializers; musl calls them without arguments, so there argv is
+/// found on the initial stack instead.
+/// The check is made at runtime too: a glibc build can be loaded by musl's
+/// dynamic linker through gcompat, and the arguments are then garbage.
+extern "C" fn scrub_argv_at_load(argc: c_int, argv: *mut *mut c_char, _envp: *mut *mut c_char) {
+    let state = lock_state();
+    if !state.argv_scrub || state.mode == Mode::Observe {
+        return;
+    }
+    let passed = cfg!(target_os = "macos") || (cfg!(target_env = "gnu") && !musl::detected());
+    let (argc, argv) = if passed {
+        (argc, argv)
+    } else {
+        // SAFETY: constructors run before main, while environ is still the
+        // initial environment array
+        match unsafe { argv::initial_argv(environ::current()) } {
+            Some(args) => args,
+            None => return,
+        }
+    };
+
+    let values = protected_token_values(&state);
+    let known: Vec<&[u8]> = values.iter().map(|value| value.as_bytes()).collect();
+    // SAFETY: argc/argv come straight from the dynamic linker
+    let scrubbed = unsafe { argv::scrub_argv(argc, argv, &known) };
+
+    if state.debug_enabled && !scrubbed.is_empty() {
+        log_line!(
+            Debug,
+            "argv_scrub",
+            None,
+            "Scrubbed secrets from argv index(es) {:?} in /proc/self/cmdline",
+            scrubbed
+        );
+    }
+}
+
+#[cfg(any(target_env = "gnu", target_os = "macos"))]
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static SCRUB_ARGV_AT_LOAD: extern "C" fn(c_int, *mut *mut c_char, *mut *mut c_char) =
+    scrub_argv_at_load;
+
+/// Constructor without arguments for libcs that do not pass them
+#[cfg(not(any(target_env = "gnu", target_os = "macos")))]
+extern "C" fn scrub_argv_at_load_noargs() {
+    scrub_argv_at_load(0, ptr::null_mut(), ptr::null_mut());
+}
+
+#[cfg(not(any(target_env = "gnu", target_os = "macos")))]
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static SCRUB_ARGV_AT_LOAD: extern "C" fn() = scrub_argv_at_load_noargs;
+
+/// Cache and scrub all protected tokens at load when AWF_ONE_SHOT_EAGER is set
+extern "C" fn eager_scrub_at_load() {
+    let mut state = lock_state();
+    if state.eager && state.mode == Mode::Enforce {
+        eager_scrub(&mut state);
+    }
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static EAGER_SCRUB_AT_LOAD: extern "C" fn() = eager_scrub_at_load;
+
+/// Whether protection is switched off (AWF_ONE_SHOT_DISABLE), read once
+///
+/// Checked by the policies that live outside the state lock as well as at
+/// init, so every interposer passes calls straight through.
+static DISABLED: Lazy<bool> = Lazy::new(|| read_config_flag(c"AWF_ONE_SHOT_DISABLE", false));
+
+/// Whether AWF_ONE_SHOT_DISABLE switched protection off
+fn protection_disabled() -> bool {
+    *DISABLED
+}
+
 /// Global state protected by a mutex
 static STATE: Lazy<Mutex<TokenState>> = Lazy::new(|| Mutex::new(TokenState::new()));
 
+/// Names that getenv looks up in the state, published once the configuration
+/// is loaded
+///
+/// Any other name (PATH, HOME, ...) is passed straight through without the
+/// state lock or a heap allocation. The protected, aliased, denied, proxy and
+/// canary names only change when the configuration file is reloaded (see the
+/// config module); replaced sets are leaked, as a reader may still use them.
+static WATCHED_NAMES: AtomicPtr<NameSet> = AtomicPtr::new(ptr::null_mut());
+
+/// Publish the names the interposers act on
+fn publish_watched_names(state: &TokenState) {
+    let names = Box::into_raw(Box::new(watched_names(state)));
+    WATCHED_NAMES.store(names, Ordering::Release);
+}
+
+/// Build the set of names the interposers act on
+fn watched_names(state: &TokenState) -> NameSet {
+    let proxy = state.enforced_proxy.keys().map(|name| EnvName::from(name.as_str()));
+    NameSet::new(
+        state
+            .tokens
+            .iter()
+            .map(|spec| spec.name.clone())
+            .chain(state.aliases.keys().cloned())
+            .chain(state.deny.iter().cloned())
+            .chain(proxy)
+            .chain(canary::names().map(EnvName::from)),
+    )
+}
+
+/// Check whether a lookup of `name` must take the state lock
+///
+/// True for every name until the configuration has been loaded.
+fn is_watched(name: &[u8]) -> bool {
+    published_watched_names().is_none_or(|names| names.contains(name))
+}
+
+/// The names the interposers act on, once the configuration has been loaded
+fn published_watched_names() -> Option<&'static NameSet> {
+    // SAFETY: published sets are never freed
+    unsafe { WATCHED_NAMES.load(Ordering::Acquire).as_ref() }
+}
+
+/// The locked global state
+///
+/// Releasing it publishes the lock-free snapshot of served tokens (see the
+/// snapshot module). The thread counts as inside the library (see the
+/// reentry module) from before locking until after unlocking.
+pub(crate) struct StateGuard {
+    guard: Option<MutexGuard<'static, TokenState>>,
+    _entered: reentry::Entered,
+}
+
+impl Deref for StateGuard {
+    type Target = TokenState;
+
+    fn deref(&self) -> &TokenState {
+        self.guard.as_ref().expect("state guard is held until dropped")
+    }
+}
+
+impl DerefMut for StateGuard {
+    fn deref_mut(&mut self) -> &mut TokenState {
+        self.guard.as_mut().expect("state guard is held until dropped")
+    }
+}
+
+impl Drop for StateGuard {
+    fn drop(&mut self) {
+        if let Some(state) = self.guard.take() {
+            if state.initialized {
+                snapshot::publish(&state);
+            }
+        }
+    }
+}
+
+/// Lock the global state, initializing it on first use
+///
+/// A poisoned lock is recovered rather than propagated: a panic in one
+/// interceptor must not turn every later getenv into a crash.
+fn lock_state() -> StateGuard {
+    let entered = reentry::enter();
+    let mut state = match STATE.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+
+    if !state.initialized {
+        init_token_list(&mut state);
+    }
+    fork::settle(&mut state);
+    snapshot::fold_reads(&mut state);
+    StateGuard {
+        guard: Some(state),
+        _entered: entered,
+    }
+}
+
 /// Type alias for the real getenv function
 type GetenvFn = unsafe extern "C" fn(*const c_char) -> *mut c_char;
 
+/// Look up the next definition of a libc symbol, aborting if it is missing
+///
+/// Interposers cannot do anything sensible without the real function, and
+/// silently returning an error would change program behavior.
+pub(crate) fn resolve_next(name: &CStr) -> *mut c_void {
+    // SAFETY: dlsym with RTLD_NEXT and a valid C string
+    let symbol = unsafe { libc::dlsym(libc::RTLD_NEXT, name.as_ptr()) };
+    if symbol.is_null() {
+        log_line!(
+            Fatal,
+            "init",
+            None,
+            "Could not find real {}",
+            name.to_string_lossy()
+        );
+        std::process::abort();
+    }
+    symbol
+}
+
 /// Cached pointer to the real getenv function
 static REAL_GETENV: Lazy<GetenvFn> = Lazy::new(|| {
     // SAFETY: We're looking up a standard C library function
     unsafe {
         let symbol = libc::dlsym(libc::RTLD_NEXT, c"getenv".as_ptr());
         if symbol.is_null() {
-            eprintln!("[one-shot-token] FATAL: Could not find real getenv");
+            log_line!(Fatal, "init", None, "Could not find real getenv");
             std::process::abort();
         }
         std::mem::transmute::<*mut c_void, GetenvFn>(symbol)
@@ -106,9 +807,11 @@ static REAL_SECURE_GETENV: Lazy<Option<GetenvFn>> = Lazy::new(|| {
         let symbol = libc::dlsym(libc::RTLD_NEXT, c"secure_getenv".as_ptr());
         if symbol.is_null() {
             // Note: We can't check debug flag here because it would cause infinite recursion
-            // during initialization. This is a rare case (secure_getenv unavailable) so we
-            // always log it.
-            eprintln!("[one-shot-token] WARNING: secure_getenv not available, falling back to getenv");
+            // during initialization. Only musl and macOS are expected to lack secure_getenv,
+            // so on any other libc this is always logged.
+            if cfg!(not(target_os = "macos")) && !musl::detected() {
+                log_line!(Warning, "init", None, "secure_getenv not available, checking AT_SECURE");
+            }
             None
         } else {
             Some(std::mem::transmute::<*mut c_void, GetenvFn>(symbol))
@@ -124,14 +827,18 @@ unsafe fn call_real_getenv(name: *const c_char) -> *mut c_char {
     (*REAL_GETENV)(name)
 }
 
-/// Call the real secure_getenv function, falling back to getenv if unavailable
+/// Call the real secure_getenv function, falling back to an AT_SECURE check
+/// (issetugid on macOS) and getenv if unavailable
 ///
 /// # Safety
 /// The `name` parameter must be a valid null-terminated C string
 unsafe fn call_real_secure_getenv(name: *const c_char) -> *mut c_char {
     match *REAL_SECURE_GETENV {
         Some(func) => func(name),
-        None => call_real_getenv(name),
+        #[cfg(target_os = "linux")]
+        None => musl::secure_getenv_fallback(*REAL_GETENV, name),
+        #[cfg(target_os = "macos")]
+        None => macos::secure_getenv_fallback(*REAL_GETENV, name),
     }
 }
 
@@ -160,6 +867,89 @@ fn is_debug_enabled() -> bool {
     false
 }
 
+/// Read a boolean configuration flag through the real getenv
+///
+/// "1", "true", "yes" and "on" enable the flag and "0", "false", "no" and
+/// "off" disable it (case-insensitive); anything else, or an unset variable,
+/// yields `default`.
+fn read_config_flag(name: &CStr, default: bool) -> bool {
+    match read_config_var(name).map(|value| value.trim().to_ascii_lowercase()) {
+        Some(value) if matches!(value.as_str(), "1" | "true" | "yes" | "on") => true,
+        Some(value) if matches!(value.as_str(), "0" | "false" | "no" | "off") => false,
+        _ => default,
+    }
+}
+
+/// Read a configuration variable through the real getenv, or else take the
+/// value the hardening profile implies (see the profile module)
+///
+/// Configuration must never go through the intercepted getenv, both to avoid
+/// recursion and so that reading config never touches the token cache.
+fn read_config_var(name: &CStr) -> Option<String> {
+    merge::resolve(|source| match source {
+        Source::Env => read_env_var(name),
+        Source::Profile => profile::default_value(name).map(str::to_string),
+        _ => None,
+    })
+    .map(|(_, value)| value)
+}
+
+/// Read a configuration variable through the real getenv, ignoring the
+/// hardening profile
+fn read_env_var(name: &CStr) -> Option<String> {
+    // SAFETY: We're calling the real getenv with a valid C string
+    let value_ptr = unsafe { call_real_getenv(name.as_ptr()) };
+    if value_ptr.is_null() {
+        return None;
+    }
+
+    // SAFETY: value_ptr is valid if not null
+    let value = unsafe { CStr::from_ptr(value_ptr) };
+    value.to_str().ok().map(str::to_string)
+}
+
+/// Read a setting from the first source that sets it: the configuration
+/// file, the host policy, the environment or the hardening profile (see the
+/// merge module)
+fn resolve_setting(state: &TokenState, name: &CStr) -> Option<(Source, Vec<u8>)> {
+    merge::resolve(|source| match source {
+        Source::ConfigFile => state.config.as_ref()?.get(name).map(<[u8]>::to_vec),
+        Source::HostPolicy => state.host_policy.as_ref()?.get(name).map(<[u8]>::to_vec),
+        Source::Env => read_config_bytes(name),
+        Source::Profile => profile::default_value(name).map(|value| value.as_bytes().to_vec()),
+        Source::Default | Source::Api => None,
+    })
+}
+
+/// Read a token list setting from the configuration file, the host policy
+/// or else the environment (see the config and hostpolicy modules)
+fn read_policy_bytes(state: &TokenState, name: &CStr) -> Option<Vec<u8>> {
+    resolve_setting(state, name).map(|(_, value)| value)
+}
+
+/// The origin of the tokens `setting` implies
+fn setting_origin(state: &TokenState, setting: &'static CStr) -> Origin {
+    Origin {
+        source: resolve_setting(state, setting).map_or(Source::Default, |(source, _)| source),
+        setting,
+    }
+}
+
+/// Read a configuration variable through the real getenv as raw bytes
+///
+/// Used for lists of variable names, which need not be valid UTF-8 (see the
+/// envname module).
+fn read_config_bytes(name: &CStr) -> Option<Vec<u8>> {
+    // SAFETY: We're calling the real getenv with a valid C string
+    let value_ptr = unsafe { call_real_getenv(name.as_ptr()) };
+    if value_ptr.is_null() {
+        return None;
+    }
+
+    // SAFETY: value_ptr is valid if not null
+    Some(unsafe { CStr::from_ptr(value_ptr) }.to_bytes().to_vec())
+}
+
 /// Initialize the token list from AWF_ONE_SHOT_TOKENS or defaults
 ///
 /// # Safety
@@ -172,221 +962,2461 @@ fn init_token_list(state: &mut TokenState) {
     // Check if debug logging is enabled
     state.debug_enabled = is_debug_enabled();
 
-    // Get configuration from environment
-    let config_cstr = CString::new("AWF_ONE_SHOT_TOKENS").unwrap();
-    // SAFETY: We're calling the real getenv with a valid C string
-    let config_ptr = unsafe { call_real_getenv(config_cstr.as_ptr()) };
-
-    if !config_ptr.is_null() {
-        // SAFETY: config_ptr is valid if not null
-        let config = unsafe { CStr::from_ptr(config_ptr) };
-        if let Ok(config_str) = config.to_str() {
-            if !config_str.is_empty() {
-                // Parse comma-separated token list
-                for token in config_str.split(',') {
-                    let token = token.trim();
-                    if !token.is_empty() && state.tokens.len() < MAX_TOKENS {
-                        state.tokens.push(token.to_string());
-                    }
-                }
-
-                if !state.tokens.is_empty() {
-                    if state.debug_enabled {
-                        eprintln!(
-                            "[one-shot-token] Initialized with {} custom token(s) from AWF_ONE_SHOT_TOKENS",
-                            state.tokens.len()
-                        );
-                    }
-                    state.initialized = true;
-                    return;
-                }
-
-                // Config was set but parsed to zero tokens - fall back to defaults
-                if state.debug_enabled {
-                    eprintln!("[one-shot-token] WARNING: AWF_ONE_SHOT_TOKENS was set but parsed to zero tokens");
-                    eprintln!("[one-shot-token] WARNING: Falling back to default token list to maintain protection");
-                }
-            }
-        }
+    context::init(read_config_var(c"AWF_STEP_ID"));
+    state.step = read_config_var(c"AWF_STEP_ID")
+        .map(|step| step.trim().to_string())
+        .filter(|step| !step.is_empty());
+    load_log_format();
+    load_sink_policy();
+    load_log_destination(state);
+    load_event_socket(state);
+    #[cfg(not(feature = "minimal"))]
+    load_otlp(state);
+    #[cfg(not(feature = "minimal"))]
+    if read_config_flag(c"AWF_ONE_SHOT_AUDIT_CHAIN", false) {
+        chain::enable();
     }
-
-    // Use default token list
-    for token in DEFAULT_SENSITIVE_TOKENS {
-        if state.tokens.len() >= MAX_TOKENS {
-            break;
-        }
-        state.tokens.push((*token).to_string());
+    #[cfg(not(feature = "minimal"))]
+    sign::load();
+    if protection_disabled() {
+        load_disabled(state);
+        publish_watched_names(state);
+        healthcheck::warn_config_problems(state);
+        state.initialized = true;
+        return;
     }
-
-    if state.debug_enabled {
-        eprintln!(
-            "[one-shot-token] Initialized with {} default token(s)",
-            state.tokens.len()
+    load_mode(state);
+    load_scrub_mode(state);
+    privilege::load();
+    filewrite::load();
+    load_cache_backend(state);
+    load_host_policy(state);
+    state.config = read_config_var(c"AWF_ONE_SHOT_CONFIG")
+        .and_then(|path| config::ConfigFile::load(&path));
+    load_token_list(state);
+    load_token_aliases(state);
+    load_deny_list(state);
+    load_split_tokens(state);
+    load_token_digests(state);
+    load_secrets_dir(state);
+    load_json_secrets(state);
+    state.argv_scrub = read_config_flag(c"AWF_ONE_SHOT_ARGV_SCRUB", true);
+    state.eager = read_config_flag(c"AWF_ONE_SHOT_EAGER", false);
+    state.file_secrets = read_config_flag(c"AWF_ONE_SHOT_FILE_SECRETS", false);
+    state.broker = read_config_var(c"AWF_ONE_SHOT_BROKER_SOCKET").and_then(|socket| {
+        broker::Broker::parse(&socket, read_config_var(c"AWF_ONE_SHOT_BROKER_UID").as_deref())
+    });
+    state.vault = read_config_var(c"AWF_ONE_SHOT_VAULT_SOCKET").and_then(|socket| {
+        broker::Broker::parse(&socket, read_config_var(c"AWF_ONE_SHOT_VAULT_UID").as_deref())
+    });
+    load_env_key(state);
+    load_mint_tokens(state);
+    load_secret_namespace(state);
+    autodetect::load(state);
+    state.refresh_command = read_config_var(c"AWF_ONE_SHOT_REFRESH_COMMAND")
+        .filter(|command| !command.trim().is_empty())
+        .and_then(|command| CString::new(command.trim()).ok());
+    if state.debug_enabled
+        && state.refresh_command.is_none()
+        && state.broker.is_none()
+        && state.tokens.iter().any(|spec| spec.refresh_secs.is_some())
+    {
+        log_line!(
+            Warning,
+            "config",
+            None,
+            "refresh needs AWF_ONE_SHOT_REFRESH_COMMAND or AWF_ONE_SHOT_BROKER_SOCKET, not refreshing"
+        );
+    }
+    state.swap = read_config_var(c"AWF_ONE_SHOT_SWAP_SOCKET").and_then(|socket| {
+        broker::Broker::parse(&socket, read_config_var(c"AWF_ONE_SHOT_SWAP_UID").as_deref())
+    });
+    if state.debug_enabled
+        && state.swap.is_none()
+        && state
+            .tokens
+            .iter()
+            .any(|spec| spec.policy == TokenPolicy::Placeholder)
+    {
+        log_line!(
+            Warning,
+            "config",
+            None,
+            "placeholder needs AWF_ONE_SHOT_SWAP_SOCKET, placeholders will not be swapped"
         );
     }
+    if state.mode != Mode::Observe {
+        state.step_file = read_config_var(c"AWF_ONE_SHOT_STEP_FILE")
+            .and_then(|path| step::StepFile::new(&path));
+    }
+    state.protect_proxy = read_config_flag(c"AWF_ONE_SHOT_PROTECT_PROXY", true);
+    state.enforced_proxy = proxy::enforced_values(
+        read_config_var(c"AWF_ENFORCED_PROXY").as_deref(),
+        read_config_var(c"AWF_ENFORCED_NO_PROXY").as_deref(),
+    );
+    state.redact_proc_environ = read_config_flag(c"AWF_ONE_SHOT_PROC_ENVIRON", true);
+    load_proc_snoop_policy(state);
+    #[cfg(target_os = "linux")]
+    load_proc_memory_policy(state);
+    state.dns_audit = read_config_flag(c"AWF_ONE_SHOT_DNS_AUDIT", false);
+    load_correlation(state);
+    fork::set_track_children(read_config_flag(c"AWF_ONE_SHOT_TRACK_CHILDREN", false));
+    state.allowlist = read_config_var(c"AWF_ONE_SHOT_ALLOWED_DOMAINS")
+        .filter(|domains| !domains.trim().is_empty())
+        .map(|domains| {
+            net::Allowlist::new(&domains, read_config_var(c"AWF_ENFORCED_PROXY").as_deref())
+        });
+    state.allowed_comms = read_config_var(c"AWF_ONE_SHOT_ALLOWED_COMMS")
+        .map(|config| parse_name_list(&config))
+        .filter(|comms| !comms.is_empty());
+    if state.allowed_comms.is_some() {
+        state.comm = process::comm().unwrap_or_default();
+    }
+    load_exe(state);
+    state.parent_policy = read_config_var(c"AWF_ONE_SHOT_PARENT_POLICY")
+        .filter(|config| !config.trim().is_empty())
+        .map(|config| process::ParentPolicy::parse(&config));
+    if state.parent_policy.is_some() {
+        state.parent = Some(process::Parent::current());
+    }
+    state.rate_limit = read_config_var(c"AWF_ONE_SHOT_RATE_LIMIT")
+        .and_then(|value| ratelimit::RateLimit::parse(&value));
+    state.late_read = lateread::LateRead::load();
+    state.idle = idle::Idle::load();
+    state.caller_policy = read_config_var(c"AWF_ONE_SHOT_CALLER_POLICY")
+        .filter(|config| !config.trim().is_empty())
+        .map(|config| caller::CallerPolicy::parse(&config));
+    state.report_path =
+        read_config_var(c"AWF_ONE_SHOT_REPORT").filter(|path| !path.trim().is_empty());
+    if read_config_flag(c"AWF_ONE_SHOT_STEP_SUMMARY", false) {
+        state.step_summary_path =
+            read_config_var(c"GITHUB_STEP_SUMMARY").filter(|path| !path.trim().is_empty());
+    }
+    load_optional(state);
+    if read_config_flag(c"AWF_ONE_SHOT_PROPAGATE", true) {
+        state.propagation = Some(exec::Propagation::load());
+    }
+    egress::publish_secrets(state);
+
+    publish_watched_names(state);
+    healthcheck::warn_config_problems(state);
+    required::check(state);
     state.initialized = true;
 }
 
-/// Check if a token name is sensitive
-fn is_sensitive_token(state: &TokenState, name: &str) -> bool {
-    state.tokens.iter().any(|t| t == name)
+/// Load the settings of the subsystems that minimal builds leave out
+#[cfg(not(feature = "minimal"))]
+fn load_optional(state: &mut TokenState) {
+    state.watchdog_interval = read_config_var(c"AWF_ONE_SHOT_WATCHDOG")
+        .and_then(|value| watchdog::parse_interval(&value));
+    state.signals = read_config_flag(c"AWF_ONE_SHOT_SIGNALS", false);
+    state.signal_chain = read_config_flag(c"AWF_ONE_SHOT_SIGNAL_CHAIN", false);
+    state.step_signal = read_config_var(c"AWF_ONE_SHOT_STEP_SIGNAL")
+        .and_then(|value| signals::parse_signal(&value));
+    state.crash_wipe = read_config_flag(c"AWF_ONE_SHOT_CRASH_WIPE", false);
+    state.strict_grace = read_config_var(c"AWF_ONE_SHOT_STRICT_GRACE")
+        .and_then(|value| invalidate::parse_grace(&value));
+    load_stats_shm(state);
+    load_hardening(state);
 }
 
-/// Format token value for logging: show first 4 characters + "..."
-fn format_token_value(value: &str) -> String {
-    if value.is_empty() {
-        return "(empty)".to_string();
-    }
-
-    if value.len() <= 4 {
-        format!("{}...", value)
-    } else {
-        format!("{}...", &value[..4])
-    }
+/// Load the hardening switches and the step file watch, which rely on
+/// Linux-only interfaces
+#[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+fn load_hardening(state: &mut TokenState) {
+    state.nodump = read_config_flag(c"AWF_ONE_SHOT_NODUMP", false);
+    state.nocore = read_config_flag(c"AWF_ONE_SHOT_NOCORE", false);
+    state.seccomp = read_config_flag(c"AWF_ONE_SHOT_SECCOMP", false);
+    state.landlock = read_config_flag(c"AWF_ONE_SHOT_LANDLOCK", false);
+    state.step_watch = read_config_flag(c"AWF_ONE_SHOT_STEP_WATCH", false);
 }
 
-/// Check if a token still exists in the process environment
-///
-/// This function verifies whether unsetenv() successfully cleared the token
-/// by directly checking the process's environ pointer. This works correctly
-/// in both chroot and non-chroot modes (reading /proc/self/environ fails in
-/// chroot because it shows the host's procfs, not the chrooted process's state).
-fn check_task_environ_exposure(token_name: &str, debug_enabled: bool) {
-    // SAFETY: environ is a standard POSIX global that points to the process's environment.
-    // It's safe to read as long as we don't hold references across modifications.
-    // We're only reading it after unsetenv() has completed, so the pointer is stable.
-    unsafe {
-        let mut env_ptr = environ;
-        if env_ptr.is_null() {
-            if debug_enabled {
-                eprintln!("[one-shot-token] INFO: Token {} cleared (environ is null)", token_name);
-            }
-            return;
-        }
+/// Switches that rely on Linux-only interfaces
+#[cfg(all(not(feature = "minimal"), not(target_os = "linux")))]
+const LINUX_ONLY_FLAGS: [&CStr; 5] = [
+    c"AWF_ONE_SHOT_NODUMP",
+    c"AWF_ONE_SHOT_NOCORE",
+    c"AWF_ONE_SHOT_SECCOMP",
+    c"AWF_ONE_SHOT_LANDLOCK",
+    c"AWF_ONE_SHOT_STEP_WATCH",
+];
 
-        // Iterate through environment variables
-        let token_prefix = format!("{}=", token_name);
-        let token_prefix_bytes = token_prefix.as_bytes();
+/// Warn about Linux-only switches this platform does not support
+#[cfg(all(not(feature = "minimal"), not(target_os = "linux")))]
+fn load_hardening(_state: &mut TokenState) {
+    warn_unsupported(&LINUX_ONLY_FLAGS, &[], "on this platform");
+}
 
-        while !(*env_ptr).is_null() {
-            let env_cstr = CStr::from_ptr(*env_ptr);
-            let env_bytes = env_cstr.to_bytes();
+/// Switches of the subsystems that minimal builds leave out
+#[cfg(feature = "minimal")]
+const UNAVAILABLE_FLAGS: [&CStr; 9] = [
+    c"AWF_ONE_SHOT_AUDIT_CHAIN",
+    c"AWF_ONE_SHOT_SIGNALS",
+    c"AWF_ONE_SHOT_SIGNAL_CHAIN",
+    c"AWF_ONE_SHOT_STEP_WATCH",
+    c"AWF_ONE_SHOT_CRASH_WIPE",
+    c"AWF_ONE_SHOT_NODUMP",
+    c"AWF_ONE_SHOT_NOCORE",
+    c"AWF_ONE_SHOT_SECCOMP",
+    c"AWF_ONE_SHOT_LANDLOCK",
+];
 
-            // Check if this entry starts with our token name
-            if env_bytes.len() >= token_prefix_bytes.len()
-                && &env_bytes[..token_prefix_bytes.len()] == token_prefix_bytes {
-                if debug_enabled {
-                    eprintln!(
-                        "[one-shot-token] WARNING: Token {} still exposed in process environment",
-                        token_name
-                    );
-                }
-                return;
-            }
+/// Values of the subsystems that minimal builds leave out
+#[cfg(feature = "minimal")]
+const UNAVAILABLE_VARS: [&CStr; 8] = [
+    c"AWF_ONE_SHOT_AUDIT_KEY_FD",
+    c"AWF_ONE_SHOT_AUDIT_KEY_FILE",
+    c"AWF_ONE_SHOT_SYSLOG",
+    c"AWF_ONE_SHOT_OTLP_ENDPOINT",
+    c"AWF_ONE_SHOT_STATS_SHM",
+    c"AWF_ONE_SHOT_WATCHDOG",
+    c"AWF_ONE_SHOT_STRICT_GRACE",
+    c"AWF_ONE_SHOT_STEP_SIGNAL",
+];
 
-            env_ptr = env_ptr.add(1);
-        }
+/// Warn about settings of the subsystems this minimal build leaves out
+#[cfg(feature = "minimal")]
+fn load_optional(_state: &mut TokenState) {
+    warn_unsupported(&UNAVAILABLE_FLAGS, &UNAVAILABLE_VARS, "by this minimal build");
+}
 
-        // Token not found in environment - success!
-        if debug_enabled {
-            eprintln!(
-                "[one-shot-token] INFO: Token {} cleared from process environment",
-                token_name
-            );
-        }
+/// Warn about each of `flags` that is enabled and each of `vars` that is
+/// set, as not supported `why`
+///
+/// A hardening switch that silently does nothing would be worse than a
+/// larger library, so the warning is not limited to debug logging.
+#[cfg(any(feature = "minimal", not(target_os = "linux")))]
+fn warn_unsupported(flags: &[&CStr], vars: &[&CStr], why: &str) {
+    let flags = flags.iter().filter(|name| read_config_flag(name, false));
+    let vars = vars
+        .iter()
+        .filter(|name| read_config_var(name).is_some_and(|value| !value.trim().is_empty()));
+    for name in flags.chain(vars) {
+        log_line!(
+            Warning,
+            "config",
+            None,
+            "{} is not supported {}",
+            name.to_string_lossy(),
+            why
+        );
     }
 }
 
-/// Core implementation for cached token access
+/// Pass every call through for AWF_ONE_SHOT_DISABLE, and record who
+/// disabled protection
 ///
-/// # Safety
-/// - `name` must be a valid null-terminated C string
-/// - `real_getenv_fn` must be a valid function to call for getting the real value
-unsafe fn handle_getenv_impl(
-    name: *const c_char,
-    real_getenv_fn: unsafe fn(*const c_char) -> *mut c_char,
-    via_secure: bool,
-) -> *mut c_char {
-    // Null name - pass through
-    if name.is_null() {
-        return real_getenv_fn(name);
+/// No tokens are loaded and the checks that are on by default are turned
+/// off. Logging is already configured, so the audit event reaches the host
+/// wherever it collects events: unlike dropping LD_PRELOAD, disabling the
+/// library leaves a trail in every process it is loaded into.
+fn load_disabled(state: &mut TokenState) {
+    state.argv_scrub = false;
+    state.protect_proxy = false;
+    state.redact_proc_environ = false;
+    state.proc_snoop = credfile::FilePolicy::Allow;
+    #[cfg(target_os = "linux")]
+    {
+        state.proc_memory = credfile::FilePolicy::Allow;
     }
 
-    // Convert name to Rust string for comparison
-    let name_cstr = CStr::from_ptr(name);
-    let name_str = match name_cstr.to_str() {
-        Ok(s) => s,
-        Err(_) => return real_getenv_fn(name),
-    };
+    // SAFETY: getuid and geteuid have no preconditions
+    let (uid, euid) = unsafe { (libc::getuid(), libc::geteuid()) };
+    let mut detail = format!(
+        "severity=high disabled_at={} uid={} euid={} exe={} {}",
+        audit::timestamp(),
+        uid,
+        euid,
+        audit::detail_word(&process::exe().unwrap_or_default()),
+        process::Parent::current().describe()
+    );
+    let who = [
+        ("user", c"USER"),
+        ("actor", c"GITHUB_ACTOR"),
+        ("workflow", c"GITHUB_WORKFLOW"),
+        ("run_id", c"GITHUB_RUN_ID"),
+        ("job", c"GITHUB_JOB"),
+    ];
+    for (key, name) in who {
+        if let Some(value) = read_config_var(name) {
+            detail.push_str(&format!(" {}={}", key, audit::detail_word(&value)));
+        }
+    }
+    audit::emit("protection_disabled", "*", &detail);
+    log_line!(
+        Warning,
+        "config",
+        None,
+        "Token protection DISABLED by AWF_ONE_SHOT_DISABLE: all calls pass through"
+    );
+}
 
-    // Lock state and ensure initialization
-    let mut state = match STATE.lock() {
-        Ok(guard) => guard,
-        Err(poisoned) => poisoned.into_inner(),
+/// Select the log line format from AWF_ONE_SHOT_LOG_FORMAT
+///
+/// Loaded before the rest of the configuration so that configuration
+/// warnings already use the selected format.
+fn load_log_format() {
+    let Some(value) = read_config_var(c"AWF_ONE_SHOT_LOG_FORMAT") else {
+        return;
     };
 
-    if !state.initialized {
-        init_token_list(&mut state);
-    }
+    log::set_format(&value);
+}
 
-    // Check if this is a sensitive token
-    if !is_sensitive_token(&state, name_str) {
-        // Not sensitive - pass through (drop lock first for performance)
-        drop(state);
-        return real_getenv_fn(name);
+/// Select what reads do while an audit sink fails from
+/// AWF_ONE_SHOT_SINK_FAILURE
+fn load_sink_policy() {
+    let Some(value) = read_config_var(c"AWF_ONE_SHOT_SINK_FAILURE") else {
+        return;
+    };
+    match sink::Policy::parse(&value) {
+        Some(policy) => sink::set_policy(policy),
+        None => log_line!(
+            Warning,
+            "config",
+            None,
+            "Unknown AWF_ONE_SHOT_SINK_FAILURE value {:?}, keeping open",
+            value
+        ),
     }
+}
 
-    // Sensitive token - check if already cached
-    if let Some(&cached_ptr) = state.cache.get(name_str) {
-        // Already accessed - return cached value (may be null if token wasn't set)
-        return cached_ptr;
+/// Count reads in the AWF_ONE_SHOT_STATS_SHM segment, if set
+#[cfg(not(feature = "minimal"))]
+fn load_stats_shm(state: &TokenState) {
+    let name = read_config_var(c"AWF_ONE_SHOT_STATS_SHM");
+    let Some(name) = name.filter(|name| !name.trim().is_empty()) else {
+        return;
+    };
+    let tokens: Vec<EnvName> = state.tokens.iter().map(|spec| spec.name.clone()).collect();
+    match shmstats::install(&name, &tokens) {
+        Ok(()) if state.debug_enabled => {
+            log_line!(Debug, "init", None, "Counting reads in shared memory {}", name);
+        }
+        Ok(()) => {}
+        Err(err) => {
+            log_line!(Warning, "config", None, "Could not map stats segment {}: {}", name, err);
+        }
     }
+}
 
-    // First access - get the real value and cache it
-    let result = real_getenv_fn(name);
-
-    if result.is_null() {
-        // Token not set - cache null to prevent repeated log messages
-        state.cache.insert(name_str.to_string(), ptr::null_mut());
-        return ptr::null_mut();
+/// Send events to the AWF_ONE_SHOT_EVENT_SOCKET supervisor socket, if set
+fn load_event_socket(state: &TokenState) {
+    let path = read_config_var(c"AWF_ONE_SHOT_EVENT_SOCKET");
+    let Some(path) = path.filter(|path| !path.trim().is_empty()) else {
+        return;
+    };
+    match events::install(&path) {
+        Ok(()) if state.debug_enabled => {
+            log_line!(Debug, "init", None, "Sending events to {}", path);
+        }
+        Ok(()) => {}
+        Err(err) => {
+            log_line!(Warning, "config", None, "Could not use event socket {}: {}", path, err);
+        }
     }
+}
 
-    // Copy the value before unsetting
-    let value_cstr = CStr::from_ptr(result);
-    let value_str = value_cstr.to_str().unwrap_or("");
-    let value_bytes = value_cstr.to_bytes_with_nul();
-
-    // Allocate memory that will never be freed (must persist for caller's use)
-    let cached = libc::malloc(value_bytes.len()) as *mut c_char;
-    if cached.is_null() {
-        eprintln!("[one-shot-token] ERROR: Failed to allocate memory for token value");
-        std::process::abort();
+/// Export events to the AWF_ONE_SHOT_OTLP_ENDPOINT collector, if set
+#[cfg(not(feature = "minimal"))]
+fn load_otlp(state: &TokenState) {
+    let url = read_config_var(c"AWF_ONE_SHOT_OTLP_ENDPOINT");
+    let Some(url) = url.filter(|url| !url.trim().is_empty()) else {
+        return;
+    };
+    let headers = read_config_var(c"AWF_ONE_SHOT_OTLP_HEADERS");
+    let service = read_config_var(c"AWF_ONE_SHOT_OTLP_SERVICE");
+    match otlp::install(&url, headers.as_deref(), service.as_deref()) {
+        Ok(()) if state.debug_enabled => {
+            log_line!(Debug, "init", None, "Exporting events to {}", url);
+        }
+        Ok(()) => {}
+        Err(err) => {
+            log_line!(Warning, "config", None, "Could not export events to {}: {}", url, err);
+        }
     }
+}
 
-    // Copy the value
-    ptr::copy_nonoverlapping(value_bytes.as_ptr(), cached as *mut u8, value_bytes.len());
+/// Send log lines to AWF_ONE_SHOT_LOG_FD or AWF_ONE_SHOT_LOG_FILE, if set,
+/// and also to the AWF_ONE_SHOT_SYSLOG backend, syncing them as
+/// AWF_ONE_SHOT_LOG_SYNC asks
+///
+/// The descriptor takes precedence over the file. If neither can be used,
+/// lines keep going to stderr.
+fn load_log_destination(state: &TokenState) {
+    #[cfg(not(feature = "minimal"))]
+    if let Some(value) = read_config_var(c"AWF_ONE_SHOT_SYSLOG") {
+        syslog::install(&value, state.debug_enabled);
+    }
+    if let Some(value) = read_config_var(c"AWF_ONE_SHOT_LOG_SYNC") {
+        match logfile::Durability::parse(&value) {
+            Some(durability) => logfile::set_durability(durability),
+            None => log_line!(
+                Warning,
+                "config",
+                None,
+                "Unknown AWF_ONE_SHOT_LOG_SYNC value {:?}, not syncing",
+                value
+            ),
+        }
+    }
 
-    // Get debug flag before dropping the state
-    let debug_enabled = state.debug_enabled;
+    if let Some(value) = read_config_var(c"AWF_ONE_SHOT_LOG_FD") {
+        let installed = value
+            .trim()
+            .parse::<c_int>()
+            .map_err(|_| std::io::Error::from_raw_os_error(libc::EBADF))
+            .and_then(logfile::install_fd);
+        match installed {
+            Ok(()) => return,
+            Err(err) => {
+                log_line!(Warning, "config", None, "Could not log to fd {}: {}", value, err);
+            }
+        }
+    }
 
-    // Cache the pointer so subsequent reads return the same value
-    state.cache.insert(name_str.to_string(), cached);
+    let path = read_config_var(c"AWF_ONE_SHOT_LOG_FILE");
+    let Some(path) = path.filter(|path| !path.trim().is_empty()) else {
+        return;
+    };
+    let max_size = read_config_var(c"AWF_ONE_SHOT_LOG_MAX_SIZE")
+        .and_then(|value| logfile::parse_size(&value))
+        .unwrap_or(logfile::DEFAULT_MAX_SIZE);
 
-    // Unset the environment variable so it's no longer accessible
-    libc::unsetenv(name);
+    match logfile::install(&path, max_size) {
+        Ok(()) if state.debug_enabled => {
+            log_line!(Debug, "init", None, "Logging to {} (rotated at {} bytes)", path, max_size);
+        }
+        Ok(()) => {}
+        Err(err) => {
+            log_line!(Warning, "config", None, "Could not open log file {}: {}", path, err);
+        }
+    }
+}
+
+/// Load the enforcement mode from AWF_ONE_SHOT_MODE
+///
+/// Unrecognized values fall back to enforcement so a typo never silently
+/// disables protection.
+fn load_mode(state: &mut TokenState) {
+    let Some(value) = read_config_var(c"AWF_ONE_SHOT_MODE") else {
+        return;
+    };
+
+    if let Some(mode) = Mode::parse(&value) {
+        state.mode = mode;
+    }
+
+    if state.debug_enabled && state.mode == Mode::Observe {
+        log_line!(Debug, "init", None, "Observe mode: tokens are logged but not unset or cached");
+    }
+}
+
+/// Load the scrub mode from AWF_ONE_SHOT_SCRUB
+///
+/// Unrecognized values fall back to unsetenv, the strongest scrub.
+fn load_scrub_mode(state: &mut TokenState) {
+    let Some(value) = read_config_var(c"AWF_ONE_SHOT_SCRUB") else {
+        return;
+    };
+
+    if let Some(scrub_mode) = ScrubMode::parse(&value) {
+        state.scrub_mode = scrub_mode;
+    }
+}
+
+/// Load the cache backend from AWF_ONE_SHOT_CACHE_BACKEND
+///
+/// Unrecognized values keep values in memory.
+fn load_cache_backend(state: &mut TokenState) {
+    let Some(value) = read_config_var(c"AWF_ONE_SHOT_CACHE_BACKEND") else {
+        return;
+    };
+
+    if let Some(backend) = seal::Backend::parse(&value) {
+        state.cache_backend = backend;
+    }
+}
+
+/// Load the tokens the broker mints from AWF_ONE_SHOT_MINT_TOKENS
+///
+/// Aliases stand for their canonical token, and names that are not protected
+/// yet are added to the token list. Deny-listed names are skipped. Without a
+/// broker the list is ignored.
+fn load_mint_tokens(state: &mut TokenState) {
+    let Some(config) = read_config_bytes(c"AWF_ONE_SHOT_MINT_TOKENS") else {
+        return;
+    };
+    if state.broker.is_none() {
+        if state.debug_enabled {
+            log_line!(
+                Warning,
+                "config",
+                None,
+                "AWF_ONE_SHOT_MINT_TOKENS needs AWF_ONE_SHOT_BROKER_SOCKET, not minting"
+            );
+        }
+        return;
+    }
+
+    let origin = setting_origin(state, c"AWF_ONE_SHOT_MINT_TOKENS");
+    for name in envname::split_list(&config).map(EnvName::new) {
+        if is_denied_token(state, &name) {
+            continue;
+        }
+        let canonical = match resolve_sensitive_token(state, &name) {
+            Some(canonical) => canonical.clone(),
+            None if state.tokens.len() < MAX_TOKENS => {
+                state.tokens.push(TokenSpec::implied(&name, origin));
+                name
+            }
+            None => continue,
+        };
+        if !state.mint.contains(&canonical) {
+            state.mint.push(canonical);
+        }
+    }
+}
+
+/// Load the key of encrypted values from AWF_ONE_SHOT_ENV_KEY_FD or
+/// AWF_ONE_SHOT_ENV_KEY_FILE
+///
+/// The descriptor variable is unset once read: children must not read from a
+/// closed number. Observe mode reads no key, since it never caches a value.
+fn load_env_key(state: &mut TokenState) {
+    let fd = read_config_var(c"AWF_ONE_SHOT_ENV_KEY_FD");
+    if fd.is_some() {
+        // SAFETY: valid C string
+        unsafe { call_real_unsetenv(c"AWF_ONE_SHOT_ENV_KEY_FD".as_ptr()) };
+    }
+    if state.mode == Mode::Observe {
+        return;
+    }
+    let file = read_config_var(c"AWF_ONE_SHOT_ENV_KEY_FILE");
+    state.env_key = envcrypt::load_key(fd.as_deref(), file.as_deref(), state.cache_backend);
+    if state.debug_enabled && state.env_key.is_some() {
+        log_line!(
+            Debug,
+            "init",
+            None,
+            "Loaded the key of encrypted values ({} storage)",
+            state.cache_backend.as_str()
+        );
+    }
+}
+
+/// Load the snooping policy from AWF_ONE_SHOT_PROC_SNOOP
+///
+/// Unrecognized values keep the default of logging.
+fn load_proc_snoop_policy(state: &mut TokenState) {
+    let Some(value) = read_config_var(c"AWF_ONE_SHOT_PROC_SNOOP") else {
+        return;
+    };
+
+    if let Some(policy) = credfile::FilePolicy::parse(&value) {
+        state.proc_snoop = policy;
+    }
+}
+
+/// Load the memory access policy from AWF_ONE_SHOT_PROC_MEMORY
+///
+/// Unrecognized values keep the default of denying. Also reads whether this
+/// process is exempted from it (AWF_ONE_SHOT_PROC_MEMORY_ALLOW).
+#[cfg(target_os = "linux")]
+fn load_proc_memory_policy(state: &mut TokenState) {
+    if let Some(tools) = read_config_var(c"AWF_ONE_SHOT_PROC_MEMORY_ALLOW") {
+        state.proc_memory_exempt = procmem::load_exemption(&parse_name_list(&tools));
+    }
+    let Some(value) = read_config_var(c"AWF_ONE_SHOT_PROC_MEMORY") else {
+        return;
+    };
+
+    if let Some(policy) = credfile::FilePolicy::parse(&value) {
+        state.proc_memory = policy;
+    }
+}
+
+/// Parse a comma-separated list of variable names
+///
+/// Whitespace around names is trimmed, empty entries are skipped and at most
+/// MAX_TOKENS names are returned.
+fn parse_name_list(config: &str) -> Vec<String> {
+    config
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .take(MAX_TOKENS)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parse a token entry of AWF_ONE_SHOT_TOKENS or AWF_ONE_SHOT_EXTRA_TOKENS,
+/// resolving the symlinks of its executables
+///
+/// Unknown options are reported by the configuration check (see the
+/// healthcheck module).
+fn parse_token_entry(entry: &[u8]) -> Option<TokenSpec> {
+    let (mut spec, _) = policy::parse_token_spec(entry)?;
+    for exe in &mut spec.allowed_exes {
+        *exe = process::canonical_exe(exe);
+    }
+    Some(spec)
+}
+
+/// Load the token policy the host provisioned, from AWF_ONE_SHOT_POLICY_FILE
+/// or the well-known path
+fn load_host_policy(state: &mut TokenState) {
+    let path = read_config_var(c"AWF_ONE_SHOT_POLICY_FILE")
+        .unwrap_or_else(|| hostpolicy::DEFAULT_PATH.to_string());
+    let path = path.trim();
+    if path.is_empty() {
+        return;
+    }
+    state.host_policy = hostpolicy::HostPolicy::load(path);
+    if let Some(policy) = state.host_policy.as_ref().filter(|_| state.debug_enabled) {
+        log_line!(
+            Debug,
+            "init",
+            None,
+            "Loaded {} setting(s) from the host policy {}",
+            policy.len(),
+            path
+        );
+    }
+}
+
+/// Populate the protected token list from AWF_ONE_SHOT_TOKENS or the
+/// presets, then apply AWF_ONE_SHOT_EXTRA_TOKENS (see the merge module)
+fn load_token_list(state: &mut TokenState) {
+    let settings = merge::TokenSettings {
+        tokens: resolve_setting(state, c"AWF_ONE_SHOT_TOKENS"),
+        presets: resolve_setting(state, c"AWF_ONE_SHOT_PRESETS"),
+        extra: resolve_setting(state, c"AWF_ONE_SHOT_EXTRA_TOKENS"),
+    };
+    let (tokens, custom) = merge::token_list(&settings, parse_token_entry);
+    state.tokens = tokens;
+
+    if state.debug_enabled {
+        let (kind, setting, resolved) = if custom {
+            ("custom", "AWF_ONE_SHOT_TOKENS", &settings.tokens)
+        } else {
+            ("default", "AWF_ONE_SHOT_PRESETS", &settings.presets)
+        };
+        let source = resolved.as_ref().map_or(Source::Default, |(source, _)| *source);
+        log_line!(
+            Debug,
+            "init",
+            None,
+            "Initialized with {} {} token(s) from {} ({})",
+            state.tokens.len(),
+            kind,
+            setting,
+            source.as_str()
+        );
+    }
+}
+
+/// Parse AWF_ONE_SHOT_TOKEN_ALIASES into (alias, canonical) pairs
+///
+/// Entries are comma-separated `ALIAS=CANONICAL` pairs with whitespace trimmed.
+/// Malformed entries and self-aliases are skipped.
+fn parse_token_aliases(config: &[u8]) -> Vec<(EnvName, EnvName)> {
+    envname::split_list(config)
+        .filter_map(|entry| {
+            let eq = entry.iter().position(|&byte| byte == b'=')?;
+            let (alias, canonical) = (entry[..eq].trim_ascii(), entry[eq + 1..].trim_ascii());
+            if alias.is_empty() || canonical.is_empty() || alias == canonical {
+                return None;
+            }
+            Some((EnvName::new(alias), EnvName::new(canonical)))
+        })
+        .collect()
+}
+
+/// Register token aliases from AWF_ONE_SHOT_TOKEN_ALIASES
+///
+/// The canonical name of every alias is added to the protected token list so
+/// that both names are scrubbed from the environment on first access. Alias
+/// names are removed from the token list so the cache is keyed by canonical name.
+fn load_token_aliases(state: &mut TokenState) {
+    let Some((source, config)) = resolve_setting(state, c"AWF_ONE_SHOT_TOKEN_ALIASES") else {
+        return;
+    };
+    let origin = Origin {
+        source,
+        setting: c"AWF_ONE_SHOT_TOKEN_ALIASES",
+    };
+
+    for (alias, canonical) in parse_token_aliases(&config) {
+        if state.aliases.len() >= MAX_TOKENS {
+            break;
+        }
+        // Only one level of aliasing is supported
+        if state.aliases.contains_key(&canonical) || state.aliases.values().any(|c| *c == alias) {
+            if state.debug_enabled {
+                log_line!(
+                    Warning,
+                    "config",
+                    None,
+                    "Ignoring chained alias {}={}",
+                    alias, canonical
+                );
+            }
+            continue;
+        }
+
+        state.tokens.retain(|t| t.name != alias);
+        if !is_sensitive_token(state, &canonical) && state.tokens.len() < MAX_TOKENS {
+            state.tokens.push(TokenSpec::implied(&canonical, origin));
+        }
+        state.aliases.insert(alias, canonical);
+    }
+
+    if state.debug_enabled && !state.aliases.is_empty() {
+        log_line!(
+            Debug,
+            "init",
+            None,
+            "Registered {} token alias(es) from AWF_ONE_SHOT_TOKEN_ALIASES",
+            state.aliases.len()
+        );
+    }
+}
+
+/// Load the deny list from AWF_ONE_SHOT_DENY_TOKENS
+///
+/// Denied variables take precedence over protected tokens and aliases.
+fn load_deny_list(state: &mut TokenState) {
+    let Some(config) = read_policy_bytes(state, c"AWF_ONE_SHOT_DENY_TOKENS") else {
+        return;
+    };
+
+    state.deny = envname::split_list(&config)
+        .take(MAX_TOKENS)
+        .map(EnvName::new)
+        .collect();
+
+    if state.debug_enabled && !state.deny.is_empty() {
+        log_line!(
+            Debug,
+            "init",
+            None,
+            "Denying {} token(s) from AWF_ONE_SHOT_DENY_TOKENS",
+            state.deny.len()
+        );
+    }
+}
+
+/// Read the secret files of AWF_ONE_SHOT_SECRETS_DIR into the cache
+///
+/// Each file becomes a protected token (or fills the cache entry of the one
+/// it is named after, or of the token it is an alias of), and a variable of
+/// the same name is scrubbed. Deny-listed names are skipped. Observe mode
+/// only reports what would be read.
+fn load_secrets_dir(state: &mut TokenState) {
+    let Some(dir) =
+        read_config_var(c"AWF_ONE_SHOT_SECRETS_DIR").filter(|dir| !dir.trim().is_empty())
+    else {
+        return;
+    };
+    let map = read_config_var(c"AWF_ONE_SHOT_SECRETS_MAP");
+    let revoke = read_config_flag(c"AWF_ONE_SHOT_SECRETS_REVOKE", false);
+
+    let origin = setting_origin(state, c"AWF_ONE_SHOT_SECRETS_DIR");
+    for secret in secretdir::list(dir.trim(), map.as_deref()) {
+        if is_denied_token(state, secret.name.as_bytes()) {
+            continue;
+        }
+        if state.mode == Mode::Observe {
+            audit::emit("observed", &secret.name, "would=discover_secret");
+            continue;
+        }
+        let canonical = match resolve_sensitive_token(state, secret.name.as_bytes()) {
+            Some(canonical) => canonical.clone(),
+            None => {
+                state.tokens.push(TokenSpec::implied(&secret.name, origin));
+                EnvName::from(secret.name.as_str())
+            }
+        };
+        if state.cache.contains_key(&canonical) {
+            continue;
+        }
+        // SAFETY: only performs system calls on the discovered path
+        let Some(value) = (unsafe { secretdir::read(&secret, revoke) }) else {
+            continue;
+        };
+        cache_loaded_value(state, canonical, value);
+    }
+}
+
+/// Cache a value the library read itself (from a secret file or a JSON
+/// blob) and wipe the copy
+///
+/// The value takes the place of any variable of the token's name or of its
+/// aliases, which is scrubbed.
+fn cache_loaded_value(state: &mut TokenState, canonical: EnvName, value: CString) {
+    verify_token_digest(state, &canonical, value.as_bytes());
+    let mut cached = alloc_cached_value(state, &canonical, &value);
+    secretfile::wipe(value);
+
+    let group = token_group(state, &canonical);
+    let group_cstrs: Vec<CString> = group
+        .iter()
+        .map(|member| member.to_cstring().unwrap_or_default())
+        .collect();
+    // SAFETY: call_real_getenv is the real libc getenv, and group_cstrs
+    // holds the C string forms of group
+    unsafe {
+        let present = group_cstrs
+            .iter()
+            .any(|member| !call_real_getenv(member.as_ptr()).is_null());
+        if present {
+            cached.scrub = Some(scrub_token_group(
+                &group,
+                &group_cstrs,
+                state.scrub_mode,
+                state.debug_enabled,
+            ));
+        }
+    }
+    state.cache.insert(canonical, cached);
+}
+
+/// Serve the members of the AWF_JSON_SECRETS object as protected tokens
+///
+/// Each member becomes a protected token (or fills the cache entry of the one
+/// it is named after, or of the token it is an alias of), like a secret file,
+/// unless a secret file already did. Deny-listed names are skipped. The blob
+/// is scrubbed once parsed, whether it parsed or not. Observe mode only
+/// reports what would be expanded.
+fn load_json_secrets(state: &mut TokenState) {
+    // SAFETY: valid C string
+    let blob = unsafe { call_real_getenv(jsonsecrets::VAR.as_ptr()) };
+    if blob.is_null() {
+        return;
+    }
+    // SAFETY: blob is a valid C string; it is parsed before being scrubbed
+    let parsed = jsonsecrets::parse(unsafe { CStr::from_ptr(blob) }.to_bytes());
+    if state.mode == Mode::Observe {
+        for secret in parsed.into_iter().flat_map(|parsed| parsed.secrets) {
+            audit::emit("observed", secret.name.lossy(), "would=expand_json_secret");
+            secretfile::wipe(secret.value);
+        }
+        return;
+    }
+    let group = [EnvName::new(jsonsecrets::VAR.to_bytes())];
+    // SAFETY: the C string is the name in group
+    unsafe {
+        scrub_token_group(
+            &group,
+            &[jsonsecrets::VAR.to_owned()],
+            state.scrub_mode,
+            state.debug_enabled,
+        )
+    };
+    let parsed = match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            audit::emit(
+                "json_secret",
+                "*",
+                &format!("severity=high action=invalid error={}", audit::detail_word(&err)),
+            );
+            return;
+        }
+    };
+    for (key, reason) in parsed.skipped {
+        let detail = format!("action=skipped reason={}", reason);
+        audit::emit("json_secret", audit::detail_word(&key), &detail);
+    }
+
+    for secret in parsed.secrets {
+        let canonical = match resolve_sensitive_token(state, secret.name.as_bytes()) {
+            Some(canonical) => Some(canonical.clone()),
+            None if is_denied_token(state, secret.name.as_bytes())
+                || state.tokens.len() >= MAX_TOKENS =>
+            {
+                None
+            }
+            None => {
+                let origin = Origin {
+                    source: Source::Env,
+                    setting: jsonsecrets::VAR,
+                };
+                state.tokens.push(TokenSpec::implied(&secret.name, origin));
+                Some(secret.name.clone())
+            }
+        };
+        let Some(canonical) = canonical.filter(|canonical| !state.cache.contains_key(canonical))
+        else {
+            secretfile::wipe(secret.value);
+            continue;
+        };
+        let detail = format!("action=cached bytes={}", secret.value.as_bytes().len());
+        audit::emit("json_secret", canonical.lossy(), &detail);
+        cache_loaded_value(state, canonical, secret.value);
+    }
+}
+
+/// Serve AWF_SECRET_<NAME> variables as the protected token NAME
+///
+/// Each namespaced variable becomes an alias of its bare name (or of the
+/// token the bare name is an alias of), and the token is cached and scrubbed
+/// at once, so that neither name stays in the environment. A namespaced
+/// variable of a deny-listed name is scrubbed without being read. Observe
+/// mode only reports what would be remapped.
+fn load_secret_namespace(state: &mut TokenState) {
+    if !read_config_flag(c"AWF_ONE_SHOT_SECRET_NAMESPACE", true) {
+        return;
+    }
+    let origin = setting_origin(state, c"AWF_ONE_SHOT_SECRET_NAMESPACE");
+    // SAFETY: environ is only read; the state lock is held
+    for (namespaced, bare) in unsafe { namespace::list() } {
+        let detail = format!("from={}", namespaced);
+        if state.mode == Mode::Observe {
+            audit::emit(
+                "observed",
+                bare.lossy(),
+                &format!("would=remap_secret {}", detail),
+            );
+            continue;
+        }
+        // A namespaced name configured in its own right is left alone
+        if is_sensitive_token(state, &namespaced)
+            || state.aliases.contains_key(&namespaced)
+            || is_denied_token(state, &namespaced)
+        {
+            continue;
+        }
+        let group = [namespaced.clone()];
+        let group_cstrs: Vec<CString> = group
+            .iter()
+            .map(|member| member.to_cstring().unwrap_or_default())
+            .collect();
+        let canonical = match resolve_sensitive_token(state, &bare) {
+            Some(canonical) => Some(canonical.clone()),
+            None if is_denied_token(state, &bare) || state.tokens.len() >= MAX_TOKENS => None,
+            None => {
+                state.tokens.push(TokenSpec::implied(&bare, origin));
+                Some(bare.clone())
+            }
+        };
+        let Some(canonical) = canonical.filter(|_| state.aliases.len() < MAX_TOKENS) else {
+            // SAFETY: group_cstrs holds the C string form of group
+            unsafe {
+                scrub_token_group(&group, &group_cstrs, state.scrub_mode, state.debug_enabled)
+            };
+            audit::emit(
+                "secret_remapped",
+                bare.lossy(),
+                &format!("{} action=scrubbed", detail),
+            );
+            continue;
+        };
+        state.aliases.insert(namespaced, canonical.clone());
+
+        // A value already cached (e.g. from a secret file) takes precedence
+        let cached = if state.cache.contains_key(&canonical) {
+            // SAFETY: group_cstrs holds the C string form of group
+            unsafe {
+                scrub_token_group(&group, &group_cstrs, state.scrub_mode, state.debug_enabled)
+            };
+            false
+        } else {
+            // SAFETY: call_real_getenv is the real libc getenv
+            unsafe { cache_token(state, &canonical, call_real_getenv) }
+        };
+        audit::emit(
+            "secret_remapped",
+            canonical.lossy(),
+            &format!("{} action={}", detail, if cached { "cached" } else { "scrubbed" }),
+        );
+    }
+}
+
+/// Register the parts of split tokens set in the environment
+/// (AWF_ONE_SHOT_SPLIT_TOKENS)
+///
+/// Parts that are themselves protected, aliased or denied are left alone.
+fn load_split_tokens(state: &mut TokenState) {
+    if !read_config_flag(c"AWF_ONE_SHOT_SPLIT_TOKENS", false) || state.mode == Mode::Observe {
+        return;
+    }
+
+    let names: Vec<EnvName> = state.tokens.iter().map(|spec| spec.name.clone()).collect();
+    for canonical in names {
+        let parts = split::part_names(&canonical);
+        let present = parts.iter().any(|part| {
+            part.to_cstring()
+                .is_some_and(|part| read_config_bytes(&part).is_some())
+        });
+        let taken = parts.iter().any(|part| {
+            is_sensitive_token(state, part)
+                || state.aliases.contains_key(part)
+                || is_denied_token(state, part)
+        });
+        if !present || taken || state.aliases.len() + 2 > MAX_TOKENS {
+            continue;
+        }
+        for part in &parts {
+            state.aliases.insert(part.clone(), canonical.clone());
+        }
+        state.split.insert(canonical, parts);
+    }
+
+    if state.debug_enabled && !state.split.is_empty() {
+        log_line!(
+            Debug,
+            "init",
+            None,
+            "Recombining {} split token(s) from their parts",
+            state.split.len()
+        );
+    }
+}
+
+/// Load the expected digests of token values from AWF_ONE_SHOT_TOKEN_DIGESTS
+///
+/// A digest given for an alias applies to its canonical token, and a name
+/// that is not protected yet is added to the token list. Deny-listed names
+/// are skipped.
+fn load_token_digests(state: &mut TokenState) {
+    let Some(config) = read_config_bytes(c"AWF_ONE_SHOT_TOKEN_DIGESTS") else {
+        return;
+    };
+    let origin = setting_origin(state, c"AWF_ONE_SHOT_TOKEN_DIGESTS");
+
+    for (name, expected) in digest::parse(&config) {
+        if is_denied_token(state, &name) {
+            continue;
+        }
+        let canonical = match resolve_sensitive_token(state, &name) {
+            Some(canonical) => canonical.clone(),
+            None if state.tokens.len() < MAX_TOKENS => {
+                state.tokens.push(TokenSpec::implied(&name, origin));
+                name
+            }
+            None => continue,
+        };
+        state.digests.insert(canonical, expected);
+    }
+
+    if state.debug_enabled && !state.digests.is_empty() {
+        log_line!(
+            Debug,
+            "init",
+            None,
+            "Verifying {} token(s) against AWF_ONE_SHOT_TOKEN_DIGESTS",
+            state.digests.len()
+        );
+    }
+}
+
+/// Compare a value about to be cached with the digest the host gave for the
+/// token, if any (see the digest module)
+fn verify_token_digest(state: &TokenState, canonical: &EnvName, value: &[u8]) {
+    if let Some(expected) = state.digests.get(canonical) {
+        digest::verify(&canonical.lossy(), expected, value);
+    }
+}
+
+/// Rebuild the token and deny lists after the configuration file changed
+/// (see the config module)
+///
+/// Tokens protected for other reasons than the lists (aliases, split tokens,
+/// minted tokens, digests, tokens added through awf_token_protect and every
+/// cached token, whose value has already left the environment) stay
+/// protected, with their previous options if they are no longer listed. Returns the names added to and removed from the
+/// token list.
+#[cfg(target_os = "linux")]
+fn reload_token_list(state: &mut TokenState) -> (Vec<EnvName>, Vec<EnvName>) {
+    let previous = std::mem::take(&mut state.tokens);
+    state.deny.clear();
+    load_token_list(state);
+    load_deny_list(state);
+    let aliases = &state.aliases;
+    state.tokens.retain(|spec| !aliases.contains_key(&spec.name));
+
+    let required: Vec<EnvName> = state
+        .aliases
+        .values()
+        .chain(state.split.keys())
+        .chain(&state.mint)
+        .chain(state.digests.keys())
+        .chain(state.cache.keys())
+        .chain(
+            previous
+                .iter()
+                .filter(|spec| spec.origin.source == Source::Api)
+                .map(|spec| &spec.name),
+        )
+        .cloned()
+        .collect();
+    for name in required {
+        if is_sensitive_token(state, &name) || state.tokens.len() >= MAX_TOKENS {
+            continue;
+        }
+        let spec = previous.iter().find(|spec| spec.name == name);
+        state
+            .tokens
+            .push(spec.cloned().unwrap_or_else(|| TokenSpec::new(&name)));
+    }
+
+    load_exe(state);
+    publish_watched_names(state);
+    snapshot::reset();
+
+    let added = state
+        .tokens
+        .iter()
+        .filter(|spec| !previous.iter().any(|listed| listed.name == spec.name))
+        .map(|spec| spec.name.clone())
+        .collect();
+    let removed = previous
+        .into_iter()
+        .filter(|listed| !is_sensitive_token(state, &listed.name))
+        .map(|listed| listed.name)
+        .collect();
+    (added, removed)
+}
+
+/// Resolve the executable if a token policy depends on it
+fn load_exe(state: &mut TokenState) {
+    let needed = state
+        .tokens
+        .iter()
+        .any(|spec| !spec.allowed_exes.is_empty() || spec.policy == TokenPolicy::Derive);
+    if needed && state.exe.is_empty() {
+        state.exe = process::exe().unwrap_or_default();
+    }
+}
+
+/// Check if a variable is on the deny list
+fn is_denied_token(state: &TokenState, name: &[u8]) -> bool {
+    state.deny.iter().any(|t| *t == *name)
+}
+
+/// Handle a read of a deny-listed variable
+///
+/// The variable is unset if it is still present in the environment, an audit
+/// event is emitted, and NULL is returned to the caller.
+///
+/// # Safety
+/// - `name` must be a valid null-terminated C string
+/// - `real_getenv_fn` must be a valid function to call for getting the real value
+unsafe fn handle_denied_token(
+    name: *const c_char,
+    name_bytes: &[u8],
+    real_getenv_fn: unsafe fn(*const c_char) -> *mut c_char,
+    debug_enabled: bool,
+) -> *mut c_char {
+    let present = !real_getenv_fn(name).is_null();
+    if present {
+        call_real_unsetenv(name);
+        check_task_environ_exposure(name_bytes, ScrubMode::Unset, debug_enabled);
+    }
+
+    let name = String::from_utf8_lossy(name_bytes);
+    let Some(reads) = kill::count_denied_read() else {
+        audit::emit("denied", &name, &format!("present={}", present));
+        return ptr::null_mut();
+    };
+    audit::emit(
+        "denied",
+        &name,
+        &format!("present={} action=killed", present),
+    );
+    kill::terminate("denied", &name, &format!("reads={}", reads))
+}
+
+/// Check if a token name is sensitive
+fn is_sensitive_token(state: &TokenState, name: &[u8]) -> bool {
+    state.tokens.iter().any(|t| t.name == *name)
+}
+
+/// Look up the spec (name and policy options) of a protected token
+fn token_spec<'a>(state: &'a TokenState, name: &[u8]) -> Option<&'a TokenSpec> {
+    state.tokens.iter().find(|t| t.name == *name)
+}
+
+/// Check whether a token's ttl has elapsed since library load
+fn ttl_elapsed(spec: Option<&TokenSpec>) -> bool {
+    let Some(ttl_secs) = spec.and_then(|spec| spec.ttl_secs) else {
+        return false;
+    };
+    let load_time = *LOAD_TIME.get_or_init(Instant::now);
+    load_time.elapsed() >= Duration::from_secs(ttl_secs)
+}
+
+/// The `expires_at` instant of a token, if it has passed
+fn past_expiry(spec: Option<&TokenSpec>) -> Option<SystemTime> {
+    spec?
+        .expires_at
+        .filter(|&expires_at| SystemTime::now() >= expires_at)
+}
+
+/// Log that the credential behind a token expired, so reads now fail
+fn log_expiry(canonical: &EnvName, expires_at: SystemTime) {
+    log_line!(
+        Warning,
+        "expired",
+        Some(&canonical.lossy()),
+        "Token {} expired at {}; reads return NULL until the host provides a new one",
+        canonical,
+        policy::format_expires_at(expires_at)
+    );
+}
+
+/// Overwrite a cached C string with zeros
+///
+/// The allocation is deliberately kept: callers may still hold the pointer
+/// from an earlier read, and must see an empty string rather than freed memory.
+///
+/// # Safety
+/// `value` must be null or point to a valid null-terminated C string
+unsafe fn zeroize_cached_value(value: *mut c_char) {
+    if value.is_null() {
+        return;
+    }
+    let len = libc::strlen(value);
+    sys::explicit_bzero(value as *mut c_void, len);
+}
+
+/// Zeroize every cached token and scrub those not read yet from the
+/// environment, so later reads are refused with `reason`
+///
+/// Returns the number of cached values zeroized. Tokens that were not set
+/// stay unset, and tokens already wiped keep their reason.
+fn wipe_every_token(state: &mut TokenState, reason: &'static str) -> usize {
+    let mut wiped = 0;
+    let names: Vec<EnvName> = state.tokens.iter().map(|spec| spec.name.clone()).collect();
+    for name in names {
+        match state.cache.get_mut(&name) {
+            Some(entry) if entry.value.is_null() && entry.wiped.is_none() => {}
+            Some(entry) => {
+                if entry.wiped.is_none() {
+                    entry.wipe(reason);
+                    wiped += 1;
+                } else if !entry.handed_over.is_null() {
+                    // Keeps the reason the read was refused with
+                    entry.wipe(entry.wiped.unwrap_or(reason));
+                    wiped += 1;
+                }
+            }
+            None => {
+                // Not read yet: the value may still be in the environment
+                let group = token_group(state, &name);
+                let group_cstrs: Vec<CString> = group
+                    .iter()
+                    .map(|member| member.to_cstring().unwrap_or_default())
+                    .collect();
+                // SAFETY: group_cstrs are the C string forms of group
+                unsafe {
+                    scrub_token_group(&group, &group_cstrs, state.scrub_mode, state.debug_enabled)
+                };
+                let mut entry = CachedToken::unset();
+                entry.wiped = Some(reason);
+                state.cache.insert(name, entry);
+            }
+        }
+    }
+    wiped
+}
+
+/// Serve a read of an already-cached token, enforcing strict, ttl and max_reads
+///
+/// Returns None if the token has not been cached yet.
+fn serve_cached_token(state: &mut TokenState, canonical: &EnvName) -> Option<*mut c_char> {
+    let spec = token_spec(state, canonical);
+    let max_reads = spec.and_then(|spec| spec.max_reads);
+    let strict = spec.is_some_and(|spec| spec.policy == TokenPolicy::Strict);
+    let expired_now = ttl_elapsed(spec);
+    let expiry = past_expiry(spec);
+    let entry = state.cache.get_mut(canonical)?;
+
+    if entry.locked {
+        audit::emit("locked", canonical, &format!("reads={}", entry.reads));
+        return Some(ptr::null_mut());
+    }
+
+    // Token wasn't set - nothing to count or wipe
+    if entry.value.is_null() && entry.wiped.is_none() {
+        return Some(ptr::null_mut());
+    }
+
+    if entry.wiped.is_none() {
+        if expired_now {
+            entry.wipe("expired");
+        } else if let Some(expires_at) = expiry {
+            entry.wipe("expired");
+            log_expiry(canonical, expires_at);
+        } else if strict && entry.reads > 0 {
+            // The one permitted read already happened
+            entry.wipe("strict_reread");
+        }
+    }
+
+    if let Some(event) = entry.wiped {
+        // A re-read of a strict token also zeroizes the value its one read
+        // handed over
+        if !entry.handed_over.is_null() {
+            entry.wipe(event);
+        }
+        audit::emit(event, canonical, &format!("reads={}", entry.reads));
+        return Some(ptr::null_mut());
+    }
+
+    if let Some(max_reads) = max_reads {
+        if entry.reads >= max_reads {
+            let detail = format!("max_reads={} reads={}", max_reads, entry.reads);
+            let honeypot =
+                honeypot::serve(&canonical.lossy(), state.swap.as_ref(), || entry.plaintext());
+            if honeypot.is_null() {
+                audit::emit("max_reads_exceeded", canonical, &detail);
+            } else {
+                let detail = format!("{} action=honeypot", detail);
+                audit::emit("max_reads_exceeded", canonical, &detail);
+            }
+            return Some(honeypot);
+        }
+    }
+
+    entry.reads += 1;
+    let now = SystemTime::now();
+    entry.first_read.get_or_insert(now);
+    let previous = entry.last_read.replace(now);
+    entry.unseal();
+    let (value, reads) = (entry.value, entry.reads);
+    if strict {
+        entry.hand_over();
+    }
+    state.late_read.check(canonical, previous, now, reads);
+    #[cfg(not(feature = "minimal"))]
+    if strict {
+        invalidate::schedule(state, canonical, value);
+    }
+    Some(value)
+}
+
+/// Resolve a variable name to the canonical protected token it refers to
+///
+/// Returns the canonical name for aliases, the name itself for protected
+/// tokens, and None for variables that are not protected. Names are compared
+/// byte for byte (see the envname module).
+fn resolve_sensitive_token<'a>(state: &'a TokenState, name: &[u8]) -> Option<&'a EnvName> {
+    let canonical = state.aliases.get(name).map_or(name, EnvName::as_bytes);
+    token_spec(state, canonical).map(|spec| &spec.name)
+}
+
+/// Current environment values of all protected tokens and their aliases
+///
+/// Used to recognize protected secrets outside the environment (e.g. in argv).
+/// Tokens that were already cached and unset are read from the cache.
+fn protected_token_values(state: &TokenState) -> Vec<String> {
+    protected_token_entries(state)
+        .into_iter()
+        .map(|(_, value)| value)
+        .collect()
+}
+
+/// Like protected_token_values, but paired with the canonical token name
+///
+/// The redaction placeholder served for `redact` tokens is not a secret and
+/// is left out.
+fn protected_token_entries(state: &TokenState) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    for spec in &state.tokens {
+        if let Some(entry) = state.cache.get(&spec.name) {
+            if let Some(value) = entry.plaintext() {
+                if let Ok(value) = String::from_utf8(value) {
+                    entries.push((spec.name.to_string(), value));
+                }
+            }
+            continue;
+        }
+
+        for member in token_group(state, &spec.name) {
+            let Some(member) = member.to_cstring() else {
+                continue;
+            };
+            if let Some(value) = read_config_var(&member) {
+                entries.push((spec.name.to_string(), value));
+            }
+        }
+    }
+    entries.retain(|(_, value)| !value.is_empty() && value != REDACTED_PLACEHOLDER);
+    entries
+}
+
+/// Names that share the cached value of `canonical`: the canonical token
+/// itself, followed by every alias that maps to it
+fn token_group(state: &TokenState, canonical: &EnvName) -> Vec<EnvName> {
+    let mut group = vec![canonical.clone()];
+    group.extend(
+        state
+            .aliases
+            .iter()
+            .filter(|(_, c)| *c == canonical)
+            .map(|(alias, _)| alias.clone()),
+    );
+    group
+}
+
+/// Format token value for logging: show first 4 characters + "..."
+fn format_token_value(value: &str) -> String {
+    if value.is_empty() {
+        return "(empty)".to_string();
+    }
+
+    if value.len() <= 4 {
+        format!("{}...", value)
+    } else {
+        format!("{}...", &value[..4])
+    }
+}
+
+/// Check whether a token is exposed in the process environment
+///
+/// In mask and placeholder mode a scrubbed entry does not count as exposure.
+///
+/// # Safety
+/// Must not race with concurrent modification of the environment
+#[cfg(not(feature = "minimal"))]
+unsafe fn exposed_in_environ(name: &[u8], scrub_mode: ScrubMode) -> bool {
+    match environ::find_entry(name) {
+        None => false,
+        Some(entry) => !scrubbed_in_place(CStr::from_ptr(entry), name, scrub_mode),
+    }
+}
+
+/// Check whether an environ entry was scrubbed in place by `scrub_mode`
+fn scrubbed_in_place(entry: &CStr, name: &[u8], scrub_mode: ScrubMode) -> bool {
+    match scrub_mode {
+        ScrubMode::Unset => false,
+        ScrubMode::Mask => environ::is_masked(entry, name),
+        ScrubMode::Placeholder => environ::is_placeholder(entry, name),
+    }
+}
+
+/// Check if a token still exists in the process environment
+///
+/// This function verifies whether unsetenv() successfully cleared the token
+/// by directly checking the process's environ pointer. This works correctly
+/// in both chroot and non-chroot modes (reading /proc/self/environ fails in
+/// chroot because it shows the host's procfs, not the chrooted process's state).
+///
+/// In mask and placeholder mode the entry is expected to remain, so only an
+/// unscrubbed value counts as exposure. Returns "cleared", "masked" or
+/// "exposed".
+fn check_task_environ_exposure(
+    name: &[u8],
+    scrub_mode: ScrubMode,
+    debug_enabled: bool,
+) -> &'static str {
+    // SAFETY: We're only reading environ after unsetenv()/masking has
+    // completed, with the state lock held, so the entries are stable.
+    let entry = unsafe { environ::find_entry(name) };
+    let (level, result) = match entry {
+        None => (log::Level::Info, "cleared"),
+        // SAFETY: find_entry returns valid null-terminated environ entries
+        Some(entry) if scrubbed_in_place(unsafe { CStr::from_ptr(entry) }, name, scrub_mode) => {
+            (log::Level::Info, "masked")
+        }
+        Some(_) => (log::Level::Warning, "exposed"),
+    };
+    let token_name = &*String::from_utf8_lossy(name);
+    let fields = [("result".to_string(), result.to_string())];
+    events::send(level, "unset_verification", Some(token_name), &fields);
+
+    if !debug_enabled {
+        return result;
+    }
+    match result {
+        "cleared" => {
+            log_line!(
+                Info,
+                "unset_verification",
+                Some(token_name),
+                "Token {} cleared from process environment",
+                token_name
+            );
+        }
+        "masked" => {
+            log_line!(
+                Info,
+                "unset_verification",
+                Some(token_name),
+                "Token {} masked in process environment",
+                token_name
+            );
+        }
+        _ => {
+            log_line!(
+                Warning,
+                "unset_verification",
+                Some(token_name),
+                "Token {} still exposed in process environment",
+                token_name
+            );
+        }
+    }
+    result
+}
+
+/// Remove a token and all of its aliases from the environment
+///
+/// Depending on the scrub mode the variables are unset (and the value bytes of
+/// the orphaned `NAME=value` strings zeroed), kept with their values
+/// overwritten by `*`, or replaced with `NAME=***AWF-PROTECTED***`. Returns the least successful verification result
+/// among the group (see check_task_environ_exposure).
+///
+/// # Safety
+/// `group_cstrs` must hold the C string forms of the names in `group`
+unsafe fn scrub_token_group(
+    group: &[EnvName],
+    group_cstrs: &[CString],
+    scrub_mode: ScrubMode,
+    debug_enabled: bool,
+) -> &'static str {
+    let mut outcome = "cleared";
+    for (member, member_cstr) in group.iter().zip(group_cstrs) {
+        match scrub_mode {
+            ScrubMode::Unset => {
+                // Zero the NAME=value string before unsetting: once unset,
+                // environ no longer points at it, and musl frees the strings
+                // that setenv allocated
+                if let Some(entry) = environ::find_entry(member) {
+                    if !environ::zero_value(entry, member) && debug_enabled {
+                        log_line!(
+                            Warning,
+                            "unset_verification",
+                            Some(&member.lossy()),
+                            "Could not zero residual bytes of {} (read-only string)",
+                            member
+                        );
+                    }
+                }
+                call_real_unsetenv(member_cstr.as_ptr());
+            }
+            ScrubMode::Mask => {
+                environ::mask_value(member);
+            }
+            ScrubMode::Placeholder => {
+                if !environ::replace_with_placeholder(member)
+                    && environ::find_entry(member).is_some()
+                    && debug_enabled
+                {
+                    log_line!(
+                        Warning,
+                        "unset_verification",
+                        Some(&member.lossy()),
+                        "Could not zero residual bytes of {} (read-only string)",
+                        member
+                    );
+                }
+            }
+        }
+
+        // Verify the token was cleared from the process environment
+        let result = check_task_environ_exposure(member, scrub_mode, debug_enabled);
+        if outcome == "cleared" || result == "exposed" {
+            outcome = result;
+        }
+    }
+    outcome
+}
+
+/// A copy of `value` without leading and trailing ASCII whitespace, or None
+/// if it has none (the `trim` token option)
+///
+/// The bytes removed are reported with a `token_trimmed` audit event.
+fn trim_token_value(canonical: &EnvName, value: &CStr) -> Option<CString> {
+    let bytes = value.to_bytes();
+    let start = bytes.trim_ascii_start();
+    let trimmed = start.trim_ascii_end();
+    if trimmed.len() == bytes.len() {
+        return None;
+    }
+    audit::emit(
+        "token_trimmed",
+        canonical,
+        &format!(
+            "leading={} trailing={}",
+            bytes.len() - start.len(),
+            start.len() - trimmed.len()
+        ),
+    );
+    // A part of a C string has no NUL byte
+    CString::new(trimmed).ok()
+}
+
+/// Seal a token value and allocate its stable return buffer
+///
+/// Redacted tokens store the placeholder instead, derived tokens the value
+/// derived for this executable, and placeholder tokens a placeholder the swap
+/// proxy knows the value by, so the real value is never handed to the caller. The buffer is zero-filled until the value is first
+/// served (see the seal module). It is never freed: it must stay valid for as
+/// long as a caller may hold the pointer. It lives in secret or locked memory
+/// that is excluded from core dumps (see the secmem module).
+fn alloc_cached_value(state: &TokenState, canonical: &EnvName, value: &CStr) -> CachedToken {
+    let spec = token_spec(state, canonical);
+    let policy = spec.map(|spec| spec.policy);
+    let trimmed = spec
+        .filter(|spec| spec.trim)
+        .and_then(|_| trim_token_value(canonical, value));
+    let value = trimmed.as_deref().unwrap_or(value);
+    let placeholder = CString::new(REDACTED_PLACEHOLDER).unwrap();
+    let derived;
+    let swapped;
+    let value_cstr = match policy {
+        Some(TokenPolicy::Redact) => placeholder.as_c_str(),
+        Some(TokenPolicy::Derive) => {
+            // Init reads the executable only when a token policy needs it
+            let exe = match state.exe.as_str() {
+                "" => process::exe().unwrap_or_default(),
+                exe => exe.to_string(),
+            };
+            let identity = derive::identity(&exe);
+            audit::emit(
+                "token_derive",
+                canonical,
+                &format!("action=derived identity={}", audit::detail_word(&identity)),
+            );
+            // Neither the identity nor the hex MAC contains a NUL byte
+            derived = CString::new(derive::derive(value.to_bytes(), &identity)).unwrap();
+            derived.as_c_str()
+        }
+        Some(TokenPolicy::Placeholder) => {
+            let placeholder = swap::placeholder().unwrap_or_else(|| swap::PREFIX.to_string());
+            if let Some(swap) = &state.swap {
+                // SAFETY: only performs system calls on a socket it creates
+                unsafe { swap::register(swap, &canonical.lossy(), &placeholder, value) };
+            }
+            // The placeholder is ASCII
+            swapped = CString::new(placeholder).unwrap();
+            swapped.as_c_str()
+        }
+        _ => value,
+    };
+    let sealed = seal::Sealed::with_backend(value_cstr.to_bytes(), state.cache_backend);
+    if let Some(trimmed) = trimmed {
+        secretfile::wipe(trimmed);
+    }
+    let buffer = vec![0; sealed.len() + 1];
+    let (cached, protection) = alloc_revocable(state, policy, &buffer)
+        .unwrap_or_else(|| secmem::alloc_copy(&buffer));
+    if state.debug_enabled {
+        log_line!(
+            Debug,
+            "cache",
+            Some(&canonical.lossy()),
+            "Cached value of {} sealed in {} until served, buffer in {} memory",
+            canonical,
+            sealed.backend().as_str(),
+            protection.as_str()
+        );
+    }
+    let refresh_at = token_spec(state, canonical)
+        .and_then(|spec| spec.refresh_secs)
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    CachedToken {
+        protection: Some(protection),
+        refresh_at,
+        ..CachedToken::sealed(cached, sealed)
+    }
+}
+
+/// Copy `buffer` into a mapping of its own if a value with `policy` is
+/// revoked after its grace period (see the invalidate module)
+#[cfg(not(feature = "minimal"))]
+fn alloc_revocable(
+    state: &TokenState,
+    policy: Option<TokenPolicy>,
+    buffer: &[u8],
+) -> Option<(*mut c_char, secmem::Protection)> {
+    if policy != Some(TokenPolicy::Strict) || state.strict_grace.is_none() {
+        return None;
+    }
+    secmem::alloc_isolated(buffer)
+}
+
+/// Minimal builds never revoke values
+#[cfg(feature = "minimal")]
+fn alloc_revocable(
+    _state: &TokenState,
+    _policy: Option<TokenPolicy>,
+    _buffer: &[u8],
+) -> Option<(*mut c_char, secmem::Protection)> {
+    None
+}
+
+/// A fresh value of `canonical` from AWF_ONE_SHOT_REFRESH_COMMAND, or else
+/// minted by the broker; None if neither is configured or the fetch failed
+///
+/// # Safety
+/// Runs the refresh helper or performs system calls on the broker's socket
+unsafe fn fetch_fresh_value(state: &TokenState, canonical: &EnvName) -> Option<CString> {
+    match (&state.refresh_command, &state.broker) {
+        (Some(command), _) => {
+            let program = multilib::Program::Path(command);
+            let child = exec::child_env(state, environ::current() as _, program, "refresh");
+            let envp = child
+                .as_ref()
+                .map_or(environ::current() as _, exec::ChildEnv::as_ptr);
+            refresh::fetch(refresh::Source::Command(command, envp), &canonical.lossy())
+        }
+        (None, Some(broker)) => refresh::fetch(refresh::Source::Broker(broker), &canonical.lossy()),
+        (None, None) => None,
+    }
+}
+
+/// Replace a cached value that is due for refresh (see the refresh module)
+///
+/// A value that cannot be refreshed is kept, and the refresh retried later.
+///
+/// # Safety
+/// Runs the refresh helper or performs system calls on the broker's socket
+unsafe fn refresh_token(state: &mut TokenState, canonical: &EnvName) {
+    let due = state.cache.get(canonical).is_some_and(|entry| {
+        !entry.locked
+            && entry.wiped.is_none()
+            && entry.refresh_at.is_some_and(|at| Instant::now() >= at)
+    });
+    if !due {
+        return;
+    }
+
+    let Some(fresh) = fetch_fresh_value(state, canonical) else {
+        if let Some(entry) = state.cache.get_mut(canonical) {
+            entry.refresh_at = Some(Instant::now() + refresh::RETRY);
+        }
+        return;
+    };
+
+    // The previous buffer stays allocated: callers may still hold it
+    let cached = alloc_cached_value(state, canonical, &fresh);
+    secretfile::wipe(fresh);
+    if let Some(entry) = state.cache.get_mut(canonical) {
+        entry.value = cached.value;
+        entry.sealed = cached.sealed;
+        entry.protection = cached.protection;
+        entry.refresh_at = cached.refresh_at;
+    }
+    egress::publish_secrets(state);
+}
+
+/// Cache a token's value and scrub it (and its aliases) from the environment
+///
+/// The value is taken from the canonical name, falling back to its aliases.
+/// The new cache entry has a read count of zero; the caller decides whether
+/// this counts as a read. Returns true if a value was cached.
+///
+/// # Safety
+/// `real_getenv_fn` must be a valid function to call for getting the real value
+unsafe fn cache_token(
+    state: &mut TokenState,
+    canonical: &EnvName,
+    real_getenv_fn: unsafe fn(*const c_char) -> *mut c_char,
+) -> bool {
+    let group = token_group(state, canonical);
+    let group_cstrs: Vec<CString> = group
+        .iter()
+        .map(|member| member.to_cstring().unwrap_or_default())
+        .collect();
+    // The parts of a split token only count together (see the split module)
+    let split = state.split.get(canonical).cloned();
+    let result = group
+        .iter()
+        .zip(&group_cstrs)
+        .filter(|(member, _)| split.as_ref().is_none_or(|parts| !parts.contains(member)))
+        .map(|(_, member)| real_getenv_fn(member.as_ptr()))
+        // A placeholder left by a scrubbing parent stands for an unset token
+        .find(|&value| {
+            !value.is_null()
+                && CStr::from_ptr(value).to_bytes() != environ::PROTECTED_PLACEHOLDER.as_bytes()
+        })
+        .unwrap_or(ptr::null_mut());
+    let parts = match &split {
+        Some(parts) if result.is_null() => parts.each_ref().map(|part| {
+            let value = real_getenv_fn(part.to_cstring().unwrap_or_default().as_ptr());
+            (!value.is_null()).then(|| CStr::from_ptr(value))
+        }),
+        _ => [None, None],
+    };
+
+    // A token the broker mints is requested when it is read and not set
+    let unset = result.is_null() && parts == [None, None];
+    let mint = unset && state.broker.is_some() && state.mint.contains(canonical);
+    if unset && !mint {
+        if state.digests.contains_key(canonical) {
+            digest::report_missing(&canonical.lossy());
+        }
+        // Token not set - cache null to prevent repeated log messages
+        state.cache.insert(canonical.clone(), CachedToken::unset());
+        return false;
+    }
+
+    let debug_enabled = state.debug_enabled;
+    let scrub_mode = state.scrub_mode;
+
+    // Read after the ttl elapsed or the credential expired - scrub the
+    // environment but never cache or serve the value
+    let spec = token_spec(state, canonical);
+    let expiry = past_expiry(spec);
+    if let Some(expires_at) = expiry {
+        log_expiry(canonical, expires_at);
+    }
+    if ttl_elapsed(spec) || expiry.is_some() {
+        let scrub = scrub_token_group(&group, &group_cstrs, scrub_mode, debug_enabled);
+        state.cache.insert(
+            canonical.clone(),
+            CachedToken {
+                wiped: Some("expired"),
+                scrub: Some(scrub),
+                ..CachedToken::unset()
+            },
+        );
+        return false;
+    }
+
+    // A value minted by the broker, or recombined from the parts of a split
+    // token, instead of the value of a variable
+    let taken = match (&state.broker, &split) {
+        (Some(broker), _) if mint => match broker::mint(broker, &canonical.lossy()) {
+            Some(minted) => Some(minted),
+            None => {
+                state.cache.insert(canonical.clone(), CachedToken::unset());
+                return false;
+            }
+        },
+        (_, Some(split)) if result.is_null() => {
+            match split::recombine(&canonical.lossy(), split, parts) {
+                Some(recombined) => Some(recombined),
+                None => {
+                    scrub_unresolved_token(state, canonical, &group, &group_cstrs);
+                    return false;
+                }
+            }
+        }
+        _ => None,
+    };
+    let mut value = match &taken {
+        Some(taken) => taken.as_c_str(),
+        None => CStr::from_ptr(result),
+    };
+
+    // A file indirection, broker handle, Vault reference or encrypted value is
+    // replaced by the value it refers to (see the secretfile, broker, vault and
+    // envcrypt modules); one that cannot be resolved leaves the token unset
+    let resolved = match resolve_indirect_value(state, canonical, value.to_bytes()) {
+        Some(Some(resolved)) => Some(resolved),
+        Some(None) => {
+            scrub_unresolved_token(state, canonical, &group, &group_cstrs);
+            if let Some(taken) = taken {
+                secretfile::wipe(taken);
+            }
+            return false;
+        }
+        None => None,
+    };
+    if let Some(resolved) = &resolved {
+        value = resolved.as_c_str();
+    }
+
+    // Copy the value before unsetting
+    verify_token_digest(state, canonical, value.to_bytes());
+    let cached = alloc_cached_value(state, canonical, value);
+    for taken in [resolved, taken].into_iter().flatten() {
+        secretfile::wipe(taken);
+    }
+
+    // Cache the buffer so subsequent reads return the same pointer
+    state.cache.insert(canonical.clone(), cached);
+    egress::publish_secrets(state);
+
+    // Unset the token and all of its aliases so none remain accessible
+    let scrub = scrub_token_group(&group, &group_cstrs, scrub_mode, debug_enabled);
+    if let Some(entry) = state.cache.get_mut(canonical) {
+        entry.scrub = Some(scrub);
+    }
+
+    true
+}
+
+/// Scrub a token whose value could not be resolved, and cache it as unset
+///
+/// # Safety
+/// Must not race with concurrent modification of the environment
+unsafe fn scrub_unresolved_token(
+    state: &mut TokenState,
+    canonical: &EnvName,
+    group: &[EnvName],
+    group_cstrs: &[CString],
+) {
+    let scrub = scrub_token_group(group, group_cstrs, state.scrub_mode, state.debug_enabled);
+    state.cache.insert(
+        canonical.clone(),
+        CachedToken {
+            scrub: Some(scrub),
+            ..CachedToken::unset()
+        },
+    );
+}
+
+/// The value a file indirection, broker handle, Vault reference or encrypted
+/// value refers to
+///
+/// Returns None if `value` is none of these (or the mode is off), and
+/// Some(None) if it could not be resolved.
+///
+/// # Safety
+/// Only performs system calls on the file or socket the value names
+unsafe fn resolve_indirect_value(
+    state: &TokenState,
+    canonical: &EnvName,
+    value: &[u8],
+) -> Option<Option<CString>> {
+    if let Some(path) = secretfile::indirection(value).filter(|_| state.file_secrets) {
+        return Some(secretfile::take(&canonical.lossy(), path));
+    }
+    if let (Some(key), Some(payload)) = (&state.env_key, envcrypt::payload(value)) {
+        return Some(envcrypt::decrypt(key, &canonical.lossy(), payload));
+    }
+    if let (Some(agent), Some(reference)) = (&state.vault, vault::reference(value)) {
+        return Some(vault::fetch(agent, &canonical.lossy(), reference));
+    }
+    let broker = state.broker.as_ref()?;
+    let handle = broker::handle(value)?;
+    Some(broker::fetch(broker, &canonical.lossy(), handle))
+}
+
+/// Eagerly cache and scrub every protected token (AWF_ONE_SHOT_EAGER)
+///
+/// Runtimes such as Node.js and Go copy the environment at startup and never
+/// call getenv for it again, so lazy interception would leave their copy
+/// populated. Scrubbing from a constructor cleans environ before they snapshot it.
+fn eager_scrub(state: &mut TokenState) {
+    let names: Vec<EnvName> = state.tokens.iter().map(|spec| spec.name.clone()).collect();
+    for name in names {
+        if state.cache.contains_key(&name) {
+            continue;
+        }
+        // SAFETY: call_real_getenv is the real libc getenv
+        let cached = unsafe { cache_token(state, &name, call_real_getenv) };
+        if cached && state.debug_enabled {
+            log_line!(
+                Debug,
+                "eager_scrub",
+                Some(&name.lossy()),
+                "Token {} cached and scrubbed at load",
+                name
+            );
+        }
+    }
+}
+
+/// Load AWF_ONE_SHOT_CORRELATION
+fn load_correlation(state: &mut TokenState) {
+    let Some(config) = read_config_var(c"AWF_ONE_SHOT_CORRELATION") else {
+        return;
+    };
+    state.correlation = correlation::Correlation::parse(&config).0;
+}
 
-    // Verify the token was cleared from the process environment
-    check_task_environ_exposure(name_str, debug_enabled);
+/// Attribute a served read of `canonical` (read as `name`) to its caller,
+/// count it in the stats segment, start a correlation if configured, and log
+/// it and send an access event if configured
+fn record_access(
+    state: &mut TokenState,
+    name: &[u8],
+    canonical: &EnvName,
+    via_secure: bool,
+    first: bool,
+    caller: Option<&str>,
+) {
+    #[cfg(not(feature = "minimal"))]
+    shmstats::record_read(canonical);
+    let Some(entry) = state.cache.get_mut(canonical) else {
+        return;
+    };
+    if let Some(caller) = caller {
+        match entry.callers.iter_mut().find(|(object, _)| object == caller) {
+            Some((_, reads)) => *reads += 1,
+            None => entry.callers.push((caller.to_string(), 1)),
+        }
+    }
+    let reads = entry.reads;
+    let via = if via_secure { "secure_getenv" } else { "getenv" };
+    let corr = state
+        .correlation
+        .as_mut()
+        .map(|correlation| correlation.read(canonical, via));
+    if !events::enabled() && !log::logs_access() {
+        return;
+    }
+    let mut fields = vec![
+        ("via".to_string(), via.to_string()),
+        ("reads".to_string(), reads.to_string()),
+        ("first".to_string(), first.to_string()),
+    ];
+    if let Some(corr) = corr {
+        fields.push(("corr".to_string(), corr));
+    }
+    if name != canonical.as_bytes() {
+        fields.push(("name".to_string(), String::from_utf8_lossy(name).into_owned()));
+    }
+    fields.extend(audit::thread_fields());
+    let canonical = canonical.lossy();
+    log::write_access(&canonical, &fields);
+    events::send(log::Level::Info, "access", Some(&canonical), &fields);
+}
+
+/// Core implementation for cached token access
+///
+/// # Safety
+/// - `name` must be a valid null-terminated C string
+/// - `real_getenv_fn` must be a valid function to call for getting the real value
+unsafe fn handle_getenv_impl(
+    name: *const c_char,
+    real_getenv_fn: unsafe fn(*const c_char) -> *mut c_char,
+    via_secure: bool,
+) -> *mut c_char {
+    // Null name - pass through
+    if name.is_null() {
+        return real_getenv_fn(name);
+    }
+
+    // Names are matched as bytes (see the envname module)
+    let name_bytes = CStr::from_ptr(name).to_bytes();
+
+    // Fast path for names that are not configured at all
+    if !is_watched(name_bytes) {
+        if trace::enabled() && !reentry::active() {
+            trace_decision(&lock_state(), name_bytes, "pass_through");
+        }
+        return real_getenv_fn(name);
+    }
+
+    // Cached tokens whose reads need no bookkeeping are served without the
+    // lock (see the snapshot module), unless the credentials changed (see the
+    // privilege module)
+    let privilege_changed = privilege::changed();
+    if !privilege_changed {
+        if let Some(value) = snapshot::serve(name_bytes) {
+            return value;
+        }
+    }
+    // Nested call from our own code path or a signal handler: taking the
+    // lock could deadlock (see the reentry module)
+    if reentry::active() {
+        return real_getenv_fn(name);
+    }
+    let _entered = reentry::enter();
+
+    // name_str is only used for display
+    let name_str = &*String::from_utf8_lossy(name_bytes);
+    let proxy_name = std::str::from_utf8(name_bytes).ok();
+
+    // Decoy variables are served as-is, but every read is reported
+    canary::check_read(name_bytes, if via_secure { "secure_getenv" } else { "getenv" });
+
+    // Lock state and ensure initialization
+    let mut state = lock_state();
+    if privilege_changed {
+        privilege::wipe(&mut state);
+    }
+
+    // Observe mode - report what enforcement would do, then pass through
+    if state.mode == Mode::Observe {
+        let action = if proxy_name.is_some_and(|name| state.enforced_proxy.contains_key(name)) {
+            Some("enforce_proxy")
+        } else if is_denied_token(&state, name_bytes) {
+            Some("deny")
+        } else {
+            resolve_sensitive_token(&state, name_bytes).map(|canonical| {
+                if comm_denied(&state) {
+                    return "deny_comm";
+                }
+                if exe_denied(&state, canonical) {
+                    return "deny_exe";
+                }
+                if parent_rule(&state) == credfile::FilePolicy::Deny {
+                    return "deny_parent";
+                }
+                let spec = token_spec(&state, canonical);
+                spec.map(|spec| spec.policy).unwrap_or_default().as_str()
+            })
+        };
+        if let Some(canonical) = resolve_sensitive_token(&state, name_bytes).cloned() {
+            check_rate(&mut state, &canonical);
+        }
+        let verdict = format!("pass_through would={}", action.unwrap_or("none"));
+        trace_decision(&state, name_bytes, &verdict);
+        let caller_policy = state.caller_policy.clone();
+        drop(state);
+
+        let result = real_getenv_fn(name);
+        if let Some(mut action) = action {
+            let mut caller_note = String::new();
+            if let Some(caller_policy) = caller_policy {
+                let caller = caller::identify();
+                if caller_policy.for_caller(&caller) == credfile::FilePolicy::Deny {
+                    action = "deny_caller";
+                }
+                caller_note = format!(" caller={}", caller);
+            }
+            audit::emit(
+                "observed",
+                name_str,
+                &format!("would={} present={}{}", action, !result.is_null(), caller_note),
+            );
+        }
+        return result;
+    }
+
+    // Proxy variables always report the host-mandated value
+    if let Some(enforced) = proxy_name.and_then(|name| state.enforced_proxy.get(name)) {
+        trace_decision(&state, name_bytes, "enforced_value");
+        if state.debug_enabled {
+            let current = real_getenv_fn(name);
+            if current.is_null() || CStr::from_ptr(current) != enforced.as_c_str() {
+                log_line!(
+                    Debug,
+                    "proxy_enforced",
+                    Some(name_str),
+                    "Serving enforced value for {} (environment differs)",
+                    name_str
+                );
+            }
+        }
+        return enforced.as_ptr() as *mut c_char;
+    }
+
+    // Deny-listed variables are never served
+    if is_denied_token(&state, name_bytes) {
+        trace_decision(&state, name_bytes, "denied");
+        let debug_enabled = state.debug_enabled;
+        drop(state);
+        return handle_denied_token(name, name_bytes, real_getenv_fn, debug_enabled);
+    }
+
+    // Check if this is a sensitive token (or an alias of one)
+    let canonical = match resolve_sensitive_token(&state, name_bytes) {
+        Some(canonical) => canonical.clone(),
+        None => {
+            // Not sensitive - pass through (drop lock first for performance)
+            trace_decision(&state, name_bytes, "pass_through");
+            drop(state);
+            return real_getenv_fn(name);
+        }
+    };
+
+    // Only allowed programs receive protected tokens
+    if comm_denied(&state) {
+        let detail = format!("comm={}", state.comm);
+        return refuse_read(
+            &mut state,
+            name_bytes,
+            &canonical,
+            "comm_denied",
+            &detail,
+            real_getenv_fn,
+        );
+    }
+
+    if exe_denied(&state, &canonical) {
+        let detail = format!("exe={}", state.exe);
+        return refuse_read(
+            &mut state,
+            name_bytes,
+            &canonical,
+            "exe_denied",
+            &detail,
+            real_getenv_fn,
+        );
+    }
+
+    let parent_rule = parent_rule(&state);
+    let parent_detail = state.parent.as_ref().map(process::Parent::describe);
+    if parent_rule == credfile::FilePolicy::Deny {
+        let detail = parent_detail.unwrap_or_default();
+        return refuse_read(
+            &mut state,
+            name_bytes,
+            &canonical,
+            "parent_denied",
+            &detail,
+            real_getenv_fn,
+        );
+    }
+
+    // Attribute the read to its caller, outside the lock (see the caller module)
+    let caller = if state.caller_policy.is_some() || state.report_path.is_some() {
+        drop(state);
+        let caller = caller::identify();
+        state = lock_state();
+        Some(caller)
+    } else {
+        None
+    };
+    let caller_rule = match (&caller, &state.caller_policy) {
+        (Some(caller), Some(policy)) => policy.for_caller(caller),
+        _ => credfile::FilePolicy::Allow,
+    };
+
+    if caller_rule == credfile::FilePolicy::Deny {
+        let detail = format!("caller={}", caller.unwrap_or_default());
+        return refuse_read(
+            &mut state,
+            name_bytes,
+            &canonical,
+            "caller_denied",
+            &detail,
+            real_getenv_fn,
+        );
+    }
+
+    if check_rate(&mut state, &canonical) {
+        trace_decision(&state, name_bytes, "refused reason=rate_exceeded");
+        return ptr::null_mut();
+    }
+
+    // No token is handed out that the host could not account for (see the
+    // sink module); it is still scrubbed from the environment
+    if let Some(sinks) = sink::refuses_reads(state.report_path.as_deref()) {
+        if !state.cache.contains_key(&canonical) {
+            cache_token(&mut state, &canonical, real_getenv_fn);
+        }
+        trace_decision(&state, name_bytes, "refused reason=sink_denied");
+        audit::emit("sink_denied", &canonical, &format!("sinks={}", sinks));
+        return ptr::null_mut();
+    }
+
+    // Details of policies that log served reads
+    let mut read_notes = Vec::new();
+    if caller_rule == credfile::FilePolicy::Log {
+        read_notes.push(format!("caller={}", caller.as_deref().unwrap_or("unknown")));
+    }
+    if parent_rule == credfile::FilePolicy::Log {
+        read_notes.extend(parent_detail);
+    }
+
+    // Sensitive token - check if already cached (and still fresh, and granted
+    // in this step, and not wiped for lack of reads)
+    step::check_file(&mut state);
+    idle::check(&mut state);
+    idle::refetch(&mut state, &canonical);
+    refresh_token(&mut state, &canonical);
+    if let Some(cached_ptr) = serve_cached_token(&mut state, &canonical) {
+        let verdict = served_verdict(&state, &canonical, cached_ptr, false);
+        trace_decision(&state, name_bytes, &verdict);
+        let served = !cached_ptr.is_null() && !honeypot::contains(cached_ptr);
+        if !read_notes.is_empty() && served {
+            audit::emit("token_read", &canonical, &read_notes.join(" "));
+        }
+        if served {
+            let canonical = &canonical;
+            record_access(&mut state, name_bytes, canonical, via_secure, false, caller.as_deref());
+        }
+        if state.debug_enabled && served {
+            log_line!(
+                Debug,
+                "cache_hit",
+                Some(&canonical.lossy()),
+                "Token {} served from cache",
+                name_str
+            );
+        }
+        // Already accessed - return cached value (null if token wasn't set
+        // or its read budget is exhausted, or a honeypot value)
+        return cached_ptr;
+    }
+
+    // First access - cache the value and scrub the environment, then serve
+    // it like any other cached read so the read is counted
+    let newly_cached = cache_token(&mut state, &canonical, real_getenv_fn);
+    let result = serve_cached_token(&mut state, &canonical).unwrap_or(ptr::null_mut());
+    let verdict = served_verdict(&state, &canonical, result, newly_cached);
+    trace_decision(&state, name_bytes, &verdict);
+    if !read_notes.is_empty() && !result.is_null() {
+        audit::emit("token_read", &canonical, &read_notes.join(" "));
+    }
+    if !result.is_null() {
+        let first = newly_cached;
+        record_access(&mut state, name_bytes, &canonical, via_secure, first, caller.as_deref());
+    }
 
-    if debug_enabled {
+    if newly_cached && state.debug_enabled && !result.is_null() {
         let suffix = if via_secure { " (via secure_getenv)" } else { "" };
-        eprintln!(
-            "[one-shot-token] Token {} accessed and cached (value: {}){}",
-            name_str, format_token_value(value_str), suffix
+        let alias_note = if canonical != name_bytes {
+            format!(" (alias of {})", canonical)
+        } else {
+            String::new()
+        };
+        let action = match token_spec(&state, &canonical).map(|spec| spec.policy) {
+            Some(TokenPolicy::Redact) => "redacted",
+            Some(TokenPolicy::Derive) => "derived",
+            Some(TokenPolicy::Placeholder) => "swapped",
+            _ => "cached",
+        };
+        // Preview from our copy: the environ entry may have been masked by now
+        let value_str = CStr::from_ptr(result).to_str().unwrap_or("");
+        log_line!(
+            Debug,
+            "access",
+            Some(name_str),
+            "Token {}{} accessed and {} (value: {}){}",
+            name_str, alias_note, action, format_token_value(value_str), suffix
         );
     }
 
-    cached
+    result
+}
+
+/// Trace the decision for a getenv of `name`, if enabled (see the trace
+/// module)
+fn trace_decision(state: &TokenState, name: &[u8], verdict: &str) {
+    if !trace::enabled() {
+        return;
+    }
+    let lossy = String::from_utf8_lossy(name);
+    let rule = if state.enforced_proxy.contains_key(&*lossy) {
+        "enforced_proxy".to_string()
+    } else if is_denied_token(state, name) {
+        format!("deny:{}", lossy)
+    } else if let Some(canonical) = state.aliases.get(name) {
+        format!("alias:{}={}", lossy, canonical)
+    } else if is_sensitive_token(state, name) {
+        format!("token:{}", lossy)
+    } else if canary::names().any(|canary| canary.as_bytes() == name) {
+        "canary".to_string()
+    } else {
+        let protected = state.tokens.iter().map(|spec| &spec.name);
+        trace::unprotected(name, protected.chain(state.aliases.keys()));
+        return;
+    };
+    let spec = resolve_sensitive_token(state, name).and_then(|token| token_spec(state, token));
+    trace::decision(name, &rule, spec, verdict);
+}
+
+/// Verdict of a read of `canonical` served from the cache, for tracing
+fn served_verdict(
+    state: &TokenState,
+    canonical: &EnvName,
+    value: *mut c_char,
+    first: bool,
+) -> String {
+    if honeypot::contains(value) {
+        return "honeypot reason=max_reads".to_string();
+    }
+    if !value.is_null() {
+        return format!("served first={}", first);
+    }
+    let reason = match state.cache.get(canonical) {
+        Some(entry) if entry.protection.is_none() => "not_set",
+        Some(entry) if entry.locked => "locked",
+        Some(entry) => entry.wiped.unwrap_or("max_reads"),
+        None => "not_cached",
+    };
+    format!("null reason={}", reason)
+}
+
+/// Check whether this process is excluded by AWF_ONE_SHOT_ALLOWED_COMMS
+fn comm_denied(state: &TokenState) -> bool {
+    state
+        .allowed_comms
+        .as_ref()
+        .is_some_and(|allowed| !process::comm_allowed(&state.comm, allowed))
+}
+
+/// Check whether this executable may not read `canonical` (the token's
+/// `exe=` options)
+fn exe_denied(state: &TokenState, canonical: &[u8]) -> bool {
+    token_spec(state, canonical).is_some_and(|spec| {
+        !spec.allowed_exes.is_empty() && !spec.allowed_exes.contains(&state.exe)
+    })
+}
+
+/// Policy for this process's parent (AWF_ONE_SHOT_PARENT_POLICY)
+fn parent_rule(state: &TokenState) -> credfile::FilePolicy {
+    match (&state.parent_policy, &state.parent) {
+        (Some(policy), Some(parent)) => policy.for_parent(parent),
+        _ => credfile::FilePolicy::Allow,
+    }
+}
+
+/// Record a read of `canonical` against the rate limit
+///
+/// The first read over the limit in a burst is reported. Returns true if the
+/// read must be refused (`:deny` limits in enforce mode).
+fn check_rate(state: &mut TokenState, canonical: &EnvName) -> bool {
+    let Some(limit) = state.rate_limit else {
+        return false;
+    };
+    let deny = limit.action == credfile::FilePolicy::Deny;
+    let check = state.read_history.record(canonical, Instant::now(), &limit);
+    if let ratelimit::RateCheck::Exceeded { reads } = check {
+        let detail = format!("reads={} window={}s", reads, limit.window.as_secs());
+        let (event, detail) = match (deny, state.mode) {
+            (true, Mode::Observe) => ("observed", format!("would=deny_rate {}", detail)),
+            (true, _) => ("rate_exceeded", format!("{} action=denied", detail)),
+            (false, _) => ("rate_exceeded", format!("{} action=logged", detail)),
+        };
+        audit::emit(event, canonical, &detail);
+    }
+    deny && state.mode == Mode::Enforce && check != ratelimit::RateCheck::Within
+}
+
+/// Refuse a read of a protected token with an audit event
+///
+/// The token is still cached and scrubbed from the environment, so it does
+/// not linger in environ, and reads that are allowed later are served from
+/// the cache.
+///
+/// `name` is the name that was read, `canonical` the token it resolved to.
+///
+/// # Safety
+/// `real_getenv_fn` must be a valid function to call for getting the real value
+unsafe fn refuse_read(
+    state: &mut TokenState,
+    name: &[u8],
+    canonical: &EnvName,
+    event: &str,
+    detail: &str,
+    real_getenv_fn: unsafe fn(*const c_char) -> *mut c_char,
+) -> *mut c_char {
+    if !state.cache.contains_key(canonical) {
+        cache_token(state, canonical, real_getenv_fn);
+    }
+    // Unauthorized readers may get a honeypot value instead (see the honeypot
+    // module)
+    let honeypot = match state.cache.get(canonical) {
+        Some(entry) => {
+            honeypot::serve(&canonical.lossy(), state.swap.as_ref(), || entry.plaintext())
+        }
+        None => ptr::null_mut(),
+    };
+    if honeypot.is_null() {
+        trace_decision(state, name, &format!("refused reason={}", event));
+        audit::emit(event, canonical, detail);
+    } else {
+        trace_decision(state, name, &format!("honeypot reason={}", event));
+        audit::emit(event, canonical, &format!("{} action=honeypot", detail));
+    }
+    honeypot
+}
+
+/// Histogram a sampled lookup of `name` is timed into (see the latency
+/// module)
+///
+/// # Safety
+/// `name` must be null or a valid null-terminated C string
+unsafe fn latency_class(name: *const c_char) -> latency::Class {
+    if !name.is_null() && is_watched(CStr::from_ptr(name).to_bytes()) {
+        latency::Class::Sensitive
+    } else {
+        latency::Class::Passthrough
+    }
 }
 
 /// Intercepted getenv function
@@ -403,9 +3433,18 @@ unsafe fn handle_getenv_impl(
 /// # Safety
 /// This function is called from C code and must maintain C ABI compatibility.
 /// The `name` parameter must be a valid null-terminated C string.
-#[no_mangle]
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
 pub unsafe extern "C" fn getenv(name: *const c_char) -> *mut c_char {
-    handle_getenv_impl(name, call_real_getenv, false)
+    if !early::initialized() {
+        return early::getenv(name, false);
+    }
+    if !reentry::active() {
+        fork::start_threads();
+    }
+    latency::measure(
+        || latency_class(name),
+        || handle_getenv_impl(name, call_real_getenv, false),
+    )
 }
 
 /// Intercepted secure_getenv function
@@ -417,14 +3456,47 @@ pub unsafe extern "C" fn getenv(name: *const c_char) -> *mut c_char {
 /// - First call: caches the value, unsets from environment, returns cached value
 /// - Subsequent calls: returns the cached value from memory
 ///
-/// For all other variables: passes through to real secure_getenv (or getenv if unavailable)
+/// For all other variables: passes through to real secure_getenv (or an AT_SECURE
+/// check if unavailable)
 ///
 /// # Safety
 /// This function is called from C code and must maintain C ABI compatibility.
 /// The `name` parameter must be a valid null-terminated C string.
-#[no_mangle]
+#[cfg_attr(feature = "preload", no_mangle)]
 pub unsafe extern "C" fn secure_getenv(name: *const c_char) -> *mut c_char {
-    handle_getenv_impl(name, call_real_secure_getenv, true)
+    if !early::initialized() {
+        return early::getenv(name, true);
+    }
+    if !reentry::active() {
+        fork::start_threads();
+    }
+    latency::measure(
+        || latency_class(name),
+        || handle_getenv_impl(name, call_real_secure_getenv, true),
+    )
+}
+
+/// Intercepted __secure_getenv, the name glibc exported secure_getenv under
+/// before 2.17
+///
+/// glibc still provides it as a compatibility symbol, so binaries built
+/// against older releases read the environment through it. The interposers
+/// are unversioned (see awf.map), so this definition also satisfies their
+/// `__secure_getenv@GLIBC_2.2.5` references. The real function is reached
+/// through secure_getenv, which has the same semantics.
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// The `name` parameter must be a valid null-terminated C string.
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn __secure_getenv(name: *const c_char) -> *mut c_char {
+    if !early::initialized() {
+        return early::getenv(name, true);
+    }
+    latency::measure(
+        || latency_class(name),
+        || handle_getenv_impl(name, call_real_secure_getenv, true),
+    )
 }
 
 #[cfg(test)]
@@ -433,9 +3505,9 @@ mod tests {
 
     #[test]
     fn test_default_tokens_defined() {
-        assert!(!DEFAULT_SENSITIVE_TOKENS.is_empty());
-        assert!(DEFAULT_SENSITIVE_TOKENS.contains(&"GITHUB_TOKEN"));
-        assert!(DEFAULT_SENSITIVE_TOKENS.contains(&"OPENAI_API_KEY"));
+        let defaults = detect::preset_tokens(None);
+        assert!(defaults.contains(&"GITHUB_TOKEN"));
+        assert!(defaults.contains(&"OPENAI_API_KEY"));
     }
 
     #[test]
@@ -454,4 +3526,383 @@ mod tests {
         assert_eq!(format_token_value("abcde"), "abcd...");
         assert_eq!(format_token_value("ghp_1234567890"), "ghp_...");
     }
+
+    #[test]
+    fn test_parse_name_list() {
+        assert_eq!(
+            parse_name_list(" MY_API_KEY, ,SECRET_TOKEN,,"),
+            vec!["MY_API_KEY", "SECRET_TOKEN"]
+        );
+        assert!(parse_name_list(" , ,,").is_empty());
+
+        let many = vec!["T"; MAX_TOKENS + 5].join(",");
+        assert_eq!(parse_name_list(&many).len(), MAX_TOKENS);
+    }
+
+    #[test]
+    fn test_parse_token_aliases() {
+        let aliases = parse_token_aliases(b" GH_TOKEN = GITHUB_TOKEN ,bogus,=X,Y=,SAME=SAME,A=B");
+        assert_eq!(
+            aliases,
+            vec![("GH_TOKEN".into(), "GITHUB_TOKEN".into()), ("A".into(), "B".into())]
+        );
+        assert!(parse_token_aliases(b"").is_empty());
+    }
+
+    #[test]
+    fn test_resolve_sensitive_token_via_alias() {
+        let mut state = TokenState::new();
+        state.tokens.push(TokenSpec::new("GITHUB_TOKEN"));
+        state.aliases.insert("GH_TOKEN".into(), "GITHUB_TOKEN".into());
+
+        let canonical = EnvName::from("GITHUB_TOKEN");
+        assert_eq!(resolve_sensitive_token(&state, b"GH_TOKEN"), Some(&canonical));
+        assert_eq!(resolve_sensitive_token(&state, b"GITHUB_TOKEN"), Some(&canonical));
+        assert_eq!(resolve_sensitive_token(&state, b"PATH"), None);
+        assert_eq!(token_group(&state, &canonical), vec!["GITHUB_TOKEN", "GH_TOKEN"]);
+    }
+
+    #[test]
+    fn test_watched_names() {
+        let mut state = TokenState::new();
+        state.tokens.push(TokenSpec::new("GITHUB_TOKEN"));
+        state.aliases.insert("GH_TOKEN".into(), "GITHUB_TOKEN".into());
+        state.deny.push("AWS_SECRET_ACCESS_KEY".into());
+        state.enforced_proxy = proxy::enforced_values(Some("http://proxy:3128"), None);
+
+        let names = watched_names(&state);
+        for name in ["GITHUB_TOKEN", "GH_TOKEN", "AWS_SECRET_ACCESS_KEY", "HTTPS_PROXY"] {
+            assert!(names.contains(name.as_bytes()), "{}", name);
+        }
+        assert!(!names.contains(b"PATH"));
+        assert!(!names.contains(b"GITHUB_ACTOR"));
+    }
+
+    #[test]
+    fn test_resolve_sensitive_token_non_utf8() {
+        let mut state = TokenState::new();
+        state.tokens.push(TokenSpec::new(b"TOKEN_\xff"));
+        state.aliases.extend(parse_token_aliases(b"ALIAS_\xfe=TOKEN_\xff"));
+
+        let canonical = EnvName::new(b"TOKEN_\xff");
+        assert_eq!(resolve_sensitive_token(&state, b"TOKEN_\xff"), Some(&canonical));
+        assert_eq!(resolve_sensitive_token(&state, b"ALIAS_\xfe"), Some(&canonical));
+        // Distinct invalid bytes are not conflated by a lossy conversion
+        assert_eq!(resolve_sensitive_token(&state, b"TOKEN_\xfe"), None);
+        assert_eq!(resolve_sensitive_token(&state, "TOKEN_\u{fffd}".as_bytes()), None);
+    }
+
+    #[test]
+    fn test_serve_cached_token_enforces_max_reads() {
+        let mut state = TokenState::new();
+        let (spec, _) = policy::parse_token_spec("GITHUB_TOKEN:max_reads=2").unwrap();
+        state.tokens.push(spec);
+
+        assert_eq!(serve_cached_token(&mut state, &"GITHUB_TOKEN".into()), None);
+
+        let mut value = *b"ghp_test\0";
+        let value_ptr = value.as_mut_ptr() as *mut c_char;
+        state.cache.insert(
+            "GITHUB_TOKEN".into(),
+            CachedToken {
+                reads: 1,
+                ..CachedToken::cached(value_ptr)
+            },
+        );
+
+        assert_eq!(serve_cached_token(&mut state, &"GITHUB_TOKEN".into()), Some(value_ptr));
+        assert_eq!(serve_cached_token(&mut state, &"GITHUB_TOKEN".into()), Some(ptr::null_mut()));
+        assert_eq!(state.cache[&EnvName::from("GITHUB_TOKEN")].reads, 2);
+    }
+
+    #[test]
+    fn test_serve_cached_token_strict_hands_over_first_read() {
+        let mut state = TokenState::new();
+        let (spec, _) = policy::parse_token_spec("GITHUB_TOKEN:strict").unwrap();
+        state.tokens.push(spec);
+
+        let mut value = *b"ghp_test\0";
+        let value_ptr = value.as_mut_ptr() as *mut c_char;
+        state.cache.insert("GITHUB_TOKEN".into(), CachedToken::cached(value_ptr));
+
+        // The cache keeps no reference to the value once it is served
+        assert_eq!(serve_cached_token(&mut state, &"GITHUB_TOKEN".into()), Some(value_ptr));
+        let entry = &state.cache[&EnvName::from("GITHUB_TOKEN")];
+        assert!(entry.value.is_null());
+        assert_eq!(entry.wiped, Some("strict_reread"));
+        assert_eq!(&value, b"ghp_test\0");
+
+        // A re-read is refused and zeroizes the handed-over buffer
+        assert_eq!(serve_cached_token(&mut state, &"GITHUB_TOKEN".into()), Some(ptr::null_mut()));
+        assert!(state.cache[&EnvName::from("GITHUB_TOKEN")].handed_over.is_null());
+        assert_eq!(value, [0u8; 9]);
+    }
+
+    #[test]
+    fn test_serve_cached_token_zeroizes_after_ttl() {
+        let mut state = TokenState::new();
+        let (spec, _) = policy::parse_token_spec("GITHUB_TOKEN:ttl=0").unwrap();
+        state.tokens.push(spec);
+
+        let mut value = *b"ghp_test\0";
+        let value_ptr = value.as_mut_ptr() as *mut c_char;
+        state.cache.insert(
+            "GITHUB_TOKEN".into(),
+            CachedToken {
+                reads: 1,
+                ..CachedToken::cached(value_ptr)
+            },
+        );
+
+        assert_eq!(serve_cached_token(&mut state, &"GITHUB_TOKEN".into()), Some(ptr::null_mut()));
+        assert_eq!(state.cache[&EnvName::from("GITHUB_TOKEN")].wiped, Some("expired"));
+        assert_eq!(value, [0u8; 9]);
+    }
+
+    #[test]
+    fn test_eager_scrub_caches_without_counting_a_read() {
+        let mut state = TokenState::new();
+        let (spec, _) = policy::parse_token_spec("AWF_TEST_EAGER_TOKEN:strict").unwrap();
+        state.tokens.push(spec);
+        unsafe { libc::setenv(c"AWF_TEST_EAGER_TOKEN".as_ptr(), c"eager-value".as_ptr(), 1) };
+
+        eager_scrub(&mut state);
+
+        assert!(unsafe { call_real_getenv(c"AWF_TEST_EAGER_TOKEN".as_ptr()) }.is_null());
+        assert_eq!(state.cache[&EnvName::from("AWF_TEST_EAGER_TOKEN")].reads, 0);
+        // Sealed until served
+        let buffer = state.cache[&EnvName::from("AWF_TEST_EAGER_TOKEN")].value;
+        assert_eq!(unsafe { CStr::from_ptr(buffer) }, c"");
+        assert_eq!(
+            protected_token_values(&state),
+            vec!["eager-value".to_string()]
+        );
+
+        // The program's first read is still served under the strict policy
+        let value = serve_cached_token(&mut state, &"AWF_TEST_EAGER_TOKEN".into()).unwrap();
+        assert_eq!(unsafe { CStr::from_ptr(value) }, c"eager-value");
+        assert_eq!(
+            serve_cached_token(&mut state, &"AWF_TEST_EAGER_TOKEN".into()),
+            Some(ptr::null_mut())
+        );
+    }
+
+    #[test]
+    fn test_trim_option() {
+        let mut state = TokenState::new();
+        for entry in ["AWF_TEST_TRIM_TOKEN:trim", "AWF_TEST_UNTRIMMED_TOKEN"] {
+            state.tokens.push(policy::parse_token_spec(entry).unwrap().0);
+        }
+        unsafe {
+            libc::setenv(c"AWF_TEST_TRIM_TOKEN".as_ptr(), c" trim-value\r\n".as_ptr(), 1);
+            libc::setenv(c"AWF_TEST_UNTRIMMED_TOKEN".as_ptr(), c"value\n".as_ptr(), 1);
+        }
+
+        for name in ["AWF_TEST_TRIM_TOKEN", "AWF_TEST_UNTRIMMED_TOKEN"] {
+            assert!(unsafe { cache_token(&mut state, &name.into(), call_real_getenv) });
+        }
+        let served = serve_cached_token(&mut state, &"AWF_TEST_TRIM_TOKEN".into()).unwrap();
+        assert_eq!(unsafe { CStr::from_ptr(served) }, c"trim-value");
+        let served = serve_cached_token(&mut state, &"AWF_TEST_UNTRIMMED_TOKEN".into()).unwrap();
+        assert_eq!(unsafe { CStr::from_ptr(served) }, c"value\n");
+        assert!(trim_token_value(&"AWF_TEST_TRIM_TOKEN".into(), c"clean").is_none());
+    }
+
+    #[test]
+    fn test_serve_cached_token_strict_wipes_on_reread() {
+        let mut state = TokenState::new();
+        let (spec, _) = policy::parse_token_spec("GITHUB_TOKEN:strict").unwrap();
+        state.tokens.push(spec);
+
+        let mut value = *b"ghp_test\0";
+        let value_ptr = value.as_mut_ptr() as *mut c_char;
+        state.cache.insert(
+            "GITHUB_TOKEN".into(),
+            CachedToken {
+                reads: 1,
+                ..CachedToken::cached(value_ptr)
+            },
+        );
+
+        assert_eq!(serve_cached_token(&mut state, &"GITHUB_TOKEN".into()), Some(ptr::null_mut()));
+        assert_eq!(serve_cached_token(&mut state, &"GITHUB_TOKEN".into()), Some(ptr::null_mut()));
+        assert_eq!(state.cache[&EnvName::from("GITHUB_TOKEN")].wiped, Some("strict_reread"));
+        assert_eq!(state.cache[&EnvName::from("GITHUB_TOKEN")].reads, 1);
+        assert_eq!(value, [0u8; 9]);
+    }
+
+    #[test]
+    fn test_cache_token_reads_secret_file() {
+        let mut state = TokenState::new();
+        state.file_secrets = true;
+        for name in ["AWF_TEST_FILE_TOKEN", "AWF_TEST_MISSING_FILE_TOKEN"] {
+            state.tokens.push(policy::parse_token_spec(name).unwrap().0);
+        }
+        let path = std::env::temp_dir().join(format!("awf-test-secret-{}", std::process::id()));
+        std::fs::write(&path, "file-value\n").unwrap();
+        let value = CString::new(format!("@{}", path.display())).unwrap();
+        unsafe {
+            libc::setenv(c"AWF_TEST_FILE_TOKEN".as_ptr(), value.as_ptr(), 1);
+            libc::setenv(c"AWF_TEST_MISSING_FILE_TOKEN".as_ptr(), c"@/nonexistent/awf".as_ptr(), 1);
+        }
+
+        assert!(unsafe { cache_token(&mut state, &"AWF_TEST_FILE_TOKEN".into(), call_real_getenv) });
+        assert!(!path.exists());
+        assert!(unsafe { call_real_getenv(c"AWF_TEST_FILE_TOKEN".as_ptr()) }.is_null());
+        let served = serve_cached_token(&mut state, &"AWF_TEST_FILE_TOKEN".into()).unwrap();
+        assert_eq!(unsafe { CStr::from_ptr(served) }, c"file-value");
+
+        // An unreadable file leaves the token unset, and the variable scrubbed
+        let missing = EnvName::from("AWF_TEST_MISSING_FILE_TOKEN");
+        assert!(!unsafe { cache_token(&mut state, &missing, call_real_getenv) });
+        assert!(unsafe { call_real_getenv(c"AWF_TEST_MISSING_FILE_TOKEN".as_ptr()) }.is_null());
+        assert_eq!(serve_cached_token(&mut state, &missing), Some(ptr::null_mut()));
+    }
+
+    #[test]
+    fn test_load_secrets_dir() {
+        let dir = std::env::temp_dir().join(format!("awf-test-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("awf_test_dir_token"), "dir-value\n").unwrap();
+        std::fs::write(dir.join("awf-test-denied"), "x").unwrap();
+        let dir_value = CString::new(dir.to_str().unwrap()).unwrap();
+        let mut state = TokenState::new();
+        state.deny.push(EnvName::from("AWF_TEST_DENIED"));
+        unsafe {
+            libc::setenv(c"AWF_TEST_DIR_TOKEN".as_ptr(), c"from-env".as_ptr(), 1);
+            libc::setenv(c"AWF_ONE_SHOT_SECRETS_DIR".as_ptr(), dir_value.as_ptr(), 1);
+        }
+        load_secrets_dir(&mut state);
+        unsafe { libc::unsetenv(c"AWF_ONE_SHOT_SECRETS_DIR".as_ptr()) };
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let name = EnvName::from("AWF_TEST_DIR_TOKEN");
+        assert!(is_sensitive_token(&state, name.as_bytes()));
+        assert!(!is_sensitive_token(&state, b"AWF_TEST_DENIED"));
+        assert!(unsafe { call_real_getenv(c"AWF_TEST_DIR_TOKEN".as_ptr()) }.is_null());
+        let served = serve_cached_token(&mut state, &name).unwrap();
+        assert_eq!(unsafe { CStr::from_ptr(served) }, c"dir-value");
+    }
+
+    #[test]
+    fn test_load_json_secrets() {
+        let mut state = TokenState::new();
+        state.tokens.push(TokenSpec::new("JSON_TEST_CACHED"));
+        state.cache.insert(
+            "JSON_TEST_CACHED".into(),
+            alloc_cached_value(&state, &"JSON_TEST_CACHED".into(), c"from-file"),
+        );
+        state.deny.push(EnvName::from("JSON_TEST_DENIED"));
+        unsafe {
+            libc::setenv(c"JSON_TEST_TOKEN".as_ptr(), c"from-env".as_ptr(), 1);
+            libc::setenv(
+                c"AWF_JSON_SECRETS".as_ptr(),
+                cr#"{"JSON_TEST_TOKEN":"json-value","JSON_TEST_CACHED":"x","JSON_TEST_DENIED":"x"}"#
+                    .as_ptr(),
+                1,
+            );
+        }
+        load_json_secrets(&mut state);
+
+        assert!(unsafe { call_real_getenv(c"AWF_JSON_SECRETS".as_ptr()) }.is_null());
+        assert!(unsafe { call_real_getenv(c"JSON_TEST_TOKEN".as_ptr()) }.is_null());
+        let name = EnvName::from("JSON_TEST_TOKEN");
+        let served = serve_cached_token(&mut state, &name).unwrap();
+        assert_eq!(unsafe { CStr::from_ptr(served) }, c"json-value");
+        // A value already cached takes precedence
+        let served = serve_cached_token(&mut state, &"JSON_TEST_CACHED".into()).unwrap();
+        assert_eq!(unsafe { CStr::from_ptr(served) }, c"from-file");
+        assert!(!is_sensitive_token(&state, b"JSON_TEST_DENIED"));
+    }
+
+    #[test]
+    fn test_load_secret_namespace() {
+        let mut state = TokenState::new();
+        state.tokens.push(TokenSpec::new("NS_TEST_CANONICAL"));
+        state
+            .aliases
+            .insert("NS_TEST_ALIAS".into(), "NS_TEST_CANONICAL".into());
+        state.deny.push(EnvName::from("NS_TEST_DENIED"));
+        unsafe {
+            libc::setenv(c"AWF_SECRET_NS_TEST_TOKEN".as_ptr(), c"ns-value".as_ptr(), 1);
+            libc::setenv(c"AWF_SECRET_NS_TEST_ALIAS".as_ptr(), c"alias-value".as_ptr(), 1);
+            libc::setenv(c"AWF_SECRET_NS_TEST_DENIED".as_ptr(), c"denied".as_ptr(), 1);
+        }
+        load_secret_namespace(&mut state);
+
+        let name = EnvName::from("NS_TEST_TOKEN");
+        assert!(is_sensitive_token(&state, name.as_bytes()));
+        assert_eq!(
+            resolve_sensitive_token(&state, b"AWF_SECRET_NS_TEST_TOKEN"),
+            Some(&name)
+        );
+        let served = serve_cached_token(&mut state, &name).unwrap();
+        assert_eq!(unsafe { CStr::from_ptr(served) }, c"ns-value");
+        // An alias fills its canonical token
+        let canonical = EnvName::from("NS_TEST_CANONICAL");
+        let served = serve_cached_token(&mut state, &canonical).unwrap();
+        assert_eq!(unsafe { CStr::from_ptr(served) }, c"alias-value");
+        // A denied name is scrubbed but never served
+        assert!(!is_sensitive_token(&state, b"NS_TEST_DENIED"));
+        for namespaced in [
+            c"AWF_SECRET_NS_TEST_TOKEN",
+            c"AWF_SECRET_NS_TEST_ALIAS",
+            c"AWF_SECRET_NS_TEST_DENIED",
+        ] {
+            assert!(unsafe { call_real_getenv(namespaced.as_ptr()) }.is_null());
+        }
+    }
+
+    #[test]
+    fn test_split_tokens() {
+        let mut state = TokenState::new();
+        state.tokens.push(TokenSpec::new("AWF_TEST_SPLIT_TOKEN"));
+        state.tokens.push(TokenSpec::new("AWF_TEST_HALF_TOKEN"));
+        unsafe {
+            libc::setenv(c"AWF_TEST_SPLIT_TOKEN_PART1".as_ptr(), c"ghp_01".as_ptr(), 1);
+            libc::setenv(c"AWF_TEST_SPLIT_TOKEN_PART2".as_ptr(), c"23456789".as_ptr(), 1);
+            libc::setenv(c"AWF_TEST_HALF_TOKEN_PART1".as_ptr(), c"ghp_01".as_ptr(), 1);
+            libc::setenv(c"AWF_ONE_SHOT_SPLIT_TOKENS".as_ptr(), c"1".as_ptr(), 1);
+        }
+        load_split_tokens(&mut state);
+        unsafe { libc::unsetenv(c"AWF_ONE_SHOT_SPLIT_TOKENS".as_ptr()) };
+
+        let name = EnvName::from("AWF_TEST_SPLIT_TOKEN");
+        assert_eq!(
+            resolve_sensitive_token(&state, b"AWF_TEST_SPLIT_TOKEN_PART2"),
+            Some(&name)
+        );
+        assert!(unsafe { cache_token(&mut state, &name, call_real_getenv) });
+        let served = serve_cached_token(&mut state, &name).unwrap();
+        assert_eq!(unsafe { CStr::from_ptr(served) }, c"ghp_0123456789");
+        assert!(unsafe { call_real_getenv(c"AWF_TEST_SPLIT_TOKEN_PART1".as_ptr()) }.is_null());
+        assert!(unsafe { call_real_getenv(c"AWF_TEST_SPLIT_TOKEN_PART2".as_ptr()) }.is_null());
+
+        // A part on its own is scrubbed and never served
+        let half = EnvName::from("AWF_TEST_HALF_TOKEN");
+        assert!(!unsafe { cache_token(&mut state, &half, call_real_getenv) });
+        assert!(serve_cached_token(&mut state, &half).unwrap().is_null());
+        assert!(unsafe { call_real_getenv(c"AWF_TEST_HALF_TOKEN_PART1".as_ptr()) }.is_null());
+    }
+
+    #[test]
+    fn test_load_token_digests() {
+        let mut state = TokenState::new();
+        state.tokens.push(TokenSpec::new("GITHUB_TOKEN"));
+        state.aliases.insert("GH_TOKEN".into(), "GITHUB_TOKEN".into());
+        state.deny.push(EnvName::from("AWF_TEST_DENIED"));
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let config = CString::new(format!(
+            "GH_TOKEN={digest},AWF_TEST_DIGEST_TOKEN={digest},AWF_TEST_DENIED={digest}"
+        ))
+        .unwrap();
+        unsafe { libc::setenv(c"AWF_ONE_SHOT_TOKEN_DIGESTS".as_ptr(), config.as_ptr(), 1) };
+        load_token_digests(&mut state);
+        unsafe { libc::unsetenv(c"AWF_ONE_SHOT_TOKEN_DIGESTS".as_ptr()) };
+
+        assert_eq!(state.digests.len(), 2);
+        assert!(state.digests.contains_key(&b"GITHUB_TOKEN"[..]));
+        assert!(is_sensitive_token(&state, b"AWF_TEST_DIGEST_TOKEN"));
+        assert!(!is_sensitive_token(&state, b"AWF_TEST_DENIED"));
+    }
 }
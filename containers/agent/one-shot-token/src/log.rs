@@ -0,0 +1,599 @@
+//! Log line formatting (AWF_ONE_SHOT_LOG_FORMAT)
+//!
+//! Every line the library writes goes through this module. By default lines
+//! are human-readable and prefixed with `[one-shot-token]`. With
+//! AWF_ONE_SHOT_LOG_FORMAT=json each line is instead a single JSON object
+//! with stable field names, for the firewall's log pipeline:
+//!
+//! - `time`: seconds since the Unix epoch, with millisecond precision
+//! - `pid`: process id
+//! - `schema_version`: version of the event schema (see the schema module)
+//! - `step`, `container`, `cgroup`: the run context, when known (see the
+//!   context module)
+//! - `level`: `debug`, `info`, `warning`, `error`, `fatal` or `audit`
+//! - `event`: what happened, e.g. `init`, `access`, `cache_hit`,
+//!   `unset_verification` or, for audit lines, the audit event name
+//! - `token`: the token concerned, if any
+//! - `message`: the text of the human-readable line
+//!
+//! Audit lines carry their `key=value` details as further string fields
+//! instead of a message.
+//!
+//! With AWF_ONE_SHOT_LOG_FORMAT=firewall, audit lines and a line for every
+//! served read of a protected token are written in the firewall's Squid
+//! access log format (`firewall_detailed`), so the existing log parser and
+//! summaries pick them up as requests with the token as the domain:
+//!
+//! ```text
+//! 1761074374.646 -:4121 GITHUB_TOKEN -:- - TOKEN 200 TCP_MISS:ACCESS token://GITHUB_TOKEN/access?via=getenv&reads=1 "gh"
+//! 1761074375.002 -:4121 GITHUB_TOKEN -:- - TOKEN 403 TCP_DENIED:MAX_READS_EXCEEDED token://GITHUB_TOKEN/max_reads_exceeded?max_reads=1&reads=1 "gh"
+//! ```
+//!
+//! The client port is the process id and the user agent the executable name.
+//! Refused or blocked accesses have status 403 and a `TCP_DENIED` decision,
+//! everything else 200 and `TCP_MISS`. Other lines keep the text format,
+//! which the parser skips.
+//!
+//! Lines go to stderr, or to the inherited descriptor or log file if one is
+//! configured (see the logfile module), and also to syslog or journald if
+//! enabled (see the syslog module).
+
+use crate::sink::{self, Sink};
+#[cfg(not(feature = "minimal"))]
+use crate::{chain, sign, syslog};
+use crate::{context, events, logfile};
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::OnceLock;
+
+/// Write a log line: `log_line!(Warning, "event", token, "format", args...)`
+///
+/// `token` is an `Option<&str>` naming the token the line is about.
+macro_rules! log_line {
+    ($level:ident, $event:expr, $token:expr, $($arg:tt)+) => {
+        $crate::log::write(
+            $crate::log::Level::$level,
+            $event,
+            $token,
+            format_args!($($arg)+),
+        )
+    };
+}
+
+/// Line format (AWF_ONE_SHOT_LOG_FORMAT)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Format {
+    Text,
+    Json,
+    Firewall,
+}
+
+static FORMAT: AtomicU8 = AtomicU8::new(Format::Text as u8);
+
+/// Current line format
+fn format() -> Format {
+    match FORMAT.load(Ordering::Relaxed) {
+        1 => Format::Json,
+        2 => Format::Firewall,
+        _ => Format::Text,
+    }
+}
+
+/// Audit events that record a refused or blocked access (see is_refusal);
+/// events with an `action` detail are classified by it instead
+const REFUSED_EVENTS: &[&str] = &[
+    "denied",
+    "locked",
+    "max_reads_exceeded",
+    "expired",
+    "strict_reread",
+    "strict_invalidated",
+    "wiped",
+    "wiped_at_exit",
+    "comm_denied",
+    "exe_denied",
+    "parent_denied",
+    "caller_denied",
+    "sink_denied",
+    "connect_blocked",
+    "file_denied",
+    "canary_egress",
+];
+
+/// Fields every JSON line may carry; detail keys that clash are prefixed
+const RESERVED_FIELDS: &[&str] = &[
+    "time",
+    "pid",
+    "schema_version",
+    "step",
+    "container",
+    "cgroup",
+    "level",
+    "event",
+    "token",
+    "message",
+    "seq",
+    "hash",
+    "sigbase",
+    "sigseq",
+    "sig",
+];
+
+/// Severity of a log line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Level {
+    Debug,
+    Info,
+    Warning,
+    Error,
+    Fatal,
+    Audit,
+}
+
+impl Level {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warning => "warning",
+            Level::Error => "error",
+            Level::Fatal => "fatal",
+            Level::Audit => "audit",
+        }
+    }
+
+    /// Prefix of the human-readable message
+    fn text_prefix(self) -> &'static str {
+        match self {
+            Level::Debug => "",
+            Level::Info => "INFO: ",
+            Level::Warning => "WARNING: ",
+            Level::Error => "ERROR: ",
+            Level::Fatal => "FATAL: ",
+            Level::Audit => "AUDIT ",
+        }
+    }
+}
+
+/// Select the line format from the value of AWF_ONE_SHOT_LOG_FORMAT
+///
+/// Returns false for unrecognized values, which keep the text format.
+pub(crate) fn set_format(value: &str) -> bool {
+    let Some(format) = parse_format(value) else {
+        return false;
+    };
+    FORMAT.store(format as u8, Ordering::Relaxed);
+    true
+}
+
+/// Whether `value` names a line format
+pub(crate) fn is_format(value: &str) -> bool {
+    parse_format(value).is_some()
+}
+
+fn parse_format(value: &str) -> Option<Format> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "text" => Some(Format::Text),
+        "json" => Some(Format::Json),
+        "firewall" => Some(Format::Firewall),
+        _ => None,
+    }
+}
+
+/// Write a log line
+///
+/// Use the `log_line!` macro rather than calling this directly.
+pub(crate) fn write(level: Level, event: &str, token: Option<&str>, message: fmt::Arguments) {
+    let message = message.to_string();
+    let text = format!("{}{}", level.text_prefix(), message);
+    emit(
+        level,
+        event,
+        token,
+        &[("message".to_string(), message)],
+        &text,
+    );
+}
+
+/// Write an audit line (see the audit module)
+///
+/// `text` is the text-format line; the run context, the chain fields if
+/// AWF_ONE_SHOT_AUDIT_CHAIN is set (see the chain module) and the signature
+/// fields if an audit key is loaded (see the sign module) are appended to it.
+pub(crate) fn write_audit(event: &str, token: &str, detail: &str, text: &str) {
+    let mut line = text
+        .strip_prefix("[one-shot-token] ")
+        .unwrap_or(text)
+        .to_string();
+    for (key, value) in context::fields() {
+        line.push_str(&format!(" {}={}", key, value));
+    }
+    let mut fields = detail_fields(detail);
+    link_chain(event, token, &mut fields, &mut line);
+    sign_record(event, token, &mut fields, &mut line);
+    emit(Level::Audit, event, Some(token), &fields, &line);
+    events::send(Level::Audit, event, Some(token), &fields);
+}
+
+/// Append the chain fields of an audit record, if AWF_ONE_SHOT_AUDIT_CHAIN
+/// is set
+#[cfg(not(feature = "minimal"))]
+fn link_chain(event: &str, token: &str, fields: &mut Vec<(String, String)>, line: &mut String) {
+    let chained: Vec<(String, String)> = fields.iter().chain(context::fields()).cloned().collect();
+    if let Some((seq, hash)) = chain::link(event, token, &chained) {
+        let pid = std::process::id();
+        line.push_str(&format!(" pid={} seq={} hash={}", pid, seq, hash));
+        fields.push(("seq".to_string(), seq.to_string()));
+        fields.push(("hash".to_string(), hash));
+    }
+}
+
+/// Minimal builds have no audit chain
+#[cfg(feature = "minimal")]
+fn link_chain(_event: &str, _token: &str, _fields: &mut Vec<(String, String)>, _line: &mut String) {
+}
+
+/// Append the signature fields of an audit record, if an audit key is loaded
+#[cfg(not(feature = "minimal"))]
+fn sign_record(event: &str, token: &str, fields: &mut Vec<(String, String)>, line: &mut String) {
+    let Some(signature) = sign::sign(event, token, fields, context::fields()) else {
+        return;
+    };
+    if let Some(base) = signature.base {
+        line.push_str(&format!(" sigbase={}", base));
+        fields.push(("sigbase".to_string(), base));
+    }
+    line.push_str(&format!(" sigseq={} sig={}", signature.seq, signature.sig));
+    fields.push(("sigseq".to_string(), signature.seq.to_string()));
+    fields.push(("sig".to_string(), signature.sig));
+}
+
+/// Minimal builds do not sign audit records
+#[cfg(feature = "minimal")]
+fn sign_record(
+    _event: &str,
+    _token: &str,
+    _fields: &mut Vec<(String, String)>,
+    _line: &mut String,
+) {
+}
+
+/// Whether served reads are logged (the firewall format)
+pub(crate) fn logs_access() -> bool {
+    format() == Format::Firewall
+}
+
+/// Write a line for a served read of `token`, in the firewall format only
+pub(crate) fn write_access(token: &str, fields: &[(String, String)]) {
+    if logs_access() {
+        emit(Level::Info, "access", Some(token), fields, "");
+    }
+}
+
+/// Format a line and send it to its destinations
+///
+/// `text` is the human-readable line without the `[one-shot-token]` prefix.
+fn emit(level: Level, event: &str, token: Option<&str>, fields: &[(String, String)], text: &str) {
+    let line = match format() {
+        Format::Json => json_line(level, event, token, fields),
+        Format::Firewall if level == Level::Audit || event == "access" => {
+            firewall_line(event, token.unwrap_or("-"), fields)
+        }
+        _ => format!("[one-shot-token] {}", text),
+    };
+    output(&line);
+    #[cfg(not(feature = "minimal"))]
+    {
+        let message = if format() == Format::Text {
+            text
+        } else {
+            &line
+        };
+        syslog::send(level, event, token, fields, message);
+    }
+}
+
+/// Write a finished line to the inherited descriptor or the log file, or to
+/// stderr if neither is configured
+///
+/// Lines the descriptor or file does not take are kept until it takes them
+/// (see the sink module).
+fn output(line: &str) {
+    if logfile::has_fd() {
+        sink::deliver(Sink::Log, line, |line| {
+            logfile::write_fd_line(line).unwrap_or(false)
+        });
+        return;
+    }
+    match logfile::get() {
+        Some(file) => sink::deliver(Sink::Log, line, |line| file.write_line(line)),
+        None => eprintln!("{}", line),
+    }
+}
+
+/// Format a JSON line (without trailing newline)
+pub(crate) fn json_line(
+    level: Level,
+    event: &str,
+    token: Option<&str>,
+    fields: &[(String, String)],
+) -> String {
+    let mut line = format!(
+        "{{\"time\":{},\"pid\":{},\"schema_version\":{}",
+        crate::audit::timestamp(),
+        std::process::id(),
+        crate::schema::SCHEMA_VERSION
+    );
+    for (key, value) in context::fields() {
+        line.push_str(&format!(",{}:{}", json_string(key), json_string(value)));
+    }
+    line.push_str(&format!(
+        ",\"level\":\"{}\",\"event\":{}",
+        level.as_str(),
+        json_string(event)
+    ));
+    if let Some(token) = token {
+        line.push_str(&format!(",\"token\":{}", json_string(token)));
+    }
+    for (key, value) in fields {
+        line.push_str(&format!(",{}:{}", json_string(key), json_string(value)));
+    }
+    line.push('}');
+    line
+}
+
+/// Name of the executable, the user agent of firewall lines
+fn exe_name() -> &'static str {
+    static EXE_NAME: OnceLock<String> = OnceLock::new();
+    EXE_NAME.get_or_init(|| {
+        let exe = crate::process::exe().unwrap_or_default();
+        let name = exe.rsplit('/').next().unwrap_or_default().replace('"', "");
+        if name.is_empty() {
+            "-".to_string()
+        } else {
+            name
+        }
+    })
+}
+
+/// Percent-encode a URL component
+fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'*' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Whether an audit event records a refused or blocked access
+pub(crate) fn is_refusal(event: &str, fields: &[(String, String)]) -> bool {
+    let action = fields
+        .iter()
+        .find(|(key, _)| key == "action")
+        .map(|(_, action)| action.as_str());
+    match action {
+        Some("blocked" | "denied") => true,
+        Some(_) => false,
+        None => REFUSED_EVENTS.contains(&event),
+    }
+}
+
+/// Format a line in the firewall's Squid access log format (without trailing
+/// newline)
+fn firewall_line(event: &str, token: &str, fields: &[(String, String)]) -> String {
+    let (status, decision) = if is_refusal(event, fields) {
+        (403, "TCP_DENIED")
+    } else {
+        (200, "TCP_MISS")
+    };
+    let query: Vec<String> = fields
+        .iter()
+        .chain(context::fields())
+        .map(|(key, value)| format!("{}={}", url_encode(key), url_encode(value)))
+        .collect();
+    let mut url = format!("token://{}/{}", url_encode(token), url_encode(event));
+    if !query.is_empty() {
+        url.push('?');
+        url.push_str(&query.join("&"));
+    }
+    format!(
+        "{} -:{} {} -:- - TOKEN {} {}:{} {} \"{}\"",
+        crate::audit::timestamp(),
+        std::process::id(),
+        url_encode(token),
+        status,
+        decision,
+        event.to_ascii_uppercase(),
+        url,
+        exe_name()
+    )
+}
+
+/// Split an audit detail string into fields
+///
+/// Details are `key=value` words separated by spaces; values may be quoted
+/// with Rust string escapes. Words without a key are collected in `detail`.
+fn detail_fields(detail: &str) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    let mut loose = Vec::new();
+    let mut chars = detail.chars().peekable();
+    loop {
+        while chars.next_if_eq(&' ').is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|&c| c != ' ' && c != '=') {
+            key.push(c);
+        }
+        if chars.next_if_eq(&'=').is_none() {
+            loose.push(key);
+            continue;
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some('r') => value.push('\r'),
+                        Some(c) => value.push(c),
+                        None => {}
+                    },
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|&c| c != ' ') {
+                value.push(c);
+            }
+        }
+        if RESERVED_FIELDS.contains(&key.as_str()) {
+            key.insert_str(0, "detail_");
+        }
+        fields.push((key, value));
+    }
+    if !loose.is_empty() {
+        fields.push(("detail".to_string(), loose.join(" ")));
+    }
+    fields
+}
+
+/// Quote a string as a JSON string literal
+pub(crate) fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detail_fields() {
+        let fields = detail_fields(r#"via=system command="curl -H \"x\" a" time=1 stray"#);
+        let fields: Vec<(&str, &str)> = fields
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("via", "system"),
+                ("command", "curl -H \"x\" a"),
+                ("detail_time", "1"),
+                ("detail", "stray"),
+            ]
+        );
+        assert!(detail_fields("").is_empty());
+    }
+
+    #[test]
+    fn test_firewall_line() {
+        context::init(None);
+        let context: String = context::fields()
+            .iter()
+            .map(|(key, value)| format!("&{}={}", key, url_encode(value)))
+            .collect();
+        let fields = detail_fields(r#"max_reads=1 reads=1 command="a b&c""#);
+        let line = firewall_line("max_reads_exceeded", "GITHUB_TOKEN", &fields);
+        let (time, rest) = line.split_once(' ').unwrap();
+        assert!(time.parse::<f64>().is_ok());
+        let expected = format!(
+            concat!(
+                "-:{} GITHUB_TOKEN -:- - TOKEN 403 TCP_DENIED:MAX_READS_EXCEEDED ",
+                "token://GITHUB_TOKEN/max_reads_exceeded?max_reads=1&reads=1&command=a%20b%26c{} ",
+                "\"{}\""
+            ),
+            std::process::id(),
+            context,
+            exe_name()
+        );
+        assert_eq!(rest, expected);
+
+        let logged = detail_fields("via=write action=logged");
+        assert!(firewall_line("egress_secret", "GH_TOKEN", &logged).contains(" 200 TCP_MISS:"));
+        let blocked = detail_fields("via=write action=blocked");
+        assert!(firewall_line("egress_secret", "GH_TOKEN", &blocked).contains(" 403 TCP_DENIED:"));
+        let bare = firewall_line("token_read", "*", &[]);
+        assert!(bare.contains(" token://*/token_read") && !bare.contains("/token_read&"));
+    }
+
+    #[test]
+    fn test_json_line() {
+        context::init(None);
+        let context: String = context::fields()
+            .iter()
+            .map(|(key, value)| format!(",{}:{}", json_string(key), json_string(value)))
+            .collect();
+        let line = json_line(
+            Level::Audit,
+            "denied",
+            Some("GITHUB_TOKEN"),
+            &[("present".to_string(), "true".to_string())],
+        );
+        assert!(line.starts_with("{\"time\":"));
+        assert!(line.ends_with(&format!(
+            ",\"pid\":{},\"schema_version\":1{},\"level\":\"audit\",\"event\":\"denied\",\"token\":\"GITHUB_TOKEN\",\"present\":\"true\"}}",
+            std::process::id(),
+            context
+        )));
+        assert_eq!(json_string("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+    }
+
+    #[test]
+    fn test_json_line_schema() {
+        // Lines match the published schema: they parse back as events, and
+        // the levels and reserved fields are the ones it declares
+        let fields = detail_fields("via=execve step=x");
+        let line = json_line(Level::Audit, "denied", Some("GITHUB_TOKEN"), &fields);
+        let event = crate::schema::Event::parse(&line).unwrap();
+        assert_eq!(event.schema_version, crate::schema::SCHEMA_VERSION);
+        assert_eq!(event.event, "denied");
+        assert_eq!(event.field("detail_step"), Some("x"));
+
+        let schema = crate::json::parse(crate::schema::EVENT_SCHEMA.as_bytes()).unwrap();
+        let properties = schema.get("properties").unwrap();
+        for field in RESERVED_FIELDS {
+            assert!(properties.get(field).is_some(), "{}", field);
+        }
+        let Some(crate::json::Value::Array(levels)) =
+            properties.get("level").and_then(|level| level.get("enum"))
+        else {
+            panic!("no level enum");
+        };
+        let levels: Vec<&str> = levels
+            .iter()
+            .filter_map(crate::json::Value::as_str)
+            .collect();
+        for level in [
+            Level::Debug,
+            Level::Info,
+            Level::Warning,
+            Level::Error,
+            Level::Fatal,
+            Level::Audit,
+        ] {
+            assert!(levels.contains(&level.as_str()), "{:?}", level);
+        }
+    }
+}
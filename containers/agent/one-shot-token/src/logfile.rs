@@ -0,0 +1,468 @@
+//! Log destinations other than stderr
+//!
+//! # Log file (AWF_ONE_SHOT_LOG_FILE)
+//!
+//! Long agent sessions lose early stderr lines to buffering, and log lines
+//! mixed into program output can break tools that parse it. With
+//! AWF_ONE_SHOT_LOG_FILE set, every log and audit line is appended to that
+//! file instead. Once the file reaches AWF_ONE_SHOT_LOG_MAX_SIZE (default
+//! 10M) it is renamed to `<path>.1`, older files shift up to `<path>.3`, and
+//! a new file is started.
+//!
+//! Every process of an agent run appends to the same path: forked children
+//! share the descriptor, exec'd children reopen the propagated path. Lines
+//! are written with a single write(2) on an O_APPEND descriptor, so they
+//! never interleave. A process that finds the path renamed by another one
+//! reopens it before its next line; rotation itself happens under an flock
+//! on a fresh open file description, so that processes sharing a descriptor
+//! across fork still exclude each other. The new file is dup'ed onto the old
+//! descriptor number, so threads writing concurrently never write to a
+//! descriptor the program has reused.
+//!
+//! Files are opened through the real open and written through the real
+//! write, bypassing the interposers.
+//!
+//! A line is in the kernel once written, so it survives the process crashing
+//! or being killed, but not the machine going down before the kernel writes
+//! it back. AWF_ONE_SHOT_LOG_SYNC makes lines durable with fdatasync:
+//!
+//! - `none` (default): the kernel writes lines back when it sees fit
+//! - `periodic`: at most once per SYNC_INTERVAL, on a write; lines written
+//!   since the last sync are synced at exit and before the file is rotated
+//! - `event`: after every line
+//!
+//! # Inherited descriptor (AWF_ONE_SHOT_LOG_FD)
+//!
+//! The host can instead pass a descriptor, typically the write end of a
+//! pipe, and receive the lines completely out of band. The descriptor is
+//! duplicated to a close-on-exec copy at load, so lines keep flowing if the
+//! program closes or reuses the number; the original stays open for exec'd
+//! children, which log to it through the propagated setting. SIGPIPE is
+//! blocked around each write, so a host that stops reading never kills the
+//! program; lines it does not take are kept for a while (see the sink
+//! module).
+
+use crate::{egress, open, sys};
+use libc::c_int;
+use std::ffi::CString;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Default size at which the file is rotated
+pub(crate) const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Number of rotated files kept next to the current one
+const ROTATED_FILES: usize = 3;
+
+/// Least time between two syncs in the `periodic` mode
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When log lines are synced to disk (AWF_ONE_SHOT_LOG_SYNC)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub(crate) enum Durability {
+    /// Never (default)
+    #[default]
+    None,
+    /// At most once per SYNC_INTERVAL, and at exit
+    Periodic,
+    /// After every line
+    Event,
+}
+
+impl Durability {
+    /// Parse an AWF_ONE_SHOT_LOG_SYNC value (case-insensitive)
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "none" => Some(Durability::None),
+            "periodic" => Some(Durability::Periodic),
+            "event" | "per-event" => Some(Durability::Event),
+            _ => None,
+        }
+    }
+}
+
+static DURABILITY: AtomicU8 = AtomicU8::new(Durability::None as u8);
+
+/// Set the durability from AWF_ONE_SHOT_LOG_SYNC
+pub(crate) fn set_durability(durability: Durability) {
+    DURABILITY.store(durability as u8, Ordering::Relaxed);
+}
+
+/// The configured durability
+pub(crate) fn durability() -> Durability {
+    match DURABILITY.load(Ordering::Relaxed) {
+        1 => Durability::Periodic,
+        2 => Durability::Event,
+        _ => Durability::None,
+    }
+}
+
+/// Private copy of the descriptor passed in AWF_ONE_SHOT_LOG_FD
+static LOG_FD: OnceLock<c_int> = OnceLock::new();
+
+/// The configured log file, if any
+static LOG_FILE: OnceLock<LogFile> = OnceLock::new();
+
+/// An append-only log file
+pub(crate) struct LogFile {
+    path: CString,
+    fd: c_int,
+    max_size: u64,
+    /// Whether lines were written since the last sync
+    unsynced: AtomicBool,
+    /// When the file was last synced
+    synced_at: Mutex<Option<Instant>>,
+}
+
+/// Device and inode of a file
+fn identity(stat: &libc::stat) -> (libc::dev_t, libc::ino_t) {
+    (stat.st_dev, stat.st_ino)
+}
+
+impl LogFile {
+    /// Open (or create) the file at `path` for appending
+    pub(crate) fn open(path: &str, max_size: u64) -> io::Result<LogFile> {
+        let path = CString::new(path).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let fd = Self::open_path(&path)?;
+        Ok(LogFile {
+            path,
+            fd,
+            max_size,
+            unsynced: AtomicBool::new(false),
+            synced_at: Mutex::new(None),
+        })
+    }
+
+    fn open_path(path: &CString) -> io::Result<c_int> {
+        // SAFETY: path is a valid C string
+        let fd = unsafe {
+            open::call_real_open_mode(
+                path.as_ptr(),
+                libc::O_WRONLY | libc::O_APPEND | libc::O_CREAT | libc::O_CLOEXEC,
+                0o600,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(fd)
+    }
+
+    /// Stat the open descriptor
+    fn fstat(&self) -> Option<libc::stat> {
+        // SAFETY: stat is a plain output buffer
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        (unsafe { libc::fstat(self.fd, &mut stat) } == 0).then_some(stat)
+    }
+
+    /// Whether the path still names the open file
+    fn is_current(&self) -> bool {
+        // SAFETY: stat is a plain output buffer and path a valid C string
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::stat(self.path.as_ptr(), &mut stat) } != 0 {
+            return false;
+        }
+        self.fstat()
+            .is_some_and(|own| identity(&own) == identity(&stat))
+    }
+
+    /// Sync the lines written since the last sync to disk
+    pub(crate) fn sync(&self) {
+        if !self.unsynced.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        // SAFETY: fdatasync on our own descriptor
+        unsafe { libc::fdatasync(self.fd) };
+        *self.synced_at.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+    }
+
+    /// Sync as the configured durability asks after a line was written
+    fn sync_written(&self) {
+        self.unsynced.store(true, Ordering::SeqCst);
+        let due = match durability() {
+            Durability::None => false,
+            Durability::Event => true,
+            Durability::Periodic => self
+                .synced_at
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .is_none_or(|synced_at| synced_at.elapsed() >= SYNC_INTERVAL),
+        };
+        if due {
+            self.sync();
+        }
+    }
+
+    /// Point the descriptor at the file currently at the path
+    ///
+    /// Lines not synced yet are synced first, while the descriptor still
+    /// refers to their file.
+    fn reopen(&self) {
+        if durability() != Durability::None {
+            self.sync();
+        }
+        let Ok(fd) = Self::open_path(&self.path) else {
+            return;
+        };
+        // SAFETY: both descriptors are ours; the duplicate replaces self.fd
+        unsafe {
+            sys::dup_cloexec(fd, self.fd);
+            libc::close(fd);
+        }
+    }
+
+    /// Path of the rotated file with the given index
+    fn rotated_path(&self, index: usize) -> CString {
+        let mut path = self.path.as_bytes().to_vec();
+        path.extend_from_slice(format!(".{}", index).as_bytes());
+        CString::new(path).unwrap()
+    }
+
+    /// Rename the full file out of the way unless another process already did
+    fn rotate(&self) {
+        // A fresh open file description, so the lock also excludes processes
+        // that share self.fd across fork
+        let Ok(lock_fd) = Self::open_path(&self.path) else {
+            return;
+        };
+        // SAFETY: lock_fd is ours; the renamed paths are valid C strings
+        unsafe {
+            libc::flock(lock_fd, libc::LOCK_EX);
+            let still_full = self.is_current()
+                && self
+                    .fstat()
+                    .is_some_and(|stat| stat.st_size as u64 >= self.max_size);
+            if still_full {
+                for index in (1..ROTATED_FILES).rev() {
+                    libc::rename(
+                        self.rotated_path(index).as_ptr(),
+                        self.rotated_path(index + 1).as_ptr(),
+                    );
+                }
+                libc::rename(self.path.as_ptr(), self.rotated_path(1).as_ptr());
+            }
+            libc::close(lock_fd);
+        }
+        self.reopen();
+    }
+
+    /// Append a line (a newline is added), returning whether it was written
+    pub(crate) fn write_line(&self, line: &str) -> bool {
+        if !self.is_current() {
+            self.reopen();
+        }
+        let mut bytes = Vec::with_capacity(line.len() + 1);
+        bytes.extend_from_slice(line.as_bytes());
+        bytes.push(b'\n');
+        // SAFETY: bytes is a valid buffer of its length
+        let written =
+            unsafe { egress::call_real_write(self.fd, bytes.as_ptr().cast(), bytes.len()) };
+        if written > 0 {
+            self.sync_written();
+        }
+        if self
+            .fstat()
+            .is_some_and(|stat| stat.st_size as u64 >= self.max_size)
+        {
+            self.rotate();
+        }
+        written == bytes.len() as isize
+    }
+}
+
+/// Send log lines to `path` from now on
+pub(crate) fn install(path: &str, max_size: u64) -> io::Result<()> {
+    let file = LogFile::open(path, max_size)?;
+    if let Err(file) = LOG_FILE.set(file) {
+        // SAFETY: the descriptor was just opened and is not shared
+        unsafe { libc::close(file.fd) };
+    }
+    Ok(())
+}
+
+/// The configured log file, if any
+pub(crate) fn get() -> Option<&'static LogFile> {
+    LOG_FILE.get()
+}
+
+/// Sync the lines not synced yet, unless durability is `none` (at exit)
+pub(crate) fn sync_pending() {
+    if durability() == Durability::None {
+        return;
+    }
+    if let Some(file) = get() {
+        file.sync();
+    }
+}
+
+/// Send log lines to the inherited descriptor `fd` from now on
+pub(crate) fn install_fd(fd: c_int) -> io::Result<()> {
+    // SAFETY: plain descriptor queries and duplication
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if flags & libc::O_ACCMODE == libc::O_RDONLY {
+        return Err(io::Error::from_raw_os_error(libc::EBADF));
+    }
+    // SAFETY: fd is an open descriptor
+    let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 3) };
+    if copy < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if LOG_FD.set(copy).is_err() {
+        // SAFETY: copy was just created and is not shared
+        unsafe { libc::close(copy) };
+    }
+    Ok(())
+}
+
+/// Whether lines go to an inherited descriptor
+pub(crate) fn has_fd() -> bool {
+    LOG_FD.get().is_some()
+}
+
+/// Write a line (a newline is added) to the inherited descriptor
+///
+/// Returns whether it was written, or None if no descriptor is configured.
+pub(crate) fn write_fd_line(line: &str) -> Option<bool> {
+    let &fd = LOG_FD.get()?;
+    let mut bytes = Vec::with_capacity(line.len() + 1);
+    bytes.extend_from_slice(line.as_bytes());
+    bytes.push(b'\n');
+    // SAFETY: the signal sets are plain output buffers; bytes is a valid
+    // buffer of its length
+    unsafe {
+        let mut sigpipe: libc::sigset_t = std::mem::zeroed();
+        let mut previous: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut sigpipe);
+        libc::sigaddset(&mut sigpipe, libc::SIGPIPE);
+        let already_pending = {
+            let mut pending: libc::sigset_t = std::mem::zeroed();
+            libc::sigpending(&mut pending);
+            libc::sigismember(&pending, libc::SIGPIPE) == 1
+        };
+        libc::pthread_sigmask(libc::SIG_BLOCK, &sigpipe, &mut previous);
+        let written = egress::call_real_write(fd, bytes.as_ptr().cast(), bytes.len());
+        if written < 0 && *sys::errno_location() == libc::EPIPE && !already_pending {
+            // Consume the SIGPIPE our write raised before unblocking it
+            sys::discard_pending(libc::SIGPIPE);
+        }
+        libc::pthread_sigmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+        Some(written == bytes.len() as isize)
+    }
+}
+
+/// Parse a size such as `1048576`, `512K`, `10M` or `1G`
+pub(crate) fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (digits, unit) = match value.char_indices().last()? {
+        (index, c) if c.is_ascii_alphabetic() => (&value[..index], c.to_ascii_uppercase()),
+        _ => (value, 'B'),
+    };
+    let multiplier = match unit {
+        'B' => 1,
+        'K' => 1 << 10,
+        'M' => 1 << 20,
+        'G' => 1 << 30,
+        _ => return None,
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|&size| size > 0)?
+        .checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("512k"), Some(512 * 1024));
+        assert_eq!(parse_size(" 10M "), Some(10 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Some(1 << 30));
+        assert_eq!(parse_size("0"), None);
+        assert_eq!(parse_size("10X"), None);
+        assert_eq!(parse_size(""), None);
+    }
+
+    #[test]
+    fn test_sync() {
+        assert_eq!(Durability::parse(" Periodic "), Some(Durability::Periodic));
+        assert_eq!(Durability::parse("per-event"), Some(Durability::Event));
+        assert_eq!(Durability::parse("always"), None);
+
+        let path = std::env::temp_dir().join(format!("awf-logsync-{}.log", std::process::id()));
+        let file = LogFile::open(path.to_str().unwrap(), DEFAULT_MAX_SIZE).unwrap();
+        assert!(file.write_line("first"));
+        assert!(file.unsynced.load(Ordering::SeqCst));
+        file.sync();
+        assert!(!file.unsynced.load(Ordering::SeqCst));
+        assert!(file.synced_at.lock().unwrap().is_some());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_fd_line_closed_pipe() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        install_fd(fds[1]).unwrap();
+        let copy = *LOG_FD.get().unwrap();
+        assert_ne!(copy, fds[1]);
+        unsafe { libc::close(fds[1]) };
+
+        // Lines still arrive after the program closed the original
+        assert_eq!(write_fd_line("first"), Some(true));
+        let mut buf = [0u8; 16];
+        let read = unsafe { libc::read(fds[0], buf.as_mut_ptr().cast(), buf.len()) };
+        assert_eq!(&buf[..read as usize], b"first\n");
+
+        // A reader that went away neither kills us nor leaves SIGPIPE pending
+        unsafe { libc::close(fds[0]) };
+        assert_eq!(write_fd_line("second"), Some(false));
+        let mut pending: libc::sigset_t = unsafe { std::mem::zeroed() };
+        unsafe { libc::sigpending(&mut pending) };
+        assert_eq!(unsafe { libc::sigismember(&pending, libc::SIGPIPE) }, 0);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("awf-logfile-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("one-shot.log");
+        let file = LogFile::open(path.to_str().unwrap(), 48).unwrap();
+
+        for index in 0..20 {
+            file.write_line(&format!("line {:02} of the test log", index));
+        }
+        // Another process rotated the file: the next line goes to the new one
+        fs::rename(&path, dir.join("moved.log")).unwrap();
+        file.write_line("after move");
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap_or_default();
+        let current = read("one-shot.log");
+        let rotated: Vec<String> = (1..=4)
+            .map(|i| read(&format!("one-shot.log.{}", i)))
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(current, "after move\n");
+        assert_eq!(
+            rotated[0],
+            "line 18 of the test log\nline 19 of the test log\n"
+        );
+        assert_eq!(
+            rotated[2],
+            "line 14 of the test log\nline 15 of the test log\n"
+        );
+        assert_eq!(rotated[3], "");
+    }
+}
@@ -0,0 +1,301 @@
+//! macOS support: dyld interposition and System Integrity Protection
+//!
+//! On macOS the library is loaded with DYLD_INSERT_LIBRARIES. Programs bind
+//! getenv to libSystem through their two-level namespace, so an exported
+//! `getenv` would never be called. Instead the `__DATA,__interpose` section
+//! lists {replacement, original} pairs, and dyld rebinds every other image's
+//! references to the originals. That includes libSystem's own calls between
+//! its libraries: execvp and system reach execve and posix_spawn this way,
+//! so they need no interposers of their own. References from the library
+//! itself are never rebound, which is why the interposers are not exported
+//! under the libc names on macOS: the originals below would then bind to the
+//! library's own definitions.
+//!
+//! open and openat are only interposed on x86_64. The interposers take the
+//! creation mode as a fixed argument, and on arm64 a variadic argument is
+//! passed on the stack instead, so Apple silicon builds cover fopen only.
+//!
+//! System Integrity Protection makes dyld ignore DYLD_* variables for the
+//! programs Apple ships (flagged `restricted`, under /bin, /sbin, /usr and
+//! /System) and for set-user-ID programs, and removes the variables from the
+//! environment such a program passes on. A child started through /bin/sh or
+//! `#!/usr/bin/env` therefore runs without the library, and so does
+//! everything it starts. Protected tokens are still scrubbed from the
+//! child's environment, and each such exec is reported with a
+//! `sip_restricted` audit event, so launchers can be changed to start
+//! programs directly or through an unrestricted shell.
+
+use crate::multilib::{self, Program};
+use crate::{audit, GetenvFn};
+use libc::{c_char, c_int, c_void, mach_port_t};
+use std::ffi::{CStr, CString};
+
+/// A dyld interposing tuple
+#[repr(C)]
+struct Interpose {
+    replacement: *const c_void,
+    original: *const c_void,
+}
+
+// SAFETY: the tuples are constant addresses, only read by dyld
+unsafe impl Sync for Interpose {}
+
+/// Interposers, rebound by dyld in every image but this library
+#[cfg(feature = "preload")]
+#[used]
+#[link_section = "__DATA,__interpose"]
+static INTERPOSE: [Interpose; INTERPOSED] = [
+    Interpose {
+        replacement: crate::getenv as *const c_void,
+        original: libc::getenv as *const c_void,
+    },
+    Interpose {
+        replacement: crate::setenv::setenv as *const c_void,
+        original: libc::setenv as *const c_void,
+    },
+    Interpose {
+        replacement: crate::setenv::putenv as *const c_void,
+        original: libc::putenv as *const c_void,
+    },
+    Interpose {
+        replacement: crate::setenv::unsetenv as *const c_void,
+        original: libc::unsetenv as *const c_void,
+    },
+    Interpose {
+        replacement: crate::exec::execve as *const c_void,
+        original: libc::execve as *const c_void,
+    },
+    Interpose {
+        replacement: crate::exec::posix_spawn as *const c_void,
+        original: libc::posix_spawn as *const c_void,
+    },
+    Interpose {
+        replacement: crate::exec::posix_spawnp as *const c_void,
+        original: libc::posix_spawnp as *const c_void,
+    },
+    Interpose {
+        replacement: crate::open::fopen as *const c_void,
+        original: libc::fopen as *const c_void,
+    },
+    #[cfg(target_arch = "x86_64")]
+    Interpose {
+        replacement: crate::open::open as *const c_void,
+        original: libc::open as *const c_void,
+    },
+    #[cfg(target_arch = "x86_64")]
+    Interpose {
+        replacement: crate::open::openat as *const c_void,
+        original: libc::openat as *const c_void,
+    },
+    Interpose {
+        replacement: crate::net::getaddrinfo as *const c_void,
+        original: libc::getaddrinfo as *const c_void,
+    },
+    Interpose {
+        replacement: crate::net::connect as *const c_void,
+        original: libc::connect as *const c_void,
+    },
+    Interpose {
+        replacement: crate::egress::write as *const c_void,
+        original: libc::write as *const c_void,
+    },
+    Interpose {
+        replacement: crate::egress::send as *const c_void,
+        original: libc::send as *const c_void,
+    },
+    Interpose {
+        replacement: crate::egress::sendto as *const c_void,
+        original: libc::sendto as *const c_void,
+    },
+];
+
+/// Number of interposed functions
+#[cfg(all(feature = "preload", target_arch = "x86_64"))]
+const INTERPOSED: usize = 15;
+#[cfg(all(feature = "preload", not(target_arch = "x86_64")))]
+const INTERPOSED: usize = 13;
+
+/// secure_getenv for macOS, which does not provide it
+///
+/// Returns NULL in set-user-ID and set-group-ID programs (issetugid), and
+/// the real getenv result otherwise.
+///
+/// # Safety
+/// `name` must be a valid null-terminated C string
+pub(crate) unsafe fn secure_getenv_fallback(
+    real_getenv: GetenvFn,
+    name: *const c_char,
+) -> *mut c_char {
+    if libc::issetugid() != 0 {
+        return std::ptr::null_mut();
+    }
+    real_getenv(name)
+}
+
+/// VM_REGION_BASIC_INFO_64, from <mach/vm_region.h>
+const VM_REGION_BASIC_INFO_64: c_int = 9;
+
+/// vm_region_basic_info_data_64_t, which the headers pack to 4 bytes
+#[repr(C, packed(4))]
+#[derive(Default)]
+struct RegionBasicInfo {
+    protection: c_int,
+    max_protection: c_int,
+    inheritance: u32,
+    shared: u32,
+    reserved: u32,
+    offset: u64,
+    behavior: c_int,
+    user_wired_count: u16,
+}
+
+extern "C" {
+    static mach_task_self_: mach_port_t;
+
+    fn mach_vm_region(
+        task: mach_port_t,
+        address: *mut u64,
+        size: *mut u64,
+        flavor: c_int,
+        info: *mut c_int,
+        count: *mut u32,
+        object_name: *mut mach_port_t,
+    ) -> c_int;
+}
+
+/// Check whether an address range lies in a single writable VM region
+///
+/// The macOS counterpart of the /proc/self/maps check in the environ module.
+pub(crate) fn is_writable(addr: usize, len: usize) -> bool {
+    let mut start = addr as u64;
+    let mut size = 0;
+    let mut info = RegionBasicInfo::default();
+    let mut count = (std::mem::size_of::<RegionBasicInfo>() / std::mem::size_of::<c_int>()) as u32;
+    let mut object = 0;
+    // SAFETY: every pointer is a valid output of the size mach_vm_region
+    // expects; the region found starts at or above `start`
+    let result = unsafe {
+        mach_vm_region(
+            mach_task_self_,
+            &mut start,
+            &mut size,
+            VM_REGION_BASIC_INFO_64,
+            (&mut info as *mut RegionBasicInfo).cast(),
+            &mut count,
+            &mut object,
+        )
+    };
+    let protection = info.protection;
+    result == 0
+        && start <= addr as u64
+        && addr as u64 + len as u64 <= start + size
+        && protection & libc::VM_PROT_WRITE != 0
+}
+
+/// Command name of the process `pid`
+pub(crate) fn process_name(pid: libc::pid_t) -> Option<String> {
+    let mut buf = [0u8; 64];
+    // SAFETY: buf is a writable buffer of the given size
+    let len = unsafe { libc::proc_name(pid, buf.as_mut_ptr().cast(), buf.len() as u32) };
+    let len = usize::try_from(len).ok().filter(|&len| len > 0)?;
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+/// Executable path of the process `pid`
+pub(crate) fn process_path(pid: libc::pid_t) -> Option<String> {
+    // PROC_PIDPATHINFO_MAXSIZE
+    let mut buf = vec![0u8; 4096];
+    // SAFETY: buf is a writable buffer of the given size
+    let len = unsafe { libc::proc_pidpath(pid, buf.as_mut_ptr().cast(), buf.len() as u32) };
+    let len = usize::try_from(len).ok().filter(|&len| len > 0)?;
+    buf.truncate(len);
+    String::from_utf8(buf).ok()
+}
+
+/// File flag System Integrity Protection sets on the programs it protects
+/// (SF_RESTRICTED in <sys/stat.h>)
+const SF_RESTRICTED: u32 = 0x0008_0000;
+
+/// Whether dyld ignores DYLD_INSERT_LIBRARIES for the program at `path`
+///
+/// Returns None if `path` is not an executable regular file.
+fn ignores_insert(path: &CStr) -> Option<bool> {
+    // SAFETY: stat is a plain output buffer and path a valid C string
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::stat(path.as_ptr(), &mut stat) } != 0
+        || stat.st_mode & libc::S_IFMT != libc::S_IFREG
+        || unsafe { libc::access(path.as_ptr(), libc::X_OK) } != 0
+    {
+        return None;
+    }
+    Some(stat.st_flags & SF_RESTRICTED != 0 || stat.st_mode & (libc::S_ISUID | libc::S_ISGID) != 0)
+}
+
+/// The file that keeps the library out of `program`: the program itself or
+/// the interpreter of its `#!` line
+fn sip_restricted(program: Program) -> Option<CString> {
+    program
+        .find_map(|path| {
+            if ignores_insert(path)? {
+                return Some(Some(path.to_owned()));
+            }
+            let interpreter = multilib::interpreter(path)
+                .filter(|interpreter| ignores_insert(interpreter) == Some(true));
+            Some(interpreter)
+        })
+        .flatten()
+}
+
+/// Report an exec of a program that System Integrity Protection will start
+/// without the library
+pub(crate) fn check_exec(program: Program, via: &str) {
+    let Some(path) = sip_restricted(program) else {
+        return;
+    };
+    audit::emit(
+        "sip_restricted",
+        "*",
+        &format!(
+            "path={} via={}",
+            audit::detail_word(&path.to_string_lossy()),
+            via
+        ),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_writable() {
+        let mut stack = [0u8; 16];
+        assert!(is_writable(stack.as_mut_ptr() as usize, stack.len()));
+        static LITERAL: &[u8] = b"GITHUB_TOKEN=ghp_secret";
+        assert!(!is_writable(LITERAL.as_ptr() as usize, LITERAL.len()));
+    }
+
+    #[test]
+    fn test_process_identity() {
+        let pid = unsafe { libc::getpid() };
+        let exe = std::env::current_exe().unwrap();
+        assert_eq!(process_path(pid).as_deref(), exe.to_str());
+        assert!(process_name(pid).is_some_and(|name| !name.is_empty()));
+    }
+
+    #[test]
+    fn test_sip_restricted() {
+        assert_eq!(
+            sip_restricted(Program::Path(c"/bin/sh")).as_deref(),
+            Some(c"/bin/sh")
+        );
+        assert_eq!(
+            sip_restricted(Program::Search(c"sh")).as_deref(),
+            Some(c"/bin/sh")
+        );
+        let exe = std::env::current_exe().unwrap();
+        let exe = CString::new(exe.into_os_string().into_encoded_bytes()).unwrap();
+        assert_eq!(sip_restricted(Program::Path(&exe)), None);
+        assert_eq!(sip_restricted(Program::Path(c"/nonexistent/tool")), None);
+    }
+}
@@ -0,0 +1,345 @@
+//! Where settings come from, and how they combine
+//!
+//! A setting can be given by several sources. Each setting takes its whole
+//! value from the first of these that sets it, the others are ignored for
+//! it (values are never mixed across sources):
+//!
+//! 1. the configuration file (AWF_ONE_SHOT_CONFIG, token list settings only)
+//! 2. the host policy (AWF_ONE_SHOT_POLICY_FILE, token list settings only)
+//! 3. the environment
+//! 4. the hardening profile (AWF_ONE_SHOT_PROFILE)
+//! 5. the built-in default
+//!
+//! The token list is then built from the resolved settings in fixed steps:
+//! the rules of AWF_ONE_SHOT_TOKENS, or the tokens of the presets of
+//! AWF_ONE_SHOT_PRESETS if it lists none (the default presets if that is not
+//! set either), then the entries of AWF_ONE_SHOT_EXTRA_TOKENS in order,
+//! where an entry for a listed token replaces its rule in place and `-NAME`
+//! removes it. Settings that imply tokens (aliases, minted and digested
+//! tokens, secret files) add the names not listed yet with the default
+//! policy afterwards.
+//!
+//! Every rule records its origin, the setting and the source of the value
+//! it came from, and the verify report shows it next to each token.
+
+use crate::detect::preset_tokens;
+use crate::envname::{self, EnvName};
+use crate::policy::TokenSpec;
+use crate::MAX_TOKENS;
+use std::ffi::CStr;
+
+/// A source of settings, lowest precedence first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Source {
+    /// Not set anywhere: the built-in default applies
+    Default,
+    /// Implied by AWF_ONE_SHOT_PROFILE
+    Profile,
+    /// The process environment
+    Env,
+    /// The policy file provisioned by the host
+    HostPolicy,
+    /// The file named by AWF_ONE_SHOT_CONFIG
+    ConfigFile,
+    /// Added at run time through awf_token_protect (not a setting)
+    Api,
+}
+
+/// The sources that can set a value, highest precedence first
+const PRECEDENCE: [Source; 4] = [
+    Source::ConfigFile,
+    Source::HostPolicy,
+    Source::Env,
+    Source::Profile,
+];
+
+impl Source {
+    /// Short name used in the verify report and in log output
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Source::Default => "default",
+            Source::Profile => "profile",
+            Source::Env => "env",
+            Source::HostPolicy => "host_policy",
+            Source::ConfigFile => "config_file",
+            Source::Api => "api",
+        }
+    }
+}
+
+/// Where a token rule came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Origin {
+    /// The source of the setting's value
+    pub(crate) source: Source,
+    /// The setting that holds or implies the rule
+    pub(crate) setting: &'static CStr,
+}
+
+impl Origin {
+    /// The tokens of the default presets
+    pub(crate) const BUILT_IN: Origin = Origin {
+        source: Source::Default,
+        setting: c"AWF_ONE_SHOT_PRESETS",
+    };
+
+    /// Tokens added with awf_token_protect
+    pub(crate) const API: Origin = Origin {
+        source: Source::Api,
+        setting: c"awf_token_protect",
+    };
+}
+
+/// The value of a setting from the source of highest precedence that sets
+/// it, as asked of `lookup` source by source
+pub(crate) fn resolve<T>(mut lookup: impl FnMut(Source) -> Option<T>) -> Option<(Source, T)> {
+    PRECEDENCE
+        .into_iter()
+        .find_map(|source| lookup(source).map(|value| (source, value)))
+}
+
+/// An AWF_ONE_SHOT_EXTRA_TOKENS entry
+#[derive(Debug)]
+pub(crate) enum ExtraToken {
+    /// `NAME[:option...]`: protect a token, or change the policy of a listed one
+    Add(TokenSpec),
+    /// `-NAME`: stop protecting a listed token
+    Remove(EnvName),
+}
+
+/// Apply AWF_ONE_SHOT_EXTRA_TOKENS entries to the token list, in order
+///
+/// Additions beyond MAX_TOKENS are dropped.
+pub(crate) fn apply_extra_tokens(tokens: &mut Vec<TokenSpec>, entries: Vec<ExtraToken>) {
+    for entry in entries {
+        match entry {
+            ExtraToken::Add(spec) => {
+                match tokens.iter().position(|listed| listed.name == spec.name) {
+                    Some(index) => tokens[index] = spec,
+                    None if tokens.len() < MAX_TOKENS => tokens.push(spec),
+                    None => {}
+                }
+            }
+            ExtraToken::Remove(name) => tokens.retain(|listed| listed.name != name),
+        }
+    }
+}
+
+/// The resolved token list settings, each with the source of its value
+#[derive(Debug, Default)]
+pub(crate) struct TokenSettings {
+    /// AWF_ONE_SHOT_TOKENS
+    pub(crate) tokens: Option<(Source, Vec<u8>)>,
+    /// AWF_ONE_SHOT_PRESETS
+    pub(crate) presets: Option<(Source, Vec<u8>)>,
+    /// AWF_ONE_SHOT_EXTRA_TOKENS
+    pub(crate) extra: Option<(Source, Vec<u8>)>,
+}
+
+/// Build the token list from `settings`, parsing rules with `parse`
+///
+/// Returns the list and whether it is based on AWF_ONE_SHOT_TOKENS rather
+/// than on presets.
+pub(crate) fn token_list(
+    settings: &TokenSettings,
+    parse: impl Fn(&[u8]) -> Option<TokenSpec>,
+) -> (Vec<TokenSpec>, bool) {
+    let parse_from =
+        |entry: &[u8], origin: Origin| parse(entry).map(|spec| TokenSpec { origin, ..spec });
+    let mut tokens: Vec<TokenSpec> = Vec::new();
+    if let Some((source, config)) = &settings.tokens {
+        let origin = Origin {
+            source: *source,
+            setting: c"AWF_ONE_SHOT_TOKENS",
+        };
+        tokens.extend(
+            envname::split_list(config)
+                .take(MAX_TOKENS)
+                .filter_map(|entry| parse_from(entry, origin)),
+        );
+    }
+    // A list that names no token falls back to the presets (reported by the
+    // configuration check)
+    let custom = !tokens.is_empty();
+    if !custom {
+        let origin = Origin {
+            source: settings
+                .presets
+                .as_ref()
+                .map_or(Source::Default, |(source, _)| *source),
+            setting: c"AWF_ONE_SHOT_PRESETS",
+        };
+        let presets = settings
+            .presets
+            .as_ref()
+            .map(|(_, config)| String::from_utf8_lossy(config));
+        tokens.extend(
+            preset_tokens(presets.as_deref())
+                .into_iter()
+                .take(MAX_TOKENS)
+                .map(|name| TokenSpec {
+                    origin,
+                    ..TokenSpec::new(name)
+                }),
+        );
+    }
+
+    if let Some((source, config)) = &settings.extra {
+        let origin = Origin {
+            source: *source,
+            setting: c"AWF_ONE_SHOT_EXTRA_TOKENS",
+        };
+        let entries = envname::split_list(config)
+            .filter_map(|entry| match entry.strip_prefix(b"-") {
+                Some(name) => Some(ExtraToken::Remove(EnvName::new(name.trim_ascii()))),
+                None => parse_from(entry, origin).map(ExtraToken::Add),
+            })
+            .collect();
+        apply_extra_tokens(&mut tokens, entries);
+    }
+    (tokens, custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{self, TokenPolicy};
+
+    fn parse(entry: &[u8]) -> Option<TokenSpec> {
+        policy::parse_token_spec(entry).map(|(spec, _)| spec)
+    }
+
+    #[test]
+    fn test_resolve() {
+        let sources = [Source::Env, Source::Profile, Source::HostPolicy];
+        let lookup = |source| sources.contains(&source).then(|| source.as_str());
+        assert_eq!(resolve(lookup), Some((Source::HostPolicy, "host_policy")));
+        let lookup = |source| (source == Source::Profile).then_some(1);
+        assert_eq!(resolve(lookup), Some((Source::Profile, 1)));
+        assert_eq!(resolve(|_| None::<u8>), None);
+    }
+
+    #[test]
+    fn test_apply_extra_tokens() {
+        let mut tokens: Vec<TokenSpec> = preset_tokens(None).iter().map(TokenSpec::new).collect();
+        let defaults = tokens.len();
+        let (redacted, _) = policy::parse_token_spec("OPENAI_API_KEY:redact").unwrap();
+        apply_extra_tokens(
+            &mut tokens,
+            vec![
+                ExtraToken::Add(TokenSpec::new("MY_API_KEY")),
+                ExtraToken::Remove("GITHUB_TOKEN".into()),
+                ExtraToken::Add(redacted.clone()),
+                ExtraToken::Remove("NOT_LISTED".into()),
+            ],
+        );
+
+        // Defaults are kept, extended, and single defaults removed
+        assert_eq!(tokens.len(), defaults);
+        assert_eq!(tokens.last().unwrap().name, "MY_API_KEY");
+        assert!(!tokens.iter().any(|spec| spec.name == "GITHUB_TOKEN"));
+        assert!(tokens.iter().any(|spec| spec.name == "GH_TOKEN"));
+        // A listed token keeps its position and takes the new options
+        let openai = tokens.iter().position(|spec| spec.name == "OPENAI_API_KEY");
+        assert_eq!(
+            openai,
+            preset_tokens(None)
+                .iter()
+                .position(|name| *name == "OPENAI_API_KEY")
+                .map(|index| index - 1)
+        );
+        assert_eq!(tokens[openai.unwrap()], redacted);
+
+        // Entries apply in order: a removed token can be added back
+        apply_extra_tokens(
+            &mut tokens,
+            vec![
+                ExtraToken::Remove("MY_API_KEY".into()),
+                ExtraToken::Add(TokenSpec::new("GITHUB_TOKEN")),
+            ],
+        );
+        assert!(!tokens.iter().any(|spec| spec.name == "MY_API_KEY"));
+        assert_eq!(tokens.last().unwrap().name, "GITHUB_TOKEN");
+
+        // Additions stop at MAX_TOKENS, but listed tokens can still change
+        let mut full: Vec<TokenSpec> = (0..MAX_TOKENS)
+            .map(|index| TokenSpec::new(format!("TOKEN_{}", index)))
+            .collect();
+        let (strict, _) = policy::parse_token_spec("TOKEN_0:strict").unwrap();
+        apply_extra_tokens(
+            &mut full,
+            vec![
+                ExtraToken::Add(TokenSpec::new("EXTRA")),
+                ExtraToken::Add(strict.clone()),
+            ],
+        );
+        assert_eq!(full.len(), MAX_TOKENS);
+        assert_eq!(full[0], strict);
+    }
+
+    #[test]
+    fn test_token_list() {
+        // Nothing set: the default presets
+        let (tokens, custom) = token_list(&TokenSettings::default(), parse);
+        assert!(!custom);
+        assert_eq!(tokens.len(), preset_tokens(None).len());
+        assert!(tokens.iter().all(|spec| spec.origin == Origin::BUILT_IN));
+
+        let settings = TokenSettings {
+            tokens: Some((Source::HostPolicy, b"GITHUB_TOKEN:strict,MY_KEY".to_vec())),
+            presets: Some((Source::Env, b"npm".to_vec())),
+            extra: Some((Source::ConfigFile, b"-MY_KEY,GITHUB_TOKEN,OTHER".to_vec())),
+        };
+        let (tokens, custom) = token_list(&settings, parse);
+        assert!(custom);
+        let origins: Vec<(String, &CStr, Source)> = tokens
+            .iter()
+            .map(|spec| {
+                (
+                    spec.name.lossy().into_owned(),
+                    spec.origin.setting,
+                    spec.origin.source,
+                )
+            })
+            .collect();
+        // The extra entry replaced the host's rule for GITHUB_TOKEN
+        assert_eq!(
+            origins,
+            [
+                (
+                    "GITHUB_TOKEN".to_string(),
+                    c"AWF_ONE_SHOT_EXTRA_TOKENS",
+                    Source::ConfigFile
+                ),
+                (
+                    "OTHER".to_string(),
+                    c"AWF_ONE_SHOT_EXTRA_TOKENS",
+                    Source::ConfigFile
+                ),
+            ]
+        );
+        assert_eq!(tokens[0].policy, TokenPolicy::Cache);
+
+        // A token list naming nothing falls back to the presets it names
+        let settings = TokenSettings {
+            tokens: Some((Source::Env, b" , ".to_vec())),
+            ..settings
+        };
+        let (tokens, custom) = token_list(
+            &TokenSettings {
+                extra: None,
+                ..settings
+            },
+            parse,
+        );
+        assert!(!custom);
+        assert_eq!(tokens[0].name, "NPM_TOKEN");
+        assert_eq!(
+            tokens[0].origin,
+            Origin {
+                source: Source::Env,
+                setting: c"AWF_ONE_SHOT_PRESETS"
+            }
+        );
+    }
+}
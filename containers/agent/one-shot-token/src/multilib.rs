@@ -0,0 +1,317 @@
+//! Choice of the library build matching a child's architecture
+//!
+//! The dynamic linker skips an LD_PRELOAD entry built for another ELF class
+//! or machine with a "wrong ELF class" error, so a 32-bit tool started by a
+//! 64-bit agent, or an aarch64 program run through binfmt emulation, would
+//! run unprotected. When AWF_ONE_SHOT_LIB32, AWF_ONE_SHOT_LIB64 and
+//! AWF_ONE_SHOT_LIB_AARCH64 name the i686, x86_64 and aarch64 builds, the
+//! exec interposers read the ELF header of the program being started (or of
+//! its `#!` interpreter) and put the matching build in its LD_PRELOAD in
+//! place of the others. preload.sh does the same for the first command.
+
+use crate::{call_real_getenv, open, read_config_var};
+use libc::c_char;
+use std::ffi::{CStr, CString};
+
+/// Bytes read from the start of a program
+const HEADER_LEN: usize = 256;
+
+/// PATH searched when the variable is unset, as glibc's execvp does
+const DEFAULT_PATH: &[u8] = b"/bin:/usr/bin";
+
+/// e_machine values of the supported architectures
+const EM_386: u16 = 3;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+
+/// Architecture of a program, from its ELF class and machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Arch {
+    I686,
+    X86_64,
+    Aarch64,
+}
+
+impl Arch {
+    /// The architecture of an ELF class (EI_CLASS) and machine (e_machine)
+    fn from_elf(class: u8, machine: u16) -> Option<Arch> {
+        match (class, machine) {
+            (1, EM_386) => Some(Arch::I686),
+            (2, EM_X86_64) => Some(Arch::X86_64),
+            (2, EM_AARCH64) => Some(Arch::Aarch64),
+            _ => None,
+        }
+    }
+}
+
+/// The program an exec starts, as named by the caller
+#[derive(Clone, Copy)]
+pub(crate) enum Program<'a> {
+    /// A path, as passed to execve and posix_spawn
+    Path(&'a CStr),
+    /// A file name searched in PATH, as passed to execvpe and posix_spawnp
+    Search(&'a CStr),
+    /// No program name was passed
+    Unknown,
+}
+
+impl Program<'_> {
+    /// The program at `path`
+    ///
+    /// # Safety
+    /// `path` must be null or a valid null-terminated C string
+    pub(crate) unsafe fn path<'a>(path: *const c_char) -> Program<'a> {
+        if path.is_null() {
+            return Program::Unknown;
+        }
+        Program::Path(CStr::from_ptr(path))
+    }
+
+    /// The program named `file`, searched in PATH
+    ///
+    /// # Safety
+    /// `file` must be null or a valid null-terminated C string
+    pub(crate) unsafe fn search<'a>(file: *const c_char) -> Program<'a> {
+        if file.is_null() {
+            return Program::Unknown;
+        }
+        Program::Search(CStr::from_ptr(file))
+    }
+
+    /// Architecture of the program, or None if it cannot be read or is not
+    /// one of the supported ones
+    pub(crate) fn arch(&self) -> Option<Arch> {
+        self.find_map(|path| arch_of(path, true))
+    }
+
+    /// Apply `check` to the program's path, or to each PATH candidate in
+    /// turn until it returns Some
+    pub(crate) fn find_map<T>(&self, mut check: impl FnMut(&CStr) -> Option<T>) -> Option<T> {
+        match *self {
+            Program::Path(path) => check(path),
+            Program::Search(file) if file.to_bytes().contains(&b'/') => check(file),
+            Program::Search(file) => {
+                // SAFETY: PATH is read through the real getenv with a C string literal
+                let path = unsafe { call_real_getenv(c"PATH".as_ptr()) };
+                let path = if path.is_null() {
+                    DEFAULT_PATH
+                } else {
+                    // SAFETY: a non-null getenv result is a valid C string
+                    unsafe { CStr::from_ptr(path) }.to_bytes()
+                };
+                path.split(|&byte| byte == b':').find_map(|dir| {
+                    let dir = if dir.is_empty() { b".".as_slice() } else { dir };
+                    let mut candidate = dir.to_vec();
+                    candidate.push(b'/');
+                    candidate.extend_from_slice(file.to_bytes());
+                    check(&CString::new(candidate).ok()?)
+                })
+            }
+            Program::Unknown => None,
+        }
+    }
+}
+
+/// Paths of the builds for each architecture (AWF_ONE_SHOT_LIB32, LIB64 and
+/// LIB_AARCH64)
+#[derive(Debug, Default)]
+pub(crate) struct Builds {
+    pub(crate) i686: Option<String>,
+    pub(crate) x86_64: Option<String>,
+    pub(crate) aarch64: Option<String>,
+}
+
+impl Builds {
+    /// Read the configured build paths
+    pub(crate) fn load() -> Self {
+        let read = |name: &CStr| read_config_var(name).filter(|path| !path.trim().is_empty());
+        Self {
+            i686: read(c"AWF_ONE_SHOT_LIB32"),
+            x86_64: read(c"AWF_ONE_SHOT_LIB64"),
+            aarch64: read(c"AWF_ONE_SHOT_LIB_AARCH64"),
+        }
+    }
+
+    /// Whether any build path is configured
+    pub(crate) fn configured(&self) -> bool {
+        self.paths().next().is_some()
+    }
+
+    /// The build for programs of `arch`
+    pub(crate) fn for_arch(&self, arch: Arch) -> Option<&str> {
+        match arch {
+            Arch::I686 => self.i686.as_deref(),
+            Arch::X86_64 => self.x86_64.as_deref(),
+            Arch::Aarch64 => self.aarch64.as_deref(),
+        }
+    }
+
+    /// Every configured build path
+    pub(crate) fn paths(&self) -> impl Iterator<Item = &str> {
+        [&self.i686, &self.x86_64, &self.aarch64]
+            .into_iter()
+            .filter_map(Option::as_deref)
+    }
+}
+
+/// What the first bytes of a program say about it
+#[derive(Debug, PartialEq, Eq)]
+enum Header<'a> {
+    /// An ELF file, with its architecture if supported
+    Elf(Option<Arch>),
+    /// A `#!` script, with the interpreter path
+    Script(&'a [u8]),
+    Other,
+}
+
+/// Classify a program from its first bytes
+fn parse_header(header: &[u8]) -> Header<'_> {
+    if header.starts_with(b"\x7fELF") {
+        // e_machine follows e_ident (16 bytes) and e_type, in the byte order
+        // given by EI_DATA
+        let (Some(&class), Some(&data), Some(machine)) =
+            (header.get(4), header.get(5), header.get(18..20))
+        else {
+            return Header::Other;
+        };
+        let machine = [machine[0], machine[1]];
+        let machine = match data {
+            1 => u16::from_le_bytes(machine),
+            2 => u16::from_be_bytes(machine),
+            _ => return Header::Other,
+        };
+        return Header::Elf(Arch::from_elf(class, machine));
+    }
+    let Some(line) = header.strip_prefix(b"#!") else {
+        return Header::Other;
+    };
+    let start = line
+        .iter()
+        .position(|&byte| byte != b' ' && byte != b'\t')
+        .unwrap_or(line.len());
+    let line = &line[start..];
+    let end = line
+        .iter()
+        .position(|&byte| byte.is_ascii_whitespace())
+        .unwrap_or(line.len());
+    match &line[..end] {
+        [] => Header::Other,
+        interpreter => Header::Script(interpreter),
+    }
+}
+
+/// Read the first bytes of the file at `path` into `header`, returning how
+/// many were read
+fn read_header(path: &CStr, header: &mut [u8; HEADER_LEN]) -> Option<usize> {
+    // SAFETY: path is a valid C string; the descriptor is only read into
+    // `header` and closed
+    unsafe {
+        let fd = open::call_real_open(path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return None;
+        }
+        let len = libc::read(fd, header.as_mut_ptr().cast(), header.len());
+        libc::close(fd);
+        usize::try_from(len).ok()
+    }
+}
+
+/// Architecture of the file at `path`, following one `#!` line if `follow`
+fn arch_of(path: &CStr, follow: bool) -> Option<Arch> {
+    let mut header = [0u8; HEADER_LEN];
+    let len = read_header(path, &mut header)?;
+    match parse_header(&header[..len]) {
+        Header::Elf(arch) => arch,
+        Header::Script(interpreter) if follow => arch_of(&CString::new(interpreter).ok()?, false),
+        _ => None,
+    }
+}
+
+/// Interpreter named by the `#!` line of the file at `path`, if it is a
+/// script
+#[cfg(any(target_os = "macos", test))]
+pub(crate) fn interpreter(path: &CStr) -> Option<CString> {
+    let mut header = [0u8; HEADER_LEN];
+    let len = read_header(path, &mut header)?;
+    match parse_header(&header[..len]) {
+        Header::Script(interpreter) => CString::new(interpreter).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first 20 bytes of an ELF header
+    fn elf(class: u8, data: u8, machine: [u8; 2]) -> Vec<u8> {
+        let mut header = b"\x7fELF".to_vec();
+        header.extend_from_slice(&[class, data, 1]);
+        header.resize(18, 0);
+        header.extend_from_slice(&machine);
+        header
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            parse_header(&elf(1, 1, [3, 0])),
+            Header::Elf(Some(Arch::I686))
+        );
+        assert_eq!(
+            parse_header(&elf(2, 1, [62, 0])),
+            Header::Elf(Some(Arch::X86_64))
+        );
+        assert_eq!(
+            parse_header(&elf(2, 1, [183, 0])),
+            Header::Elf(Some(Arch::Aarch64))
+        );
+        assert_eq!(
+            parse_header(&elf(2, 2, [0, 183])),
+            Header::Elf(Some(Arch::Aarch64))
+        );
+        // 32-bit ARM, and a 64-bit class with an i386 machine
+        assert_eq!(parse_header(&elf(1, 1, [40, 0])), Header::Elf(None));
+        assert_eq!(parse_header(&elf(2, 1, [3, 0])), Header::Elf(None));
+        assert_eq!(parse_header(b"\x7fELF\x02\x01"), Header::Other);
+        assert_eq!(
+            parse_header(b"#! /usr/bin/env python3\nprint()"),
+            Header::Script(b"/usr/bin/env")
+        );
+        assert_eq!(parse_header(b"#!/bin/sh"), Header::Script(b"/bin/sh"));
+        assert_eq!(parse_header(b"#!\n"), Header::Other);
+        assert_eq!(parse_header(b"echo hi"), Header::Other);
+    }
+
+    #[test]
+    fn test_program_arch() {
+        let native = if cfg!(target_arch = "aarch64") {
+            Arch::Aarch64
+        } else if cfg!(target_arch = "x86") {
+            Arch::I686
+        } else {
+            Arch::X86_64
+        };
+        let exe = std::env::current_exe().unwrap();
+        let exe = CString::new(exe.into_os_string().into_encoded_bytes()).unwrap();
+        assert_eq!(Program::Path(&exe).arch(), Some(native));
+        assert_eq!(Program::Search(c"sh").arch(), Some(native));
+        assert_eq!(Program::Path(c"/nonexistent/tool").arch(), None);
+        assert_eq!(Program::Unknown.arch(), None);
+    }
+
+    #[test]
+    fn test_interpreter() {
+        let dir = std::env::temp_dir().join(format!("awf-multilib-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("script");
+        std::fs::write(&script, "#!/bin/sh -e\necho hi\n").unwrap();
+        let script = CString::new(script.into_os_string().into_encoded_bytes()).unwrap();
+        assert_eq!(interpreter(&script).as_deref(), Some(c"/bin/sh"));
+        assert_eq!(interpreter(c"/nonexistent/script"), None);
+        let exe = std::env::current_exe().unwrap();
+        let exe = CString::new(exe.into_os_string().into_encoded_bytes()).unwrap();
+        assert_eq!(interpreter(&exe), None);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
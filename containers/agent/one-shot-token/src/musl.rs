@@ -0,0 +1,86 @@
+//! musl libc compatibility
+//!
+//! Alpine-based agent images use musl, which differs from glibc where the
+//! interposers depend on libc behavior:
+//!
+//! - Library constructors are called without (argc, argv, envp), so argv
+//!   scrubbing finds argv on the initial stack instead (see the argv module)
+//! - unsetenv frees strings that setenv allocated, so residual value bytes
+//!   are zeroed before the variable is unset, not after
+//! - secure_getenv may be missing, and there is no __secure_getenv or
+//!   symbol versioning (dlvsym), so the secure lookup falls back to checking
+//!   AT_SECURE itself
+//!
+//! The libc is detected at runtime from the library that provides the real
+//! getenv, because a library built for glibc can still be loaded by musl's
+//! dynamic linker through the gcompat compatibility layer.
+
+#[cfg(target_os = "linux")]
+use crate::GetenvFn;
+#[cfg(target_os = "linux")]
+use libc::c_char;
+use once_cell::sync::Lazy;
+use std::ffi::CStr;
+
+/// Whether the real getenv lives in musl's libc
+static DETECTED: Lazy<bool> = Lazy::new(|| {
+    let real = *crate::REAL_GETENV as *const libc::c_void;
+    // SAFETY: dladdr only reads the loader's tables; info is written on success
+    unsafe {
+        let mut info: libc::Dl_info = std::mem::zeroed();
+        libc::dladdr(real, &mut info) != 0
+            && !info.dli_fname.is_null()
+            && is_musl_path(CStr::from_ptr(info.dli_fname).to_bytes())
+    }
+});
+
+/// Whether the process runs on musl libc
+pub(crate) fn detected() -> bool {
+    *DETECTED
+}
+
+/// Check whether a shared object path names musl's libc
+///
+/// musl's libc is its dynamic linker, installed as ld-musl-<arch>.so.1 and
+/// linked as libc.musl-<arch>.so.1 on Alpine.
+fn is_musl_path(path: &[u8]) -> bool {
+    let file = path.rsplit(|&byte| byte == b'/').next().unwrap_or(path);
+    file.starts_with(b"ld-musl-") || file.starts_with(b"libc.musl-")
+}
+
+/// secure_getenv for a libc that does not provide it
+///
+/// Returns NULL in set-user-ID, set-group-ID and capability-raising
+/// executables (AT_SECURE), like glibc's secure_getenv, and the real getenv
+/// result otherwise.
+///
+/// # Safety
+/// `name` must be a valid null-terminated C string
+#[cfg(target_os = "linux")]
+pub(crate) unsafe fn secure_getenv_fallback(
+    real_getenv: GetenvFn,
+    name: *const c_char,
+) -> *mut c_char {
+    if libc::getauxval(libc::AT_SECURE) != 0 {
+        return std::ptr::null_mut();
+    }
+    real_getenv(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_musl_path() {
+        assert!(is_musl_path(b"/lib/ld-musl-x86_64.so.1"));
+        assert!(is_musl_path(b"/usr/lib/libc.musl-aarch64.so.1"));
+        assert!(!is_musl_path(b"/lib/x86_64-linux-gnu/libc.so.6"));
+        assert!(!is_musl_path(b"/opt/musl/libfoo.so"));
+    }
+
+    #[test]
+    fn test_detected() {
+        assert_eq!(detected(), cfg!(target_env = "musl"));
+    }
+}
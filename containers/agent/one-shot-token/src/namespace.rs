@@ -0,0 +1,67 @@
+//! Namespaced secrets (`AWF_SECRET_<NAME>`)
+//!
+//! Even a token scrubbed on first read sits in the environment under its
+//! conventional name until then, where anything that inspects the process
+//! early finds it by that name. The host can instead inject it as
+//! `AWF_SECRET_GITHUB_TOKEN`: at load, every `AWF_SECRET_<NAME>` variable
+//! becomes an alias of NAME (which is protected if it was not already), and
+//! the token is cached and scrubbed right away, so a program asking for
+//! GITHUB_TOKEN is served the value while neither name is ever left in the
+//! environment.
+//!
+//! Names after the prefix that are empty or themselves start with `AWF_`
+//! are ignored, so the namespace cannot inject library settings.
+
+use crate::environ;
+use crate::envname::EnvName;
+use std::ffi::CStr;
+
+/// Prefix of namespaced secret variables
+const PREFIX: &[u8] = b"AWF_SECRET_";
+
+/// The name the namespaced variable `name` is served under, if it has one
+fn bare_name(name: &[u8]) -> Option<&[u8]> {
+    let bare = name.strip_prefix(PREFIX)?;
+    (!bare.is_empty() && !bare.starts_with(b"AWF_")).then_some(bare)
+}
+
+/// The namespaced variables of the environment as (namespaced, bare) names,
+/// in environment order; when two map to the same name, the first one wins
+///
+/// # Safety
+/// Must not race with concurrent modification of the environment
+pub(crate) unsafe fn list() -> Vec<(EnvName, EnvName)> {
+    let mut found: Vec<(EnvName, EnvName)> = Vec::new();
+    let mut entry = environ::current();
+    if entry.is_null() {
+        return found;
+    }
+    while !(*entry).is_null() {
+        let bytes = CStr::from_ptr(*entry).to_bytes();
+        let name = bytes.split(|&byte| byte == b'=').next().unwrap_or_default();
+        if let Some(bare) = bare_name(name) {
+            if found.iter().all(|(_, listed)| *listed != bare) {
+                found.push((EnvName::new(name), EnvName::new(bare)));
+            }
+        }
+        entry = entry.add(1);
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bare_name() {
+        assert_eq!(
+            bare_name(b"AWF_SECRET_GITHUB_TOKEN"),
+            Some(&b"GITHUB_TOKEN"[..])
+        );
+        assert_eq!(bare_name(b"AWF_SECRET_"), None);
+        assert_eq!(bare_name(b"AWF_SECRET_AWF_ONE_SHOT_TOKENS"), None);
+        assert_eq!(bare_name(b"GITHUB_TOKEN"), None);
+        assert_eq!(bare_name(b"AWF_SECRETS_DIR"), None);
+    }
+}
@@ -0,0 +1,279 @@
+//! Node.js adapter for eager scrubbing (a Node-API addon)
+//!
+//! With AWF_ONE_SHOT_EAGER the tokens leave environ before Node.js builds
+//! `process.env`, so code that looks at the object rather than asking for a
+//! name (`'GITHUB_TOKEN' in process.env`, `Object.hasOwn`, schema validators
+//! walking its properties) finds them missing. The library is therefore also
+//! a Node-API addon: loaded with `process.dlopen` (runtimes/node does so from
+//! `--require`), it replaces `process.env` with a proxy that has every
+//! protected token holding a value at that point as a non-enumerable
+//! accessor backed by the library's getenv. Each access is a read under the
+//! token's policy; the accessors are left out of `Object.keys`, spreading
+//! and the environment `child_process` copies, so copying `process.env`
+//! reads nothing and hands no token to a child.
+//!
+//! The Node-API functions are looked up in the running program when the
+//! addon is loaded, so the library does not depend on Node.js.
+
+use crate::{lock_state, TokenState};
+use libc::{c_char, c_int, c_void};
+use std::ffi::{CStr, CString};
+use std::mem::transmute;
+use std::ptr;
+use std::sync::OnceLock;
+
+type Env = *mut c_void;
+type Value = *mut c_void;
+type CallbackInfo = *mut c_void;
+type Callback = unsafe extern "C" fn(Env, CallbackInfo) -> Value;
+type Symbol = *mut c_void;
+
+/// napi_ok
+const OK: c_int = 0;
+
+/// Replaces `process.env`; called with the getenv function and the names
+///
+/// The proxy's target is an empty object rather than the environment, since
+/// the proxy invariants look up the target's own property, which would make
+/// Node.js read the token.
+const INSTALL: &str = r#"(function (getenv, names) {
+  const env = process.env;
+  const listed = new Set(names.split(',').filter(Boolean));
+  const accessor = (key) => ({ get: () => getenv(key), enumerable: false, configurable: true });
+  process.env = new Proxy(Object.create(null), {
+    get: (_, key) => (listed.has(key) ? getenv(key) : env[key]),
+    set: (_, key, value) => Reflect.set(env, key, value),
+    has: (_, key) => listed.has(key) || key in env,
+    ownKeys: () => [...new Set([...Reflect.ownKeys(env), ...listed])],
+    getOwnPropertyDescriptor: (_, key) =>
+      listed.has(key) ? accessor(key) : Reflect.getOwnPropertyDescriptor(env, key),
+    defineProperty: (_, key, descriptor) => Reflect.defineProperty(env, key, descriptor),
+    deleteProperty: (_, key) => {
+      listed.delete(key);
+      return Reflect.deleteProperty(env, key);
+    },
+  });
+})"#;
+
+type CreateFunction =
+    unsafe extern "C" fn(Env, *const c_char, usize, Callback, *mut c_void, *mut Value) -> c_int;
+type CreateString = unsafe extern "C" fn(Env, *const c_char, usize, *mut Value) -> c_int;
+type GetString = unsafe extern "C" fn(Env, Value, *mut c_char, usize, *mut usize) -> c_int;
+type GetCallbackInfo = unsafe extern "C" fn(
+    Env,
+    CallbackInfo,
+    *mut usize,
+    *mut Value,
+    *mut Value,
+    *mut *mut c_void,
+) -> c_int;
+type GetUndefined = unsafe extern "C" fn(Env, *mut Value) -> c_int;
+type SetNamedProperty = unsafe extern "C" fn(Env, Value, *const c_char, Value) -> c_int;
+type RunScript = unsafe extern "C" fn(Env, Value, *mut Value) -> c_int;
+type CallFunction =
+    unsafe extern "C" fn(Env, Value, Value, usize, *const Value, *mut Value) -> c_int;
+
+/// The Node-API functions the addon calls
+struct Api {
+    create_function: CreateFunction,
+    create_string_utf8: CreateString,
+    get_value_string_utf8: GetString,
+    get_cb_info: GetCallbackInfo,
+    get_undefined: GetUndefined,
+    set_named_property: SetNamedProperty,
+    run_script: RunScript,
+    call_function: CallFunction,
+}
+
+static API: OnceLock<Option<Api>> = OnceLock::new();
+
+/// Look up a function of the running program
+fn lookup(name: &CStr) -> Option<Symbol> {
+    // SAFETY: dlsym with the pseudo-handle and a valid C string
+    let symbol = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) };
+    (!symbol.is_null()).then_some(symbol)
+}
+
+/// The Node-API functions, or None outside Node.js
+fn api() -> Option<&'static Api> {
+    API.get_or_init(|| {
+        // SAFETY: the symbols are the Node-API functions of these signatures
+        unsafe {
+            Some(Api {
+                create_function: transmute::<Symbol, CreateFunction>(lookup(
+                    c"napi_create_function",
+                )?),
+                create_string_utf8: transmute::<Symbol, CreateString>(lookup(
+                    c"napi_create_string_utf8",
+                )?),
+                get_value_string_utf8: transmute::<Symbol, GetString>(lookup(
+                    c"napi_get_value_string_utf8",
+                )?),
+                get_cb_info: transmute::<Symbol, GetCallbackInfo>(lookup(c"napi_get_cb_info")?),
+                get_undefined: transmute::<Symbol, GetUndefined>(lookup(c"napi_get_undefined")?),
+                set_named_property: transmute::<Symbol, SetNamedProperty>(lookup(
+                    c"napi_set_named_property",
+                )?),
+                run_script: transmute::<Symbol, RunScript>(lookup(c"napi_run_script")?),
+                call_function: transmute::<Symbol, CallFunction>(lookup(c"napi_call_function")?),
+            })
+        }
+    })
+    .as_ref()
+}
+
+/// Comma-separated protected tokens that hold a value, in list order
+fn available_names(state: &TokenState) -> String {
+    let names: Vec<String> = state
+        .tokens
+        .iter()
+        .filter(|spec| {
+            state.cache.get(&spec.name).is_some_and(|entry| {
+                !entry.value.is_null() && entry.wiped.is_none() && !entry.locked
+            })
+        })
+        .map(|spec| spec.name.to_string())
+        .collect();
+    names.join(",")
+}
+
+/// A JavaScript string
+///
+/// # Safety
+/// `env` must be the environment of the current call
+unsafe fn string(api: &Api, env: Env, text: &[u8]) -> Option<Value> {
+    let mut value = ptr::null_mut();
+    ((api.create_string_utf8)(env, text.as_ptr().cast(), text.len(), &mut value) == OK)
+        .then_some(value)
+}
+
+/// The C string copy of a JavaScript string, None for other values
+///
+/// # Safety
+/// `env` must be the environment of the current call
+unsafe fn c_string(api: &Api, env: Env, value: Value) -> Option<CString> {
+    let mut len = 0;
+    if (api.get_value_string_utf8)(env, value, ptr::null_mut(), 0, &mut len) != OK {
+        return None;
+    }
+    let mut buf = vec![0u8; len + 1];
+    if (api.get_value_string_utf8)(env, value, buf.as_mut_ptr().cast(), buf.len(), &mut len) != OK {
+        return None;
+    }
+    buf.truncate(len);
+    CString::new(buf).ok()
+}
+
+/// `getenv(name)`: the value the library serves, or undefined
+unsafe extern "C" fn getenv_callback(env: Env, info: CallbackInfo) -> Value {
+    let Some(api) = api() else {
+        return ptr::null_mut();
+    };
+    let mut undefined = ptr::null_mut();
+    (api.get_undefined)(env, &mut undefined);
+    let (mut argc, mut arg) = (1, ptr::null_mut());
+    let status = (api.get_cb_info)(
+        env,
+        info,
+        &mut argc,
+        &mut arg,
+        ptr::null_mut(),
+        ptr::null_mut(),
+    );
+    if status != OK || argc == 0 {
+        return undefined;
+    }
+    let Some(name) = c_string(api, env, arg) else {
+        return undefined;
+    };
+    let value = crate::getenv(name.as_ptr());
+    if value.is_null() {
+        return undefined;
+    }
+    string(api, env, CStr::from_ptr(value).to_bytes()).unwrap_or(undefined)
+}
+
+/// Export `getenv` and replace `process.env`, or name the step that failed
+///
+/// # Safety
+/// `env` and `exports` must be those of the current module registration
+unsafe fn install(api: &Api, env: Env, exports: Value) -> Result<(), &'static str> {
+    let mut getenv = ptr::null_mut();
+    let name = "getenv";
+    let status = (api.create_function)(
+        env,
+        name.as_ptr().cast(),
+        name.len(),
+        getenv_callback,
+        ptr::null_mut(),
+        &mut getenv,
+    );
+    if status != OK || (api.set_named_property)(env, exports, c"getenv".as_ptr(), getenv) != OK {
+        return Err("napi_create_function");
+    }
+    let names = available_names(&lock_state());
+    let names = string(api, env, names.as_bytes()).ok_or("napi_create_string_utf8")?;
+    let script = string(api, env, INSTALL.as_bytes()).ok_or("napi_create_string_utf8")?;
+    let mut function = ptr::null_mut();
+    if (api.run_script)(env, script, &mut function) != OK {
+        return Err("napi_run_script");
+    }
+    let mut result = ptr::null_mut();
+    let args = [getenv, names];
+    if (api.call_function)(env, exports, function, 2, args.as_ptr(), &mut result) != OK {
+        return Err("napi_call_function");
+    }
+    Ok(())
+}
+
+/// Entry point of the addon, called by `process.dlopen`
+///
+/// # Safety
+/// Must only be called by Node.js when it loads the library as an addon
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn napi_register_module_v1(env: Env, exports: Value) -> Value {
+    let Some(api) = api() else {
+        return exports;
+    };
+    if let Err(step) = install(api, env, exports) {
+        log_line!(
+            Warning,
+            "init",
+            None,
+            "Could not install the process.env adapter: {} failed",
+            step
+        );
+    }
+    exports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::TokenSpec;
+    use crate::CachedToken;
+
+    #[test]
+    fn test_available_names() {
+        let mut state = TokenState::new();
+        for name in ["NAPI_TEST_SET", "NAPI_TEST_UNSET", "NAPI_TEST_LOCKED"] {
+            state.tokens.push(TokenSpec::new(name));
+        }
+        let mut value = *b"value\0";
+        let set = CachedToken {
+            value: value.as_mut_ptr().cast(),
+            ..CachedToken::unset()
+        };
+        let locked = CachedToken {
+            value: value.as_mut_ptr().cast(),
+            locked: true,
+            ..CachedToken::unset()
+        };
+        state.cache.insert("NAPI_TEST_SET".into(), set);
+        state
+            .cache
+            .insert("NAPI_TEST_UNSET".into(), CachedToken::unset());
+        state.cache.insert("NAPI_TEST_LOCKED".into(), locked);
+        assert_eq!(available_names(&state), "NAPI_TEST_SET");
+    }
+}
@@ -0,0 +1,519 @@
+//! Audit and enforcement of network activity
+//!
+//! The firewall host already sees DNS traffic at the proxy. When
+//! AWF_ONE_SHOT_DNS_AUDIT is enabled, every getaddrinfo lookup is also
+//! reported from inside the process with a `dns_lookup` audit event carrying
+//! the name, a timestamp and the resolved addresses, so the two views can be
+//! correlated.
+//!
+//! As defense in depth against proxy bypass, the host can deliver its domain
+//! allowlist (AWF_ONE_SHOT_ALLOWED_DOMAINS). connect() to an IP address is
+//! then only permitted if the address was returned by a getaddrinfo lookup of
+//! an allowed domain, is a loopback address, or belongs to the enforced
+//! proxy. Other connections fail with ECONNREFUSED and a `connect_blocked`
+//! audit event.
+//!
+//! The cloud metadata service (169.254.169.254, and fd00:ec2::254 on EC2)
+//! hands out credentials for the machine's role, which would let an agent
+//! mint fresh tokens that no environment variable ever held. With
+//! AWF_ONE_SHOT_METADATA_ACCESS at `block` (the default), connect() to these
+//! addresses fails with ECONNREFUSED and a `metadata_blocked` audit event,
+//! unless the address is listed in AWF_ONE_SHOT_ALLOWED_DOMAINS itself;
+//! addresses learned from lookups of allowed domains do not count. `log`
+//! lets the connection through with a `metadata_connect` event, and `allow`
+//! does not check it at all.
+//!
+//! connect() also reports connections made after a token read, if
+//! correlation IDs are configured to (see the correlation module), and
+//! refuses DNS traffic to other servers than the firewall's resolver, if it
+//! is configured (see the dns module). getaddrinfo() refuses names carrying
+//! protected values, if query names are scanned (also the dns module).
+
+use crate::correlation::Correlation;
+use crate::{
+    audit, dns, lock_state, protection_disabled, read_config_var, resolve_next, sys, Mode,
+};
+use libc::{addrinfo, c_char, c_int, sockaddr, sockaddr_in, sockaddr_in6, socklen_t};
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+type GetaddrinfoFn = unsafe extern "C" fn(
+    *const c_char,
+    *const c_char,
+    *const addrinfo,
+    *mut *mut addrinfo,
+) -> c_int;
+
+type ConnectFn = unsafe extern "C" fn(c_int, *const sockaddr, socklen_t) -> c_int;
+
+/// Cached pointer to the real getaddrinfo function
+static REAL_GETADDRINFO: Lazy<GetaddrinfoFn> = Lazy::new(|| {
+    // SAFETY: getaddrinfo has the GetaddrinfoFn signature
+    unsafe { std::mem::transmute::<*mut libc::c_void, GetaddrinfoFn>(resolve_next(c"getaddrinfo")) }
+});
+
+/// Cached pointer to the real connect function
+static REAL_CONNECT: Lazy<ConnectFn> = Lazy::new(|| {
+    // SAFETY: connect has the ConnectFn signature
+    unsafe { std::mem::transmute::<*mut libc::c_void, ConnectFn>(resolve_next(c"connect")) }
+});
+
+/// Call the real connect function, bypassing the allowlist, DNS and
+/// correlation checks
+///
+/// # Safety
+/// Arguments must satisfy the requirements of connect(2)
+#[cfg(not(feature = "minimal"))]
+pub(crate) unsafe fn call_real_connect(fd: c_int, addr: *const sockaddr, len: socklen_t) -> c_int {
+    (*REAL_CONNECT)(fd, addr, len)
+}
+
+/// Addresses of the cloud metadata service
+const METADATA_ADDRESSES: [IpAddr; 2] = [
+    IpAddr::V4(Ipv4Addr::new(169, 254, 169, 254)),
+    IpAddr::V6(Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254)),
+];
+
+/// What happens to connections to the cloud metadata service
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MetadataAccess {
+    Allow,
+    Log,
+    Block,
+}
+
+impl MetadataAccess {
+    /// Parse AWF_ONE_SHOT_METADATA_ACCESS (case-insensitive)
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "allow" => Some(MetadataAccess::Allow),
+            "log" => Some(MetadataAccess::Log),
+            "block" | "" => Some(MetadataAccess::Block),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `value` is a valid AWF_ONE_SHOT_METADATA_ACCESS setting
+pub(crate) fn is_metadata_policy(value: &str) -> bool {
+    MetadataAccess::parse(value).is_some()
+}
+
+static METADATA_ACCESS: Lazy<MetadataAccess> = Lazy::new(|| {
+    if protection_disabled() {
+        return MetadataAccess::Allow;
+    }
+    read_config_var(c"AWF_ONE_SHOT_METADATA_ACCESS")
+        .map(|value| MetadataAccess::parse(&value).unwrap_or(MetadataAccess::Block))
+        .unwrap_or(MetadataAccess::Block)
+});
+
+/// Check whether `ip` is an address of the cloud metadata service
+fn is_metadata_ip(ip: IpAddr) -> bool {
+    METADATA_ADDRESSES.contains(&ip.to_canonical())
+}
+
+/// Host-delivered domain allowlist and the addresses learned from it
+pub(crate) struct Allowlist {
+    /// Lowercase domains; each also covers its subdomains
+    domains: Vec<String>,
+    /// Addresses connect() may reach
+    ips: HashSet<IpAddr>,
+    /// Addresses listed in the allowlist itself
+    listed: HashSet<IpAddr>,
+}
+
+impl Allowlist {
+    /// Build the allowlist from AWF_ONE_SHOT_ALLOWED_DOMAINS
+    ///
+    /// `proxy_url` is the enforced proxy: its host is allowed as well.
+    pub(crate) fn new(domains: &str, proxy_url: Option<&str>) -> Self {
+        let mut allowlist = Self {
+            domains: Vec::new(),
+            ips: HashSet::new(),
+            listed: HashSet::new(),
+        };
+        let proxy_host = proxy_url.and_then(url_host);
+        let listed = domains.split(',').map(|entry| (entry, true));
+        for (entry, is_listed) in listed.chain(proxy_host.map(|host| (host, false))) {
+            let entry = entry.trim().trim_start_matches("*.").trim_end_matches('.');
+            if entry.is_empty() {
+                continue;
+            }
+            match entry
+                .trim_matches(|c| c == '[' || c == ']')
+                .parse::<IpAddr>()
+            {
+                Ok(ip) => {
+                    allowlist.ips.insert(ip.to_canonical());
+                    if is_listed {
+                        allowlist.listed.insert(ip.to_canonical());
+                    }
+                }
+                Err(_) => allowlist.domains.push(entry.to_ascii_lowercase()),
+            }
+        }
+        allowlist
+    }
+
+    /// Check whether `name` is an allowed domain or one of its subdomains
+    fn allows_domain(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        self.domains.iter().any(|domain| {
+            name == *domain
+                || name
+                    .strip_suffix(domain.as_str())
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        })
+    }
+
+    /// Remember the addresses of a lookup if the name is allowed
+    fn learn(&mut self, name: &str, addresses: &[IpAddr]) {
+        if self.allows_domain(name) {
+            self.ips
+                .extend(addresses.iter().map(|ip| ip.to_canonical()));
+        }
+    }
+
+    /// Check whether connect() may reach `ip`
+    pub(crate) fn allows_ip(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        ip.is_loopback() || self.ips.contains(&ip)
+    }
+
+    /// Check whether `ip` is listed in the allowlist itself, rather than
+    /// learned from a lookup
+    fn lists_ip(&self, ip: IpAddr) -> bool {
+        self.listed.contains(&ip.to_canonical())
+    }
+}
+
+/// Host part of a proxy URL such as `http://user@172.30.0.10:3128/`
+fn url_host(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split('/').next()?;
+    let host_port = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = if host_port.starts_with('[') {
+        host_port.split_once(']').map(|(host, _)| &host[1..])?
+    } else {
+        host_port.split(':').next()?
+    };
+    (!host.is_empty()).then_some(host)
+}
+
+/// Extract the IP address of an AF_INET or AF_INET6 socket address
+///
+/// # Safety
+/// `addr` must be null or point to a socket address of at least the size its
+/// family implies
+pub(crate) unsafe fn sockaddr_ip(addr: *const sockaddr) -> Option<IpAddr> {
+    if addr.is_null() {
+        return None;
+    }
+    match c_int::from((*addr).sa_family) {
+        libc::AF_INET => {
+            let addr = &*(addr as *const sockaddr_in);
+            Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                addr.sin_addr.s_addr,
+            ))))
+        }
+        libc::AF_INET6 => {
+            let addr = &*(addr as *const sockaddr_in6);
+            Some(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)))
+        }
+        _ => None,
+    }
+}
+
+/// Distinct IP addresses of a getaddrinfo result list, in order
+///
+/// # Safety
+/// `list` must be null or a list returned by getaddrinfo
+unsafe fn resolved_addresses(list: *const addrinfo) -> Vec<IpAddr> {
+    let mut addresses = Vec::new();
+    let mut entry = list;
+    while !entry.is_null() {
+        if let Some(ip) = sockaddr_ip((*entry).ai_addr) {
+            if !addresses.contains(&ip) {
+                addresses.push(ip);
+            }
+        }
+        entry = (*entry).ai_next;
+    }
+    addresses
+}
+
+/// Format the detail of a `dns_lookup` audit event
+fn format_lookup(name: &str, timestamp: &str, result: Result<&[IpAddr], &c_int>) -> String {
+    match result {
+        Ok(addresses) => {
+            let addresses: Vec<String> = addresses.iter().map(IpAddr::to_string).collect();
+            format!(
+                "name={} ts={} result=ok addrs={}",
+                name,
+                timestamp,
+                addresses.join(",")
+            )
+        }
+        Err(code) => format!("name={} ts={} result=error code={}", name, timestamp, code),
+    }
+}
+
+/// Intercepted getaddrinfo function
+///
+/// The real lookup is performed unless the name carries a protected value
+/// and query name scanning blocks it (see the dns module), in which case
+/// EAI_FAIL is returned. With AWF_ONE_SHOT_DNS_AUDIT enabled its outcome is
+/// reported, and addresses of allowed domains are added to the connect()
+/// allowlist.
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of getaddrinfo(3).
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn getaddrinfo(
+    node: *const c_char,
+    service: *const c_char,
+    hints: *const addrinfo,
+    res: *mut *mut addrinfo,
+) -> c_int {
+    if !node.is_null()
+        && dns::scanning()
+        && !dns::allow_name(&CStr::from_ptr(node).to_string_lossy(), "getaddrinfo")
+    {
+        return libc::EAI_FAIL;
+    }
+    let timestamp = audit::timestamp();
+    let result = (*REAL_GETADDRINFO)(node, service, hints, res);
+
+    if node.is_null() {
+        return result;
+    }
+    let mut state = lock_state();
+    if !state.dns_audit && state.allowlist.is_none() {
+        return result;
+    }
+
+    let name = CStr::from_ptr(node).to_string_lossy();
+    let lookup = if result == 0 && !res.is_null() {
+        Ok(resolved_addresses(*res))
+    } else {
+        Err(result)
+    };
+    if state.dns_audit {
+        let detail = format_lookup(&name, &timestamp, lookup.as_deref());
+        audit::emit("dns_lookup", "*", &detail);
+    }
+    if let (Some(allowlist), Ok(addresses)) = (state.allowlist.as_mut(), &lookup) {
+        allowlist.learn(&name, addresses);
+    }
+    result
+}
+
+/// Intercepted connect function
+///
+/// With an allowlist configured, connections to IP addresses that do not
+/// belong to an allowed domain are refused, as are connections to port 53 of
+/// other servers than a configured DNS resolver and, unless allowed, to the
+/// cloud metadata service. Other address families (such as Unix sockets) are
+/// passed through.
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of connect(2).
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn connect(fd: c_int, addr: *const sockaddr, len: socklen_t) -> c_int {
+    let Some(peer) = sockaddr_socket(addr) else {
+        return (*REAL_CONNECT)(fd, addr, len);
+    };
+    if !dns::allow_peer(peer, "connect") {
+        *sys::errno_location() = libc::ECONNREFUSED;
+        return -1;
+    }
+    let correlation = {
+        let state = lock_state();
+        let access = *METADATA_ACCESS;
+        if access != MetadataAccess::Allow
+            && is_metadata_ip(peer.ip())
+            && !state
+                .allowlist
+                .as_ref()
+                .is_some_and(|allowlist| allowlist.lists_ip(peer.ip()))
+        {
+            let detail = format!("ip={} port={}", peer.ip(), peer.port());
+            if access == MetadataAccess::Log {
+                audit::emit("metadata_connect", "*", &detail);
+            } else if state.mode == Mode::Observe {
+                let detail = format!("would=block_metadata_connect {}", detail);
+                audit::emit("observed", "*", &detail);
+            } else {
+                audit::emit("metadata_blocked", "*", &detail);
+                *sys::errno_location() = libc::ECONNREFUSED;
+                return -1;
+            }
+        }
+        if let Some(allowlist) = &state.allowlist {
+            if !allowlist.allows_ip(peer.ip()) {
+                let detail = format!("ip={} port={}", peer.ip(), peer.port());
+                if state.mode == Mode::Observe {
+                    audit::emit("observed", "*", &format!("would=block_connect {}", detail));
+                } else {
+                    audit::emit("connect_blocked", "*", &detail);
+                    *sys::errno_location() = libc::ECONNREFUSED;
+                    return -1;
+                }
+            }
+        }
+        state
+            .correlation
+            .clone()
+            .filter(Correlation::reports_connect)
+    };
+
+    let result = (*REAL_CONNECT)(fd, addr, len);
+    if let Some(correlation) = correlation {
+        let errno = *sys::errno_location();
+        if result == 0 || errno == libc::EINPROGRESS {
+            correlation.connected(local_socket(fd), peer);
+        }
+        *sys::errno_location() = errno;
+    }
+    result
+}
+
+/// Socket address structure and length for `addr`, the inverse of
+/// sockaddr_socket
+#[cfg(not(feature = "minimal"))]
+pub(crate) fn socket_addr_storage(addr: SocketAddr) -> (libc::sockaddr_storage, socklen_t) {
+    // SAFETY: sockaddr_storage is a plain struct, large and aligned enough
+    // for either address family
+    unsafe {
+        let mut storage: libc::sockaddr_storage = std::mem::zeroed();
+        let len = match addr {
+            SocketAddr::V4(v4) => {
+                let sin = &mut *(&mut storage as *mut libc::sockaddr_storage).cast::<sockaddr_in>();
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = v4.port().to_be();
+                sin.sin_addr.s_addr = u32::from_ne_bytes(v4.ip().octets());
+                std::mem::size_of::<sockaddr_in>()
+            }
+            SocketAddr::V6(v6) => {
+                let sin6 =
+                    &mut *(&mut storage as *mut libc::sockaddr_storage).cast::<sockaddr_in6>();
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = v6.port().to_be();
+                sin6.sin6_flowinfo = v6.flowinfo();
+                sin6.sin6_addr.s6_addr = v6.ip().octets();
+                sin6.sin6_scope_id = v6.scope_id();
+                std::mem::size_of::<sockaddr_in6>()
+            }
+        };
+        (storage, len as socklen_t)
+    }
+}
+
+/// IP address and port of an AF_INET or AF_INET6 socket address
+///
+/// # Safety
+/// As for sockaddr_ip
+pub(crate) unsafe fn sockaddr_socket(addr: *const sockaddr) -> Option<SocketAddr> {
+    let ip = sockaddr_ip(addr)?;
+    let port = u16::from_be(if ip.is_ipv4() {
+        (*(addr as *const sockaddr_in)).sin_port
+    } else {
+        (*(addr as *const sockaddr_in6)).sin6_port
+    });
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Local address of a socket
+fn local_socket(fd: c_int) -> Option<SocketAddr> {
+    // SAFETY: storage is large enough for any socket address
+    unsafe {
+        let mut storage: libc::sockaddr_storage = std::mem::zeroed();
+        let mut len = std::mem::size_of::<libc::sockaddr_storage>() as socklen_t;
+        if libc::getsockname(fd, &mut storage as *mut _ as *mut sockaddr, &mut len) != 0 {
+            return None;
+        }
+        sockaddr_socket(&storage as *const _ as *const sockaddr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sockaddr_ip() {
+        let mut addr: sockaddr_in = unsafe { std::mem::zeroed() };
+        addr.sin_family = libc::AF_INET as libc::sa_family_t;
+        addr.sin_addr.s_addr = u32::from(Ipv4Addr::new(10, 0, 0, 1)).to_be();
+        let ip = unsafe { sockaddr_ip(&addr as *const sockaddr_in as *const sockaddr) };
+        assert_eq!(ip, Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+        assert_eq!(unsafe { sockaddr_ip(std::ptr::null()) }, None);
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let mut allowlist = Allowlist::new(
+            "github.com, *.githubusercontent.com,10.1.2.3",
+            Some("http://172.30.0.10:3128"),
+        );
+        assert!(allowlist.allows_domain("api.GitHub.com."));
+        assert!(allowlist.allows_domain("raw.githubusercontent.com"));
+        assert!(!allowlist.allows_domain("evilgithub.com"));
+
+        let github = IpAddr::V4(Ipv4Addr::new(140, 82, 112, 3));
+        let evil = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+        allowlist.learn("api.github.com", &[github]);
+        allowlist.learn("evil.example", &[evil]);
+        assert!(allowlist.allows_ip(github));
+        assert!(!allowlist.allows_ip(evil));
+        assert!(allowlist.allows_ip("172.30.0.10".parse().unwrap()));
+        assert!(allowlist.allows_ip("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(allowlist.allows_ip(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+        assert!(allowlist.lists_ip("10.1.2.3".parse().unwrap()));
+        assert!(!allowlist.lists_ip("172.30.0.10".parse().unwrap()));
+        assert!(!allowlist.lists_ip(github));
+    }
+
+    #[test]
+    fn test_metadata() {
+        assert_eq!(MetadataAccess::parse(" LOG "), Some(MetadataAccess::Log));
+        assert_eq!(MetadataAccess::parse("allow"), Some(MetadataAccess::Allow));
+        assert_eq!(MetadataAccess::parse("deny"), None);
+        assert!(is_metadata_ip("169.254.169.254".parse().unwrap()));
+        assert!(is_metadata_ip("::ffff:169.254.169.254".parse().unwrap()));
+        assert!(is_metadata_ip("fd00:ec2::254".parse().unwrap()));
+        assert!(!is_metadata_ip("169.254.169.253".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_url_host() {
+        assert_eq!(url_host("http://172.30.0.10:3128"), Some("172.30.0.10"));
+        assert_eq!(url_host("http://user:pw@squid:3128/"), Some("squid"));
+        assert_eq!(url_host("http://[fd00::1]:3128"), Some("fd00::1"));
+        assert_eq!(url_host("squid"), Some("squid"));
+        assert_eq!(url_host("http://"), None);
+    }
+
+    #[test]
+    fn test_format_lookup() {
+        let addresses = [
+            IpAddr::V4(Ipv4Addr::new(140, 82, 112, 3)),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ];
+        assert_eq!(
+            format_lookup("github.com", "1700000000.000", Ok(&addresses)),
+            "name=github.com ts=1700000000.000 result=ok addrs=140.82.112.3,::1"
+        );
+        assert_eq!(
+            format_lookup("nx.invalid", "1700000000.000", Err(&libc::EAI_NONAME)),
+            "name=nx.invalid ts=1700000000.000 result=error code=-2"
+        );
+    }
+}
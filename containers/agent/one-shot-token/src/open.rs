@@ -0,0 +1,340 @@
+//! Interception of file opens
+//!
+//! open, open64, openat and openat64 (and fopen, fopen64, freopen and
+//! freopen64, which glibc implements with an internal open that bypasses
+//! these symbols) are interposed so that reads of /proc/<pid>/environ can be served a redacted
+//! copy, reads of other processes' environ and cmdline are reported (see the
+//! procfs module), opens of their mem and maps are denied (see the procmem
+//! module) and credential files are subject to their
+//! access policy (see the credfile module). All other opens are passed
+//! through untouched.
+//!
+//! On Apple silicon open and openat are not interposed (see the macos
+//! module), so only fopen is.
+#![cfg_attr(
+    all(target_os = "macos", not(target_arch = "x86_64")),
+    allow(dead_code)
+)]
+
+#[cfg(target_os = "linux")]
+use crate::procmem;
+use crate::{credfile, procfs, resolve_next, sys};
+use libc::{c_char, c_int, mode_t, FILE};
+use once_cell::sync::Lazy;
+use std::ffi::CStr;
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+
+type OpenFn = unsafe extern "C" fn(*const c_char, c_int, ...) -> c_int;
+type OpenatFn = unsafe extern "C" fn(c_int, *const c_char, c_int, ...) -> c_int;
+type FopenFn = unsafe extern "C" fn(*const c_char, *const c_char) -> *mut FILE;
+#[cfg(target_os = "linux")]
+type FreopenFn = unsafe extern "C" fn(*const c_char, *const c_char, *mut FILE) -> *mut FILE;
+
+/// Cached pointer to the real open function
+static REAL_OPEN: Lazy<OpenFn> = Lazy::new(|| {
+    // SAFETY: open has the OpenFn signature
+    unsafe { std::mem::transmute::<*mut libc::c_void, OpenFn>(resolve_next(c"open")) }
+});
+
+/// Cached pointer to the real open64 function
+static REAL_OPEN64: Lazy<OpenFn> = Lazy::new(|| {
+    // SAFETY: open64 has the same signature as open
+    unsafe { std::mem::transmute::<*mut libc::c_void, OpenFn>(resolve_next(c"open64")) }
+});
+
+/// Cached pointer to the real openat function
+static REAL_OPENAT: Lazy<OpenatFn> = Lazy::new(|| {
+    // SAFETY: openat has the OpenatFn signature
+    unsafe { std::mem::transmute::<*mut libc::c_void, OpenatFn>(resolve_next(c"openat")) }
+});
+
+/// Cached pointer to the real openat64 function
+static REAL_OPENAT64: Lazy<OpenatFn> = Lazy::new(|| {
+    // SAFETY: openat64 has the same signature as openat
+    unsafe { std::mem::transmute::<*mut libc::c_void, OpenatFn>(resolve_next(c"openat64")) }
+});
+
+/// Cached pointer to the real fopen function
+static REAL_FOPEN: Lazy<FopenFn> = Lazy::new(|| {
+    // SAFETY: fopen has the FopenFn signature
+    unsafe { std::mem::transmute::<*mut libc::c_void, FopenFn>(resolve_next(c"fopen")) }
+});
+
+/// Cached pointer to the real fopen64 function
+static REAL_FOPEN64: Lazy<FopenFn> = Lazy::new(|| {
+    // SAFETY: fopen64 has the same signature as fopen
+    unsafe { std::mem::transmute::<*mut libc::c_void, FopenFn>(resolve_next(c"fopen64")) }
+});
+
+/// Cached pointer to the real freopen function
+#[cfg(target_os = "linux")]
+static REAL_FREOPEN: Lazy<FreopenFn> = Lazy::new(|| {
+    // SAFETY: freopen has the FreopenFn signature
+    unsafe { std::mem::transmute::<*mut libc::c_void, FreopenFn>(resolve_next(c"freopen")) }
+});
+
+/// Cached pointer to the real freopen64 function
+#[cfg(target_os = "linux")]
+static REAL_FREOPEN64: Lazy<FreopenFn> = Lazy::new(|| {
+    // SAFETY: freopen64 has the same signature as freopen
+    unsafe { std::mem::transmute::<*mut libc::c_void, FreopenFn>(resolve_next(c"freopen64")) }
+});
+
+/// Call the real open function, bypassing the interposer
+///
+/// # Safety
+/// `path` must be a valid null-terminated C string
+pub(crate) unsafe fn call_real_open(path: *const c_char, flags: c_int) -> c_int {
+    (*REAL_OPEN)(path, flags)
+}
+
+/// Call the real open function with a creation mode, bypassing the interposer
+///
+/// # Safety
+/// `path` must be a valid null-terminated C string
+pub(crate) unsafe fn call_real_open_mode(path: *const c_char, flags: c_int, mode: mode_t) -> c_int {
+    (*REAL_OPEN)(path, flags, mode as libc::c_uint)
+}
+
+/// Apply the procfs and credential file policies to an open
+///
+/// Returns the file descriptor (or -1 with errno set) to hand back to the
+/// caller, or None if the open should be passed through.
+///
+/// # Safety
+/// `path` must be null or a valid null-terminated C string
+unsafe fn intercept(path: *const c_char, flags: c_int, via: &str) -> Option<c_int> {
+    if path.is_null() {
+        return None;
+    }
+    let path = CStr::from_ptr(path);
+    if !procfs::check_snooping(path, via) {
+        *sys::errno_location() = libc::EACCES;
+        return Some(-1);
+    }
+    #[cfg(target_os = "linux")]
+    if !procmem::check_open(path, via) {
+        *sys::errno_location() = libc::EACCES;
+        return Some(-1);
+    }
+    if let Some(fd) = procfs::intercept_open(path, flags, via) {
+        return Some(fd);
+    }
+    if !credfile::check_access(path, via) {
+        *sys::errno_location() = libc::EACCES;
+        return Some(-1);
+    }
+    None
+}
+
+/// Translate an fopen mode string into open flags
+///
+/// # Safety
+/// `mode` must be null or a valid null-terminated C string
+unsafe fn fopen_flags(mode: *const c_char) -> c_int {
+    if mode.is_null() {
+        return libc::O_RDONLY;
+    }
+    let mode = CStr::from_ptr(mode).to_bytes();
+    let mut flags = match (mode.first(), mode.contains(&b'+')) {
+        (_, true) => libc::O_RDWR,
+        (Some(b'r'), false) => libc::O_RDONLY,
+        _ => libc::O_WRONLY,
+    };
+    if mode.contains(&b'e') {
+        flags |= libc::O_CLOEXEC;
+    }
+    flags
+}
+
+/// Shared fopen/fopen64 handling: wrap an intercepted descriptor in a stream
+///
+/// # Safety
+/// `path` and `mode` must be null or valid null-terminated C strings
+unsafe fn intercept_fopen(
+    path: *const c_char,
+    mode: *const c_char,
+    via: &str,
+) -> Option<*mut FILE> {
+    let fd = intercept(path, fopen_flags(mode), via)?;
+    if fd < 0 {
+        return Some(std::ptr::null_mut());
+    }
+    let stream = libc::fdopen(fd, mode);
+    if stream.is_null() {
+        libc::close(fd);
+    }
+    Some(stream)
+}
+
+/// Shared freopen/freopen64 handling: reopen the stream on an intercepted
+/// descriptor through /proc/self/fd
+///
+/// # Safety
+/// `path` and `mode` must be null or valid null-terminated C strings, and
+/// `stream` an open stream
+#[cfg(target_os = "linux")]
+unsafe fn intercept_freopen(
+    path: *const c_char,
+    mode: *const c_char,
+    stream: *mut FILE,
+    via: &str,
+    real: FreopenFn,
+) -> Option<*mut FILE> {
+    let fd = intercept(path, fopen_flags(mode), via)?;
+    if fd < 0 {
+        // freopen closes the stream even when the new open fails
+        let errno = *sys::errno_location();
+        libc::fclose(stream);
+        *sys::errno_location() = errno;
+        return Some(std::ptr::null_mut());
+    }
+    let fd_path = CString::new(format!("/proc/self/fd/{}", fd)).unwrap_or_default();
+    let reopened = real(fd_path.as_ptr(), mode, stream);
+    let errno = *sys::errno_location();
+    libc::close(fd);
+    *sys::errno_location() = errno;
+    Some(reopened)
+}
+
+/// Intercepted open function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// `path` must be null or a valid null-terminated C string. `mode` is only
+/// read by the kernel when `flags` contains O_CREAT or O_TMPFILE, matching the
+/// variadic C prototype.
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn open(path: *const c_char, flags: c_int, mode: mode_t) -> c_int {
+    if let Some(fd) = intercept(path, flags, "open") {
+        return fd;
+    }
+    (*REAL_OPEN)(path, flags, mode as libc::c_uint)
+}
+
+/// Intercepted open64 function
+///
+/// # Safety
+/// Same requirements as open.
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn open64(path: *const c_char, flags: c_int, mode: mode_t) -> c_int {
+    if let Some(fd) = intercept(path, flags, "open64") {
+        return fd;
+    }
+    (*REAL_OPEN64)(path, flags, mode as libc::c_uint)
+}
+
+/// Intercepted openat function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// `path` must be null or a valid null-terminated C string.
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn openat(
+    dirfd: c_int,
+    path: *const c_char,
+    flags: c_int,
+    mode: mode_t,
+) -> c_int {
+    if let Some(fd) = intercept(path, flags, "openat") {
+        return fd;
+    }
+    (*REAL_OPENAT)(dirfd, path, flags, mode as libc::c_uint)
+}
+
+/// Intercepted openat64 function
+///
+/// # Safety
+/// Same requirements as openat.
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn openat64(
+    dirfd: c_int,
+    path: *const c_char,
+    flags: c_int,
+    mode: mode_t,
+) -> c_int {
+    if let Some(fd) = intercept(path, flags, "openat64") {
+        return fd;
+    }
+    (*REAL_OPENAT64)(dirfd, path, flags, mode as libc::c_uint)
+}
+
+/// Intercepted fopen function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// `path` and `mode` must be null or valid null-terminated C strings.
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn fopen(path: *const c_char, mode: *const c_char) -> *mut FILE {
+    if let Some(stream) = intercept_fopen(path, mode, "fopen") {
+        return stream;
+    }
+    (*REAL_FOPEN)(path, mode)
+}
+
+/// Intercepted fopen64 function
+///
+/// # Safety
+/// Same requirements as fopen.
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn fopen64(path: *const c_char, mode: *const c_char) -> *mut FILE {
+    if let Some(stream) = intercept_fopen(path, mode, "fopen64") {
+        return stream;
+    }
+    (*REAL_FOPEN64)(path, mode)
+}
+
+/// Intercepted freopen function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// `path` and `mode` must be null or valid null-terminated C strings, and
+/// `stream` an open stream.
+#[cfg(target_os = "linux")]
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn freopen(
+    path: *const c_char,
+    mode: *const c_char,
+    stream: *mut FILE,
+) -> *mut FILE {
+    if let Some(stream) = intercept_freopen(path, mode, stream, "freopen", *REAL_FREOPEN) {
+        return stream;
+    }
+    (*REAL_FREOPEN)(path, mode, stream)
+}
+
+/// Intercepted freopen64 function
+///
+/// # Safety
+/// Same requirements as freopen.
+#[cfg(target_os = "linux")]
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn freopen64(
+    path: *const c_char,
+    mode: *const c_char,
+    stream: *mut FILE,
+) -> *mut FILE {
+    if let Some(stream) = intercept_freopen(path, mode, stream, "freopen64", *REAL_FREOPEN64) {
+        return stream;
+    }
+    (*REAL_FREOPEN64)(path, mode, stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fopen_flags() {
+        unsafe {
+            assert_eq!(fopen_flags(c"r".as_ptr()), libc::O_RDONLY);
+            assert_eq!(fopen_flags(c"rb+".as_ptr()), libc::O_RDWR);
+            assert_eq!(fopen_flags(c"a".as_ptr()), libc::O_WRONLY);
+            assert_eq!(
+                fopen_flags(c"re".as_ptr()),
+                libc::O_RDONLY | libc::O_CLOEXEC
+            );
+        }
+    }
+}
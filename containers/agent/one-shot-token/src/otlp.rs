@@ -0,0 +1,589 @@
+//! OpenTelemetry log exporter (AWF_ONE_SHOT_OTLP_ENDPOINT)
+//!
+//! Organizations that already run an OpenTelemetry pipeline can collect the
+//! library's telemetry without a custom ingester. With
+//! AWF_ONE_SHOT_OTLP_ENDPOINT set to an OTLP/HTTP collector (for example
+//! `http://127.0.0.1:4318`), every event of the event stream (token
+//! accesses, audit events including violations, and scrub results, see the
+//! events module) is also exported as an OTLP log record, POSTed in the JSON
+//! encoding to `/v1/logs` (or to the endpoint's own path, if it has one).
+//!
+//! A record's body is the event name; the event, token and line fields
+//! become `awf.*` attributes, next to the run context (see the context
+//! module). Refused accesses and violations are `WARN`, other audit events
+//! and accesses `INFO`. The scope carries the event schema version as
+//! `awf.schema_version` (see the schema module), and the resource
+//! `service.name` (AWF_ONE_SHOT_OTLP_SERVICE, default `one-shot-token`),
+//! `process.pid` and `process.executable.name`. Extra request headers, such
+//! as the collector's API key, are given in AWF_ONE_SHOT_OTLP_HEADERS as
+//! `key=value` pairs separated by commas.
+//!
+//! Export never blocks the program: records are queued, and a background
+//! thread, started with the first record, sends them in batches once a
+//! second; the rest is sent at exit. Records beyond MAX_QUEUED are dropped
+//! (and counted in a warning) while the collector is unreachable. Only
+//! `http://` endpoints are supported: TLS and gRPC are left to a local
+//! collector. The exporter's connections bypass the connect interposer, so
+//! an allowlist does not have to name the collector.
+
+use crate::log::{self, Level};
+use crate::{context, egress, net, sys};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Most records waiting to be sent; later ones are dropped
+const MAX_QUEUED: usize = 4096;
+
+/// Records sent in one request
+const BATCH: usize = 256;
+
+/// How often the thread sends what is queued
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// Send and receive timeout of a request (also bounds connect)
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where records are sent
+#[derive(Debug, PartialEq, Eq)]
+struct Endpoint {
+    /// `host:port`, for resolving and the Host header
+    authority: String,
+    path: String,
+}
+
+impl Endpoint {
+    /// Parse `http://host[:port][/path]`; the port defaults to 4318 and an
+    /// empty path to `/v1/logs`
+    fn parse(url: &str) -> Option<Self> {
+        let rest = url.trim().strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return None;
+        }
+        let has_port = match authority.rfind(']') {
+            // IPv6 literal: a port follows the bracket
+            Some(bracket) => authority[bracket..].contains(':'),
+            None => authority.contains(':'),
+        };
+        let authority = if has_port {
+            authority.to_string()
+        } else {
+            format!("{}:4318", authority)
+        };
+        let path = match path.trim_end_matches('/') {
+            "" => "/v1/logs".to_string(),
+            path => path.to_string(),
+        };
+        Some(Endpoint { authority, path })
+    }
+}
+
+/// Whether `url` is an AWF_ONE_SHOT_OTLP_ENDPOINT value
+pub(crate) fn is_endpoint(url: &str) -> bool {
+    Endpoint::parse(url).is_some()
+}
+
+/// The configured exporter
+struct Exporter {
+    endpoint: Endpoint,
+    /// Extra header lines, each ending in CRLF
+    headers: String,
+    /// Resource attributes other than the process ID, which changes in
+    /// forked children
+    resource: String,
+    /// Collector address, resolved on the first send
+    addr: OnceLock<Option<SocketAddr>>,
+}
+
+static EXPORTER: OnceLock<Exporter> = OnceLock::new();
+
+/// Records waiting for the sender thread
+struct Queue {
+    records: VecDeque<String>,
+    /// Records dropped since the last warning
+    dropped: u64,
+    /// Whether this process's sender thread is running
+    running: bool,
+    /// Whether a failed request was already reported
+    failed: bool,
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    records: VecDeque::new(),
+    dropped: 0,
+    running: false,
+    failed: false,
+});
+
+/// Wakes the sender thread when a batch is full
+static WAKE: Condvar = Condvar::new();
+
+fn lock_queue() -> MutexGuard<'static, Queue> {
+    QUEUE.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Parse AWF_ONE_SHOT_OTLP_HEADERS (`key=value,key=value`) into header lines
+pub(crate) fn parse_headers(value: &str) -> String {
+    value
+        .split(',')
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            let (key, value) = (key.trim(), value.trim());
+            let valid = !key.is_empty()
+                && key
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+                && !value.contains(['\r', '\n']);
+            valid.then(|| format!("{}: {}\r\n", key, value))
+        })
+        .collect()
+}
+
+/// A key-value attribute of the OTLP JSON encoding
+fn attribute(key: &str, value: &str) -> String {
+    format!(
+        "{{\"key\":{},\"value\":{{\"stringValue\":{}}}}}",
+        log::json_string(key),
+        log::json_string(value)
+    )
+}
+
+/// Export events to the collector at `url` from now on
+pub(crate) fn install(url: &str, headers: Option<&str>, service: Option<&str>) -> io::Result<()> {
+    let endpoint =
+        Endpoint::parse(url).ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let exe = crate::process::exe().unwrap_or_default();
+    let attributes = [
+        attribute("service.name", service.unwrap_or("one-shot-token")),
+        attribute(
+            "process.executable.name",
+            exe.rsplit('/').next().unwrap_or_default(),
+        ),
+    ];
+    let exporter = Exporter {
+        endpoint,
+        headers: headers.map(parse_headers).unwrap_or_default(),
+        resource: attributes.join(","),
+        addr: OnceLock::new(),
+    };
+    if EXPORTER.set(exporter).is_ok() {
+        // SAFETY: flush_at_exit is a plain extern "C" function without
+        // arguments
+        if unsafe { libc::atexit(flush_at_exit) } != 0 {
+            log_line!(
+                Warning,
+                "otlp",
+                None,
+                "Could not register OTLP flush at exit"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Send what is still queued when the process exits
+extern "C" fn flush_at_exit() {
+    flush();
+}
+
+/// Whether events are being exported
+pub(crate) fn enabled() -> bool {
+    EXPORTER.get().is_some()
+}
+
+/// Severity number and text of an event
+fn severity(level: Level, event: &str, fields: &[(String, String)]) -> (u8, &'static str) {
+    match level {
+        Level::Debug => (5, "DEBUG"),
+        Level::Audit if log::is_refusal(event, fields) => (13, "WARN"),
+        Level::Info | Level::Audit => (9, "INFO"),
+        Level::Warning => (13, "WARN"),
+        Level::Error => (17, "ERROR"),
+        Level::Fatal => (21, "FATAL"),
+    }
+}
+
+/// Format an event as an OTLP log record
+fn record(
+    time: SystemTime,
+    level: Level,
+    event: &str,
+    token: Option<&str>,
+    fields: &[(String, String)],
+) -> String {
+    let nanos = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let (number, text) = severity(level, event, fields);
+    let mut attributes = vec![attribute("awf.event", event)];
+    if let Some(token) = token {
+        attributes.push(attribute("awf.token", token));
+    }
+    for (key, value) in context::fields().iter().chain(fields) {
+        attributes.push(attribute(&format!("awf.{}", key), value));
+    }
+    format!(
+        "{{\"timeUnixNano\":\"{}\",\"severityNumber\":{},\"severityText\":\"{}\",\"body\":{{\"stringValue\":{}}},\"attributes\":[{}]}}",
+        nanos,
+        number,
+        text,
+        log::json_string(event),
+        attributes.join(",")
+    )
+}
+
+/// Queue an event for export, if configured
+pub(crate) fn send(level: Level, event: &str, token: Option<&str>, fields: &[(String, String)]) {
+    if !enabled() {
+        return;
+    }
+    let record = record(SystemTime::now(), level, event, token, fields);
+    let mut queue = lock_queue();
+    if queue.records.len() >= MAX_QUEUED {
+        queue.dropped += 1;
+        return;
+    }
+    queue.records.push_back(record);
+    if queue.records.len() >= BATCH {
+        WAKE.notify_one();
+    }
+    if !queue.running {
+        start(&mut queue);
+    }
+}
+
+/// Start the sender thread
+fn start(queue: &mut Queue) {
+    let spawned = std::thread::Builder::new()
+        .name("awf-otlp".to_string())
+        .spawn(run);
+    match spawned {
+        Ok(_) => queue.running = true,
+        Err(err) => log_line!(
+            Warning,
+            "otlp",
+            None,
+            "Could not start OTLP exporter thread: {}",
+            err
+        ),
+    }
+}
+
+/// Sender thread: send a batch whenever one is full or a second has passed
+fn run() {
+    loop {
+        let queue = lock_queue();
+        let (queue, _) = WAKE
+            .wait_timeout_while(queue, INTERVAL, |queue| queue.records.len() < BATCH)
+            .unwrap_or_else(|err| err.into_inner());
+        drop(queue);
+        flush();
+    }
+}
+
+/// Send every queued record now
+///
+/// Also called at exit, for what the thread has not sent yet.
+pub(crate) fn flush() {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    loop {
+        let (batch, dropped) = {
+            let mut queue = lock_queue();
+            let count = queue.records.len().min(BATCH);
+            let batch: Vec<String> = queue.records.drain(..count).collect();
+            (batch, std::mem::take(&mut queue.dropped))
+        };
+        if dropped > 0 {
+            log_line!(
+                Warning,
+                "otlp",
+                None,
+                "Dropped {} OTLP records while the collector was unreachable",
+                dropped
+            );
+        }
+        if batch.is_empty() {
+            return;
+        }
+        let result = exporter.send(&batch);
+        let mut queue = lock_queue();
+        match result {
+            Ok(()) => queue.failed = false,
+            Err(err) if !queue.failed => {
+                queue.failed = true;
+                drop(queue);
+                log_line!(
+                    Warning,
+                    "otlp",
+                    None,
+                    "Could not export {} records to {}{}: {}",
+                    batch.len(),
+                    exporter.endpoint.authority,
+                    exporter.endpoint.path,
+                    err
+                );
+                return;
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+impl Exporter {
+    /// POST `records` to the collector
+    fn send(&self, records: &[String]) -> io::Result<()> {
+        let addr = self
+            .addr
+            .get_or_init(|| {
+                let mut addrs = self.endpoint.authority.to_socket_addrs().ok()?;
+                addrs.next()
+            })
+            .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+        let body = format!(
+            "{{\"resourceLogs\":[{{\"resource\":{{\"attributes\":[{},{{\"key\":\"process.pid\",\"value\":{{\"intValue\":\"{}\"}}}}]}},\"scopeLogs\":[{{\"scope\":{{\"name\":\"one-shot-token\",\"version\":\"{}\",\"attributes\":[{{\"key\":\"awf.schema_version\",\"value\":{{\"intValue\":\"{}\"}}}}]}},\"logRecords\":[{}]}}]}}]}}",
+            self.resource,
+            std::process::id(),
+            env!("CARGO_PKG_VERSION"),
+            crate::schema::SCHEMA_VERSION,
+            records.join(",")
+        );
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+            self.endpoint.path,
+            self.endpoint.authority,
+            body.len(),
+            self.headers,
+            body
+        );
+        match post(addr, request.as_bytes())? {
+            200..=299 => Ok(()),
+            status => Err(io::Error::other(format!("HTTP status {}", status))),
+        }
+    }
+}
+
+/// Send `request` to `addr` over a new connection and return the response
+/// status
+fn post(addr: SocketAddr, request: &[u8]) -> io::Result<u16> {
+    let family = if addr.is_ipv4() {
+        libc::AF_INET
+    } else {
+        libc::AF_INET6
+    };
+    let fd = sys::socket_cloexec(family, libc::SOCK_STREAM);
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd was just created and is owned here
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    let timeout = libc::timeval {
+        tv_sec: TIMEOUT.as_secs() as libc::time_t,
+        tv_usec: 0,
+    };
+    for option in [libc::SO_SNDTIMEO, libc::SO_RCVTIMEO] {
+        // SAFETY: timeout is a valid timeval of the given size
+        unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                option,
+                (&timeout as *const libc::timeval).cast(),
+                std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+    }
+
+    let (storage, len) = net::socket_addr_storage(addr);
+    // SAFETY: storage holds a socket address of len bytes
+    if unsafe {
+        net::call_real_connect(fd, (&storage as *const libc::sockaddr_storage).cast(), len)
+    } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    let mut sent = 0;
+    while sent < request.len() {
+        // SAFETY: the range is within request; no destination for a
+        // connected socket
+        let n = unsafe {
+            egress::call_real_sendto(
+                socket.as_raw_fd(),
+                request[sent..].as_ptr().cast(),
+                request.len() - sent,
+                libc::MSG_NOSIGNAL,
+                std::ptr::null(),
+                0,
+            )
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        sent += n as usize;
+    }
+
+    let mut response = [0u8; 64];
+    let mut received = 0;
+    while received < response.len() && !response[..received].contains(&b'\n') {
+        // SAFETY: the range is within response
+        let n = unsafe {
+            libc::read(
+                socket.as_raw_fd(),
+                response[received..].as_mut_ptr().cast(),
+                response.len() - received,
+            )
+        };
+        if n < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+            continue;
+        }
+        if n <= 0 {
+            break;
+        }
+        received += n as usize;
+    }
+    parse_status(&response[..received]).ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData))
+}
+
+/// Status code of an HTTP response starting with `response`
+fn parse_status(response: &[u8]) -> Option<u16> {
+    let line = std::str::from_utf8(response).ok()?.lines().next()?;
+    let mut parts = line.split(' ');
+    parts.next()?.strip_prefix("HTTP/")?;
+    parts.next()?.parse().ok()
+}
+
+thread_local! {
+    /// Queue lock held by the forking thread between prepare and parent/child
+    static FORK_GUARD: RefCell<Option<MutexGuard<'static, Queue>>> =
+        const { RefCell::new(None) };
+}
+
+/// Take the queue lock before fork, so the sender thread does not hold it
+pub(crate) fn prepare_fork() {
+    if enabled() {
+        let guard = lock_queue();
+        FORK_GUARD.with(|slot| *slot.borrow_mut() = Some(guard));
+    }
+}
+
+/// Release the queue lock in the parent after fork
+pub(crate) fn release_in_parent() {
+    FORK_GUARD.with(|slot| slot.borrow_mut().take());
+}
+
+/// Release the queue lock in the child, which starts with an empty queue
+/// and no sender thread; the parent sends what it had queued
+pub(crate) fn reset_in_child() {
+    if let Some(mut queue) = FORK_GUARD.with(|slot| slot.borrow_mut().take()) {
+        queue.records.clear();
+        queue.dropped = 0;
+        queue.running = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_endpoint_parse() {
+        assert_eq!(
+            Endpoint::parse("http://collector:4318"),
+            Some(Endpoint {
+                authority: "collector:4318".to_string(),
+                path: "/v1/logs".to_string()
+            })
+        );
+        assert_eq!(
+            Endpoint::parse("http://10.0.0.1/"),
+            Some(Endpoint {
+                authority: "10.0.0.1:4318".to_string(),
+                path: "/v1/logs".to_string()
+            })
+        );
+        assert_eq!(
+            Endpoint::parse("http://[::1]:9000/otlp/v1/logs"),
+            Some(Endpoint {
+                authority: "[::1]:9000".to_string(),
+                path: "/otlp/v1/logs".to_string()
+            })
+        );
+        assert_eq!(
+            Endpoint::parse("http://[::1]").map(|endpoint| endpoint.authority),
+            Some("[::1]:4318".to_string())
+        );
+        assert_eq!(Endpoint::parse("https://collector:4318"), None);
+        assert_eq!(Endpoint::parse("http:///v1/logs"), None);
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        assert_eq!(
+            parse_headers("x-api-key=secret, Authorization=Bearer abc"),
+            "x-api-key: secret\r\nAuthorization: Bearer abc\r\n"
+        );
+        assert_eq!(parse_headers("bad key=1,=2,novalue"), "");
+    }
+
+    #[test]
+    fn test_record() {
+        let time = UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789);
+        let fields = [("reads".to_string(), "1".to_string())];
+        // The run context (such as the cgroup) sits between the token and
+        // the event's own fields
+        let record = record(time, Level::Info, "access", Some("GITHUB_TOKEN"), &fields);
+        assert!(record.starts_with(concat!(
+            "{\"timeUnixNano\":\"1700000000123456789\",\"severityNumber\":9,",
+            "\"severityText\":\"INFO\",\"body\":{\"stringValue\":\"access\"},\"attributes\":[",
+            "{\"key\":\"awf.event\",\"value\":{\"stringValue\":\"access\"}},",
+            "{\"key\":\"awf.token\",\"value\":{\"stringValue\":\"GITHUB_TOKEN\"}},"
+        )));
+        assert!(record.ends_with("{\"key\":\"awf.reads\",\"value\":{\"stringValue\":\"1\"}}]}"));
+        assert_eq!(severity(Level::Audit, "denied", &[]), (13, "WARN"));
+        assert_eq!(severity(Level::Audit, "token_read", &[]), (9, "INFO"));
+    }
+
+    #[test]
+    fn test_post() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let collector = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 256];
+            while !request.ends_with(b"{}") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let request = "POST /v1/logs HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(post(addr, request.as_bytes()).unwrap(), 200);
+        assert_eq!(collector.join().unwrap(), request);
+        assert_eq!(
+            parse_status(b"HTTP/1.1 503 Service Unavailable\r\n"),
+            Some(503)
+        );
+        assert_eq!(parse_status(b"SSH-2.0"), None);
+    }
+}
@@ -0,0 +1,505 @@
+//! Per-token access policies
+//!
+//! Entries in AWF_ONE_SHOT_TOKENS may carry policy options after the token
+//! name, separated by colons:
+//!
+//!   GITHUB_TOKEN            - cache the real value (default)
+//!   GITHUB_TOKEN:redact     - serve a placeholder instead of the real value
+//!   GITHUB_TOKEN:max_reads=2 - serve the value at most twice, then NULL
+//!   GITHUB_TOKEN:ttl=30     - readable only during the first 30 seconds
+//!   GITHUB_TOKEN:expires_at=2026-10-17T12:00:00Z
+//!                           - readable only until this RFC 3339 instant,
+//!                           the expiry of the credential itself
+//!   GITHUB_TOKEN:refresh=3000 - replace the cached value with a fresh one
+//!                           every 3000 seconds (see the refresh module)
+//!   GITHUB_TOKEN:strict     - readable exactly once, then wiped
+//!   GITHUB_TOKEN:exe=/usr/bin/gh:exe=/usr/bin/git
+//!                           - served only to these executables
+//!   GITHUB_TOKEN:required   - must be set when the library loads (see the
+//!                           required module)
+//!   GITHUB_TOKEN:trim       - strip leading and trailing whitespace from
+//!                           the value when it is cached
+
+use crate::envname::EnvName;
+use crate::merge::Origin;
+use std::time::{Duration, SystemTime};
+
+/// Global enforcement mode (from AWF_ONE_SHOT_MODE)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Mode {
+    /// Apply all policies (default)
+    #[default]
+    Enforce,
+    /// Log would-be-protected accesses without unsetting or caching anything
+    Observe,
+}
+
+impl Mode {
+    /// Parse an AWF_ONE_SHOT_MODE value (case-insensitive)
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "enforce" => Some(Mode::Enforce),
+            "observe" => Some(Mode::Observe),
+            _ => None,
+        }
+    }
+
+    /// Name as accepted by parse
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Mode::Enforce => "enforce",
+            Mode::Observe => "observe",
+        }
+    }
+}
+
+/// How protected tokens are removed from the environment (from AWF_ONE_SHOT_SCRUB)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum ScrubMode {
+    /// Remove the variable with unsetenv (default)
+    #[default]
+    Unset,
+    /// Keep the variable but overwrite its value bytes with `*`
+    Mask,
+    /// Replace the entry with `NAME=***AWF-PROTECTED***`
+    Placeholder,
+}
+
+impl ScrubMode {
+    /// Parse an AWF_ONE_SHOT_SCRUB value (case-insensitive)
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "unset" => Some(ScrubMode::Unset),
+            "mask" => Some(ScrubMode::Mask),
+            "placeholder" => Some(ScrubMode::Placeholder),
+            _ => None,
+        }
+    }
+}
+
+/// Placeholder served for tokens with the `redact` policy
+pub(crate) const REDACTED_PLACEHOLDER: &str = "***AWF_REDACTED***";
+
+/// How reads of a protected token are served
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum TokenPolicy {
+    /// Cache the real value and serve it on every read
+    #[default]
+    Cache,
+    /// Serve a stable placeholder; the real value never reaches the caller
+    Redact,
+    /// Serve the real value exactly once; the copy is wiped on the next read
+    Strict,
+    /// Serve a value derived for the reading executable (see the derive
+    /// module); the real value never reaches the caller
+    Derive,
+    /// Serve a placeholder the swap proxy replaces with the real value (see
+    /// the swap module); the real value never reaches the caller
+    Placeholder,
+}
+
+/// A protected token name together with its policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TokenSpec {
+    pub(crate) name: EnvName,
+    pub(crate) policy: TokenPolicy,
+    /// Maximum number of successful reads before getenv returns NULL
+    pub(crate) max_reads: Option<u32>,
+    /// Seconds after library load during which the token may be read
+    pub(crate) ttl_secs: Option<u64>,
+    /// Wall-clock instant from which the token may no longer be read
+    pub(crate) expires_at: Option<SystemTime>,
+    /// Seconds after which a cached value is replaced with a fresh one
+    pub(crate) refresh_secs: Option<u64>,
+    /// Executables allowed to read the token (any if empty)
+    pub(crate) allowed_exes: Vec<String>,
+    /// Whether the token must be set when the library loads
+    pub(crate) required: bool,
+    /// Whether whitespace around the value is removed when it is cached
+    pub(crate) trim: bool,
+    /// The setting and source the rule came from (see the merge module)
+    pub(crate) origin: Origin,
+}
+
+impl TokenPolicy {
+    /// Short name used in log and audit output
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            TokenPolicy::Cache => "cache",
+            TokenPolicy::Redact => "redact",
+            TokenPolicy::Strict => "strict",
+            TokenPolicy::Derive => "derive",
+            TokenPolicy::Placeholder => "placeholder",
+        }
+    }
+}
+
+impl TokenSpec {
+    /// A token protected with the default policy
+    pub(crate) fn new(name: impl AsRef<[u8]>) -> Self {
+        Self {
+            name: EnvName::new(name),
+            policy: TokenPolicy::default(),
+            max_reads: None,
+            ttl_secs: None,
+            expires_at: None,
+            refresh_secs: None,
+            allowed_exes: Vec::new(),
+            required: false,
+            trim: false,
+            origin: Origin::BUILT_IN,
+        }
+    }
+
+    /// A token that another setting implies, protected with the default
+    /// policy
+    pub(crate) fn implied(name: impl AsRef<[u8]>, origin: Origin) -> Self {
+        Self {
+            origin,
+            ..Self::new(name)
+        }
+    }
+}
+
+/// Parse a single AWF_ONE_SHOT_TOKENS entry of the form `NAME[:option...]`
+///
+/// Returns the spec along with any options that were not recognized, so the
+/// caller can report them. Returns None if the name is empty. The name is
+/// taken as raw bytes (see the envname module).
+pub(crate) fn parse_token_spec(entry: impl AsRef<[u8]>) -> Option<(TokenSpec, Vec<String>)> {
+    let mut parts = entry
+        .as_ref()
+        .split(|&byte| byte == b':')
+        .map(<[u8]>::trim_ascii);
+    let name = parts.next().filter(|name| !name.is_empty())?;
+
+    let mut spec = TokenSpec::new(name);
+    let mut unknown = Vec::new();
+    let mut options = parts
+        .map(String::from_utf8_lossy)
+        .filter(|option| !option.is_empty())
+        .peekable();
+    while let Some(option) = options.next() {
+        let option = option.as_ref();
+        match option
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+        {
+            None if option == "redact" => spec.policy = TokenPolicy::Redact,
+            None if option == "strict" => spec.policy = TokenPolicy::Strict,
+            None if option == "derive" => spec.policy = TokenPolicy::Derive,
+            None if option == "placeholder" => spec.policy = TokenPolicy::Placeholder,
+            None if option == "required" => spec.required = true,
+            None if option == "trim" => spec.trim = true,
+            Some(("max_reads", value)) => match value.parse::<u32>() {
+                Ok(max_reads) if max_reads > 0 => spec.max_reads = Some(max_reads),
+                _ => unknown.push(option.to_string()),
+            },
+            Some(("ttl", value)) => match value.parse::<u64>() {
+                Ok(ttl_secs) => spec.ttl_secs = Some(ttl_secs),
+                Err(_) => unknown.push(option.to_string()),
+            },
+            Some(("expires_at", value)) => {
+                // The time of day holds the option separator: take the
+                // parts that follow until the timestamp is complete
+                let mut value = value.to_string();
+                while parse_expires_at(&value).is_none()
+                    && options
+                        .peek()
+                        .is_some_and(|next| next.starts_with(|c: char| c.is_ascii_digit()))
+                {
+                    value.push(':');
+                    value.push_str(&options.next().unwrap_or_default());
+                }
+                match parse_expires_at(&value) {
+                    Some(expires_at) => spec.expires_at = Some(expires_at),
+                    None => unknown.push(format!("expires_at={}", value)),
+                }
+            }
+            Some(("refresh", value)) => match value.parse::<u64>() {
+                Ok(refresh_secs) if refresh_secs > 0 => spec.refresh_secs = Some(refresh_secs),
+                _ => unknown.push(option.to_string()),
+            },
+            Some(("exe", value)) if value.starts_with('/') => {
+                spec.allowed_exes.push(value.to_string())
+            }
+            _ => unknown.push(option.to_string()),
+        }
+    }
+
+    Some((spec, unknown))
+}
+
+/// Days from 1970-01-01 to the given proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The date of a day counted from 1970-01-01 (inverse of days_from_civil)
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Parse an `expires_at` value, an RFC 3339 date and time with an offset
+/// (`2026-10-17T12:00:00Z`, `2026-10-17T14:00:00.5+02:00`)
+pub(crate) fn parse_expires_at(value: &str) -> Option<SystemTime> {
+    let value = value.trim().as_bytes();
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = value.get(range)?;
+        if !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        std::str::from_utf8(digits).ok()?.parse().ok()
+    };
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if value.len() < 20
+        || separators.iter().any(|&(at, byte)| value[at] != byte)
+        || !matches!(value[10], b'T' | b't' | b' ')
+    {
+        return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    let days_in_month = match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    // A leap second is taken as the last second of its minute
+    if day < 1 || day > days_in_month || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let mut rest = &value[19..];
+    let mut nanos = 0u32;
+    if let Some(fraction) = rest.strip_prefix(b".") {
+        let len = fraction.iter().take_while(|b| b.is_ascii_digit()).count();
+        if len == 0 {
+            return None;
+        }
+        for (place, digit) in fraction[..len.min(9)].iter().enumerate() {
+            nanos += u32::from(digit - b'0') * 10u32.pow(8 - place as u32);
+        }
+        rest = &fraction[len..];
+    }
+    let offset = match rest {
+        b"Z" | b"z" => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let start = value.len() - 5;
+            let (hours, minutes) = (number(start..start + 2)?, number(start + 3..start + 5)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            let offset = hours * 3600 + minutes * 60;
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => return None,
+    };
+
+    let secs =
+        days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second.min(59)
+            - offset;
+    let secs = u64::try_from(secs).ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+/// Format a wall-clock time as an RFC 3339 UTC timestamp, to the second
+pub(crate) fn format_expires_at(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let second_of_day = secs.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_parse() {
+        assert_eq!(Mode::parse("observe"), Some(Mode::Observe));
+        assert_eq!(Mode::parse(" ENFORCE "), Some(Mode::Enforce));
+        assert_eq!(Mode::parse("dry-run"), None);
+    }
+
+    #[test]
+    fn test_scrub_mode_parse() {
+        assert_eq!(ScrubMode::parse("Mask"), Some(ScrubMode::Mask));
+        assert_eq!(ScrubMode::parse("unset"), Some(ScrubMode::Unset));
+        assert_eq!(
+            ScrubMode::parse(" Placeholder "),
+            Some(ScrubMode::Placeholder)
+        );
+        assert_eq!(ScrubMode::parse("zero"), None);
+    }
+
+    #[test]
+    fn test_parse_token_spec_plain_name() {
+        let (spec, unknown) = parse_token_spec(" GITHUB_TOKEN ").unwrap();
+        assert_eq!(spec, TokenSpec::new("GITHUB_TOKEN"));
+        assert!(unknown.is_empty());
+    }
+
+    #[test]
+    fn test_parse_token_spec_non_utf8_name() {
+        let (spec, unknown) = parse_token_spec(b"TOKEN_\xff:redact").unwrap();
+        assert_eq!(spec.name.as_bytes(), b"TOKEN_\xff");
+        assert_eq!(spec.policy, TokenPolicy::Redact);
+        assert!(unknown.is_empty());
+    }
+
+    #[test]
+    fn test_parse_token_spec_options() {
+        let (spec, unknown) = parse_token_spec("GITHUB_TOKEN: redact :bogus").unwrap();
+        assert_eq!(spec.name, "GITHUB_TOKEN");
+        assert_eq!(spec.policy, TokenPolicy::Redact);
+        assert_eq!(unknown, vec!["bogus"]);
+
+        let (spec, _) = parse_token_spec("GITHUB_TOKEN:strict").unwrap();
+        assert_eq!(spec.policy, TokenPolicy::Strict);
+
+        let (spec, _) = parse_token_spec("GITHUB_TOKEN:derive").unwrap();
+        assert_eq!(spec.policy, TokenPolicy::Derive);
+
+        let (spec, _) = parse_token_spec("GITHUB_TOKEN:placeholder").unwrap();
+        assert_eq!(spec.policy, TokenPolicy::Placeholder);
+
+        let (spec, _) = parse_token_spec("GITHUB_TOKEN:required:redact:trim").unwrap();
+        assert!(spec.required && spec.trim);
+        assert_eq!(spec.policy, TokenPolicy::Redact);
+
+        assert!(parse_token_spec(":redact").is_none());
+    }
+
+    #[test]
+    fn test_parse_token_spec_max_reads() {
+        let (spec, unknown) = parse_token_spec("GITHUB_TOKEN:max_reads=2").unwrap();
+        assert_eq!(spec.max_reads, Some(2));
+        assert!(unknown.is_empty());
+
+        let (spec, unknown) = parse_token_spec("GITHUB_TOKEN:max_reads=0:max_reads=x").unwrap();
+        assert_eq!(spec.max_reads, None);
+        assert_eq!(unknown, vec!["max_reads=0", "max_reads=x"]);
+    }
+
+    #[test]
+    fn test_parse_token_spec_ttl() {
+        let (spec, unknown) = parse_token_spec("GITHUB_TOKEN:ttl=30:max_reads=1").unwrap();
+        assert_eq!(spec.ttl_secs, Some(30));
+        assert_eq!(spec.max_reads, Some(1));
+        assert!(unknown.is_empty());
+
+        let (spec, unknown) = parse_token_spec("GITHUB_TOKEN:ttl=-1").unwrap();
+        assert_eq!(spec.ttl_secs, None);
+        assert_eq!(unknown, vec!["ttl=-1"]);
+        assert!(parse_token_spec("  ").is_none());
+    }
+
+    #[test]
+    fn test_parse_token_spec_expires_at() {
+        let (spec, unknown) =
+            parse_token_spec("GITHUB_TOKEN:expires_at=2026-10-17T12:00:00Z:max_reads=1").unwrap();
+        assert_eq!(spec.expires_at, parse_expires_at("2026-10-17T12:00:00Z"));
+        assert_eq!(spec.max_reads, Some(1));
+        assert!(unknown.is_empty());
+
+        let (spec, _) =
+            parse_token_spec("GITHUB_TOKEN:expires_at=2026-10-17T14:00:00+02:00").unwrap();
+        assert_eq!(spec.expires_at, parse_expires_at("2026-10-17T12:00:00Z"));
+
+        let (spec, unknown) =
+            parse_token_spec("GITHUB_TOKEN:expires_at=2026-10-17T12:00:00:strict").unwrap();
+        assert_eq!(spec.expires_at, None);
+        assert_eq!(spec.policy, TokenPolicy::Strict);
+        assert_eq!(unknown, vec!["expires_at=2026-10-17T12:00:00"]);
+    }
+
+    #[test]
+    fn test_parse_expires_at() {
+        let secs = |value: &str| {
+            parse_expires_at(value).map(|time| time.duration_since(SystemTime::UNIX_EPOCH).unwrap())
+        };
+        assert_eq!(secs("1970-01-01T00:00:00Z"), Some(Duration::ZERO));
+        assert_eq!(
+            secs("2026-10-17T12:00:00Z"),
+            Some(Duration::from_secs(1_792_238_400))
+        );
+        assert_eq!(
+            secs("2024-02-29t01:30:00.25-00:30"),
+            Some(Duration::new(1_709_172_000, 250_000_000))
+        );
+        for invalid in [
+            "2026-10-17T12:00:00",
+            "2026-10-17",
+            "2026-02-29T00:00:00Z",
+            "2026-13-01T00:00:00Z",
+            "2026-10-17T24:00:00Z",
+            "2026-10-17T12:00:00.Z",
+            "2026-10-17T12:00:00+2:00",
+            "1969-12-31T23:59:59Z",
+            "1792238400",
+        ] {
+            assert_eq!(parse_expires_at(invalid), None, "{}", invalid);
+        }
+
+        let time = parse_expires_at("2026-10-17T14:30:05+02:00").unwrap();
+        assert_eq!(format_expires_at(time), "2026-10-17T12:30:05Z");
+        let time = parse_expires_at("2000-03-01T00:00:00Z").unwrap();
+        assert_eq!(format_expires_at(time), "2000-03-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_parse_token_spec_refresh() {
+        let (spec, unknown) = parse_token_spec("GITHUB_TOKEN:refresh=3000").unwrap();
+        assert_eq!(spec.refresh_secs, Some(3000));
+        assert!(unknown.is_empty());
+
+        let (spec, unknown) = parse_token_spec("GITHUB_TOKEN:refresh=0:refresh=1h").unwrap();
+        assert_eq!(spec.refresh_secs, None);
+        assert_eq!(unknown, vec!["refresh=0", "refresh=1h"]);
+    }
+
+    #[test]
+    fn test_parse_token_spec_exe() {
+        let (spec, unknown) =
+            parse_token_spec("GITHUB_TOKEN:exe=/usr/bin/gh:exe=/usr/bin/git:exe=gh").unwrap();
+        assert_eq!(spec.allowed_exes, vec!["/usr/bin/gh", "/usr/bin/git"]);
+        assert_eq!(unknown, vec!["exe=gh"]);
+    }
+}
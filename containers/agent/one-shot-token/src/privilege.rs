@@ -0,0 +1,224 @@
+//! Wiping the cache when the process changes credentials
+//! (AWF_ONE_SHOT_PRIVILEGE_WATCH)
+//!
+//! secure_getenv refuses values in set-user-ID programs, but decides once, at
+//! exec (AT_SECURE). A process that gains privileges later (a setuid() call
+//! after a capability was granted, a helper that escalates) or drops them
+//! (a daemon switching to an unprivileged user) would keep serving the
+//! tokens it cached under its old identity.
+//!
+//! The real and effective user and group IDs are recorded when the library
+//! is loaded, and compared before every read of a watched name. On the first
+//! difference every cached value is zeroized, tokens not read yet are
+//! scrubbed from the environment, and a `privilege_change` audit event
+//! reports the old and new IDs, including whether the process now runs like
+//! a set-user-ID program (`secure=`, real and effective IDs differ). From
+//! then on reads of protected tokens return NULL with a `privilege_change`
+//! audit event, even if the host sets a token again, and switching back does
+//! not undo the change. In observe mode the change is only reported.
+//!
+//! The comparison only reads the IDs, so it also runs on the lock-free path
+//! (see the snapshot module).
+
+use crate::{
+    audit, egress, protection_disabled, read_config_flag, wipe_every_token, Mode, TokenState,
+};
+use libc::{gid_t, uid_t};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+/// Why reads are refused once the credentials changed
+pub(crate) const REASON: &str = "privilege_change";
+
+/// Credentials of a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Ids {
+    uid: uid_t,
+    euid: uid_t,
+    gid: gid_t,
+    egid: gid_t,
+}
+
+impl Ids {
+    fn current() -> Self {
+        // SAFETY: these calls have no preconditions and cannot fail
+        unsafe {
+            Ids {
+                uid: libc::getuid(),
+                euid: libc::geteuid(),
+                gid: libc::getgid(),
+                egid: libc::getegid(),
+            }
+        }
+    }
+
+    /// Whether the process runs like a set-user-ID or set-group-ID program
+    fn secure(&self) -> bool {
+        self.uid != self.euid || self.gid != self.egid
+    }
+}
+
+/// Credentials at load, with whether the kernel set AT_SECURE
+static BASELINE: OnceLock<(Ids, bool)> = OnceLock::new();
+
+/// Whether the credentials are compared (set once the state is initialized)
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Set once a change was seen; never cleared
+static CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// Set once the change was reported
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Record the credentials the process was loaded with
+pub(crate) fn record() {
+    BASELINE.get_or_init(|| (Ids::current(), at_secure()));
+}
+
+/// Enable the comparison if AWF_ONE_SHOT_PRIVILEGE_WATCH allows it
+/// (default: on)
+pub(crate) fn load() {
+    record();
+    let enabled = !protection_disabled() && read_config_flag(c"AWF_ONE_SHOT_PRIVILEGE_WATCH", true);
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the credentials changed since load
+///
+/// Async-signal-safe: only reads the IDs and atomics.
+pub(crate) fn changed() -> bool {
+    if CHANGED.load(Ordering::Relaxed) {
+        return true;
+    }
+    if !ENABLED.load(Ordering::Relaxed) {
+        return false;
+    }
+    let Some((baseline, _)) = BASELINE.get() else {
+        return false;
+    };
+    if Ids::current() == *baseline {
+        return false;
+    }
+    CHANGED.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Zeroize every token after a credential change, reporting the change the
+/// first time
+///
+/// Called with the state lock held whenever changed() is true, so tokens the
+/// host sets again are wiped before they are read.
+pub(crate) fn wipe(state: &mut TokenState) {
+    let first = !REPORTED.swap(true, Ordering::Relaxed);
+    let Some(&(baseline, secure)) = BASELINE.get() else {
+        return;
+    };
+    if state.mode == Mode::Observe {
+        if first {
+            let detail = format!("would=wipe {}", describe(baseline, secure, Ids::current()));
+            audit::emit("observed", "*", &detail);
+        }
+        return;
+    }
+    let wiped = wipe_every_token(state, REASON);
+    if first {
+        let detail = format!(
+            "severity=high {} wiped={}",
+            describe(baseline, secure, Ids::current()),
+            wiped
+        );
+        audit::emit(REASON, "*", &detail);
+    }
+    if wiped > 0 {
+        egress::publish_secrets(state);
+    }
+}
+
+/// Audit detail of a change from `from` (loaded with AT_SECURE `secure`) to
+/// `to`, listing the IDs that changed
+fn describe(from: Ids, secure: bool, to: Ids) -> String {
+    let mut detail = Vec::new();
+    for (name, from, to) in [
+        ("uid", from.uid, to.uid),
+        ("euid", from.euid, to.euid),
+        ("gid", from.gid, to.gid),
+        ("egid", from.egid, to.egid),
+    ] {
+        if from != to {
+            detail.push(format!("{}={}->{}", name, from, to));
+        }
+    }
+    let was_secure = secure || from.secure();
+    if was_secure != to.secure() {
+        detail.push(format!("secure={}->{}", was_secure, to.secure()));
+    }
+    detail.join(" ")
+}
+
+/// Whether the kernel started the process in secure-execution mode
+fn at_secure() -> bool {
+    #[cfg(target_os = "linux")]
+    // SAFETY: getauxval has no preconditions
+    return unsafe { libc::getauxval(libc::AT_SECURE) } != 0;
+    #[cfg(target_os = "macos")]
+    // SAFETY: issetugid has no preconditions
+    return unsafe { libc::issetugid() } != 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envname::EnvName;
+    use crate::CachedToken;
+
+    fn ids(uid: uid_t, euid: uid_t, gid: gid_t, egid: gid_t) -> Ids {
+        Ids {
+            uid,
+            euid,
+            gid,
+            egid,
+        }
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            describe(ids(1000, 1000, 100, 100), false, ids(1000, 0, 100, 100)),
+            "euid=1000->0 secure=false->true"
+        );
+        assert_eq!(
+            describe(ids(0, 0, 0, 0), false, ids(1000, 1000, 100, 100)),
+            "uid=0->1000 euid=0->1000 gid=0->100 egid=0->100"
+        );
+        // A set-user-ID program dropping its privileges
+        assert_eq!(
+            describe(ids(1000, 0, 100, 100), true, ids(1000, 1000, 100, 100)),
+            "euid=0->1000 secure=true->false"
+        );
+        assert_eq!(
+            describe(ids(1000, 1000, 100, 100), true, ids(1000, 1000, 100, 101)),
+            "egid=100->101"
+        );
+    }
+
+    #[test]
+    fn test_wipe() {
+        record();
+        let mut state = TokenState::new();
+        for name in ["AWF_TEST_PRIV_CACHED", "AWF_TEST_PRIV_UNREAD"] {
+            let (spec, _) = crate::policy::parse_token_spec(name).unwrap();
+            state.tokens.push(spec);
+        }
+        let mut value = *b"ghp_priv\0";
+        state.cache.insert(
+            "AWF_TEST_PRIV_CACHED".into(),
+            CachedToken::cached(value.as_mut_ptr().cast()),
+        );
+
+        wipe(&mut state);
+        assert_eq!(value, [0u8; 9]);
+        for name in ["AWF_TEST_PRIV_CACHED", "AWF_TEST_PRIV_UNREAD"] {
+            assert_eq!(state.cache[&EnvName::from(name)].wiped, Some(REASON));
+        }
+    }
+}
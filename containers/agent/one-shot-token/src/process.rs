@@ -0,0 +1,228 @@
+//! Identity of the current process (command name and executable path) and
+//! of its parent, for policies that restrict which programs may read tokens
+//!
+//! The identity is read once, when the state is initialized at load, so a
+//! program cannot rename itself (for example with prctl(PR_SET_NAME)) into an
+//! allowed identity afterwards. An exec loads the library afresh and
+//! re-reads it.
+//!
+//! macOS has no /proc; there the identity comes from libproc (see the macos
+//! module).
+
+use crate::credfile::FilePolicy;
+#[cfg(target_os = "macos")]
+use crate::macos;
+#[cfg(not(target_os = "macos"))]
+use crate::procfs;
+#[cfg(not(target_os = "macos"))]
+use std::ffi::CString;
+
+/// Command name of this process from /proc/self/comm
+///
+/// The kernel truncates it to 15 bytes.
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn comm() -> Option<String> {
+    read_comm(c"/proc/self/comm")
+}
+
+/// Command name of this process
+#[cfg(target_os = "macos")]
+pub(crate) fn comm() -> Option<String> {
+    // SAFETY: getpid has no preconditions
+    macos::process_name(unsafe { libc::getpid() })
+}
+
+#[cfg(not(target_os = "macos"))]
+fn read_comm(path: &std::ffi::CStr) -> Option<String> {
+    // SAFETY: path is a valid C string
+    let content = unsafe { procfs::read_file(path) }?;
+    let comm = String::from_utf8_lossy(&content);
+    Some(comm.trim_end_matches('\n').to_string())
+}
+
+/// The process that started this one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Parent {
+    pub(crate) pid: libc::pid_t,
+    /// Command name, empty if it could not be read
+    pub(crate) comm: String,
+    /// Executable path, empty if it could not be read (e.g. another user's
+    /// process)
+    pub(crate) exe: String,
+}
+
+impl Parent {
+    /// Identify the parent process
+    pub(crate) fn current() -> Self {
+        // SAFETY: getppid has no preconditions
+        let pid = unsafe { libc::getppid() };
+        #[cfg(target_os = "macos")]
+        let (comm, exe) = (macos::process_name(pid), macos::process_path(pid));
+        #[cfg(not(target_os = "macos"))]
+        let (comm, exe) = (
+            CString::new(format!("/proc/{}/comm", pid))
+                .ok()
+                .and_then(|path| read_comm(&path)),
+            std::fs::read_link(format!("/proc/{}/exe", pid))
+                .ok()
+                .and_then(|exe| exe.to_str().map(str::to_string)),
+        );
+        Parent {
+            pid,
+            comm: comm.unwrap_or_default(),
+            exe: exe.unwrap_or_default(),
+        }
+    }
+
+    /// Audit event detail describing the parent
+    pub(crate) fn describe(&self) -> String {
+        format!(
+            "parent={} ppid={} parent_exe={}",
+            self.comm, self.pid, self.exe
+        )
+    }
+}
+
+/// Per-parent policies (AWF_ONE_SHOT_PARENT_POLICY)
+///
+/// Entries are `PARENT:POLICY`, where PARENT is a command name or, if it
+/// starts with `/`, an executable path; `*` sets the default, which is
+/// otherwise `allow`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ParentPolicy {
+    rules: Vec<(String, FilePolicy)>,
+    default: FilePolicy,
+}
+
+impl ParentPolicy {
+    /// Parse `PARENT:POLICY` entries; entries with an unknown policy are ignored
+    /// and executable paths have their symlinks resolved
+    pub(crate) fn parse(config: &str) -> Self {
+        let mut policy = ParentPolicy {
+            rules: Vec::new(),
+            default: FilePolicy::Allow,
+        };
+        let entries = config
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty());
+        for entry in entries {
+            let Some((parent, rule)) = entry.rsplit_once(':') else {
+                continue;
+            };
+            let (parent, Some(rule)) = (parent.trim(), FilePolicy::parse(rule.trim())) else {
+                continue;
+            };
+            match parent {
+                "" => {}
+                "*" => policy.default = rule,
+                parent if parent.starts_with('/') => {
+                    policy.rules.push((canonical_exe(parent), rule))
+                }
+                parent => policy.rules.push((parent.to_string(), rule)),
+            }
+        }
+        policy
+    }
+
+    /// Policy for the given parent
+    pub(crate) fn for_parent(&self, parent: &Parent) -> FilePolicy {
+        self.rules
+            .iter()
+            .find(|(entry, _)| {
+                if entry.starts_with('/') {
+                    *entry == parent.exe
+                } else {
+                    comm_allowed(&parent.comm, std::slice::from_ref(entry))
+                }
+            })
+            .map(|(_, rule)| *rule)
+            .unwrap_or(self.default)
+    }
+}
+
+/// Check a command name against AWF_ONE_SHOT_ALLOWED_COMMS entries
+///
+/// Entries longer than the kernel's 15-byte limit match the truncated name.
+pub(crate) fn comm_allowed(comm: &str, allowed: &[String]) -> bool {
+    allowed
+        .iter()
+        .any(|entry| entry == comm || (comm.len() == 15 && entry.starts_with(comm)))
+}
+
+/// Canonical path of this process's executable from /proc/self/exe
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn exe() -> Option<String> {
+    let exe = std::fs::read_link("/proc/self/exe").ok()?;
+    exe.to_str().map(str::to_string)
+}
+
+/// Canonical path of this process's executable
+#[cfg(target_os = "macos")]
+pub(crate) fn exe() -> Option<String> {
+    // SAFETY: getpid has no preconditions
+    macos::process_path(unsafe { libc::getpid() })
+}
+
+/// Resolve symlinks in a configured executable path
+///
+/// Paths that cannot be resolved (for example because the file does not
+/// exist) are kept as given.
+pub(crate) fn canonical_exe(path: &str) -> String {
+    std::fs::canonicalize(path)
+        .ok()
+        .and_then(|path| path.to_str().map(str::to_string))
+        .unwrap_or_else(|| path.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comm_allowed() {
+        let allowed = vec!["git".to_string(), "copilot-language-server".to_string()];
+        assert!(comm_allowed("git", &allowed));
+        assert!(!comm_allowed("gitk", &allowed));
+        assert!(comm_allowed("copilot-languag", &allowed));
+        assert!(!comm_allowed("copilot", &allowed));
+    }
+
+    #[test]
+    fn test_exe() {
+        let exe = exe().unwrap();
+        assert_eq!(canonical_exe(&exe), exe);
+        assert_eq!(canonical_exe("/nonexistent/bin"), "/nonexistent/bin");
+    }
+
+    #[test]
+    fn test_parent_policy() {
+        let policy = ParentPolicy::parse("bash:deny, /usr/bin/sh:log, *:log, x:bogus");
+        let parent = |comm: &str, exe: &str| Parent {
+            pid: 1,
+            comm: comm.to_string(),
+            exe: exe.to_string(),
+        };
+        assert_eq!(
+            policy.for_parent(&parent("bash", "/usr/bin/bash")),
+            FilePolicy::Deny
+        );
+        assert_eq!(
+            policy.for_parent(&parent("supervisor", "")),
+            FilePolicy::Log
+        );
+        assert_eq!(
+            ParentPolicy::parse("").for_parent(&parent("bash", "")),
+            FilePolicy::Allow
+        );
+
+        let current = Parent::current();
+        assert!(current.pid > 0);
+    }
+
+    #[test]
+    fn test_comm() {
+        let comm = comm().unwrap();
+        assert!(!comm.is_empty() && !comm.ends_with('\n'));
+    }
+}
@@ -0,0 +1,316 @@
+//! Redacted views of /proc/<pid>/environ and detection of snooping
+//!
+//! Scrubbing only changes the live environment of this process. The
+//! `environ` file of another process in the container still shows that
+//! process's environment, and a process that never read its tokens still has
+//! them there. Opens of any `/proc/<pid>/environ` path are therefore
+//! intercepted (see the open module): the real file is read by the library and the caller receives
+//! a memfd holding a copy in which protected entries carry the redaction
+//! placeholder, so the original contents never reach the caller.
+//!
+//! Opening the environ or cmdline file of another process is how an agent
+//! scrapes sibling processes for secrets, so such opens are also reported
+//! with a `proc_snoop` audit event, or refused (AWF_ONE_SHOT_PROC_SNOOP).
+//!
+//! Only absolute paths are recognized; opens relative to a directory file
+//! descriptor or to a working directory inside /proc are passed through.
+
+use crate::credfile::FilePolicy;
+use crate::envname::EnvName;
+use crate::exec::{sanitize_entry, scrubbed_names, split_entry, EntryAction};
+use crate::{audit, lock_state, open, protected_token_values, sys, Mode, REDACTED_PLACEHOLDER};
+use libc::c_int;
+use std::ffi::{CStr, CString};
+
+/// Files of other processes whose opens are reported as snooping
+const SNOOPED_FILES: &[&[u8]] = &[b"environ", b"cmdline"];
+
+/// The process a /proc path refers to
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ProcOwner {
+    /// `/proc/self` or `/proc/thread-self`
+    Current,
+    /// `/proc/<pid>` (which may still be this process)
+    Pid(libc::pid_t),
+}
+
+/// Split a `/proc/<pid>/<file>` or `/proc/<pid>/task/<tid>/<file>` path
+///
+/// Returns the owning process and the file name, or None for any other path.
+pub(crate) fn parse_proc_path(path: &[u8]) -> Option<(ProcOwner, &[u8])> {
+    let rest = path.strip_prefix(b"/proc/")?;
+    let parts: Vec<&[u8]> = rest
+        .split(|&b| b == b'/')
+        .filter(|part| !part.is_empty() && *part != b".")
+        .collect();
+    let (owner, file) = match parts.as_slice() {
+        [owner, file] => (*owner, *file),
+        [owner, b"task", tid, file] if is_decimal(tid) => (*owner, *file),
+        _ => return None,
+    };
+
+    let owner = match owner {
+        b"self" | b"thread-self" => ProcOwner::Current,
+        pid if is_decimal(pid) => ProcOwner::Pid(std::str::from_utf8(pid).ok()?.parse().ok()?),
+        _ => return None,
+    };
+    Some((owner, file))
+}
+
+fn is_decimal(part: &[u8]) -> bool {
+    !part.is_empty() && part.iter().all(u8::is_ascii_digit)
+}
+
+/// Check whether `pid` is neither this process nor one of its threads
+pub(crate) fn is_other_process(pid: libc::pid_t) -> bool {
+    // SAFETY: getpid has no preconditions
+    if pid == unsafe { libc::getpid() } {
+        return false;
+    }
+    let task = CString::new(format!("/proc/self/task/{}", pid)).unwrap();
+    // SAFETY: task is a valid C string
+    unsafe { libc::access(task.as_ptr(), libc::F_OK) != 0 }
+}
+
+/// Apply the snooping policy to an open of `path`
+///
+/// Returns false if the open must fail with EACCES.
+pub(crate) fn check_snooping(path: &CStr, via: &str) -> bool {
+    let Some((ProcOwner::Pid(pid), file)) = parse_proc_path(path.to_bytes()) else {
+        return true;
+    };
+    if !SNOOPED_FILES.contains(&file) || !is_other_process(pid) {
+        return true;
+    }
+
+    let state = lock_state();
+    let detail = format!(
+        "pid={} file={} via={}",
+        pid,
+        String::from_utf8_lossy(file),
+        via
+    );
+    match state.proc_snoop {
+        FilePolicy::Allow => true,
+        FilePolicy::Log => {
+            audit::emit("proc_snoop", "*", &format!("{} action=logged", detail));
+            true
+        }
+        FilePolicy::Deny if state.mode == Mode::Observe => {
+            audit::emit(
+                "observed",
+                "*",
+                &format!("would=deny_proc_snoop {}", detail),
+            );
+            true
+        }
+        FilePolicy::Deny => {
+            audit::emit("proc_snoop", "*", &format!("{} action=denied", detail));
+            false
+        }
+    }
+}
+
+/// Rewrite the contents of an environ file
+///
+/// Protected and deny-listed entries keep their name but get the placeholder
+/// as value; other entries have protected values replaced. Returns the new
+/// contents and the number of entries changed.
+fn redact_environ(content: &[u8], names: &[EnvName], known: &[&[u8]]) -> (Vec<u8>, usize) {
+    let mut redacted = Vec::with_capacity(content.len());
+    let mut changed = 0;
+    for entry in content.split(|&b| b == 0).filter(|entry| !entry.is_empty()) {
+        match sanitize_entry(entry, names, known) {
+            EntryAction::Keep => redacted.extend_from_slice(entry),
+            EntryAction::Remove => {
+                redacted.extend_from_slice(split_entry(entry).0);
+                redacted.push(b'=');
+                redacted.extend_from_slice(REDACTED_PLACEHOLDER.as_bytes());
+                changed += 1;
+            }
+            EntryAction::Redact(entry) => {
+                redacted.extend_from_slice(&entry);
+                changed += 1;
+            }
+        }
+        redacted.push(0);
+    }
+    (redacted, changed)
+}
+
+/// Read a whole file through the real open
+///
+/// # Safety
+/// `path` must be a valid null-terminated C string
+pub(crate) unsafe fn read_file(path: &CStr) -> Option<Vec<u8>> {
+    let fd = open::call_real_open(path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC);
+    if fd < 0 {
+        return None;
+    }
+    let content = read_fd(fd);
+    libc::close(fd);
+    content
+}
+
+/// Read from `fd` until end of file
+///
+/// # Safety
+/// `fd` must be an open descriptor
+pub(crate) unsafe fn read_fd(fd: c_int) -> Option<Vec<u8>> {
+    let mut content = Vec::new();
+    let mut buf = [0u8; 4096];
+    let complete = loop {
+        let n = libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len());
+        match n {
+            0 => break true,
+            n if n > 0 => content.extend_from_slice(&buf[..n as usize]),
+            _ if *sys::errno_location() == libc::EINTR => continue,
+            _ => break false,
+        }
+    };
+    complete.then_some(content)
+}
+
+/// Create a memfd holding `content`, positioned at the start
+///
+/// # Safety
+/// Only performs system calls on the descriptor it creates; marked unsafe
+/// because of the raw libc calls
+#[cfg(target_os = "linux")]
+unsafe fn memfd_with(content: &[u8], flags: c_int) -> Option<c_int> {
+    let memfd_flags = if flags & libc::O_CLOEXEC != 0 {
+        libc::MFD_CLOEXEC
+    } else {
+        0
+    };
+    let fd = libc::memfd_create(c"awf-environ".as_ptr(), memfd_flags);
+    if fd < 0 {
+        return None;
+    }
+    let mut written = 0;
+    while written < content.len() {
+        let n = libc::write(
+            fd,
+            content[written..].as_ptr() as *const libc::c_void,
+            content.len() - written,
+        );
+        if n <= 0 {
+            libc::close(fd);
+            return None;
+        }
+        written += n as usize;
+    }
+    libc::lseek(fd, 0, libc::SEEK_SET);
+    Some(fd)
+}
+
+/// There is no /proc, and no memfd, on macOS: the open fails instead
+#[cfg(not(target_os = "linux"))]
+unsafe fn memfd_with(_content: &[u8], _flags: c_int) -> Option<c_int> {
+    None
+}
+
+/// Serve a redacted copy of an environ file
+///
+/// Returns the file descriptor (or -1 with errno set) to hand back to the
+/// caller, or None if the open should be passed through.
+///
+/// # Safety
+/// Performs raw system calls; `flags` must be the caller's open flags
+pub(crate) unsafe fn intercept_open(path: &CStr, flags: c_int, via: &str) -> Option<c_int> {
+    if !matches!(parse_proc_path(path.to_bytes()), Some((_, b"environ"))) {
+        return None;
+    }
+    // environ files are read-only; let the kernel reject write opens
+    if flags & libc::O_ACCMODE != libc::O_RDONLY {
+        return None;
+    }
+
+    let state = lock_state();
+    if !state.redact_proc_environ {
+        return None;
+    }
+    let path_str = path.to_string_lossy();
+    if state.mode == Mode::Observe {
+        audit::emit(
+            "observed",
+            "*",
+            &format!("would=redact_environ path={} via={}", path_str, via),
+        );
+        return None;
+    }
+
+    let values = protected_token_values(&state);
+    let known: Vec<&[u8]> = values.iter().map(|value| value.as_bytes()).collect();
+    let names = scrubbed_names(&state);
+
+    // Never fall back to the original: on failure the open fails
+    let Some(content) = read_file(path) else {
+        return Some(-1);
+    };
+    let (redacted, changed) = redact_environ(&content, &names, &known);
+    let Some(fd) = memfd_with(&redacted, flags) else {
+        *sys::errno_location() = libc::EACCES;
+        return Some(-1);
+    };
+
+    if state.debug_enabled {
+        log_line!(
+            Debug,
+            "proc_environ",
+            None,
+            "Serving redacted {} via {} ({} entries redacted)",
+            path_str,
+            via,
+            changed
+        );
+    }
+    Some(fd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_path() {
+        assert_eq!(
+            parse_proc_path(b"/proc/self/environ"),
+            Some((ProcOwner::Current, b"environ".as_slice()))
+        );
+        assert_eq!(
+            parse_proc_path(b"/proc/42//task/43/environ"),
+            Some((ProcOwner::Pid(42), b"environ".as_slice()))
+        );
+        assert_eq!(
+            parse_proc_path(b"/proc/1/cmdline"),
+            Some((ProcOwner::Pid(1), b"cmdline".as_slice()))
+        );
+        assert_eq!(parse_proc_path(b"/proc/meminfo"), None);
+        assert_eq!(parse_proc_path(b"/proc/self/fd/3"), None);
+        assert_eq!(parse_proc_path(b"/tmp/proc/1/environ"), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_is_other_process() {
+        let pid = unsafe { libc::getpid() };
+        assert!(!is_other_process(pid));
+        let tid = unsafe { libc::gettid() };
+        assert!(!is_other_process(tid));
+        assert!(is_other_process(1) || pid == 1);
+    }
+
+    #[test]
+    fn test_redact_environ() {
+        let names = vec![EnvName::from("GITHUB_TOKEN")];
+        let known: &[&[u8]] = &[b"ghp_secretvalue"];
+        let content = b"PATH=/bin\0GITHUB_TOKEN=ghp_secretvalue\0URL=https://ghp_secretvalue@x\0";
+        let (redacted, changed) = redact_environ(content, &names, known);
+        assert_eq!(
+            redacted,
+            b"PATH=/bin\0GITHUB_TOKEN=***AWF_REDACTED***\0URL=https://***AWF_REDACTED***@x\0"
+        );
+        assert_eq!(changed, 2);
+    }
+}
@@ -0,0 +1,223 @@
+//! Denial of access to the memory of other processes
+//!
+//! Scrubbing and redaction protect the environment, but a token cached by a
+//! sibling process (or still in the environment of one that never read it)
+//! can be read straight out of that process's memory. The ptrace libc
+//! wrapper is therefore interposed: PTRACE_ATTACH and PTRACE_SEIZE of
+//! another process, and PTRACE_PEEKTEXT and PTRACE_PEEKDATA reads of its
+//! memory, fail with EPERM and a `proc_memory` audit event. So do
+//! process_vm_readv() calls, which copy another process's memory without
+//! attaching to it. Opens of another process's `/proc/<pid>/mem`, which
+//! reads its memory through the file system, and `/proc/<pid>/maps`, which
+//! tells a reader where to look, fail with EACCES (see the open module).
+//!
+//! AWF_ONE_SHOT_PROC_MEMORY selects the policy: `deny` (the default), `log`
+//! (report but allow) or `allow`. Requests toward this process and its
+//! threads are not affected; in observe mode `deny` is reported with an
+//! `observed` audit event instead. Debugging tools can be exempted with
+//! AWF_ONE_SHOT_PROC_MEMORY_ALLOW, a comma-separated list of command names
+//! or absolute executable paths; the identity is read once, at load, like
+//! that of AWF_ONE_SHOT_ALLOWED_COMMS (see the process module). A command
+//! name matches any program of that name, so tools are pinned with their
+//! path. Programs that issue the system calls
+//! directly bypass the wrappers; the seccomp filter (see the harden module)
+//! covers them.
+
+use crate::credfile::FilePolicy;
+use crate::procfs::{parse_proc_path, ProcOwner};
+use crate::{audit, lock_state, process, procfs, resolve_next, sys, Mode};
+use libc::{c_int, c_long, c_ulong, c_void, iovec, pid_t, ssize_t};
+use once_cell::sync::Lazy;
+use std::ffi::CStr;
+
+/// Files of other processes that expose their memory
+const MEMORY_FILES: &[&[u8]] = &[b"mem", b"maps"];
+
+type PtraceFn = unsafe extern "C" fn(c_int, ...) -> c_long;
+type ProcessVmReadvFn =
+    unsafe extern "C" fn(pid_t, *const iovec, c_ulong, *const iovec, c_ulong, c_ulong) -> ssize_t;
+
+/// Cached pointer to the real ptrace function
+static REAL_PTRACE: Lazy<PtraceFn> = Lazy::new(|| {
+    // SAFETY: ptrace has the PtraceFn signature
+    unsafe { std::mem::transmute::<*mut c_void, PtraceFn>(resolve_next(c"ptrace")) }
+});
+
+/// Cached pointer to the real process_vm_readv function
+static REAL_PROCESS_VM_READV: Lazy<ProcessVmReadvFn> = Lazy::new(|| {
+    // SAFETY: process_vm_readv has the ProcessVmReadvFn signature
+    unsafe {
+        std::mem::transmute::<*mut c_void, ProcessVmReadvFn>(resolve_next(c"process_vm_readv"))
+    }
+});
+
+/// Whether the program `comm`, running `exe`, is one of the `tools`
+///
+/// Absolute paths match the canonical executable, other entries the command
+/// name.
+fn is_exempt(tools: &[String], comm: &str, exe: &str) -> bool {
+    tools.iter().any(|tool| {
+        if tool.starts_with('/') {
+            !exe.is_empty() && process::canonical_exe(tool) == exe
+        } else {
+            process::comm_allowed(comm, std::slice::from_ref(tool))
+        }
+    })
+}
+
+/// Whether this process is exempted by AWF_ONE_SHOT_PROC_MEMORY_ALLOW
+pub(crate) fn load_exemption(tools: &[String]) -> bool {
+    !tools.is_empty()
+        && is_exempt(
+            tools,
+            &process::comm().unwrap_or_default(),
+            &process::exe().unwrap_or_default(),
+        )
+}
+
+/// Name of a ptrace request that attaches to or reads the memory of its
+/// target, or None for other requests
+fn guarded_request(request: c_int) -> Option<&'static str> {
+    match request {
+        r if r == libc::PTRACE_ATTACH as c_int => Some("attach"),
+        r if r == libc::PTRACE_SEIZE as c_int => Some("seize"),
+        r if r == libc::PTRACE_PEEKTEXT as c_int => Some("peektext"),
+        r if r == libc::PTRACE_PEEKDATA as c_int => Some("peekdata"),
+        _ => None,
+    }
+}
+
+/// Apply the memory access policy to an access of `pid` through `via`
+///
+/// Returns false if the call must fail with EPERM.
+fn check_access(pid: pid_t, detail: &str, via: &str) -> bool {
+    if !procfs::is_other_process(pid) {
+        return true;
+    }
+
+    let state = lock_state();
+    if state.proc_memory_exempt {
+        return true;
+    }
+    let detail = format!("pid={} {} via={}", pid, detail, via);
+    match state.proc_memory {
+        FilePolicy::Allow => true,
+        FilePolicy::Log => {
+            audit::emit("proc_memory", "*", &format!("{} action=logged", detail));
+            true
+        }
+        FilePolicy::Deny if state.mode == Mode::Observe => {
+            audit::emit(
+                "observed",
+                "*",
+                &format!("would=deny_proc_memory {}", detail),
+            );
+            true
+        }
+        FilePolicy::Deny => {
+            audit::emit("proc_memory", "*", &format!("{} action=denied", detail));
+            false
+        }
+    }
+}
+
+/// Apply the memory access policy to an open of `path`
+///
+/// Returns false if the open must fail with EACCES.
+pub(crate) fn check_open(path: &CStr, via: &str) -> bool {
+    let Some((ProcOwner::Pid(pid), file)) = parse_proc_path(path.to_bytes()) else {
+        return true;
+    };
+    if !MEMORY_FILES.contains(&file) {
+        return true;
+    }
+    let detail = format!("file={}", String::from_utf8_lossy(file));
+    check_access(pid, &detail, via)
+}
+
+/// Intercepted ptrace function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of ptrace(2); the variadic
+/// prototype is read as the pid, addr and data arguments glibc always reads.
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn ptrace(
+    request: c_int,
+    pid: pid_t,
+    addr: *mut c_void,
+    data: *mut c_void,
+) -> c_long {
+    if let Some(name) = guarded_request(request) {
+        if !check_access(pid, &format!("request={}", name), "ptrace") {
+            *sys::errno_location() = libc::EPERM;
+            return -1;
+        }
+    }
+    (*REAL_PTRACE)(request, pid, addr, data)
+}
+
+/// Total length of an iovec array, or 0 if it cannot be read
+///
+/// # Safety
+/// `iov` must be null or valid for reads of `count` entries, unless `count`
+/// is above the limit the kernel accepts
+unsafe fn iov_bytes(iov: *const iovec, count: c_ulong) -> usize {
+    if iov.is_null() || count > libc::UIO_MAXIOV as c_ulong {
+        return 0;
+    }
+    std::slice::from_raw_parts(iov, count as usize)
+        .iter()
+        .fold(0usize, |total, entry| total.saturating_add(entry.iov_len))
+}
+
+/// Intercepted process_vm_readv function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of process_vm_readv(2).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn process_vm_readv(
+    pid: pid_t,
+    local_iov: *const iovec,
+    liovcnt: c_ulong,
+    remote_iov: *const iovec,
+    riovcnt: c_ulong,
+    flags: c_ulong,
+) -> ssize_t {
+    let detail = format!("bytes={}", iov_bytes(remote_iov, riovcnt));
+    if !check_access(pid, &detail, "process_vm_readv") {
+        *sys::errno_location() = libc::EPERM;
+        return -1;
+    }
+    (*REAL_PROCESS_VM_READV)(pid, local_iov, liovcnt, remote_iov, riovcnt, flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_exempt() {
+        let tools = vec!["gdb".to_string(), "/no/such/strace".to_string()];
+        assert!(is_exempt(&tools, "gdb", "/usr/bin/gdb"));
+        assert!(is_exempt(&tools, "strace", "/no/such/strace"));
+        assert!(!is_exempt(&tools, "strace", "/usr/bin/strace"));
+        assert!(!is_exempt(&tools, "python3", "/usr/bin/python3"));
+        assert!(!is_exempt(&[], "gdb", "/usr/bin/gdb"));
+    }
+
+    #[test]
+    fn test_guarded_request() {
+        assert_eq!(
+            guarded_request(libc::PTRACE_ATTACH as c_int),
+            Some("attach")
+        );
+        assert_eq!(
+            guarded_request(libc::PTRACE_PEEKDATA as c_int),
+            Some("peekdata")
+        );
+        assert_eq!(guarded_request(libc::PTRACE_TRACEME as c_int), None);
+        assert_eq!(guarded_request(libc::PTRACE_CONT as c_int), None);
+    }
+}
@@ -0,0 +1,120 @@
+//! Hardening profiles (AWF_ONE_SHOT_PROFILE)
+//!
+//! Operators should not need to know every setting to get a coherent level
+//! of protection. AWF_ONE_SHOT_PROFILE names a bundle of settings that
+//! serves as the default for each of them: a setting that is set explicitly
+//! always wins over the profile.
+//!
+//! - `minimal` turns the checks that are on by default down to the token
+//!   cache and environment redaction: no argv scrubbing, snooping of other
+//!   processes' environ and cmdline allowed, memory access only reported
+//! - `standard` is the built-in defaults
+//! - `paranoid` adds eager scrubbing, non-dumpable processes without core
+//!   files, crash wipes, the watchdog, the seccomp filter and Landlock
+//!   ruleset, denial of snooping, and blocking of protected values in socket
+//!   writes, files and DNS query names. Cached values are locked into memory
+//!   in every profile (see the secmem module)
+//!
+//! Unrecognized names fall back to `standard`. The profile is propagated to
+//! children with the rest of the configuration; the settings it implies are
+//! not, so a child applies its own reading of the profile.
+
+use once_cell::sync::Lazy;
+use std::ffi::CStr;
+
+/// A bundle of setting defaults
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Profile {
+    Minimal,
+    Standard,
+    Paranoid,
+}
+
+impl Profile {
+    /// Parse AWF_ONE_SHOT_PROFILE (case-insensitive)
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "minimal" => Some(Profile::Minimal),
+            "standard" | "" => Some(Profile::Standard),
+            "paranoid" => Some(Profile::Paranoid),
+            _ => None,
+        }
+    }
+
+    /// The settings the profile implies, as (name, value)
+    fn settings(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Profile::Minimal => &[
+                ("AWF_ONE_SHOT_ARGV_SCRUB", "0"),
+                ("AWF_ONE_SHOT_PROC_SNOOP", "allow"),
+                ("AWF_ONE_SHOT_PROC_MEMORY", "log"),
+                ("AWF_ONE_SHOT_COMMAND_FILE_SCAN", "off"),
+            ],
+            Profile::Standard => &[],
+            Profile::Paranoid => &[
+                ("AWF_ONE_SHOT_EAGER", "1"),
+                ("AWF_ONE_SHOT_NODUMP", "1"),
+                ("AWF_ONE_SHOT_NOCORE", "1"),
+                ("AWF_ONE_SHOT_CRASH_WIPE", "1"),
+                ("AWF_ONE_SHOT_WATCHDOG", "5"),
+                ("AWF_ONE_SHOT_SECCOMP", "1"),
+                ("AWF_ONE_SHOT_LANDLOCK", "1"),
+                ("AWF_ONE_SHOT_PROC_SNOOP", "deny"),
+                ("AWF_ONE_SHOT_PROC_MEMORY", "deny"),
+                ("AWF_ONE_SHOT_EGRESS_SCAN", "block"),
+                ("AWF_ONE_SHOT_REDACT_OUTPUT", "1"),
+                ("AWF_ONE_SHOT_FILE_WRITE_SCAN", "block"),
+                ("AWF_ONE_SHOT_WORKSPACE_GUARD", "1"),
+                ("AWF_ONE_SHOT_DNS_EXFIL", "block"),
+            ],
+        }
+    }
+
+    /// The value the profile implies for `name`, if any
+    fn value(self, name: &[u8]) -> Option<&'static str> {
+        self.settings()
+            .iter()
+            .find(|(setting, _)| setting.as_bytes() == name)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// The configured profile, read once through the real getenv
+static PROFILE: Lazy<Profile> = Lazy::new(|| {
+    crate::read_env_var(c"AWF_ONE_SHOT_PROFILE")
+        .and_then(|value| Profile::parse(&value))
+        .unwrap_or(Profile::Standard)
+});
+
+/// The value the configured profile implies for the setting `name`
+pub(crate) fn default_value(name: &CStr) -> Option<&'static str> {
+    PROFILE.value(name.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_parse() {
+        assert_eq!(Profile::parse("Paranoid"), Some(Profile::Paranoid));
+        assert_eq!(Profile::parse(" minimal "), Some(Profile::Minimal));
+        assert_eq!(Profile::parse(""), Some(Profile::Standard));
+        assert_eq!(Profile::parse("strict"), None);
+    }
+
+    #[test]
+    fn test_profile_value() {
+        assert_eq!(
+            Profile::Paranoid.value(b"AWF_ONE_SHOT_PROC_SNOOP"),
+            Some("deny")
+        );
+        assert_eq!(
+            Profile::Minimal.value(b"AWF_ONE_SHOT_PROC_SNOOP"),
+            Some("allow")
+        );
+        assert_eq!(Profile::Standard.value(b"AWF_ONE_SHOT_PROC_SNOOP"), None);
+        assert_eq!(Profile::Paranoid.value(b"AWF_ONE_SHOT_MODE"), None);
+        assert_eq!(Profile::Paranoid.value(b"HOME"), None);
+    }
+}
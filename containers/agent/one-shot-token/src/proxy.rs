@@ -0,0 +1,141 @@
+//! Protection of the firewall's proxy configuration
+//!
+//! The firewall routes agent traffic through Squid by setting the standard
+//! proxy variables. An agent that clears or rewrites them can try to bypass the
+//! proxy (the iptables rules still apply, but the attempt itself is a policy
+//! violation worth recording). Writes and unsets of these variables are blocked
+//! and reported with a `proxy_tamper` audit event; the caller is told the call
+//! succeeded so that tools which merely tidy up their environment keep working.
+//!
+//! Tools can also copy and mutate their own environment before reading it, so
+//! the host may additionally supply the mandated values (AWF_ENFORCED_PROXY and
+//! AWF_ENFORCED_NO_PROXY). getenv then always serves those values for the proxy
+//! variables, regardless of what the process environment currently says.
+
+use crate::{audit, call_real_getenv, lock_state, Mode};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+
+/// Proxy variables honored by curl, git, Node.js, Python and most other tools
+pub(crate) const PROXY_VARIABLES: &[&str] = &[
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+    "ALL_PROXY",
+    "http_proxy",
+    "https_proxy",
+    "no_proxy",
+    "all_proxy",
+];
+
+/// Variables served from AWF_ENFORCED_PROXY
+const ENFORCED_PROXY_URL_VARIABLES: &[&str] =
+    &["HTTP_PROXY", "HTTPS_PROXY", "http_proxy", "https_proxy"];
+
+/// Variables served from AWF_ENFORCED_NO_PROXY
+const ENFORCED_NO_PROXY_VARIABLES: &[&str] = &["NO_PROXY", "no_proxy"];
+
+/// Build the map of enforced proxy values from the host-supplied settings
+///
+/// Empty settings are ignored, as are values containing NUL bytes (which
+/// cannot be represented as C strings).
+pub(crate) fn enforced_values(
+    proxy_url: Option<&str>,
+    no_proxy: Option<&str>,
+) -> HashMap<String, CString> {
+    let mut values = HashMap::new();
+    let sources = [
+        (proxy_url, ENFORCED_PROXY_URL_VARIABLES),
+        (no_proxy, ENFORCED_NO_PROXY_VARIABLES),
+    ];
+    for (value, variables) in sources {
+        let Some(value) = value.map(str::trim).filter(|value| !value.is_empty()) else {
+            continue;
+        };
+        let Ok(value) = CString::new(value) else {
+            continue;
+        };
+        for variable in variables {
+            values.insert(variable.to_string(), value.clone());
+        }
+    }
+    values
+}
+
+/// Check if a variable is one of the protected proxy variables
+pub(crate) fn is_proxy_variable(name: &str) -> bool {
+    PROXY_VARIABLES.contains(&name)
+}
+
+/// Decide whether a write to `name` must be blocked as proxy tampering
+///
+/// `new_value` is the value being written, or None for an unset. Writes that
+/// would not change the variable (re-exporting the same value, unsetting a
+/// variable that is not set) are allowed. Returns true if the caller must not
+/// perform the write.
+pub(crate) fn block_tampering(name: &str, new_value: Option<&CStr>, via: &str) -> bool {
+    if !is_proxy_variable(name) {
+        return false;
+    }
+
+    let state = lock_state();
+    if !state.protect_proxy {
+        return false;
+    }
+
+    let Ok(name_cstr) = CString::new(name) else {
+        return false;
+    };
+    // SAFETY: name_cstr is a valid C string
+    let current = unsafe { call_real_getenv(name_cstr.as_ptr()) };
+    let current = (!current.is_null()).then(|| unsafe { CStr::from_ptr(current) });
+    if current == new_value {
+        return false;
+    }
+
+    let action = if new_value.is_some() {
+        "write"
+    } else {
+        "unset"
+    };
+    if state.mode == Mode::Observe {
+        audit::emit(
+            "observed",
+            name,
+            &format!("would=block_proxy_{} via={}", action, via),
+        );
+        return false;
+    }
+
+    audit::emit(
+        "proxy_tamper",
+        name,
+        &format!("action={} via={}", action, via),
+    );
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enforced_values() {
+        let values = enforced_values(Some(" http://172.30.0.10:3128 "), Some("localhost"));
+        assert_eq!(values.len(), 6);
+        assert_eq!(values["https_proxy"].as_c_str(), c"http://172.30.0.10:3128");
+        assert_eq!(values["NO_PROXY"].as_c_str(), c"localhost");
+
+        let values = enforced_values(Some("http://172.30.0.10:3128"), Some(" "));
+        assert!(!values.contains_key("NO_PROXY"));
+        assert!(enforced_values(None, None).is_empty());
+    }
+
+    #[test]
+    fn test_is_proxy_variable() {
+        assert!(is_proxy_variable("HTTPS_PROXY"));
+        assert!(is_proxy_variable("no_proxy"));
+        assert!(!is_proxy_variable("Https_Proxy"));
+        assert!(!is_proxy_variable("GITHUB_TOKEN"));
+    }
+}
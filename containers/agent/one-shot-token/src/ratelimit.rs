@@ -0,0 +1,153 @@
+//! Read-rate limits for protected tokens
+//!
+//! A program that reads a token a handful of times is normal; one that reads
+//! it dozens of times a second is usually a loop exfiltrating it or trying it
+//! against something. AWF_ONE_SHOT_RATE_LIMIT sets a limit of the form
+//! `READS/SECONDS`, optionally followed by `:deny`, that applies to every
+//! protected token separately. When a token's reads within the window exceed
+//! the limit, a `rate_exceeded` audit event is emitted once per burst; with
+//! `:deny` every read over the limit also returns NULL.
+
+use crate::credfile::FilePolicy;
+use crate::envname::EnvName;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// A read-rate limit (AWF_ONE_SHOT_RATE_LIMIT)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RateLimit {
+    /// Reads allowed within the window
+    pub(crate) max_reads: usize,
+    pub(crate) window: Duration,
+    /// `Log` or `Deny`
+    pub(crate) action: FilePolicy,
+}
+
+impl RateLimit {
+    /// Parse `READS/SECONDS[:log|:deny]`
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let (rate, action) = match value.trim().split_once(':') {
+            Some((rate, action)) => (rate, FilePolicy::parse(action.trim())?),
+            None => (value.trim(), FilePolicy::Log),
+        };
+        if action == FilePolicy::Allow {
+            return None;
+        }
+        let (reads, secs) = rate.split_once('/')?;
+        let max_reads = reads.trim().parse::<usize>().ok().filter(|&n| n > 0)?;
+        let secs = secs.trim().parse::<u64>().ok().filter(|&n| n > 0)?;
+        Some(RateLimit {
+            max_reads,
+            window: Duration::from_secs(secs),
+            action,
+        })
+    }
+}
+
+/// Outcome of recording a read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RateCheck {
+    /// Within the limit
+    Within,
+    /// Over the limit for the first time in this burst; `reads` in the window
+    Exceeded { reads: usize },
+    /// Still over the limit, already reported
+    StillExceeded,
+}
+
+/// Recent read times of each protected token
+#[derive(Debug, Default)]
+pub(crate) struct ReadHistory {
+    tokens: HashMap<EnvName, TokenHistory>,
+}
+
+#[derive(Debug, Default)]
+struct TokenHistory {
+    times: VecDeque<Instant>,
+    /// Whether the current burst has been reported
+    alerted: bool,
+}
+
+impl ReadHistory {
+    /// Record a read of `token` at `now` and check it against `limit`
+    pub(crate) fn record(&mut self, token: &[u8], now: Instant, limit: &RateLimit) -> RateCheck {
+        let history = self.tokens.entry(EnvName::new(token)).or_default();
+        while history
+            .times
+            .front()
+            .is_some_and(|&time| now.duration_since(time) >= limit.window)
+        {
+            history.times.pop_front();
+        }
+        history.times.push_back(now);
+
+        if history.times.len() <= limit.max_reads {
+            history.alerted = false;
+            return RateCheck::Within;
+        }
+        // Keep memory bounded while a loop keeps hammering
+        if history.times.len() > limit.max_reads + 1 {
+            history.times.pop_front();
+        }
+        if history.alerted {
+            RateCheck::StillExceeded
+        } else {
+            history.alerted = true;
+            RateCheck::Exceeded {
+                reads: history.times.len(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_parse() {
+        assert_eq!(
+            RateLimit::parse("20/10"),
+            Some(RateLimit {
+                max_reads: 20,
+                window: Duration::from_secs(10),
+                action: FilePolicy::Log,
+            })
+        );
+        assert_eq!(
+            RateLimit::parse("5/1:deny").unwrap().action,
+            FilePolicy::Deny
+        );
+        assert_eq!(RateLimit::parse("0/10"), None);
+        assert_eq!(RateLimit::parse("20/10:allow"), None);
+        assert_eq!(RateLimit::parse("20"), None);
+    }
+
+    #[test]
+    fn test_read_history() {
+        let limit = RateLimit::parse("2/10").unwrap();
+        let mut history = ReadHistory::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(history.record(b"T", at(0), &limit), RateCheck::Within);
+        assert_eq!(history.record(b"T", at(1), &limit), RateCheck::Within);
+        assert_eq!(history.record(b"OTHER", at(1), &limit), RateCheck::Within);
+        assert_eq!(
+            history.record(b"T", at(2), &limit),
+            RateCheck::Exceeded { reads: 3 }
+        );
+        assert_eq!(
+            history.record(b"T", at(3), &limit),
+            RateCheck::StillExceeded
+        );
+
+        // Once the window has passed, reads are within the limit again
+        assert_eq!(history.record(b"T", at(20), &limit), RateCheck::Within);
+        assert_eq!(history.record(b"T", at(21), &limit), RateCheck::Within);
+        assert_eq!(
+            history.record(b"T", at(22), &limit),
+            RateCheck::Exceeded { reads: 3 }
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! Load confirmation for launchers (AWF_ONE_SHOT_READY_FD)
+//!
+//! Nothing outside a process shows whether the dynamic linker loaded the
+//! library: a mistyped LD_PRELOAD path only makes it print a warning, and the
+//! program runs unprotected. A launcher such as awf-preload passes the write
+//! end of a pipe in AWF_ONE_SHOT_READY_FD; a constructor writes one line to
+//! it, closes it and unsets the variable, so the launcher reads either the
+//! line or end of file. The line is `ready <pid> <version>`, or
+//! `disabled <pid> <version>` when AWF_ONE_SHOT_DISABLE is set.
+
+use crate::egress::call_real_write;
+use crate::setenv::call_real_unsetenv;
+use crate::{protection_disabled, read_config_var};
+use libc::c_int;
+
+/// Parse AWF_ONE_SHOT_READY_FD, refusing the standard streams
+fn parse_fd(value: &str) -> Option<c_int> {
+    value.trim().parse::<c_int>().ok().filter(|&fd| fd > 2)
+}
+
+/// The line announcing that the library is loaded in process `pid`
+fn ready_line(pid: libc::pid_t, disabled: bool) -> String {
+    let status = if disabled { "disabled" } else { "ready" };
+    format!("{} {} {}\n", status, pid, env!("CARGO_PKG_VERSION"))
+}
+
+/// Confirm the load to the launcher and close its descriptor
+extern "C" fn announce_ready_at_load() {
+    let Some(value) = read_config_var(c"AWF_ONE_SHOT_READY_FD") else {
+        return;
+    };
+    // SAFETY: valid C string; children must not write to a closed number
+    unsafe { call_real_unsetenv(c"AWF_ONE_SHOT_READY_FD".as_ptr()) };
+    let Some(fd) = parse_fd(&value) else {
+        log_line!(
+            Warning,
+            "config",
+            None,
+            "Invalid AWF_ONE_SHOT_READY_FD {}",
+            value
+        );
+        return;
+    };
+    // SAFETY: getpid has no preconditions
+    let line = ready_line(unsafe { libc::getpid() }, protection_disabled());
+    // SAFETY: line is a valid buffer of its length; fd was handed to this
+    // process for this one line
+    unsafe {
+        call_real_write(fd, line.as_ptr().cast(), line.len());
+        libc::close(fd);
+    }
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static ANNOUNCE_READY_AT_LOAD: extern "C" fn() = announce_ready_at_load;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fd() {
+        assert_eq!(parse_fd("7"), Some(7));
+        assert_eq!(parse_fd(" 12\n"), Some(12));
+        assert_eq!(parse_fd("2"), None);
+        assert_eq!(parse_fd("-1"), None);
+        assert_eq!(parse_fd("pipe"), None);
+    }
+
+    #[test]
+    fn test_ready_line() {
+        let version = env!("CARGO_PKG_VERSION");
+        assert_eq!(ready_line(42, false), format!("ready 42 {}\n", version));
+        assert_eq!(ready_line(42, true), format!("disabled 42 {}\n", version));
+    }
+}
@@ -0,0 +1,55 @@
+//! Recursion guard for getenv
+//!
+//! Code running inside the library can end up calling getenv again: an
+//! allocator reading its tuning variables, libc reading TZ while a log line
+//! is formatted, or a signal handler that interrupts an interposer. With the
+//! state lock held, the nested call would wait for the lock forever. Each
+//! thread therefore counts how deep it is inside getenv or a locked section,
+//! and nested getenv calls go straight to the real getenv.
+//!
+//! The counter is a const-initialized thread-local without a destructor, so
+//! checking it neither allocates nor takes a lock.
+
+use std::cell::Cell;
+
+thread_local! {
+    /// Entered getenv calls and locked sections of this thread
+    static DEPTH: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Marks this thread as inside the library until dropped
+pub(crate) struct Entered(());
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get().saturating_sub(1)));
+    }
+}
+
+/// Enter getenv or a locked section
+pub(crate) fn enter() -> Entered {
+    DEPTH.with(|depth| depth.set(depth.get() + 1));
+    Entered(())
+}
+
+/// Whether this thread is already inside the library
+pub(crate) fn active() -> bool {
+    DEPTH.with(Cell::get) > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enter() {
+        assert!(!active());
+        let outer = enter();
+        let inner = enter();
+        assert!(active());
+        drop(inner);
+        assert!(active());
+        drop(outer);
+        assert!(!active());
+    }
+}
@@ -0,0 +1,256 @@
+//! Refresh of cached values (the `refresh=N` token option)
+//!
+//! Installation tokens such as GITHUB_TOKEN expire after an hour, so a long
+//! agent run that cached one at startup eventually fails. With `refresh=N`, a
+//! cached value is replaced with a fresh one on the first read at least N
+//! seconds after it was cached. The fresh value is printed (on its first
+//! line) by the helper AWF_ONE_SHOT_REFRESH_COMMAND, run with the token name
+//! as its only argument in the same scrubbed environment an exec'd child gets,
+//! or else minted by the broker (see the broker module).
+//!
+//! The previous buffer is kept as it is, since callers may still hold it. A
+//! refresh that fails keeps serving the previous value and is retried on the
+//! first read 30 seconds later. Refreshes happen under the state lock, so
+//! refreshed tokens are never served lock-free (see the snapshot module).
+
+use crate::{audit, broker, exec, sys};
+use libc::{c_char, c_int, pid_t};
+use std::ffi::{CStr, CString};
+use std::io;
+use std::ptr;
+use std::time::{Duration, Instant};
+
+/// Delay before a failed refresh is attempted again
+pub(crate) const RETRY: Duration = Duration::from_secs(30);
+
+/// How long the helper may take to print the value
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most output read from the helper
+const MAX_OUTPUT: usize = 64 * 1024;
+
+/// Where fresh values come from
+pub(crate) enum Source<'a> {
+    /// AWF_ONE_SHOT_REFRESH_COMMAND, with the environment to run it in
+    Command(&'a CStr, *const *const c_char),
+    /// A `MINT` request to the broker
+    Broker(&'a broker::Broker),
+}
+
+/// The value a helper printed: its first line, if it exited successfully
+fn first_line(output: &[u8], success: bool) -> io::Result<CString> {
+    let line = output
+        .split(|&byte| byte == b'\n')
+        .next()
+        .unwrap_or_default();
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if !success {
+        return Err(io::Error::other("helper failed"));
+    }
+    if line.is_empty() {
+        return Err(io::Error::other("no value printed"));
+    }
+    CString::new(line).map_err(|err| {
+        zero(&mut err.into_vec());
+        io::ErrorKind::InvalidData.into()
+    })
+}
+
+/// Read the helper's output until it closes its end of the pipe
+///
+/// # Safety
+/// `fd` must be the read end of a pipe
+unsafe fn read_output(fd: c_int, output: &mut Vec<u8>) -> io::Result<()> {
+    let deadline = Instant::now() + TIMEOUT;
+    let mut buf = [0u8; 4096];
+    let result = loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break Err(io::ErrorKind::TimedOut.into());
+        }
+        let mut poll = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = libc::poll(
+            &mut poll,
+            1,
+            left.as_millis().min(c_int::MAX as u128) as c_int,
+        );
+        if ready == 0 || (ready < 0 && *sys::errno_location() == libc::EINTR) {
+            continue;
+        }
+        if ready < 0 {
+            break Err(io::Error::last_os_error());
+        }
+        let n = libc::read(fd, buf.as_mut_ptr().cast(), buf.len());
+        match n {
+            0 => break Ok(()),
+            n if n > 0 => output.extend_from_slice(&buf[..n as usize]),
+            _ if *sys::errno_location() == libc::EINTR => continue,
+            _ => break Err(io::Error::last_os_error()),
+        }
+        if output.len() > MAX_OUTPUT {
+            break Err(io::ErrorKind::InvalidData.into());
+        }
+    };
+    zero(&mut buf);
+    result
+}
+
+/// Wait for the helper; true if it exited successfully
+///
+/// A program that ignores SIGCHLD, or reaps every child itself, leaves
+/// nothing to wait for; the helper's output is then taken at its word.
+///
+/// # Safety
+/// `pid` must be a child of this process
+unsafe fn wait_success(pid: pid_t) -> bool {
+    let mut status = 0;
+    loop {
+        if libc::waitpid(pid, &mut status, 0) == pid {
+            return libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0;
+        }
+        if *sys::errno_location() != libc::EINTR {
+            return true;
+        }
+    }
+}
+
+/// Run the helper for `token` and take the value it prints
+///
+/// # Safety
+/// `envp` must be a null-terminated array of valid C strings
+unsafe fn run_command(
+    command: &CStr,
+    token: &str,
+    envp: *const *const c_char,
+) -> io::Result<CString> {
+    let token = CString::new(token).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let mut fds = [0; 2];
+    if libc::pipe(fds.as_mut_ptr()) != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    for fd in fds {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+
+    // The write end becomes the helper's stdout (dup2 clears FD_CLOEXEC)
+    let mut actions: libc::posix_spawn_file_actions_t = std::mem::zeroed();
+    libc::posix_spawn_file_actions_init(&mut actions);
+    libc::posix_spawn_file_actions_adddup2(&mut actions, fds[1], libc::STDOUT_FILENO);
+    let argv = [command.as_ptr(), token.as_ptr(), ptr::null()];
+    let mut pid = 0;
+    let spawned = exec::call_real_posix_spawn(
+        &mut pid,
+        command.as_ptr(),
+        &actions,
+        ptr::null(),
+        argv.as_ptr(),
+        envp,
+    );
+    libc::posix_spawn_file_actions_destroy(&mut actions);
+    libc::close(fds[1]);
+    if spawned != 0 {
+        libc::close(fds[0]);
+        return Err(io::Error::from_raw_os_error(spawned));
+    }
+
+    let mut output = Vec::new();
+    let read = read_output(fds[0], &mut output);
+    libc::close(fds[0]);
+    if read.is_err() {
+        libc::kill(pid, libc::SIGKILL);
+    }
+    let success = wait_success(pid);
+    let value = read.and_then(|()| first_line(&output, success));
+    zero(&mut output);
+    value
+}
+
+/// Get a fresh value for `token`
+///
+/// Emits a `token_refresh` audit event either way. Returns None if no value
+/// could be had.
+///
+/// # Safety
+/// Runs the helper or performs system calls on the broker's socket
+pub(crate) unsafe fn fetch(source: Source, token: &str) -> Option<CString> {
+    let (name, value) = match source {
+        Source::Command(command, envp) => ("command", run_command(command, token, envp)),
+        Source::Broker(broker) => (
+            "broker",
+            broker::mint(broker, token).ok_or_else(|| io::Error::other("broker refused")),
+        ),
+    };
+    match value {
+        Ok(value) => {
+            audit::emit(
+                "token_refresh",
+                token,
+                &format!(
+                    "action=refreshed source={} bytes={}",
+                    name,
+                    value.as_bytes().len()
+                ),
+            );
+            Some(value)
+        }
+        Err(err) => {
+            audit::emit(
+                "token_refresh",
+                token,
+                &format!(
+                    "action=failed source={} error={} retry_in={}",
+                    name,
+                    audit::detail_word(&err.to_string()),
+                    RETRY.as_secs()
+                ),
+            );
+            None
+        }
+    }
+}
+
+fn zero(bytes: &mut [u8]) {
+    // SAFETY: bytes is a writable buffer of the given length
+    unsafe { sys::explicit_bzero(bytes.as_mut_ptr().cast(), bytes.len()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_first_line() {
+        let value = first_line(b"ghs_fresh\r\nexpires=3600\n", true).unwrap();
+        assert_eq!(value.as_bytes(), b"ghs_fresh");
+        assert!(first_line(b"ghs_fresh\n", false).is_err());
+        assert!(first_line(b"\nghs_fresh\n", true).is_err());
+        assert!(first_line(b"ghs\0fresh\n", true).is_err());
+    }
+
+    #[test]
+    fn test_run_command() {
+        let dir = std::env::temp_dir().join(format!("awf-refresh-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let helper = dir.join("helper");
+        std::fs::write(
+            &helper,
+            "#!/bin/sh\necho \"ghs_fresh_$1\"\n[ \"$1\" != FAIL ]\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&helper, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let command = CString::new(helper.to_str().unwrap()).unwrap();
+        let envp = [c"PATH=/usr/bin:/bin".as_ptr(), ptr::null()];
+
+        let value = unsafe { run_command(&command, "GITHUB_TOKEN", envp.as_ptr()) };
+        assert_eq!(value.unwrap().as_bytes(), b"ghs_fresh_GITHUB_TOKEN");
+        assert!(unsafe { run_command(&command, "FAIL", envp.as_ptr()) }.is_err());
+        let missing = CString::new(dir.join("missing").to_str().unwrap()).unwrap();
+        assert!(unsafe { run_command(&missing, "GITHUB_TOKEN", envp.as_ptr()) }.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,322 @@
+//! Cleanup and access report at process exit
+//!
+//! Cached values otherwise live until the process is torn down. An atexit
+//! handler zeroizes every value cached by this process, including those a
+//! forked child inherited, which it holds in memory of its own (see the fork
+//! module). Reads after the handler ran (from later exit
+//! handlers or other threads) return NULL with a `wiped_at_exit` event.
+//!
+//! With AWF_ONE_SHOT_REPORT set to a path, the handler first appends a JSON
+//! summary of the process's token accesses to that file, one line per
+//! process, so that the processes of a whole agent run can share a report:
+//! which tokens existed, how often each was read and by which callers (see
+//! the caller module), and whether scrubbing them succeeded. With an audit
+//! key loaded, the line ends in `sigseq` and `sig` fields (see the sign
+//! module).
+//! With AWF_ONE_SHOT_LATENCY_SAMPLE, the summary also holds the process's
+//! getenv latency histograms (see the latency module). The same summary is
+//! returned by awf_token_stats (see the control module).
+//!
+//! With AWF_ONE_SHOT_STEP_SUMMARY set inside GitHub Actions, the handler also
+//! appends a markdown table of the process's token accesses to
+//! $GITHUB_STEP_SUMMARY, so workflow authors see them on the run page.
+//! Processes that looked up no protected token add nothing.
+//!
+//! If AWF_ONE_SHOT_LOG_SYNC asks for durable writes (see the logfile
+//! module), the report lines are synced to disk as they are appended, and
+//! the handler finally syncs log lines not synced yet.
+
+use crate::envname::EnvName;
+use crate::log::json_string;
+#[cfg(not(feature = "minimal"))]
+use crate::sign;
+use crate::{audit, environ, filewrite, latency, lock_state, logfile, CachedToken, TokenState};
+use std::io::Write;
+use std::time::SystemTime;
+
+/// JSON form of an optional timestamp
+fn json_time(time: Option<SystemTime>) -> String {
+    time.map_or_else(|| "null".to_string(), audit::format_time)
+}
+
+/// JSON object of a token's accesses; `set` is whether the token existed
+fn token_json(name: &EnvName, entry: &CachedToken, set: bool) -> String {
+    let callers: Vec<String> = entry
+        .callers
+        .iter()
+        .map(|(object, reads)| format!("{}:{}", json_string(object), reads))
+        .collect();
+    format!(
+        concat!(
+            "{{\"name\":{},\"set\":{},\"reads\":{},",
+            "\"first_read\":{},\"last_read\":{},\"callers\":{{{}}},",
+            "\"scrub\":{},\"memory\":{},\"locked\":{},\"wiped\":{}}}"
+        ),
+        json_string(&name.lossy()),
+        set,
+        entry.reads,
+        json_time(entry.first_read),
+        json_time(entry.last_read),
+        callers.join(","),
+        entry.scrub.map_or_else(|| "null".to_string(), json_string),
+        entry
+            .protection
+            .map_or_else(|| "null".to_string(), |memory| json_string(memory.as_str())),
+        entry.locked,
+        entry.wiped.map_or_else(|| "null".to_string(), json_string)
+    )
+}
+
+/// Summary of the token accesses of this process as a single JSON object
+///
+/// Tokens are listed in configuration order; tokens that were never looked
+/// up are listed only if they are still set in the environment.
+pub(crate) fn summary(state: &TokenState) -> String {
+    let unread = CachedToken::unset();
+    let tokens: Vec<String> = state
+        .tokens
+        .iter()
+        .filter_map(|spec| match state.cache.get(&spec.name) {
+            Some(entry) => {
+                let set = !entry.value.is_null() || entry.wiped.is_some();
+                Some(token_json(&spec.name, entry, set))
+            }
+            // SAFETY: environ is only read; the state lock is held
+            None if unsafe { environ::find_entry(&spec.name) }.is_some() => {
+                Some(token_json(&spec.name, &unread, true))
+            }
+            None => None,
+        })
+        .collect();
+    let latency =
+        latency::json().map_or_else(String::new, |latency| format!(",\"latency\":{}", latency));
+    format!(
+        "{{\"pid\":{},\"exe\":{},\"mode\":{},\"time\":{},\"tokens\":[{}]{}}}",
+        std::process::id(),
+        json_string(&crate::process::exe().unwrap_or_default()),
+        json_string(state.mode.as_str()),
+        audit::format_time(SystemTime::now()),
+        tokens.join(","),
+        latency
+    )
+}
+
+/// Markdown table of the token accesses of this process, or None if no
+/// protected token was looked up
+pub(crate) fn step_summary(state: &TokenState) -> Option<String> {
+    let rows: Vec<String> = state
+        .tokens
+        .iter()
+        .filter_map(|spec| Some((&spec.name, state.cache.get(&spec.name)?)))
+        .map(|(name, entry)| {
+            let environment = match (entry.scrub, entry.wiped) {
+                (Some(scrub), _) => scrub,
+                (None, Some(_)) => "wiped",
+                (None, None) => "not set",
+            };
+            format!("| `{}` | {} | {} |", name, entry.reads, environment)
+        })
+        .collect();
+    if rows.is_empty() {
+        return None;
+    }
+    let exe = crate::process::exe().unwrap_or_default();
+    let exe = exe.rsplit('/').next().unwrap_or_default();
+    Some(format!(
+        concat!(
+            "\n### Protected token access: `{}` (pid {})\n\n",
+            "| Token | Reads | Environment |\n",
+            "|-------|------:|-------------|\n",
+            "{}\n"
+        ),
+        exe.replace('`', "'"),
+        std::process::id(),
+        rows.join("\n")
+    ))
+}
+
+/// A summary line with its signature fields appended, if an audit key is
+/// loaded
+#[cfg(not(feature = "minimal"))]
+fn signed(summary: String) -> String {
+    let fields = [("report".to_string(), summary.clone())];
+    match sign::sign("report", "*", &fields, &[]) {
+        Some(signature) => format!(
+            "{},\"sigseq\":{},\"sig\":\"{}\"}}",
+            summary.strip_suffix('}').unwrap_or(&summary),
+            signature.seq,
+            signature.sig
+        ),
+        None => summary,
+    }
+}
+
+/// Append a summary line to the report file, synced to disk unless
+/// AWF_ONE_SHOT_LOG_SYNC is `none` (see the logfile module)
+fn append(path: &str, summary: &str) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(format!("{}\n", summary).as_bytes())?;
+    if logfile::durability() != logfile::Durability::None {
+        file.sync_data()?;
+    }
+    Ok(())
+}
+
+/// Write the reports, if configured, and zeroize cached values
+extern "C" fn at_exit() {
+    // Before the values are wiped, which the sweep has to know
+    filewrite::sweep_command_files();
+    let mut state = lock_state();
+    let report = state
+        .report_path
+        .clone()
+        .map(|path| (path, summary(&state)));
+    let step_summary = state
+        .step_summary_path
+        .clone()
+        .and_then(|path| Some((path, step_summary(&state)?)));
+    for entry in state.cache.values_mut() {
+        if !entry.value.is_null() || !entry.handed_over.is_null() {
+            entry.wipe("wiped_at_exit");
+        }
+    }
+    drop(state);
+
+    // Written without the state lock: the open goes through the interposers
+    if let Some((path, summary)) = report {
+        #[cfg(not(feature = "minimal"))]
+        let summary = signed(summary);
+        if let Err(err) = append(&path, &summary) {
+            log_line!(
+                Warning,
+                "report",
+                None,
+                "Could not write access report to {}: {}",
+                path,
+                err
+            );
+        }
+    }
+    if let Some((path, table)) = step_summary {
+        if let Err(err) = append(&path, &table) {
+            log_line!(
+                Warning,
+                "report",
+                None,
+                "Could not write step summary to {}: {}",
+                path,
+                err
+            );
+        }
+    }
+    logfile::sync_pending();
+}
+
+/// Register the exit handler at load
+extern "C" fn register_at_exit() {
+    // SAFETY: at_exit is a plain extern "C" function without arguments
+    if unsafe { libc::atexit(at_exit) } != 0 {
+        log_line!(Warning, "init", None, "Could not register exit handler");
+    }
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static REGISTER_AT_EXIT: extern "C" fn() = register_at_exit;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy;
+
+    #[test]
+    fn test_summary() {
+        let mut state = TokenState::new();
+        let tokens = [
+            "GITHUB_TOKEN",
+            "OPENAI_API_KEY",
+            "AWF_REPORT_TEST_UNREAD",
+            "AWF_REPORT_TEST_UNSET",
+        ];
+        for token in tokens {
+            state
+                .tokens
+                .push(policy::parse_token_spec(token).unwrap().0);
+        }
+        let mut value = *b"ghp_test\0";
+        state.cache.insert(
+            "GITHUB_TOKEN".into(),
+            CachedToken {
+                reads: 2,
+                first_read: Some(SystemTime::UNIX_EPOCH),
+                last_read: Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(1500)),
+                callers: vec![("libcurl.so.4".to_string(), 2)],
+                scrub: Some("cleared"),
+                ..CachedToken::cached(value.as_mut_ptr() as *mut libc::c_char)
+            },
+        );
+        state
+            .cache
+            .insert("OPENAI_API_KEY".into(), CachedToken::unset());
+        std::env::set_var("AWF_REPORT_TEST_UNREAD", "secret");
+
+        let summary = summary(&state);
+        assert!(summary.contains(concat!(
+            "\"tokens\":[",
+            "{\"name\":\"GITHUB_TOKEN\",\"set\":true,\"reads\":2,",
+            "\"first_read\":0.000,\"last_read\":1.500,",
+            "\"callers\":{\"libcurl.so.4\":2},\"scrub\":\"cleared\",",
+            "\"memory\":null,\"locked\":false,\"wiped\":null},",
+            "{\"name\":\"OPENAI_API_KEY\",\"set\":false,\"reads\":0,",
+            "\"first_read\":null,\"last_read\":null,\"callers\":{},\"scrub\":null,",
+            "\"memory\":null,\"locked\":false,\"wiped\":null},",
+            "{\"name\":\"AWF_REPORT_TEST_UNREAD\",\"set\":true,\"reads\":0,",
+            "\"first_read\":null,\"last_read\":null,\"callers\":{},\"scrub\":null,",
+            "\"memory\":null,\"locked\":false,\"wiped\":null}]}"
+        )));
+        assert!(summary.starts_with(&format!("{{\"pid\":{},", std::process::id())));
+    }
+
+    #[test]
+    fn test_step_summary() {
+        let mut state = TokenState::new();
+        for token in ["GITHUB_TOKEN", "OPENAI_API_KEY", "GH_TOKEN"] {
+            state
+                .tokens
+                .push(policy::parse_token_spec(token).unwrap().0);
+        }
+        assert_eq!(step_summary(&state), None);
+
+        let mut value = *b"ghp_test\0";
+        state.cache.insert(
+            "GITHUB_TOKEN".into(),
+            CachedToken {
+                reads: 3,
+                scrub: Some("cleared"),
+                ..CachedToken::cached(value.as_mut_ptr() as *mut libc::c_char)
+            },
+        );
+        state
+            .cache
+            .insert("OPENAI_API_KEY".into(), CachedToken::unset());
+
+        let table = step_summary(&state).unwrap();
+        assert!(table.starts_with("\n### Protected token access: `"));
+        assert!(table.ends_with(concat!(
+            "\n\n| Token | Reads | Environment |\n",
+            "|-------|------:|-------------|\n",
+            "| `GITHUB_TOKEN` | 3 | cleared |\n",
+            "| `OPENAI_API_KEY` | 0 | not set |\n"
+        )));
+    }
+}
@@ -0,0 +1,188 @@
+//! Failing fast on missing required tokens (the `required` token option,
+//! AWF_ONE_SHOT_REQUIRED_ACTION)
+//!
+//! A workflow whose secret never reached the agent does not fail where the
+//! mistake is: it runs until its first API call and stops there with a 401,
+//! often minutes later and deep inside a tool's output. Tokens listed with
+//! the `required` option (`GITHUB_TOKEN:required`) are checked when the
+//! library loads instead. A required token is missing if it is not set (or
+//! empty) under its name, an alias or its split parts, was not read from the
+//! secrets directory and is not minted by the broker.
+//!
+//! Each missing token is reported with a `required_missing` audit event and
+//! the process gets one error line naming them all. AWF_ONE_SHOT_REQUIRED_ACTION
+//! then decides: `abort` (default) ends the process with exit status 78
+//! (EX_CONFIG) before the program's own code runs, `log` lets it continue. In
+//! observe mode the process keeps running and the events are `observed`
+//! (`would=exit`).
+//!
+//! Only the first process of a tree is checked, since the tokens it scrubs
+//! are gone from the environment of its children: it sets
+//! AWF_ONE_SHOT_REQUIRED_CHECKED, which is propagated to children (see the
+//! exec module), and processes that find it skip the check.
+
+use crate::envname::EnvName;
+use crate::setenv::call_real_setenv;
+use crate::{audit, call_real_getenv, read_config_var, token_group, Mode, TokenState};
+use std::ffi::CStr;
+use std::sync::OnceLock;
+
+/// Exit status of a process missing a required token (EX_CONFIG)
+pub(crate) const EXIT_STATUS: i32 = 78;
+
+/// Variable marking the process tree as checked
+const CHECKED_VAR: &CStr = c"AWF_ONE_SHOT_REQUIRED_CHECKED";
+
+/// Required tokens this process found missing, once checked
+static MISSING: OnceLock<Vec<EnvName>> = OnceLock::new();
+
+/// What happens when a required token is missing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Abort,
+    Log,
+}
+
+impl Action {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "abort" => Some(Action::Abort),
+            "log" => Some(Action::Log),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `value` is an AWF_ONE_SHOT_REQUIRED_ACTION value
+pub(crate) fn is_action(value: &str) -> bool {
+    Action::parse(value).is_some()
+}
+
+/// Whether the value of `canonical` is available to the process
+fn present(state: &TokenState, canonical: &EnvName) -> bool {
+    if state.cache.contains_key(canonical) || state.mint.contains(canonical) {
+        return true;
+    }
+    token_group(state, canonical).iter().any(|member| {
+        let Some(name) = member.to_cstring() else {
+            return false;
+        };
+        // SAFETY: name is a valid C string; a non-null result is one too
+        unsafe {
+            let value = call_real_getenv(name.as_ptr());
+            !value.is_null() && *value != 0
+        }
+    })
+}
+
+/// The required tokens of `state` that are missing
+fn missing(state: &TokenState) -> Vec<EnvName> {
+    state
+        .tokens
+        .iter()
+        .filter(|spec| spec.required && !present(state, &spec.name))
+        .map(|spec| spec.name.clone())
+        .collect()
+}
+
+/// The required tokens this process found missing at load
+pub(crate) fn missing_at_load() -> &'static [EnvName] {
+    MISSING.get().map_or(&[], Vec::as_slice)
+}
+
+/// Check the required tokens once the configuration is loaded
+///
+/// Called with the state lock held at the end of initialization. Does not
+/// return if a token is missing and the action is `abort`.
+pub(crate) fn check(state: &mut TokenState) {
+    if read_config_var(CHECKED_VAR).is_some() || !state.tokens.iter().any(|spec| spec.required) {
+        return;
+    }
+    let missing = MISSING.get_or_init(|| missing(state));
+    // SAFETY: both are valid C strings
+    unsafe { call_real_setenv(CHECKED_VAR.as_ptr(), c"1".as_ptr(), 1) };
+    if let Some(propagation) = state.propagation.as_mut() {
+        propagation.set_config("AWF_ONE_SHOT_REQUIRED_CHECKED", Some("1"));
+    }
+    if missing.is_empty() {
+        return;
+    }
+
+    let abort = read_config_var(c"AWF_ONE_SHOT_REQUIRED_ACTION")
+        .and_then(|value| Action::parse(&value))
+        .unwrap_or(Action::Abort)
+        == Action::Abort;
+    let observe = state.mode == Mode::Observe;
+    for name in missing {
+        if observe && abort {
+            audit::emit("observed", name, "would=exit reason=required_missing");
+        } else {
+            let action = if abort { "exit" } else { "logged" };
+            audit::emit(
+                "required_missing",
+                name,
+                &format!("severity=high action={}", action),
+            );
+        }
+    }
+    let names: Vec<String> = missing.iter().map(ToString::to_string).collect();
+    let outcome = if abort && !observe {
+        format!(
+            "; exiting with status {} (AWF_ONE_SHOT_REQUIRED_ACTION=log to continue)",
+            EXIT_STATUS
+        )
+    } else {
+        String::new()
+    };
+    log_line!(
+        Error,
+        "config",
+        None,
+        "Required token(s) {} not set{}",
+        names.join(","),
+        outcome
+    );
+    if abort && !observe {
+        // SAFETY: _exit has no preconditions
+        unsafe { libc::_exit(EXIT_STATUS) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::parse_token_spec;
+
+    #[test]
+    fn test_action_parse() {
+        assert_eq!(Action::parse(" Abort "), Some(Action::Abort));
+        assert_eq!(Action::parse("log"), Some(Action::Log));
+        assert_eq!(Action::parse("exit"), None);
+    }
+
+    #[test]
+    fn test_missing() {
+        let mut state = TokenState::new();
+        for entry in [
+            "AWF_TEST_REQUIRED_SET:required",
+            "AWF_TEST_REQUIRED_UNSET:required",
+            "AWF_TEST_REQUIRED_ALIASED:required",
+            "AWF_TEST_REQUIRED_OPTIONAL",
+        ] {
+            state.tokens.push(parse_token_spec(entry).unwrap().0);
+        }
+        state.aliases.insert(
+            "AWF_TEST_REQUIRED_ALIAS".into(),
+            "AWF_TEST_REQUIRED_ALIASED".into(),
+        );
+        // SAFETY: valid C strings; no other test uses these names
+        unsafe {
+            call_real_setenv(c"AWF_TEST_REQUIRED_SET".as_ptr(), c"value".as_ptr(), 1);
+            call_real_setenv(c"AWF_TEST_REQUIRED_ALIAS".as_ptr(), c"value".as_ptr(), 1);
+        }
+        assert_eq!(
+            missing(&state),
+            vec![EnvName::from("AWF_TEST_REQUIRED_UNSET")]
+        );
+    }
+}
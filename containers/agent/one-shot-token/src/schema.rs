@@ -0,0 +1,223 @@
+//! Versioned schema of the event stream
+//!
+//! JSON log lines and event socket datagrams (see the log and events
+//! modules) are read by host-side consumers that are released separately
+//! from the library. Every event carries `schema_version`, and
+//! `schema/event.schema.json` describes the fields of that version as a JSON
+//! Schema, shipped in the crate as [`EVENT_SCHEMA`].
+//!
+//! Within a version, fields are only added: consumers must ignore fields
+//! they do not know. Removing, renaming or retyping a field bumps
+//! [`SCHEMA_VERSION`]. Events written before the field existed have the same
+//! layout and are read as version 0.
+//!
+//! [`Event`] is the typed form for Rust consumers that link the rlib.
+
+use crate::json::{self, Value};
+use std::fmt;
+
+/// Version of the event schema written by this library
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schema of the current version
+pub const EVENT_SCHEMA: &str = include_str!("../schema/event.schema.json");
+
+/// Fields of an event that are not string fields
+const TYPED_FIELDS: &[&str] = &["time", "pid", "schema_version", "level", "event", "token"];
+
+/// An event of the event stream, as read by a consumer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// Schema version the event was written with (0 before versioning)
+    pub schema_version: u32,
+    /// Seconds since the Unix epoch
+    pub time: f64,
+    pub pid: u32,
+    /// `debug`, `info`, `warning`, `error`, `fatal` or `audit`
+    pub level: String,
+    /// Log or audit event name
+    pub event: String,
+    /// The token concerned, if any
+    pub token: Option<String>,
+    /// The run context, `message`, the chain fields and the event's details,
+    /// in document order
+    pub fields: Vec<(String, String)>,
+}
+
+impl Event {
+    /// Parse a JSON log line or datagram
+    pub fn parse(line: &str) -> Result<Self, EventError> {
+        let value = json::parse(line.trim_end().as_bytes()).map_err(EventError::Json)?;
+        let Value::Object(members) = &value else {
+            return Err(EventError::Json("not an object".to_string()));
+        };
+        let schema_version = match value.get("schema_version") {
+            None => 0,
+            Some(version) => version
+                .as_u64()
+                .and_then(|version| u32::try_from(version).ok())
+                .ok_or(EventError::Invalid("schema_version"))?,
+        };
+        if schema_version > SCHEMA_VERSION {
+            return Err(EventError::UnsupportedVersion(schema_version));
+        }
+        let string = |key: &'static str| {
+            value
+                .get(key)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or(EventError::Invalid(key))
+        };
+        let time = match value.get("time") {
+            Some(Value::Number(time)) if *time >= 0.0 => *time,
+            _ => return Err(EventError::Invalid("time")),
+        };
+        let pid = value
+            .get("pid")
+            .and_then(Value::as_u64)
+            .and_then(|pid| u32::try_from(pid).ok())
+            .ok_or(EventError::Invalid("pid"))?;
+        let token = match value.get("token") {
+            None => None,
+            Some(_) => Some(string("token")?),
+        };
+        let mut fields = Vec::new();
+        for (key, member) in members {
+            if TYPED_FIELDS.contains(&key.as_str()) {
+                continue;
+            }
+            // Later versions may add fields of other types; the string
+            // fields of this version are kept
+            if let Some(member) = member.as_str() {
+                fields.push((key.clone(), member.to_string()));
+            }
+        }
+        Ok(Event {
+            schema_version,
+            time,
+            pid,
+            level: string("level")?,
+            event: string("event")?,
+            token,
+            fields,
+        })
+    }
+
+    /// The string field `key`
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A line that is not an event of a supported schema version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventError {
+    /// Not a JSON object
+    Json(String),
+    /// A required field is missing or has the wrong type
+    Invalid(&'static str),
+    /// Written with a newer schema version than this library reads
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for EventError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventError::Json(err) => write!(f, "invalid event: {}", err),
+            EventError::Invalid(field) => write!(f, "invalid event: bad or missing {}", field),
+            EventError::UnsupportedVersion(version) => write!(
+                f,
+                "event schema version {} is newer than {}",
+                version, SCHEMA_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EventError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines as written by earlier releases, which must stay readable
+    const RECORDED: &[&str] = &[
+        // Version 0, before schema_version
+        r#"{"time":1792171557.763,"pid":13668,"level":"audit","event":"max_reads_exceeded","token":"GITHUB_TOKEN","max_reads":"1","reads":"1"}"#,
+        // Version 1
+        r#"{"time":1792171929.669,"pid":18333,"schema_version":1,"step":"build","level":"info","event":"access","token":"GITHUB_TOKEN","via":"getenv","reads":"1","first":"true"}"#,
+        r#"{"time":1792171929.670,"pid":18333,"schema_version":1,"level":"debug","event":"init","message":"Initialized"}"#,
+    ];
+
+    #[test]
+    fn test_parse_recorded() {
+        let events: Vec<Event> = RECORDED
+            .iter()
+            .map(|line| Event::parse(line).unwrap())
+            .collect();
+        assert_eq!(events[0].schema_version, 0);
+        assert_eq!(events[0].event, "max_reads_exceeded");
+        assert_eq!(events[0].field("max_reads"), Some("1"));
+        assert_eq!(events[1].schema_version, 1);
+        assert_eq!(events[1].pid, 18333);
+        assert_eq!(events[1].level, "info");
+        assert_eq!(events[1].token.as_deref(), Some("GITHUB_TOKEN"));
+        assert_eq!(
+            events[1].fields,
+            [
+                ("step", "build"),
+                ("via", "getenv"),
+                ("reads", "1"),
+                ("first", "true")
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+        assert_eq!(events[2].token, None);
+        assert_eq!(events[2].field("message"), Some("Initialized"));
+    }
+
+    #[test]
+    fn test_parse_newer() {
+        // Unknown fields of a later release of the same version are ignored
+        let event = Event::parse(
+            r#"{"time":1.5,"pid":7,"schema_version":1,"level":"info","event":"access","tags":["a"],"new":"x"}"#,
+        )
+        .unwrap();
+        assert_eq!(event.fields, [("new".to_string(), "x".to_string())]);
+        assert_eq!(
+            Event::parse(r#"{"time":1.5,"pid":7,"schema_version":2,"level":"info","event":"x"}"#),
+            Err(EventError::UnsupportedVersion(2))
+        );
+        assert_eq!(
+            Event::parse(r#"{"time":1.5,"schema_version":1,"level":"info","event":"x"}"#),
+            Err(EventError::Invalid("pid"))
+        );
+        assert!(matches!(Event::parse("[]"), Err(EventError::Json(_))));
+    }
+
+    #[test]
+    fn test_schema_document() {
+        let schema = json::parse(EVENT_SCHEMA.as_bytes()).unwrap();
+        let properties = schema.get("properties").unwrap();
+        let version = properties.get("schema_version").unwrap().get("const");
+        assert_eq!(
+            version.and_then(Value::as_u64),
+            Some(u64::from(SCHEMA_VERSION))
+        );
+        assert_eq!(
+            schema.get("$id").and_then(Value::as_str),
+            Some(format!("urn:awf:one-shot-token:event:{}", SCHEMA_VERSION).as_str())
+        );
+        let Some(Value::Array(required)) = schema.get("required") else {
+            panic!("no required fields");
+        };
+        for field in required {
+            let field = field.as_str().unwrap();
+            assert!(properties.get(field).is_some(), "{}", field);
+            assert!(TYPED_FIELDS.contains(&field), "{}", field);
+        }
+    }
+}
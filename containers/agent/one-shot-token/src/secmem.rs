@@ -0,0 +1,517 @@
+//! Storage for cached token values outside the ordinary heap
+//!
+//! Cached values are never freed (callers may keep the pointers getenv
+//! returned), so they are carved out of dedicated mappings instead of malloc.
+//! On kernels that support memfd_secret(2) the mappings are secret memory:
+//! removed from the kernel's direct map, never swapped and never dumped.
+//! Otherwise they are anonymous mappings locked into memory with mlock, so
+//! secrets never reach swap, and marked MADV_DONTDUMP, so they are left out
+//! of core dumps. If the mapping cannot be created the value falls back to
+//! malloc; if mlock fails (RLIMIT_MEMLOCK) the pages are still excluded from
+//! dumps.
+//!
+//! Keeping values out of the heap also keeps them away from programs that
+//! interpose malloc themselves. Each mapping sits between two inaccessible
+//! guard pages, so a write running off either end of it faults instead of
+//! corrupting the neighbouring mapping, and an overflow from a neighbouring
+//! buffer cannot reach the secrets.
+//!
+//! Secret memory is a shared mapping, so a forked child sees the parent's
+//! pages rather than a copy. The child starts a new region for its own
+//! values and copies the values cached before the fork into it (see the fork
+//! module), so wiping them in the child leaves the parent's values intact.
+//!
+//! Every mapping and heap copy is also recorded in a fixed table that can be
+//! read without a lock, so a crash handler can zeroize them all (see the
+//! crash module).
+//!
+//! A value that may have to be revoked later is copied into a mapping of its
+//! own instead of the shared region, so making it inaccessible leaves every
+//! other value readable (see the invalidate module).
+
+use libc::c_char;
+#[cfg(target_os = "linux")]
+use libc::c_int;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Mapping currently being filled
+struct Region {
+    base: *mut u8,
+    len: usize,
+    used: usize,
+    protection: Protection,
+}
+
+// SAFETY: the region is only accessed through the ARENA mutex
+unsafe impl Send for Region {}
+
+static ARENA: Mutex<Option<Region>> = Mutex::new(None);
+
+/// Most mappings and heap copies the tracking table holds; later ones are
+/// not wiped on a crash
+const MAX_TRACKED: usize = 256;
+
+/// Memory holding cached values, as recorded for the crash wipe
+struct Tracked {
+    base: AtomicPtr<u8>,
+    len: AtomicUsize,
+    /// Process that created a shared (secret memory) mapping, or 0 if the
+    /// memory is private to each process
+    owner: AtomicU32,
+    /// Whether the mapping holds a single value and may be revoked
+    isolated: AtomicBool,
+}
+
+/// Table of the memory holding cached values
+///
+/// Entries are only added under the ARENA lock, and the count is published
+/// after the entry, so a lock-free reader never sees a partial entry.
+static TRACKED: [Tracked; MAX_TRACKED] = [const {
+    Tracked {
+        base: AtomicPtr::new(ptr::null_mut()),
+        len: AtomicUsize::new(0),
+        owner: AtomicU32::new(0),
+        isolated: AtomicBool::new(false),
+    }
+}; MAX_TRACKED];
+
+/// Number of entries of TRACKED in use
+static TRACKED_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Record `len` bytes at `base`, unless the table is full; must be called
+/// with the ARENA lock held
+fn track(base: *mut u8, len: usize, shared: bool) -> bool {
+    let count = TRACKED_COUNT.load(Ordering::Relaxed);
+    let Some(entry) = TRACKED.get(count) else {
+        return false;
+    };
+    entry.base.store(base, Ordering::Relaxed);
+    entry.len.store(len, Ordering::Relaxed);
+    entry.owner.store(
+        if shared { std::process::id() } else { 0 },
+        Ordering::Relaxed,
+    );
+    entry.isolated.store(false, Ordering::Relaxed);
+    TRACKED_COUNT.store(count + 1, Ordering::Release);
+    true
+}
+
+/// Zero all memory holding cached values, except secret memory shared with
+/// the process that created it; returns the number of bytes zeroed
+///
+/// Async-signal-safe: takes no lock and does not allocate.
+///
+/// # Safety
+/// Every cached value is destroyed: pointers getenv returned read as empty
+/// strings afterwards. Only for a process that is about to die.
+#[cfg(not(feature = "minimal"))]
+pub(crate) unsafe fn wipe_tracked() -> usize {
+    let pid = libc::getpid() as u32;
+    let count = TRACKED_COUNT.load(Ordering::Acquire);
+    let mut zeroed = 0;
+    for entry in &TRACKED[..count.min(MAX_TRACKED)] {
+        let owner = entry.owner.load(Ordering::Relaxed);
+        if owner != 0 && owner != pid {
+            continue;
+        }
+        let len = entry.len.load(Ordering::Relaxed);
+        crate::sys::explicit_bzero(entry.base.load(Ordering::Relaxed).cast(), len);
+        zeroed += len;
+    }
+    zeroed
+}
+
+/// Whether memfd_secret is still worth trying; cleared on the first failure
+#[cfg(target_os = "linux")]
+static SECRET_AVAILABLE: AtomicBool = AtomicBool::new(true);
+
+/// How an allocation is protected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Protection {
+    /// memfd_secret memory
+    Secret,
+    /// Locked into memory and excluded from core dumps
+    Locked,
+    /// Excluded from core dumps, but mlock failed
+    DontDump,
+    /// Ordinary heap memory
+    Heap,
+}
+
+impl Protection {
+    /// Short name used in log output
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Protection::Secret => "memfd_secret",
+            Protection::Locked => "mlock+dontdump",
+            Protection::DontDump => "dontdump",
+            Protection::Heap => "heap",
+        }
+    }
+}
+
+fn page_size() -> usize {
+    // SAFETY: sysconf has no preconditions
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if size > 0 {
+        size as usize
+    } else {
+        4096
+    }
+}
+
+/// Reserve `len` bytes between two guard pages, all inaccessible
+///
+/// Returns the start of the `len` bytes, which the caller maps or makes
+/// accessible.
+///
+/// # Safety
+/// Only performs system calls on the mapping it creates
+unsafe fn reserve_guarded(len: usize, page: usize) -> Option<*mut libc::c_void> {
+    let base = libc::mmap(
+        ptr::null_mut(),
+        len + 2 * page,
+        libc::PROT_NONE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+        -1,
+        0,
+    );
+    if base == libc::MAP_FAILED {
+        return None;
+    }
+    Some((base as *mut u8).add(page) as *mut libc::c_void)
+}
+
+/// Release a reservation made by reserve_guarded
+///
+/// # Safety
+/// `start` must have been returned by reserve_guarded for the same `len`
+unsafe fn release_guarded(start: *mut libc::c_void, len: usize, page: usize) {
+    libc::munmap(
+        (start as *mut u8).sub(page) as *mut libc::c_void,
+        len + 2 * page,
+    );
+}
+
+/// Map secret memory over the reserved bytes at `start`, if the kernel
+/// supports it
+///
+/// # Safety
+/// `start` must be a reservation of `len` bytes made by reserve_guarded
+#[cfg(target_os = "linux")]
+unsafe fn map_secret(start: *mut libc::c_void, len: usize) -> bool {
+    if !SECRET_AVAILABLE.load(Ordering::Relaxed) {
+        return false;
+    }
+    let fd = libc::syscall(libc::SYS_memfd_secret, libc::O_CLOEXEC) as c_int;
+    if fd < 0 {
+        SECRET_AVAILABLE.store(false, Ordering::Relaxed);
+        return false;
+    }
+    let base = if libc::ftruncate(fd, len as libc::off_t) == 0 {
+        libc::mmap(
+            start,
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_FIXED,
+            fd,
+            0,
+        )
+    } else {
+        libc::MAP_FAILED
+    };
+    // The mapping keeps the memory alive
+    libc::close(fd);
+    if base == libc::MAP_FAILED {
+        SECRET_AVAILABLE.store(false, Ordering::Relaxed);
+        return false;
+    }
+    true
+}
+
+/// Secret memory is Linux-only
+#[cfg(not(target_os = "linux"))]
+unsafe fn map_secret(_start: *mut libc::c_void, _len: usize) -> bool {
+    false
+}
+
+/// Map a new protected region of at least `min_len` bytes, between guard
+/// pages
+///
+/// # Safety
+/// Only performs system calls on the mapping it creates
+unsafe fn map_region(min_len: usize) -> Option<Region> {
+    let page = page_size();
+    let len = min_len.div_ceil(page).max(1) * page;
+    let base = reserve_guarded(len, page)?;
+    if map_secret(base, len) {
+        return Some(Region {
+            base: base as *mut u8,
+            len,
+            used: 0,
+            protection: Protection::Secret,
+        });
+    }
+
+    if libc::mprotect(base, len, libc::PROT_READ | libc::PROT_WRITE) != 0 {
+        release_guarded(base, len, page);
+        return None;
+    }
+    #[cfg(target_os = "linux")]
+    libc::madvise(base, len, libc::MADV_DONTDUMP);
+    let protection = if libc::mlock(base, len) == 0 {
+        Protection::Locked
+    } else {
+        Protection::DontDump
+    };
+    Some(Region {
+        base: base as *mut u8,
+        len,
+        used: 0,
+        protection,
+    })
+}
+
+/// Copy `bytes` (which must include the terminating NUL) into protected
+/// storage
+///
+/// The allocation is never freed. Aborts if no memory is available at all.
+pub(crate) fn alloc_copy(bytes: &[u8]) -> (*mut c_char, Protection) {
+    let mut arena = ARENA.lock().unwrap_or_else(|err| err.into_inner());
+    let fits = arena
+        .as_ref()
+        .is_some_and(|region| region.len - region.used >= bytes.len());
+    if !fits {
+        // The rest of a full region is abandoned; values are small
+        // SAFETY: creates a fresh private mapping
+        match unsafe { map_region(bytes.len()) } {
+            Some(region) => *arena = Some(region),
+            None => {
+                let copy = heap_copy(bytes);
+                track(copy.cast(), bytes.len(), false);
+                return (copy, Protection::Heap);
+            }
+        }
+    }
+
+    let region = arena.as_mut().expect("region was just ensured");
+    if region.used == 0 {
+        track(
+            region.base,
+            region.len,
+            region.protection == Protection::Secret,
+        );
+    }
+    let protection = region.protection;
+    // SAFETY: the region has at least bytes.len() unused bytes
+    unsafe {
+        let dest = region.base.add(region.used);
+        ptr::copy_nonoverlapping(bytes.as_ptr(), dest, bytes.len());
+        region.used += bytes.len();
+        (dest as *mut c_char, protection)
+    }
+}
+
+/// Copy `bytes` (which must include the terminating NUL) into a protected
+/// mapping of its own, which revoke can later make inaccessible
+///
+/// Returns None if no mapping could be created or tracked; the caller falls
+/// back to alloc_copy.
+#[cfg(not(feature = "minimal"))]
+pub(crate) fn alloc_isolated(bytes: &[u8]) -> Option<(*mut c_char, Protection)> {
+    let _arena = ARENA.lock().unwrap_or_else(|err| err.into_inner());
+    // SAFETY: creates a fresh private mapping
+    let region = unsafe { map_region(bytes.len()) }?;
+    let shared = region.protection == Protection::Secret;
+    if !track(region.base, region.len, shared) {
+        // Untracked memory could be neither wiped on a crash nor revoked
+        // SAFETY: the region was just mapped and holds nothing yet
+        unsafe { release_guarded(region.base.cast(), region.len, page_size()) };
+        return None;
+    }
+    let count = TRACKED_COUNT.load(Ordering::Relaxed);
+    TRACKED[count - 1].isolated.store(true, Ordering::Relaxed);
+    // SAFETY: the region is at least bytes.len() bytes long
+    unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), region.base, bytes.len()) };
+    Some((region.base as *mut c_char, region.protection))
+}
+
+/// Zeroize the mapping alloc_isolated returned as `value` and make it
+/// inaccessible, so any later access faults
+///
+/// Returns the length of the revoked mapping, or None if `value` is not an
+/// isolated value of this process or was already revoked (or mprotect
+/// failed, in which case the mapping is still zeroized). A revoked mapping
+/// is left out of the crash wipe.
+///
+/// # Safety
+/// Pointers into the mapping must no longer be dereferenced by the library
+#[cfg(not(feature = "minimal"))]
+pub(crate) unsafe fn revoke(value: *mut c_char) -> Option<usize> {
+    let _arena = ARENA.lock().unwrap_or_else(|err| err.into_inner());
+    let count = TRACKED_COUNT.load(Ordering::Acquire);
+    let entry = TRACKED[..count.min(MAX_TRACKED)].iter().find(|entry| {
+        entry.isolated.load(Ordering::Relaxed)
+            && entry.base.load(Ordering::Relaxed) == value.cast::<u8>()
+    })?;
+    let len = entry.len.load(Ordering::Relaxed);
+    if len == 0 {
+        return None;
+    }
+    crate::sys::explicit_bzero(value.cast(), len);
+    if libc::mprotect(value.cast(), len, libc::PROT_NONE) != 0 {
+        return None;
+    }
+    entry.len.store(0, Ordering::Relaxed);
+    Some(len)
+}
+
+/// Start a new region in a forked child
+///
+/// A secret memory region is shared with the parent, which keeps filling it
+/// from its own copy of the fill level. Anonymous regions are private copies
+/// and are simply not shared, so a new region is harmless for them.
+pub(crate) fn reset_after_fork() {
+    let mut arena = ARENA.lock().unwrap_or_else(|err| err.into_inner());
+    *arena = None;
+}
+
+/// The tracked secret memory containing `value`, if another process created
+/// it: the parent of this forked child, which shares the pages
+fn shared_with_parent(value: *const c_char) -> Option<&'static Tracked> {
+    // SAFETY: getpid has no preconditions
+    let pid = unsafe { libc::getpid() } as u32;
+    let addr = value as usize;
+    let count = TRACKED_COUNT.load(Ordering::Acquire);
+    TRACKED[..count.min(MAX_TRACKED)].iter().find(|entry| {
+        let owner = entry.owner.load(Ordering::Relaxed);
+        let base = entry.base.load(Ordering::Relaxed) as usize;
+        owner != 0
+            && owner != pid
+            && (base..base + entry.len.load(Ordering::Relaxed)).contains(&addr)
+    })
+}
+
+/// Whether `value` lies in secret memory this forked child shares with its
+/// parent
+pub(crate) fn is_shared(value: *const c_char) -> bool {
+    shared_with_parent(value).is_some()
+}
+
+/// Copy the `len` bytes at `value` into memory of this process if they lie
+/// in secret memory shared with the parent of this forked child
+///
+/// The copy is made the way the original was: an isolated value gets a
+/// mapping of its own, so it can still be revoked. Returns None, and leaves
+/// the value alone, if it is not shared.
+///
+/// # Safety
+/// `value` must be valid for reads of `len` bytes
+pub(crate) unsafe fn unshare(value: *mut c_char, len: usize) -> Option<(*mut c_char, Protection)> {
+    let entry = shared_with_parent(value)?;
+    let mut bytes = std::slice::from_raw_parts(value.cast::<u8>(), len).to_vec();
+    #[cfg(not(feature = "minimal"))]
+    let isolated = entry
+        .isolated
+        .load(Ordering::Relaxed)
+        .then(|| alloc_isolated(&bytes))
+        .flatten();
+    #[cfg(feature = "minimal")]
+    let isolated = {
+        let _ = entry;
+        None
+    };
+    let copy = isolated.unwrap_or_else(|| alloc_copy(&bytes));
+    crate::sys::explicit_bzero(bytes.as_mut_ptr().cast(), bytes.len());
+    Some(copy)
+}
+
+/// Copy `bytes` into a malloc'd buffer (the fallback when mapping fails)
+fn heap_copy(bytes: &[u8]) -> *mut c_char {
+    // SAFETY: malloc'd buffer is exactly bytes.len() bytes long
+    unsafe {
+        let cached = libc::malloc(bytes.len()) as *mut c_char;
+        if cached.is_null() {
+            log_line!(
+                Error,
+                "secmem",
+                None,
+                "Failed to allocate memory for token value"
+            );
+            std::process::abort();
+        }
+        ptr::copy_nonoverlapping(bytes.as_ptr(), cached.cast::<u8>(), bytes.len());
+        cached
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_alloc_copy() {
+        let (first, protection) = alloc_copy(b"ghp_first\0");
+        assert_ne!(protection, Protection::Heap);
+        let (second, _) = alloc_copy(b"second\0");
+        unsafe {
+            assert_eq!(CStr::from_ptr(first), c"ghp_first");
+            assert_eq!(CStr::from_ptr(second), c"second");
+        }
+
+        // Values larger than a page get their own mapping
+        let mut large = vec![b'x'; page_size() * 2];
+        large.push(0);
+        let (value, _) = alloc_copy(&large);
+        assert_eq!(
+            unsafe { CStr::from_ptr(value) }.to_bytes().len(),
+            page_size() * 2
+        );
+    }
+
+    /// Permissions of the mapping containing `addr`, from /proc/self/maps
+    fn permissions(addr: usize) -> Option<String> {
+        let maps = std::fs::read_to_string("/proc/self/maps").ok()?;
+        maps.lines().find_map(|line| {
+            let (range, rest) = line.split_once(' ')?;
+            let (start, end) = range.split_once('-')?;
+            let start = usize::from_str_radix(start, 16).ok()?;
+            let end = usize::from_str_radix(end, 16).ok()?;
+            (start..end).contains(&addr).then(|| rest[..4].to_string())
+        })
+    }
+
+    #[test]
+    #[cfg(not(feature = "minimal"))]
+    fn test_revoke() {
+        let (value, _) = alloc_isolated(b"ghp_revoked\0").unwrap();
+        let (neighbour, _) = alloc_copy(b"kept\0");
+        let addr = value as usize;
+        assert_eq!(addr % page_size(), 0);
+        assert!(permissions(addr).unwrap().starts_with("rw"));
+
+        assert_eq!(unsafe { revoke(value) }, Some(page_size()));
+        // Private ("---p") or secret memory ("---s")
+        assert!(permissions(addr).unwrap().starts_with("---"));
+        assert_eq!(unsafe { revoke(value) }, None);
+        // Only isolated values can be revoked
+        assert_eq!(unsafe { revoke(neighbour) }, None);
+        assert_eq!(unsafe { CStr::from_ptr(neighbour) }, c"kept");
+    }
+
+    #[test]
+    fn test_guard_pages() {
+        let page = page_size();
+        let region = unsafe { map_region(page * 2) }.unwrap();
+        let start = region.base as usize;
+        let end = start + region.len;
+        assert_eq!(region.len, page * 2);
+        assert!(permissions(start).unwrap().starts_with("rw"));
+        assert!(permissions(end - 1).unwrap().starts_with("rw"));
+        assert_eq!(permissions(start - 1).unwrap(), "---p");
+        assert_eq!(permissions(end).unwrap(), "---p");
+    }
+}
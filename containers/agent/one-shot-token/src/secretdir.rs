@@ -0,0 +1,220 @@
+//! Docker and OCI secret discovery (`/run/secrets`)
+//!
+//! Docker, Compose and Kubernetes mount each secret as a file in a directory,
+//! e.g. /run/secrets/github_token. With AWF_ONE_SHOT_SECRETS_DIR naming such a
+//! directory, every regular file in it is read at load and its content served
+//! through getenv under an environment-style name: the file name upper-cased,
+//! with every character other than a letter, digit or `_` turned into `_`
+//! (`github-token` becomes GITHUB_TOKEN). AWF_ONE_SHOT_SECRETS_MAP overrides
+//! the derived names with comma-separated `FILE=NAME` entries, and `-FILE`
+//! leaves a file out. Discovered names are protected tokens: a variable of the
+//! same name is scrubbed, and the file's value is served instead.
+//!
+//! Hidden files (Kubernetes keeps its `..data` link there) and anything that
+//! is not a regular file after following symlinks are skipped, as are files
+//! whose name yields no valid variable name. With AWF_ONE_SHOT_SECRETS_REVOKE
+//! the read permissions of each file are removed once it has been read, so
+//! processes started later cannot read it either; read-only mounts are left
+//! as they are and reported.
+
+use crate::{audit, secretfile};
+use std::ffi::{CStr, CString};
+
+/// A secret file found in the directory, not read yet
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct SecretFile {
+    /// Token name the content is served under
+    pub(crate) name: String,
+    pub(crate) path: CString,
+}
+
+/// The variable name derived from a file name, if it makes a valid one
+fn derive_name(file: &str) -> Option<String> {
+    if file.is_empty() || file.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let name: String = file
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    name.bytes().any(|b| b != b'_').then_some(name)
+}
+
+/// Parse AWF_ONE_SHOT_SECRETS_MAP into (file, name) overrides, where a None
+/// name leaves the file out
+pub(crate) fn parse_map(config: &str) -> Vec<(String, Option<String>)> {
+    config
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            if let Some(file) = entry.strip_prefix('-') {
+                return Some((file.trim().to_string(), None));
+            }
+            let (file, name) = entry.split_once('=')?;
+            let (file, name) = (file.trim(), name.trim());
+            if file.is_empty() || name.is_empty() {
+                return None;
+            }
+            Some((file.to_string(), Some(name.to_string())))
+        })
+        .collect()
+}
+
+/// The token name for `file`, from the map or derived from the file name
+fn name_for(file: &str, map: &[(String, Option<String>)]) -> Option<String> {
+    match map.iter().find(|(mapped, _)| mapped == file) {
+        Some((_, name)) => name.clone(),
+        None => derive_name(file),
+    }
+}
+
+/// List the secret files in `dir`, sorted by file name
+///
+/// When two files map to the same name, the first one wins.
+pub(crate) fn list(dir: &str, map_config: Option<&str>) -> Vec<SecretFile> {
+    let map = map_config.map(parse_map).unwrap_or_default();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        audit::emit(
+            "secret_discovered",
+            "*",
+            &format!("action=unreadable path={}", audit::detail_word(dir)),
+        );
+        return Vec::new();
+    };
+    let mut files: Vec<(String, std::path::PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| Some((entry.file_name().into_string().ok()?, entry.path())))
+        .filter(|(file, _)| !file.starts_with('.'))
+        .filter(|(_, path)| path.metadata().is_ok_and(|meta| meta.is_file()))
+        .collect();
+    files.sort();
+
+    let mut found: Vec<SecretFile> = Vec::new();
+    for (file, path) in files {
+        let Some(name) = name_for(&file, &map) else {
+            continue;
+        };
+        let Some(path) = path.to_str().and_then(|path| CString::new(path).ok()) else {
+            continue;
+        };
+        if found.iter().all(|secret| secret.name != name) {
+            found.push(SecretFile { name, path });
+        }
+    }
+    found
+}
+
+/// Read a discovered secret file, removing its read permissions with
+/// `revoke`
+///
+/// Emits a `secret_discovered` audit event either way. Returns None if the
+/// file could not be read or does not hold a valid value.
+///
+/// # Safety
+/// Only performs system calls on the file's path and the descriptor it opens
+pub(crate) unsafe fn read(secret: &SecretFile, revoke: bool) -> Option<CString> {
+    let shown = audit::detail_word(&path_str(&secret.path));
+    match secretfile::read_value(&secret.path, revoke) {
+        Ok((value, revoked)) => {
+            audit::emit(
+                "secret_discovered",
+                &secret.name,
+                &format!(
+                    "path={} bytes={} revoked={}",
+                    shown,
+                    value.as_bytes().len(),
+                    revoked
+                ),
+            );
+            Some(value)
+        }
+        Err(err) => {
+            let error = audit::detail_word(&err.to_string());
+            audit::emit(
+                "secret_discovered",
+                &secret.name,
+                &format!("action=unreadable path={} error={}", shown, error),
+            );
+            None
+        }
+    }
+}
+
+/// The path of a discovered file for log messages
+fn path_str(path: &CStr) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_derive_name() {
+        assert_eq!(derive_name("github_token").as_deref(), Some("GITHUB_TOKEN"));
+        assert_eq!(
+            derive_name("npm-token.txt").as_deref(),
+            Some("NPM_TOKEN_TXT")
+        );
+        assert_eq!(derive_name("API_KEY").as_deref(), Some("API_KEY"));
+        assert_eq!(derive_name("1password"), None);
+        assert_eq!(derive_name("--"), None);
+        assert_eq!(derive_name(""), None);
+    }
+
+    #[test]
+    fn test_parse_map() {
+        let map = parse_map(" gh = GITHUB_TOKEN ,-ssh_key,bad,=X,y=,");
+        assert_eq!(
+            map,
+            vec![
+                ("gh".to_string(), Some("GITHUB_TOKEN".to_string())),
+                ("ssh_key".to_string(), None),
+            ]
+        );
+        assert_eq!(name_for("gh", &map).as_deref(), Some("GITHUB_TOKEN"));
+        assert_eq!(name_for("ssh_key", &map), None);
+        assert_eq!(name_for("npm", &map).as_deref(), Some("NPM"));
+    }
+
+    #[test]
+    fn test_list_and_read() {
+        let dir = std::env::temp_dir().join(format!("awf-secretdir-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("github-token"), "ghp_value\n").unwrap();
+        std::fs::write(dir.join("GITHUB_TOKEN"), "first").unwrap();
+        std::fs::write(dir.join(".hidden"), "x").unwrap();
+        std::fs::write(dir.join("skipped"), "x").unwrap();
+        std::fs::write(dir.join("db"), "hunter2").unwrap();
+        std::os::unix::fs::symlink(dir.join("db"), dir.join("db_link")).unwrap();
+
+        let found = list(dir.to_str().unwrap(), Some("db=DATABASE_PASSWORD,-skipped"));
+        let names: Vec<&str> = found.iter().map(|secret| secret.name.as_str()).collect();
+        assert_eq!(names, ["GITHUB_TOKEN", "DATABASE_PASSWORD", "DB_LINK"]);
+        assert_eq!(
+            path_str(&found[0].path),
+            dir.join("GITHUB_TOKEN").to_str().unwrap()
+        );
+
+        let value = unsafe { read(&found[2], false) }.unwrap();
+        assert_eq!(value.as_bytes(), b"hunter2");
+        let value = unsafe { read(&found[1], true) }.unwrap();
+        assert_eq!(value.as_bytes(), b"hunter2");
+        let mode = std::fs::metadata(dir.join("db"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o444, 0);
+
+        assert!(list(dir.join("missing").to_str().unwrap(), None).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+//! File-indirection secrets (`@/path` values)
+//!
+//! With AWF_ONE_SHOT_FILE_SECRETS, a protected token whose value is `@`
+//! followed by an absolute path, e.g. `GITHUB_TOKEN=@/run/secrets/github_token`,
+//! names a file holding the real value, so the host never has to put the
+//! plaintext in an environment. When the token is cached (on its first read,
+//! or at load with AWF_ONE_SHOT_EAGER) the file is read, its content becomes
+//! the cached value, and the file is overwritten with zeros and unlinked; the
+//! variable itself is scrubbed like any other token.
+//!
+//! One trailing newline is dropped from the content. The file must be a
+//! regular file; a symlink in the last component is not followed. A file that
+//! cannot be written or removed (a read-only mount, as Docker secrets are) is
+//! still read, and the audit event says what was left behind. A file that
+//! cannot be read leaves the token unset.
+
+use crate::{audit, open, sys};
+use libc::c_int;
+use std::ffi::{CStr, CString};
+use std::io;
+
+/// Largest secret file read
+const MAX_SIZE: usize = 64 * 1024;
+
+/// The path a token value points to, if it is a file indirection
+pub(crate) fn indirection(value: &[u8]) -> Option<&[u8]> {
+    value
+        .strip_prefix(b"@")
+        .filter(|path| path.starts_with(b"/"))
+}
+
+/// Drop one trailing newline (LF or CRLF)
+fn trim_newline(content: &mut Vec<u8>) {
+    if content.last() == Some(&b'\n') {
+        content.pop();
+        if content.last() == Some(&b'\r') {
+            content.pop();
+        }
+    }
+}
+
+/// Open the secret file, for writing as well if possible
+///
+/// # Safety
+/// Only performs system calls on `path` and the descriptor it opens
+unsafe fn open_file(path: &CStr) -> io::Result<(c_int, bool)> {
+    let flags = libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    let fd = open::call_real_open(path.as_ptr(), libc::O_RDWR | flags);
+    if fd >= 0 {
+        return Ok((fd, true));
+    }
+    let err = io::Error::last_os_error();
+    if !matches!(
+        err.raw_os_error(),
+        Some(libc::EACCES | libc::EROFS | libc::EPERM | libc::ETXTBSY)
+    ) {
+        return Err(err);
+    }
+    let fd = open::call_real_open(path.as_ptr(), libc::O_RDONLY | flags);
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((fd, false))
+}
+
+/// Read the whole file from the start
+///
+/// # Safety
+/// `fd` must be an open descriptor
+unsafe fn read_fd(fd: c_int, size: usize) -> io::Result<Vec<u8>> {
+    let mut content = Vec::with_capacity(size);
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = libc::read(fd, buf.as_mut_ptr().cast(), buf.len());
+        match n {
+            0 => break,
+            n if n > 0 => content.extend_from_slice(&buf[..n as usize]),
+            _ if *sys::errno_location() == libc::EINTR => continue,
+            _ => {
+                let err = io::Error::last_os_error();
+                zero(&mut content);
+                return Err(err);
+            }
+        }
+        if content.len() > MAX_SIZE {
+            zero(&mut content);
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+    }
+    zero(&mut buf);
+    Ok(content)
+}
+
+/// Overwrite the first `size` bytes of the file with zeros and flush them
+///
+/// # Safety
+/// `fd` must be a descriptor open for writing
+unsafe fn overwrite(fd: c_int, size: usize) -> bool {
+    let zeros = [0u8; 4096];
+    let mut offset = 0;
+    while offset < size {
+        let len = zeros.len().min(size - offset);
+        let n = libc::pwrite(fd, zeros.as_ptr().cast(), len, offset as libc::off_t);
+        if n < 0 && *sys::errno_location() == libc::EINTR {
+            continue;
+        }
+        if n <= 0 {
+            return false;
+        }
+        offset += n as usize;
+    }
+    libc::fsync(fd) == 0
+}
+
+/// Check that `fd` is a regular file and read it; the descriptor is closed
+/// on failure
+///
+/// # Safety
+/// `fd` must be an open descriptor owned by the caller
+unsafe fn read_regular(fd: c_int) -> io::Result<(Vec<u8>, libc::stat)> {
+    let mut stat: libc::stat = std::mem::zeroed();
+    let content = if libc::fstat(fd, &mut stat) != 0 {
+        Err(io::Error::last_os_error())
+    } else if stat.st_mode & libc::S_IFMT != libc::S_IFREG {
+        Err(io::ErrorKind::InvalidInput.into())
+    } else {
+        read_fd(fd, stat.st_size as usize)
+    };
+    if content.is_err() {
+        libc::close(fd);
+    }
+    Ok((content?, stat))
+}
+
+/// Turn file content into a token value, dropping one trailing newline
+fn into_value(mut content: Vec<u8>) -> io::Result<CString> {
+    trim_newline(&mut content);
+    CString::new(content).map_err(|err| {
+        zero(&mut err.into_vec());
+        io::ErrorKind::InvalidData.into()
+    })
+}
+
+/// Read the secret file at `path`, then shred it
+///
+/// Returns the content and what was done to the file: "shredded"
+/// (overwritten and unlinked), "overwritten", "unlinked" or "kept".
+///
+/// # Safety
+/// Only performs system calls on `path` and the descriptor it opens
+unsafe fn read_and_shred(path: &CStr) -> io::Result<(Vec<u8>, &'static str)> {
+    let (fd, writable) = open_file(path)?;
+    let (content, stat) = read_regular(fd)?;
+    let overwritten = writable && overwrite(fd, content.len().max(stat.st_size as usize));
+    libc::close(fd);
+    let unlinked = libc::unlink(path.as_ptr()) == 0;
+    let action = match (overwritten, unlinked) {
+        (true, true) => "shredded",
+        (true, false) => "overwritten",
+        (false, true) => "unlinked",
+        (false, false) => "kept",
+    };
+    Ok((content, action))
+}
+
+/// Read the secret file at `path` without changing its content; with
+/// `revoke` its read permissions are removed afterwards
+///
+/// Unlike `@/path` files, symlinks are followed: secret volumes are made of
+/// them. Returns the value and whether the permissions were removed.
+///
+/// # Safety
+/// Only performs system calls on `path` and the descriptor it opens
+pub(crate) unsafe fn read_value(path: &CStr, revoke: bool) -> io::Result<(CString, bool)> {
+    let fd = open::call_real_open(path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC);
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (content, stat) = read_regular(fd)?;
+    let mode = stat.st_mode & 0o7777 & !0o444;
+    let revoked = revoke && libc::fchmod(fd, mode) == 0;
+    libc::close(fd);
+    Ok((into_value(content)?, revoked))
+}
+
+/// Take the value of `token` from the file at `path`, shredding the file
+///
+/// Emits a `secret_file` audit event either way. Returns None if the file
+/// could not be read or does not hold a valid value.
+///
+/// # Safety
+/// Only performs system calls on `path` and the descriptor it opens
+pub(crate) unsafe fn take(token: &str, path: &[u8]) -> Option<CString> {
+    let shown = audit::detail_word(&String::from_utf8_lossy(path));
+    let result = CString::new(path)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+        .and_then(|path| {
+            let (content, action) = read_and_shred(&path)?;
+            Ok((into_value(content)?, action))
+        });
+    let (value, action) = match result {
+        Ok(read) => read,
+        Err(err) => {
+            let error = audit::detail_word(&err.to_string());
+            audit::emit(
+                "secret_file",
+                token,
+                &format!("action=unreadable path={} error={}", shown, error),
+            );
+            return None;
+        }
+    };
+    audit::emit(
+        "secret_file",
+        token,
+        &format!(
+            "action={} path={} bytes={}",
+            action,
+            shown,
+            value.as_bytes().len()
+        ),
+    );
+    Some(value)
+}
+
+fn zero(bytes: &mut [u8]) {
+    // SAFETY: bytes is a writable buffer of the given length
+    unsafe { sys::explicit_bzero(bytes.as_mut_ptr().cast(), bytes.len()) };
+}
+
+/// Zero a value taken from a file (or the broker, or decrypted) once it has
+/// been cached
+pub(crate) fn wipe(value: CString) {
+    zero(&mut value.into_bytes_with_nul());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("awf-secretfile-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_indirection() {
+        assert_eq!(
+            indirection(b"@/run/secrets/token"),
+            Some(&b"/run/secrets/token"[..])
+        );
+        assert_eq!(indirection(b"@relative"), None);
+        assert_eq!(indirection(b"ghp_abc"), None);
+        assert_eq!(indirection(b"/run/secrets/token"), None);
+    }
+
+    #[test]
+    fn test_trim_newline() {
+        let trim = |content: &[u8]| {
+            let mut content = content.to_vec();
+            trim_newline(&mut content);
+            content
+        };
+        assert_eq!(trim(b"value\n"), b"value");
+        assert_eq!(trim(b"value\r\n"), b"value");
+        assert_eq!(trim(b"value\n\n"), b"value\n");
+        assert_eq!(trim(b"value"), b"value");
+    }
+
+    #[test]
+    fn test_take_shreds() {
+        let path = temp_file("shred", b"s3cr3t-value\n");
+        let value = unsafe { take("TEST_TOKEN", path.to_str().unwrap().as_bytes()) };
+        assert_eq!(value.unwrap().as_bytes(), b"s3cr3t-value");
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_take_refuses() {
+        let missing = std::env::temp_dir().join("awf-secretfile-missing");
+        assert!(unsafe { take("TEST_TOKEN", missing.to_str().unwrap().as_bytes()) }.is_none());
+        let dir = std::env::temp_dir();
+        assert!(unsafe { take("TEST_TOKEN", dir.to_str().unwrap().as_bytes()) }.is_none());
+        let path = temp_file("nul", b"a\0b");
+        assert!(unsafe { take("TEST_TOKEN", path.to_str().unwrap().as_bytes()) }.is_none());
+    }
+}
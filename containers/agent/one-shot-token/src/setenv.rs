@@ -0,0 +1,316 @@
+//! Interception of environment writes (setenv/putenv/unsetenv/clearenv)
+//!
+//! Once a token has been scrubbed, a program could put it straight back into
+//! /proc/self/environ with `setenv("GITHUB_TOKEN", ...)`. Writes to protected
+//! names (and their aliases) therefore update the in-memory cache instead of
+//! the real environment: later getenv calls see the new value, but it never
+//! lands in environ. Writes to deny-listed names are dropped, and writes or
+//! unsets of the firewall's proxy variables are blocked (see the proxy module).
+//! clearenv caches every outstanding token first, so protected tokens keep
+//! being served after the environment is rebuilt. A write that changes
+//! AWF_STEP_ID starts a new step (see the step module).
+//!
+//! The library's own scrubbing must use call_real_unsetenv: the unsetenv
+//! interposer takes the state lock, which scrubbing code already holds.
+
+use crate::envname::EnvName;
+use crate::{
+    alloc_cached_value, audit, call_real_getenv, eager_scrub, egress, is_denied_token, is_watched,
+    lock_state, proxy, resolve_next, resolve_sensitive_token, scrub_token_group, step, token_group,
+    Mode, ScrubMode,
+};
+use libc::{c_char, c_int};
+use once_cell::sync::Lazy;
+use std::ffi::{CStr, CString};
+
+type SetenvFn = unsafe extern "C" fn(*const c_char, *const c_char, c_int) -> c_int;
+type PutenvFn = unsafe extern "C" fn(*mut c_char) -> c_int;
+type UnsetenvFn = unsafe extern "C" fn(*const c_char) -> c_int;
+type ClearenvFn = unsafe extern "C" fn() -> c_int;
+
+/// Cached pointer to the real setenv function
+static REAL_SETENV: Lazy<SetenvFn> = Lazy::new(|| {
+    // SAFETY: setenv has the SetenvFn signature
+    unsafe { std::mem::transmute::<*mut libc::c_void, SetenvFn>(resolve_next(c"setenv")) }
+});
+
+/// Cached pointer to the real putenv function
+static REAL_PUTENV: Lazy<PutenvFn> = Lazy::new(|| {
+    // SAFETY: putenv has the PutenvFn signature
+    unsafe { std::mem::transmute::<*mut libc::c_void, PutenvFn>(resolve_next(c"putenv")) }
+});
+
+/// Cached pointer to the real unsetenv function
+static REAL_UNSETENV: Lazy<UnsetenvFn> = Lazy::new(|| {
+    // SAFETY: unsetenv has the UnsetenvFn signature
+    unsafe { std::mem::transmute::<*mut libc::c_void, UnsetenvFn>(resolve_next(c"unsetenv")) }
+});
+
+/// Cached pointer to the real clearenv function
+static REAL_CLEARENV: Lazy<ClearenvFn> = Lazy::new(|| {
+    // SAFETY: clearenv has the ClearenvFn signature
+    unsafe { std::mem::transmute::<*mut libc::c_void, ClearenvFn>(resolve_next(c"clearenv")) }
+});
+
+/// Call the real setenv function, bypassing the interposer
+///
+/// # Safety
+/// `name` and `value` must be valid null-terminated C strings
+pub(crate) unsafe fn call_real_setenv(
+    name: *const c_char,
+    value: *const c_char,
+    overwrite: c_int,
+) -> c_int {
+    (*REAL_SETENV)(name, value, overwrite)
+}
+
+/// Call the real unsetenv function, bypassing the interposer
+///
+/// # Safety
+/// The `name` parameter must be a valid null-terminated C string
+pub(crate) unsafe fn call_real_unsetenv(name: *const c_char) -> c_int {
+    (*REAL_UNSETENV)(name)
+}
+
+/// Apply an environment write to a protected or denied name
+///
+/// Returns the value to hand back to the caller if the write was intercepted,
+/// or None if it should be passed through to libc.
+fn intercept_write(name: &[u8], value: &CStr, overwrite: bool, via: &str) -> Option<c_int> {
+    if !is_watched(name) {
+        return None;
+    }
+    let mut state = lock_state();
+    let display = String::from_utf8_lossy(name);
+
+    if state.mode == Mode::Observe {
+        let action = if is_denied_token(&state, name) {
+            "drop"
+        } else if resolve_sensitive_token(&state, name).is_some() {
+            "cache"
+        } else {
+            return None;
+        };
+        audit::emit(
+            "observed",
+            &display,
+            &format!("would={} via={}", action, via),
+        );
+        return None;
+    }
+
+    // Deny-listed variables may never enter the environment
+    if is_denied_token(&state, name) {
+        let name_cstr = CString::new(name).ok()?;
+        // SAFETY: name_cstr is the C string form of name
+        unsafe {
+            scrub_token_group(
+                &[EnvName::new(name)],
+                &[name_cstr],
+                ScrubMode::Unset,
+                state.debug_enabled,
+            )
+        };
+        audit::emit("denied", &display, &format!("via={}", via));
+        return Some(0);
+    }
+
+    let canonical = resolve_sensitive_token(&state, name)?.clone();
+    let group = token_group(&state, &canonical);
+    let group_cstrs: Vec<CString> = group
+        .iter()
+        .map(|member| member.to_cstring().unwrap_or_default())
+        .collect();
+
+    // setenv(..., 0) must not replace an existing value
+    if !overwrite {
+        let has_value = match state.cache.get(&canonical) {
+            Some(entry) => !entry.value.is_null(),
+            // SAFETY: group_cstrs are valid C strings
+            None => group_cstrs
+                .iter()
+                .any(|member| !unsafe { call_real_getenv(member.as_ptr()) }.is_null()),
+        };
+        if has_value {
+            return Some(0);
+        }
+    }
+
+    // Replace the cached value. The previous allocation is kept alive because
+    // callers may still hold pointers from earlier reads.
+    let cached = alloc_cached_value(&state, &canonical, value);
+    match state.cache.get_mut(&canonical) {
+        Some(entry) => {
+            entry.value = cached.value;
+            entry.sealed = cached.sealed;
+            entry.protection = cached.protection;
+            entry.wiped = None;
+        }
+        None => {
+            state.cache.insert(canonical.clone(), cached);
+        }
+    }
+
+    egress::publish_secrets(&state);
+
+    // Remove any value still sitting in environ (e.g. a token never read)
+    // SAFETY: group_cstrs are the C string forms of group
+    unsafe { scrub_token_group(&group, &group_cstrs, state.scrub_mode, state.debug_enabled) };
+
+    if state.debug_enabled {
+        log_line!(
+            Debug,
+            "setenv",
+            Some(&display),
+            "Token {} set via {}: cached, not written to environment",
+            display,
+            via
+        );
+    }
+    Some(0)
+}
+
+/// Intercepted setenv function
+///
+/// Writes to protected tokens update the cache and never reach the real
+/// environment; writes to deny-listed names are dropped. All other variables
+/// are passed through to the real setenv.
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// `name` and `value` must be null or valid null-terminated C strings.
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn setenv(
+    name: *const c_char,
+    value: *const c_char,
+    overwrite: c_int,
+) -> c_int {
+    if !name.is_null() && !value.is_null() {
+        let name = CStr::from_ptr(name).to_bytes();
+        let value = CStr::from_ptr(value);
+        if let Ok(name_str) = std::str::from_utf8(name) {
+            if proxy::block_tampering(name_str, Some(value), "setenv") {
+                return 0;
+            }
+        }
+        if let Some(result) = intercept_write(name, value, overwrite != 0, "setenv") {
+            return result;
+        }
+    }
+    let result = (*REAL_SETENV)(name, value, overwrite);
+    if result == 0 && !name.is_null() {
+        step::written(CStr::from_ptr(name).to_bytes(), "setenv");
+    }
+    result
+}
+
+/// Intercepted putenv function
+///
+/// `NAME=value` strings for protected tokens update the cache instead of being
+/// linked into environ (so later changes to the caller's buffer are not seen).
+/// Strings without `=`, which glibc treats as an unset, are passed through.
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// `string` must be null or a valid null-terminated C string.
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn putenv(string: *mut c_char) -> c_int {
+    if !string.is_null() {
+        let entry = CStr::from_ptr(string).to_bytes();
+        if let Some(eq) = entry.iter().position(|&b| b == b'=') {
+            let (name, value) = (&entry[..eq], CStr::from_ptr(string.add(eq + 1)));
+            if let Ok(name_str) = std::str::from_utf8(name) {
+                if proxy::block_tampering(name_str, Some(value), "putenv") {
+                    return 0;
+                }
+            }
+            if let Some(result) = intercept_write(name, value, true, "putenv") {
+                return result;
+            }
+        }
+    }
+    let result = (*REAL_PUTENV)(string);
+    if result == 0 && !string.is_null() {
+        let entry = CStr::from_ptr(string).to_bytes();
+        let name = entry.split(|&b| b == b'=').next().unwrap_or_default();
+        step::written(name, "putenv");
+    }
+    result
+}
+
+/// Intercepted unsetenv function
+///
+/// Unsetting a protected proxy variable is blocked (and reported); everything
+/// else is passed through to the real unsetenv.
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// `name` must be null or a valid null-terminated C string.
+#[cfg_attr(all(feature = "preload", not(target_os = "macos")), no_mangle)]
+pub unsafe extern "C" fn unsetenv(name: *const c_char) -> c_int {
+    if !name.is_null() {
+        if let Ok(name_str) = CStr::from_ptr(name).to_str() {
+            if proxy::block_tampering(name_str, None, "unsetenv") {
+                return 0;
+            }
+        }
+    }
+    call_real_unsetenv(name)
+}
+
+/// Intercepted clearenv function
+///
+/// Protected tokens that have not been read yet are cached before the
+/// environment is wiped, so getenv keeps serving them once the program
+/// rebuilds its environment. Proxy variables are restored afterwards when
+/// proxy protection is enabled. Every call is reported with an
+/// `environ_cleared` audit event.
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn clearenv() -> c_int {
+    let mut state = lock_state();
+
+    if state.mode == Mode::Observe {
+        drop(state);
+        audit::emit("observed", "*", "would=cache_before_clear via=clearenv");
+        return (*REAL_CLEARENV)();
+    }
+
+    eager_scrub(&mut state);
+    let cached = state
+        .cache
+        .values()
+        .filter(|entry| !entry.value.is_null())
+        .count();
+
+    let proxy_values: Vec<(CString, CString)> = if state.protect_proxy {
+        proxy::PROXY_VARIABLES
+            .iter()
+            .filter_map(|name| {
+                let name = CString::new(*name).ok()?;
+                let value = call_real_getenv(name.as_ptr());
+                (!value.is_null()).then(|| (name, CStr::from_ptr(value).to_owned()))
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let result = (*REAL_CLEARENV)();
+    for (name, value) in &proxy_values {
+        (*REAL_SETENV)(name.as_ptr(), value.as_ptr(), 1);
+    }
+
+    audit::emit(
+        "environ_cleared",
+        "*",
+        &format!(
+            "cached_tokens={} restored_proxy={}",
+            cached,
+            proxy_values.len()
+        ),
+    );
+    result
+}
@@ -0,0 +1,323 @@
+//! Live access counters in shared memory (AWF_ONE_SHOT_STATS_SHM)
+//!
+//! The access report is only written at exit. With AWF_ONE_SHOT_STATS_SHM set
+//! to a POSIX shared memory name, every process of the agent run maps the
+//! same small segment (created by the first one) and counts the reads it
+//! serves there, so the host can poll `/dev/shm/<name>` while the agent is
+//! still running.
+//!
+//! The segment has a fixed layout of native-endian integers:
+//!
+//! - header (24 bytes): magic `AWFSTAT1`, version (u32, 2), slot count
+//!   (u32), slots in use (u32), padding (u32)
+//! - `SLOTS` slots of 96 bytes: token name (64 bytes, NUL-padded), reads
+//!   (u64), first and last read (u64 milliseconds since the Unix epoch, 0 if
+//!   never read), padding (u64)
+//! - two getenv latency histograms of 280 bytes, sensitive then passthrough,
+//!   summed over the processes that sample (see the latency module):
+//!   samples, total and maximum nanoseconds, and 32 buckets (u64 each)
+//!
+//! Slots are claimed for the configured tokens when a process maps the
+//! segment, under an flock(2) on the segment so processes agree on them;
+//! counters are then updated with atomic operations and no lock. A slot is
+//! in use once its name is written; pollers read `slots in use` first.
+
+use crate::envname::EnvName;
+use crate::latency::{Class, Histogram};
+use libc::c_int;
+use std::ffi::CString;
+use std::io;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+/// Segment signature
+const MAGIC: [u8; 8] = *b"AWFSTAT1";
+
+/// Layout version
+const VERSION: u32 = 2;
+
+/// Number of token slots
+const SLOTS: usize = 64;
+
+/// Size of a slot's name field, including the terminating NUL
+const NAME_LEN: usize = 64;
+
+#[repr(C)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    slots: u32,
+    used: AtomicU32,
+    _pad: u32,
+}
+
+#[repr(C)]
+struct Slot {
+    name: [u8; NAME_LEN],
+    reads: AtomicU64,
+    first_read: AtomicU64,
+    last_read: AtomicU64,
+    _pad: u64,
+}
+
+/// Offset of the latency histograms
+const LATENCY: usize = std::mem::size_of::<Header>() + SLOTS * std::mem::size_of::<Slot>();
+
+/// Total size of the segment
+const SIZE: usize = LATENCY + 2 * std::mem::size_of::<Histogram>();
+
+/// A mapped segment and the slots of this process's tokens
+pub(crate) struct Segment {
+    base: *mut u8,
+    tokens: Vec<(EnvName, *const Slot)>,
+}
+
+// SAFETY: the mapping lives until the process exits and is only updated
+// through atomics
+unsafe impl Send for Segment {}
+unsafe impl Sync for Segment {}
+
+static SEGMENT: OnceLock<Segment> = OnceLock::new();
+
+/// Milliseconds since the Unix epoch
+fn now_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Shared memory object name for a configured value (a leading `/` is added)
+fn object_name(value: &str) -> io::Result<CString> {
+    let value = value.trim().trim_start_matches('/');
+    if value.is_empty() || value.contains('/') || value.len() > 250 {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    CString::new(format!("/{}", value)).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+impl Segment {
+    /// Map the segment named `value`, creating it if needed, and claim slots
+    /// for `tokens`
+    pub(crate) fn open(value: &str, tokens: &[EnvName]) -> io::Result<Segment> {
+        let name = object_name(value)?;
+        // SAFETY: name is a valid C string
+        let fd = unsafe {
+            libc::shm_open(
+                name.as_ptr(),
+                libc::O_RDWR | libc::O_CREAT | libc::O_CLOEXEC,
+                0o600,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd is a valid descriptor owned by this function
+        let result = unsafe { Self::map(fd, tokens) };
+        // SAFETY: the mapping stays valid after the descriptor is closed
+        unsafe { libc::close(fd) };
+        result
+    }
+
+    /// Initialize or validate the segment behind `fd` and map it
+    ///
+    /// # Safety
+    /// `fd` must be a valid shared memory descriptor
+    unsafe fn map(fd: c_int, tokens: &[EnvName]) -> io::Result<Segment> {
+        if libc::flock(fd, libc::LOCK_EX) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let result = Self::map_locked(fd, tokens);
+        libc::flock(fd, libc::LOCK_UN);
+        result
+    }
+
+    /// # Safety
+    /// `fd` must be a valid shared memory descriptor, locked with flock
+    unsafe fn map_locked(fd: c_int, tokens: &[EnvName]) -> io::Result<Segment> {
+        let mut st: libc::stat = std::mem::zeroed();
+        if libc::fstat(fd, &mut st) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let created = st.st_size == 0;
+        if created && libc::ftruncate(fd, SIZE as libc::off_t) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if !created && (st.st_size as usize) < SIZE {
+            return Err(io::Error::from_raw_os_error(libc::EPROTO));
+        }
+        let base = libc::mmap(
+            std::ptr::null_mut(),
+            SIZE,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        );
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let segment = Segment {
+            base: base.cast(),
+            tokens: Vec::new(),
+        };
+        let header = &mut *(base as *mut Header);
+        if created {
+            header.magic = MAGIC;
+            header.version = VERSION;
+            header.slots = SLOTS as u32;
+        } else if header.magic != MAGIC
+            || header.version != VERSION
+            || header.slots as usize != SLOTS
+        {
+            libc::munmap(base, SIZE);
+            return Err(io::Error::from_raw_os_error(libc::EPROTO));
+        }
+        Ok(segment.claim(tokens))
+    }
+
+    /// Header of the segment
+    fn header(&self) -> &Header {
+        // SAFETY: base maps a validated segment of SIZE bytes
+        unsafe { &*(self.base as *const Header) }
+    }
+
+    /// Slot `index`
+    fn slot(&self, index: usize) -> *mut Slot {
+        // SAFETY: index < SLOTS, so the slot lies within the mapping
+        unsafe { (self.base.add(std::mem::size_of::<Header>()) as *mut Slot).add(index) }
+    }
+
+    /// Find or claim the slots of `tokens`; tokens that do not fit are not
+    /// counted. Must be called with the segment locked.
+    fn claim(mut self, tokens: &[EnvName]) -> Segment {
+        for token in tokens {
+            if token.is_empty() || token.len() >= NAME_LEN {
+                continue;
+            }
+            let used = (self.header().used.load(Ordering::Acquire) as usize).min(SLOTS);
+            // SAFETY: slots below `used` have their name written
+            let existing = (0..used)
+                .map(|index| self.slot(index))
+                .find(|&slot| unsafe { slot_name(&*slot) } == token.as_bytes());
+            let slot = match existing {
+                Some(slot) => slot,
+                None if used < SLOTS => {
+                    let slot = self.slot(used);
+                    // SAFETY: the slot is unused and the segment is locked
+                    let name = unsafe { &mut (*slot).name };
+                    name[..token.len()].copy_from_slice(token);
+                    self.header().used.store(used as u32 + 1, Ordering::Release);
+                    slot
+                }
+                None => continue,
+            };
+            self.tokens.push((token.clone(), slot));
+        }
+        self
+    }
+
+    /// Latency histogram of `class`
+    fn latency(&self, class: Class) -> &Histogram {
+        // SAFETY: both histograms lie within the mapping, after the slots
+        unsafe { &*(self.base.add(LATENCY) as *const Histogram).add(class.index()) }
+    }
+
+    /// Count a served read of `token` at `time`
+    pub(crate) fn record_read(&self, token: &[u8], time: SystemTime) {
+        let Some(&(_, slot)) = self.tokens.iter().find(|(name, _)| *name == *token) else {
+            return;
+        };
+        // SAFETY: slot points into the mapping; counters are atomics
+        let slot = unsafe { &*slot };
+        let millis = now_millis(time);
+        let _ = slot
+            .first_read
+            .compare_exchange(0, millis, Ordering::Relaxed, Ordering::Relaxed);
+        slot.last_read.fetch_max(millis, Ordering::Relaxed);
+        slot.reads.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Name stored in a slot, without NUL padding
+fn slot_name(slot: &Slot) -> &[u8] {
+    let len = slot
+        .name
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(NAME_LEN);
+    &slot.name[..len]
+}
+
+/// Count reads in the segment named `value` from now on
+pub(crate) fn install(value: &str, tokens: &[EnvName]) -> io::Result<()> {
+    let segment = Segment::open(value, tokens)?;
+    let _ = SEGMENT.set(segment);
+    Ok(())
+}
+
+/// Count a served read of `token`, if a segment is configured
+pub(crate) fn record_read(token: &[u8]) {
+    if let Some(segment) = SEGMENT.get() {
+        segment.record_read(token, SystemTime::now());
+    }
+}
+
+/// Count a sampled getenv call of `class` that took `nanos`, if a segment
+/// is configured
+pub(crate) fn record_latency(class: Class, nanos: u64) {
+    if let Some(segment) = SEGMENT.get() {
+        segment.latency(class).record(nanos);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_segment() {
+        let name = format!("awf-stats-test-{}", std::process::id());
+        let tokens = ["GITHUB_TOKEN".into(), "GH_TOKEN".into()];
+        let first = Segment::open(&name, &tokens).unwrap();
+        let second = Segment::open(&name, &["GH_TOKEN".into(), "OPENAI_API_KEY".into()]).unwrap();
+
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1500);
+        first.record_read(b"GH_TOKEN", time);
+        second.record_read(b"GH_TOKEN", time + Duration::from_secs(1));
+        second.record_read(b"OPENAI_API_KEY", time);
+        second.record_read(b"ANTHROPIC_API_KEY", time);
+        first.latency(Class::Passthrough).record(100);
+        second.latency(Class::Passthrough).record(3000);
+
+        let path = format!("/dev/shm/{}", name);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bytes.len(), SIZE);
+        assert_eq!(&bytes[..8], b"AWFSTAT1");
+        let word =
+            |offset: usize| u32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let long =
+            |offset: usize| u64::from_ne_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        assert_eq!((word(8), word(12), word(16)), (2, 64, 3));
+
+        // Second slot: GH_TOKEN, shared by both mappings
+        let slot = 24 + 96;
+        assert_eq!(&bytes[slot..slot + 9], b"GH_TOKEN\0");
+        assert_eq!(long(slot + 64), 2);
+        assert_eq!((long(slot + 72), long(slot + 80)), (1500, 2500));
+        // Third slot: OPENAI_API_KEY, claimed by the second process
+        assert_eq!(&bytes[slot + 96..slot + 110], b"OPENAI_API_KEY");
+        assert_eq!(long(slot + 96 + 64), 1);
+        // Passthrough histogram, summed over both mappings
+        let histogram = LATENCY + 280;
+        assert_eq!(LATENCY, 24 + 64 * 96);
+        assert_eq!(
+            (long(histogram), long(histogram + 8), long(histogram + 16)),
+            (2, 3100, 3000)
+        );
+        assert_eq!(long(histogram + 24 + 6 * 8), 1);
+        assert_eq!(long(histogram + 24 + 11 * 8), 1);
+        assert_eq!(long(LATENCY), 0);
+    }
+}
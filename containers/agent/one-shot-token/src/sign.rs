@@ -0,0 +1,211 @@
+//! Signed audit records (AWF_ONE_SHOT_AUDIT_KEY_FD, AWF_ONE_SHOT_AUDIT_KEY_FILE)
+//!
+//! The hash chain (see the chain module) shows that a log was cut or edited,
+//! but a compromised agent can write a whole new log with a valid chain: a
+//! clean token-access history. With a key the host generated for the run,
+//! every audit record and the exit report carry an HMAC-SHA256 the agent
+//! can only compute while it holds the key for that record.
+//!
+//! The key is 32 bytes, written as 64 hex digits, read at load from the file
+//! descriptor AWF_ONE_SHOT_AUDIT_KEY_FD (which is then closed) or the file
+//! AWF_ONE_SHOT_AUDIT_KEY_FILE, such as one in a systemd credentials
+//! directory. The library never keeps it: each process starts from
+//! `HMAC(key, "pid=<pid>")`, and after signing a record replaces its key
+//! with `HMAC(key, "next")` and zeroizes the old one. Code that takes over
+//! the process later can sign records from then on, but not rewrite the
+//! ones before.
+//!
+//! Signed records carry `sigseq` (position in the process's sequence,
+//! starting at 1) and `sig` (the HMAC in hex) of the record's lines as
+//! hashed by the chain, with `sigseq` as `seq` and the details including the
+//! chain fields.
+//! A forked child continues from `HMAC(key, "fork pid=<child pid>")`, where
+//! `key` is the parent's for its next record, and its first record names
+//! that record as `sigbase=<parent pid>:<sigseq>`. The exit report (see the
+//! report module) is signed as a record with the event `report`, token `*`
+//! and the single field `report`, the JSON line without its signature.
+
+use crate::chain::{hex, record_input};
+use crate::envcrypt::{read_key, report_unreadable};
+use crate::setenv::call_real_unsetenv;
+use crate::sha256::hmac_sha256;
+use crate::{read_config_var, sys};
+use std::sync::Mutex;
+
+/// Key and position of this process's signatures
+struct Signer {
+    /// Key of the next record
+    key: [u8; 32],
+    /// sigseq of the last record signed
+    seq: u64,
+    /// Parent pid and sigseq this process's key was derived from, until the
+    /// first record names them
+    base: Option<(u32, u64)>,
+}
+
+static SIGNER: Mutex<Option<Signer>> = Mutex::new(None);
+
+/// A record's signature fields
+pub(crate) struct Signature {
+    pub(crate) base: Option<String>,
+    pub(crate) seq: u64,
+    pub(crate) sig: String,
+}
+
+/// Load the key from AWF_ONE_SHOT_AUDIT_KEY_FD or AWF_ONE_SHOT_AUDIT_KEY_FILE
+///
+/// The descriptor variable is unset once read: children must not read from
+/// a closed number. A source that cannot be read or does not hold a key is
+/// reported with an `audit_key` audit event, and records are not signed.
+pub(crate) fn load() {
+    let fd = read_config_var(c"AWF_ONE_SHOT_AUDIT_KEY_FD");
+    if fd.is_some() {
+        // SAFETY: valid C string
+        unsafe { call_real_unsetenv(c"AWF_ONE_SHOT_AUDIT_KEY_FD".as_ptr()) };
+    }
+    let file = read_config_var(c"AWF_ONE_SHOT_AUDIT_KEY_FILE");
+    let Some((source, key)) = read_key(fd.as_deref(), file.as_deref()) else {
+        return;
+    };
+    match key {
+        Ok(mut key) => {
+            let signer = Signer::new(&key, std::process::id());
+            zero(&mut key);
+            *SIGNER.lock().unwrap_or_else(|err| err.into_inner()) = Some(signer);
+        }
+        Err(err) => report_unreadable("audit_key", &source, &err),
+    }
+}
+
+/// Sign a record, or None if no key was loaded
+///
+/// `details` are the record's details, including the chain fields, and
+/// `context` its run context; `sigbase` is signed between them.
+pub(crate) fn sign(
+    event: &str,
+    token: &str,
+    details: &[(String, String)],
+    context: &[(String, String)],
+) -> Option<Signature> {
+    let mut signer = SIGNER.lock().unwrap_or_else(|err| err.into_inner());
+    let pid = std::process::id();
+    Some(signer.as_mut()?.sign(pid, event, token, details, context))
+}
+
+/// Continue from the parent's key in a forked child
+pub(crate) fn reset_after_fork() {
+    let mut signer = SIGNER.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(signer) = signer.as_mut() {
+        // SAFETY: getppid cannot fail
+        let parent = unsafe { libc::getppid() } as u32;
+        signer.fork(parent, std::process::id());
+    }
+}
+
+impl Signer {
+    /// Signer of process `pid` with the host's `key`
+    fn new(key: &[u8; 32], pid: u32) -> Self {
+        Signer {
+            key: hmac_sha256(key, format!("pid={}", pid).as_bytes()),
+            seq: 0,
+            base: None,
+        }
+    }
+
+    /// Sign the next record of process `pid` and move on to the next key
+    fn sign(
+        &mut self,
+        pid: u32,
+        event: &str,
+        token: &str,
+        details: &[(String, String)],
+        context: &[(String, String)],
+    ) -> Signature {
+        let base = self
+            .base
+            .take()
+            .map(|(pid, seq)| format!("{}:{}", pid, seq));
+        let mut fields = details.to_vec();
+        fields.extend(
+            base.iter()
+                .map(|base| ("sigbase".to_string(), base.clone())),
+        );
+        fields.extend_from_slice(context);
+        let seq = self.seq + 1;
+        let input = record_input(pid, seq, event, token, &fields);
+        let sig = hmac_sha256(&self.key, input.as_bytes());
+        self.ratchet(hmac_sha256(&self.key, b"next"));
+        self.seq = seq;
+        Signature {
+            base,
+            seq,
+            sig: hex(&sig),
+        }
+    }
+
+    /// Continue as child `pid` of `parent`, from the key of the parent's
+    /// next record
+    fn fork(&mut self, parent: u32, pid: u32) {
+        self.ratchet(hmac_sha256(
+            &self.key,
+            format!("fork pid={}", pid).as_bytes(),
+        ));
+        self.base = Some((parent, self.seq + 1));
+        self.seq = 0;
+    }
+
+    /// Replace the key with `next`, zeroizing the old one
+    fn ratchet(&mut self, mut next: [u8; 32]) {
+        std::mem::swap(&mut self.key, &mut next);
+        zero(&mut next);
+    }
+}
+
+fn zero(bytes: &mut [u8]) {
+    // SAFETY: bytes is a writable buffer of the given length
+    unsafe { sys::explicit_bzero(bytes.as_mut_ptr().cast(), bytes.len()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Key of record `seq` of a process starting from `start`
+    fn key_at(start: [u8; 32], seq: u64) -> [u8; 32] {
+        (1..seq).fold(start, |key, _| hmac_sha256(&key, b"next"))
+    }
+
+    #[test]
+    fn test_sign() {
+        let host_key = [7u8; 32];
+        let mut signer = Signer::new(&host_key, 42);
+        let details = vec![("action".to_string(), "blocked".to_string())];
+        let context = vec![("step".to_string(), "build".to_string())];
+
+        let first = signer.sign(42, "denied", "GITHUB_TOKEN", &details, &context);
+        let second = signer.sign(42, "denied", "GITHUB_TOKEN", &details, &context);
+        assert_eq!((first.seq, second.seq), (1, 2));
+        assert!(first.base.is_none());
+        assert_ne!(first.sig, second.sig);
+
+        // The host recomputes both from its key
+        let start = hmac_sha256(&host_key, b"pid=42");
+        let input = "pid=2:42\nseq=1:2\nevent=6:denied\ntoken=12:GITHUB_TOKEN\n\
+                     action=7:blocked\nstep=5:build\n";
+        assert_eq!(
+            second.sig,
+            hex(&hmac_sha256(&key_at(start, 2), input.as_bytes()))
+        );
+
+        // A child continues from the key of the parent's next record
+        let mut child = signer;
+        child.fork(42, 43);
+        let signature = child.sign(43, "denied", "*", &[], &[]);
+        assert_eq!(signature.base.as_deref(), Some("42:3"));
+        assert_eq!(signature.seq, 1);
+        let input = "pid=2:43\nseq=1:1\nevent=6:denied\ntoken=1:*\nsigbase=4:42:3\n";
+        let key = hmac_sha256(&key_at(start, 3), b"fork pid=43");
+        assert_eq!(signature.sig, hex(&hmac_sha256(&key, input.as_bytes())));
+        assert!(child.sign(43, "denied", "*", &[], &[]).base.is_none());
+    }
+}
@@ -0,0 +1,388 @@
+//! Signal-driven statistics dump, emergency wipe and step end
+//! (AWF_ONE_SHOT_SIGNALS and AWF_ONE_SHOT_STEP_SIGNAL)
+//!
+//! With AWF_ONE_SHOT_SIGNALS set, the host can act on a running agent without
+//! its cooperation:
+//!
+//! - SIGUSR1 writes the access summary (see the report module) to the log
+//! - SIGUSR2 zeroizes every protected token, as `awf_token_wipe(NULL)` does,
+//!   so the host can cut off token access when it detects suspicious egress
+//!
+//! AWF_ONE_SHOT_STEP_SIGNAL names a signal (e.g. `SIGRTMIN+1`) that ends the
+//! current workflow step right away, as a touched step file does on the next
+//! read (see the step module): every cached value is zeroized and must be
+//! granted again. It takes precedence if it is SIGUSR1 or SIGUSR2.
+//!
+//! None of the actions is async-signal-safe: all take the state lock. The handler
+//! therefore only writes the signal number to a pipe, and a background thread
+//! performs the action. Like the watchdog thread, it is restarted in forked
+//! children, with a new pipe so that signals sent to the child are not
+//! handled by the parent; until then, only signals that end the process are
+//! acted on.
+//!
+//! A signal the program already handles or ignores at load keeps its action,
+//! unless AWF_ONE_SHOT_SIGNAL_CHAIN is set: the handler is then installed over
+//! it and calls the program's handler after notifying the thread. SIGHUP,
+//! SIGALRM and SIGTERM still end a process that left them at their default
+//! action: once the step has ended, the default action is restored and the
+//! signal raised again.
+
+use crate::{control, lock_state, report, step, sys};
+use libc::{c_int, c_void};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+
+/// Write end of the pipe the handler notifies the thread through (-1 if none)
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Read end of the pipe, kept to close it in forked children
+static READ_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Signal that ends the current step (-1 if none)
+static STEP_SIGNAL: AtomicI32 = AtomicI32::new(-1);
+
+/// Signal numbers go up to SIGRTMAX (64 on Linux)
+const SLOTS: usize = 65;
+
+/// Action of each signal before the handler was installed
+static PREVIOUS: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(libc::SIG_DFL) }; SLOTS];
+
+/// Whether the previous action of each signal takes a `siginfo_t`
+static PREVIOUS_SIGINFO: [AtomicBool; SLOTS] = [const { AtomicBool::new(false) }; SLOTS];
+
+/// Signals whose default action ends the process but which are not meant
+/// for user-defined actions
+const TERMINATING: [c_int; 3] = [libc::SIGHUP, libc::SIGALRM, libc::SIGTERM];
+
+/// Start the signal thread at load if AWF_ONE_SHOT_SIGNALS is configured
+extern "C" fn start_signals_at_load() {
+    start();
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static START_SIGNALS_AT_LOAD: extern "C" fn() = start_signals_at_load;
+
+/// Signal handler: forward the signal number to the signal thread, then
+/// call the program's handler if it was chained
+extern "C" fn on_signal(signum: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    let fd = WRITE_FD.load(Ordering::Relaxed);
+    if fd < 0 && terminates(signum) {
+        // No thread to end the step first, as in a forked child that has
+        // not started its own yet
+        terminate(signum);
+    } else if fd >= 0 {
+        let byte = signum as u8;
+        // SAFETY: write(2) and errno access are async-signal-safe; the raw
+        // syscall bypasses the write interposer and its lock
+        unsafe {
+            let errno = *sys::errno_location();
+            sys::raw_write(fd, (&byte as *const u8).cast(), 1);
+            *sys::errno_location() = errno;
+        }
+    }
+    chain(signum, info, context);
+}
+
+/// Call the handler `signum` had before the library's, if it had one
+fn chain(signum: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    let Some(previous) = PREVIOUS.get(signum as usize) else {
+        return;
+    };
+    let previous = previous.load(Ordering::Relaxed);
+    if previous == libc::SIG_DFL || previous == libc::SIG_IGN {
+        return;
+    }
+    // SAFETY: previous was installed by the program as the handler of
+    // signum, with the signature its SA_SIGINFO flag selects
+    unsafe {
+        if PREVIOUS_SIGINFO[signum as usize].load(Ordering::Relaxed) {
+            let handler: extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) =
+                std::mem::transmute(previous);
+            handler(signum, info, context);
+        } else {
+            let handler: extern "C" fn(c_int) = std::mem::transmute(previous);
+            handler(signum);
+        }
+    }
+}
+
+/// Whether `signum` ends the process by default and the program left it so
+fn terminates(signum: c_int) -> bool {
+    TERMINATING.contains(&signum)
+        && PREVIOUS[signum as usize].load(Ordering::Relaxed) == libc::SIG_DFL
+}
+
+/// Restore the default action of `signum` and raise it again, so the
+/// process ends the way it would have without the handler
+fn terminate(signum: c_int) {
+    // SAFETY: sigaction and sigset_t are plain structs
+    unsafe {
+        let mut default: libc::sigaction = std::mem::zeroed();
+        default.sa_sigaction = libc::SIG_DFL;
+        libc::sigaction(signum, &default, std::ptr::null_mut());
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, signum);
+        libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+        libc::raise(signum);
+    }
+}
+
+/// Perform the action requested by `signum`
+fn handle(signum: c_int) {
+    if signum == STEP_SIGNAL.load(Ordering::Relaxed) {
+        step::end("signal");
+        if terminates(signum) {
+            terminate(signum);
+        }
+        return;
+    }
+    match signum {
+        libc::SIGUSR1 => {
+            let summary = report::summary(&lock_state());
+            log_line!(Info, "stats", None, "Statistics: {}", summary);
+        }
+        libc::SIGUSR2 => {
+            let wiped = control::wipe_all("SIGUSR2");
+            log_line!(
+                Warning,
+                "wiped",
+                None,
+                "SIGUSR2: wiped {} cached token values",
+                wiped
+            );
+        }
+        _ => {}
+    }
+}
+
+/// Stop notifying the parent's thread, in a forked child that has not
+/// started its own yet (see the fork module)
+///
+/// Only does async-signal-safe work, as it runs in the child's fork handler.
+pub(crate) fn forget_pipe() {
+    let old = WRITE_FD.swap(-1, Ordering::Relaxed);
+    if old >= 0 {
+        // SAFETY: the descriptor belongs to the parent's pipe
+        unsafe { libc::close(old) };
+    }
+}
+
+/// Create a new notification pipe, closing the previous one
+fn replace_pipe() -> std::io::Result<c_int> {
+    let mut fds = [0 as c_int; 2];
+    // SAFETY: fds has room for both descriptors
+    if unsafe { sys::pipe_cloexec(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // A full pipe drops notifications instead of blocking the handler
+    // SAFETY: fds[1] was just created
+    unsafe { libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK) };
+    for (slot, fd) in [(&READ_FD, fds[0]), (&WRITE_FD, fds[1])] {
+        let old = slot.swap(fd, Ordering::Relaxed);
+        if old >= 0 {
+            // SAFETY: the previous pipe belongs to the parent's thread, which
+            // does not exist in this process
+            unsafe { libc::close(old) };
+        }
+    }
+    Ok(fds[0])
+}
+
+/// Signals that can end a step, by name without the `SIG` prefix
+const STEP_SIGNALS: [(&str, c_int); 6] = [
+    ("HUP", libc::SIGHUP),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("WINCH", libc::SIGWINCH),
+];
+
+/// Parse an AWF_ONE_SHOT_STEP_SIGNAL value: a name from STEP_SIGNALS, with
+/// or without `SIG`, `RTMIN+n` or `RTMAX-n` (Linux), or the number of one
+/// of those
+pub(crate) fn parse_signal(value: &str) -> Option<c_int> {
+    let value = value.trim();
+    let name = value.strip_prefix("SIG").unwrap_or(value);
+    if let Ok(signum) = name.parse::<c_int>() {
+        let named = STEP_SIGNALS.iter().any(|&(_, known)| known == signum);
+        return (named || realtime(signum)).then_some(signum);
+    }
+    if let Some(&(_, signum)) = STEP_SIGNALS.iter().find(|(known, _)| *known == name) {
+        return Some(signum);
+    }
+    parse_realtime(name)
+}
+
+/// Whether `signum` is a real-time signal
+#[cfg(target_os = "linux")]
+fn realtime(signum: c_int) -> bool {
+    (libc::SIGRTMIN()..=libc::SIGRTMAX()).contains(&signum)
+}
+
+/// Real-time signals are Linux-only
+#[cfg(not(target_os = "linux"))]
+fn realtime(_signum: c_int) -> bool {
+    false
+}
+
+/// Parse `RTMIN`, `RTMIN+n`, `RTMAX` or `RTMAX-n`
+#[cfg(target_os = "linux")]
+fn parse_realtime(name: &str) -> Option<c_int> {
+    let offset = |rest: &str, sign: char| match rest {
+        "" => Some(0),
+        rest => rest.strip_prefix(sign)?.parse::<c_int>().ok(),
+    };
+    let signum = if let Some(rest) = name.strip_prefix("RTMIN") {
+        libc::SIGRTMIN() + offset(rest, '+')?
+    } else {
+        libc::SIGRTMAX() - offset(name.strip_prefix("RTMAX")?, '-')?
+    };
+    realtime(signum).then_some(signum)
+}
+
+/// Real-time signals are Linux-only
+#[cfg(not(target_os = "linux"))]
+fn parse_realtime(_name: &str) -> Option<c_int> {
+    None
+}
+
+/// Install the handlers of `signals`, over actions the program already set
+/// only if `chain`
+fn install_handlers(signals: &[c_int], chain: bool) -> std::io::Result<()> {
+    let handler =
+        on_signal as extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) as libc::sighandler_t;
+    for &signum in signals {
+        let Some(slot) = PREVIOUS.get(signum as usize) else {
+            continue;
+        };
+        // SAFETY: sigaction is a plain struct; on_signal is async-signal-safe
+        unsafe {
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(signum, std::ptr::null(), &mut previous) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            // Forked children inherit the handler and the previous actions
+            if previous.sa_sigaction == handler {
+                continue;
+            }
+            if previous.sa_sigaction != libc::SIG_DFL && !chain {
+                log_line!(
+                    Warning,
+                    "signals",
+                    None,
+                    "Signal {} already has a handler; set AWF_ONE_SHOT_SIGNAL_CHAIN=1 to act on it too",
+                    signum
+                );
+                continue;
+            }
+            slot.store(previous.sa_sigaction, Ordering::Relaxed);
+            PREVIOUS_SIGINFO[signum as usize]
+                .store(previous.sa_flags & libc::SA_SIGINFO != 0, Ordering::Relaxed);
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handler;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(signum, &action, std::ptr::null_mut()) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Start the signal thread and install the handlers if AWF_ONE_SHOT_SIGNALS
+/// or AWF_ONE_SHOT_STEP_SIGNAL is configured
+///
+/// Called at load, and again in forked children, which do not inherit the
+/// parent's threads.
+pub(crate) fn start() {
+    let mut signals = Vec::new();
+    let chain;
+    {
+        let state = lock_state();
+        chain = state.signal_chain;
+        if state.signals {
+            signals.extend([libc::SIGUSR1, libc::SIGUSR2]);
+        }
+        if let Some(signum) = state.step_signal {
+            STEP_SIGNAL.store(signum, Ordering::Relaxed);
+            signals.push(signum);
+        }
+    }
+    if signals.is_empty() {
+        return;
+    }
+
+    let started = replace_pipe().and_then(|read_fd| {
+        std::thread::Builder::new()
+            .name("awf-signals".to_string())
+            .spawn(move || loop {
+                let mut byte = 0u8;
+                // SAFETY: byte is valid for a one-byte read
+                let n = unsafe { libc::read(read_fd, (&mut byte as *mut u8).cast(), 1) };
+                match n {
+                    1 => handle(byte as c_int),
+                    -1 if std::io::Error::last_os_error().kind()
+                        == std::io::ErrorKind::Interrupted => {}
+                    _ => return,
+                }
+            })?;
+        install_handlers(&signals, chain)
+    });
+
+    if let Err(err) = started {
+        log_line!(
+            Warning,
+            "signals",
+            None,
+            "Could not install signal handlers: {}",
+            err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGHUP"), Some(libc::SIGHUP));
+        assert_eq!(parse_signal("usr2"), None);
+        assert_eq!(parse_signal(" USR2 "), Some(libc::SIGUSR2));
+        assert_eq!(parse_signal("15"), Some(libc::SIGTERM));
+        assert_eq!(parse_signal("SIGKILL"), None);
+        assert_eq!(parse_signal("9"), None);
+        assert_eq!(parse_signal("SIGSEGV"), None);
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(parse_signal("SIGRTMIN"), Some(libc::SIGRTMIN()));
+            assert_eq!(parse_signal("SIGRTMIN+2"), Some(libc::SIGRTMIN() + 2));
+            assert_eq!(parse_signal("RTMAX-1"), Some(libc::SIGRTMAX() - 1));
+            assert_eq!(parse_signal("RTMIN-1"), None);
+            assert_eq!(parse_signal("RTMIN+99"), None);
+        }
+    }
+
+    #[test]
+    fn test_handler_notifies_thread() {
+        replace_pipe().unwrap();
+        let read_fd = replace_pipe().unwrap();
+        assert_eq!(READ_FD.load(Ordering::Relaxed), read_fd);
+
+        on_signal(libc::SIGUSR2, std::ptr::null_mut(), std::ptr::null_mut());
+        let mut byte = 0u8;
+        // SAFETY: byte is valid for a one-byte read
+        let n = unsafe { libc::read(read_fd, (&mut byte as *mut u8).cast(), 1) };
+        assert_eq!((n, byte as c_int), (1, libc::SIGUSR2));
+    }
+}
@@ -0,0 +1,305 @@
+//! Audit sink failures (AWF_ONE_SHOT_SINK_FAILURE)
+//!
+//! The log file or descriptor, the event socket and the report file can stop
+//! accepting writes in the middle of a run: the disk fills up, the host
+//! closes its end of the pipe, the supervisor exits. Lines a sink does not
+//! take are kept in memory, up to MAX_BUFFERED per sink (the oldest are
+//! dropped beyond that), and written out in order once it takes a line
+//! again, before the new one. A sink that starts failing is reported with a
+//! `sink_failed` audit event, and one that recovers with `sink_recovered`
+//! and the number of lines it lost (`dropped=`); both events go to the
+//! other sinks right away and to the recovered one in order.
+//!
+//! What happens to reads in between depends on AWF_ONE_SHOT_SINK_FAILURE:
+//!
+//! - `open` (default): protected tokens are served as usual
+//! - `closed`: reads of protected tokens are refused with a `sink_denied`
+//!   event until every sink takes writes again, so no token is handed out
+//!   that the host cannot account for. The report file is only written at
+//!   exit, so in this mode whether it could be appended to is checked on
+//!   reads, at most once per PROBE_INTERVAL.
+//!
+//! A failed sink is tried again whenever it has a line to write, so refused
+//! reads, whose events are lines too, also retry it. Lines written to stderr
+//! and events exported over OTLP are not covered.
+
+use crate::audit;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Most lines kept per failed sink
+pub(crate) const MAX_BUFFERED: usize = 1024;
+
+/// Least time between two checks of the report file
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What reads do while a sink fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub(crate) enum Policy {
+    /// Keep serving tokens (default)
+    #[default]
+    Open,
+    /// Refuse protected tokens until the sinks recover
+    Closed,
+}
+
+impl Policy {
+    /// Parse an AWF_ONE_SHOT_SINK_FAILURE value (case-insensitive)
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "open" => Some(Policy::Open),
+            "closed" => Some(Policy::Closed),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Policy::Open => "open",
+            Policy::Closed => "closed",
+        }
+    }
+}
+
+/// A destination of log lines or events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Sink {
+    /// AWF_ONE_SHOT_LOG_FD or AWF_ONE_SHOT_LOG_FILE
+    Log,
+    /// AWF_ONE_SHOT_EVENT_SOCKET
+    Events,
+    /// AWF_ONE_SHOT_REPORT
+    Report,
+}
+
+const SINKS: [Sink; 3] = [Sink::Log, Sink::Events, Sink::Report];
+
+impl Sink {
+    fn as_str(self) -> &'static str {
+        match self {
+            Sink::Log => "log",
+            Sink::Events => "events",
+            Sink::Report => "report",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Lines waiting for a failed sink
+struct Backlog {
+    lines: VecDeque<String>,
+    dropped: u64,
+}
+
+impl Backlog {
+    const fn new() -> Self {
+        Backlog {
+            lines: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Keep `line` for later, dropping the oldest line if full
+    fn push(&mut self, line: &str) {
+        if self.lines.len() >= MAX_BUFFERED {
+            self.lines.pop_front();
+            self.dropped += 1;
+        }
+        self.lines.push_back(line.to_string());
+    }
+
+    /// Write the kept lines and then `line`, keeping whatever is not taken
+    ///
+    /// Returns whether everything was written.
+    fn deliver(&mut self, line: &str, write: &mut impl FnMut(&str) -> bool) -> bool {
+        while let Some(front) = self.lines.front() {
+            if !write(front) {
+                self.push(line);
+                return false;
+            }
+            self.lines.pop_front();
+        }
+        if write(line) {
+            return true;
+        }
+        self.push(line);
+        false
+    }
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(Policy::Open as u8);
+
+/// Sinks that are failing, one bit per Sink
+static FAILED: AtomicU8 = AtomicU8::new(0);
+
+static LOG_BACKLOG: Mutex<Backlog> = Mutex::new(Backlog::new());
+static EVENTS_BACKLOG: Mutex<Backlog> = Mutex::new(Backlog::new());
+
+/// When the report file was last checked
+static LAST_PROBE: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Set the policy from AWF_ONE_SHOT_SINK_FAILURE
+pub(crate) fn set_policy(policy: Policy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+fn policy() -> Policy {
+    match POLICY.load(Ordering::Relaxed) {
+        1 => Policy::Closed,
+        _ => Policy::Open,
+    }
+}
+
+/// Whether reads have to take the state lock so that refuses_reads is asked
+pub(crate) fn gates_reads() -> bool {
+    policy() == Policy::Closed
+}
+
+/// Record whether `sink` takes writes, reporting a change
+///
+/// `dropped` is the number of lines lost while it failed.
+fn set_failed(sink: Sink, failed: bool, dropped: u64) {
+    let previous = if failed {
+        FAILED.fetch_or(sink.bit(), Ordering::SeqCst)
+    } else {
+        FAILED.fetch_and(!sink.bit(), Ordering::SeqCst)
+    };
+    if (previous & sink.bit() != 0) == failed {
+        return;
+    }
+    if failed {
+        let detail = format!("sink={} policy={}", sink.as_str(), policy().as_str());
+        audit::emit("sink_failed", "*", &detail);
+    } else {
+        let detail = format!("sink={} dropped={}", sink.as_str(), dropped);
+        audit::emit("sink_recovered", "*", &detail);
+    }
+}
+
+/// Send `line` to `sink` through `write`, which returns whether the sink
+/// took it, after the lines kept while the sink failed
+pub(crate) fn deliver(sink: Sink, line: &str, mut write: impl FnMut(&str) -> bool) {
+    let backlog = match sink {
+        Sink::Events => &EVENTS_BACKLOG,
+        _ => &LOG_BACKLOG,
+    };
+    let mut backlog = backlog.lock().unwrap_or_else(|e| e.into_inner());
+    let delivered = backlog.deliver(line, &mut write);
+    if delivered == (FAILED.load(Ordering::SeqCst) & sink.bit() == 0) {
+        return;
+    }
+    let dropped = if delivered {
+        std::mem::take(&mut backlog.dropped)
+    } else {
+        0
+    };
+    // Reported without the lock: the event is delivered to this sink too
+    drop(backlog);
+    set_failed(sink, !delivered, dropped);
+}
+
+/// Whether the report file at `path` could be appended to
+fn report_writable(path: &str) -> bool {
+    let Ok(path_cstr) = CString::new(path) else {
+        return false;
+    };
+    // SAFETY: path_cstr is a valid C string
+    if unsafe { libc::access(path_cstr.as_ptr(), libc::W_OK) } == 0 {
+        return true;
+    }
+    if std::io::Error::last_os_error().raw_os_error() != Some(libc::ENOENT) {
+        return false;
+    }
+    // Not created yet: the directory must let us create it
+    let dir = match path.rsplit_once('/') {
+        Some(("", _)) => "/",
+        Some((dir, _)) => dir,
+        None => ".",
+    };
+    let Ok(dir) = CString::new(dir) else {
+        return false;
+    };
+    // SAFETY: dir is a valid C string
+    unsafe { libc::access(dir.as_ptr(), libc::W_OK | libc::X_OK) == 0 }
+}
+
+/// Check the report file at `path`, unless it was checked less than
+/// PROBE_INTERVAL ago
+fn probe_report(path: &str) {
+    let mut last = LAST_PROBE.lock().unwrap_or_else(|e| e.into_inner());
+    let now = Instant::now();
+    if last.is_some_and(|last| now.duration_since(last) < PROBE_INTERVAL) {
+        return;
+    }
+    *last = Some(now);
+    drop(last);
+    set_failed(Sink::Report, !report_writable(path), 0);
+}
+
+/// The failing sinks if reads of protected tokens are to be refused,
+/// comma-separated
+///
+/// `report_path` is AWF_ONE_SHOT_REPORT, checked here in the `closed` mode.
+pub(crate) fn refuses_reads(report_path: Option<&str>) -> Option<String> {
+    if policy() != Policy::Closed {
+        return None;
+    }
+    if let Some(path) = report_path {
+        probe_report(path);
+    }
+    let failed = FAILED.load(Ordering::SeqCst);
+    let names: Vec<&str> = SINKS
+        .iter()
+        .filter(|sink| failed & sink.bit() != 0)
+        .map(|sink| sink.as_str())
+        .collect();
+    (!names.is_empty()).then(|| names.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_parse() {
+        assert_eq!(Policy::parse(" Closed "), Some(Policy::Closed));
+        assert_eq!(Policy::parse("open"), Some(Policy::Open));
+        assert_eq!(Policy::parse("fail"), None);
+    }
+
+    #[test]
+    fn test_backlog() {
+        let mut backlog = Backlog::new();
+        for index in 0..MAX_BUFFERED + 2 {
+            assert!(!backlog.deliver(&index.to_string(), &mut |_: &str| false));
+        }
+        assert_eq!(backlog.lines.len(), MAX_BUFFERED);
+        assert_eq!(backlog.dropped, 2);
+
+        let mut written = Vec::new();
+        let mut write = |line: &str| {
+            written.push(line.to_string());
+            true
+        };
+        assert!(backlog.deliver("last", &mut write));
+        assert!(backlog.lines.is_empty());
+        assert_eq!(written.len(), MAX_BUFFERED + 1);
+        assert_eq!(written[0], "2");
+        assert_eq!(written[MAX_BUFFERED], "last");
+    }
+
+    #[test]
+    fn test_report_writable() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("awf-sink-{}.json", std::process::id()));
+        assert!(report_writable(path.to_str().unwrap()));
+        assert!(!report_writable("/nonexistent-awf-dir/report.json"));
+    }
+}
@@ -0,0 +1,364 @@
+//! Lock-free reads of cached tokens
+//!
+//! getenv may be called from signal handlers. A handler that interrupts an
+//! interposer on the thread holding the state lock would deadlock on the
+//! lock, and one that interrupts malloc would deadlock on the first
+//! allocation. Reads that need no bookkeeping beyond a counter are therefore
+//! served from an immutable snapshot behind an atomic pointer: a reader never
+//! locks, allocates or writes anything but atomics.
+//!
+//! The snapshot is rebuilt (copy-on-write) whenever the state lock is
+//! released after a change that affects it, such as a value being cached,
+//! unsealed, replaced or wiped. Reads served from it are counted in atomics
+//! and folded into the cache entries the next time the lock is taken, so the
+//! exit report and the control API still see them.
+//!
+//! A cached token is served lock-free only if nothing else has to happen on
+//! a read: enforce mode, no debug logging, no access log or event socket, no
+//! rate limit, late-read check, fail-closed sink policy, caller policy,
+//! report, step file, or process rule that refuses or logs reads, and a plain (`cache` or `redact`) policy without
+//! `max_reads`, `ttl`, `expires_at` or `refresh`. Everything else takes the state lock as
+//! before (a handler that interrupted the lock holder reads the real
+//! environment instead, see the reentry module).
+//!
+//! Retired snapshots are freed once no reader is inside one: readers
+//! announce themselves in a counter before loading the pointer.
+
+use crate::envname::{EnvName, NameMap};
+#[cfg(not(feature = "minimal"))]
+use crate::shmstats;
+use crate::{
+    comm_denied, credfile, events, exe_denied, is_denied_token, log, parent_rule, sink, trace,
+    Mode, TokenPolicy, TokenState, MAX_TOKENS,
+};
+use libc::c_char;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// How a name is served without the state lock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Served {
+    /// The cached value of the token in counter slot `slot`
+    Token { value: *mut c_char, slot: usize },
+    /// The token was not set: NULL, nothing to count
+    Unset,
+    /// A host-mandated proxy value
+    Proxy(*const c_char),
+    /// The read needs the state lock
+    Locked,
+}
+
+/// Names and how each is served
+struct Snapshot {
+    /// Index in `served` of each name
+    names: NameMap,
+    /// One entry per protected token (in configuration order), then one per
+    /// enforced proxy variable
+    served: Box<[Served]>,
+    /// Canonical name of each token, for the stats segment
+    #[cfg(not(feature = "minimal"))]
+    canonical: Box<[EnvName]>,
+}
+
+// SAFETY: the pointers refer to cached values and enforced proxy strings,
+// which outlive every snapshot that refers to them
+unsafe impl Send for Snapshot {}
+unsafe impl Sync for Snapshot {}
+
+static SNAPSHOT: AtomicPtr<Snapshot> = AtomicPtr::new(ptr::null_mut());
+
+/// Readers currently inside a snapshot
+static READERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Snapshots replaced while a reader may still use them (only touched with
+/// the state lock held)
+static RETIRED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+/// Reads served from the snapshot and not yet folded into the cache, per
+/// token slot, with the first and last read (nanoseconds since the Unix
+/// epoch, 0 if none)
+static READS: [AtomicU32; MAX_TOKENS] = [const { AtomicU32::new(0) }; MAX_TOKENS];
+static FIRST_READ: [AtomicU64; MAX_TOKENS] = [const { AtomicU64::new(0) }; MAX_TOKENS];
+static LAST_READ: [AtomicU64; MAX_TOKENS] = [const { AtomicU64::new(0) }; MAX_TOKENS];
+
+/// Whether any of the counters above is pending
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Serve a read of `name` from the snapshot
+///
+/// Returns None if the read needs the state lock.
+pub(crate) fn serve(name: &[u8]) -> Option<*mut c_char> {
+    READERS.fetch_add(1, Ordering::SeqCst);
+    let snapshot = SNAPSHOT.load(Ordering::SeqCst);
+    // SAFETY: a snapshot is not freed while READERS counts a reader
+    let result = unsafe { snapshot.as_ref() }.and_then(|snapshot| snapshot.serve(name));
+    READERS.fetch_sub(1, Ordering::SeqCst);
+    result
+}
+
+impl Snapshot {
+    fn serve(&self, name: &[u8]) -> Option<*mut c_char> {
+        match self.served[self.names.get(name)?] {
+            Served::Token { value, slot } => {
+                count_read(slot);
+                #[cfg(not(feature = "minimal"))]
+                shmstats::record_read(&self.canonical[slot]);
+                Some(value)
+            }
+            Served::Unset => Some(ptr::null_mut()),
+            Served::Proxy(value) => Some(value as *mut c_char),
+            Served::Locked => None,
+        }
+    }
+}
+
+/// Nanoseconds since the Unix epoch (clock_gettime, async-signal-safe)
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(1, |since| since.as_nanos() as u64)
+}
+
+/// Count a lock-free read of the token in `slot`
+fn count_read(slot: usize) {
+    let now = now_nanos();
+    READS[slot].fetch_add(1, Ordering::Relaxed);
+    let _ = FIRST_READ[slot].compare_exchange(0, now, Ordering::Relaxed, Ordering::Relaxed);
+    LAST_READ[slot].fetch_max(now, Ordering::Relaxed);
+    PENDING.store(true, Ordering::Release);
+}
+
+/// Fold the reads served from the snapshot into the cache entries
+pub(crate) fn fold_reads(state: &mut TokenState) {
+    if !PENDING.swap(false, Ordering::Acquire) {
+        return;
+    }
+    let time =
+        |nanos: u64| (nanos != 0).then(|| SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos));
+    for (slot, spec) in state.tokens.iter().enumerate().take(MAX_TOKENS) {
+        let reads = READS[slot].swap(0, Ordering::Relaxed);
+        if reads == 0 {
+            continue;
+        }
+        let first = time(FIRST_READ[slot].swap(0, Ordering::Relaxed));
+        let last = time(LAST_READ[slot].swap(0, Ordering::Relaxed));
+        if let Some(entry) = state.cache.get_mut(&spec.name) {
+            entry.reads = entry.reads.saturating_add(reads);
+            entry.first_read = entry.first_read.or(first);
+            entry.last_read = entry.last_read.max(last);
+        }
+    }
+}
+
+/// Whether served reads may skip the state lock in this process
+fn lock_free_reads(state: &TokenState) -> bool {
+    state.mode == Mode::Enforce
+        && !state.debug_enabled
+        && state.rate_limit.is_none()
+        && !state.late_read.enabled()
+        && !sink::gates_reads()
+        && state.caller_policy.is_none()
+        && state.report_path.is_none()
+        && state.step_file.is_none()
+        && state.correlation.is_none()
+        && !trace::enabled()
+        && !events::enabled()
+        && !log::logs_access()
+        && !comm_denied(state)
+        && parent_rule(state) == credfile::FilePolicy::Allow
+}
+
+/// How each protected token is served, given whether this process serves
+/// reads lock-free at all
+fn served_tokens(state: &TokenState, lock_free: bool) -> Vec<Served> {
+    state
+        .tokens
+        .iter()
+        .enumerate()
+        .map(|(slot, spec)| {
+            let plain = matches!(
+                spec.policy,
+                TokenPolicy::Cache
+                    | TokenPolicy::Redact
+                    | TokenPolicy::Derive
+                    | TokenPolicy::Placeholder
+            ) && spec.max_reads.is_none()
+                && spec.ttl_secs.is_none()
+                && spec.expires_at.is_none()
+                && spec.refresh_secs.is_none();
+            let entry = state.cache.get(&spec.name);
+            match entry {
+                _ if !lock_free || !plain || slot >= MAX_TOKENS => Served::Locked,
+                _ if exe_denied(state, &spec.name) => Served::Locked,
+                _ if is_denied_token(state, &spec.name) => Served::Locked,
+                Some(entry) if entry.locked || entry.wiped.is_some() => Served::Locked,
+                Some(entry) if entry.value.is_null() => Served::Unset,
+                Some(entry) if entry.sealed.is_none() => Served::Token {
+                    value: entry.value,
+                    slot,
+                },
+                _ => Served::Locked,
+            }
+        })
+        .collect()
+}
+
+/// Publish a new snapshot if the way tokens are served has changed
+///
+/// Must be called with the state lock held.
+pub(crate) fn publish(state: &TokenState) {
+    let mut served = served_tokens(state, lock_free_reads(state));
+    let current = SNAPSHOT.load(Ordering::SeqCst);
+    // SAFETY: only the lock holder replaces or frees snapshots
+    if let Some(current) = unsafe { current.as_ref() } {
+        if current.served.get(..served.len()) == Some(&served[..]) {
+            return;
+        }
+    } else if !served.iter().any(|served| *served != Served::Locked) {
+        return;
+    }
+
+    let mut names: Vec<(EnvName, usize)> = state
+        .tokens
+        .iter()
+        .enumerate()
+        .map(|(index, spec)| (spec.name.clone(), index))
+        .collect();
+    for (alias, canonical) in &state.aliases {
+        if let Some(index) = state.tokens.iter().position(|spec| spec.name == *canonical) {
+            names.push((alias.clone(), index));
+        }
+    }
+    let proxy_served = state.mode == Mode::Enforce && !state.debug_enabled;
+    for (name, value) in &state.enforced_proxy {
+        names.push((EnvName::from(name.as_str()), served.len()));
+        served.push(if proxy_served {
+            Served::Proxy(value.as_ptr())
+        } else {
+            Served::Locked
+        });
+    }
+
+    let snapshot = Box::new(Snapshot {
+        names: NameMap::new(names),
+        served: served.into_boxed_slice(),
+        #[cfg(not(feature = "minimal"))]
+        canonical: state.tokens.iter().map(|spec| spec.name.clone()).collect(),
+    });
+    replace(Box::into_raw(snapshot));
+}
+
+/// Drop the snapshot after the token list changed, so that the next
+/// `publish` builds a new one even if every token is served as before
+///
+/// Must be called with the state lock held.
+#[cfg(target_os = "linux")]
+pub(crate) fn reset() {
+    replace(ptr::null_mut());
+}
+
+/// Make `snapshot` the current snapshot and free the retired ones no reader
+/// can still be inside
+fn replace(snapshot: *mut Snapshot) {
+    let previous = SNAPSHOT.swap(snapshot, Ordering::SeqCst);
+
+    let mut retired = RETIRED.lock().unwrap_or_else(|err| err.into_inner());
+    if !previous.is_null() {
+        retired.push(previous as usize);
+    }
+    // A reader that loaded a retired pointer announced itself before the swap
+    if READERS.load(Ordering::SeqCst) == 0 {
+        for snapshot in retired.drain(..) {
+            // SAFETY: retired snapshots are unreachable and no reader is inside one
+            drop(unsafe { Box::from_raw(snapshot as *mut Snapshot) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{policy, CachedToken};
+
+    #[test]
+    fn test_served_tokens() {
+        let mut state = TokenState::new();
+        for token in [
+            "GITHUB_TOKEN",
+            "GH_TOKEN:max_reads=2",
+            "OPENAI_API_KEY",
+            "UNREAD",
+        ] {
+            state
+                .tokens
+                .push(policy::parse_token_spec(token).unwrap().0);
+        }
+        let mut value = *b"ghp_test\0";
+        let value_ptr = value.as_mut_ptr() as *mut c_char;
+        state
+            .cache
+            .insert("GITHUB_TOKEN".into(), CachedToken::cached(value_ptr));
+        state
+            .cache
+            .insert("GH_TOKEN".into(), CachedToken::cached(value_ptr));
+        state
+            .cache
+            .insert("OPENAI_API_KEY".into(), CachedToken::unset());
+
+        assert_eq!(
+            served_tokens(&state, true),
+            vec![
+                Served::Token {
+                    value: value_ptr,
+                    slot: 0
+                },
+                Served::Locked,
+                Served::Unset,
+                Served::Locked,
+            ]
+        );
+
+        // Wiped values and per-read bookkeeping need the lock
+        state
+            .cache
+            .get_mut(&EnvName::from("GITHUB_TOKEN"))
+            .unwrap()
+            .wiped = Some("wiped");
+        assert_eq!(served_tokens(&state, true)[0], Served::Locked);
+        assert_eq!(served_tokens(&state, false)[2], Served::Locked);
+        state.mode = Mode::Observe;
+        assert!(!lock_free_reads(&state));
+    }
+
+    #[test]
+    fn test_snapshot_serve() {
+        let mut value = *b"ghp_test\0";
+        let value_ptr = value.as_mut_ptr() as *mut c_char;
+        let snapshot = Snapshot {
+            names: NameMap::new([
+                (EnvName::from("GH_TOKEN"), 0),
+                (EnvName::from("GITHUB_TOKEN"), 0),
+                (EnvName::from("OPENAI_API_KEY"), 1),
+            ]),
+            served: vec![
+                Served::Token {
+                    value: value_ptr,
+                    slot: MAX_TOKENS - 1,
+                },
+                Served::Locked,
+            ]
+            .into_boxed_slice(),
+            #[cfg(not(feature = "minimal"))]
+            canonical: (0..MAX_TOKENS).map(|_| "GITHUB_TOKEN".into()).collect(),
+        };
+
+        assert_eq!(snapshot.serve(b"GH_TOKEN"), Some(value_ptr));
+        assert_eq!(snapshot.serve(b"GITHUB_TOKEN"), Some(value_ptr));
+        assert_eq!(snapshot.serve(b"OPENAI_API_KEY"), None);
+        assert_eq!(snapshot.serve(b"PATH"), None);
+        assert_eq!(READS[MAX_TOKENS - 1].load(Ordering::Relaxed), 2);
+        assert_ne!(FIRST_READ[MAX_TOKENS - 1].load(Ordering::Relaxed), 0);
+    }
+}
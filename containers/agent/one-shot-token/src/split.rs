@@ -0,0 +1,81 @@
+//! Split tokens (AWF_ONE_SHOT_SPLIT_TOKENS)
+//!
+//! The host can put the first part of a secret in NAME_PART1 and the rest in
+//! NAME_PART2, so that a copy of the environment taken before the library
+//! scrubbed it (or one of the two variables alone) holds no usable credential.
+//! The parts are registered as aliases of NAME: reading NAME (or either part)
+//! concatenates them, caches the value and scrubs both parts like any alias.
+//! A token whose parts are not both set is left unset.
+
+use crate::audit;
+use crate::envname::EnvName;
+use std::ffi::{CStr, CString};
+
+/// Suffixes of the two parts, in order
+const SUFFIXES: [&str; 2] = ["_PART1", "_PART2"];
+
+/// Names of the parts of token `name`
+pub(crate) fn part_names(name: &EnvName) -> [EnvName; 2] {
+    SUFFIXES.map(|suffix| EnvName::new([name.as_bytes(), suffix.as_bytes()].concat()))
+}
+
+/// Concatenate the values of the parts of `token`
+///
+/// Emits a `split_token` audit event either way. Returns None if a part is
+/// missing.
+pub(crate) fn recombine(
+    token: &str,
+    parts: &[EnvName; 2],
+    values: [Option<&CStr>; 2],
+) -> Option<CString> {
+    let [Some(first), Some(second)] = values else {
+        let missing = parts
+            .iter()
+            .zip(values)
+            .find(|(_, value)| value.is_none())
+            .map_or_else(String::new, |(part, _)| part.lossy().into_owned());
+        audit::emit(
+            "split_token",
+            token,
+            &format!("action=incomplete missing={}", audit::detail_word(&missing)),
+        );
+        return None;
+    };
+    // Room for the terminator, so CString::new does not reallocate and leave
+    // a copy behind
+    let mut value = Vec::with_capacity(first.to_bytes().len() + second.to_bytes().len() + 1);
+    value.extend_from_slice(first.to_bytes());
+    value.extend_from_slice(second.to_bytes());
+    audit::emit(
+        "split_token",
+        token,
+        &format!("action=recombined bytes={}", value.len()),
+    );
+    // Neither part contains a NUL byte
+    CString::new(value).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_part_names() {
+        let [first, second] = part_names(&EnvName::from("GITHUB_TOKEN"));
+        assert_eq!(first, "GITHUB_TOKEN_PART1");
+        assert_eq!(second, "GITHUB_TOKEN_PART2");
+    }
+
+    #[test]
+    fn test_recombine() {
+        let parts = part_names(&EnvName::from("GITHUB_TOKEN"));
+        let value = recombine(
+            "GITHUB_TOKEN",
+            &parts,
+            [Some(c"ghp_0123"), Some(c"456789abcdef")],
+        );
+        assert_eq!(value.unwrap().as_bytes(), b"ghp_0123456789abcdef");
+        assert!(recombine("GITHUB_TOKEN", &parts, [Some(c"ghp_0123"), None]).is_none());
+        assert!(recombine("GITHUB_TOKEN", &parts, [None, None]).is_none());
+    }
+}
@@ -0,0 +1,652 @@
+//! Output redaction and file write scanning for glibc's stdio
+//!
+//! glibc's stdio flushes its buffers through an internal entry point that the
+//! write interposer never sees, so printf, puts and fwrite would reach the
+//! step log unredacted and the scanned files unchecked. The stdio functions
+//! that take whole strings are interposed instead: what the program writes is
+//! redacted on a stream whose descriptor is 1 or 2 (see the egress module),
+//! or checked as a write to the stream's descriptor would be (see the
+//! filewrite module), before it enters the stream buffer, so the streams
+//! themselves and their buffering are left alone. Formatted output is
+//! formatted here with vsnprintf first. Nothing is done until a protected
+//! value is cached.
+//!
+//! printf, fprintf, dprintf and their fortified variants are variadic, which
+//! Rust cannot define. Each is a naked function that saves the argument
+//! registers and hands the fixed arguments and a `va_list` over the rest to
+//! its Rust counterpart, as the C function's prologue would. Characters
+//! written one at a time (putc, fputc) are not scanned, nor is output glibc
+//! writes internally (perror, assert).
+
+use crate::filewrite::{self, Check};
+use crate::{egress, resolve_next, sys};
+use libc::{c_char, c_int, c_void, size_t, FILE};
+use once_cell::sync::Lazy;
+
+extern "C" {
+    static mut stdout: *mut FILE;
+
+    fn vsnprintf(buf: *mut c_char, size: size_t, format: *const c_char, va: *mut c_void) -> c_int;
+    fn __vsnprintf_chk(
+        buf: *mut c_char,
+        size: size_t,
+        flag: c_int,
+        len: size_t,
+        format: *const c_char,
+        va: *mut c_void,
+    ) -> c_int;
+}
+
+/// Type signature for fwrite and fwrite_unlocked
+type FwriteFn = unsafe extern "C" fn(*const c_void, size_t, size_t, *mut FILE) -> size_t;
+
+/// Type signature for fputs and fputs_unlocked
+type FputsFn = unsafe extern "C" fn(*const c_char, *mut FILE) -> c_int;
+
+/// Type signature for puts
+type PutsFn = unsafe extern "C" fn(*const c_char) -> c_int;
+
+/// Type signature for vfprintf
+type VfprintfFn = unsafe extern "C" fn(*mut FILE, *const c_char, *mut c_void) -> c_int;
+
+/// Type signature for __vfprintf_chk
+type VfprintfChkFn = unsafe extern "C" fn(*mut FILE, c_int, *const c_char, *mut c_void) -> c_int;
+
+/// Type signature for vdprintf
+type VdprintfFn = unsafe extern "C" fn(c_int, *const c_char, *mut c_void) -> c_int;
+
+/// Type signature for __vdprintf_chk
+type VdprintfChkFn = unsafe extern "C" fn(c_int, c_int, *const c_char, *mut c_void) -> c_int;
+
+/// Cached pointer to the real fwrite function
+static REAL_FWRITE: Lazy<FwriteFn> = Lazy::new(|| {
+    // SAFETY: fwrite has the FwriteFn signature
+    unsafe { std::mem::transmute::<*mut c_void, FwriteFn>(resolve_next(c"fwrite")) }
+});
+
+/// Cached pointer to the real fwrite_unlocked function
+static REAL_FWRITE_UNLOCKED: Lazy<FwriteFn> = Lazy::new(|| {
+    // SAFETY: fwrite_unlocked has the FwriteFn signature
+    unsafe { std::mem::transmute::<*mut c_void, FwriteFn>(resolve_next(c"fwrite_unlocked")) }
+});
+
+/// Cached pointer to the real fputs function
+static REAL_FPUTS: Lazy<FputsFn> = Lazy::new(|| {
+    // SAFETY: fputs has the FputsFn signature
+    unsafe { std::mem::transmute::<*mut c_void, FputsFn>(resolve_next(c"fputs")) }
+});
+
+/// Cached pointer to the real fputs_unlocked function
+static REAL_FPUTS_UNLOCKED: Lazy<FputsFn> = Lazy::new(|| {
+    // SAFETY: fputs_unlocked has the FputsFn signature
+    unsafe { std::mem::transmute::<*mut c_void, FputsFn>(resolve_next(c"fputs_unlocked")) }
+});
+
+/// Cached pointer to the real puts function
+static REAL_PUTS: Lazy<PutsFn> = Lazy::new(|| {
+    // SAFETY: puts has the PutsFn signature
+    unsafe { std::mem::transmute::<*mut c_void, PutsFn>(resolve_next(c"puts")) }
+});
+
+/// Cached pointer to the real vfprintf function
+static REAL_VFPRINTF: Lazy<VfprintfFn> = Lazy::new(|| {
+    // SAFETY: vfprintf has the VfprintfFn signature
+    unsafe { std::mem::transmute::<*mut c_void, VfprintfFn>(resolve_next(c"vfprintf")) }
+});
+
+/// Cached pointer to the real __vfprintf_chk function
+static REAL_VFPRINTF_CHK: Lazy<VfprintfChkFn> = Lazy::new(|| {
+    // SAFETY: __vfprintf_chk has the VfprintfChkFn signature
+    unsafe { std::mem::transmute::<*mut c_void, VfprintfChkFn>(resolve_next(c"__vfprintf_chk")) }
+});
+
+/// Cached pointer to the real vdprintf function
+static REAL_VDPRINTF: Lazy<VdprintfFn> = Lazy::new(|| {
+    // SAFETY: vdprintf has the VdprintfFn signature
+    unsafe { std::mem::transmute::<*mut c_void, VdprintfFn>(resolve_next(c"vdprintf")) }
+});
+
+/// Cached pointer to the real __vdprintf_chk function
+static REAL_VDPRINTF_CHK: Lazy<VdprintfChkFn> = Lazy::new(|| {
+    // SAFETY: __vdprintf_chk has the VdprintfChkFn signature
+    unsafe { std::mem::transmute::<*mut c_void, VdprintfChkFn>(resolve_next(c"__vdprintf_chk")) }
+});
+
+/// Whether anything written through stdio may have to be changed or blocked
+fn checking() -> bool {
+    (egress::redacting_output() || filewrite::enabled())
+        && egress::with_secrets(|secrets| !secrets.is_empty())
+}
+
+/// What to write to `fd` for `data`: redacted on stdout and stderr, checked
+/// as a write to a file otherwise
+///
+/// # Safety
+/// Called only from the interposers
+unsafe fn check(fd: c_int, data: &[u8]) -> Check {
+    match egress::redact_output(fd, data) {
+        Some(redacted) => Check::Redact(redacted),
+        None => filewrite::check(fd, data.as_ptr().cast(), data.len(), "stdio"),
+    }
+}
+
+/// Write `data` to `stream` with `fwrite` if it has to be changed or
+/// blocked first, returning whether all of it was written, or None if the
+/// caller's buffer can be written as is
+///
+/// # Safety
+/// `stream` must be null or a valid stream and `fwrite` fwrite or
+/// fwrite_unlocked
+unsafe fn write_checked(fwrite: FwriteFn, stream: *mut FILE, data: &[u8]) -> Option<bool> {
+    if stream.is_null() || !checking() {
+        return None;
+    }
+    match check(libc::fileno(stream), data) {
+        Check::Allow => None,
+        Check::Redact(copy) => Some(write_all(fwrite, stream, &copy)),
+        Check::Block => {
+            *sys::errno_location() = libc::EACCES;
+            Some(false)
+        }
+    }
+}
+
+/// Write all of `data` to `stream` with `fwrite`
+///
+/// # Safety
+/// `stream` must be a valid stream and `fwrite` fwrite or fwrite_unlocked
+unsafe fn write_all(fwrite: FwriteFn, stream: *mut FILE, data: &[u8]) -> bool {
+    fwrite(data.as_ptr().cast(), 1, data.len(), stream) == data.len()
+}
+
+/// A va_list of the C ABI, as copied by va_copy
+#[cfg(target_arch = "x86_64")]
+type VaList = [u64; 3];
+#[cfg(target_arch = "aarch64")]
+type VaList = [u64; 4];
+/// A va_list of the C ABI is a plain pointer to the arguments
+#[cfg(target_arch = "x86")]
+type VaList = ();
+
+/// A va_list to read the arguments of `va` from, stored in `copy` where
+/// the ABI needs it, leaving `va` for another reader
+///
+/// # Safety
+/// `va` must be a va_list as passed to the v*printf functions
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+unsafe fn va_copy(va: *mut c_void, copy: &mut VaList) -> *mut c_void {
+    *copy = *(va as *const VaList);
+    copy as *mut VaList as *mut c_void
+}
+
+#[cfg(target_arch = "x86")]
+unsafe fn va_copy(va: *mut c_void, _copy: &mut VaList) -> *mut c_void {
+    va
+}
+
+/// The output of `fmt` with the arguments of `va`, or None if it cannot
+/// be formatted
+///
+/// `flag` is the fortification level of the _chk variants. `va` is left for
+/// the real function, should the output not need redacting.
+///
+/// # Safety
+/// The arguments must satisfy the requirements of vsnprintf(3)
+unsafe fn format(flag: Option<c_int>, fmt: *const c_char, va: *mut c_void) -> Option<Vec<u8>> {
+    let render = |buf: *mut c_char, size: size_t| {
+        let mut copy = VaList::default();
+        let va = va_copy(va, &mut copy);
+        match flag {
+            Some(flag) => __vsnprintf_chk(buf, size, flag, size, fmt, va),
+            None => vsnprintf(buf, size, fmt, va),
+        }
+    };
+    let len = usize::try_from(render(std::ptr::null_mut(), 0)).ok()?;
+    let mut buf = vec![0u8; len + 1];
+    if render(buf.as_mut_ptr().cast(), buf.len()) < 0 {
+        return None;
+    }
+    buf.truncate(len);
+    Some(buf)
+}
+
+/// Formatted output to `stream`, through `real` unless it has to be checked
+///
+/// # Safety
+/// The arguments must satisfy the requirements of vfprintf(3)
+unsafe fn print(
+    stream: *mut FILE,
+    flag: Option<c_int>,
+    fmt: *const c_char,
+    va: *mut c_void,
+    real: impl FnOnce() -> c_int,
+) -> c_int {
+    if stream.is_null() || !checking() {
+        return real();
+    }
+    let Some(text) = format(flag, fmt, va) else {
+        return real();
+    };
+    let written = write_checked(*REAL_FWRITE, stream, &text)
+        .unwrap_or_else(|| write_all(*REAL_FWRITE, stream, &text));
+    if written {
+        text.len() as c_int
+    } else {
+        -1
+    }
+}
+
+/// Formatted output to `fd`, through `real` unless it has to be checked
+///
+/// The output is written through the write interposer, which checks it.
+///
+/// # Safety
+/// The arguments must satisfy the requirements of vdprintf(3)
+unsafe fn dprint(
+    fd: c_int,
+    flag: Option<c_int>,
+    fmt: *const c_char,
+    va: *mut c_void,
+    real: impl FnOnce() -> c_int,
+) -> c_int {
+    if !checking() {
+        return real();
+    }
+    let Some(text) = format(flag, fmt, va) else {
+        return real();
+    };
+    let mut written = 0;
+    while written < text.len() {
+        let rest = &text[written..];
+        let n = egress::write(fd, rest.as_ptr().cast(), rest.len());
+        if n >= 0 {
+            written += n as usize;
+        } else if *sys::errno_location() != libc::EINTR {
+            return -1;
+        }
+    }
+    text.len() as c_int
+}
+
+/// Intercepted fwrite function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of fwrite(3).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn fwrite(
+    ptr: *const c_void,
+    size: size_t,
+    nmemb: size_t,
+    stream: *mut FILE,
+) -> size_t {
+    fwrite_with(*REAL_FWRITE, ptr, size, nmemb, stream)
+}
+
+/// Intercepted fwrite_unlocked function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of fwrite_unlocked(3).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn fwrite_unlocked(
+    ptr: *const c_void,
+    size: size_t,
+    nmemb: size_t,
+    stream: *mut FILE,
+) -> size_t {
+    fwrite_with(*REAL_FWRITE_UNLOCKED, ptr, size, nmemb, stream)
+}
+
+/// fwrite through `real`, unless the data has to be changed or blocked
+///
+/// # Safety
+/// Arguments must satisfy the requirements of fwrite(3)
+unsafe fn fwrite_with(
+    real: FwriteFn,
+    ptr: *const c_void,
+    size: size_t,
+    nmemb: size_t,
+    stream: *mut FILE,
+) -> size_t {
+    let Some(len) = size
+        .checked_mul(nmemb)
+        .filter(|len| *len > 0 && !ptr.is_null())
+    else {
+        return real(ptr, size, nmemb, stream);
+    };
+    let data = std::slice::from_raw_parts(ptr as *const u8, len);
+    match write_checked(real, stream, data) {
+        Some(true) => nmemb,
+        Some(false) => 0,
+        None => real(ptr, size, nmemb, stream),
+    }
+}
+
+/// Intercepted fputs function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of fputs(3).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn fputs(s: *const c_char, stream: *mut FILE) -> c_int {
+    fputs_with(*REAL_FPUTS, *REAL_FWRITE, s, stream)
+}
+
+/// Intercepted fputs_unlocked function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of fputs_unlocked(3).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn fputs_unlocked(s: *const c_char, stream: *mut FILE) -> c_int {
+    fputs_with(*REAL_FPUTS_UNLOCKED, *REAL_FWRITE_UNLOCKED, s, stream)
+}
+
+/// fputs through `real`, or through `fwrite` if the string has to be
+/// changed or blocked
+///
+/// # Safety
+/// Arguments must satisfy the requirements of fputs(3)
+unsafe fn fputs_with(
+    real: FputsFn,
+    fwrite: FwriteFn,
+    s: *const c_char,
+    stream: *mut FILE,
+) -> c_int {
+    if s.is_null() || !checking() {
+        return real(s, stream);
+    }
+    let data = std::ffi::CStr::from_ptr(s).to_bytes();
+    match write_checked(fwrite, stream, data) {
+        Some(true) => 1,
+        Some(false) => libc::EOF,
+        None => real(s, stream),
+    }
+}
+
+/// Intercepted puts function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of puts(3).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn puts(s: *const c_char) -> c_int {
+    let stream = *std::ptr::addr_of!(stdout);
+    if s.is_null() || !checking() {
+        return (*REAL_PUTS)(s);
+    }
+    let mut line = std::ffi::CStr::from_ptr(s).to_bytes().to_vec();
+    line.push(b'\n');
+    match write_checked(*REAL_FWRITE, stream, &line) {
+        Some(true) => 1,
+        Some(false) => libc::EOF,
+        None => (*REAL_PUTS)(s),
+    }
+}
+
+/// Intercepted vfprintf function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of vfprintf(3).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn vfprintf(stream: *mut FILE, fmt: *const c_char, va: *mut c_void) -> c_int {
+    print(stream, None, fmt, va, || (*REAL_VFPRINTF)(stream, fmt, va))
+}
+
+/// Intercepted __vfprintf_chk function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of vfprintf(3).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn __vfprintf_chk(
+    stream: *mut FILE,
+    flag: c_int,
+    fmt: *const c_char,
+    va: *mut c_void,
+) -> c_int {
+    print(stream, Some(flag), fmt, va, || {
+        (*REAL_VFPRINTF_CHK)(stream, flag, fmt, va)
+    })
+}
+
+/// Intercepted vprintf function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of vprintf(3).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn vprintf(fmt: *const c_char, va: *mut c_void) -> c_int {
+    vfprintf(*std::ptr::addr_of!(stdout), fmt, va)
+}
+
+/// Intercepted __vprintf_chk function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of vprintf(3).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn __vprintf_chk(flag: c_int, fmt: *const c_char, va: *mut c_void) -> c_int {
+    __vfprintf_chk(*std::ptr::addr_of!(stdout), flag, fmt, va)
+}
+
+/// Intercepted vdprintf function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of vdprintf(3).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn vdprintf(fd: c_int, fmt: *const c_char, va: *mut c_void) -> c_int {
+    dprint(fd, None, fmt, va, || (*REAL_VDPRINTF)(fd, fmt, va))
+}
+
+/// Intercepted __vdprintf_chk function
+///
+/// # Safety
+/// This function is called from C code and must maintain C ABI compatibility.
+/// Arguments must satisfy the requirements of vdprintf(3).
+#[cfg_attr(feature = "preload", no_mangle)]
+pub unsafe extern "C" fn __vdprintf_chk(
+    fd: c_int,
+    flag: c_int,
+    fmt: *const c_char,
+    va: *mut c_void,
+) -> c_int {
+    dprint(fd, Some(flag), fmt, va, || {
+        (*REAL_VDPRINTF_CHK)(fd, flag, fmt, va)
+    })
+}
+
+/// Define the variadic function `$name`, whose first `$fixed` arguments are
+/// not variadic, as a call of `$target` with those arguments, as an array
+/// of words, and a va_list over the rest
+#[cfg(all(feature = "preload", target_arch = "x86_64"))]
+macro_rules! variadic {
+    ($name:ident, $fixed:literal, $target:path) => {
+        /// Intercepted variadic function, see the module documentation
+        ///
+        /// # Safety
+        /// This function is called from C code and must maintain C ABI
+        /// compatibility.
+        #[unsafe(naked)]
+        #[no_mangle]
+        pub unsafe extern "C" fn $name() {
+            core::arch::naked_asm!(
+                // Save the argument registers as the register save area:
+                // six words, then eight vector registers
+                "push rbp",
+                "mov rbp, rsp",
+                "sub rsp, 208",
+                "mov [rsp], rdi",
+                "mov [rsp + 8], rsi",
+                "mov [rsp + 16], rdx",
+                "mov [rsp + 24], rcx",
+                "mov [rsp + 32], r8",
+                "mov [rsp + 40], r9",
+                "movaps [rsp + 48], xmm0",
+                "movaps [rsp + 64], xmm1",
+                "movaps [rsp + 80], xmm2",
+                "movaps [rsp + 96], xmm3",
+                "movaps [rsp + 112], xmm4",
+                "movaps [rsp + 128], xmm5",
+                "movaps [rsp + 144], xmm6",
+                "movaps [rsp + 160], xmm7",
+                // va_list: gp_offset, fp_offset, overflow_arg_area and
+                // reg_save_area
+                "mov dword ptr [rsp + 176], {gp_offset}",
+                "mov dword ptr [rsp + 180], 48",
+                "lea rax, [rbp + 16]",
+                "mov [rsp + 184], rax",
+                "mov [rsp + 192], rsp",
+                "mov rdi, rsp",
+                "lea rsi, [rsp + 176]",
+                "call {target}",
+                "leave",
+                "ret",
+                gp_offset = const $fixed * 8,
+                target = sym $target,
+            )
+        }
+    };
+}
+
+#[cfg(all(feature = "preload", target_arch = "aarch64"))]
+macro_rules! variadic {
+    ($name:ident, $fixed:literal, $target:path) => {
+        /// Intercepted variadic function, see the module documentation
+        ///
+        /// # Safety
+        /// This function is called from C code and must maintain C ABI
+        /// compatibility.
+        #[unsafe(naked)]
+        #[no_mangle]
+        pub unsafe extern "C" fn $name() {
+            core::arch::naked_asm!(
+                // Save the argument registers: eight words, then eight
+                // vector registers
+                "stp x29, x30, [sp, #-16]!",
+                "mov x29, sp",
+                "sub sp, sp, #224",
+                "stp x0, x1, [sp]",
+                "stp x2, x3, [sp, #16]",
+                "stp x4, x5, [sp, #32]",
+                "stp x6, x7, [sp, #48]",
+                "stp q0, q1, [sp, #64]",
+                "stp q2, q3, [sp, #96]",
+                "stp q4, q5, [sp, #128]",
+                "stp q6, q7, [sp, #160]",
+                // va_list: __stack, __gr_top, __vr_top, __gr_offs and
+                // __vr_offs
+                "add x9, x29, #16",
+                "str x9, [sp, #192]",
+                "add x9, sp, #64",
+                "str x9, [sp, #200]",
+                "add x9, sp, #192",
+                "str x9, [sp, #208]",
+                "mov w9, #{gr_offs}",
+                "str w9, [sp, #216]",
+                "mov w9, #-128",
+                "str w9, [sp, #220]",
+                "mov x0, sp",
+                "add x1, sp, #192",
+                "bl {target}",
+                "mov sp, x29",
+                "ldp x29, x30, [sp], #16",
+                "ret",
+                gr_offs = const $fixed * 8 - 64,
+                target = sym $target,
+            )
+        }
+    };
+}
+
+#[cfg(all(feature = "preload", target_arch = "x86"))]
+macro_rules! variadic {
+    ($name:ident, $fixed:literal, $target:path) => {
+        /// Intercepted variadic function, see the module documentation
+        ///
+        /// # Safety
+        /// This function is called from C code and must maintain C ABI
+        /// compatibility.
+        #[unsafe(naked)]
+        #[no_mangle]
+        pub unsafe extern "C" fn $name() {
+            core::arch::naked_asm!(
+                // The arguments are all on the stack, and a va_list points
+                // to the first variadic one
+                "lea eax, [esp + 4]",
+                "lea ecx, [esp + {va_offset}]",
+                "sub esp, 4",
+                "push ecx",
+                "push eax",
+                "call {target}",
+                "add esp, 12",
+                "ret",
+                va_offset = const 4 + $fixed * 4,
+                target = sym $target,
+            )
+        }
+    };
+}
+
+#[cfg(feature = "preload")]
+variadic!(printf, 1, printf_va);
+#[cfg(feature = "preload")]
+variadic!(fprintf, 2, fprintf_va);
+#[cfg(feature = "preload")]
+variadic!(dprintf, 2, dprintf_va);
+#[cfg(feature = "preload")]
+variadic!(__printf_chk, 2, printf_chk_va);
+#[cfg(feature = "preload")]
+variadic!(__fprintf_chk, 3, fprintf_chk_va);
+#[cfg(feature = "preload")]
+variadic!(__dprintf_chk, 3, dprintf_chk_va);
+
+/// printf(format, ...)
+#[cfg(feature = "preload")]
+unsafe extern "C" fn printf_va(args: *const usize, va: *mut c_void) -> c_int {
+    vprintf(*args as *const c_char, va)
+}
+
+/// fprintf(stream, format, ...)
+#[cfg(feature = "preload")]
+unsafe extern "C" fn fprintf_va(args: *const usize, va: *mut c_void) -> c_int {
+    vfprintf(*args as *mut FILE, *args.add(1) as *const c_char, va)
+}
+
+/// dprintf(fd, format, ...)
+#[cfg(feature = "preload")]
+unsafe extern "C" fn dprintf_va(args: *const usize, va: *mut c_void) -> c_int {
+    vdprintf(*args as c_int, *args.add(1) as *const c_char, va)
+}
+
+/// __printf_chk(flag, format, ...)
+#[cfg(feature = "preload")]
+unsafe extern "C" fn printf_chk_va(args: *const usize, va: *mut c_void) -> c_int {
+    __vprintf_chk(*args as c_int, *args.add(1) as *const c_char, va)
+}
+
+/// __fprintf_chk(stream, flag, format, ...)
+#[cfg(feature = "preload")]
+unsafe extern "C" fn fprintf_chk_va(args: *const usize, va: *mut c_void) -> c_int {
+    let stream = *args as *mut FILE;
+    __vfprintf_chk(
+        stream,
+        *args.add(1) as c_int,
+        *args.add(2) as *const c_char,
+        va,
+    )
+}
+
+/// __dprintf_chk(fd, flag, format, ...)
+#[cfg(feature = "preload")]
+unsafe extern "C" fn dprintf_chk_va(args: *const usize, va: *mut c_void) -> c_int {
+    __vdprintf_chk(
+        *args as c_int,
+        *args.add(1) as c_int,
+        *args.add(2) as *const c_char,
+        va,
+    )
+}
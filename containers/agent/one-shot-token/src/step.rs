@@ -0,0 +1,221 @@
+//! Per-step scoping (AWF_STEP_ID and AWF_ONE_SHOT_STEP_FILE)
+//!
+//! A long-lived process can outlive the workflow step its tokens were granted
+//! for: a token a build step needed should not still be served when the same
+//! process runs a later "post comment" step. The host announces a new step by
+//! setting AWF_STEP_ID in the process (setenv or putenv, which the library
+//! sees), or by writing the new step ID to, or just touching, the file named
+//! by AWF_ONE_SHOT_STEP_FILE, which is checked before every read of a
+//! protected token.
+//!
+//! The step file is only checked on reads, so a process that stopped reading
+//! its tokens would keep them until its next read. For an immediate step
+//! end, the host can send the signal named by AWF_ONE_SHOT_STEP_SIGNAL (see
+//! the signals module), or set AWF_ONE_SHOT_STEP_WATCH to have the file
+//! watched in the background (see the stepwatch module).
+//!
+//! On a step change every cached value is zeroized, tokens not read yet are
+//! scrubbed from the environment, and later reads return NULL with a
+//! `step_changed` audit event. A token has to be authorized again for the new
+//! step: the host can set it anew (see the setenv module), and tokens the
+//! broker mints are minted again on their next read, so the broker decides
+//! whether the new step gets one.
+
+use crate::{
+    audit, call_real_getenv, context, egress, lock_state, procfs, wipe_every_token, Mode,
+    TokenState,
+};
+use std::ffi::{CStr, CString};
+
+/// Variable holding the step ID
+const STEP_VAR: &CStr = c"AWF_STEP_ID";
+
+/// Why reads after a step change are refused
+const REASON: &str = "step_changed";
+
+/// What identifies a version of the step file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    dev: u64,
+    ino: u64,
+    size: i64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+/// The step file and the version of it last seen
+pub(crate) struct StepFile {
+    path: CString,
+    stamp: Option<Stamp>,
+}
+
+impl StepFile {
+    /// Watch the file at `path`; None if the path is empty
+    pub(crate) fn new(path: &str) -> Option<Self> {
+        let path = CString::new(path.trim())
+            .ok()
+            .filter(|path| !path.is_empty())?;
+        let mut file = StepFile { path, stamp: None };
+        file.stamp = file.stamp();
+        Some(file)
+    }
+
+    /// Path of the watched file
+    #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+    pub(crate) fn path(&self) -> &CStr {
+        &self.path
+    }
+
+    fn stamp(&self) -> Option<Stamp> {
+        // SAFETY: stat is plain data, and path is a valid C string
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::stat(self.path.as_ptr(), &mut stat) } != 0 {
+            return None;
+        }
+        Some(Stamp {
+            dev: stat.st_dev as u64,
+            ino: stat.st_ino as u64,
+            size: stat.st_size as i64,
+            mtime: stat.st_mtime as i64,
+            mtime_nsec: stat.st_mtime_nsec as i64,
+        })
+    }
+
+    /// If the file changed since it was last checked, the step ID it holds
+    /// (None if it is empty or gone)
+    pub(crate) fn poll(&mut self) -> Option<Option<String>> {
+        let stamp = self.stamp();
+        if stamp == self.stamp {
+            return None;
+        }
+        self.stamp = stamp;
+        // SAFETY: path is a valid C string
+        let content = unsafe { procfs::read_file(&self.path) }.unwrap_or_default();
+        let step = String::from_utf8_lossy(&content).trim().to_string();
+        Some((!step.is_empty()).then_some(step))
+    }
+}
+
+/// Start step `step`: zeroize and scrub every token, and refuse later reads
+///
+/// Minted tokens are dropped from the cache instead of being refused, so the
+/// broker is asked for a new one.
+pub(crate) fn change(state: &mut TokenState, step: Option<String>, via: &str) {
+    let previous = std::mem::replace(&mut state.step, step);
+    let wiped = wipe_every_token(state, REASON);
+    state.cache.retain(|name, _| !state.mint.contains(name));
+
+    context::set_step(state.step.as_deref());
+    if let Some(propagation) = state.propagation.as_mut() {
+        propagation.set_config("AWF_STEP_ID", state.step.as_deref());
+    }
+    egress::publish_secrets(state);
+    audit::emit(
+        REASON,
+        "*",
+        &format!(
+            "from={} to={} via={} wiped={}",
+            audit::detail_word(previous.as_deref().unwrap_or("")),
+            audit::detail_word(state.step.as_deref().unwrap_or("")),
+            via,
+            wiped
+        ),
+    );
+}
+
+/// Start a new step if the step file changed
+pub(crate) fn check_file(state: &mut TokenState) {
+    let Some(step) = state.step_file.as_mut().and_then(StepFile::poll) else {
+        return;
+    };
+    // A touched file keeps the step ID but still starts a new step
+    let step = step.or_else(|| state.step.clone());
+    change(state, step, "file");
+}
+
+/// End the current step now, at the host's request through `via`: zeroize
+/// every token, keeping the step ID, as a touched step file does
+#[cfg(not(feature = "minimal"))]
+pub(crate) fn end(via: &str) {
+    let mut state = lock_state();
+    if state.mode == Mode::Observe {
+        audit::emit("observed", "*", &format!("would=end_step via={}", via));
+        return;
+    }
+    let step = state.step.clone();
+    change(&mut state, step, via);
+}
+
+/// Start a new step if a write to `name` changed AWF_STEP_ID
+///
+/// Called after the write reached the environment; the value is read back, as
+/// `setenv(..., 0)` leaves an existing value in place.
+pub(crate) fn written(name: &[u8], via: &str) {
+    if name != STEP_VAR.to_bytes() {
+        return;
+    }
+    let mut state = lock_state();
+    if state.mode == Mode::Observe {
+        return;
+    }
+    // SAFETY: the name is a valid C string, and a non-null result is one too
+    let value = unsafe { call_real_getenv(STEP_VAR.as_ptr()) };
+    let step = (!value.is_null())
+        .then(|| {
+            unsafe { CStr::from_ptr(value) }
+                .to_string_lossy()
+                .trim()
+                .to_string()
+        })
+        .filter(|step| !step.is_empty());
+    if step != state.step {
+        change(&mut state, step, via);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envname::EnvName;
+    use crate::CachedToken;
+
+    #[test]
+    fn test_step_file_poll() {
+        let path = std::env::temp_dir().join(format!("awf-step-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut file = StepFile::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(file.poll(), None);
+
+        std::fs::write(&path, "build\n").unwrap();
+        assert_eq!(file.poll(), Some(Some("build".to_string())));
+        assert_eq!(file.poll(), None);
+
+        std::fs::write(&path, "post-comment-step\n").unwrap();
+        assert_eq!(file.poll(), Some(Some("post-comment-step".to_string())));
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(file.poll(), Some(None));
+        assert!(StepFile::new(" ").is_none());
+    }
+
+    #[test]
+    fn test_change_wipes_cache() {
+        let mut state = TokenState::new();
+        for name in ["AWF_TEST_STEP_CACHED", "AWF_TEST_STEP_UNREAD"] {
+            let (spec, _) = crate::policy::parse_token_spec(name).unwrap();
+            state.tokens.push(spec);
+        }
+        let mut value = *b"ghp_step\0";
+        state.cache.insert(
+            "AWF_TEST_STEP_CACHED".into(),
+            CachedToken::cached(value.as_mut_ptr().cast()),
+        );
+
+        change(&mut state, Some("post-comment".to_string()), "setenv");
+        assert_eq!(state.step.as_deref(), Some("post-comment"));
+        assert_eq!(value, [0u8; 9]);
+        for name in ["AWF_TEST_STEP_CACHED", "AWF_TEST_STEP_UNREAD"] {
+            assert_eq!(state.cache[&EnvName::from(name)].wiped, Some(REASON));
+        }
+    }
+}
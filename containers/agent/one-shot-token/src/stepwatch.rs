@@ -0,0 +1,193 @@
+//! Immediate step changes from the step file (AWF_ONE_SHOT_STEP_WATCH)
+//!
+//! The step file (see the step module) is checked before every read of a
+//! protected token, so a process that cached its tokens and stopped reading
+//! them keeps the values until its next read. With AWF_ONE_SHOT_STEP_WATCH
+//! set, a background thread watches the file with inotify and starts the new
+//! step as soon as the host writes, touches, replaces or removes it, so every
+//! cached value is zeroized right away.
+//!
+//! The directory holding the file is watched, so the file may also be created
+//! later or replaced by a rename. Like the watchdog thread, the watcher is
+//! restarted in forked children, with a new inotify instance.
+
+use crate::{lock_state, step};
+use libc::c_int;
+use std::ffi::{CStr, CString};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Events on the directory that may change the step file
+const MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_ATTRIB
+    | libc::IN_MODIFY
+    | libc::IN_CREATE
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE;
+
+/// Size of the fixed part of an inotify event
+const HEADER: usize = std::mem::size_of::<libc::inotify_event>();
+
+/// inotify instance of this process's watcher (-1 if none)
+static WATCH_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Start the watcher at load if AWF_ONE_SHOT_STEP_WATCH is configured
+extern "C" fn start_step_watch_at_load() {
+    start();
+}
+
+#[used]
+#[cfg_attr(feature = "preload", link_section = ".init_array")]
+static START_STEP_WATCH_AT_LOAD: extern "C" fn() = start_step_watch_at_load;
+
+/// Directory and file name of `path`
+fn split_path(path: &CStr) -> Option<(CString, Vec<u8>)> {
+    let bytes = path.to_bytes();
+    let (dir, name) = match bytes.iter().rposition(|&byte| byte == b'/') {
+        Some(0) => (&b"/"[..], &bytes[1..]),
+        Some(slash) => (&bytes[..slash], &bytes[slash + 1..]),
+        None => (&b"."[..], bytes),
+    };
+    if name.is_empty() {
+        return None;
+    }
+    Some((CString::new(dir).ok()?, name.to_vec()))
+}
+
+/// Whether the events in `buf` include one on the file `name`
+fn names_file(buf: &[u8], name: &[u8]) -> bool {
+    let mut offset = 0;
+    while offset + HEADER <= buf.len() {
+        // SAFETY: a whole header is in bounds; it may not be aligned
+        let event: libc::inotify_event =
+            unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr().cast()) };
+        let start = offset + HEADER;
+        let end = (start + event.len as usize).min(buf.len());
+        // The name is padded with NULs
+        let event_name = buf[start..end].split(|&byte| byte == 0).next();
+        if event_name == Some(name) {
+            return true;
+        }
+        offset = end;
+    }
+    false
+}
+
+/// Watch directory `dir`, replacing this process's previous instance
+fn watch(dir: &CStr) -> std::io::Result<c_int> {
+    // SAFETY: plain system calls on a descriptor created here
+    unsafe {
+        let fd = libc::inotify_init1(libc::IN_CLOEXEC);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if libc::inotify_add_watch(fd, dir.as_ptr(), MASK) < 0 {
+            let err = std::io::Error::last_os_error();
+            libc::close(fd);
+            return Err(err);
+        }
+        let old = WATCH_FD.swap(fd, Ordering::Relaxed);
+        if old >= 0 {
+            // The previous instance belongs to the parent's thread, which
+            // does not exist in this process
+            libc::close(old);
+        }
+        Ok(fd)
+    }
+}
+
+/// Start the watcher thread if AWF_ONE_SHOT_STEP_WATCH and a step file are
+/// configured
+///
+/// Called at load, and again in forked children, which do not inherit the
+/// parent's threads.
+pub(crate) fn start() {
+    let path = {
+        let state = lock_state();
+        match (&state.step_file, state.step_watch) {
+            (Some(file), true) => file.path().to_owned(),
+            _ => return,
+        }
+    };
+    let Some((dir, name)) = split_path(&path) else {
+        return;
+    };
+
+    let started = watch(&dir).and_then(|fd| {
+        std::thread::Builder::new()
+            .name("awf-stepwatch".to_string())
+            .spawn(move || {
+                let mut buf = [0u8; 4096];
+                loop {
+                    // SAFETY: buf is valid for buf.len() bytes
+                    let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+                    if n < 0
+                        && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+                    {
+                        continue;
+                    }
+                    if n <= 0 {
+                        return;
+                    }
+                    if names_file(&buf[..n as usize], &name) {
+                        step::check_file(&mut lock_state());
+                    }
+                }
+            })
+    });
+
+    if let Err(err) = started {
+        log_line!(
+            Warning,
+            "stepwatch",
+            None,
+            "Could not watch the step file {}: {}",
+            path.to_string_lossy(),
+            err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_path() {
+        let split = split_path;
+        assert_eq!(
+            split(c"/run/awf/step"),
+            Some((c"/run/awf".to_owned(), b"step".to_vec()))
+        );
+        assert_eq!(split(c"/step"), Some((c"/".to_owned(), b"step".to_vec())));
+        assert_eq!(split(c"step"), Some((c".".to_owned(), b"step".to_vec())));
+        assert_eq!(split(c"/run/awf/"), None);
+    }
+
+    #[test]
+    fn test_names_file() {
+        let event = |name: &[u8], padded: usize| {
+            let header = libc::inotify_event {
+                wd: 1,
+                mask: libc::IN_ATTRIB,
+                cookie: 0,
+                len: padded as u32,
+            };
+            // SAFETY: inotify_event is plain data
+            let mut bytes = unsafe {
+                std::slice::from_raw_parts(
+                    (&header as *const libc::inotify_event).cast::<u8>(),
+                    HEADER,
+                )
+            }
+            .to_vec();
+            bytes.extend_from_slice(name);
+            bytes.resize(HEADER + padded, 0);
+            bytes
+        };
+        let mut buf = event(b"other", 16);
+        assert!(!names_file(&buf, b"step"));
+        buf.extend(event(b"step", 16));
+        assert!(names_file(&buf, b"step"));
+        assert!(!names_file(&event(b"steps", 16), b"step"));
+    }
+}
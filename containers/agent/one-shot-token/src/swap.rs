@@ -0,0 +1,124 @@
+//! Placeholder tokens swapped by the proxy (the `placeholder` token policy)
+//!
+//! A `placeholder` token is served as `awfp_` followed by 32 random hex
+//! digits, made up when the value is cached, so the program never holds the
+//! real credential. The library registers the pair with the swap proxy
+//! listening on AWF_ONE_SHOT_SWAP_SOCKET, e.g. awf-token-swap on the host
+//! with the socket mounted into the container, and the proxy puts the real
+//! value in place of the placeholder in requests to the hosts allowed for
+//! the token.
+//!
+//! The socket is checked like the broker's: its other end must run as
+//! AWF_ONE_SHOT_SWAP_UID (default 0) before the value is sent. A placeholder
+//! that could not be registered is still served; requests carrying it then
+//! fail to authenticate, and the real value stays out of the program either
+//! way.
+//!
+//! The same socket takes honeypot values (see the honeypot module), which
+//! stand for no value at all: the proxy refuses every request carrying one.
+//!
+//! Protocol: one exchange per connection. The request is the line
+//! `REGISTER <name> <placeholder> <value>` or `HONEYPOT <name> <value>`, the
+//! answer `OK registered` or `ERR <reason>`.
+
+use crate::broker::{self, Broker, Failure};
+use crate::{audit, sys};
+use std::ffi::CStr;
+
+/// Prefix of placeholder values
+pub(crate) const PREFIX: &str = "awfp_";
+
+/// A new placeholder, or None if no entropy could be read
+pub(crate) fn placeholder() -> Option<String> {
+    let mut bytes = [0u8; 16];
+    if !sys::fill_random(&mut bytes) {
+        return None;
+    }
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Some(format!("{}{}", PREFIX, hex))
+}
+
+/// Register `placeholder` as standing for `value` of `token`
+///
+/// Emits a `token_swap` audit event either way (neither the placeholder nor
+/// the value is logged). Returns whether the proxy accepted the pair.
+///
+/// # Safety
+/// Only performs system calls on a socket it creates
+pub(crate) unsafe fn register(swap: &Broker, token: &str, placeholder: &str, value: &CStr) -> bool {
+    let mut line = [
+        b"REGISTER ",
+        token.as_bytes(),
+        b" ",
+        placeholder.as_bytes(),
+        b" ",
+        value.to_bytes(),
+        b"\n",
+    ]
+    .concat();
+    let result = broker::request(swap, &line, broker::TIMEOUT_SECS);
+    zero(&mut line);
+    report(swap, token, result.map(drop), "")
+}
+
+/// Register the honeypot `value` of `token`
+///
+/// Emits a `token_swap` audit event with `kind=honeypot` either way (the
+/// value is not logged). Returns whether the proxy accepted it.
+///
+/// # Safety
+/// Only performs system calls on a socket it creates
+pub(crate) unsafe fn register_honeypot(swap: &Broker, token: &str, value: &str) -> bool {
+    let line = format!("HONEYPOT {} {}\n", token, value);
+    let result = broker::request(swap, line.as_bytes(), broker::TIMEOUT_SECS);
+    report(swap, token, result.map(drop), " kind=honeypot")
+}
+
+/// Emit the `token_swap` audit event of a registration; returns whether it
+/// succeeded
+fn report(swap: &Broker, token: &str, result: Result<(), Failure>, kind: &str) -> bool {
+    let registered = result.is_ok();
+    let socket = audit::detail_word(&swap.socket());
+    let detail = match result {
+        Ok(()) => format!("action=registered socket={}", socket),
+        Err(Failure::Refused(reason)) => format!(
+            "action=refused socket={} reason={}",
+            socket,
+            audit::detail_word(&reason)
+        ),
+        Err(Failure::Unreachable(err)) => format!(
+            "action=unreachable socket={} error={}",
+            socket,
+            audit::detail_word(&err.to_string())
+        ),
+    };
+    audit::emit("token_swap", token, &format!("{}{}", detail, kind));
+    registered
+}
+
+fn zero(bytes: &mut [u8]) {
+    // SAFETY: bytes is a writable buffer of the given length
+    unsafe { sys::explicit_bzero(bytes.as_mut_ptr().cast(), bytes.len()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholder() {
+        let placeholder = placeholder().unwrap();
+        assert_eq!(placeholder.len(), PREFIX.len() + 32);
+        assert!(placeholder[PREFIX.len()..]
+            .bytes()
+            .all(|byte| byte.is_ascii_hexdigit()));
+        assert_ne!(super::placeholder().unwrap(), placeholder);
+    }
+
+    #[test]
+    fn test_register_unreachable() {
+        let swap = Broker::parse("/nonexistent/awf-swap.sock", None).unwrap();
+        assert!(!unsafe { register(&swap, "GITHUB_TOKEN", "awfp_00", c"ghp_value") });
+        assert!(!unsafe { register_honeypot(&swap, "GITHUB_TOKEN", "ghp_fake") });
+    }
+}
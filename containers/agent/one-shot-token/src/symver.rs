@@ -0,0 +1,63 @@
+//! Versioned aliases of the getenv interposers
+//!
+//! The interposers are exported without a version, which the dynamic linker
+//! accepts for a program's versioned references such as getenv@GLIBC_2.2.5.
+//! dlvsym is stricter: it only returns a definition of exactly the requested
+//! version, so `dlvsym(RTLD_DEFAULT, "getenv", "GLIBC_2.2.5")` would skip
+//! this library and return libc's getenv. The wrappers below are exported
+//! under the glibc versions of each function (the nodes are defined in
+//! awf.map) so that such lookups find the interposers too. Only the
+//! versioned aliases are global; the wrappers keep their local names, and
+//! `.set` is used instead of `.symver` because an alias of a local symbol
+//! would otherwise stay local.
+
+use crate::{getenv, secure_getenv};
+use libc::c_char;
+
+/// Define `$alias` (a versioned name) as a global alias of `$target`
+macro_rules! versioned_alias {
+    ($($alias:literal => $target:ident),* $(,)?) => {
+        std::arch::global_asm!(
+            $(
+                concat!(".globl \"", $alias, "\""),
+                concat!(".type \"", $alias, "\", @function"),
+                concat!(".set \"", $alias, "\", {", stringify!($target), "}"),
+            )*
+            $($target = sym $target,)*
+        );
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+versioned_alias!(
+    "getenv@GLIBC_2.2.5" => versioned_getenv,
+    "secure_getenv@GLIBC_2.17" => versioned_secure_getenv,
+    "__secure_getenv@GLIBC_2.2.5" => versioned_compat_secure_getenv,
+);
+
+#[cfg(target_arch = "x86")]
+versioned_alias!(
+    "getenv@GLIBC_2.0" => versioned_getenv,
+    "secure_getenv@GLIBC_2.17" => versioned_secure_getenv,
+    "__secure_getenv@GLIBC_2.0" => versioned_compat_secure_getenv,
+);
+
+// glibc for aarch64 starts at 2.17, after __secure_getenv was retired
+#[cfg(target_arch = "aarch64")]
+versioned_alias!(
+    "getenv@GLIBC_2.17" => versioned_getenv,
+    "secure_getenv@GLIBC_2.17" => versioned_secure_getenv,
+);
+
+unsafe extern "C" fn versioned_getenv(name: *const c_char) -> *mut c_char {
+    getenv(name)
+}
+
+unsafe extern "C" fn versioned_secure_getenv(name: *const c_char) -> *mut c_char {
+    secure_getenv(name)
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+unsafe extern "C" fn versioned_compat_secure_getenv(name: *const c_char) -> *mut c_char {
+    crate::__secure_getenv(name)
+}
@@ -0,0 +1,187 @@
+//! libc calls that differ between Linux and macOS
+//!
+//! The rest of the library calls these instead of the Linux-only names, so
+//! both builds share the same code.
+
+use libc::{c_int, c_void, sigset_t};
+
+/// Address of the calling thread's errno
+pub(crate) fn errno_location() -> *mut c_int {
+    // SAFETY: both functions only return the thread's errno address
+    #[cfg(target_os = "linux")]
+    return unsafe { libc::__errno_location() };
+    #[cfg(target_os = "macos")]
+    return unsafe { libc::__error() };
+}
+
+/// Zero `len` bytes at `ptr` in a way the compiler cannot optimize out
+///
+/// # Safety
+/// `ptr` must be valid for writes of `len` bytes
+pub(crate) unsafe fn explicit_bzero(ptr: *mut c_void, len: usize) {
+    #[cfg(target_os = "linux")]
+    libc::explicit_bzero(ptr, len);
+    #[cfg(target_os = "macos")]
+    libc::memset_s(ptr, len, 0, len);
+}
+
+/// ID of the calling thread, as shown by the system's tools
+pub(crate) fn thread_id() -> u64 {
+    #[cfg(target_os = "linux")]
+    // SAFETY: gettid has no preconditions
+    return unsafe { libc::gettid() } as u64;
+    #[cfg(target_os = "macos")]
+    {
+        let mut id = 0;
+        // SAFETY: a null thread means the calling thread; id is a valid output
+        unsafe { libc::pthread_threadid_np(0, &mut id) };
+        id
+    }
+}
+
+/// Fill `bytes` from the kernel's random number generator
+///
+/// Returns false if no entropy could be read.
+pub(crate) fn fill_random(bytes: &mut [u8]) -> bool {
+    // SAFETY: bytes is a writable buffer of the given length
+    #[cfg(target_os = "linux")]
+    return unsafe { libc::getrandom(bytes.as_mut_ptr().cast(), bytes.len(), 0) }
+        == bytes.len() as isize;
+    // getentropy reads at most 256 bytes per call
+    #[cfg(target_os = "macos")]
+    return bytes
+        .chunks_mut(256)
+        .all(|chunk| unsafe { libc::getentropy(chunk.as_mut_ptr().cast(), chunk.len()) } == 0);
+}
+
+/// Mark a descriptor close-on-exec, for calls that cannot do it atomically
+///
+/// # Safety
+/// `fd` must be an open descriptor owned by the caller
+#[cfg(target_os = "macos")]
+unsafe fn set_cloexec(fd: c_int) {
+    libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+}
+
+/// Create a close-on-exec socket
+///
+/// macOS has no SOCK_CLOEXEC, so there the flag is set after creation.
+pub(crate) fn socket_cloexec(domain: c_int, kind: c_int) -> c_int {
+    // SAFETY: plain socket creation
+    #[cfg(target_os = "linux")]
+    return unsafe { libc::socket(domain, kind | libc::SOCK_CLOEXEC, 0) };
+    #[cfg(target_os = "macos")]
+    // SAFETY: plain socket creation; the flag is set on the new descriptor
+    unsafe {
+        let fd = libc::socket(domain, kind, 0);
+        if fd >= 0 {
+            set_cloexec(fd);
+        }
+        fd
+    }
+}
+
+/// Create a close-on-exec pipe
+///
+/// # Safety
+/// `fds` must have room for two descriptors
+#[cfg(not(feature = "minimal"))]
+pub(crate) unsafe fn pipe_cloexec(fds: *mut c_int) -> c_int {
+    #[cfg(target_os = "linux")]
+    return libc::pipe2(fds, libc::O_CLOEXEC);
+    #[cfg(target_os = "macos")]
+    {
+        let result = libc::pipe(fds);
+        if result == 0 {
+            set_cloexec(*fds);
+            set_cloexec(*fds.add(1));
+        }
+        result
+    }
+}
+
+/// Make `target` a close-on-exec duplicate of `fd`, replacing what it was
+///
+/// # Safety
+/// Both descriptors must be owned by the caller
+pub(crate) unsafe fn dup_cloexec(fd: c_int, target: c_int) {
+    #[cfg(target_os = "linux")]
+    libc::dup3(fd, target, libc::O_CLOEXEC);
+    #[cfg(target_os = "macos")]
+    if libc::dup2(fd, target) >= 0 {
+        set_cloexec(target);
+    }
+}
+
+/// Write to a descriptor from a signal handler, bypassing the write
+/// interposer and its lock
+///
+/// On macOS the library's own calls are never interposed (see the macos
+/// module), so plain write(2) reaches the kernel.
+///
+/// # Safety
+/// `buf` must be valid for reads of `len` bytes
+#[cfg(not(feature = "minimal"))]
+pub(crate) unsafe fn raw_write(fd: c_int, buf: *const c_void, len: usize) {
+    #[cfg(target_os = "linux")]
+    libc::syscall(libc::SYS_write, fd, buf, len);
+    #[cfg(target_os = "macos")]
+    libc::write(fd, buf, len);
+}
+
+/// Accept `signum` if it is pending for the calling thread, without waiting
+///
+/// # Safety
+/// `signum` must be blocked in the calling thread
+pub(crate) unsafe fn discard_pending(signum: c_int) {
+    let mut set: sigset_t = std::mem::zeroed();
+    libc::sigemptyset(&mut set);
+    libc::sigaddset(&mut set, signum);
+    #[cfg(target_os = "linux")]
+    {
+        let timeout = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        libc::sigtimedwait(&set, std::ptr::null_mut(), &timeout);
+    }
+    // No sigtimedwait: sigwait returns at once for a signal already pending
+    #[cfg(target_os = "macos")]
+    {
+        let mut pending: sigset_t = std::mem::zeroed();
+        libc::sigpending(&mut pending);
+        if libc::sigismember(&pending, signum) == 1 {
+            let mut received = 0;
+            libc::sigwait(&set, &mut received);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_random() {
+        let mut bytes = [0u8; 300];
+        assert!(fill_random(&mut bytes));
+        assert!(bytes.iter().any(|&byte| byte != 0));
+    }
+
+    #[test]
+    fn test_explicit_bzero() {
+        let mut bytes = *b"ghp_secret";
+        unsafe { explicit_bzero(bytes.as_mut_ptr().cast(), bytes.len()) };
+        assert_eq!(bytes, [0; 10]);
+    }
+
+    #[test]
+    fn test_socket_cloexec() {
+        let fd = socket_cloexec(libc::AF_UNIX, libc::SOCK_DGRAM);
+        assert!(fd >= 0);
+        unsafe {
+            assert_ne!(libc::fcntl(fd, libc::F_GETFD) & libc::FD_CLOEXEC, 0);
+            libc::close(fd);
+        }
+    }
+}
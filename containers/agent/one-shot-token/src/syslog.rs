@@ -0,0 +1,267 @@
+//! syslog and journald logging backend (AWF_ONE_SHOT_SYSLOG)
+//!
+//! With AWF_ONE_SHOT_SYSLOG set, every log and audit line is also sent to the
+//! system log, so runner-level log collection picks up token events without
+//! any per-workflow plumbing. Lines still go to their usual destination.
+//!
+//! - `journald` sends native journal entries to
+//!   /run/systemd/journal/socket, with the line's fields as `AWF_*` journal
+//!   fields (`AWF_EVENT`, `AWF_TOKEN`, `AWF_VIA`, ...)
+//! - `syslog` sends plain messages through syslog(3) with the `authpriv`
+//!   facility
+//! - `1`/`true`/`auto` uses journald when its socket exists, else syslog
+//!
+//! Priorities follow the line's level: debug lines are `debug`, info lines
+//! `info`, audit events `notice` (`alert` for `severity=high`), warnings
+//! `warning`, errors `err` and fatal errors `crit`.
+
+use crate::log::Level;
+use crate::{context, egress, sys};
+use libc::c_int;
+use std::ffi::CString;
+use std::sync::OnceLock;
+
+/// Path of the journald native protocol socket
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Identifier of the library's messages
+const IDENTIFIER: &std::ffi::CStr = c"one-shot-token";
+
+/// Where lines are sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Backend {
+    Syslog,
+    /// Datagram socket connected to nothing; entries are sent with sendto
+    Journald(c_int),
+}
+
+static BACKEND: OnceLock<Backend> = OnceLock::new();
+
+/// Backend requested in AWF_ONE_SHOT_SYSLOG
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Request {
+    Off,
+    Auto,
+    Journald,
+    Syslog,
+}
+
+/// Parse the value of AWF_ONE_SHOT_SYSLOG; None for unknown values
+fn parse(value: &str) -> Option<Request> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "0" | "false" | "no" | "off" => Some(Request::Off),
+        "1" | "true" | "yes" | "on" | "auto" => Some(Request::Auto),
+        "journald" => Some(Request::Journald),
+        "syslog" => Some(Request::Syslog),
+        _ => None,
+    }
+}
+
+/// Whether `value` is an AWF_ONE_SHOT_SYSLOG value
+pub(crate) fn is_request(value: &str) -> bool {
+    parse(value).is_some()
+}
+
+/// Open a datagram socket for the journal, if journald is running
+fn journal_socket() -> Option<c_int> {
+    if !std::path::Path::new(JOURNAL_SOCKET).exists() {
+        return None;
+    }
+    let fd = sys::socket_cloexec(libc::AF_UNIX, libc::SOCK_DGRAM);
+    (fd >= 0).then_some(fd)
+}
+
+/// Enable the backend selected by the value of AWF_ONE_SHOT_SYSLOG
+pub(crate) fn install(value: &str, debug_enabled: bool) {
+    let backend = match parse(value) {
+        Some(Request::Off) => return,
+        Some(Request::Syslog) => Backend::Syslog,
+        Some(Request::Journald) => match journal_socket() {
+            Some(fd) => Backend::Journald(fd),
+            None => {
+                log_line!(
+                    Warning,
+                    "config",
+                    None,
+                    "journald socket {} not available, not logging to journald",
+                    JOURNAL_SOCKET
+                );
+                return;
+            }
+        },
+        Some(Request::Auto) => journal_socket().map_or(Backend::Syslog, Backend::Journald),
+        // Reported by the configuration check (see the healthcheck module)
+        None => return,
+    };
+    if backend == Backend::Syslog {
+        // SAFETY: IDENTIFIER is a static C string, as openlog requires
+        unsafe { libc::openlog(IDENTIFIER.as_ptr(), libc::LOG_PID, libc::LOG_AUTHPRIV) };
+    }
+    if BACKEND.set(backend).is_ok() && debug_enabled {
+        let name = match backend {
+            Backend::Syslog => "syslog",
+            Backend::Journald(_) => "journald",
+        };
+        log_line!(Debug, "init", None, "Also sending log lines to {}", name);
+    }
+}
+
+/// syslog priority of a line
+fn priority(level: Level, fields: &[(String, String)]) -> c_int {
+    match level {
+        Level::Debug => libc::LOG_DEBUG,
+        Level::Info => libc::LOG_INFO,
+        Level::Audit
+            if fields
+                .iter()
+                .any(|(key, value)| key == "severity" && value == "high") =>
+        {
+            libc::LOG_ALERT
+        }
+        Level::Audit => libc::LOG_NOTICE,
+        Level::Warning => libc::LOG_WARNING,
+        Level::Error => libc::LOG_ERR,
+        Level::Fatal => libc::LOG_CRIT,
+    }
+}
+
+/// Append a field in the journal native protocol
+fn push_field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    entry.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// Journal field name for a line field: `AWF_` and the key in upper case,
+/// with characters journald does not accept replaced by `_`
+fn journal_key(key: &str) -> String {
+    let key: String = key
+        .chars()
+        .map(|c| match c {
+            'a'..='z' => c.to_ascii_uppercase(),
+            'A'..='Z' | '0'..='9' => c,
+            _ => '_',
+        })
+        .collect();
+    format!("AWF_{}", key)
+}
+
+/// Build a journal entry
+fn journal_entry(
+    level: Level,
+    event: &str,
+    token: Option<&str>,
+    fields: &[(String, String)],
+    message: &str,
+) -> Vec<u8> {
+    let mut entry = Vec::new();
+    push_field(&mut entry, "MESSAGE", message);
+    push_field(&mut entry, "PRIORITY", &priority(level, fields).to_string());
+    push_field(
+        &mut entry,
+        "SYSLOG_FACILITY",
+        &(libc::LOG_AUTHPRIV >> 3).to_string(),
+    );
+    push_field(
+        &mut entry,
+        "SYSLOG_IDENTIFIER",
+        IDENTIFIER.to_str().unwrap(),
+    );
+    push_field(&mut entry, "AWF_LEVEL", level.as_str());
+    push_field(&mut entry, "AWF_EVENT", event);
+    if let Some(token) = token {
+        push_field(&mut entry, "AWF_TOKEN", token);
+    }
+    let fields = fields.iter().chain(context::fields());
+    for (key, value) in fields.filter(|(key, _)| key != "message") {
+        push_field(&mut entry, &journal_key(key), value);
+    }
+    entry
+}
+
+/// Send a line to the configured backend, if any
+///
+/// `message` is the line as written in the configured log format.
+pub(crate) fn send(
+    level: Level,
+    event: &str,
+    token: Option<&str>,
+    fields: &[(String, String)],
+    message: &str,
+) {
+    match BACKEND.get() {
+        None => {}
+        Some(Backend::Syslog) => {
+            let Ok(message) = CString::new(message.replace('\0', "")) else {
+                return;
+            };
+            // SAFETY: the format string takes exactly one C string argument
+            unsafe { libc::syslog(priority(level, fields), c"%s".as_ptr(), message.as_ptr()) };
+        }
+        Some(&Backend::Journald(fd)) => {
+            let entry = journal_entry(level, event, token, fields, message);
+            // SAFETY: sockaddr_un is a plain struct; JOURNAL_SOCKET fits in sun_path
+            unsafe {
+                let mut addr: libc::sockaddr_un = std::mem::zeroed();
+                addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+                for (dest, byte) in addr.sun_path.iter_mut().zip(JOURNAL_SOCKET.bytes()) {
+                    *dest = byte as libc::c_char;
+                }
+                egress::call_real_sendto(
+                    fd,
+                    entry.as_ptr().cast(),
+                    entry.len(),
+                    libc::MSG_NOSIGNAL,
+                    &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("1"), Some(Request::Auto));
+        assert_eq!(parse(" Journald "), Some(Request::Journald));
+        assert_eq!(parse("syslog"), Some(Request::Syslog));
+        assert_eq!(parse("0"), Some(Request::Off));
+        assert_eq!(parse("kafka"), None);
+    }
+
+    #[test]
+    fn test_journal_entry() {
+        context::init(None);
+        let fields = [
+            ("severity".to_string(), "high".to_string()),
+            ("via-x".to_string(), "a\nb".to_string()),
+        ];
+        let entry = journal_entry(
+            Level::Audit,
+            "canary_read",
+            Some("GITHUB_TOKEN"),
+            &fields,
+            "AUDIT event=canary_read",
+        );
+        let mut expected = b"MESSAGE=AUDIT event=canary_read\nPRIORITY=1\n".to_vec();
+        expected.extend_from_slice(b"SYSLOG_FACILITY=10\nSYSLOG_IDENTIFIER=one-shot-token\n");
+        expected.extend_from_slice(b"AWF_LEVEL=audit\nAWF_EVENT=canary_read\n");
+        expected.extend_from_slice(b"AWF_TOKEN=GITHUB_TOKEN\nAWF_SEVERITY=high\nAWF_VIA_X\n");
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        for (key, value) in context::fields() {
+            push_field(&mut expected, &journal_key(key), value);
+        }
+        assert_eq!(entry, expected);
+    }
+}
@@ -0,0 +1,155 @@
+//! Policy decision tracing (AWF_ONE_SHOT_TRACE)
+//!
+//! Working out why a token was or was not protected otherwise means reading
+//! the configuration against the source. With AWF_ONE_SHOT_TRACE=1 every
+//! getenv of a configured name logs a `trace` line with the rule that
+//! matched, the token's policy and what the caller got:
+//!
+//! ```text
+//! Trace GH_TOKEN: rule=alias:GH_TOKEN=GITHUB_TOKEN policy=strict,max_reads=2 verdict=served first=true
+//! ```
+//!
+//! Lookups of names that are not configured are only traced if they look
+//! like they were meant to be: a protected name in different case, or a name
+//! that reads like a credential (`..._TOKEN`, `..._API_KEY`, ...). These get
+//! `rule=none` and the reason. Tracing takes the state lock for every
+//! getenv of a near-miss or configured name, so it is meant for debugging.
+
+use crate::envname::EnvName;
+use crate::policy::{format_expires_at, TokenSpec};
+use crate::read_config_flag;
+use once_cell::sync::Lazy;
+
+/// Name parts that mark a variable as a likely credential
+const CREDENTIAL_WORDS: [&str; 7] = [
+    "TOKEN",
+    "API_KEY",
+    "APIKEY",
+    "SECRET",
+    "PASSWORD",
+    "ACCESS_KEY",
+    "PAT",
+];
+
+/// Whether tracing is on, read once outside the state lock
+static ENABLED: Lazy<bool> = Lazy::new(|| read_config_flag(c"AWF_ONE_SHOT_TRACE", false));
+
+/// Whether getenv decisions are traced
+pub(crate) fn enabled() -> bool {
+    *ENABLED
+}
+
+/// Log the decision for a getenv of `name`
+///
+/// `rule` is the configuration entry that matched, `spec` the policy of the
+/// token it resolved to, if any, and `verdict` what the caller got.
+pub(crate) fn decision(name: &[u8], rule: &str, spec: Option<&TokenSpec>, verdict: &str) {
+    let name = String::from_utf8_lossy(name);
+    let policy = spec.map_or_else(|| "-".to_string(), describe);
+    log_line!(
+        Info,
+        "trace",
+        Some(&name),
+        "Trace {}: rule={} policy={} verdict={}",
+        name,
+        rule,
+        policy,
+        verdict
+    );
+}
+
+/// Log a getenv of a name that is not configured, if it looks like a near
+/// miss of the protected names
+pub(crate) fn unprotected<'a>(name: &[u8], protected: impl IntoIterator<Item = &'a EnvName>) {
+    if let Some(reason) = near_miss(name, protected) {
+        decision(
+            name,
+            &format!("none reason={}", reason),
+            None,
+            "pass_through",
+        );
+    }
+}
+
+/// Why `name`, which is not configured, may have been meant to be protected
+fn near_miss<'a>(name: &[u8], protected: impl IntoIterator<Item = &'a EnvName>) -> Option<String> {
+    if let Some(protected) = protected
+        .into_iter()
+        .find(|protected| protected.as_bytes().eq_ignore_ascii_case(name))
+    {
+        return Some(format!("case_differs_from:{}", protected));
+    }
+    let upper = String::from_utf8_lossy(name).to_ascii_uppercase();
+    let credential = upper
+        .split('_')
+        .any(|word| CREDENTIAL_WORDS.contains(&word))
+        || CREDENTIAL_WORDS
+            .iter()
+            .any(|word| word.contains('_') && upper.contains(word));
+    credential.then(|| "looks_like_credential".to_string())
+}
+
+/// A token's policy and options, comma-separated
+fn describe(spec: &TokenSpec) -> String {
+    let mut parts = vec![spec.policy.as_str().to_string()];
+    if let Some(max_reads) = spec.max_reads {
+        parts.push(format!("max_reads={}", max_reads));
+    }
+    if let Some(ttl) = spec.ttl_secs {
+        parts.push(format!("ttl={}", ttl));
+    }
+    if let Some(expires_at) = spec.expires_at {
+        parts.push(format!("expires_at={}", format_expires_at(expires_at)));
+    }
+    if let Some(refresh) = spec.refresh_secs {
+        parts.push(format!("refresh={}", refresh));
+    }
+    parts.extend(spec.allowed_exes.iter().map(|exe| format!("exe={}", exe)));
+    parts.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::parse_token_spec;
+
+    #[test]
+    fn test_near_miss() {
+        let protected = [
+            EnvName::from("GITHUB_TOKEN"),
+            EnvName::from("OPENAI_API_KEY"),
+        ];
+        assert_eq!(
+            near_miss(b"github_token", &protected).as_deref(),
+            Some("case_differs_from:GITHUB_TOKEN")
+        );
+        for name in [
+            "NPM_TOKEN",
+            "MY_API_KEY",
+            "db_password",
+            "GH_PAT",
+            "AWS_SECRET_ACCESS_KEY",
+        ] {
+            assert_eq!(
+                near_miss(name.as_bytes(), &protected).as_deref(),
+                Some("looks_like_credential"),
+                "{}",
+                name
+            );
+        }
+        for name in ["HOME", "PATH", "TOKENIZERS_PARALLELISM", "PATCH_LEVEL"] {
+            assert_eq!(near_miss(name.as_bytes(), &protected), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_describe() {
+        let (spec, _) = parse_token_spec("GITHUB_TOKEN").unwrap();
+        assert_eq!(describe(&spec), "cache");
+        let (spec, _) =
+            parse_token_spec("GITHUB_TOKEN:strict:max_reads=2:exe=/usr/bin/gh").unwrap();
+        assert_eq!(describe(&spec), "strict,max_reads=2,exe=/usr/bin/gh");
+        let (spec, _) = parse_token_spec("GITHUB_TOKEN:expires_at=2026-10-17T12:00:00Z").unwrap();
+        assert_eq!(describe(&spec), "cache,expires_at=2026-10-17T12:00:00Z");
+    }
+}
@@ -0,0 +1,254 @@
+//! HashiCorp Vault references (`vault:` values, AWF_ONE_SHOT_VAULT_SOCKET)
+//!
+//! Organizations that keep their CI secrets in Vault usually run a Vault
+//! agent next to the workload: it authenticates on its own (auto-auth) and
+//! serves the Vault API on a local listener. With AWF_ONE_SHOT_VAULT_SOCKET
+//! naming the agent's Unix socket listener, a protected token whose value is
+//! `vault:` followed by a secret path and a field, e.g.
+//! `GITHUB_TOKEN=vault:secret/data/ci#token`, holds only the reference. When
+//! the token is cached (on its first read, or at load with AWF_ONE_SHOT_EAGER)
+//! the library asks the agent for `GET /v1/secret/data/ci` and caches the
+//! field `token` of the secret: `data.data.token` as KV version 2 answers,
+//! or `data.token` from other secret engines. The value never appears in the
+//! environment; the variable is scrubbed like any other token.
+//!
+//! The library sends no Vault token: the agent must add its own
+//! (`use_auto_auth_token` in its `api_proxy` stanza). As with the token
+//! broker, the other end of the socket must run as AWF_ONE_SHOT_VAULT_UID
+//! (default 0). An agent that does not answer within five seconds, an answer
+//! other than 200 and a secret without the field leave the token unset. Each
+//! lookup is reported with a `vault` audit event naming the path and field,
+//! never the value.
+
+use crate::broker::{self, Broker};
+use crate::json::{self, Value};
+use crate::{audit, sys};
+use std::ffi::CString;
+use std::io;
+
+/// Prefix of token values that are Vault references
+const PREFIX: &[u8] = b"vault:";
+
+/// A secret path and the field of the secret to serve
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Reference<'a> {
+    path: &'a str,
+    field: &'a str,
+}
+
+/// The reference a token value holds, if it is a Vault reference
+///
+/// Paths are made of letters, digits and `/_-.` (without `..`), fields of
+/// letters, digits and `_-.`, so neither can change the request.
+pub(crate) fn reference(value: &[u8]) -> Option<Reference<'_>> {
+    let rest = std::str::from_utf8(value.strip_prefix(PREFIX)?).ok()?;
+    let (path, field) = rest.split_once('#')?;
+    let path = path.trim_start_matches('/');
+    let path_ok = path
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'/' | b'_' | b'-' | b'.'));
+    let field_ok = field
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'.'));
+    (!path.is_empty() && !field.is_empty() && path_ok && field_ok && !path.contains(".."))
+        .then_some(Reference { path, field })
+}
+
+/// The field's value in a Vault HTTP response, or why there is none
+fn parse_response(response: &[u8], field: &str) -> Result<CString, String> {
+    let split = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or("malformed_response")?;
+    let (head, body) = (&response[..split], &response[split + 4..]);
+    let status = head
+        .split(|&b| b == b' ')
+        .nth(1)
+        .and_then(|status| std::str::from_utf8(status).ok())
+        .ok_or("malformed_response")?;
+    let document = json::parse(body).map_err(|_| "malformed_response".to_string())?;
+    if status != "200" {
+        let error = document
+            .get("errors")
+            .and_then(|errors| match errors {
+                Value::Array(errors) => errors.first().and_then(Value::as_str),
+                _ => None,
+            })
+            .map(|error| format!(" {}", error))
+            .unwrap_or_default();
+        zero_value(document);
+        return Err(format!("status={}{}", status, error));
+    }
+    let data = document.get("data");
+    let found = data
+        .and_then(|data| data.get("data"))
+        .and_then(|data| data.get(field))
+        .or_else(|| data.and_then(|data| data.get(field)))
+        .and_then(Value::as_str)
+        .map(|value| CString::new(value).map_err(|_| "malformed_value".to_string()));
+    zero_value(document);
+    found.unwrap_or_else(|| Err("no_such_field".to_string()))
+}
+
+/// Ask the agent behind `socket` for the secret at `path`
+///
+/// # Safety
+/// Only performs system calls on a socket it creates
+unsafe fn get(socket: &Broker, path: &str) -> io::Result<Vec<u8>> {
+    let fd = socket.connect(broker::TIMEOUT_SECS)?;
+    let request = format!(
+        "GET /v1/{} HTTP/1.0\r\nHost: localhost\r\nX-Vault-Request: true\r\n\r\n",
+        path
+    );
+    let mut response = Vec::new();
+    let result = broker::send(fd, request.as_bytes())
+        .and_then(|()| broker::receive(fd, &mut response, |_| false));
+    libc::close(fd);
+    result.map(|()| response)
+}
+
+/// Resolve the Vault reference of `token`
+///
+/// Emits a `vault` audit event either way. Returns None if the agent could
+/// not be reached or the secret has no such field.
+///
+/// # Safety
+/// Only performs system calls on a socket it creates
+pub(crate) unsafe fn fetch(socket: &Broker, token: &str, reference: Reference) -> Option<CString> {
+    let shown = format!(
+        "path={} field={} socket={}",
+        reference.path,
+        reference.field,
+        audit::detail_word(&socket.socket())
+    );
+    let result = match get(socket, reference.path) {
+        Ok(mut response) => {
+            let value = parse_response(&response, reference.field);
+            zero(&mut response);
+            value.map_err(|reason| format!("action=refused {} reason={}", shown, reason))
+        }
+        Err(err) => Err(format!(
+            "action=unreachable {} error={}",
+            shown,
+            audit::detail_word(&err.to_string())
+        )),
+    };
+    match result {
+        Ok(value) => {
+            let detail = format!("action=fetched {} bytes={}", shown, value.as_bytes().len());
+            audit::emit("vault", token, &detail);
+            Some(value)
+        }
+        Err(detail) => {
+            audit::emit("vault", token, &detail);
+            None
+        }
+    }
+}
+
+/// Zero the strings of a parsed response, which hold the secret's fields
+fn zero_value(value: Value) {
+    match value {
+        Value::String(value) => zero(&mut value.into_bytes()),
+        Value::Array(values) => values.into_iter().for_each(zero_value),
+        Value::Object(members) => {
+            for (name, value) in members {
+                zero(&mut name.into_bytes());
+                zero_value(value);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn zero(bytes: &mut [u8]) {
+    // SAFETY: bytes is a writable buffer of the given length
+    unsafe { sys::explicit_bzero(bytes.as_mut_ptr().cast(), bytes.len()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_reference() {
+        assert_eq!(
+            reference(b"vault:secret/data/ci#token"),
+            Some(Reference {
+                path: "secret/data/ci",
+                field: "token"
+            })
+        );
+        assert_eq!(reference(b"vault:/kv/ci#api-key").unwrap().path, "kv/ci");
+        assert!(reference(b"vault:secret/data/ci").is_none());
+        assert!(reference(b"vault:secret/data/ci#").is_none());
+        assert!(reference(b"vault:secret/../sys/raw#token").is_none());
+        assert!(reference(b"vault:secret/ci HTTP/1.1#token").is_none());
+        assert!(reference(b"ghp_value").is_none());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let kv2 = b"HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n\
+                    {\"data\":{\"data\":{\"token\":\"ghp_vault\"},\"metadata\":{}}}";
+        assert_eq!(
+            parse_response(kv2, "token").unwrap().as_bytes(),
+            b"ghp_vault"
+        );
+        let kv1 = b"HTTP/1.0 200 OK\r\n\r\n{\"data\":{\"token\":\"ghp_kv1\"}}";
+        assert_eq!(parse_response(kv1, "token").unwrap().as_bytes(), b"ghp_kv1");
+        assert_eq!(parse_response(kv1, "other").unwrap_err(), "no_such_field");
+        let denied = b"HTTP/1.0 403 Forbidden\r\n\r\n{\"errors\":[\"permission denied\"]}";
+        assert_eq!(
+            parse_response(denied, "token").unwrap_err(),
+            "status=403 permission denied"
+        );
+        assert_eq!(
+            parse_response(b"HTTP/1.0 200 OK\r\n", "token").unwrap_err(),
+            "malformed_response"
+        );
+    }
+
+    #[test]
+    fn test_fetch() {
+        let path = std::env::temp_dir().join(format!("awf-vault-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(
+                    b"HTTP/1.0 200 OK\r\n\r\n{\"data\":{\"data\":{\"token\":\"ghp_vault\"}}}",
+                )
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let uid = unsafe { libc::geteuid() };
+        let socket = Broker::parse(path.to_str().unwrap(), Some(&uid.to_string())).unwrap();
+        let reference = reference(b"vault:secret/data/ci#token").unwrap();
+        let value = unsafe { fetch(&socket, "GITHUB_TOKEN", reference) }.unwrap();
+        assert_eq!(value.as_bytes(), b"ghp_vault");
+        let request = server.join().unwrap();
+        assert!(
+            request.starts_with("GET /v1/secret/data/ci HTTP/1.0\r\n"),
+            "{}",
+            request
+        );
+        assert!(request.contains("X-Vault-Request: true\r\n"));
+
+        // An agent run by another user is not asked
+        let other = Broker::parse(path.to_str().unwrap(), Some(&(uid + 1).to_string())).unwrap();
+        let reference = super::reference(b"vault:secret/data/ci#token").unwrap();
+        assert!(unsafe { fetch(&other, "GITHUB_TOKEN", reference) }.is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+//! Self-verification report at load (AWF_ONE_SHOT_VERIFY)
+//!
+//! The ready line (see the ready module) shows that the library was loaded,
+//! not that it works. With AWF_ONE_SHOT_VERIFY set, a constructor checks the
+//! library and reports the result as one JSON object: the library version,
+//! the configured tokens, whether each is present and where its rule came
+//! from, the source of every setting that is set (see the merge module),
+//! whether every interposer found the libc function it wraps and is the definition that
+//! lookups of its name resolve to, and which hardening features are asked
+//! for and in effect:
+//!
+//! ```text
+//! {"version":"0.1.0","pid":42,"mode":"enforce","disabled":false,"ok":true,
+//!  "tokens":[{"name":"GITHUB_TOKEN","policy":"cache","present":true,
+//!             "source":"env","setting":"AWF_ONE_SHOT_TOKENS"},...],
+//!  "settings":[{"name":"AWF_ONE_SHOT_TOKENS","source":"env"},...],
+//!  "interposition":[{"name":"getenv","resolved":true,"interposed":true},...],
+//!  "hardening":[{"name":"nocore","requested":true,"active":true},...]}
+//! ```
+//!
+//! `ok` is false if protection is disabled, an interposer is not in place or
+//! a requested hardening feature is not in effect, so the host can refuse to
+//! start the workflow. "1" writes the report to the log as a `verify` line;
+//! a path appends it to that file instead. The variable is unset afterwards,
+//! so only the process the host started reports.
+//!
+//! Hardening is applied before the check (the harden and landlock modules
+//! apply it once, whichever constructor runs first). Landlock cannot be
+//! queried, so it counts as active only if this library installed it; the
+//! other features are read back from the kernel. dyld interposition on macOS
+//! rebinds the references of other images and cannot be observed from the
+//! library, so there only the resolution of the real functions is checked.
+
+use crate::exec::PROPAGATED_CONFIG;
+use crate::log::json_string;
+use crate::setenv::call_real_unsetenv;
+use crate::{environ, lock_state, protection_disabled, read_config_var, resolve_setting};
+#[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+use crate::{harden, landlock, Mode};
+use libc::c_void;
+use std::ffi::{CStr, CString};
+use std::io::Write;
+
+/// Where the report goes
+#[derive(Debug, PartialEq, Eq)]
+enum Output {
+    Log,
+    File(String),
+}
+
+/// Parse AWF_ONE_SHOT_VERIFY; None for off or an invalid value
+fn parse_output(value: &str) -> Option<Output> {
+    let value = value.trim();
+    if value.starts_with('/') {
+        return Some(Output::File(value.to_string()));
+    }
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(Output::Log),
+        _ => None,
+    }
+}
+
+/// The interposers checked: the libc name and this library's definition
+fn interposers() -> [(&'static CStr, *const c_void); 8] {
+    [
+        (c"getenv", crate::getenv as *const c_void),
+        (c"setenv", crate::setenv::setenv as *const c_void),
+        (c"unsetenv", crate::setenv::unsetenv as *const c_void),
+        (c"putenv", crate::setenv::putenv as *const c_void),
+        (c"execve", crate::exec::execve as *const c_void),
+        (c"posix_spawn", crate::exec::posix_spawn as *const c_void),
+        (c"open", crate::open::open as *const c_void),
+        (c"fopen", crate::open::fopen as *const c_void),
+    ]
+}
+
+/// JSON object for an interposer, and whether it is in place
+fn check_interposer(name: &CStr, ours: *const c_void) -> (String, bool) {
+    // SAFETY: dlsym with the pseudo-handles and a valid C string
+    let (next, default) = unsafe {
+        (
+            libc::dlsym(libc::RTLD_NEXT, name.as_ptr()),
+            libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()),
+        )
+    };
+    let resolved = !next.is_null();
+    let interposed = if cfg!(target_os = "macos") {
+        resolved
+    } else {
+        std::ptr::eq(default, ours)
+    };
+    let json = format!(
+        "{{\"name\":{},\"resolved\":{},\"interposed\":{}}}",
+        json_string(&name.to_string_lossy()),
+        resolved,
+        interposed
+    );
+    (json, resolved && interposed)
+}
+
+/// Whether /proc/self/status shows a seccomp filter
+#[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+fn seccomp_filtered(status: &str) -> bool {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Seccomp:"))
+        .is_some_and(|mode| mode.trim() == "2")
+}
+
+/// The hardening features as (name, requested, active)
+///
+/// Must be called without the state lock: reading /proc/self/status goes
+/// through the open interposers.
+#[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+fn hardening(requested: [bool; 4]) -> Vec<(&'static str, bool, bool)> {
+    // SAFETY: PR_GET_DUMPABLE takes no arguments
+    let nodump = unsafe { libc::prctl(libc::PR_GET_DUMPABLE) } == 0;
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: limit is a valid rlimit to fill in
+    let nocore =
+        unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) } == 0 && limit.rlim_cur == 0;
+    let seccomp =
+        std::fs::read_to_string("/proc/self/status").is_ok_and(|status| seccomp_filtered(&status));
+    let active = [nodump, nocore, seccomp, landlock::installed()];
+    ["nodump", "nocore", "seccomp", "landlock"]
+        .into_iter()
+        .zip(requested)
+        .zip(active)
+        .map(|((name, requested), active)| (name, requested, active))
+        .collect()
+}
+
+/// Minimal builds and other platforms have no hardening
+#[cfg(not(all(not(feature = "minimal"), target_os = "linux")))]
+fn hardening(_requested: [bool; 4]) -> Vec<(&'static str, bool, bool)> {
+    Vec::new()
+}
+
+/// Check the library and build the report
+fn report() -> String {
+    let state = lock_state();
+    let mode = state.mode.as_str();
+    let tokens: Vec<String> = state
+        .tokens
+        .iter()
+        .map(|spec| {
+            let cached = state
+                .cache
+                .get(&spec.name)
+                .is_some_and(|entry| !entry.value.is_null());
+            // SAFETY: environ is only read; the state lock is held
+            let present = cached || unsafe { environ::find_entry(&spec.name) }.is_some();
+            format!(
+                "{{\"name\":{},\"policy\":{},\"present\":{},\"source\":{},\"setting\":{}}}",
+                json_string(&spec.name.lossy()),
+                json_string(spec.policy.as_str()),
+                present,
+                json_string(spec.origin.source.as_str()),
+                json_string(&spec.origin.setting.to_string_lossy())
+            )
+        })
+        .collect();
+    let settings: Vec<String> = PROPAGATED_CONFIG
+        .iter()
+        .filter_map(|name| {
+            let (source, _) = resolve_setting(&state, &CString::new(*name).ok()?)?;
+            Some(format!(
+                "{{\"name\":{},\"source\":{}}}",
+                json_string(name),
+                json_string(source.as_str())
+            ))
+        })
+        .collect();
+    #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+    let requested = [state.nodump, state.nocore, state.seccomp, state.landlock]
+        .map(|on| on && state.mode == Mode::Enforce);
+    #[cfg(not(all(not(feature = "minimal"), target_os = "linux")))]
+    let requested = [false; 4];
+    drop(state);
+
+    let disabled = protection_disabled();
+    let mut ok = !disabled;
+    let mut interposition = Vec::new();
+    for (name, ours) in interposers() {
+        let (json, in_place) = check_interposer(name, ours);
+        interposition.push(json);
+        ok &= in_place;
+    }
+    let hardening: Vec<String> = hardening(requested)
+        .into_iter()
+        .map(|(name, requested, active)| {
+            ok &= active || !requested;
+            format!(
+                "{{\"name\":{},\"requested\":{},\"active\":{}}}",
+                json_string(name),
+                requested,
+                active
+            )
+        })
+        .collect();
+
+    format!(
+        concat!(
+            "{{\"version\":{},\"pid\":{},\"mode\":{},\"disabled\":{},\"ok\":{},",
+            "\"tokens\":[{}],\"settings\":[{}],\"interposition\":[{}],\"hardening\":[{}]}}"
+        ),
+        json_string(env!("CARGO_PKG_VERSION")),
+        std::process::id(),
+        json_string(if disabled { "disabled" } else { mode }),
+        disabled,
+        ok,
+        tokens.join(","),
+        settings.join(","),
+        interposition.join(","),
+        hardening.join(",")
+    )
+}
+
+/// Whether `value` is an AWF_ONE_SHOT_VERIFY value, including the values
+/// that turn it off
+pub(crate) fn is_output(value: &str) -> bool {
+    parse_output(value).is_some()
+        || matches!(
+            value.trim().to_ascii_lowercase().as_str(),
+            "" | "0" | "false" | "no" | "off"
+        )
+}
+
+/// Append the report to `path`
+fn append(path: &str, report: &str) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(format!("{}\n", report).as_bytes())
+}
+
+/// Write the report when AWF_ONE_SHOT_VERIFY is set
+extern "C" fn verify_at_load() {
+    let Some(value) = read_config_var(c"AWF_ONE_SHOT_VERIFY") else {
+        return;
+    };
+    // SAFETY: valid C string; children do not report again
+    unsafe { call_real_unsetenv(c"AWF_ONE_SHOT_VERIFY".as_ptr()) };
+    let Some(output) = parse_output(&value) else {
+        return;
+    };
+    #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+    {
+        harden::apply_at_load();
+        landlock::install_at_load();
+    }
+
+    let report = report();
+    match output {
+        Output::Log => log_line!(Info, "verify", None, "Verify {}", report),
+        Output::File(path) => {
+            if let Err(err) = append(&path, &report) {
+                log_line!(
+                    Warning,
+                    "verify",
+                    None,
+                    "Could not write verification report to {}: {}",
+                    path,
+                    err
+                );
+            }
+        }
+    }
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static VERIFY_AT_LOAD: extern "C" fn() = verify_at_load;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        assert_eq!(parse_output("1"), Some(Output::Log));
+        assert_eq!(parse_output("True"), Some(Output::Log));
+        assert_eq!(
+            parse_output(" /tmp/verify.json\n"),
+            Some(Output::File("/tmp/verify.json".to_string()))
+        );
+        assert_eq!(parse_output("0"), None);
+        assert_eq!(parse_output("verify.json"), None);
+    }
+
+    #[cfg(all(not(feature = "minimal"), target_os = "linux"))]
+    #[test]
+    fn test_seccomp_filtered() {
+        assert!(seccomp_filtered(
+            "Name:\tcat\nSeccomp:\t2\nSeccomp_filters:\t1\n"
+        ));
+        assert!(!seccomp_filtered("Name:\tcat\nSeccomp:\t0\n"));
+        assert!(!seccomp_filtered("Name:\tcat\n"));
+    }
+}
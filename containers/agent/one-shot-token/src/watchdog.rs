@@ -0,0 +1,121 @@
+//! Background watchdog that re-scrubs reappearing tokens
+//!
+//! Some programs save a copy of their environment and restore it later (for
+//! example around a child process launch), which puts already-scrubbed tokens
+//! back into environ. When AWF_ONE_SHOT_WATCHDOG is set to an interval in
+//! seconds, a background thread periodically checks every cached token and its
+//! aliases, scrubs any that reappeared, and emits a `reappeared` audit event.
+//!
+//! Tokens that have never been read are left alone: until the first read their
+//! presence in environ is expected.
+
+use crate::envname::EnvName;
+use crate::{audit, exposed_in_environ, lock_state, scrub_token_group, token_group, Mode};
+use std::time::Duration;
+
+/// Start the watchdog thread at load if AWF_ONE_SHOT_WATCHDOG is configured
+extern "C" fn start_watchdog_at_load() {
+    start();
+}
+
+#[used]
+#[cfg_attr(
+    all(feature = "preload", not(target_os = "macos")),
+    link_section = ".init_array"
+)]
+#[cfg_attr(
+    all(feature = "preload", target_os = "macos"),
+    link_section = "__DATA,__mod_init_func"
+)]
+static START_WATCHDOG_AT_LOAD: extern "C" fn() = start_watchdog_at_load;
+
+/// Start the watchdog thread if AWF_ONE_SHOT_WATCHDOG is configured
+///
+/// Called at load, and again in forked children, which do not inherit the
+/// parent's threads.
+pub(crate) fn start() {
+    let interval = {
+        let state = lock_state();
+        if state.mode != Mode::Enforce {
+            return;
+        }
+        match state.watchdog_interval {
+            Some(interval) => interval,
+            None => return,
+        }
+    };
+
+    let spawned = std::thread::Builder::new()
+        .name("awf-watchdog".to_string())
+        .spawn(move || loop {
+            std::thread::sleep(interval);
+            rescrub_reappeared_tokens();
+        });
+
+    if let Err(err) = spawned {
+        log_line!(
+            Warning,
+            "watchdog",
+            None,
+            "Could not start watchdog thread: {}",
+            err
+        );
+    }
+}
+
+/// Parse an AWF_ONE_SHOT_WATCHDOG interval in whole seconds (0 disables)
+pub(crate) fn parse_interval(value: &str) -> Option<Duration> {
+    match value.trim().parse::<u64>() {
+        Ok(0) | Err(_) => None,
+        Ok(secs) => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// Scrub every cached token (or alias) that has reappeared in environ
+///
+/// Returns the names that were scrubbed again.
+pub(crate) fn rescrub_reappeared_tokens() -> Vec<EnvName> {
+    let state = lock_state();
+    let mut reappeared = Vec::new();
+
+    for canonical in state.cache.keys() {
+        for member in token_group(&state, canonical) {
+            // SAFETY: environ is read with the state lock held, like every
+            // other environ access in this library
+            if unsafe { exposed_in_environ(&member, state.scrub_mode) } {
+                reappeared.push(member);
+            }
+        }
+    }
+
+    for member in &reappeared {
+        let Some(member_cstr) = member.to_cstring() else {
+            continue;
+        };
+        // SAFETY: member_cstr is the C string form of member
+        unsafe {
+            scrub_token_group(
+                std::slice::from_ref(member),
+                std::slice::from_ref(&member_cstr),
+                state.scrub_mode,
+                state.debug_enabled,
+            )
+        };
+        audit::emit("reappeared", member, "action=rescrubbed");
+    }
+
+    reappeared
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("5"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_interval(" 30 "), Some(Duration::from_secs(30)));
+        assert_eq!(parse_interval("0"), None);
+        assert_eq!(parse_interval("fast"), None);
+    }
+}
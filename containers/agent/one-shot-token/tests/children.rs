@@ -0,0 +1,97 @@
+//! Children started with fork, vfork and posix_spawn are reported
+//!
+//! A small C program starts one child each way, and the audit events are
+//! checked for the children's PIDs; the vfork child reports itself when it
+//! execs. Needs a C compiler (`cc`); the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#include <spawn.h>
+#include <stdio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+extern char **environ;
+
+static volatile int borrowed;
+
+int main(void) {
+    char *argv[] = {"/bin/true", NULL};
+    pid_t pid = fork();
+    if (pid == 0) {
+        _exit(0);
+    }
+    waitpid(pid, NULL, 0);
+    printf("fork %d\n", pid);
+
+    /* A real vfork child shares the parent's memory until it execs */
+    pid = vfork();
+    if (pid == 0) {
+        borrowed = 1;
+        execve("/bin/true", argv, environ);
+        _exit(127);
+    }
+    waitpid(pid, NULL, 0);
+    if (!borrowed) {
+        return 1;
+    }
+    printf("vfork %d\n", pid);
+
+    if (posix_spawn(&pid, "/bin/true", NULL, NULL, argv, environ) != 0) {
+        return 1;
+    }
+    waitpid(pid, NULL, 0);
+    printf("posix_spawn %d\n", pid);
+    return 0;
+}
+"#;
+
+#[test]
+fn test_children_reported() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let Some(probe) = compile_probe(&scratch_dir("children"), "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let run = |track: &str| {
+        let output = Command::new(&probe)
+            .env_clear()
+            .env("LD_PRELOAD", &library)
+            .env("AWF_ONE_SHOT_TRACK_CHILDREN", track)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        (
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    };
+
+    let (stdout, stderr) = run("1");
+    for line in stdout.lines() {
+        let (via, pid) = line.split_once(' ').unwrap();
+        let event = format!("event=child_started token=* pid={} parent=", pid);
+        let via = format!(" via={} ", via);
+        assert!(
+            stderr
+                .lines()
+                .any(|audit| audit.contains(&event) && audit.contains(&via)),
+            "no event for {}: {}",
+            line,
+            stderr
+        );
+    }
+    assert_eq!(stdout.lines().count(), 3);
+
+    let (_, stderr) = run("0");
+    assert!(!stderr.contains("child_started"), "{}", stderr);
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
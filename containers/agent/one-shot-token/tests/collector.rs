@@ -0,0 +1,217 @@
+//! awf-token-collector turns the events of a parent and its child into one
+//! run report
+//!
+//! A small C program reads a token under the library, then forks a child
+//! that reads it again, both sending their events to the collector's
+//! socket. Given a port, the parent then connects to it, and the test logs
+//! the connection in a proxy access log for the collector to join. Needs a
+//! C compiler (`cc`); the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const PROBE: &str = r#"
+#include <arpa/inet.h>
+#include <netinet/in.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <sys/socket.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+int main(int argc, char **argv) {
+    const char *value = getenv("GITHUB_TOKEN");
+    printf("parent=%s\n", value ? value : "(null)");
+    fflush(stdout);
+    pid_t child = fork();
+    if (child == 0) {
+        value = getenv("GITHUB_TOKEN");
+        printf("child=%s\n", value ? value : "(null)");
+        return 0;
+    }
+    waitpid(child, NULL, 0);
+    if (argc > 1) {
+        struct sockaddr_in addr = {0};
+        addr.sin_family = AF_INET;
+        addr.sin_port = htons(atoi(argv[1]));
+        addr.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+        int fd = socket(AF_INET, SOCK_STREAM, 0);
+        printf("connect=%d\n", connect(fd, (struct sockaddr *)&addr, sizeof(addr)));
+        close(fd);
+    }
+    return 0;
+}
+"#;
+
+/// Start the collector on `socket` and wait for it to bind
+fn start_collector(socket: &Path, report: &Path, args: &[&str]) -> Child {
+    let collector = Command::new(env!("CARGO_BIN_EXE_awf-token-collector"))
+        .arg("--socket")
+        .arg(socket)
+        .arg("--report")
+        .arg(report)
+        .args(args)
+        .env_remove("LD_PRELOAD")
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !socket.exists() {
+        assert!(Instant::now() < deadline, "collector did not start");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    collector
+}
+
+/// Stop the collector and return its report
+fn stop_collector(mut collector: Child, report: &Path) -> String {
+    // SAFETY: the collector is our child and has not been waited for
+    unsafe { libc::kill(collector.id() as libc::pid_t, libc::SIGTERM) };
+    assert!(collector.wait().unwrap().success());
+    std::fs::read_to_string(report).unwrap()
+}
+
+/// Run the probe under the library, sending events to `socket`
+fn run_probe(probe: &Path, socket: &Path, args: &[String], settings: &[(&str, &str)]) -> String {
+    let output = Command::new(probe)
+        .args(args)
+        .env_clear()
+        .env("LD_PRELOAD", library())
+        .env("GITHUB_TOKEN", "ghp_collector")
+        .env("AWF_ONE_SHOT_EVENT_SOCKET", socket)
+        .envs(settings.iter().copied())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(stdout.contains("parent=ghp_collector"), "{}", stdout);
+    assert!(stdout.contains("child=ghp_collector"), "{}", stdout);
+    stdout
+}
+
+/// A scratch directory and the compiled probe, or None without a compiler
+fn setup(name: &str) -> Option<(PathBuf, PathBuf)> {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = std::env::temp_dir().join(format!(
+        "one-shot-token-collector-{}-{}",
+        name,
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    let Some(probe) = compile_probe(&dir, "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return None;
+    };
+    Some((dir, probe))
+}
+
+#[test]
+fn test_collector_report() {
+    let Some((dir, probe)) = setup("report") else {
+        return;
+    };
+    let socket = dir.join("events.sock");
+    let report = dir.join("report.json");
+
+    let collector = start_collector(&socket, &report, &[]);
+    run_probe(&probe, &socket, &[], &[("AWF_STEP_ID", "build")]);
+    let report = stop_collector(collector, &report);
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert!(!socket.exists());
+    assert!(
+        report.contains("\"duplicates\":0,\"invalid\":0"),
+        "{}",
+        report
+    );
+    assert!(
+        report.contains(
+            "\"name\":\"GITHUB_TOKEN\",\"reads\":2,\"processes\":2,\"via\":{\"getenv\":2}"
+        ),
+        "{}",
+        report
+    );
+    assert_eq!(
+        report.matches("\"step\":\"build\"").count(),
+        2,
+        "{}",
+        report
+    );
+    assert!(!report.contains("ghp_collector"), "{}", report);
+    assert!(!report.contains("\"egress\""), "{}", report);
+}
+
+#[test]
+fn test_collector_egress() {
+    let Some((dir, probe)) = setup("egress") else {
+        return;
+    };
+    let socket = dir.join("events.sock");
+    let report = dir.join("report.json");
+    let proxy_log = dir.join("access.log");
+    std::fs::write(
+        &proxy_log,
+        "1.000 127.0.0.1:1 old.example.com:443 -:- 1.1 CONNECT 200 TCP_TUNNEL:HIER_DIRECT old.example.com:443 \"-\"\n",
+    )
+    .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let collector = start_collector(
+        &socket,
+        &report,
+        &["--proxy-log", proxy_log.to_str().unwrap()],
+    );
+    let stdout = run_probe(
+        &probe,
+        &socket,
+        &[port.to_string()],
+        &[("AWF_ONE_SHOT_CORRELATION", "connect")],
+    );
+    assert!(stdout.contains("connect=0"), "{}", stdout);
+    // Log the parent's connection as the proxy would
+    let (_, client) = listener.accept().unwrap();
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64();
+    let mut log = OpenOptions::new().append(true).open(&proxy_log).unwrap();
+    writeln!(
+        log,
+        "{:.3} {} api.github.com:443 140.82.114.22:443 1.1 CONNECT 200 TCP_TUNNEL:HIER_DIRECT api.github.com:443 \"gh\"",
+        time, client
+    )
+    .unwrap();
+    drop(log);
+    let report = stop_collector(collector, &report);
+    let _ = std::fs::remove_dir_all(&dir);
+
+    // The line from before the start is skipped; the parent's read led to
+    // an allowed request, the child's read to none
+    assert!(
+        report.contains("\"egress\":{\"window\":10,\"requests\":1,\"suspicious\":1,"),
+        "{}",
+        report
+    );
+    assert!(
+        report.contains(&format!(
+            "\"client\":\"{}\",\"host\":\"api.github.com:443\",\"status\":200,\"decision\":\"TCP_TUNNEL:HIER_DIRECT\",\"allowed\":true}}],\"suspicious\":false}}",
+            client
+        )),
+        "{}",
+        report
+    );
+    assert!(
+        report.contains("\"requests\":[],\"suspicious\":true}"),
+        "{}",
+        report
+    );
+}
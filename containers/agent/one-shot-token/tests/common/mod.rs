@@ -0,0 +1,39 @@
+//! Helpers shared by the integration tests that preload the library into
+//! small C programs
+
+// Each test crate uses only some of the helpers
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The cdylib, built next to the directory of the test executables
+pub fn library() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    let dir = exe.parent().and_then(Path::parent).unwrap();
+    dir.join("libone_shot_token.so")
+}
+
+/// A scratch directory for the test `name`, distinct per test process
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("one-shot-token-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Compile `source` to `name` in `dir`, passing `args` to `cc` after the
+/// source file, or None if there is no C compiler
+pub fn compile_probe(dir: &Path, name: &str, source: &str, args: &[&str]) -> Option<PathBuf> {
+    let source_path = dir.join(Path::new(name).with_extension("c"));
+    let binary = dir.join(name);
+    std::fs::write(&source_path, source).unwrap();
+    let status = Command::new("cc")
+        .arg("-o")
+        .arg(&binary)
+        .arg(&source_path)
+        .args(args)
+        .status()
+        .ok()?;
+    assert!(status.success(), "{} did not compile", name);
+    Some(binary)
+}
@@ -0,0 +1,81 @@
+//! Token reads and later connections share a correlation ID
+//!
+//! A small C program reads the token, then connects to a listener of its
+//! own, and prints the AWF_CORRELATION_ID it sees; the audit events are
+//! checked for the same ID. Needs a C compiler (`cc`); the test is skipped
+//! without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#include <arpa/inet.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <sys/socket.h>
+
+int main(void) {
+    int listener = socket(AF_INET, SOCK_STREAM, 0);
+    struct sockaddr_in addr = {0};
+    socklen_t len = sizeof addr;
+    addr.sin_family = AF_INET;
+    addr.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+    if (bind(listener, (struct sockaddr *)&addr, sizeof addr) || listen(listener, 1) ||
+        getsockname(listener, (struct sockaddr *)&addr, &len)) {
+        return 1;
+    }
+    getenv("GITHUB_TOKEN");
+    const char *corr = getenv("AWF_CORRELATION_ID");
+    int fd = socket(AF_INET, SOCK_STREAM, 0);
+    if (connect(fd, (struct sockaddr *)&addr, sizeof addr)) {
+        return 1;
+    }
+    printf("%s %d\n", corr ? corr : "-", ntohs(addr.sin_port));
+    return 0;
+}
+"#;
+#[test]
+fn test_correlation() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let Some(probe) = compile_probe(&scratch_dir("correlation"), "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let output = Command::new(&probe)
+        .env_clear()
+        .env("LD_PRELOAD", &library)
+        .env("GITHUB_TOKEN", "ghp_test")
+        .env("AWF_ONE_SHOT_CORRELATION", "env,connect")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let (corr, port) = stdout.trim().split_once(' ').unwrap();
+    assert_eq!(corr.len(), 16, "{}", stdout);
+
+    let read = format!(
+        "event=token_read token=GITHUB_TOKEN corr={} via=getenv",
+        corr
+    );
+    assert!(stderr.contains(&read), "{}", stderr);
+    let egress = format!(
+        "event=egress_correlated token=GITHUB_TOKEN corr={} local=127.0.0.1:",
+        corr
+    );
+    let peer = format!(" peer=127.0.0.1:{} ", port);
+    assert!(
+        stderr
+            .lines()
+            .any(|line| line.contains(&egress) && line.contains(&peer)),
+        "{}",
+        stderr
+    );
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
@@ -0,0 +1,94 @@
+//! A crash zeroizes cached values and protected environment values
+//!
+//! A small C program reads one token, then replaces the library's SIGSEGV
+//! handler with its own, which calls the library's handler and reports what
+//! is left of the token's value and of an unread token's environment entry
+//! before exiting. Needs a C compiler (`cc`); the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command;
+
+const PROBE: &str = r#"
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+extern char **environ;
+
+static struct sigaction library;
+static const char *value;
+static const char *unread;
+
+static void on_segv(int signum) {
+    library.sa_handler(signum);
+    printf("value=%s unread=%s\n", value, unread);
+    fflush(stdout);
+    _exit(0);
+}
+
+int main(int argc, char **argv) {
+    value = getenv("GITHUB_TOKEN");
+    for (char **env = environ; *env; env++) {
+        if (!strncmp(*env, "NPM_TOKEN=", 10)) {
+            unread = *env + 10;
+        }
+    }
+    if (!value || !unread) {
+        return 1;
+    }
+    struct sigaction action;
+    memset(&action, 0, sizeof action);
+    action.sa_handler = on_segv;
+    sigaction(SIGSEGV, &action, &library);
+    if (library.sa_handler == SIG_DFL) {
+        puts("no handler");
+        return 0;
+    }
+    if (argc > 1) {
+        signal(SIGSEGV, library.sa_handler);
+    }
+    raise(SIGSEGV);
+    return 2;
+}
+"#;
+
+#[test]
+fn test_crash_wipe() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let Some(probe) = compile_probe(&scratch_dir("crash"), "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let run = |wipe: &str, args: &[&str]| {
+        Command::new(&probe)
+            .args(args)
+            .env_clear()
+            .env("LD_PRELOAD", &library)
+            .env("GITHUB_TOKEN", "ghp_crashsecret")
+            .env("NPM_TOKEN", "npm_crashsecret")
+            .env("AWF_ONE_SHOT_TOKENS", "GITHUB_TOKEN,NPM_TOKEN")
+            .env("AWF_ONE_SHOT_CRASH_WIPE", wipe)
+            .output()
+            .unwrap()
+    };
+
+    let output = run("1", &[]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "value= unread=\n");
+
+    let output = run("0", &[]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "no handler\n");
+
+    // Left in place, the handler ends the process with the signal
+    let output = run("1", &["reinstall"]);
+    assert_eq!(output.status.signal(), Some(libc::SIGSEGV), "{:?}", output);
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
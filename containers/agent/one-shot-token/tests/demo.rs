@@ -0,0 +1,48 @@
+//! one-shot-token-demo passes with the library and fails without it
+//!
+//! The demo finds the cdylib next to itself in the target directory.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+use std::process::{Command, Output};
+
+fn demo(args: &[&str]) -> Output {
+    demo_with(args, &[])
+}
+
+fn demo_with(args: &[&str], envs: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_one-shot-token-demo"))
+        .args(args)
+        .env_remove("AWF_ONE_SHOT_TOKENS")
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_pass() {
+    let output = demo(&["GITHUB_TOKEN"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("PASS"), "{}", stdout);
+    assert!(stdout.contains("GITHUB_TOKEN  (unset)"), "{}", stdout);
+}
+
+#[test]
+fn test_unprotected_name() {
+    // The fake value looks like a GitHub token, which would protect any name
+    let output = demo_with(&["NOT_A_TOKEN"], &[("AWF_ONE_SHOT_AUTODETECT", "off")]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(
+        stdout.contains("FAIL: a token is still exposed"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_missing_library() {
+    let output = demo(&["--lib", "/nonexistent/one-shot-token.so"]);
+    assert_eq!(output.status.code(), Some(2));
+}
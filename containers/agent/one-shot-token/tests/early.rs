@@ -0,0 +1,110 @@
+//! getenv calls made before the library is initialized
+//!
+//! A helper library preloaded after the library reads GITHUB_TOKEN from its
+//! constructor, which the dynamic linker runs before the library's own. The
+//! probe then reads the token from main. Needs a C compiler (`cc`); the test
+//! is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::path::Path;
+use std::process::{Command, Output};
+
+const HELPER: &str = r#"
+#define _GNU_SOURCE
+#include <stdlib.h>
+
+const char *early_value;
+const char *early_secure_value;
+
+__attribute__((constructor)) static void read_early(void) {
+    early_value = getenv("GITHUB_TOKEN");
+    early_secure_value = secure_getenv("GITHUB_TOKEN");
+    getenv("PATH");
+}
+"#;
+
+const PROBE: &str = r#"
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+extern const char *early_value;
+extern const char *early_secure_value;
+extern char **environ;
+
+static int exposed(void) {
+    for (char **entry = environ; *entry; entry++) {
+        if (!strncmp(*entry, "GITHUB_TOKEN=", 13)) {
+            return 1;
+        }
+    }
+    return 0;
+}
+
+int main(void) {
+    printf("early %s %s %s\n", early_value ? early_value : "(null)",
+           early_secure_value ? early_secure_value : "(null)",
+           exposed() ? "exposed" : "scrubbed");
+    const char *value = getenv("GITHUB_TOKEN");
+    printf("main %s %s\n", value ? value : "(null)", exposed() ? "exposed" : "scrubbed");
+    return 0;
+}
+"#;
+
+/// Run the probe with the library, then the helper, preloaded
+fn run(probe: &Path, helper: &Path, envs: &[(&str, &str)]) -> Output {
+    let preload = format!("{}:{}", library().display(), helper.display());
+    Command::new(probe)
+        .env_clear()
+        .env("LD_PRELOAD", preload)
+        .env("GITHUB_TOKEN", "ghp_early")
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_early_getenv() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("early");
+    let Some(helper) = compile_probe(&dir, "libearly.so", HELPER, &["-shared", "-fPIC"]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+    let probe = compile_probe(&dir, "probe", PROBE, &[helper.to_str().unwrap()]).unwrap();
+
+    // The early caller gets the value from environ; protection starts with
+    // the first read after initialization
+    let output = run(&probe, &helper, &[("AWF_ONE_SHOT_TOKEN_DEBUG", "1")]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "early ghp_early ghp_early exposed\nmain ghp_early scrubbed\n",
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("AUDIT event=early_read token=GITHUB_TOKEN severity=medium"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("getenv call(s) from environ before initialization"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("ghp_early"), "{}", stderr);
+
+    let output = run(&probe, &helper, &[("AWF_ONE_SHOT_DISABLE", "1")]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "early ghp_early ghp_early exposed\nmain ghp_early exposed\n"
+    );
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("early_read"));
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
@@ -0,0 +1,262 @@
+//! Protected values written to files under the workspace or to the workflow
+//! command files are caught
+//!
+//! Small C programs read the token and write it to a file with write(2),
+//! pwrite(2) and stdio. Needs a C compiler (`cc`); the tests are skipped
+//! without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+int main(int argc, char **argv) {
+    const char *token = getenv("GITHUB_TOKEN");
+    if (!token || argc < 2) {
+        return 1;
+    }
+    int fd = open(argv[1], O_WRONLY | O_CREAT | O_TRUNC, 0600);
+    if (fd < 0) {
+        return 2;
+    }
+    char line[256] = "token=";
+    strcat(line, token);
+    strcat(line, "\n");
+    ssize_t n = write(fd, line, strlen(line));
+    printf("write %zd\n", n);
+    n = pwrite(fd, token, strlen(token), strlen(line));
+    printf("pwrite %zd\n", n);
+    close(fd);
+    FILE *file = fopen(argv[1], "a");
+    printf("fprintf %d\n", fprintf(file, "fprintf=%s\n", token));
+    fclose(file);
+    return 0;
+}
+"#;
+
+const GUARD_PROBE: &str = r#"
+#include <stdio.h>
+#include <stdlib.h>
+
+/* fprintf the token into each file, then print what each fprintf returned */
+int main(int argc, char **argv) {
+    const char *token = getenv("GITHUB_TOKEN");
+    if (!token || argc < 3) {
+        return 1;
+    }
+    for (int i = 1; i < argc; i++) {
+        FILE *file = fopen(argv[i], "w");
+        if (!file) {
+            return 2;
+        }
+        printf(i > 1 ? " %d" : "%d", fprintf(file, "TOKEN=%s\n", token));
+        fclose(file);
+    }
+    printf("\n");
+    return 0;
+}
+"#;
+
+const COMMAND_PROBE: &str = r#"
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+#include <fcntl.h>
+
+int main(void) {
+    const char *token = getenv("GITHUB_TOKEN");
+    const char *path = getenv("GITHUB_ENV");
+    if (!token || !path) {
+        return 1;
+    }
+    int fd = open(path, O_WRONLY | O_APPEND);
+    char line[256] = "LEAKED=";
+    strcat(line, token);
+    strcat(line, "\n");
+    printf("write %zd\n", write(fd, line, strlen(line)));
+    write(fd, "SAFE=1\n", 7);
+    close(fd);
+    FILE *file = fopen(path, "a");
+    printf("fprintf %d\n", fprintf(file, "AGAIN=%s\n", token));
+    /* Characters written one at a time are not checked */
+    fputs("LATER=", file);
+    for (const char *c = token; *c; c++) {
+        fputc(*c, file);
+    }
+    fputc('\n', file);
+    fclose(file);
+    return 0;
+}
+"#;
+
+#[test]
+fn test_file_write_scan() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("filewrite");
+    let Some(probe) = compile_probe(&dir, "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+    let target = dir.join("out.txt");
+
+    let run = |policy: &str| {
+        let output = Command::new(&probe)
+            .arg(&target)
+            .env_clear()
+            .env("LD_PRELOAD", &library)
+            .env("GITHUB_TOKEN", "ghp_filesecret")
+            .env("GITHUB_WORKSPACE", &dir)
+            .env("AWF_ONE_SHOT_FILE_WRITE_SCAN", policy)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        (
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+            std::fs::read_to_string(&target).unwrap(),
+        )
+    };
+
+    let (stdout, stderr, content) = run("redact");
+    assert_eq!(stdout, "write 21\npwrite 14\nfprintf 23\n");
+    assert_eq!(
+        content,
+        "token=**************\n**************fprintf=**************\n"
+    );
+    assert!(
+        stderr.contains("event=file_secret token=GITHUB_TOKEN path=")
+            && stderr.contains("via=pwrite action=redacted")
+            && stderr.contains("via=stdio action=redacted"),
+        "{}",
+        stderr
+    );
+
+    let (stdout, stderr, content) = run("block");
+    assert_eq!(stdout, "write -1\npwrite -1\nfprintf -1\n");
+    assert_eq!(content, "");
+    assert!(
+        stderr.contains("via=write action=blocked") && stderr.contains("via=stdio action=blocked"),
+        "{}",
+        stderr
+    );
+
+    let (_, stderr, content) = run("off");
+    assert_eq!(
+        content,
+        "token=ghp_filesecret\nghp_filesecretfprintf=ghp_filesecret\n"
+    );
+    assert!(!stderr.contains("file_secret"), "{}", stderr);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_workspace_guard() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("workspace");
+    let Some(probe) = compile_probe(&dir, "guard", GUARD_PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+    let workspace = dir.join("repo");
+    std::fs::create_dir_all(&workspace).unwrap();
+    let inside = workspace.join(".env");
+    let outside = dir.join("notes.txt");
+
+    // Blocked in the workspace, with the scan off; /tmp keeps its policy
+    let output = Command::new(&probe)
+        .arg(&inside)
+        .arg(&outside)
+        .env_clear()
+        .env("LD_PRELOAD", &library)
+        .env("GITHUB_TOKEN", "ghp_guardsecret")
+        .env("GITHUB_WORKSPACE", &workspace)
+        .env("AWF_ONE_SHOT_WORKSPACE_GUARD", "1")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "-1 22\n");
+    assert_eq!(std::fs::read_to_string(&inside).unwrap(), "");
+    assert_eq!(
+        std::fs::read_to_string(&outside).unwrap(),
+        "TOKEN=ghp_guardsecret\n"
+    );
+    assert!(
+        stderr.contains("event=file_secret token=GITHUB_TOKEN path=")
+            && stderr.contains("/repo/.env via=stdio action=blocked"),
+        "{}",
+        stderr
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_command_file_scan() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("command");
+    let Some(probe) = compile_probe(&dir, "command", COMMAND_PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+    let env_file = dir.join("set_env");
+
+    let run = |policy: Option<&str>| {
+        std::fs::write(&env_file, "").unwrap();
+        let mut command = Command::new(&probe);
+        command
+            .env_clear()
+            .env("LD_PRELOAD", &library)
+            .env("GITHUB_TOKEN", "ghp_commandsecret")
+            .env("GITHUB_ENV", &env_file);
+        if let Some(policy) = policy {
+            command.env("AWF_ONE_SHOT_COMMAND_FILE_SCAN", policy);
+        }
+        let output = command.output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        (
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+            std::fs::read_to_string(&env_file).unwrap(),
+        )
+    };
+
+    // Blocked by default; the line written a character at a time is
+    // overwritten at exit
+    let (stdout, stderr, content) = run(None);
+    assert_eq!(stdout, "write -1\nfprintf -1\n");
+    assert_eq!(content, "SAFE=1\nLATER=*****************\n");
+    assert!(
+        stderr.contains("event=command_file_secret token=GITHUB_TOKEN file=GITHUB_ENV path=")
+            && stderr.contains("via=write action=blocked")
+            && stderr.contains("via=stdio action=blocked")
+            && stderr.contains("via=exit action=redacted"),
+        "{}",
+        stderr
+    );
+
+    let (stdout, _, content) = run(Some("redact"));
+    assert_eq!(stdout, "write 25\nfprintf 24\n");
+    assert_eq!(
+        content,
+        "LEAKED=*****************\nSAFE=1\nAGAIN=*****************\nLATER=*****************\n"
+    );
+
+    let (_, stderr, content) = run(Some("off"));
+    assert!(content.starts_with("LEAKED=ghp_commandsecret\n"));
+    assert!(!stderr.contains("command_file_secret"), "{}", stderr);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,91 @@
+//! A wipe in a forked child leaves the parent's cached value intact
+//!
+//! Values cached in secret memory before a fork would be shared with the
+//! child. A small C program reads the token, forks, wipes it in the child and
+//! reads it again in the parent. The child also counts its threads, which it
+//! only starts on its first getenv. Needs a C compiler (`cc`); the test is
+//! skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#define _GNU_SOURCE
+#include <dirent.h>
+#include <dlfcn.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+typedef int (*wipe_fn)(const char *);
+
+static const char *show(const char *value) {
+    return value ? value : "(null)";
+}
+
+static int threads(void) {
+    int count = 0;
+    DIR *dir = opendir("/proc/self/task");
+    while (dir && readdir(dir)) {
+        count++;
+    }
+    if (dir) {
+        closedir(dir);
+    }
+    return count - 2;
+}
+
+int main(void) {
+    wipe_fn wipe = (wipe_fn)dlvsym(RTLD_DEFAULT, "awf_token_wipe", "AWF_1.0");
+    if (!wipe) {
+        puts("unresolved");
+        return 1;
+    }
+    const char *before = getenv("GITHUB_TOKEN");
+    fflush(stdout);
+    pid_t pid = fork();
+    if (pid == 0) {
+        int before_getenv = threads();
+        int wiped = wipe("GITHUB_TOKEN");
+        const char *value = getenv("GITHUB_TOKEN");
+        printf("child threads=%d,%d\n", before_getenv, threads());
+        printf("child wiped=%d getenv=%s\n", wiped, show(value));
+        fflush(stdout);
+        _exit(0);
+    }
+    int status;
+    waitpid(pid, &status, 0);
+    printf("parent held=%s getenv=%s\n", show(before), show(getenv("GITHUB_TOKEN")));
+    return 0;
+}
+"#;
+
+#[test]
+fn test_child_wipe_keeps_parent_value() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("fork-wipe");
+    let Some(probe) = compile_probe(&dir, "probe", PROBE, &["-ldl"]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let output = Command::new(&probe)
+        .env_clear()
+        .env("LD_PRELOAD", &library)
+        .env("GITHUB_TOKEN", "ghp_forkwipe")
+        .env("AWF_ONE_SHOT_SIGNALS", "1")
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "child threads=1,2\nchild wiped=1 getenv=(null)\nparent held=ghp_forkwipe getenv=ghp_forkwipe\n"
+    );
+}
@@ -0,0 +1,106 @@
+//! awf_one_shot_healthcheck reports a dlopen-ed library as functional and
+//! lists configuration problems
+//!
+//! A small C program dlopens the library and prints the health check. Needs a
+//! C compiler (`cc`); the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#define _GNU_SOURCE
+#include <dlfcn.h>
+#include <stdio.h>
+#include <sys/types.h>
+
+typedef ssize_t (*healthcheck_fn)(char *, size_t);
+
+int main(int argc, char **argv) {
+    void *library = dlopen(argv[1], RTLD_NOW | RTLD_LOCAL);
+    if (!library) {
+        printf("dlopen: %s\n", dlerror());
+        return 1;
+    }
+    healthcheck_fn healthcheck = (healthcheck_fn)dlvsym(library, "awf_one_shot_healthcheck", "AWF_1.1");
+    if (!healthcheck) {
+        puts("unresolved");
+        return 1;
+    }
+    char buf[4096];
+    ssize_t len = healthcheck(buf, sizeof(buf));
+    if (len < 0 || (size_t)len >= sizeof(buf)) {
+        puts("truncated");
+        return 1;
+    }
+    puts(buf);
+    return 0;
+}
+"#;
+
+#[test]
+fn test_healthcheck() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let Some(probe) = compile_probe(&scratch_dir("healthcheck"), "probe", PROBE, &["-ldl"]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let healthcheck = |settings: &[(&str, &str)]| {
+        let output = Command::new(&probe)
+            .arg(&library)
+            .env_clear()
+            .env("AWF_ONE_SHOT_POLICY_FILE", "")
+            .envs(settings.iter().copied())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string()
+    };
+
+    let healthy = healthcheck(&[("AWF_ONE_SHOT_TOKENS", "GITHUB_TOKEN,NPM_TOKEN:strict")]);
+    assert!(
+        healthy.starts_with("{\"version\":\"")
+            && healthy.ends_with(concat!(
+                "\"ok\":true,\"disabled\":false,\"tokens\":2,",
+                "\"symbols\":{\"ok\":true,\"missing\":[]},",
+                "\"config\":{\"ok\":true,\"problems\":[]}}"
+            )),
+        "{}",
+        healthy
+    );
+
+    let broken = healthcheck(&[
+        ("AWF_ONE_SHOT_MODE", "enforcing"),
+        ("AWF_ONE_SHOT_EXTRA_TOKENS", "NPM_TOKEN:max_read=1"),
+    ]);
+    assert!(
+        broken.ends_with(concat!(
+            "\"config\":{\"ok\":false,\"problems\":[",
+            "\"AWF_ONE_SHOT_MODE: unknown value 'enforcing', expected enforce or observe; \
+             enforcing\",",
+            "\"AWF_ONE_SHOT_EXTRA_TOKENS: unknown option(s) max_read=1 for NPM_TOKEN\"]}}"
+        )),
+        "{}",
+        broken
+    );
+    assert!(broken.contains("\"ok\":false,"), "{}", broken);
+
+    let typo = healthcheck(&[("AWF_ONE_SHOT_EXTRA_TOKEN", "NPM_TOKEN")]);
+    assert!(
+        typo.ends_with(concat!(
+            "\"config\":{\"ok\":false,\"problems\":[",
+            "\"AWF_ONE_SHOT_EXTRA_TOKEN: unknown setting, did you mean \
+             AWF_ONE_SHOT_EXTRA_TOKENS?\"]}}"
+        )),
+        "{}",
+        typo
+    );
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
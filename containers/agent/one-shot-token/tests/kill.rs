@@ -0,0 +1,89 @@
+//! Terminating the process on repeated reads of deny-listed variables
+//!
+//! A small C program reads a deny-listed variable a number of times,
+//! printing a line after each read. Needs a C compiler (`cc`); the test is
+//! skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Command, Output};
+
+const PROBE: &str = r#"
+#include <stdio.h>
+#include <stdlib.h>
+
+int main(void) {
+    for (int read = 1; read <= 3; read++) {
+        const char *value = getenv("ACTIONS_RUNTIME_TOKEN");
+        printf("read %d %s\n", read, value ? value : "(null)");
+        fflush(stdout);
+    }
+    return 0;
+}
+"#;
+
+fn run(probe: &Path, envs: &[(&str, &str)]) -> Output {
+    Command::new(probe)
+        .env_clear()
+        .env("LD_PRELOAD", library())
+        .env("ACTIONS_RUNTIME_TOKEN", "runtime-secret")
+        .env("AWF_ONE_SHOT_DENY_TOKENS", "ACTIONS_RUNTIME_TOKEN")
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_deny_kill() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let Some(probe) = compile_probe(&scratch_dir("kill"), "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    // Killed at the second read, before it returns
+    let output = run(&probe, &[("AWF_ONE_SHOT_DENY_KILL", "2")]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.signal(), Some(libc::SIGKILL), "{}", stderr);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "read 1 (null)\n");
+    assert!(
+        stderr
+            .contains("AUDIT event=denied token=ACTIONS_RUNTIME_TOKEN present=false action=killed"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(
+            "AUDIT event=killed token=ACTIONS_RUNTIME_TOKEN reason=denied signal=SIGKILL reads=2"
+        ),
+        "{}",
+        stderr
+    );
+
+    let output = run(
+        &probe,
+        &[
+            ("AWF_ONE_SHOT_DENY_KILL", "1"),
+            ("AWF_ONE_SHOT_KILL_SIGNAL", "term"),
+        ],
+    );
+    assert_eq!(output.status.signal(), Some(libc::SIGTERM));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("signal=SIGTERM reads=1"));
+
+    // Without a limit every read is denied and the program finishes
+    let output = run(&probe, &[]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "read 1 (null)\nread 2 (null)\nread 3 (null)\n"
+    );
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("event=killed"));
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
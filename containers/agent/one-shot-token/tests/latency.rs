@@ -0,0 +1,72 @@
+//! Sampled getenv latencies reach the access report
+//! (AWF_ONE_SHOT_LATENCY_SAMPLE)
+//!
+//! A small C program reads a protected token and an ordinary variable ten
+//! times each. Needs a C compiler (`cc`); the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#include <stdlib.h>
+
+int main(void) {
+    for (int i = 0; i < 10; i++) {
+        getenv("GITHUB_TOKEN");
+        getenv("AWF_LATENCY_PLAIN");
+    }
+    return 0;
+}
+"#;
+
+#[test]
+fn test_latency_report() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("latency");
+    let Some(probe) = compile_probe(&dir, "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let report = |sample: &str| {
+        let path = dir.join(format!("report-{}.jsonl", sample));
+        let status = Command::new(&probe)
+            .env_clear()
+            .env("LD_PRELOAD", &library)
+            .env("GITHUB_TOKEN", "ghp_latency")
+            .env("AWF_ONE_SHOT_REPORT", &path)
+            .env("AWF_ONE_SHOT_LATENCY_SAMPLE", sample)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::fs::read_to_string(&path).unwrap()
+    };
+
+    let sampled = report("1");
+    let latency = sampled
+        .split_once(",\"latency\":{\"sample\":1,")
+        .map(|(_, latency)| latency)
+        .unwrap_or_else(|| panic!("no latency: {}", sampled));
+    let (sensitive, passthrough) = latency.split_once("\"passthrough\":").unwrap();
+    assert!(
+        sensitive.starts_with("\"sensitive\":{\"samples\":10,"),
+        "{}",
+        sampled
+    );
+    let samples: u64 = passthrough
+        .strip_prefix("{\"samples\":")
+        .and_then(|rest| rest.split(',').next())
+        .and_then(|count| count.parse().ok())
+        .unwrap();
+    // Startup code of the C library may look up other variables too
+    assert!(samples >= 10, "{}", sampled);
+
+    let unsampled = report("0");
+    assert!(!unsampled.contains("\"latency\""), "{}", unsampled);
+    let _ = std::fs::remove_dir_all(&dir);
+}
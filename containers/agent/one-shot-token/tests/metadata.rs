@@ -0,0 +1,87 @@
+//! connect() to the cloud metadata service is refused unless allowed
+//!
+//! A small C program makes a non-blocking connect to 169.254.169.254 and
+//! prints whether it was refused. Needs a C compiler (`cc`); the test is
+//! skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#include <arpa/inet.h>
+#include <errno.h>
+#include <netinet/in.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <unistd.h>
+
+int main(void) {
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_port = htons(80);
+    inet_pton(AF_INET, "169.254.169.254", &addr.sin_addr);
+
+    int fd = socket(AF_INET, SOCK_STREAM | SOCK_NONBLOCK, 0);
+    int result = connect(fd, (struct sockaddr *)&addr, sizeof(addr));
+    int refused = result < 0 && errno == ECONNREFUSED;
+    printf("refused %d\n", refused);
+    close(fd);
+    return 0;
+}
+"#;
+
+#[test]
+fn test_metadata_access() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("metadata");
+    let Some(probe) = compile_probe(&dir, "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let run = |settings: &[(&str, &str)]| {
+        let output = Command::new(&probe)
+            .env_clear()
+            .env("LD_PRELOAD", &library)
+            .envs(settings.iter().copied())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        (
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    };
+
+    // Blocked by default
+    let (stdout, stderr) = run(&[]);
+    assert_eq!(stdout, "refused 1\n", "{}", stderr);
+    assert!(
+        stderr.contains("event=metadata_blocked token=* ip=169.254.169.254 port=80"),
+        "{}",
+        stderr
+    );
+
+    // A domain allowlist does not lift the block, listing the address does
+    let (stdout, stderr) = run(&[("AWF_ONE_SHOT_ALLOWED_DOMAINS", "github.com")]);
+    assert_eq!(stdout, "refused 1\n", "{}", stderr);
+    let (_, stderr) = run(&[("AWF_ONE_SHOT_ALLOWED_DOMAINS", "github.com,169.254.169.254")]);
+    assert!(!stderr.contains("event=metadata_"), "{}", stderr);
+
+    let (_, stderr) = run(&[("AWF_ONE_SHOT_METADATA_ACCESS", "log")]);
+    assert!(
+        stderr.contains("event=metadata_connect token=* ip=169.254.169.254 port=80"),
+        "{}",
+        stderr
+    );
+    let (_, stderr) = run(&[("AWF_ONE_SHOT_METADATA_ACCESS", "allow")]);
+    assert!(!stderr.contains("event=metadata_"), "{}", stderr);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
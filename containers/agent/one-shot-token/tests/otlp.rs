@@ -0,0 +1,121 @@
+//! Token accesses are exported to an OTLP/HTTP collector
+//! (AWF_ONE_SHOT_OTLP_ENDPOINT)
+//!
+//! A small C program reads a token under the library, with the exporter
+//! pointed at a collector run by the test, which checks the records it
+//! receives. Needs a C compiler (`cc`); the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::process::Command;
+use std::time::Duration;
+
+const PROBE: &str = r#"
+#include <stdio.h>
+#include <stdlib.h>
+
+int main(void) {
+    const char *value = getenv("GITHUB_TOKEN");
+    printf("read=%s\n", value ? value : "(null)");
+    return 0;
+}
+"#;
+
+/// Accept requests, answering each with 200, until a connection closes
+/// without sending anything, and return what was received
+fn collect(listener: TcpListener) -> String {
+    let mut received = String::new();
+    while let Ok((mut stream, _)) = listener.accept() {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(count) => request.extend_from_slice(&buf[..count]),
+            }
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .and_then(|value| value.trim().parse::<usize>().ok())
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    break;
+                }
+            }
+        }
+        if request.is_empty() {
+            break;
+        }
+        let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        received.push_str(&String::from_utf8_lossy(&request));
+    }
+    received
+}
+
+#[test]
+fn test_otlp_export() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("otlp");
+    let Some(probe) = compile_probe(&dir, "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    // The probe exits at once, so its records arrive with the flush at exit
+    let collector = std::thread::spawn(move || collect(listener));
+
+    let output = Command::new(&probe)
+        .env_clear()
+        .env("LD_PRELOAD", &library)
+        .env("GITHUB_TOKEN", "ghp_otlp")
+        .env(
+            "AWF_ONE_SHOT_OTLP_ENDPOINT",
+            format!("http://127.0.0.1:{}", port),
+        )
+        .env("AWF_ONE_SHOT_OTLP_HEADERS", "x-api-key=abc123")
+        .env("AWF_ONE_SHOT_OTLP_SERVICE", "otlp-test")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("read=ghp_otlp"), "{}", stdout);
+
+    // An empty connection tells the collector the probe is done
+    std::net::TcpStream::connect(("127.0.0.1", port))
+        .and_then(|stream| stream.shutdown(std::net::Shutdown::Both))
+        .unwrap();
+    let received = collector.join().unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert!(
+        received.starts_with("POST /v1/logs HTTP/1.1"),
+        "{}",
+        received
+    );
+    assert!(received.contains("x-api-key: abc123"), "{}", received);
+    assert!(received.contains("\"resourceLogs\""), "{}", received);
+    assert!(received.contains("otlp-test"), "{}", received);
+    assert!(
+        received.contains("{\"stringValue\":\"access\"}"),
+        "{}",
+        received
+    );
+    assert!(
+        received.contains("\"awf.token\",\"value\":{\"stringValue\":\"GITHUB_TOKEN\"}"),
+        "{}",
+        received
+    );
+    assert!(!received.contains("ghp_otlp"), "{}", received);
+}
@@ -0,0 +1,167 @@
+//! Protected values written to stdout and stderr are redacted
+//!
+//! Small C programs read the token and write it to both streams, with
+//! write(2) and through stdio. Needs a C compiler (`cc`); the tests are
+//! skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+static int say(int fd, const char *line) {
+    return write(fd, line, strlen(line)) == (ssize_t)strlen(line);
+}
+
+int main(void) {
+    const char *token = getenv("GITHUB_TOKEN");
+    if (!token) {
+        return 1;
+    }
+    char line[256] = "token=";
+    strcat(line, token);
+    strcat(line, " again=");
+    strcat(line, token);
+    strcat(line, "\n");
+    char error[256] = "stderr=";
+    strcat(error, token);
+    strcat(error, "\n");
+    return say(1, line) && say(2, error) ? 0 : 2;
+}
+"#;
+
+const STDIO_PROBE: &str = r#"
+#define _XOPEN_SOURCE 600
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+int main(int argc, char **argv) {
+    const char *token = getenv("GITHUB_TOKEN");
+    if (!token || argc < 2) {
+        return 1;
+    }
+    printf("printf=%s fileno=%d\n", token, fileno(stdout));
+    puts(token);
+    fwrite(token, 1, strlen(token), stdout);
+    fputs("\n", stdout);
+    fprintf(stderr, "stderr=%s fileno=%d\n", token, fileno(stderr));
+
+    /* stdout is still the program's own stream on fd 1 */
+    int saved = dup(1);
+    int tty = posix_openpt(O_RDWR | O_NOCTTY);
+    if (tty >= 0 && grantpt(tty) == 0 && unlockpt(tty) == 0) {
+        fflush(stdout);
+        dup2(open(ptsname(tty), O_RDWR | O_NOCTTY), 1);
+        tty = isatty(fileno(stdout));
+        dup2(saved, 1);
+    }
+    printf("isatty=%d\n", tty);
+    fflush(stdout);
+
+    /* A stream the program opens on fd 1 itself */
+    FILE *one = fdopen(1, "w");
+    fprintf(one, "fdopen=%s\n", token);
+    fflush(one);
+
+    /* stdout reopened on a file keeps fd 1 */
+    if (!freopen(argv[1], "w", stdout)) {
+        return 2;
+    }
+    printf("freopen=%s fileno=%d\n", token, fileno(stdout));
+    return 0;
+}
+"#;
+
+#[test]
+fn test_output_redacted() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let Some(probe) = compile_probe(&scratch_dir("output"), "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let run = |mode: &str| {
+        let output = Command::new(&probe)
+            .env_clear()
+            .env("LD_PRELOAD", &library)
+            .env("GITHUB_TOKEN", "ghp_outputsecret")
+            .env("AWF_ONE_SHOT_REDACT_OUTPUT", "1")
+            .env("AWF_ONE_SHOT_MODE", mode)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        (
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    };
+
+    let (stdout, stderr) = run("enforce");
+    assert_eq!(stdout, "token=*** again=***\n");
+    assert!(stderr.contains("\nstderr=***\n"), "{}", stderr);
+    assert!(!stderr.contains("ghp_outputsecret"), "{}", stderr);
+    assert!(
+        stderr.contains("event=output_redacted token=GITHUB_TOKEN fd=1 matches=2"),
+        "{}",
+        stderr
+    );
+
+    let (stdout, stderr) = run("observe");
+    assert_eq!(stdout, "token=ghp_outputsecret again=ghp_outputsecret\n");
+    assert!(stderr.contains("would=redact_output fd=1"), "{}", stderr);
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
+
+#[test]
+fn test_stdio_output_redacted() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("output-stdio");
+    let Some(probe) = compile_probe(&dir, "probe", STDIO_PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+    let reopened = dir.join("reopened.txt");
+
+    // Fortified, printf and fprintf are __printf_chk and __fprintf_chk
+    let fortified = ["-O2", "-D_FORTIFY_SOURCE=2"];
+    let fortified = compile_probe(&dir, "fortified", STDIO_PROBE, &fortified).unwrap();
+    for probe in [&probe, &fortified] {
+        let output = Command::new(probe)
+            .arg(&reopened)
+            .env_clear()
+            .env("LD_PRELOAD", &library)
+            .env("GITHUB_TOKEN", "ghp_stdiosecret")
+            .env("AWF_ONE_SHOT_REDACT_OUTPUT", "1")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "printf=*** fileno=1\n***\n***\nisatty=1\nfdopen=***\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&reopened).unwrap(),
+            "freopen=*** fileno=1\n"
+        );
+        assert!(stderr.contains("\nstderr=*** fileno=2\n"), "{}", stderr);
+        assert!(
+            stderr.contains("event=output_redacted token=GITHUB_TOKEN fd=1 matches=1"),
+            "{}",
+            stderr
+        );
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
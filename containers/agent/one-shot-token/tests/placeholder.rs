@@ -0,0 +1,84 @@
+//! Placeholder scrubbing keeps protected variables listed in environ
+//!
+//! A small C program reads GITHUB_TOKEN, then walks environ and prints the
+//! entry it finds. Needs a C compiler (`cc`); the test is skipped without
+//! one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::path::Path;
+use std::process::{Command, Output};
+
+const PROBE: &str = r#"
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+extern char **environ;
+
+int main(void) {
+    const char *value = getenv("GITHUB_TOKEN");
+    printf("read %s\n", value ? value : "(null)");
+    const char *entry = "(none)";
+    for (char **env = environ; *env; env++) {
+        if (!strncmp(*env, "GITHUB_TOKEN=", 13)) {
+            entry = *env;
+        }
+    }
+    printf("environ %s\n", entry);
+    return 0;
+}
+"#;
+
+fn run(probe: &Path, token: &str, envs: &[(&str, &str)]) -> Output {
+    Command::new(probe)
+        .env_clear()
+        .env("LD_PRELOAD", library())
+        .env("GITHUB_TOKEN", token)
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_placeholder_scrub() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let Some(probe) = compile_probe(&scratch_dir("placeholder"), "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let output = run(
+        &probe,
+        "ghp_placeholder",
+        &[("AWF_ONE_SHOT_SCRUB", "placeholder")],
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "read ghp_placeholder\nenviron GITHUB_TOKEN=***AWF-PROTECTED***\n",
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // An inherited placeholder is not a credential
+    let output = run(
+        &probe,
+        "***AWF-PROTECTED***",
+        &[("AWF_ONE_SHOT_SCRUB", "placeholder")],
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "read (null)\nenviron GITHUB_TOKEN=***AWF-PROTECTED***\n"
+    );
+
+    let output = run(&probe, "ghp_placeholder", &[]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "read ghp_placeholder\nenviron (none)\n"
+    );
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
@@ -0,0 +1,68 @@
+//! awf-preload-verify passes with the library loaded, and fails without it
+//!
+//! The driver finds the cdylib next to itself in the target directory.
+//! Fixtures whose runtime is missing are skipped, so only the shell fixture
+//! is certain to run.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn awf_preload_verify(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_awf-preload-verify"))
+        .args(args)
+        .env_remove("LD_PRELOAD")
+        .output()
+        .unwrap()
+}
+
+/// A shared library that is not one-shot-token: the C library of this process
+fn other_library() -> PathBuf {
+    let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+    maps.lines()
+        .filter_map(|line| line.split_whitespace().nth(5))
+        .find(|path| path.contains("/libc.so"))
+        .map(PathBuf::from)
+        .expect("libc is mapped")
+}
+
+#[test]
+fn test_verify_passes() {
+    let output = awf_preload_verify(&[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}\n{}", stdout, stderr);
+    assert!(stdout.starts_with("{\"version\":"), "{}", stdout);
+    assert!(stdout.contains("\"ok\":true"), "{}", stdout);
+    assert!(
+        stdout.contains("{\"name\":\"shell\",\"status\":\"pass\""),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains("ghp_awfverify"), "{}", stdout);
+}
+
+#[test]
+fn test_verify_fails_without_library() {
+    let library = other_library();
+    let output = awf_preload_verify(&["--lib", library.to_str().unwrap(), "--fixture", "shell"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(stdout.contains("\"ok\":false"), "{}", stdout);
+    assert!(
+        stdout.contains(
+            "{\"name\":\"environ\",\"expected\":\"clean\",\"observed\":\"found\",\"ok\":false}"
+        ),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_verify_usage() {
+    let output = awf_preload_verify(&["--fixture", "ruby"]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = awf_preload_verify(&["--lib", "/nonexistent/one-shot-token.so"]);
+    assert_eq!(output.status.code(), Some(2));
+}
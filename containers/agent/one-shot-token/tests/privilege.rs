@@ -0,0 +1,91 @@
+//! Cached tokens are wiped when the process changes credentials
+//!
+//! A small C program reads GITHUB_TOKEN, switches its effective user ID to
+//! nobody, reads it again, then switches back and reads it a third time.
+//! Needs a C compiler (`cc`) and root; the test is skipped without them.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::path::Path;
+use std::process::{Command, Output};
+
+const PROBE: &str = r#"
+#include <stdio.h>
+#include <stdlib.h>
+#include <unistd.h>
+
+int main(void) {
+    const char *before = getenv("GITHUB_TOKEN");
+    printf("before %s\n", before ? before : "(null)");
+    if (seteuid(65534) != 0) {
+        return 1;
+    }
+    const char *after = getenv("GITHUB_TOKEN");
+    printf("after %s\n", after ? after : "(null)");
+    printf("held %s\n", before && *before ? "value" : "zeroed");
+    if (seteuid(0) != 0) {
+        return 1;
+    }
+    const char *back = getenv("GITHUB_TOKEN");
+    printf("back %s\n", back ? back : "(null)");
+    return 0;
+}
+"#;
+
+fn run(probe: &Path, envs: &[(&str, &str)]) -> Output {
+    Command::new(probe)
+        .env_clear()
+        .env("LD_PRELOAD", library())
+        .env("GITHUB_TOKEN", "ghp_privilege")
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_privilege_change() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    // SAFETY: geteuid cannot fail
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("not root, skipping");
+        return;
+    }
+    let Some(probe) = compile_probe(&scratch_dir("privilege"), "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let output = run(&probe, &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "before ghp_privilege\nafter (null)\nheld zeroed\nback (null)\n",
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(
+            "AUDIT event=privilege_change token=* severity=high euid=0->65534 \
+             secure=false->true wiped=1"
+        ),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("AUDIT event=privilege_change token=GITHUB_TOKEN reads=1"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("ghp_privilege"), "{}", stderr);
+
+    let output = run(&probe, &[("AWF_ONE_SHOT_PRIVILEGE_WATCH", "0")]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "before ghp_privilege\nafter ghp_privilege\nheld value\nback ghp_privilege\n"
+    );
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
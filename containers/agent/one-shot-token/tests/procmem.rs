@@ -0,0 +1,145 @@
+//! ptrace, process_vm_readv and /proc/<pid>/mem or maps of another process
+//! are denied (AWF_ONE_SHOT_PROC_MEMORY)
+//!
+//! A small C program forks a child and tries to attach to it and read its
+//! memory with ptrace, process_vm_readv and its /proc files, then prints the
+//! errno of each call. Needs a C compiler
+//! (`cc`); the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <signal.h>
+#include <stdio.h>
+#include <sys/ptrace.h>
+#include <sys/uio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static long word = 42;
+
+int main(void) {
+    pid_t child = fork();
+    if (child == 0) {
+        pause();
+        return 0;
+    }
+
+    errno = 0;
+    long result = ptrace(PTRACE_PEEKDATA, child, &word, NULL);
+    printf("peekdata %d\n", result == -1 ? errno : 0);
+    result = ptrace(PTRACE_ATTACH, child, NULL, NULL);
+    printf("attach %d\n", result == -1 ? errno : 0);
+    result = ptrace(PTRACE_PEEKDATA, getpid(), &word, NULL);
+    printf("self %d\n", result == -1 ? errno : 0);
+
+    long copy = 0;
+    struct iovec local = {&copy, sizeof(copy)};
+    struct iovec remote = {&word, sizeof(word)};
+    result = process_vm_readv(child, &local, 1, &remote, 1, 0);
+    printf("readv %d\n", result == -1 ? errno : 0);
+    result = process_vm_readv(getpid(), &local, 1, &remote, 1, 0);
+    printf("readv_self %d\n", result == -1 ? errno : 0);
+
+    const char *files[] = {"mem", "maps"};
+    for (int i = 0; i < 2; i++) {
+        char path[64];
+        snprintf(path, sizeof(path), "/proc/%d/%s", (int)child, files[i]);
+        int fd = open(path, O_RDONLY);
+        printf("%s %d\n", files[i], fd < 0 ? errno : 0);
+        if (fd >= 0) {
+            close(fd);
+        }
+    }
+    int fd = open("/proc/self/maps", O_RDONLY);
+    printf("self_maps %d\n", fd < 0 ? errno : 0);
+
+    kill(child, SIGKILL);
+    waitpid(child, NULL, 0);
+    return 0;
+}
+"#;
+
+#[test]
+fn test_proc_memory_denied() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let Some(probe) = compile_probe(&scratch_dir("procmem"), "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let run = |setting: Option<(&str, &str)>| {
+        let mut command = Command::new(&probe);
+        command.env_clear().env("LD_PRELOAD", &library);
+        if let Some((name, value)) = setting {
+            command.env(name, value);
+        }
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        (
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    };
+
+    let (stdout, stderr) = run(None);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        [lines[0], lines[1], lines[3], lines[4]],
+        [
+            format!("peekdata {}", libc::EPERM).as_str(),
+            &format!("attach {}", libc::EPERM),
+            &format!("readv {}", libc::EPERM),
+            "readv_self 0",
+        ],
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        lines[5..],
+        [
+            format!("mem {}", libc::EACCES).as_str(),
+            &format!("maps {}", libc::EACCES),
+            "self_maps 0",
+        ],
+        "{}",
+        stderr
+    );
+    for request in ["peekdata", "attach"] {
+        assert!(
+            stderr.contains(&format!("request={} via=ptrace action=denied", request)),
+            "{}",
+            stderr
+        );
+    }
+    assert!(
+        stderr.contains("bytes=8 via=process_vm_readv action=denied"),
+        "{}",
+        stderr
+    );
+    for file in ["mem", "maps"] {
+        assert!(
+            stderr.contains(&format!("file={} via=open action=denied", file)),
+            "{}",
+            stderr
+        );
+    }
+    // The calls toward the process itself are passed on
+    assert_eq!(stderr.matches("event=proc_memory").count(), 5, "{}", stderr);
+
+    let (_, stderr) = run(Some(("AWF_ONE_SHOT_PROC_MEMORY", "allow")));
+    assert!(!stderr.contains("event=proc_memory"), "{}", stderr);
+    let (stdout, stderr) = run(Some(("AWF_ONE_SHOT_PROC_MEMORY_ALLOW", "gdb, probe")));
+    assert!(stdout.contains("\nmem 0\n"), "{}", stderr);
+    assert!(!stderr.contains("event=proc_memory"), "{}", stderr);
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
@@ -0,0 +1,59 @@
+//! AWF_ONE_SHOT_PROFILE supplies defaults that explicit settings override
+//!
+//! The hardening section of the verification report (AWF_ONE_SHOT_VERIFY)
+//! shows which features a profile requested.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::library;
+use std::process::Command;
+
+/// Run `true` with `profile` and `settings`, returning its verification report
+fn report(profile: &str, settings: &[(&str, &str)]) -> String {
+    let path = std::env::temp_dir().join(format!(
+        "one-shot-token-profile-{}-{}",
+        profile,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    let status = Command::new("true")
+        .env_clear()
+        .env("LD_PRELOAD", library())
+        .env("AWF_ONE_SHOT_VERIFY", &path)
+        .env("AWF_ONE_SHOT_PROFILE", profile)
+        .envs(settings.iter().copied())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    contents
+}
+
+#[test]
+fn test_profiles() {
+    assert!(library().exists(), "{} not built", library().display());
+    let requested = |name: &str, on: bool| {
+        format!(
+            "{{\"name\":\"{}\",\"requested\":{},\"active\":{}}}",
+            name, on, on
+        )
+    };
+
+    let paranoid = report("paranoid", &[("AWF_ONE_SHOT_LANDLOCK", "0")]);
+    for name in ["nodump", "nocore", "seccomp"] {
+        assert!(paranoid.contains(&requested(name, true)), "{}", paranoid);
+    }
+    assert!(
+        paranoid.contains(&requested("landlock", false)),
+        "{}",
+        paranoid
+    );
+    assert!(paranoid.contains("\"ok\":true"), "{}", paranoid);
+
+    let minimal = report("minimal", &[("AWF_ONE_SHOT_NOCORE", "1")]);
+    assert!(minimal.contains(&requested("nodump", false)), "{}", minimal);
+    assert!(minimal.contains(&requested("nocore", true)), "{}", minimal);
+}
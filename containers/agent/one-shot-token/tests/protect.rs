@@ -0,0 +1,109 @@
+//! awf_token_protect adds a variable to the protected set of a running
+//! process, and awf_token_unprotect takes it out again
+//!
+//! A small C program protects a variable set in its environment, reads it,
+//! and runs a child to show the rule is passed on. Needs a C compiler (`cc`);
+//! the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#define _GNU_SOURCE
+#include <dlfcn.h>
+#include <errno.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+typedef int (*name_fn)(const char *);
+
+static void show(const char *name) {
+    const char *value = getenv(name);
+    printf("%s=%s\n", name, value ? value : "(null)");
+}
+
+/* errno is only read once the call has returned */
+static void report(const char *label, int result, int expected) {
+    printf("%s %d %d\n", label, result, errno == expected);
+}
+
+int main(void) {
+    name_fn protect = (name_fn)dlvsym(RTLD_DEFAULT, "awf_token_protect", "AWF_1.3");
+    name_fn unprotect = (name_fn)dlvsym(RTLD_DEFAULT, "awf_token_unprotect", "AWF_1.3");
+    if (!protect || !unprotect) {
+        puts("unresolved");
+        return 1;
+    }
+    printf("protect %d\n", protect("MINTED_TOKEN:max_reads=1"));
+    show("MINTED_TOKEN");
+    show("MINTED_TOKEN");
+    report("bad rule", protect("BAD:bogus"), EINVAL);
+    report("cached", unprotect("MINTED_TOKEN"), EBUSY);
+    report("configured", unprotect("GITHUB_TOKEN"), EPERM);
+    printf("protect other %d\n", protect("OTHER_TOKEN"));
+    printf("unprotect other %d\n", unprotect("OTHER_TOKEN"));
+    show("OTHER_TOKEN");
+    report("unknown", unprotect("OTHER_TOKEN"), ENOENT);
+    fflush(stdout);
+    return system("printenv AWF_ONE_SHOT_EXTRA_TOKENS; printenv MINTED_TOKEN || echo scrubbed");
+}
+"#;
+
+#[test]
+fn test_protect_at_run_time() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("protect");
+    let Some(probe) = compile_probe(&dir, "probe", PROBE, &["-ldl"]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let output = Command::new(&probe)
+        .env_clear()
+        .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+        .env("LD_PRELOAD", &library)
+        .env("AWF_ONE_SHOT_TOKENS", "GITHUB_TOKEN")
+        .env("MINTED_TOKEN", "minted-secret")
+        .env("OTHER_TOKEN", "other-value")
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(
+        stdout,
+        concat!(
+            "protect 0\n",
+            "MINTED_TOKEN=minted-secret\n",
+            "MINTED_TOKEN=(null)\n",
+            "bad rule -1 1\n",
+            "cached -1 1\n",
+            "configured -1 1\n",
+            "protect other 0\n",
+            "unprotect other 0\n",
+            "OTHER_TOKEN=other-value\n",
+            "unknown -1 1\n",
+            "MINTED_TOKEN:max_reads=1\n",
+            "scrubbed\n",
+        ),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("event=protected token=MINTED_TOKEN via=awf_token_protect"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("event=unprotected token=OTHER_TOKEN via=awf_token_unprotect"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,107 @@
+//! A required token that is not set ends the process before it runs
+//!
+//! A small C program prints a line, reads GITHUB_TOKEN and runs itself again
+//! as a child through the shell. Needs a C compiler (`cc`); the test is
+//! skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::path::Path;
+use std::process::{Command, Output};
+
+const PROBE: &str = r#"
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main(int argc, char **argv) {
+    if (argc > 1) {
+        puts("child ran");
+        return 0;
+    }
+    puts("ran");
+    fflush(stdout);
+    const char *value = getenv("GITHUB_TOKEN");
+    printf("read %s\n", value ? value : "(null)");
+    fflush(stdout);
+    char command[4096];
+    snprintf(command, sizeof(command), "%s child", argv[0]);
+    return system(command) == 0 ? 0 : 1;
+}
+"#;
+
+fn run(probe: &Path, envs: &[(&str, &str)]) -> Output {
+    Command::new(probe)
+        .env_clear()
+        .env("LD_PRELOAD", library())
+        .env("PATH", "/usr/bin:/bin")
+        .env("AWF_ONE_SHOT_TOKENS", "GITHUB_TOKEN:required,NPM_TOKEN")
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_required_token() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let Some(probe) = compile_probe(&scratch_dir("required"), "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let output = run(&probe, &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(78), "{}", stderr);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert!(
+        stderr
+            .contains("AUDIT event=required_missing token=GITHUB_TOKEN severity=high action=exit"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(
+            "Required token(s) GITHUB_TOKEN not set; exiting with status 78 \
+             (AWF_ONE_SHOT_REQUIRED_ACTION=log to continue)"
+        ),
+        "{}",
+        stderr
+    );
+
+    // The child finds the token scrubbed, but its parent already checked
+    let output = run(&probe, &[("GITHUB_TOKEN", "ghp_required")]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "ran\nread ghp_required\nchild ran\n"
+    );
+    assert!(!stderr.contains("required_missing"), "{}", stderr);
+
+    let output = run(&probe, &[("AWF_ONE_SHOT_REQUIRED_ACTION", "log")]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "ran\nread (null)\nchild ran\n"
+    );
+    assert!(
+        stderr.contains("event=required_missing token=GITHUB_TOKEN severity=high action=logged"),
+        "{}",
+        stderr
+    );
+
+    let output = run(&probe, &[("AWF_ONE_SHOT_MODE", "observe")]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(
+        stderr.contains("event=observed token=GITHUB_TOKEN would=exit reason=required_missing"),
+        "{}",
+        stderr
+    );
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
@@ -0,0 +1,86 @@
+//! The Node.js and Python adapters re-expose eagerly scrubbed tokens
+//!
+//! Each interpreter runs with the library preloaded, AWF_ONE_SHOT_EAGER set
+//! and the adapter of runtimes/ loaded, and reports what its environment
+//! object shows for a token that may be read once. The tests are skipped
+//! without `node` or `python3`.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::library;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const NODE_PROBE: &str = r#"
+const env = process.env;
+const copied = 'GITHUB_TOKEN' in { ...env } || Object.keys(env).includes('GITHUB_TOKEN');
+require('child_process').execSync('true');
+console.log('GITHUB_TOKEN' in env, copied, env.GITHUB_TOKEN, env.GITHUB_TOKEN);
+"#;
+
+const PYTHON_PROBE: &str = r#"
+import os, subprocess
+env = os.environ
+copied = "GITHUB_TOKEN" in dict(env) or "GITHUB_TOKEN" in list(env)
+subprocess.run(["true"], check=True)
+print("GITHUB_TOKEN" in env, copied, env.get("GITHUB_TOKEN"), os.getenv("GITHUB_TOKEN"))
+"#;
+
+fn runtimes() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("runtimes")
+}
+
+/// Run `program` with the library preloaded, or None if it is not installed
+fn run(program: &str, args: &[&str], envs: &[(&str, String)]) -> Option<Output> {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    Command::new(program)
+        .args(args)
+        .env_clear()
+        .env("LD_PRELOAD", library)
+        .env("PATH", "/usr/local/bin:/usr/bin:/bin")
+        .env("AWF_ONE_SHOT_EAGER", "1")
+        .env("AWF_ONE_SHOT_TOKENS", "GITHUB_TOKEN:max_reads=1")
+        .env("GITHUB_TOKEN", "ghp_runtime")
+        .envs(envs.iter().map(|(name, value)| (name, value)))
+        .output()
+        .ok()
+}
+
+#[test]
+fn test_node_adapter() {
+    let shim = runtimes().join("node/one-shot-token.js");
+    let options = format!("--require {}", shim.display());
+    let Some(output) = run("node", &["-e", NODE_PROBE], &[("NODE_OPTIONS", options)]) else {
+        eprintln!("no node, skipping");
+        return;
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    // Listed and read once; neither copying nor spawning spends the read
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "true false ghp_runtime undefined\n",
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_python_adapter() {
+    let path = runtimes().join("python").display().to_string();
+    let Some(output) = run("python3", &["-c", PYTHON_PROBE], &[("PYTHONPATH", path)]) else {
+        eprintln!("no python3, skipping");
+        return;
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "True False ghp_runtime None\n",
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,117 @@
+//! The step signal leaves the program's own handling of it alone
+//! (AWF_ONE_SHOT_STEP_SIGNAL and AWF_ONE_SHOT_SIGNAL_CHAIN)
+//!
+//! A small C program reads a token and sends itself SIGTERM, the step
+//! signal. A helper library preloaded after the library installs a SIGTERM
+//! handler from its constructor, which the dynamic linker runs before the
+//! library's own. Needs a C compiler (`cc`); the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Command, Output};
+
+const HELPER: &str = r#"
+#include <signal.h>
+#include <string.h>
+#include <unistd.h>
+
+static void on_term(int signum) {
+    (void)signum;
+    write(1, "handled\n", 8);
+}
+
+__attribute__((constructor)) static void install(void) {
+    struct sigaction action;
+    memset(&action, 0, sizeof(action));
+    action.sa_handler = on_term;
+    sigaction(SIGTERM, &action, NULL);
+}
+"#;
+
+const PROBE: &str = r#"
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <unistd.h>
+
+int main(void) {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    const char *value = getenv("GITHUB_TOKEN");
+    printf("read=%s\n", value ? value : "(null)");
+    kill(getpid(), SIGTERM);
+    for (int i = 0; i < 100 && value && value[0]; i++) {
+        usleep(10000);
+    }
+    printf("wiped=%d\n", value && !value[0]);
+    return 0;
+}
+"#;
+
+/// Run the probe with SIGTERM as the step signal, preloading `helper` after
+/// the library if given
+fn run(probe: &Path, helper: Option<&Path>, envs: &[(&str, &str)]) -> Output {
+    let mut preload = library().display().to_string();
+    if let Some(helper) = helper {
+        preload = format!("{}:{}", preload, helper.display());
+    }
+    Command::new(probe)
+        .env_clear()
+        .env("LD_PRELOAD", preload)
+        .env("GITHUB_TOKEN", "ghp_term")
+        .env("AWF_STEP_ID", "build")
+        .env("AWF_ONE_SHOT_STEP_SIGNAL", "SIGTERM")
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_step_signal_keeps_program_handling() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("signal-chain");
+    let Some(helper) = compile_probe(&dir, "libterm.so", HELPER, &["-shared", "-fPIC"]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+    let probe = compile_probe(&dir, "probe", PROBE, &[]).unwrap();
+    let step_changed = "event=step_changed token=* from=build to=build via=signal wiped=1";
+
+    // Left at its default action, SIGTERM still ends the process, after the
+    // step
+    let output = run(&probe, None, &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.signal(), Some(libc::SIGTERM), "{}", stderr);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "read=ghp_term\n");
+    assert!(stderr.contains(step_changed), "{}", stderr);
+
+    // The program's handler is kept and the process goes on as before
+    let output = run(&probe, Some(&helper), &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "read=ghp_term\nhandled\nwiped=0\n"
+    );
+    assert!(
+        stderr.contains("Signal 15 already has a handler"),
+        "{}",
+        stderr
+    );
+
+    // Chained, both the step ends and the program's handler runs
+    let output = run(&probe, Some(&helper), &[("AWF_ONE_SHOT_SIGNAL_CHAIN", "1")]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "read=ghp_term\nhandled\nwiped=1\n"
+    );
+    assert!(stderr.contains(step_changed), "{}", stderr);
+    let _ = std::fs::remove_dir_all(&dir);
+}
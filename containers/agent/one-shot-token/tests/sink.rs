@@ -0,0 +1,112 @@
+//! Reads are refused while the event socket takes no events
+//!
+//! With AWF_ONE_SHOT_SINK_FAILURE=closed, a small C program reads the token
+//! while no supervisor listens on the event socket, and again once the test
+//! has bound it. Needs a C compiler (`cc`); the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixDatagram;
+use std::process::{Command, Stdio};
+
+const PROBE: &str = r#"
+#include <stdio.h>
+#include <stdlib.h>
+
+static void show(void) {
+    const char *token = getenv("GITHUB_TOKEN");
+    printf("%s\n", token ? token : "(null)");
+    fflush(stdout);
+}
+
+int main(void) {
+    char line[8];
+    show();
+    show();
+    /* The supervisor is listening once a line arrives */
+    if (!fgets(line, sizeof line, stdin)) {
+        return 1;
+    }
+    show();
+    show();
+    return 0;
+}
+"#;
+
+#[test]
+fn test_fail_closed_event_socket() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("sink");
+    let Some(probe) = compile_probe(&dir, "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+    let socket = dir.join("events.sock");
+
+    let mut child = Command::new(&probe)
+        .env_clear()
+        .env("LD_PRELOAD", &library)
+        .env("GITHUB_TOKEN", "ghp_sinksecret")
+        .env("AWF_ONE_SHOT_EVENT_SOCKET", &socket)
+        .env("AWF_ONE_SHOT_SINK_FAILURE", "closed")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut next_line = || {
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        line
+    };
+    // The first read is served; its event finds no supervisor
+    assert_eq!(next_line(), "ghp_sinksecret\n");
+    assert_eq!(next_line(), "(null)\n");
+
+    let supervisor = UnixDatagram::bind(&socket).unwrap();
+    child.stdin.take().unwrap().write_all(b"go\n").unwrap();
+    // The refused read's event delivers the kept ones, so the next read is
+    // served again
+    assert_eq!(next_line(), "(null)\n");
+    assert_eq!(next_line(), "ghp_sinksecret\n");
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    supervisor.set_nonblocking(true).unwrap();
+    let mut events = Vec::new();
+    let mut buf = [0u8; 4096];
+    while let Ok(len) = supervisor.recv(&mut buf) {
+        let datagram = String::from_utf8_lossy(&buf[..len]).into_owned();
+        let event = datagram
+            .split("\"event\":\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap_or_default()
+            .to_string();
+        events.push(event);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("event=sink_failed token=* sink=events policy=closed"));
+    assert!(stderr.contains("event=sink_recovered token=* sink=events dropped=0"));
+    // Every event arrives, in order
+    assert_eq!(
+        events,
+        [
+            "unset_verification",
+            "sink_failed",
+            "access",
+            "sink_denied",
+            "sink_denied",
+            "sink_recovered",
+            "access"
+        ]
+    );
+}
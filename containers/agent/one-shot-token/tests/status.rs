@@ -0,0 +1,85 @@
+//! awf_token_list_protected reports the protection status of a preloaded
+//! library
+//!
+//! A small C program reads tokens and prints the status, looked up by its
+//! symbol version. Needs a C compiler (`cc`); the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#define _GNU_SOURCE
+#include <dlfcn.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <sys/types.h>
+
+typedef ssize_t (*status_fn)(char *, size_t);
+
+int main(void) {
+    status_fn status = (status_fn)dlvsym(RTLD_DEFAULT, "awf_token_list_protected", "AWF_1.2");
+    if (!status) {
+        puts("unresolved");
+        return 1;
+    }
+    getenv("GITHUB_TOKEN");
+    getenv("GH_TOKEN");
+    getenv("NPM_TOKEN");
+    char buf[4096];
+    ssize_t len = status(buf, sizeof(buf));
+    if (len < 0 || (size_t)len >= sizeof(buf)) {
+        puts("truncated");
+        return 1;
+    }
+    puts(buf);
+    return 0;
+}
+"#;
+
+#[test]
+fn test_protection_status() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("status");
+    let Some(probe) = compile_probe(&dir, "probe", PROBE, &["-ldl"]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let output = Command::new(&probe)
+        .env_clear()
+        .env("LD_PRELOAD", &library)
+        .env("AWF_ONE_SHOT_POLICY_FILE", "")
+        .env(
+            "AWF_ONE_SHOT_TOKENS",
+            "GITHUB_TOKEN:max_reads=2,OPENAI_API_KEY",
+        )
+        .env("AWF_ONE_SHOT_TOKEN_ALIASES", "GH_TOKEN=GITHUB_TOKEN")
+        .env("AWF_ONE_SHOT_DENY_TOKENS", "NPM_TOKEN")
+        .env("GITHUB_TOKEN", "ghp_status")
+        .env("OPENAI_API_KEY", "sk-status")
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let status = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
+        status.contains(concat!(
+            "\"mode\":\"enforce\",\"disabled\":false,\"tokens\":[",
+            "{\"name\":\"GITHUB_TOKEN\",\"policy\":\"cache\",\"source\":\"env\",",
+            "\"setting\":\"AWF_ONE_SHOT_TOKENS\",\"aliases\":[\"GH_TOKEN\"],",
+            "\"status\":\"exhausted\",\"reads\":2,\"max_reads\":2,\"wiped\":null},",
+            "{\"name\":\"OPENAI_API_KEY\",\"policy\":\"cache\",\"source\":\"env\",",
+            "\"setting\":\"AWF_ONE_SHOT_TOKENS\",\"aliases\":[],",
+            "\"status\":\"pending\",\"reads\":0,\"max_reads\":null,\"wiped\":null}],",
+            "\"denied\":[\"NPM_TOKEN\"]}"
+        )),
+        "{}",
+        status
+    );
+}
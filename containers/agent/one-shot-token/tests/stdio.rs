@@ -0,0 +1,74 @@
+//! Streams opened on /proc/self/environ carry the redacted copy
+//!
+//! A small C program reads its environ file through one stdio function per
+//! run, before anything has called getenv, and reports whether the token's
+//! value was in it. Needs a C compiler (`cc`); the test is skipped without
+//! one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#define _LARGEFILE64_SOURCE
+#include <stdio.h>
+#include <string.h>
+
+int main(int argc, char **argv) {
+    const char *path = argc > 1 ? argv[1] : "";
+    FILE *stream = NULL;
+    if (!strcmp(path, "fopen")) {
+        stream = fopen("/proc/self/environ", "r");
+    } else if (!strcmp(path, "fopen64")) {
+        stream = fopen64("/proc/self/environ", "r");
+    } else if (!strcmp(path, "freopen")) {
+        stream = freopen("/proc/self/environ", "r", stdin);
+    } else if (!strcmp(path, "freopen64")) {
+        stream = freopen64("/proc/self/environ", "r", stdin);
+    }
+    if (!stream) {
+        puts("unopened");
+        return 1;
+    }
+    static char content[65536];
+    size_t len = fread(content, 1, sizeof content - 1, stream);
+    int exposed = 0, listed = 0;
+    for (size_t i = 0; i < len; i += strlen(content + i) + 1) {
+        exposed |= strncmp(content + i, "GITHUB_TOKEN=ghp_", 17) == 0;
+        listed |= strncmp(content + i, "GITHUB_TOKEN=", 13) == 0;
+    }
+    printf("%s\n", exposed ? "exposed" : listed ? "redacted" : "missing");
+    return 0;
+}
+"#;
+
+#[test]
+fn test_stdio_environ() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let Some(probe) = compile_probe(&scratch_dir("stdio"), "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    for function in ["fopen", "fopen64", "freopen", "freopen64"] {
+        let output = Command::new(&probe)
+            .arg(function)
+            .env_clear()
+            .env("LD_PRELOAD", &library)
+            .env("GITHUB_TOKEN", "ghp_test")
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "redacted\n",
+            "{}: {}",
+            function,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
@@ -0,0 +1,101 @@
+//! The host ends a step without waiting for the next read
+//! (AWF_ONE_SHOT_STEP_SIGNAL and AWF_ONE_SHOT_STEP_WATCH)
+//!
+//! A small C program reads a token, then ends the step the way the host
+//! would (by signalling itself or writing the step file) and waits for the
+//! value it holds to be zeroized, without reading the token again. Needs a C
+//! compiler (`cc`); the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::path::Path;
+use std::process::Command;
+
+const PROBE: &str = r#"
+#include <fcntl.h>
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+int main(int argc, char **argv) {
+    const char *value = getenv("GITHUB_TOKEN");
+    printf("read=%s\n", value ? value : "(null)");
+    if (argc > 2) {
+        int fd = open(argv[2], O_WRONLY | O_CREAT | O_TRUNC, 0600);
+        write(fd, "post-comment\n", 13);
+        close(fd);
+    } else {
+        kill(getpid(), SIGRTMIN + 1);
+    }
+    for (int i = 0; i < 200 && value && value[0]; i++) {
+        usleep(10000);
+    }
+    printf("wiped=%d\n", value && !value[0]);
+    return 0;
+}
+"#;
+
+#[test]
+fn test_step_end() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("step");
+    let Some(probe) = compile_probe(&dir, "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+    let step_file = dir.join("step");
+    std::fs::write(&step_file, "build\n").unwrap();
+
+    let run = |args: &[&Path], settings: &[(&str, &str)]| {
+        let output = Command::new(&probe)
+            .args(args)
+            .env_clear()
+            .env("LD_PRELOAD", &library)
+            .env("GITHUB_TOKEN", "ghp_step")
+            .env("AWF_STEP_ID", "build")
+            .envs(settings.iter().copied())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        (
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    };
+
+    let (stdout, stderr) = run(&[], &[("AWF_ONE_SHOT_STEP_SIGNAL", "SIGRTMIN+1")]);
+    assert_eq!(stdout, "read=ghp_step\nwiped=1\n", "{}", stderr);
+    assert!(
+        stderr.contains("event=step_changed token=* from=build to=build via=signal wiped=1"),
+        "{}",
+        stderr
+    );
+
+    let step = step_file.to_str().unwrap();
+    let args = [Path::new("file"), step_file.as_path()];
+    let (stdout, stderr) = run(
+        &args,
+        &[
+            ("AWF_ONE_SHOT_STEP_FILE", step),
+            ("AWF_ONE_SHOT_STEP_WATCH", "1"),
+        ],
+    );
+    assert_eq!(stdout, "read=ghp_step\nwiped=1\n", "{}", stderr);
+    assert!(
+        stderr.contains("event=step_changed token=* from=build to=post-comment via=file wiped=1"),
+        "{}",
+        stderr
+    );
+
+    // Without the watch the file is only checked on the next read
+    std::fs::write(&step_file, "build\n").unwrap();
+    let (stdout, _) = run(&args, &[("AWF_ONE_SHOT_STEP_FILE", step)]);
+    assert_eq!(stdout, "read=ghp_step\nwiped=0\n");
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,79 @@
+//! A pointer to a strict token faults once its grace period has passed
+//! (AWF_ONE_SHOT_STRICT_GRACE)
+//!
+//! A small C program reads a strict token, keeps the pointer past the grace
+//! period and reads through it again. Needs a C compiler (`cc`); the test is
+//! skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command;
+
+const PROBE: &str = r#"
+#include <stdio.h>
+#include <stdlib.h>
+#include <unistd.h>
+
+int main(void) {
+    const char *value = getenv("GITHUB_TOKEN");
+    printf("first=%s\n", value ? value : "(null)");
+    fflush(stdout);
+    sleep(2);
+    const char *again = getenv("GITHUB_TOKEN");
+    printf("again=%s\n", again ? again : "(null)");
+    fflush(stdout);
+    printf("kept=%s\n", value);
+    return 0;
+}
+"#;
+
+#[test]
+fn test_strict_grace() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let dir = scratch_dir("grace");
+    let Some(probe) = compile_probe(&dir, "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let run = |grace: &str| {
+        Command::new(&probe)
+            .env_clear()
+            .env("LD_PRELOAD", &library)
+            .env("GITHUB_TOKEN", "ghp_grace")
+            .env("AWF_ONE_SHOT_TOKENS", "GITHUB_TOKEN:strict")
+            .env("AWF_ONE_SHOT_STRICT_GRACE", grace)
+            .output()
+            .unwrap()
+    };
+
+    let revoked = run("1");
+    let stdout = String::from_utf8_lossy(&revoked.stdout);
+    let stderr = String::from_utf8_lossy(&revoked.stderr);
+    assert_eq!(revoked.status.signal(), Some(libc::SIGSEGV), "{}", stderr);
+    assert_eq!(stdout, "first=ghp_grace\nagain=(null)\n");
+    assert!(
+        stderr.contains("event=strict_invalidated token=GITHUB_TOKEN action=revoked grace=1"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("event=strict_fault token=GITHUB_TOKEN addr=0x"),
+        "{}",
+        stderr
+    );
+
+    // Without a grace period the pointer stays readable
+    let kept = run("0");
+    assert!(kept.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&kept.stdout),
+        "first=ghp_grace\nagain=(null)\nkept=\n"
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
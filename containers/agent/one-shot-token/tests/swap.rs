@@ -0,0 +1,98 @@
+//! awf-token-swap takes placeholders from the library's users and swaps them
+//! for the proxy, only for allowed hosts
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// A swap service allowing GITHUB_TOKEN for api.github.com, killed when
+/// dropped
+struct Swap {
+    child: Child,
+    socket: PathBuf,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Drop for Swap {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+impl Swap {
+    fn start(name: &str) -> Swap {
+        let socket = std::env::temp_dir().join(format!(
+            "awf-swap-test-{}-{}.sock",
+            std::process::id(),
+            name
+        ));
+        let mut child = Command::new(env!("CARGO_BIN_EXE_awf-token-swap"))
+            .arg("--socket")
+            .arg(&socket)
+            .args(["--allow", "GITHUB_TOKEN=api.github.com"])
+            .env_remove("LD_PRELOAD")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !socket.exists() {
+            assert!(Instant::now() < deadline, "socket never appeared");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        Swap {
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            child,
+            socket,
+        }
+    }
+
+    fn register(&self, line: &str) -> String {
+        let mut stream = UnixStream::connect(&self.socket).unwrap();
+        stream.write_all(line.as_bytes()).unwrap();
+        let mut answer = String::new();
+        stream.read_to_string(&mut answer).unwrap();
+        answer
+    }
+
+    fn lookup(&mut self, line: &str) -> String {
+        self.stdin.write_all(line.as_bytes()).unwrap();
+        let mut answer = String::new();
+        self.stdout.read_line(&mut answer).unwrap();
+        answer
+    }
+}
+
+#[test]
+fn test_register_and_swap() {
+    let mut swap = Swap::start("swap");
+    assert_eq!(
+        swap.register("REGISTER GITHUB_TOKEN awfp_0123abcd ghp_swaptest\n"),
+        "OK registered\n"
+    );
+    assert_eq!(
+        swap.register("REGISTER GITHUB_TOKEN awfp_0123abcd ghp_other\n"),
+        "ERR duplicate_placeholder\n"
+    );
+    assert_eq!(
+        swap.lookup("api.github.com:443 awfp_0123abcd\n"),
+        "OK ghp_swaptest\n"
+    );
+    assert_eq!(
+        swap.lookup("example.com awfp_0123abcd\n"),
+        "ERR host_not_allowed\n"
+    );
+    assert_eq!(
+        swap.lookup("api.github.com awfp_ffff\n"),
+        "ERR unknown_placeholder\n"
+    );
+}
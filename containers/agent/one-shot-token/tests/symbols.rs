@@ -0,0 +1,111 @@
+//! Every path a program can take to getenv reaches the interposers
+//!
+//! A small C program is compiled and run with the library preloaded. It
+//! reads GITHUB_TOKEN through one resolution path per run and reports the
+//! value it got and whether the token is still in environ. Needs a C
+//! compiler (`cc`); the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#define _GNU_SOURCE
+#include <dlfcn.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#if defined(__x86_64__)
+#define GLIBC_BASE "GLIBC_2.2.5"
+/* What a binary built against glibc < 2.17 imports */
+__asm__(".symver old_secure_getenv, __secure_getenv@GLIBC_2.2.5");
+extern char *old_secure_getenv(const char *name);
+#elif defined(__aarch64__)
+#define GLIBC_BASE "GLIBC_2.17"
+#elif defined(__i386__)
+#define GLIBC_BASE "GLIBC_2.0"
+__asm__(".symver old_secure_getenv, __secure_getenv@GLIBC_2.0");
+extern char *old_secure_getenv(const char *name);
+#endif
+
+extern char **environ;
+typedef char *(*getenv_fn)(const char *);
+
+int main(int argc, char **argv) {
+    const char *path = argc > 1 ? argv[1] : "";
+    getenv_fn fn = NULL;
+    if (!strcmp(path, "getenv")) {
+        fn = getenv;
+    } else if (!strcmp(path, "secure_getenv")) {
+        fn = secure_getenv;
+    } else if (!strcmp(path, "dlsym")) {
+        fn = (getenv_fn)dlsym(RTLD_DEFAULT, "getenv");
+    } else if (!strcmp(path, "dlvsym")) {
+        fn = (getenv_fn)dlvsym(RTLD_DEFAULT, "getenv", GLIBC_BASE);
+    } else if (!strcmp(path, "dlsym __secure_getenv")) {
+        fn = (getenv_fn)dlsym(RTLD_DEFAULT, "__secure_getenv");
+    } else if (!strcmp(path, "dlvsym __secure_getenv")) {
+        fn = (getenv_fn)dlvsym(RTLD_DEFAULT, "__secure_getenv", GLIBC_BASE);
+#if defined(__x86_64__) || defined(__i386__)
+    } else if (!strcmp(path, "old __secure_getenv")) {
+        fn = old_secure_getenv;
+#endif
+    }
+    if (!fn) {
+        puts("unresolved");
+        return 1;
+    }
+    const char *value = fn("GITHUB_TOKEN");
+    int exposed = 0;
+    for (char **entry = environ; *entry; entry++) {
+        if (!strncmp(*entry, "GITHUB_TOKEN=", 13)) {
+            exposed = 1;
+        }
+    }
+    printf("%s %s\n", value ? value : "(null)", exposed ? "exposed" : "scrubbed");
+    return 0;
+}
+"#;
+
+#[test]
+fn test_resolution_paths() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let Some(probe) = compile_probe(&scratch_dir("symbols"), "probe", PROBE, &["-ldl"]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let mut paths = vec![
+        "getenv",
+        "secure_getenv",
+        "dlsym",
+        "dlvsym",
+        "dlsym __secure_getenv",
+        "dlvsym __secure_getenv",
+    ];
+    if cfg!(any(target_arch = "x86_64", target_arch = "x86")) {
+        paths.push("old __secure_getenv");
+    }
+    for path in paths {
+        let output = Command::new(&probe)
+            .arg(path)
+            .env_clear()
+            .env("LD_PRELOAD", &library)
+            .env("GITHUB_TOKEN", "ghp_test")
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "ghp_test scrubbed\n",
+            "{}: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
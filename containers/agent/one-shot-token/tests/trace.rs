@@ -0,0 +1,67 @@
+//! getenv decisions are traced with the rule, policy and verdict
+//!
+//! A small C program reads the names it is given, and its trace lines are
+//! checked. Needs a C compiler (`cc`); the test is skipped without one.
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::{compile_probe, library, scratch_dir};
+use std::process::Command;
+
+const PROBE: &str = r#"
+#include <stdlib.h>
+
+int main(int argc, char **argv) {
+    for (int i = 1; i < argc; i++) {
+        getenv(argv[i]);
+    }
+    return 0;
+}
+"#;
+
+#[test]
+fn test_trace() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let Some(probe) = compile_probe(&scratch_dir("trace"), "probe", PROBE, &[]) else {
+        eprintln!("no C compiler, skipping");
+        return;
+    };
+
+    let output = Command::new(&probe)
+        .args(["HOME", "github_token", "GH_TOKEN", "NPM_TOKEN", "NPM_TOKEN"])
+        .args(["ACTIONS_RUNTIME_TOKEN"])
+        .env_clear()
+        .env("LD_PRELOAD", &library)
+        .env("AWF_ONE_SHOT_TRACE", "1")
+        .env("GITHUB_TOKEN", "ghp_test")
+        .env("NPM_TOKEN", "npm_test")
+        .env("AWF_ONE_SHOT_TOKEN_ALIASES", "GH_TOKEN=GITHUB_TOKEN")
+        .env("AWF_ONE_SHOT_EXTRA_TOKENS", "NPM_TOKEN:max_reads=1")
+        .env("AWF_ONE_SHOT_DENY_TOKENS", "ACTIONS_RUNTIME_TOKEN")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let traces: Vec<&str> = stderr
+        .lines()
+        .filter_map(|line| line.split_once("Trace ").map(|(_, trace)| trace))
+        .collect();
+    assert_eq!(
+        traces,
+        [
+            "github_token: rule=none reason=case_differs_from:GITHUB_TOKEN policy=- \
+             verdict=pass_through",
+            "GH_TOKEN: rule=alias:GH_TOKEN=GITHUB_TOKEN policy=cache verdict=served first=true",
+            "NPM_TOKEN: rule=token:NPM_TOKEN policy=cache,max_reads=1 verdict=served first=true",
+            "NPM_TOKEN: rule=token:NPM_TOKEN policy=cache,max_reads=1 verdict=null \
+             reason=max_reads",
+            "ACTIONS_RUNTIME_TOKEN: rule=deny:ACTIONS_RUNTIME_TOKEN policy=- verdict=denied",
+        ],
+        "{}",
+        stderr
+    );
+    let _ = std::fs::remove_dir_all(probe.parent().unwrap());
+}
@@ -0,0 +1,85 @@
+//! AWF_ONE_SHOT_VERIFY reports the tokens, settings, interposers and
+//! hardening of a process the library is loaded into
+
+#![cfg(all(target_os = "linux", target_env = "gnu", feature = "preload"))]
+
+mod common;
+
+use common::library;
+use std::process::Command;
+
+#[test]
+fn test_verify_report() {
+    let library = library();
+    assert!(library.exists(), "{} not built", library.display());
+    let report = std::env::temp_dir().join(format!("one-shot-token-verify-{}", std::process::id()));
+    let _ = std::fs::remove_file(&report);
+    let config = report.with_extension("conf");
+    std::fs::write(&config, "AWF_ONE_SHOT_EXTRA_TOKENS=NPM_TOKEN:strict\n").unwrap();
+
+    let status = Command::new("true")
+        .env_clear()
+        .env("LD_PRELOAD", &library)
+        .env("AWF_ONE_SHOT_VERIFY", &report)
+        .env("AWF_ONE_SHOT_TOKENS", "GITHUB_TOKEN,NPM_TOKEN")
+        .env("AWF_ONE_SHOT_EXTRA_TOKENS", "IGNORED_TOKEN")
+        .env("AWF_ONE_SHOT_CONFIG", &config)
+        .env("AWF_ONE_SHOT_NOCORE", "1")
+        .env("GITHUB_TOKEN", "ghp_test")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let contents = std::fs::read_to_string(&report).unwrap();
+    let _ = std::fs::remove_file(&report);
+    let _ = std::fs::remove_file(&config);
+
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 1, "{}", contents);
+    let line = lines[0];
+    assert!(line.starts_with("{\"version\":"), "{}", line);
+    assert!(
+        line.contains("\"mode\":\"enforce\",\"disabled\":false,\"ok\":true,"),
+        "{}",
+        line
+    );
+    // The configuration file's extra list replaces the environment's
+    assert!(
+        line.contains(concat!(
+            "\"tokens\":[{\"name\":\"GITHUB_TOKEN\",\"policy\":\"cache\",\"present\":true,",
+            "\"source\":\"env\",\"setting\":\"AWF_ONE_SHOT_TOKENS\"},",
+            "{\"name\":\"NPM_TOKEN\",\"policy\":\"strict\",\"present\":false,",
+            "\"source\":\"config_file\",\"setting\":\"AWF_ONE_SHOT_EXTRA_TOKENS\"}]"
+        )),
+        "{}",
+        line
+    );
+    assert!(
+        line.contains(concat!(
+            "\"settings\":[{\"name\":\"AWF_ONE_SHOT_TOKENS\",\"source\":\"env\"},",
+            "{\"name\":\"AWF_ONE_SHOT_EXTRA_TOKENS\",\"source\":\"config_file\"},"
+        )),
+        "{}",
+        line
+    );
+    assert!(
+        line.contains("{\"name\":\"AWF_ONE_SHOT_NOCORE\",\"source\":\"env\"}"),
+        "{}",
+        line
+    );
+    assert!(
+        line.contains("{\"name\":\"getenv\",\"resolved\":true,\"interposed\":true}"),
+        "{}",
+        line
+    );
+    assert!(!line.contains("\"interposed\":false"), "{}", line);
+    assert!(
+        line.contains("{\"name\":\"nocore\",\"requested\":true,\"active\":true}"),
+        "{}",
+        line
+    );
+    assert!(
+        line.contains("{\"name\":\"seccomp\",\"requested\":false,"),
+        "{}",
+        line
+    );
+}
@@ -0,0 +1,236 @@
+//! awf-inject: run a Windows command with the one-shot-token DLL loaded
+//!
+//! Usage: awf-inject [--dll PATH] [--] COMMAND [ARGS]...
+//!
+//! The Windows counterpart of starting a command with LD_PRELOAD set. The
+//! command is created suspended, the DLL (by default `one_shot_token.dll`
+//! next to awf-inject) is loaded into it (see the library's inject module),
+//! and only then does the command start running, so it cannot read a
+//! protected variable before the hooks are in place. The DLL injects itself
+//! into the command's own children.
+//!
+//! Exit status: the command's, 1 if the command cannot be started or the DLL
+//! cannot be loaded into it (the command is then terminated before it ran),
+//! 2 on usage errors.
+
+#[cfg(windows)]
+#[allow(dead_code)]
+#[path = "../inject.rs"]
+mod inject;
+#[cfg(windows)]
+#[allow(dead_code)]
+#[path = "../sys.rs"]
+mod sys;
+
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: awf-inject [--dll PATH] [--] COMMAND [ARGS]...";
+
+/// Exit status when the command cannot be started or injected
+#[cfg(windows)]
+const EXIT_FAILED: i32 = 1;
+/// Exit status on usage errors
+const EXIT_USAGE: i32 = 2;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Options {
+    /// The DLL to load; `None` for the one next to awf-inject
+    dll: Option<PathBuf>,
+    command: Vec<String>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut dll = None;
+    let mut args = args.into_iter();
+    let mut command = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dll" => dll = Some(PathBuf::from(args.next().ok_or("--dll needs a value")?)),
+            "--" => break,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => {
+                command.push(arg);
+                break;
+            }
+        }
+    }
+    command.extend(args);
+    if command.is_empty() {
+        return Err("no command given".to_string());
+    }
+    Ok(Options { dll, command })
+}
+
+/// `arg` quoted for a command line, as the MSVC runtime splits it
+///
+/// Backslashes are literal unless they precede a quote, so only those and
+/// the ones before the closing quote are doubled.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '\x0b', '"']) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// The command line starting `command`
+#[cfg_attr(not(windows), allow(dead_code))]
+fn command_line(command: &[String]) -> String {
+    command
+        .iter()
+        .map(|arg| quote_arg(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(windows)]
+fn run(options: Options) -> i32 {
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr::null_mut;
+    use sys::*;
+
+    let dll = match options.dll {
+        Some(dll) => dll,
+        None => match std::env::current_exe() {
+            Ok(exe) => exe.with_file_name("one_shot_token.dll"),
+            Err(err) => {
+                eprintln!("awf-inject: cannot locate the DLL: {}", err);
+                return EXIT_FAILED;
+            }
+        },
+    };
+    // LoadLibraryW in the child resolves a relative path against its own
+    // search path, not this directory
+    let dll = std::path::absolute(&dll).unwrap_or(dll);
+    if !dll.is_file() {
+        eprintln!("awf-inject: {}: no such DLL", dll.display());
+        return EXIT_FAILED;
+    }
+    let dll_wide: Vec<u16> = dll.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut line = wide(&command_line(&options.command));
+
+    // SAFETY: the structures are plain data, sized as the calls expect, and
+    // every handle returned is closed once
+    unsafe {
+        let mut startup: STARTUPINFOW = std::mem::zeroed();
+        startup.cb = std::mem::size_of::<STARTUPINFOW>() as u32;
+        let mut information: PROCESS_INFORMATION = std::mem::zeroed();
+        let created = CreateProcessW(
+            std::ptr::null(),
+            line.as_mut_ptr(),
+            null_mut(),
+            null_mut(),
+            1,
+            CREATE_SUSPENDED,
+            null_mut(),
+            std::ptr::null(),
+            (&mut startup as *mut STARTUPINFOW).cast(),
+            &mut information,
+        );
+        if created == 0 {
+            eprintln!(
+                "awf-inject: {}: cannot start (error {})",
+                options.command[0],
+                GetLastError()
+            );
+            return EXIT_FAILED;
+        }
+        if let Err(code) = inject::load_into(information.hProcess, &dll_wide) {
+            eprintln!(
+                "awf-inject: cannot load {} into {} (error {})",
+                dll.display(),
+                options.command[0],
+                code
+            );
+            TerminateProcess(information.hProcess, EXIT_FAILED as u32);
+            CloseHandle(information.hThread);
+            CloseHandle(information.hProcess);
+            return EXIT_FAILED;
+        }
+        ResumeThread(information.hThread);
+        CloseHandle(information.hThread);
+        WaitForSingleObject(information.hProcess, INFINITE);
+        let mut code = EXIT_FAILED as u32;
+        GetExitCodeProcess(information.hProcess, &mut code);
+        CloseHandle(information.hProcess);
+        code as i32
+    }
+}
+
+#[cfg(not(windows))]
+fn run(_options: Options) -> i32 {
+    eprintln!("awf-inject: only supported on Windows");
+    EXIT_USAGE
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if matches!(args.first().map(String::as_str), Some("-h" | "--help")) {
+        println!("{}", USAGE);
+        return;
+    }
+    let options = parse_args(args).unwrap_or_else(|err| {
+        eprintln!("awf-inject: {}\n{}", err, USAGE);
+        std::process::exit(EXIT_USAGE);
+    });
+    std::process::exit(run(options));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse_args(args(&["--dll", "C:\\awf\\ost.dll", "gh", "--version"])),
+            Ok(Options {
+                dll: Some(PathBuf::from("C:\\awf\\ost.dll")),
+                command: args(&["gh", "--version"]),
+            })
+        );
+        assert_eq!(
+            parse_args(args(&["--", "--odd", "-x"])).unwrap().command,
+            args(&["--odd", "-x"])
+        );
+        assert!(parse_args(args(&["--dll"])).is_err());
+        assert!(parse_args(args(&["--dll", "x.dll"])).is_err());
+        assert!(parse_args(args(&["--verbose", "gh"])).is_err());
+    }
+
+    #[test]
+    fn test_quote_arg() {
+        assert_eq!(quote_arg("plain"), "plain");
+        assert_eq!(quote_arg(""), "\"\"");
+        assert_eq!(quote_arg("two words"), "\"two words\"");
+        assert_eq!(quote_arg("C:\\dir\\"), "C:\\dir\\");
+        assert_eq!(quote_arg("C:\\my dir\\"), "\"C:\\my dir\\\\\"");
+        assert_eq!(quote_arg("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quote_arg("a\\\"b"), "\"a\\\\\\\"b\"");
+        assert_eq!(command_line(&args(&["gh", "api", "a b"])), "gh api \"a b\"");
+    }
+}
@@ -0,0 +1,145 @@
+//! Cached values of protected variables
+//!
+//! The hooks return pointers into cached values (`getenv` and `_wgetenv`
+//! hand them to the caller), so values are boxed and never dropped or moved
+//! while the process runs.
+
+/// A protected value, NUL-terminated in both encodings the hooks serve
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Cached {
+    pub(crate) wide: Vec<u16>,
+    /// UTF-8, for the `A` functions and `getenv`; tokens are ASCII in
+    /// practice, where this is also the ANSI code page's encoding
+    pub(crate) narrow: Vec<u8>,
+}
+
+impl Cached {
+    /// Cache `value` (UTF-16, without terminating NUL)
+    fn new(value: &[u16]) -> Self {
+        let mut wide = value.to_vec();
+        wide.push(0);
+        let mut narrow = String::from_utf16_lossy(value).into_bytes();
+        narrow.push(0);
+        Cached { wide, narrow }
+    }
+
+    /// The start of the value, for debug lines
+    pub(crate) fn preview(&self) -> String {
+        let text = String::from_utf16_lossy(&self.wide[..self.wide.len() - 1]);
+        let shown: String = text.chars().take(4).collect();
+        format!("{}...", shown)
+    }
+}
+
+/// Copy `value` (NUL-terminated) into `buffer` as GetEnvironmentVariable does
+///
+/// Returns the length of the value without the NUL, or, if `buffer` is too
+/// small (and then left untouched), the size it needs including the NUL.
+pub(crate) fn copy_out<T: Copy>(value: &[T], buffer: &mut [T]) -> u32 {
+    if buffer.len() < value.len() {
+        return value.len() as u32;
+    }
+    buffer[..value.len()].copy_from_slice(value);
+    (value.len() - 1) as u32
+}
+
+/// The protected names and the values read so far
+pub(crate) struct TokenCache {
+    names: Vec<String>,
+    cached: Vec<(String, Box<Cached>)>,
+}
+
+impl TokenCache {
+    pub(crate) fn new(names: Vec<String>) -> Self {
+        TokenCache {
+            names,
+            cached: Vec::new(),
+        }
+    }
+
+    /// The protected name `name` (UTF-16, without NUL) matches, if any
+    pub(crate) fn protected(&self, name: &[u16]) -> Option<&str> {
+        self.names
+            .iter()
+            .find(|listed| {
+                listed.len() == name.len()
+                    && listed.bytes().zip(name).all(|(byte, &unit)| {
+                        u8::try_from(unit).is_ok_and(|unit| unit.eq_ignore_ascii_case(&byte))
+                    })
+            })
+            .map(String::as_str)
+    }
+
+    /// The value of the protected variable `name`
+    ///
+    /// The first time the variable is found, its value is read with `read`
+    /// and cached, and `scrub` removes it from the environment. Until then a
+    /// variable that is not set is looked up again on every call, so a value
+    /// set later is still protected.
+    pub(crate) fn get(
+        &mut self,
+        name: &str,
+        read: impl FnOnce() -> Option<Vec<u16>>,
+        scrub: impl FnOnce(),
+    ) -> Option<&Cached> {
+        if let Some(index) = self.cached.iter().position(|(cached, _)| cached == name) {
+            return Some(&self.cached[index].1);
+        }
+        let cached = Box::new(Cached::new(&read()?));
+        scrub();
+        self.cached.push((name.to_string(), cached));
+        self.cached.last().map(|(_, cached)| &**cached)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().collect()
+    }
+
+    #[test]
+    fn test_protected() {
+        let cache = TokenCache::new(vec!["GITHUB_TOKEN".to_string()]);
+        assert_eq!(cache.protected(&wide("GITHUB_TOKEN")), Some("GITHUB_TOKEN"));
+        assert_eq!(cache.protected(&wide("github_token")), Some("GITHUB_TOKEN"));
+        assert_eq!(cache.protected(&wide("GITHUB_TOKENS")), None);
+        assert_eq!(cache.protected(&wide("GİTHUB_TOKEN")), None);
+    }
+
+    #[test]
+    fn test_get() {
+        let mut cache = TokenCache::new(vec!["GITHUB_TOKEN".to_string()]);
+        let mut scrubbed = 0;
+        assert_eq!(cache.get("GITHUB_TOKEN", || None, || scrubbed += 1), None);
+        assert_eq!(scrubbed, 0);
+
+        let first = cache
+            .get("GITHUB_TOKEN", || Some(wide("ghp_abc")), || scrubbed += 1)
+            .unwrap() as *const Cached;
+        assert_eq!(scrubbed, 1);
+        // Later reads are served from the cache, at the same address
+        let second = cache
+            .get("GITHUB_TOKEN", || panic!("read again"), || scrubbed += 1)
+            .unwrap();
+        assert_eq!(scrubbed, 1);
+        assert!(std::ptr::eq(first, second));
+        assert_eq!(second.narrow, b"ghp_abc\0");
+        assert_eq!(second.wide, wide("ghp_abc\0"));
+        assert_eq!(second.preview(), "ghp_...");
+    }
+
+    #[test]
+    fn test_copy_out() {
+        let value = b"abc\0";
+        let mut small = [b'x'; 3];
+        assert_eq!(copy_out(value, &mut small), 4);
+        assert_eq!(&small, b"xxx");
+        let mut buffer = [b'x'; 5];
+        assert_eq!(copy_out(value, &mut buffer), 3);
+        assert_eq!(&buffer, b"abc\0x");
+        assert_eq!(copy_out(value, &mut []), 4);
+    }
+}
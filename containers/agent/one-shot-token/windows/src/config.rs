@@ -0,0 +1,65 @@
+//! Protected variable names (AWF_ONE_SHOT_TOKENS)
+
+use crate::detect::preset_tokens;
+
+/// The protected names, and the per-token options that were ignored
+///
+/// Empty entries are skipped and each name is listed once, compared without
+/// regard to ASCII case. Without AWF_ONE_SHOT_TOKENS (or with an empty
+/// value) the Linux library's default presets apply.
+pub(crate) fn protected_names(tokens: Option<&str>) -> (Vec<String>, Vec<String>) {
+    let Some(tokens) = tokens.filter(|tokens| !tokens.trim().is_empty()) else {
+        let names = preset_tokens(None)
+            .into_iter()
+            .map(|name| name.to_string())
+            .collect();
+        return (names, Vec::new());
+    };
+    let mut names: Vec<String> = Vec::new();
+    let mut ignored = Vec::new();
+    for entry in tokens.split(',').map(str::trim) {
+        let (name, options) = entry.split_once(':').unwrap_or((entry, ""));
+        let name = name.trim();
+        if !options.is_empty() {
+            ignored.push(entry.to_string());
+        }
+        if !name.is_empty()
+            && !name.contains('=')
+            && !names.iter().any(|listed| listed.eq_ignore_ascii_case(name))
+        {
+            names.push(name.to_string());
+        }
+    }
+    (names, ignored)
+}
+
+/// Whether AWF_ONE_SHOT_TOKEN_DEBUG enables debug lines ("1" or "true")
+pub(crate) fn debug_enabled(value: Option<&str>) -> bool {
+    value.is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protected_names() {
+        let (names, ignored) = protected_names(None);
+        assert!(names.contains(&"GITHUB_TOKEN".to_string()));
+        assert!(ignored.is_empty());
+        assert_eq!(protected_names(Some(" ")).0, names);
+
+        let (names, ignored) =
+            protected_names(Some("GITHUB_TOKEN, github_token,MY_KEY:strict,,A=B"));
+        assert_eq!(names, ["GITHUB_TOKEN", "MY_KEY"]);
+        assert_eq!(ignored, ["MY_KEY:strict"]);
+    }
+
+    #[test]
+    fn test_debug_enabled() {
+        assert!(debug_enabled(Some("1")));
+        assert!(debug_enabled(Some("TRUE")));
+        assert!(!debug_enabled(Some("0")));
+        assert!(!debug_enabled(None));
+    }
+}
@@ -0,0 +1,448 @@
+//! Import address table hooks
+//!
+//! When the DLL is attached, every IAT entry of every other loaded module
+//! that the hook table (see the targets module) names is pointed at the hook
+//! below. The DLL's own imports are left alone, so calling the functions
+//! from here reaches the real ones. The CRT functions, which the DLL does not
+//! import, are looked up in the CRT's module the first time they are needed.
+//!
+//! Code called with the cache locked can call back into a hook (a CRT
+//! setting its copy of the environment, say); the reentry module detects
+//! that, and the nested call goes to the real function.
+
+use std::ffi::{c_char, c_void, CStr};
+use std::mem::size_of;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use crate::cache::{copy_out, Cached, TokenCache};
+use crate::sys::*;
+use crate::targets::{hook_for, Crt, Hook};
+use crate::{config, pe, reentry};
+
+/// The DLL's own module
+static MODULE: AtomicPtr<c_void> = AtomicPtr::new(null_mut());
+
+/// The DLL's path, NUL-terminated, for injecting it into children
+static DLL_PATH: OnceLock<Vec<u16>> = OnceLock::new();
+
+static CACHE: OnceLock<Mutex<TokenCache>> = OnceLock::new();
+
+static DEBUG: AtomicBool = AtomicBool::new(false);
+
+/// Held while IATs are rewritten, so two threads loading libraries do not
+/// patch the same entries at once
+static PATCHING: Mutex<()> = Mutex::new(());
+
+/// The CRTs' own `getenv`, `_wgetenv` and `_wputenv_s`, by CRT index
+static REAL_GETENV: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static REAL_WGETENV: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static REAL_WPUTENV_S: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+type GetenvFn = unsafe extern "C" fn(*const c_char) -> *mut c_char;
+type WgetenvFn = unsafe extern "C" fn(*const u16) -> *mut u16;
+type WputenvSFn = unsafe extern "C" fn(*const u16, *const u16) -> i32;
+
+const UCRT: usize = Crt::Ucrt.index();
+const MSVCRT: usize = Crt::Msvcrt.index();
+
+/// Entry point called by the loader
+///
+/// # Safety
+/// Called by the loader only
+#[no_mangle]
+#[allow(non_snake_case)]
+pub unsafe extern "system" fn DllMain(
+    module: HMODULE,
+    reason: u32,
+    _reserved: *mut c_void,
+) -> BOOL {
+    if reason == DLL_PROCESS_ATTACH {
+        MODULE.store(module, Ordering::Relaxed);
+        attach();
+    }
+    1
+}
+
+/// Read the configuration and install the hooks
+unsafe fn attach() {
+    let mut path = vec![0u16; 32768];
+    let len = GetModuleFileNameW(MODULE.load(Ordering::Relaxed), path.as_mut_ptr(), 32768);
+    path.truncate(len as usize);
+    path.push(0);
+    let _ = DLL_PATH.set(path);
+
+    let text = |name: &str| {
+        let name: Vec<u16> = name.encode_utf16().collect();
+        read_var(&name).map(|value| String::from_utf16_lossy(&value))
+    };
+    let debug = config::debug_enabled(text("AWF_ONE_SHOT_TOKEN_DEBUG").as_deref());
+    DEBUG.store(debug, Ordering::Relaxed);
+    let (names, ignored) = config::protected_names(text("AWF_ONE_SHOT_TOKENS").as_deref());
+    for entry in ignored {
+        write_stderr(&format!(
+            "[one-shot-token] WARNING: Token options are not supported on Windows, ignoring them in {}\n",
+            entry
+        ));
+    }
+    if debug {
+        write_stderr(&format!(
+            "[one-shot-token] Initialized with {} token(s)\n",
+            names.len()
+        ));
+    }
+    let _ = CACHE.set(Mutex::new(TokenCache::new(names)));
+    patch_all();
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Point the hooked IAT entries of every other loaded module at the hooks
+unsafe fn patch_all() {
+    let _patching = lock(&PATCHING);
+    let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPMODULE | TH32CS_SNAPMODULE32, 0);
+    if snapshot == INVALID_HANDLE_VALUE {
+        return;
+    }
+    let own = MODULE.load(Ordering::Relaxed);
+    let mut entry: MODULEENTRY32W = std::mem::zeroed();
+    entry.dwSize = size_of::<MODULEENTRY32W>() as u32;
+    let mut more = Module32FirstW(snapshot, &mut entry) != 0;
+    while more {
+        if entry.hModule != own {
+            patch_module(entry.modBaseAddr);
+        }
+        more = Module32NextW(snapshot, &mut entry) != 0;
+    }
+    CloseHandle(snapshot);
+}
+
+unsafe fn patch_module(base: *const u8) {
+    for import in pe::imports(base) {
+        let Some(hook) = hook_for(&import.dll, &import.function) else {
+            continue;
+        };
+        let address = address(hook);
+        if import.slot.read_unaligned() == address {
+            continue;
+        }
+        let slot = import.slot.cast::<c_void>();
+        let mut old = 0;
+        if VirtualProtect(slot, size_of::<usize>(), PAGE_READWRITE, &mut old) == 0 {
+            continue;
+        }
+        import.slot.write_unaligned(address);
+        VirtualProtect(slot, size_of::<usize>(), old, &mut old);
+    }
+}
+
+/// Address of the function replacing `hook`
+fn address(hook: Hook) -> usize {
+    match hook {
+        Hook::GetEnvironmentVariableW => get_environment_variable_w as *const () as usize,
+        Hook::GetEnvironmentVariableA => get_environment_variable_a as *const () as usize,
+        Hook::GetProcAddress => get_proc_address as *const () as usize,
+        Hook::LoadLibraryW => load_library_w as *const () as usize,
+        Hook::LoadLibraryA => load_library_a as *const () as usize,
+        Hook::LoadLibraryExW => load_library_ex_w as *const () as usize,
+        Hook::LoadLibraryExA => load_library_ex_a as *const () as usize,
+        Hook::CreateProcessW => create_process_w as *const () as usize,
+        Hook::CreateProcessA => create_process_a as *const () as usize,
+        Hook::Getenv(Crt::Ucrt) => crt_getenv::<UCRT> as *const () as usize,
+        Hook::Getenv(Crt::Msvcrt) => crt_getenv::<MSVCRT> as *const () as usize,
+        Hook::Wgetenv(Crt::Ucrt) => crt_wgetenv::<UCRT> as *const () as usize,
+        Hook::Wgetenv(Crt::Msvcrt) => crt_wgetenv::<MSVCRT> as *const () as usize,
+    }
+}
+
+/// `function` of the CRT with index `crt`, looked up once into `cell`
+///
+/// Returns 0 if the CRT is not loaded.
+unsafe fn crt_function(cell: &AtomicUsize, crt: usize, function: &CStr) -> usize {
+    let mut address = cell.load(Ordering::Relaxed);
+    if address == 0 {
+        address = Crt::ALL[crt]
+            .modules()
+            .iter()
+            .map(|name| GetModuleHandleW(wide(name).as_ptr()))
+            .filter(|module| !module.is_null())
+            .map(|module| GetProcAddress(module, function.as_ptr()) as usize)
+            .find(|&address| address != 0)
+            .unwrap_or(0);
+        cell.store(address, Ordering::Relaxed);
+    }
+    address
+}
+
+/// Remove `name` from the process environment and every loaded CRT's copy
+unsafe fn scrub(name: &[u16]) {
+    let mut name = name.to_vec();
+    name.push(0);
+    SetEnvironmentVariableW(name.as_ptr(), std::ptr::null());
+    for crt in Crt::ALL {
+        let index = crt.index();
+        let address = crt_function(&REAL_WPUTENV_S[index], index, c"_wputenv_s");
+        if address != 0 {
+            let wputenv_s: WputenvSFn = std::mem::transmute(address);
+            // An empty value removes the variable
+            wputenv_s(name.as_ptr(), [0u16].as_ptr());
+        }
+    }
+}
+
+/// Look up the protected variable `name` (UTF-16, without NUL) and hand its
+/// cached value to `serve`
+///
+/// Returns `None` if `name` is not protected, for the caller to call the
+/// real function; otherwise the result of `serve`, which gets `None` if the
+/// variable is not set.
+unsafe fn protected<R>(name: &[u16], serve: impl FnOnce(Option<&Cached>) -> R) -> Option<R> {
+    if reentry::active() {
+        return None;
+    }
+    let _entered = reentry::enter();
+    let mut cache = lock(CACHE.get()?);
+    let token = cache.protected(name)?.to_string();
+    let mut first = false;
+    let cached = cache.get(
+        &token,
+        || read_var(name),
+        || {
+            first = true;
+            scrub(name);
+        },
+    );
+    if first && DEBUG.load(Ordering::Relaxed) {
+        if let Some(cached) = cached {
+            write_stderr(&format!(
+                "[one-shot-token] Token {} accessed and cached (value: {})\n",
+                token,
+                cached.preview()
+            ));
+        }
+    }
+    Some(serve(cached))
+}
+
+/// A NUL-terminated narrow name as UTF-16, byte for byte
+///
+/// Protected names are ASCII, so other bytes only need to not match them.
+unsafe fn widen(name: *const c_char) -> Vec<u16> {
+    CStr::from_ptr(name)
+        .to_bytes()
+        .iter()
+        .map(|&byte| u16::from(byte))
+        .collect()
+}
+
+/// `buffer` of `size` elements as a slice (empty if null)
+unsafe fn out_buffer<'a, T>(buffer: *mut T, size: u32) -> &'a mut [T] {
+    if buffer.is_null() {
+        &mut []
+    } else {
+        std::slice::from_raw_parts_mut(buffer, size as usize)
+    }
+}
+
+unsafe extern "system" fn get_environment_variable_w(
+    name: *const u16,
+    buffer: *mut u16,
+    size: u32,
+) -> u32 {
+    if !name.is_null() {
+        let served = protected(wide_str(name), |cached| match cached {
+            Some(cached) => copy_out(&cached.wide, out_buffer(buffer, size)),
+            None => {
+                SetLastError(ERROR_ENVVAR_NOT_FOUND);
+                0
+            }
+        });
+        if let Some(len) = served {
+            return len;
+        }
+    }
+    GetEnvironmentVariableW(name, buffer, size)
+}
+
+unsafe extern "system" fn get_environment_variable_a(
+    name: *const c_char,
+    buffer: *mut c_char,
+    size: u32,
+) -> u32 {
+    if !name.is_null() {
+        let served = protected(&widen(name), |cached| match cached {
+            Some(cached) => copy_out(&cached.narrow, out_buffer(buffer.cast::<u8>(), size)),
+            None => {
+                SetLastError(ERROR_ENVVAR_NOT_FOUND);
+                0
+            }
+        });
+        if let Some(len) = served {
+            return len;
+        }
+    }
+    GetEnvironmentVariableA(name, buffer, size)
+}
+
+unsafe extern "C" fn crt_getenv<const CRT: usize>(name: *const c_char) -> *mut c_char {
+    if !name.is_null() {
+        let served = protected(&widen(name), |cached| {
+            cached.map_or(null_mut(), |cached| {
+                cached.narrow.as_ptr().cast_mut().cast()
+            })
+        });
+        if let Some(value) = served {
+            return value;
+        }
+    }
+    match crt_function(&REAL_GETENV[CRT], CRT, c"getenv") {
+        0 => null_mut(),
+        address => std::mem::transmute::<usize, GetenvFn>(address)(name),
+    }
+}
+
+unsafe extern "C" fn crt_wgetenv<const CRT: usize>(name: *const u16) -> *mut u16 {
+    if !name.is_null() {
+        let served = protected(wide_str(name), |cached| {
+            cached.map_or(null_mut(), |cached| cached.wide.as_ptr().cast_mut())
+        });
+        if let Some(value) = served {
+            return value;
+        }
+    }
+    match crt_function(&REAL_WGETENV[CRT], CRT, c"_wgetenv") {
+        0 => null_mut(),
+        address => std::mem::transmute::<usize, WgetenvFn>(address)(name),
+    }
+}
+
+/// Functions looked up at run time resolve to the hooks, as imports do
+unsafe extern "system" fn get_proc_address(module: HMODULE, name: *const c_char) -> *mut c_void {
+    let real = GetProcAddress(module, name);
+    // Lookups by ordinal pass the ordinal in the low word of the pointer
+    if real.is_null() || (name as usize) >> 16 == 0 {
+        return real;
+    }
+    let mut path = [0u16; 1024];
+    let len = GetModuleFileNameW(module, path.as_mut_ptr(), path.len() as u32) as usize;
+    let path = String::from_utf16_lossy(&path[..len]);
+    let dll = path.rsplit(['\\', '/']).next().unwrap_or(&path);
+    match hook_for(dll, &CStr::from_ptr(name).to_string_lossy()) {
+        Some(hook) => address(hook) as *mut c_void,
+        None => real,
+    }
+}
+
+/// Patch the modules a successful load brought in
+unsafe fn loaded(module: HMODULE, flags: u32) -> HMODULE {
+    if !module.is_null() && flags & LOAD_LIBRARY_AS_DATA == 0 {
+        let error = GetLastError();
+        patch_all();
+        SetLastError(error);
+    }
+    module
+}
+
+unsafe extern "system" fn load_library_w(name: *const u16) -> HMODULE {
+    loaded(LoadLibraryW(name), 0)
+}
+
+unsafe extern "system" fn load_library_a(name: *const c_char) -> HMODULE {
+    loaded(LoadLibraryA(name), 0)
+}
+
+unsafe extern "system" fn load_library_ex_w(name: *const u16, file: HANDLE, flags: u32) -> HMODULE {
+    loaded(LoadLibraryExW(name, file, flags), flags)
+}
+
+unsafe extern "system" fn load_library_ex_a(
+    name: *const c_char,
+    file: HANDLE,
+    flags: u32,
+) -> HMODULE {
+    loaded(LoadLibraryExA(name, file, flags), flags)
+}
+
+/// Inject the DLL into a child created suspended, then let it run unless
+/// the caller asked for it suspended
+unsafe fn created(ok: BOOL, flags: u32, information: *mut PROCESS_INFORMATION) -> BOOL {
+    if ok == 0 || information.is_null() {
+        return ok;
+    }
+    let information = &*information;
+    let error = GetLastError();
+    if let Some(path) = DLL_PATH.get() {
+        if let Err(code) = crate::inject::load_into(information.hProcess, path) {
+            write_stderr(&format!(
+                "[one-shot-token] AUDIT event=inject_failed token=* child={} error={}\n",
+                information.dwProcessId, code
+            ));
+        }
+    }
+    if flags & CREATE_SUSPENDED == 0 {
+        ResumeThread(information.hThread);
+    }
+    SetLastError(error);
+    ok
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "system" fn create_process_w(
+    application: *const u16,
+    command_line: *mut u16,
+    process_attributes: *mut c_void,
+    thread_attributes: *mut c_void,
+    inherit_handles: BOOL,
+    flags: u32,
+    environment: *mut c_void,
+    directory: *const u16,
+    startup_info: *mut c_void,
+    information: *mut PROCESS_INFORMATION,
+) -> BOOL {
+    let ok = CreateProcessW(
+        application,
+        command_line,
+        process_attributes,
+        thread_attributes,
+        inherit_handles,
+        flags | CREATE_SUSPENDED,
+        environment,
+        directory,
+        startup_info,
+        information,
+    );
+    created(ok, flags, information)
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "system" fn create_process_a(
+    application: *const c_char,
+    command_line: *mut c_char,
+    process_attributes: *mut c_void,
+    thread_attributes: *mut c_void,
+    inherit_handles: BOOL,
+    flags: u32,
+    environment: *mut c_void,
+    directory: *const c_char,
+    startup_info: *mut c_void,
+    information: *mut PROCESS_INFORMATION,
+) -> BOOL {
+    let ok = CreateProcessA(
+        application,
+        command_line,
+        process_attributes,
+        thread_attributes,
+        inherit_handles,
+        flags | CREATE_SUSPENDED,
+        environment,
+        directory,
+        startup_info,
+        information,
+    );
+    created(ok, flags, information)
+}
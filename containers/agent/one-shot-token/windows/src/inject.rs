@@ -0,0 +1,73 @@
+//! Loading the DLL into another process
+//!
+//! The classic remote-thread injection: the DLL's path is written into the
+//! target's memory and a thread is started there at `LoadLibraryW`, with the
+//! path as its argument. kernel32.dll is mapped at the same address in every
+//! process of a boot session, so the local address of `LoadLibraryW` is valid
+//! in the target. The target must have the same pointer width; a 32-bit
+//! child of a 64-bit program fails to inject.
+
+use std::ffi::c_void;
+use std::ptr::null_mut;
+
+use crate::sys::*;
+
+/// Load the DLL at `dll` (a NUL-terminated UTF-16 path) into `process`
+///
+/// Waits for `LoadLibraryW` to return, so the DLL's hooks are installed when
+/// this returns `Ok`. The error is a Win32 error code (for a failed
+/// `LoadLibraryW`, `ERROR_MOD_NOT_FOUND`).
+pub(crate) fn load_into(process: HANDLE, dll: &[u16]) -> Result<(), u32> {
+    let size = std::mem::size_of_val(dll);
+    // SAFETY: every pointer passed is either checked or owned by the calls
+    unsafe {
+        let kernel32 = GetModuleHandleW(wide("kernel32.dll").as_ptr());
+        let load_library = GetProcAddress(kernel32, c"LoadLibraryW".as_ptr());
+        if load_library.is_null() {
+            return Err(GetLastError());
+        }
+        let remote = VirtualAllocEx(
+            process,
+            null_mut(),
+            size,
+            MEM_COMMIT | MEM_RESERVE,
+            PAGE_READWRITE,
+        );
+        if remote.is_null() {
+            return Err(GetLastError());
+        }
+        let result = run_remote(process, load_library, remote, dll, size);
+        VirtualFreeEx(process, remote, 0, MEM_RELEASE);
+        result
+    }
+}
+
+/// Write the path to `remote` and call `load_library` on it in `process`
+unsafe fn run_remote(
+    process: HANDLE,
+    load_library: *mut c_void,
+    remote: *mut c_void,
+    dll: &[u16],
+    size: usize,
+) -> Result<(), u32> {
+    let mut written = 0;
+    if WriteProcessMemory(process, remote, dll.as_ptr().cast(), size, &mut written) == 0 {
+        return Err(GetLastError());
+    }
+    let thread = CreateRemoteThread(process, null_mut(), 0, load_library, remote, 0, null_mut());
+    if thread.is_null() {
+        return Err(GetLastError());
+    }
+    WaitForSingleObject(thread, INFINITE);
+    // The thread's exit code is the low 32 bits of the module handle
+    let mut code = 0;
+    let ok = GetExitCodeThread(thread, &mut code);
+    CloseHandle(thread);
+    if ok == 0 {
+        return Err(GetLastError());
+    }
+    if code == 0 {
+        return Err(ERROR_MOD_NOT_FOUND);
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! One-Shot Token DLL for Windows runners
+//!
+//! Windows has no LD_PRELOAD. The library is built as `one_shot_token.dll`
+//! and injected instead: awf-inject starts the command suspended, has it
+//! load the DLL with a remote `LoadLibraryW` thread, and only then lets it
+//! run. When the DLL is attached it reads its configuration and rewrites the
+//! import address table (IAT) of every module in the process, so that calls
+//! to these functions reach the hooks below instead (see the hooks module):
+//!
+//! - `GetEnvironmentVariableW` and `GetEnvironmentVariableA`
+//!   (kernel32, kernelbase and the `api-ms-win-core-*` API sets)
+//! - `getenv` and `_wgetenv` of the universal CRT (`ucrtbase.dll` and the
+//!   `api-ms-win-crt-*` API sets) and of the legacy `msvcrt.dll`
+//! - `GetProcAddress`, so functions looked up at run time resolve to the
+//!   hooks as well
+//! - `LoadLibrary(Ex)A/W`, so modules loaded later are patched too
+//! - `CreateProcessA/W`, so child processes get the DLL injected before
+//!   they run (a failed injection is reported with an `inject_failed` audit
+//!   line, and the child runs unprotected)
+//!
+//! The semantics are those of the Linux library's default policy: the first
+//! read of a protected variable caches its value and removes it from the
+//! process environment and from the CRTs' copies of it, and every later read
+//! is served from the cache. Variable names are compared without regard to
+//! ASCII case, as Windows does.
+//!
+//! Configuration (read when the DLL is attached):
+//!
+//!   AWF_ONE_SHOT_TOKENS - Comma-separated list of variables to protect
+//!   (default: the Linux library's default presets). Per-token options after
+//!   `:` are not supported on Windows and are reported and ignored.
+//!
+//!   AWF_ONE_SHOT_TOKEN_DEBUG - Log every first read to stderr
+//!
+//! The modules that only parse data (configuration, the token cache, PE
+//! import tables and the hook table) build and are tested on every platform.
+
+// Outside Windows only the tests use the portable modules
+#![cfg_attr(not(windows), allow(dead_code))]
+
+mod cache;
+mod config;
+#[allow(dead_code)]
+#[path = "../../src/detect.rs"]
+mod detect;
+#[cfg(windows)]
+mod hooks;
+#[cfg(windows)]
+mod inject;
+mod pe;
+#[path = "../../src/reentry.rs"]
+mod reentry;
+// Shared with awf-inject, which uses the process creation functions
+#[cfg(windows)]
+#[allow(dead_code)]
+mod sys;
+mod targets;
@@ -0,0 +1,180 @@
+//! Import tables of loaded PE images
+//!
+//! Every module calls the functions it imports from other DLLs through its
+//! import address table (IAT), an array of function pointers the loader
+//! fills in. The hooks replace entries of that array (see the hooks module);
+//! this module finds them. Images are read in their loaded layout, where
+//! RVAs are offsets from the module's base address, and only images of the
+//! process's own pointer width are handled.
+
+use std::ffi::CStr;
+
+/// `IMAGE_NT_OPTIONAL_HDR32_MAGIC` or `IMAGE_NT_OPTIONAL_HDR64_MAGIC`,
+/// whichever matches the pointer width
+#[cfg(target_pointer_width = "64")]
+const OPTIONAL_MAGIC: u16 = 0x20b;
+#[cfg(target_pointer_width = "32")]
+const OPTIONAL_MAGIC: u16 = 0x10b;
+
+/// Offset of the data directories in the optional header
+#[cfg(target_pointer_width = "64")]
+const DATA_DIRECTORIES: usize = 112;
+#[cfg(target_pointer_width = "32")]
+const DATA_DIRECTORIES: usize = 96;
+
+/// `IMAGE_DIRECTORY_ENTRY_IMPORT`
+const IMPORT_DIRECTORY: usize = 1;
+
+/// Size of an `IMAGE_IMPORT_DESCRIPTOR`
+const DESCRIPTOR_SIZE: usize = 20;
+
+/// `IMAGE_ORDINAL_FLAG`: the import is by ordinal, not by name
+const ORDINAL_FLAG: usize = 1 << (usize::BITS - 1);
+
+/// An IAT entry of a function imported by name
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Import {
+    /// DLL the function is imported from, as named in the import table
+    pub(crate) dll: String,
+    pub(crate) function: String,
+    /// The entry, holding the address the function was bound to
+    pub(crate) slot: *mut usize,
+}
+
+/// Read a little-endian integer of type `T` at `offset` from `base`
+unsafe fn read<T: Copy>(base: *const u8, offset: usize) -> T {
+    base.add(offset).cast::<T>().read_unaligned()
+}
+
+/// The functions imported by name by the image loaded at `base`
+///
+/// Returns nothing for data that is not a loaded image of the process's
+/// pointer width.
+///
+/// # Safety
+/// `base` must be the base address of a mapped image (or of readable memory
+/// laid out like one), which stays mapped during the call
+pub(crate) unsafe fn imports(base: *const u8) -> Vec<Import> {
+    let mut found = Vec::new();
+    if read::<u16>(base, 0) != u16::from_le_bytes(*b"MZ") {
+        return found;
+    }
+    let nt = read::<u32>(base, 0x3c) as usize;
+    if read::<u32>(base, nt) != u32::from_le_bytes(*b"PE\0\0") {
+        return found;
+    }
+    // The optional header follows the signature and the 20-byte file header
+    let optional = nt + 24;
+    if read::<u16>(base, optional) != OPTIONAL_MAGIC {
+        return found;
+    }
+    let directory = optional + DATA_DIRECTORIES + IMPORT_DIRECTORY * 8;
+    let mut descriptor = read::<u32>(base, directory) as usize;
+    if descriptor == 0 {
+        return found;
+    }
+    loop {
+        let lookup = read::<u32>(base, descriptor) as usize;
+        let name = read::<u32>(base, descriptor + 12) as usize;
+        let table = read::<u32>(base, descriptor + 16) as usize;
+        if name == 0 || table == 0 {
+            break;
+        }
+        let dll = CStr::from_ptr(base.add(name).cast())
+            .to_string_lossy()
+            .into_owned();
+        // Bound images may only have the IAT, which then still holds the
+        // lookup entries until the loader overwrites them
+        let lookup = if lookup == 0 { table } else { lookup };
+        let mut index = 0;
+        loop {
+            let entry = read::<usize>(base, lookup + index * std::mem::size_of::<usize>());
+            if entry == 0 {
+                break;
+            }
+            if entry & ORDINAL_FLAG == 0 {
+                // IMAGE_IMPORT_BY_NAME: a 16-bit hint, then the name
+                let function = CStr::from_ptr(base.add(entry + 2).cast())
+                    .to_string_lossy()
+                    .into_owned();
+                found.push(Import {
+                    dll: dll.clone(),
+                    function,
+                    slot: base
+                        .add(table + index * std::mem::size_of::<usize>())
+                        .cast_mut()
+                        .cast(),
+                });
+            }
+            index += 1;
+        }
+        descriptor += DESCRIPTOR_SIZE;
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal image importing two functions by name and one by ordinal
+    /// from KERNEL32.dll
+    fn image() -> Vec<u8> {
+        let mut image = vec![0u8; 0x400];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            image[offset..offset + bytes.len()].copy_from_slice(bytes)
+        };
+        put(0, b"MZ");
+        put(0x3c, &0x80u32.to_le_bytes());
+        put(0x80, b"PE\0\0");
+        put(0x98, &OPTIONAL_MAGIC.to_le_bytes());
+        put(0x98 + DATA_DIRECTORIES + 8, &0x200u32.to_le_bytes());
+        // Descriptor: lookup table, name and IAT; the next one is zero
+        put(0x200, &0x300u32.to_le_bytes());
+        put(0x20c, &0x280u32.to_le_bytes());
+        put(0x210, &0x340u32.to_le_bytes());
+        put(0x280, b"KERNEL32.dll\0");
+        let width = std::mem::size_of::<usize>();
+        for (index, entry) in [0x3a0, ORDINAL_FLAG | 0x10, 0x3c0].iter().enumerate() {
+            put(0x300 + index * width, &entry.to_le_bytes());
+            put(0x340 + index * width, &(0x1111 * (index + 1)).to_le_bytes());
+        }
+        put(0x3a2, b"GetEnvironmentVariableW\0");
+        put(0x3c2, b"ExitProcess\0");
+        image
+    }
+
+    #[test]
+    fn test_imports() {
+        let image = image();
+        let base = image.as_ptr();
+        let width = std::mem::size_of::<usize>();
+        // SAFETY: the image is laid out as a loaded one
+        let imports = unsafe { imports(base) };
+        assert_eq!(
+            imports,
+            [
+                Import {
+                    dll: "KERNEL32.dll".to_string(),
+                    function: "GetEnvironmentVariableW".to_string(),
+                    slot: base.wrapping_add(0x340).cast_mut().cast(),
+                },
+                Import {
+                    dll: "KERNEL32.dll".to_string(),
+                    function: "ExitProcess".to_string(),
+                    slot: base.wrapping_add(0x340 + 2 * width).cast_mut().cast(),
+                },
+            ]
+        );
+        // SAFETY: the slot is inside the image
+        assert_eq!(unsafe { imports[1].slot.read_unaligned() }, 0x3333);
+
+        let mut other = image.clone();
+        other[0x98] ^= 0xff;
+        // SAFETY: as above
+        assert!(unsafe { super::imports(other.as_ptr()) }.is_empty());
+        other[0] = 0;
+        // SAFETY: as above
+        assert!(unsafe { super::imports(other.as_ptr()) }.is_empty());
+    }
+}
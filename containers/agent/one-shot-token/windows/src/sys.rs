@@ -0,0 +1,229 @@
+//! The Win32 functions and types the DLL and awf-inject use
+//!
+//! Declared by hand, as the Linux library declares what libc lacks, rather
+//! than pulling in a bindings crate for a few dozen items.
+
+#![allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+
+use std::ffi::{c_char, c_void};
+
+pub(crate) type BOOL = i32;
+pub(crate) type HANDLE = *mut c_void;
+pub(crate) type HMODULE = *mut c_void;
+
+pub(crate) const DLL_PROCESS_ATTACH: u32 = 1;
+pub(crate) const TH32CS_SNAPMODULE: u32 = 0x8;
+pub(crate) const TH32CS_SNAPMODULE32: u32 = 0x10;
+pub(crate) const PAGE_READWRITE: u32 = 0x04;
+pub(crate) const MEM_COMMIT: u32 = 0x1000;
+pub(crate) const MEM_RESERVE: u32 = 0x2000;
+pub(crate) const MEM_RELEASE: u32 = 0x8000;
+pub(crate) const CREATE_SUSPENDED: u32 = 0x4;
+pub(crate) const INFINITE: u32 = 0xffff_ffff;
+pub(crate) const STD_ERROR_HANDLE: u32 = -12i32 as u32;
+pub(crate) const ERROR_ENVVAR_NOT_FOUND: u32 = 203;
+pub(crate) const ERROR_MOD_NOT_FOUND: u32 = 126;
+pub(crate) const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+/// LoadLibraryEx flags that map a file without running or binding it
+pub(crate) const LOAD_LIBRARY_AS_DATA: u32 = 0x2 | 0x20 | 0x40;
+
+#[repr(C)]
+pub(crate) struct MODULEENTRY32W {
+    pub(crate) dwSize: u32,
+    pub(crate) th32ModuleID: u32,
+    pub(crate) th32ProcessID: u32,
+    pub(crate) GlblcntUsage: u32,
+    pub(crate) ProccntUsage: u32,
+    pub(crate) modBaseAddr: *mut u8,
+    pub(crate) modBaseSize: u32,
+    pub(crate) hModule: HMODULE,
+    pub(crate) szModule: [u16; 256],
+    pub(crate) szExePath: [u16; 260],
+}
+
+#[repr(C)]
+pub(crate) struct STARTUPINFOW {
+    pub(crate) cb: u32,
+    pub(crate) lpReserved: *mut u16,
+    pub(crate) lpDesktop: *mut u16,
+    pub(crate) lpTitle: *mut u16,
+    pub(crate) dwX: u32,
+    pub(crate) dwY: u32,
+    pub(crate) dwXSize: u32,
+    pub(crate) dwYSize: u32,
+    pub(crate) dwXCountChars: u32,
+    pub(crate) dwYCountChars: u32,
+    pub(crate) dwFillAttribute: u32,
+    pub(crate) dwFlags: u32,
+    pub(crate) wShowWindow: u16,
+    pub(crate) cbReserved2: u16,
+    pub(crate) lpReserved2: *mut u8,
+    pub(crate) hStdInput: HANDLE,
+    pub(crate) hStdOutput: HANDLE,
+    pub(crate) hStdError: HANDLE,
+}
+
+#[repr(C)]
+pub(crate) struct PROCESS_INFORMATION {
+    pub(crate) hProcess: HANDLE,
+    pub(crate) hThread: HANDLE,
+    pub(crate) dwProcessId: u32,
+    pub(crate) dwThreadId: u32,
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    pub(crate) fn GetEnvironmentVariableW(name: *const u16, buffer: *mut u16, size: u32) -> u32;
+    pub(crate) fn GetEnvironmentVariableA(
+        name: *const c_char,
+        buffer: *mut c_char,
+        size: u32,
+    ) -> u32;
+    pub(crate) fn SetEnvironmentVariableW(name: *const u16, value: *const u16) -> BOOL;
+    pub(crate) fn GetModuleHandleW(name: *const u16) -> HMODULE;
+    pub(crate) fn GetModuleFileNameW(module: HMODULE, buffer: *mut u16, size: u32) -> u32;
+    pub(crate) fn GetProcAddress(module: HMODULE, name: *const c_char) -> *mut c_void;
+    pub(crate) fn LoadLibraryW(name: *const u16) -> HMODULE;
+    pub(crate) fn LoadLibraryA(name: *const c_char) -> HMODULE;
+    pub(crate) fn LoadLibraryExW(name: *const u16, file: HANDLE, flags: u32) -> HMODULE;
+    pub(crate) fn LoadLibraryExA(name: *const c_char, file: HANDLE, flags: u32) -> HMODULE;
+    pub(crate) fn VirtualProtect(
+        address: *mut c_void,
+        size: usize,
+        protect: u32,
+        old: *mut u32,
+    ) -> BOOL;
+    pub(crate) fn CreateToolhelp32Snapshot(flags: u32, pid: u32) -> HANDLE;
+    pub(crate) fn Module32FirstW(snapshot: HANDLE, entry: *mut MODULEENTRY32W) -> BOOL;
+    pub(crate) fn Module32NextW(snapshot: HANDLE, entry: *mut MODULEENTRY32W) -> BOOL;
+    pub(crate) fn CloseHandle(handle: HANDLE) -> BOOL;
+    pub(crate) fn CreateProcessW(
+        application: *const u16,
+        command_line: *mut u16,
+        process_attributes: *mut c_void,
+        thread_attributes: *mut c_void,
+        inherit_handles: BOOL,
+        flags: u32,
+        environment: *mut c_void,
+        directory: *const u16,
+        startup_info: *mut c_void,
+        information: *mut PROCESS_INFORMATION,
+    ) -> BOOL;
+    pub(crate) fn CreateProcessA(
+        application: *const c_char,
+        command_line: *mut c_char,
+        process_attributes: *mut c_void,
+        thread_attributes: *mut c_void,
+        inherit_handles: BOOL,
+        flags: u32,
+        environment: *mut c_void,
+        directory: *const c_char,
+        startup_info: *mut c_void,
+        information: *mut PROCESS_INFORMATION,
+    ) -> BOOL;
+    pub(crate) fn VirtualAllocEx(
+        process: HANDLE,
+        address: *mut c_void,
+        size: usize,
+        allocation: u32,
+        protect: u32,
+    ) -> *mut c_void;
+    pub(crate) fn VirtualFreeEx(
+        process: HANDLE,
+        address: *mut c_void,
+        size: usize,
+        free: u32,
+    ) -> BOOL;
+    pub(crate) fn WriteProcessMemory(
+        process: HANDLE,
+        address: *mut c_void,
+        buffer: *const c_void,
+        size: usize,
+        written: *mut usize,
+    ) -> BOOL;
+    pub(crate) fn CreateRemoteThread(
+        process: HANDLE,
+        attributes: *mut c_void,
+        stack_size: usize,
+        start: *mut c_void,
+        parameter: *mut c_void,
+        flags: u32,
+        thread_id: *mut u32,
+    ) -> HANDLE;
+    pub(crate) fn WaitForSingleObject(handle: HANDLE, milliseconds: u32) -> u32;
+    pub(crate) fn GetExitCodeThread(thread: HANDLE, code: *mut u32) -> BOOL;
+    pub(crate) fn GetExitCodeProcess(process: HANDLE, code: *mut u32) -> BOOL;
+    pub(crate) fn ResumeThread(thread: HANDLE) -> u32;
+    pub(crate) fn TerminateProcess(process: HANDLE, code: u32) -> BOOL;
+    pub(crate) fn GetStdHandle(which: u32) -> HANDLE;
+    pub(crate) fn WriteFile(
+        file: HANDLE,
+        buffer: *const c_void,
+        size: u32,
+        written: *mut u32,
+        overlapped: *mut c_void,
+    ) -> BOOL;
+    pub(crate) fn GetLastError() -> u32;
+    pub(crate) fn SetLastError(code: u32);
+}
+
+/// `text` as a NUL-terminated UTF-16 string
+pub(crate) fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain(Some(0)).collect()
+}
+
+/// The NUL-terminated UTF-16 string at `text`, without the NUL
+///
+/// # Safety
+/// `text` must point to a NUL-terminated UTF-16 string
+pub(crate) unsafe fn wide_str<'a>(text: *const u16) -> &'a [u16] {
+    let mut len = 0;
+    while *text.add(len) != 0 {
+        len += 1;
+    }
+    std::slice::from_raw_parts(text, len)
+}
+
+/// The value of the environment variable `name`, read past the hooks
+pub(crate) fn read_var(name: &[u16]) -> Option<Vec<u16>> {
+    let mut name = name.to_vec();
+    name.push(0);
+    let mut buffer: Vec<u16> = Vec::new();
+    loop {
+        // SAFETY: name is NUL-terminated and buffer holds its capacity; the
+        // DLL's own imports are never hooked
+        let len = unsafe {
+            GetEnvironmentVariableW(name.as_ptr(), buffer.as_mut_ptr(), buffer.capacity() as u32)
+        } as usize;
+        if len == 0 {
+            // An empty value reads as 0 without an error
+            return (unsafe { GetLastError() } != ERROR_ENVVAR_NOT_FOUND).then(Vec::new);
+        }
+        if len < buffer.capacity() {
+            // SAFETY: the function wrote len characters and the NUL
+            unsafe { buffer.set_len(len) };
+            return Some(buffer);
+        }
+        // len is the size needed, including the NUL
+        buffer.reserve(len);
+    }
+}
+
+/// Write `text` to stderr, unbuffered and without locks the program may hold
+pub(crate) fn write_stderr(text: &str) {
+    // SAFETY: the buffer is valid for its length
+    unsafe {
+        let handle = GetStdHandle(STD_ERROR_HANDLE);
+        if handle.is_null() || handle == INVALID_HANDLE_VALUE {
+            return;
+        }
+        let mut written = 0;
+        WriteFile(
+            handle,
+            text.as_ptr().cast(),
+            text.len() as u32,
+            &mut written,
+            std::ptr::null_mut(),
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! Which imports are hooked
+//!
+//! An import is identified by the DLL named in the import table and the
+//! function name. Programs import the kernel functions from kernel32.dll or
+//! through an API set (`api-ms-win-core-*`), which the loader redirects to
+//! kernelbase.dll, and the CRT functions from ucrtbase.dll (directly or
+//! through the `api-ms-win-crt-*` API sets) or from the legacy msvcrt.dll.
+//! The two CRTs keep separate copies of the environment, so their functions
+//! are hooked separately.
+
+/// A C runtime with its own copy of the environment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Crt {
+    /// The universal CRT
+    Ucrt,
+    /// msvcrt.dll, used by MinGW programs and older system tools
+    Msvcrt,
+}
+
+impl Crt {
+    /// Every CRT, in the order of `index`
+    pub(crate) const ALL: [Crt; 2] = [Crt::Ucrt, Crt::Msvcrt];
+
+    /// Index of the CRT, for per-CRT hooks and tables
+    pub(crate) const fn index(self) -> usize {
+        self as usize
+    }
+
+    /// DLLs implementing the CRT, as loaded
+    pub(crate) fn modules(self) -> &'static [&'static str] {
+        match self {
+            Crt::Ucrt => &["ucrtbase.dll", "ucrtbased.dll"],
+            Crt::Msvcrt => &["msvcrt.dll"],
+        }
+    }
+}
+
+/// A hooked function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Hook {
+    GetEnvironmentVariableW,
+    GetEnvironmentVariableA,
+    GetProcAddress,
+    LoadLibraryW,
+    LoadLibraryA,
+    LoadLibraryExW,
+    LoadLibraryExA,
+    CreateProcessW,
+    CreateProcessA,
+    Getenv(Crt),
+    Wgetenv(Crt),
+}
+
+/// Whether `dll` (lowercase) provides the kernel functions
+fn is_kernel(dll: &str) -> bool {
+    matches!(dll, "kernel32.dll" | "kernelbase.dll") || dll.starts_with("api-ms-win-core-")
+}
+
+/// The CRT `dll` (lowercase) belongs to, if any
+fn crt(dll: &str) -> Option<Crt> {
+    if dll.starts_with("api-ms-win-crt-") {
+        return Some(Crt::Ucrt);
+    }
+    Crt::ALL
+        .into_iter()
+        .find(|crt| crt.modules().contains(&dll))
+}
+
+/// The hook for `function` imported from `dll`, if it is hooked
+pub(crate) fn hook_for(dll: &str, function: &str) -> Option<Hook> {
+    let dll = dll.to_ascii_lowercase();
+    if is_kernel(&dll) {
+        return match function {
+            "GetEnvironmentVariableW" => Some(Hook::GetEnvironmentVariableW),
+            "GetEnvironmentVariableA" => Some(Hook::GetEnvironmentVariableA),
+            "GetProcAddress" => Some(Hook::GetProcAddress),
+            "LoadLibraryW" => Some(Hook::LoadLibraryW),
+            "LoadLibraryA" => Some(Hook::LoadLibraryA),
+            "LoadLibraryExW" => Some(Hook::LoadLibraryExW),
+            "LoadLibraryExA" => Some(Hook::LoadLibraryExA),
+            "CreateProcessW" => Some(Hook::CreateProcessW),
+            "CreateProcessA" => Some(Hook::CreateProcessA),
+            _ => None,
+        };
+    }
+    let crt = crt(&dll)?;
+    match function {
+        "getenv" => Some(Hook::Getenv(crt)),
+        "_wgetenv" => Some(Hook::Wgetenv(crt)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_for() {
+        assert_eq!(
+            hook_for("KERNEL32.dll", "GetEnvironmentVariableW"),
+            Some(Hook::GetEnvironmentVariableW)
+        );
+        assert_eq!(
+            hook_for(
+                "api-ms-win-core-processenvironment-l1-1-0.dll",
+                "GetEnvironmentVariableA"
+            ),
+            Some(Hook::GetEnvironmentVariableA)
+        );
+        assert_eq!(
+            hook_for("api-ms-win-core-libraryloader-l1-2-0.dll", "GetProcAddress"),
+            Some(Hook::GetProcAddress)
+        );
+        assert_eq!(
+            hook_for("api-ms-win-crt-environment-l1-1-0.dll", "getenv"),
+            Some(Hook::Getenv(Crt::Ucrt))
+        );
+        assert_eq!(
+            hook_for("msvcrt.dll", "_wgetenv"),
+            Some(Hook::Wgetenv(Crt::Msvcrt))
+        );
+        assert_eq!(hook_for("KERNEL32.dll", "getenv"), None);
+        assert_eq!(hook_for("libc.dll", "getenv"), None);
+        assert_eq!(hook_for("ucrtbase.dll", "GetEnvironmentVariableW"), None);
+        assert_eq!(Crt::Msvcrt.index(), 1);
+    }
+}
@@ -63,6 +63,26 @@ describe('log-parser', () => {
       expect(result!.method).toBe('GET');
     });
 
+    it('should parse one-shot-token lines with the token as the domain', () => {
+      const served =
+        '1792172349.860 -:25124 GITHUB_TOKEN -:- - TOKEN 200 TCP_MISS:ACCESS token://GITHUB_TOKEN/access?via=getenv&reads=1&first=true "gh"';
+      const refused =
+        '1792172349.860 -:25124 GITHUB_TOKEN -:- - TOKEN 403 TCP_DENIED:MAX_READS_EXCEEDED token://GITHUB_TOKEN/max_reads_exceeded?max_reads=1&reads=1 "gh"';
+
+      const servedResult = parseLogLine(served);
+      expect(servedResult).not.toBeNull();
+      expect(servedResult!.domain).toBe('GITHUB_TOKEN');
+      expect(servedResult!.clientPort).toBe('25124');
+      expect(servedResult!.isAllowed).toBe(true);
+      expect(servedResult!.userAgent).toBe('gh');
+
+      const refusedResult = parseLogLine(refused);
+      expect(refusedResult).not.toBeNull();
+      expect(refusedResult!.domain).toBe('GITHUB_TOKEN');
+      expect(refusedResult!.statusCode).toBe(403);
+      expect(refusedResult!.isAllowed).toBe(false);
+    });
+
     it('should return null for empty line', () => {
       expect(parseLogLine('')).toBeNull();
       expect(parseLogLine('   ')).toBeNull();